@@ -0,0 +1,60 @@
+//! Echo bot: repeats every message it sees back to the group
+//!
+//! Set up a profile for the bot like any other user, then run it:
+//!
+//! ```text
+//! mls-chat --data-dir bot init bob
+//! mls-chat --data-dir bot login 127.0.0.1:7878
+//! cargo run --example echo_bot -- --data-dir bot --server 127.0.0.1:7878 --announce "Echo bot here"
+//! ```
+//!
+//! Add the bot's user to a group and it joins on the next round.
+
+use anyhow::{Context, Result};
+use clap::Parser;
+use mls_chat::{Bot, DeliveryClient, MlsChatApp};
+
+/// Echo every group message back, optionally announcing itself on start
+#[derive(Parser)]
+struct Args {
+    /// Directory holding the bot's profile
+    #[arg(long, default_value = "mls_chat_data")]
+    data_dir: String,
+    /// Delivery service address (host:port)
+    #[arg(long)]
+    server: String,
+    /// Namespace to use on a delivery service hosting several
+    #[arg(long)]
+    namespace: Option<String>,
+    /// Message to post to every group the bot is in when it starts
+    #[arg(long)]
+    announce: Option<String>,
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    let mut app = MlsChatApp::open(&args.data_dir)?;
+    app.load_state()?;
+
+    let account = match &args.namespace {
+        Some(namespace) => format!("{}/{}", args.server, namespace),
+        None => args.server.clone(),
+    };
+    let token = app.server_token(&account)?
+        .context("No token for this server; run `mls-chat login` with the bot's profile first")?;
+    let mut client = DeliveryClient::connect(&args.server)?.with_token(token);
+    if let Some(namespace) = &args.namespace {
+        client = client.with_namespace(namespace);
+    }
+    app.set_transport(Box::new(client), &args.server);
+
+    let mut bot = Bot::new(app)?.on_message(|context, message| {
+        context.reply(&format!("{} said: {}", message.sender, message.content))?;
+        Ok(())
+    });
+    if let Some(text) = &args.announce {
+        bot.step()?;
+        bot.announce(text)?;
+    }
+    bot.run()
+}
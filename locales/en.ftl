@@ -0,0 +1,116 @@
+# English messages, the fallback for every other catalog.
+# One `key = text` per line; `{ $name }` is filled in by the program.
+
+init-start = Initializing user identity...
+init-done = ✅ User '{ $user }' initialized successfully
+init-keys = Generated cryptographic identity and key package
+init-not-active = '{ $active }' is still the active identity; `identity use { $command }` switches to '{ $user }'
+
+group-create-start = Creating new MLS group...
+group-created = ✅ Group '{ $group }' created successfully
+group-id = MLS Group ID: { $id }
+group-initial-epoch = Initial epoch: 1
+group-secret-generated = Group secret generated and distributed
+
+member-add-start = Adding member to group...
+member-already = ⚠️  Member '{ $member }' is already in the group
+member-fetch-key-package = Fetching key package for '{ $member }' from delivery service
+member-add-proposal = Creating Add proposal for '{ $member }'
+member-fingerprint = Key fingerprint: { $fingerprint } (compare with their `whoami`)
+member-new-secret = Generating new group secret
+member-distribute = Distributing updated keys to all members
+member-added = ✅ Member '{ $member }' added to group '{ $group }'
+member-epoch = Epoch updated to: { $epoch }
+member-secret-rotated = Group secret rotated for security
+
+send-start = Sending encrypted message...
+send-encrypting = Encrypting message with group secret
+send-epoch = Using epoch: { $epoch }
+send-done = ✅ Message sent successfully
+send-encrypted = Message encrypted with group key
+send-forward-secrecy = Forward secrecy maintained
+
+list-header = Messages in group '{ $group }':
+list-topic = Topic: { $topic }
+list-group-id = Group ID: { $id }
+list-epoch = Current Epoch: { $epoch }
+list-members = Members: { $members }
+list-empty = No messages yet.
+list-no-match = No messages match.
+list-encrypted = Encrypted: { $ciphertext }
+
+groups-header = Groups:
+groups-entry = { $group }{ $draft } ({ $members } member(s), { $messages } message(s)){ $flags }
+groups-empty = No groups yet.
+groups-hidden = { $count } archived group(s) hidden; `groups --all` shows them
+
+members-add-start = Adding members...
+members-unknown-user = unknown user; expected alice or bob
+members-no-key-package = no key package ({ $error })
+members-not-initialized = not initialized
+members-add-proposal = Add proposal for { $member } (key fingerprint { $fingerprint })
+members-committing = Committing { $count } Add proposal(s) with a new group secret
+members-added-one = ✅ { $member } added
+members-already = ⚠️  { $member } is already a member
+members-pending = ⏳ { $member } waits for an admin's approval (request { $id })
+members-failed = ❌ { $member } not added: { $reason }
+members-added = ✅ { $count } member(s) added to '{ $group }' in one commit
+members-epoch = New epoch: { $epoch }
+members-savings = One commit took { $batched } against { $sequential } for { $count } one-add commits: { $saved } saved, { $epochs } fewer epochs, { $batched-size } sent instead of { $sequential-size }
+
+policy-start = Updating group policy...
+policy-unchanged = ⚠️  Nothing to change
+policy-committing = Committing the new policy at epoch { $epoch }
+policy-set = ✅ Group '{ $group }' is now { $policy }
+join-start = Joining group by external commit...
+join-already = ⚠️  { $user } is already a member of '{ $group }'
+join-committing = External commit adding { $user } at epoch { $epoch }
+join-done = ✅ { $user } joined '{ $group }'
+request-already = ⏳ { $member } is already waiting to join '{ $group }' (request { $id })
+request-queued = ⏳ Group '{ $group }' needs an admin's approval; { $member } is queued as request { $id }
+request-ask = Ask one of { $admins } to run `approve { $id }`
+requests-empty = No requests waiting in '{ $group }'.
+request-asked = asked to join
+request-invited = invited by { $member }
+request-approved = ✅ Approved request { $id } for { $member } to join '{ $group }'
+request-denied = 🚫 Denied request { $id } for { $member } to join '{ $group }'
+
+outbox-queued = ⚠️  The delivery service did not take message { $id }: { $error }
+outbox-queued-hint = It is kept in the outbox and tried again by the next `send`; `outbox { $group }` lists it
+outbox-header = Outbox:
+outbox-header-group = Outbox of '{ $group }':
+outbox-entry = { $id } in '{ $group }' from { $sender } to { $server }, for { $recipients } recipient(s), queued { $queued }, { $attempts } attempt(s)
+outbox-last-error = Last error: { $error }
+outbox-stale = '{ $group }' has moved from epoch { $from } to { $to }, so it can no longer be read; `outbox drop { $id }` and send it again
+outbox-empty = Nothing waiting to be delivered.
+outbox-uploads = { $count } unfinished upload(s) of large messages wait in `transfers`
+outbox-retried = ✅ Delivered message { $id } to { $recipients } recipient(s)
+outbox-dropped = 🗑️  Dropped message { $id } from the outbox of '{ $group }'
+outbox-dropped-hint = It stays in your history here, but the others will not receive it
+outbox-delivered = 📤 Delivered queued message { $id } to '{ $group }'
+outbox-still-queued = ⚠️  Queued message { $id } to '{ $group }' is still not delivered: { $error }
+
+gc-loss-log = Message log of missing group { $group }: { $messages } message(s), { $attachments } attachment(s) ({ $attachment-size }), { $size } stored
+gc-loss-transfer = Unfinished { $direction } of message { $id } for missing group { $group }, { $size } stored
+gc-kept = 📎 { $attachments } attachment(s) ({ $size }) referred to by messages in { $groups } group(s) are kept
+gc-empty = Nothing to collect.
+gc-removed-log = 🗑️  Removed the message log of missing group { $group }
+gc-removed-transfer = 🗑️  Removed the unfinished { $direction } of message { $id }
+gc-done = ✅ Reclaimed { $size } from { $logs } log(s) with { $attachments } attachment(s) and { $transfers } transfer(s)
+
+session-agent-mismatch = ⚠️  The agent does not hold this profile's passphrase; `lock` and start it again
+session-expired = 🔒 Locked after { $minutes } minute(s) unused
+session-count-missing = ⚠️  This profile's count of wrong passphrases is missing, so it is taken as { $failures }
+session-wiping = 🚨 { $failures } wrong passphrases in a row; wiping this profile
+session-failures = ⚠️  { $failures } wrong passphrase(s) were entered since the last unlock
+session-resealed = 🔐 Identity keys re-sealed under a salted passphrase key ({ $iterations } PBKDF2 iterations)
+session-agent = 🔓 Unlocked by the agent on { $socket }
+session-unlocked = 🔓 Unlocked until unused for { $minutes } minute(s)
+session-unprotected = ⚠️  The identity keys have no passphrase; `identity protect` sets one
+protect-changed = ✅ Passphrase changed
+protect-done = ✅ Identity keys sealed under your passphrase
+protect-unlocked = Unlocked until unused for { $minutes } minute(s); `lock` locks now
+unprotect-none = ⚠️  The identity keys have no passphrase
+unprotect-done = ✅ Passphrase removed; identity keys are stored in the clear
+lock-agent = 🔒 Stopped the agent on { $socket }
+lock-done = 🔒 Locked
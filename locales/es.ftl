@@ -0,0 +1,115 @@
+# Mensajes en español. Los que falten se muestran en inglés.
+
+init-start = Inicializando la identidad del usuario...
+init-done = ✅ Usuario '{ $user }' inicializado correctamente
+init-keys = Identidad criptográfica y paquete de claves generados
+init-not-active = '{ $active }' sigue siendo la identidad activa; `identity use { $command }` cambia a '{ $user }'
+
+group-create-start = Creando un grupo MLS nuevo...
+group-created = ✅ Grupo '{ $group }' creado correctamente
+group-id = ID del grupo MLS: { $id }
+group-initial-epoch = Época inicial: 1
+group-secret-generated = Secreto del grupo generado y distribuido
+
+member-add-start = Añadiendo un miembro al grupo...
+member-already = ⚠️  '{ $member }' ya es miembro del grupo
+member-fetch-key-package = Obteniendo el paquete de claves de '{ $member }' del servicio de entrega
+member-add-proposal = Creando la propuesta Add para '{ $member }'
+member-fingerprint = Huella de la clave: { $fingerprint } (compárala con su `whoami`)
+member-new-secret = Generando un secreto de grupo nuevo
+member-distribute = Distribuyendo las claves actualizadas a todos los miembros
+member-added = ✅ '{ $member }' añadido al grupo '{ $group }'
+member-epoch = Época actualizada a: { $epoch }
+member-secret-rotated = Secreto del grupo renovado por seguridad
+
+send-start = Enviando mensaje cifrado...
+send-encrypting = Cifrando el mensaje con el secreto del grupo
+send-epoch = Usando la época: { $epoch }
+send-done = ✅ Mensaje enviado correctamente
+send-encrypted = Mensaje cifrado con la clave del grupo
+send-forward-secrecy = Secreto hacia adelante mantenido
+
+list-header = Mensajes del grupo '{ $group }':
+list-topic = Tema: { $topic }
+list-group-id = ID del grupo: { $id }
+list-epoch = Época actual: { $epoch }
+list-members = Miembros: { $members }
+list-empty = Todavía no hay mensajes.
+list-no-match = Ningún mensaje coincide.
+list-encrypted = Cifrado: { $ciphertext }
+
+groups-header = Grupos:
+groups-entry = { $group }{ $draft } ({ $members } miembro(s), { $messages } mensaje(s)){ $flags }
+groups-empty = Todavía no hay grupos.
+groups-hidden = { $count } grupo(s) archivado(s) oculto(s); `groups --all` los muestra
+
+members-add-start = Añadiendo miembros...
+members-unknown-user = usuario desconocido; se esperaba alice o bob
+members-no-key-package = sin paquete de claves ({ $error })
+members-not-initialized = no inicializado
+members-add-proposal = Propuesta Add para { $member } (huella de clave { $fingerprint })
+members-committing = Confirmando { $count } propuesta(s) Add con un secreto de grupo nuevo
+members-added-one = ✅ { $member } añadido
+members-already = ⚠️  { $member } ya es miembro
+members-pending = ⏳ { $member } espera la aprobación de un administrador (solicitud { $id })
+members-failed = ❌ { $member } no añadido: { $reason }
+members-added = ✅ { $count } miembro(s) añadido(s) a '{ $group }' en un solo commit
+members-epoch = Nueva época: { $epoch }
+members-savings = Un commit tardó { $batched } frente a { $sequential } para { $count } commits de una alta: { $saved } ahorrado, { $epochs } épocas menos, { $batched-size } enviado en lugar de { $sequential-size }
+
+policy-start = Actualizando la política del grupo...
+policy-unchanged = ⚠️  No hay nada que cambiar
+policy-committing = Confirmando la política nueva en la época { $epoch }
+policy-set = ✅ El grupo '{ $group }' ahora es { $policy }
+join-start = Uniéndose al grupo mediante un commit externo...
+join-already = ⚠️  { $user } ya es miembro de '{ $group }'
+join-committing = Commit externo que añade a { $user } en la época { $epoch }
+join-done = ✅ { $user } se unió a '{ $group }'
+request-already = ⏳ { $member } ya espera unirse a '{ $group }' (solicitud { $id })
+request-queued = ⏳ El grupo '{ $group }' necesita la aprobación de un administrador; { $member } queda en cola como solicitud { $id }
+request-ask = Pide a { $admins } que ejecute `approve { $id }`
+requests-empty = No hay solicitudes pendientes en '{ $group }'.
+request-asked = pidió unirse
+request-invited = invitado por { $member }
+request-approved = ✅ Solicitud { $id } aprobada: { $member } se une a '{ $group }'
+request-denied = 🚫 Solicitud { $id } denegada: { $member } no se une a '{ $group }'
+
+outbox-queued = ⚠️  El servicio de entrega no aceptó el mensaje { $id }: { $error }
+outbox-queued-hint = Se guarda en la bandeja de salida y se reintenta con el próximo `send`; `outbox { $group }` lo muestra
+outbox-header = Bandeja de salida:
+outbox-header-group = Bandeja de salida de '{ $group }':
+outbox-entry = { $id } en '{ $group }' de { $sender } a { $server }, para { $recipients } destinatario(s), en cola desde { $queued }, { $attempts } intento(s)
+outbox-last-error = Último error: { $error }
+outbox-stale = '{ $group }' pasó de la época { $from } a la { $to }, así que ya no se puede leer; `outbox drop { $id }` y envíalo de nuevo
+outbox-empty = No hay nada pendiente de entrega.
+outbox-uploads = { $count } subida(s) sin terminar de mensajes grandes esperan en `transfers`
+outbox-retried = ✅ Mensaje { $id } entregado a { $recipients } destinatario(s)
+outbox-dropped = 🗑️  Mensaje { $id } descartado de la bandeja de salida de '{ $group }'
+outbox-dropped-hint = Sigue en tu historial aquí, pero los demás no lo recibirán
+outbox-delivered = 📤 Mensaje en cola { $id } entregado a '{ $group }'
+outbox-still-queued = ⚠️  El mensaje en cola { $id } para '{ $group }' sigue sin entregarse: { $error }
+
+gc-loss-log = Registro de mensajes del grupo desaparecido { $group }: { $messages } mensaje(s), { $attachments } adjunto(s) ({ $attachment-size }), { $size } almacenado(s)
+gc-loss-transfer = { $direction } sin terminar del mensaje { $id } para el grupo desaparecido { $group }, { $size } almacenado(s)
+gc-kept = 📎 Se conservan { $attachments } adjunto(s) ({ $size }) a los que se refieren mensajes de { $groups } grupo(s)
+gc-empty = No hay nada que recoger.
+gc-removed-log = 🗑️  Eliminado el registro de mensajes del grupo desaparecido { $group }
+gc-removed-transfer = 🗑️  Eliminada la { $direction } sin terminar del mensaje { $id }
+gc-done = ✅ Recuperados { $size } de { $logs } registro(s) con { $attachments } adjunto(s) y { $transfers } transferencia(s)
+
+session-agent-mismatch = ⚠️  El agente no tiene la frase de contraseña de este perfil; `lock` y vuelve a iniciarlo
+session-expired = 🔒 Bloqueado tras { $minutes } minuto(s) sin uso
+session-count-missing = ⚠️  Falta el recuento de frases de contraseña erróneas de este perfil, así que se toma como { $failures }
+session-wiping = 🚨 { $failures } frases de contraseña erróneas seguidas; borrando este perfil
+session-failures = ⚠️  Se introdujeron { $failures } frase(s) de contraseña errónea(s) desde el último desbloqueo
+session-resealed = 🔐 Claves de identidad selladas de nuevo con una clave de frase de contraseña con sal ({ $iterations } iteraciones de PBKDF2)
+session-agent = 🔓 Desbloqueado por el agente en { $socket }
+session-unlocked = 🔓 Desbloqueado hasta { $minutes } minuto(s) sin uso
+session-unprotected = ⚠️  Las claves de identidad no tienen frase de contraseña; `identity protect` pone una
+protect-changed = ✅ Frase de contraseña cambiada
+protect-done = ✅ Claves de identidad selladas con tu frase de contraseña
+protect-unlocked = Desbloqueado hasta { $minutes } minuto(s) sin uso; `lock` bloquea ahora
+unprotect-none = ⚠️  Las claves de identidad no tienen frase de contraseña
+unprotect-done = ✅ Frase de contraseña eliminada; las claves de identidad se guardan sin cifrar
+lock-agent = 🔒 Agente detenido en { $socket }
+lock-done = 🔒 Bloqueado
@@ -0,0 +1,97 @@
+//! Operator commands for a running delivery service (`serve-admin`)
+//!
+//! Each command is one admin call, made with the namespace's admin token in
+//! place of a user token.
+
+use anyhow::Result;
+use chrono::Utc;
+use colored::*;
+
+use crate::client::DeliveryClient;
+use crate::network::ago;
+
+/// Print every user the namespace knows about
+pub fn show_users(client: &mut DeliveryClient) -> Result<()> {
+    let users = client.list_users()?;
+    if users.is_empty() {
+        println!("No users yet.");
+        return Ok(());
+    }
+    println!("{}", format!("Users ({})", users.len()).blue());
+    for user in users {
+        let presence = match (user.online, user.last_seen) {
+            (true, _) => "● online".green().to_string(),
+            (false, Some(seen)) => format!("○ last seen {}", ago(seen)).dimmed().to_string(),
+            (false, None) => "○ never seen".dimmed().to_string(),
+        };
+        let key_package = match user.key_package_published_at {
+            Some(at) => format!("key package {}", ago(at)),
+            None => "no key package".to_string(),
+        };
+        let account = if user.registered { "registered" } else { "unregistered" };
+        println!("   {} — {}, {}, {}", user.user.yellow(), presence, account, key_package);
+    }
+    Ok(())
+}
+
+/// Print the groups the server has relayed messages for
+pub fn show_groups(client: &mut DeliveryClient) -> Result<()> {
+    let groups = client.list_groups()?;
+    if groups.is_empty() {
+        println!("No groups seen yet.");
+        return Ok(());
+    }
+    println!("{}", format!("Groups ({})", groups.len()).blue());
+    for group in groups {
+        println!(
+            "   {} — {} message(s), last {}, members: {}",
+            group.group_id,
+            group.messages,
+            ago(group.last_message_at),
+            group.members.join(", ")
+        );
+    }
+    Ok(())
+}
+
+/// Users whose key packages `purge_key_packages` would remove for `days`
+pub fn stale_key_packages(client: &mut DeliveryClient, days: u64) -> Result<Vec<String>> {
+    let cutoff = Utc::now() - chrono::Duration::seconds(days.saturating_mul(86400).min(i64::MAX as u64) as i64);
+    Ok(client.list_users()?
+        .into_iter()
+        .filter(|user| user.key_package_published_at.is_some_and(|at| at < cutoff))
+        .map(|user| user.user)
+        .collect())
+}
+
+/// Remove key packages published more than `days` days ago
+pub fn purge_key_packages(client: &mut DeliveryClient, days: u64) -> Result<()> {
+    let users = client.purge_key_packages(days.saturating_mul(86400))?;
+    if users.is_empty() {
+        println!("No key packages older than {} day(s).", days);
+    } else {
+        println!("✅ Purged {} key package(s): {}", users.len(), users.join(", "));
+    }
+    Ok(())
+}
+
+/// Revoke `user`'s token, ending any subscriptions they hold
+pub fn revoke_token(client: &mut DeliveryClient, user: &str) -> Result<()> {
+    client.revoke_token(user)?;
+    println!("✅ Revoked the token for '{}'; they must `login` again", user);
+    Ok(())
+}
+
+/// Print how many envelopes wait in each inbox
+pub fn show_queues(client: &mut DeliveryClient) -> Result<()> {
+    let queues = client.queue_depths()?;
+    if queues.is_empty() {
+        println!("All inboxes are empty.");
+        return Ok(());
+    }
+    println!("{}", "Queued envelopes".blue());
+    for queue in queues {
+        println!("   {}: {}", queue.user.yellow(), queue.depth);
+    }
+    Ok(())
+}
@@ -0,0 +1,245 @@
+//! `agent`: a key agent holding an unlocked profile's store key in memory
+//!
+//! With the agent running, commands on a profile protected by `identity
+//! protect` send it each sealed private key to open, and each key to seal
+//! when saving, over the Unix socket `agent/agent.sock` in the data directory.
+//! The passphrase is entered once, when the agent starts, and no session key
+//! is written to disk: the agent clears `session.json` and commands do not
+//! write one while it answers. `lock` tells the agent to forget the key and
+//! exit.
+//!
+//! Each connection carries one JSON request line and one JSON response line,
+//! and one that sends nothing is dropped after `REQUEST_TIMEOUT` so it cannot
+//! hold up the others. The socket is bound inside a directory only its owner
+//! can enter, so other users on the machine cannot ask the agent for keys,
+//! not even in the moment between `bind` and the socket's own `chmod`. The mock's signatures are keyed by
+//! the public key and need no private key, so opening and sealing are all
+//! the agent serves.
+//!
+//! The agent needs Unix domain sockets. Elsewhere `agent` fails, no agent is
+//! ever found running, and commands keep the session in `session.json`.
+
+use anyhow::{anyhow, Context, Result};
+#[cfg(unix)]
+use colored::*;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+#[cfg(unix)]
+use std::{
+    fs,
+    io::{BufRead, BufReader, Read, Write},
+    os::unix::{
+        fs::{DirBuilderExt, PermissionsExt},
+        net::{UnixListener, UnixStream},
+    },
+    time::Duration,
+};
+
+use crate::app::MlsChatApp;
+#[cfg(unix)]
+use crate::crypto;
+
+/// How long the agent waits for a connection's request, and for it to take the answer
+#[cfg(unix)]
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+/// Longest request line the agent reads, in bytes; sealed keys are far shorter
+#[cfg(unix)]
+const MAX_REQUEST_LEN: u64 = 64 * 1024;
+/// Why `agent` fails on platforms without Unix domain sockets
+const UNSUPPORTED: &str = "The agent listens on a Unix domain socket, which this platform does not have";
+
+/// The agent's socket for a profile stored in `data_dir`, in a directory of its own
+pub fn socket_path(data_dir: &Path) -> PathBuf {
+    data_dir.join("agent").join("agent.sock")
+}
+
+/// One request to the agent
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum AgentRequest {
+    Open { sealed: String }, // A sealed private key, base64
+    Seal { plaintext: String },
+    Lock,
+}
+
+/// The agent's answer to one request
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum AgentResponse {
+    Opened { plaintext: String },
+    Sealed { sealed: String },
+    Locked,
+    Error { message: String },
+}
+
+/// Connects to a running agent; cheap to create, as nothing happens until a request
+#[derive(Debug, Clone)]
+pub struct AgentClient {
+    path: PathBuf,
+}
+
+impl AgentClient {
+    /// A client for the agent listening on `path`
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// The socket this client connects to
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// True if an agent answers on the socket
+    #[cfg(unix)]
+    pub fn is_running(&self) -> bool {
+        UnixStream::connect(&self.path).is_ok()
+    }
+
+    /// False: no agent can run here
+    #[cfg(not(unix))]
+    pub fn is_running(&self) -> bool {
+        false
+    }
+
+    /// Open a sealed private key
+    pub fn open(&self, sealed: &str) -> Result<String> {
+        match self.request(&AgentRequest::Open { sealed: sealed.to_string() })? {
+            AgentResponse::Opened { plaintext } => Ok(plaintext),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Seal a private key under the agent's store key
+    pub fn seal(&self, plaintext: &str) -> Result<String> {
+        match self.request(&AgentRequest::Seal { plaintext: plaintext.to_string() })? {
+            AgentResponse::Sealed { sealed } => Ok(sealed),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Tell the agent to forget its key and exit
+    pub fn lock(&self) -> Result<()> {
+        match self.request(&AgentRequest::Lock)? {
+            AgentResponse::Locked => Ok(()),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn request(&self, _request: &AgentRequest) -> Result<AgentResponse> {
+        Err(anyhow!(UNSUPPORTED))
+    }
+
+    #[cfg(unix)]
+    fn request(&self, request: &AgentRequest) -> Result<AgentResponse> {
+        let mut stream = UnixStream::connect(&self.path)
+            .with_context(|| format!("No agent is listening on {}", self.path.display()))?;
+        let mut line = serde_json::to_string(request)?;
+        line.push('\n');
+        stream.write_all(line.as_bytes())?;
+        let mut reader = BufReader::new(stream);
+        let mut answer = String::new();
+        if reader.read_line(&mut answer)? == 0 {
+            return Err(anyhow!("The agent closed the connection"));
+        }
+        serde_json::from_str(&answer).context("Malformed answer from the agent")
+    }
+}
+
+fn unexpected(response: AgentResponse) -> anyhow::Error {
+    match response {
+        AgentResponse::Error { message } => anyhow!("Agent: {}", message),
+        other => anyhow!("Unexpected answer from the agent: {:?}", other),
+    }
+}
+
+/// Answer one request with the store `key`
+#[cfg(unix)]
+fn answer(key: &[u8; 32], request: AgentRequest) -> AgentResponse {
+    match request {
+        AgentRequest::Open { sealed } => {
+            let opened = crypto::base64::decode(&sealed)
+                .and_then(|sealed| crypto::open(key, &sealed))
+                .and_then(|plaintext| Ok(String::from_utf8(plaintext)?));
+            match opened {
+                Ok(plaintext) => AgentResponse::Opened { plaintext },
+                Err(err) => AgentResponse::Error { message: format!("{:#}", err) },
+            }
+        }
+        AgentRequest::Seal { plaintext } => AgentResponse::Sealed {
+            sealed: crypto::base64::encode(&crypto::seal(key, plaintext.as_bytes())),
+        },
+        AgentRequest::Lock => AgentResponse::Locked,
+    }
+}
+
+impl MlsChatApp {
+    /// Unlock the profile with its passphrase and serve its store key on the agent socket until `lock`
+    ///
+    /// `passphrase` is used when given; otherwise it is read from stdin.
+    pub fn run_agent(&mut self, passphrase: Option<&str>) -> Result<()> {
+        let agent = self.agent.clone().context("No agent socket for this profile")?;
+        if !self.is_protected() {
+            return Err(anyhow!("The identity keys have no passphrase; run `identity protect` first"));
+        }
+        if !cfg!(unix) {
+            return Err(anyhow!(UNSUPPORTED));
+        }
+        if agent.is_running() {
+            return Err(anyhow!("An agent is already running on {}", agent.path().display()));
+        }
+        let key = self.unlock_with_passphrase(passphrase)?;
+        // The agent holds the key from here on, so drop any copy cached on disk
+        self.storage.delete_session()?;
+        serve(&agent, &key)
+    }
+}
+
+/// Listen on `agent`'s socket, answering with the store `key` until told to lock
+#[cfg(unix)]
+fn serve(agent: &AgentClient, key: &[u8; 32]) -> Result<()> {
+    let dir = agent.path().parent().context("Agent socket has no directory")?;
+    fs::DirBuilder::new().recursive(true).mode(0o700).create(dir)
+        .with_context(|| format!("Failed to create {}", dir.display()))?;
+    // An existing directory keeps the mode it was made with, so tighten it before binding
+    fs::set_permissions(dir, fs::Permissions::from_mode(0o700))
+        .with_context(|| format!("Failed to make {} private", dir.display()))?;
+    if agent.path().exists() {
+        fs::remove_file(agent.path()).with_context(|| format!("Failed to remove stale socket {}", agent.path().display()))?;
+    }
+    let listener = UnixListener::bind(agent.path())
+        .with_context(|| format!("Failed to listen on {}", agent.path().display()))?;
+    fs::set_permissions(agent.path(), fs::Permissions::from_mode(0o600))?;
+    println!("{}", format!("🔐 Agent listening on {}", agent.path().display()).green());
+    println!("   Commands on this profile use it instead of asking for the passphrase; `lock` stops it");
+
+    for stream in listener.incoming() {
+        let Ok(mut stream) = stream else { continue };
+        if stream.set_read_timeout(Some(REQUEST_TIMEOUT)).and_then(|()| stream.set_write_timeout(Some(REQUEST_TIMEOUT))).is_err() {
+            continue;
+        }
+        let mut line = String::new();
+        if BufReader::new(&stream).take(MAX_REQUEST_LEN).read_line(&mut line).is_err() {
+            continue;
+        }
+        let response = match serde_json::from_str::<AgentRequest>(&line) {
+            Ok(request) => answer(key, request),
+            Err(err) => AgentResponse::Error { message: format!("Malformed request: {}", err) },
+        };
+        let locked = matches!(response, AgentResponse::Locked);
+        let mut json = serde_json::to_string(&response)?;
+        json.push('\n');
+        let _ = stream.write_all(json.as_bytes());
+        if locked {
+            break;
+        }
+    }
+    fs::remove_file(agent.path()).ok();
+    println!("🔒 Agent locked and stopped");
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn serve(_agent: &AgentClient, _key: &[u8; 32]) -> Result<()> {
+    Err(anyhow!(UNSUPPORTED))
+}
@@ -0,0 +1,302 @@
+//! REST API over the chat engine, for web front ends and curl-based demos
+//!
+//! Every request must carry `Authorization: Bearer <token>`. Bodies and
+//! responses are JSON with camelCase field names; groups are addressed by
+//! their MLS group ID.
+//!
+//! ```text
+//! GET  /groups                   List groups
+//! POST /groups                   Create a group          {"name": "Team"}
+//! GET  /groups/:id               Show a group
+//! POST /groups/:id/members       Add a member            {"member": "bob"}
+//! GET  /groups/:id/messages      List messages
+//! POST /groups/:id/messages      Send a message          {"content": "Hi"}
+//! GET  /keypackages/:user        Fetch a user's key package
+//! ```
+//!
+//! Errors come back as `{"error": "..."}` with a 4xx status.
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use colored::*;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    io::BufReader,
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Mutex, MutexGuard},
+    thread,
+    time::Duration,
+};
+
+use crate::app::MlsChatApp;
+use crate::crypto;
+use crate::delivery::{RpcError, StatusCode};
+use crate::http::{HttpRequest, HttpResponse};
+use crate::model::{ChatGroup, ChatMessage, ContentType, UserName};
+
+/// Default address for `api`
+pub const DEFAULT_API_ADDR: &str = "127.0.0.1:8080";
+
+/// How long a connection may take to send its request
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// HTTP server exposing one profile's chat engine
+#[derive(Clone)]
+pub struct ApiServer {
+    app: Arc<Mutex<MlsChatApp>>,
+    token: Arc<String>,
+}
+
+/// A group as returned by the API
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GroupView {
+    pub group_id: String,
+    pub name: String,
+    pub epoch: u32,
+    pub members: Vec<String>,
+    pub message_count: usize,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub has_draft: bool,
+}
+
+/// A message as returned by the API
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MessageView {
+    pub id: String,
+    pub sender: String,
+    pub content: String,
+    #[serde(default)]
+    pub content_type: ContentType,
+    pub timestamp: DateTime<Utc>,
+    pub epoch: u32,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mentions: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct CreateGroupBody {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct AddMemberBody {
+    member: String,
+}
+
+#[derive(Deserialize)]
+struct SendMessageBody {
+    content: String,
+}
+
+impl From<&ChatGroup> for GroupView {
+    fn from(group: &ChatGroup) -> Self {
+        Self {
+            group_id: group.group_id.clone(),
+            name: group.name.clone(),
+            epoch: group.mls_group.epoch,
+            members: group.members.clone(),
+            message_count: group.message_count,
+            has_draft: group.draft.is_some(),
+        }
+    }
+}
+
+impl From<&ChatMessage> for MessageView {
+    fn from(message: &ChatMessage) -> Self {
+        Self {
+            id: message.id.clone(),
+            sender: message.sender.clone(),
+            content: message.content.clone(),
+            content_type: message.content_type,
+            timestamp: message.timestamp,
+            epoch: message.epoch,
+            mentions: message.mentions.clone(),
+        }
+    }
+}
+
+impl ApiServer {
+    /// Serve `app`, accepting requests that present `token`
+    pub fn new(app: MlsChatApp, token: String) -> Result<Self> {
+        if token.is_empty() {
+            return Err(anyhow!("API token must not be empty"));
+        }
+        Ok(Self {
+            app: Arc::new(Mutex::new(app)),
+            token: Arc::new(token),
+        })
+    }
+
+    /// Accept connections forever, handling each on its own thread
+    pub fn serve(&self, listener: TcpListener) -> Result<()> {
+        println!("{}", format!("REST API listening on http://{}", listener.local_addr()?).green());
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(err) => {
+                    eprintln!("⚠️  Failed to accept connection: {}", err);
+                    continue;
+                }
+            };
+            let server = self.clone();
+            thread::spawn(move || {
+                if let Err(err) = server.handle_connection(stream) {
+                    eprintln!("⚠️  HTTP connection failed: {}", err);
+                }
+            });
+        }
+        Ok(())
+    }
+
+    fn app(&self) -> MutexGuard<'_, MlsChatApp> {
+        self.app.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn handle_connection(&self, stream: TcpStream) -> Result<()> {
+        stream.set_read_timeout(Some(READ_TIMEOUT))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut writer = stream;
+
+        let response = match HttpRequest::read(&mut reader) {
+            Ok(Some(request)) => {
+                let response = self.handle_request(&request);
+                println!("🌐 {} {} → {}", request.method, request.path, response.status);
+                response
+            }
+            Ok(None) => return Ok(()),
+            Err(err) => HttpResponse::error(400, err.to_string()),
+        };
+        response.write_to(&mut writer)
+    }
+
+    fn handle_request(&self, request: &HttpRequest) -> HttpResponse {
+        if !self.authorized(request) {
+            return HttpResponse::error(401, "Missing or invalid bearer token");
+        }
+
+        let segments: Vec<&str> = request.path.trim_matches('/').split('/').collect();
+        let result = match (request.method.as_str(), segments.as_slice()) {
+            ("GET", ["groups"]) => self.list_groups(),
+            ("POST", ["groups"]) => parse_body(request).and_then(|body| self.create_group(body)),
+            ("GET", ["groups", group_id]) => self.show_group(group_id),
+            ("POST", ["groups", group_id, "members"]) => {
+                parse_body(request).and_then(|body| self.add_member(group_id, body))
+            }
+            ("GET", ["groups", group_id, "messages"]) => self.list_messages(group_id),
+            ("POST", ["groups", group_id, "messages"]) => {
+                parse_body(request).and_then(|body| self.send_message(group_id, body))
+            }
+            ("GET", ["keypackages", user]) => self.key_package(user),
+            (_, ["groups"] | ["groups", _] | ["groups", _, "members" | "messages"] | ["keypackages", _]) => {
+                Err(HttpResponse::error(405, format!("{} not allowed on {}", request.method, request.path)))
+            }
+            _ => Err(HttpResponse::error(404, format!("No route for {}", request.path))),
+        };
+        result.unwrap_or_else(|response| response)
+    }
+
+    fn authorized(&self, request: &HttpRequest) -> bool {
+        match request.header("authorization").and_then(|value| value.strip_prefix("Bearer ")) {
+            Some(token) => crypto::constant_eq(token.as_bytes(), self.token.as_bytes()),
+            None => false,
+        }
+    }
+
+    fn list_groups(&self) -> Result<HttpResponse, HttpResponse> {
+        let app = self.app();
+        let mut groups: Vec<GroupView> = app.groups.values().map(GroupView::from).collect();
+        groups.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(HttpResponse::json(200, &groups))
+    }
+
+    fn create_group(&self, body: CreateGroupBody) -> Result<HttpResponse, HttpResponse> {
+        let mut app = self.app();
+        if app.group(&body.name).is_some() {
+            return Err(HttpResponse::error(409, format!("Group '{}' already exists", body.name)));
+        }
+        app.create_group(body.name.clone()).map_err(engine_error)?;
+        let group = app.group(&body.name).ok_or_else(|| HttpResponse::error(500, "Group vanished after creation"))?;
+        Ok(HttpResponse::json(201, &GroupView::from(group)))
+    }
+
+    fn show_group(&self, group_id: &str) -> Result<HttpResponse, HttpResponse> {
+        let app = self.app();
+        let name = group_name(&app, group_id)?;
+        let group = app.group(&name).ok_or_else(|| group_not_found(group_id))?;
+        Ok(HttpResponse::json(200, &GroupView::from(group)))
+    }
+
+    fn add_member(&self, group_id: &str, body: AddMemberBody) -> Result<HttpResponse, HttpResponse> {
+        let member = UserName::from_str(&body.member, true)
+            .map_err(|_| HttpResponse::error(400, format!("Unknown user '{}'", body.member)))?;
+        let mut app = self.app();
+        let name = group_name(&app, group_id)?;
+        app.add_member(name.clone(), member).map_err(engine_error)?;
+        let group = app.group(&name).ok_or_else(|| group_not_found(group_id))?;
+        Ok(HttpResponse::json(200, &GroupView::from(group)))
+    }
+
+    fn list_messages(&self, group_id: &str) -> Result<HttpResponse, HttpResponse> {
+        let mut app = self.app();
+        let name = group_name(&app, group_id)?;
+        let group = app.load_messages(&name).map_err(engine_error)?;
+        let messages: Vec<MessageView> = group.messages.as_deref().unwrap_or_default()
+            .iter()
+            .map(MessageView::from)
+            .collect();
+        Ok(HttpResponse::json(200, &messages))
+    }
+
+    fn send_message(&self, group_id: &str, body: SendMessageBody) -> Result<HttpResponse, HttpResponse> {
+        let mut app = self.app();
+        let name = group_name(&app, group_id)?;
+        app.send_message(name.clone(), body.content).map_err(engine_error)?;
+        let group = app.load_messages(&name).map_err(engine_error)?;
+        let message = group.messages.as_deref().unwrap_or_default()
+            .last()
+            .ok_or_else(|| HttpResponse::error(500, "Sent message not found"))?;
+        Ok(HttpResponse::json(201, &MessageView::from(message)))
+    }
+
+    fn key_package(&self, user: &str) -> Result<HttpResponse, HttpResponse> {
+        let user = UserName::from_str(user, true).map(|user| user.to_string()).unwrap_or_else(|_| user.to_string());
+        let mut app = self.app();
+        let key_package = app.key_package(&user)
+            .map_err(|err| match rate_limited(&err) {
+                true => engine_error(err),
+                false => HttpResponse::error(404, format!("No key package for '{}': {}", user, err)),
+            })?;
+        Ok(HttpResponse::json(200, &key_package))
+    }
+}
+
+fn parse_body<T: DeserializeOwned>(request: &HttpRequest) -> Result<T, HttpResponse> {
+    serde_json::from_slice(&request.body)
+        .map_err(|err| HttpResponse::error(400, format!("Invalid JSON body: {}", err)))
+}
+
+fn group_name(app: &MlsChatApp, group_id: &str) -> Result<String, HttpResponse> {
+    app.group_name_by_id(group_id).ok_or_else(|| group_not_found(group_id))
+}
+
+fn group_not_found(group_id: &str) -> HttpResponse {
+    HttpResponse::error(404, format!("Group '{}' not found", group_id))
+}
+
+/// Engine errors are rejected requests, such as sending to a group you are not in
+///
+/// Calls the delivery service turned down for rate or size limits become 429.
+fn engine_error(err: anyhow::Error) -> HttpResponse {
+    let status = if rate_limited(&err) { 429 } else { 400 };
+    HttpResponse::error(status, format!("{:#}", err))
+}
+
+/// Whether the delivery service turned a call down with `ResourceExhausted`
+fn rate_limited(err: &anyhow::Error) -> bool {
+    err.chain()
+        .filter_map(|cause| cause.downcast_ref::<RpcError>())
+        .any(|rpc| rpc.code == StatusCode::ResourceExhausted)
+}
@@ -0,0 +1,1478 @@
+//! Application logic for groups, members, and messages
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, TimeDelta, Utc};
+use clap::ValueEnum;
+use colored::*;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::{thread, time::Duration};
+use uuid::Uuid;
+
+use crate::agent::AgentClient;
+use crate::bans;
+use crate::bench;
+use crate::commit::{self, Proposals};
+use crate::content::Content;
+use crate::crypto;
+use crate::emoji;
+use crate::history;
+use crate::i18n::tr;
+use crate::markdown;
+use crate::mentions::{highlight, parse_mentions};
+use crate::notify::Notifier;
+use crate::output;
+use crate::pins::{print_pinned, short_id};
+use crate::policy;
+use crate::preview::{self, Graphics};
+use crate::model::{
+    ChatGroup, ChatMessage, Ciphersuite, ContentType, GroupMetadata, GroupPolicy, IdentityStatus, KeyPackageStatus, MockKey, MockMlsGroup, Mute, Notify,
+    Retention, RotationPolicy, Transfer, UserName,
+};
+use crate::protocol;
+use crate::session::{self, StoreKey};
+use crate::cache::CachedStorage;
+use crate::storage::{FileStorage, Storage};
+use crate::theme::{self, Role};
+use crate::timestamps::Timestamps;
+use crate::transparency::{self, TransparencyLog};
+use crate::transport::Transport;
+use crate::tree::RatchetTree;
+use crate::trust;
+
+/// How `list` prints messages
+#[derive(Debug, Clone, Default)]
+pub struct ListOptions {
+    pub status: bool, // Mark the user's own messages ✓ when delivered to all and ✓✓ when read by all
+    pub ids: bool,    // Start each message with the first characters of its ID
+    pub raw: bool,    // Print content as sent, without rendering Markdown
+    pub preview: Option<Graphics>, // How to draw image attachments; None prints their name and size
+    pub times: Timestamps, // How to show when each message was sent
+}
+
+/// What became of one member given to `add-members`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AddOutcome {
+    Added,
+    AlreadyMember,
+    Pending(String), // Waiting for an admin's approval under this request ID
+    Failed(String), // Why they could not be added
+}
+
+/// Which messages `list` shows; the default shows them all
+#[derive(Debug, Clone, Default)]
+pub struct MessageFilter {
+    pub from: Option<String>, // Only messages from this member, in any case
+    pub content_type: Option<ContentType>,
+    pub epoch: Option<u32>,
+    pub last: Option<usize>, // Only the last this many that pass the conditions above
+}
+
+impl MessageFilter {
+    /// True if `message` passes every condition set
+    pub fn matches(&self, message: &ChatMessage) -> bool {
+        self.from.as_ref().is_none_or(|from| message.sender.eq_ignore_ascii_case(from))
+            && self.content_type.is_none_or(|content_type| message.content_type == content_type)
+            && self.epoch.is_none_or(|epoch| message.epoch == epoch)
+    }
+
+    /// True if no condition is set; `last` only trims what passes, so it does not count
+    pub fn is_empty(&self) -> bool {
+        self.from.is_none() && self.content_type.is_none() && self.epoch.is_none()
+    }
+}
+
+/// How often `follow_messages` checks for new messages
+const FOLLOW_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The last message `follow_messages` has passed, by ID and time
+///
+/// Retention and pruning can drop messages from the front of the log between
+/// rounds, so this is not a count. If the message itself has been dropped,
+/// everything sent after it is new.
+#[derive(Debug, Clone, Default)]
+struct FollowCursor {
+    id: Option<String>,
+    timestamp: Option<DateTime<Utc>>,
+}
+
+impl FollowCursor {
+    /// The messages after the last one passed
+    fn after<'a>(&self, messages: &'a [ChatMessage]) -> impl Iterator<Item = &'a ChatMessage> + 'a {
+        let found = self.id.as_ref().and_then(|id| messages.iter().rposition(|message| &message.id == id));
+        let timestamp = self.timestamp;
+        messages.iter()
+            .enumerate()
+            .filter(move |(index, message)| match (found, timestamp) {
+                (Some(found), _) => *index > found,
+                (None, Some(timestamp)) => message.timestamp > timestamp,
+                (None, None) => true,
+            })
+            .map(|(_, message)| message)
+    }
+
+    /// Move past every message in `messages`
+    fn pass(&mut self, messages: &[ChatMessage]) {
+        if let Some(last) = messages.last() {
+            self.id = Some(last.id.clone());
+            self.timestamp = Some(last.timestamp);
+        }
+    }
+}
+
+/// Main application state
+pub struct MlsChatApp {
+    pub(crate) current_user: Option<UserName>,
+    pub(crate) groups: HashMap<String, ChatGroup>,
+    pub(crate) user_keys: HashMap<String, MockKey>,
+    pub(crate) storage: Box<dyn Storage>,
+    pub(crate) transport: Option<Box<dyn Transport>>, // Delivery service, when networked
+    pub(crate) server: String,                        // The transport's address, as given, for the outbox
+    pub(crate) dirty_groups: HashSet<String>,         // Groups changed since the last save
+    pub(crate) identity_dirty: bool,                  // User keys or current user changed since the last save
+    pub(crate) retention: Retention,                  // Applied as each group's messages are loaded
+    pub(crate) rotation: RotationPolicy,              // When `send` rotates the sender's key first
+    pub(crate) aliases: HashMap<String, String>,      // Short name -> group name, from the config file
+    pub(crate) shortcodes: bool,                      // Expand `:wave:`-style shortcodes in sent messages
+    pub(crate) transparency_log: Option<TransparencyLog>, // Where keys are published and fetched ones checked
+    pub(crate) store_key: Option<StoreKey>,           // Seals identity keys, once the profile's passphrase is entered
+    pub(crate) agent: Option<AgentClient>,            // Where a running `agent` would listen
+    pub(crate) idle_timeout: TimeDelta,               // How long a session stays unlocked unused
+    pub(crate) wipe_after_failures: Option<u32>,      // Wrong passphrases in a row that wipe the profile
+    pub(crate) history_epochs: u32,                   // How many epochs back `share-history` reaches by default
+    pub(crate) transfers: HashMap<String, Transfer>,  // Downloads that received chunks since the last save, by message ID
+    pub(crate) renames: Vec<(String, String)>,        // Groups renamed in this run, old name first, for the config file
+    pub(crate) notifier: Option<Notifier>,            // Raises desktop notifications for what `list --follow` prints
+}
+
+impl MlsChatApp {
+    /// Create an app backed by files in the default `mls_chat_data` directory
+    pub fn new() -> Result<Self> {
+        Self::open("mls_chat_data")
+    }
+
+    /// Create an app backed by files in `data_dir`
+    pub fn open(data_dir: &str) -> Result<Self> {
+        let storage = FileStorage::open(data_dir)?;
+        Ok(Self::with_storage(Box::new(storage)))
+    }
+
+    /// Like `open`, with what is read kept in memory for reuse, up to `messages` messages (see `cache`)
+    ///
+    /// For processes that stay up, like `list --follow`, `bot`, and `api`.
+    pub fn open_cached(data_dir: &str, messages: usize) -> Result<Self> {
+        let storage = FileStorage::open(data_dir)?;
+        Ok(Self::with_storage(Box::new(CachedStorage::new(Box::new(storage), messages))))
+    }
+
+    /// Create an app backed by the given storage
+    ///
+    /// Call `load_state` afterwards to pick up anything already persisted.
+    pub fn with_storage(storage: Box<dyn Storage>) -> Self {
+        Self {
+            current_user: None,
+            groups: HashMap::new(),
+            user_keys: HashMap::new(),
+            storage,
+            transport: None,
+            server: String::new(),
+            dirty_groups: HashSet::new(),
+            identity_dirty: false,
+            retention: Retention::default(),
+            rotation: RotationPolicy::default(),
+            aliases: HashMap::new(),
+            shortcodes: true,
+            transparency_log: None,
+            store_key: None,
+            agent: None,
+            idle_timeout: session::DEFAULT_IDLE_TIMEOUT,
+            wipe_after_failures: None,
+            history_epochs: history::DEFAULT_SHARE_EPOCHS,
+            transfers: HashMap::new(),
+            renames: Vec::new(),
+            notifier: None,
+        }
+    }
+
+    /// Route key packages and messages through the delivery service at `server`
+    pub fn set_transport(&mut self, transport: Box<dyn Transport>, server: &str) {
+        self.transport = Some(transport);
+        self.server = server.to_string();
+    }
+
+    /// Delete messages beyond `retention` whenever a group's history is loaded
+    pub fn set_retention(&mut self, retention: Retention) {
+        self.retention = retention;
+    }
+
+    /// Rotate the sender's key with an Update before `send` once `policy` says it is due
+    pub fn set_rotation(&mut self, policy: RotationPolicy) {
+        self.rotation = policy;
+    }
+
+    /// Publish keys to `log` and check key packages fetched from the delivery service against it
+    pub fn set_transparency_log(&mut self, log: TransparencyLog) {
+        self.transparency_log = Some(log);
+    }
+
+    /// Let `resolve_group` accept these short names for groups
+    pub fn set_aliases(&mut self, aliases: HashMap<String, String>) {
+        self.aliases = aliases;
+    }
+
+    /// The groups renamed since the last call, old name first, so aliases and `default-group` can follow them
+    pub fn take_renames(&mut self) -> Vec<(String, String)> {
+        std::mem::take(&mut self.renames)
+    }
+
+    /// Note that `old` is now called `new`, pointing aliases at the new name
+    pub(crate) fn note_rename(&mut self, old: &str, new: &str) {
+        for target in self.aliases.values_mut().filter(|target| *target == old) {
+            *target = new.to_string();
+        }
+        self.renames.push((old.to_string(), new.to_string()));
+    }
+
+    /// Whether `send_message` turns `:wave:`-style shortcodes into emoji; on by default
+    pub fn set_emoji_shortcodes(&mut self, enabled: bool) {
+        self.shortcodes = enabled;
+    }
+
+    /// The group a name typed by the user refers to: the group itself, else an alias
+    ///
+    /// Unknown names fail with the closest group names as suggestions.
+    pub fn resolve_group(&self, name: &str) -> Result<String> {
+        if self.groups.contains_key(name) {
+            return Ok(name.to_string());
+        }
+        if let Some(target) = self.aliases.get(name) {
+            return match self.groups.contains_key(target) {
+                true => Ok(target.clone()),
+                false => Err(anyhow::anyhow!("Alias '{}' is for group '{}', which this profile does not have", name, target)),
+            };
+        }
+        let mut known: Vec<&str> = self.groups.keys().map(String::as_str).collect();
+        known.extend(self.aliases.keys().map(String::as_str));
+        match suggest(name, &known).as_slice() {
+            [] => Err(anyhow::anyhow!("Group '{}' not found", name)),
+            [only] => Err(anyhow::anyhow!("Group '{}' not found; did you mean '{}'?", name, only)),
+            several => Err(anyhow::anyhow!("Group '{}' not found; did you mean one of: {}?", name, several.join(", "))),
+        }
+    }
+
+    /// The active user, if one has been initialized
+    pub fn current_user(&self) -> Option<UserName> {
+        self.current_user
+    }
+
+    /// Look up a group by name
+    pub fn group(&self, group_name: &str) -> Option<&ChatGroup> {
+        self.groups.get(group_name)
+    }
+
+    /// Every group in this profile, in no particular order
+    pub fn groups(&self) -> impl Iterator<Item = &ChatGroup> {
+        self.groups.values()
+    }
+
+    /// Initialize a new user identity
+    ///
+    /// The first identity in a profile becomes the active one; later ones are
+    /// added alongside it, and `use_identity` switches between them.
+    pub fn init_user(&mut self, user: UserName) -> Result<()> {
+        println!("{}", tr("init-start", &[]).green());
+        if self.user_keys.contains_key(&user.to_string()) {
+            return Err(anyhow::anyhow!(
+                "User '{}' already has an identity in this profile; `identity use {}` makes it the active one",
+                user,
+                user.to_string().to_lowercase()
+            ));
+        }
+        
+        // Generate mock cryptographic keys
+        let key = MockKey {
+            id: Uuid::new_v4().to_string(),
+            public_key: format!("pub_key_{}", Uuid::new_v4()),
+            private_key: format!("priv_key_{}", Uuid::new_v4()),
+            sealed_private_key: None,
+            store_kdf: None,
+            unlock_attempts: None,
+        };
+        
+        self.user_keys.insert(user.to_string(), key);
+        let active = match self.current_user {
+            Some(current) if self.user_keys.contains_key(&current.to_string()) => current,
+            _ => {
+                self.current_user = Some(user);
+                user
+            }
+        };
+        self.identity_dirty = true;
+        
+        println!("{}", tr("init-done", &[("user", &user)]));
+        println!("   {}", tr("init-keys", &[]));
+        self.save_state()?;
+
+        if active != user {
+            let command = user.to_string().to_lowercase();
+            println!("   {}", tr("init-not-active", &[("active", &active), ("command", &command), ("user", &user)]));
+        } else if self.transport.is_some() {
+            self.publish_key_package()?;
+        }
+        Ok(())
+    }
+
+    /// Make another identity in this profile the active one
+    pub fn use_identity(&mut self, user: UserName) -> Result<()> {
+        if !self.user_keys.contains_key(&user.to_string()) {
+            return Err(anyhow::anyhow!(
+                "No identity for '{}' in this profile; `init {}` creates one",
+                user,
+                user.to_string().to_lowercase()
+            ));
+        }
+        if self.current_user == Some(user) {
+            println!("'{}' is already the active identity", user);
+            return Ok(());
+        }
+        self.current_user = Some(user);
+        self.identity_dirty = true;
+        self.save_state()?;
+        println!("✅ Now acting as '{}'", user);
+        Ok(())
+    }
+
+    /// List the identities in this profile, marking the active one
+    pub fn list_identities(&self) {
+        let mut users: Vec<(&String, &MockKey)> = self.user_keys.iter().collect();
+        users.sort_by(|a, b| a.0.cmp(b.0));
+        println!("{}", "Identities:".blue());
+        if users.is_empty() {
+            println!("No identities yet; `init <user>` creates one.");
+        }
+        for (user, key) in users {
+            let active = self.current_user.is_some_and(|current| current.to_string() == *user);
+            println!(
+                "{} {} ({})",
+                if active { " *" } else { "  " },
+                user,
+                crypto::fingerprint(&key.public_key)
+            );
+        }
+    }
+
+    /// The active identity, and whether others can add it to groups
+    pub fn identity_status(&mut self) -> Result<IdentityStatus> {
+        let user = self.current_user.context("No user initialized; run `init` to create an identity")?.to_string();
+        let key = self.user_keys.get(&user).context("No key for the current user")?.clone();
+        let (key_package, key_package_error) = match self.transport.as_mut() {
+            None => (KeyPackageStatus::Unchecked, None),
+            Some(transport) => match transport.fetch_key_package(&user) {
+                Ok(published) if published.key_id == key.id => (KeyPackageStatus::Published, None),
+                Ok(_) => (KeyPackageStatus::Outdated, None),
+                Err(err) => (KeyPackageStatus::Missing, Some(format!("{:#}", err))),
+            },
+        };
+        Ok(IdentityStatus {
+            user,
+            key_id: key.id,
+            fingerprint: crypto::fingerprint(&key.public_key),
+            credential_type: "basic".to_string(),
+            key_package,
+            key_package_error,
+            groups: self.groups.len(),
+            storage: self.storage.location(),
+            erasure_caveat: self.storage.erasure_caveat(),
+        })
+    }
+
+    /// Print the active identity's status
+    pub fn show_identity(&mut self) -> Result<()> {
+        let status = self.identity_status()?;
+        println!("{}", format!("You are {}", status.user).blue());
+        output::rule('=', 30);
+        println!("Key ID: {}", status.key_id);
+        println!("Fingerprint: {}", status.fingerprint);
+        println!("Credential: basic (the user name)");
+        match status.key_package {
+            KeyPackageStatus::Published => println!("Key package: ✅ published on the delivery service"),
+            KeyPackageStatus::Outdated => {
+                println!("Key package: ⚠️  the delivery service has one for an older key; run `publish`")
+            }
+            KeyPackageStatus::Missing => println!(
+                "Key package: ⚠️  not available ({}); run `publish`",
+                status.key_package_error.unwrap_or_default()
+            ),
+            KeyPackageStatus::Unchecked => println!("Key package: not checked (pass --server to check)"),
+        }
+        println!("Groups: {}", status.groups);
+        println!("Storage: {}", status.storage);
+        if let Some(caveat) = status.erasure_caveat {
+            println!("Secure delete: ⚠️  {}", caveat);
+        }
+        Ok(())
+    }
+
+    /// Create a new MLS group
+    pub fn create_group(&mut self, name: String) -> Result<()> {
+        self.create_group_with_policy(name, GroupPolicy::default())
+    }
+
+    /// Create a group under `policy`, with the current user as its admin
+    pub fn create_group_with_policy(&mut self, name: String, mut policy: GroupPolicy) -> Result<()> {
+        let user = self.current_user.context("No user initialized")?;
+        println!("{}", tr("group-create-start", &[]).green());
+        
+        // Verify user has keys
+        let public_key = match self.user_keys.get(&user.to_string()) {
+            Some(key) => key.public_key.clone(),
+            None => return Err(anyhow::anyhow!("User '{}' not initialized", user)),
+        };
+        
+        // Create mock MLS group
+        policy.admins = vec![user.to_string()];
+        let chat_group = new_group(&name, &user.to_string(), &public_key, policy);
+        let group_id = chat_group.group_id.clone();
+        
+        self.groups.insert(name.clone(), chat_group);
+        self.dirty_groups.insert(name.clone());
+        println!("{}", tr("group-created", &[("group", &name)]));
+        println!("   {}", tr("group-id", &[("id", &group_id)]));
+        println!("   {}", tr("group-initial-epoch", &[]));
+        println!("   {}", tr("group-secret-generated", &[]));
+        self.save_state()?;
+        Ok(())
+    }
+
+    /// Add a member to an existing group
+    pub fn add_member(&mut self, group_name: String, member: UserName) -> Result<()> {
+        let _user = self.current_user.context("No user initialized")?;
+        println!("{}", tr("member-add-start", &[]).green());
+
+        let group = self.groups.get(&group_name)
+            .context("Group not found")?;
+
+        if group.members.contains(&member.to_string()) {
+            println!("{}", tr("member-already", &[("member", &member)]));
+            return Ok(());
+        }
+        bans::check_banned(group, &member.to_string(), None)?;
+        if self.needs_approval(group)? {
+            return self.request_join(&group_name, &member.to_string()).map(|_| ());
+        }
+        policy::check_size(group, 1)?;
+
+        // Verify member has keys, falling back to the delivery service's key package
+        let member_public_key = match (self.user_keys.get(&member.to_string()), self.transport.is_some()) {
+            (Some(key), _) => key.public_key.clone(),
+            (None, true) => {
+                println!("   {}", tr("member-fetch-key-package", &[("member", &member)]));
+                self.fetch_checked_key_package(&member.to_string())?.public_key
+            }
+            (None, false) => return Err(anyhow::anyhow!("Member '{}' not initialized", member)),
+        };
+        self.check_revoked(&member.to_string(), &member_public_key)?;
+        let group = self.groups.get(&group_name).context("Group not found")?;
+        bans::check_banned(group, &member.to_string(), Some(&member_public_key))?;
+
+        // Simulate MLS add proposal and commit
+        println!("   {}", tr("member-add-proposal", &[("member", &member)]));
+        println!("   {}", tr("member-fingerprint", &[("fingerprint", &crypto::fingerprint(&member_public_key))]));
+        println!("   {}", tr("member-new-secret", &[]));
+        println!("   {}", tr("member-distribute", &[]));
+        let adds = vec![(member.to_string(), member_public_key)];
+        self.commit(&group_name, Proposals { adds, ..Proposals::default() })?;
+
+        let group = self.groups.get(&group_name).context("Group not found")?;
+        println!("{}", tr("member-added", &[("member", &member), ("group", &group_name)]));
+        println!("   {}", tr("member-epoch", &[("epoch", &group.mls_group.epoch)]));
+        println!("   {}", tr("member-secret-rotated", &[]));
+        self.save_state()?;
+        Ok(())
+    }
+
+    /// Add several members in one commit, so the group moves on one epoch
+    /// rather than one per member; returns what became of each, in order
+    ///
+    /// A member who cannot be added, such as one with no key package, is
+    /// reported and left out, and the rest are still added. In MLS proper
+    /// this is one Add proposal per member, all covered by a single commit.
+    pub fn add_members(&mut self, group_name: &str, members: &[String]) -> Result<Vec<(String, AddOutcome)>> {
+        let user = self.current_user.context("No user initialized")?;
+        println!("{}", tr("members-add-start", &[]).green());
+        let log_entries = self.checked_log_entries()?;
+        let group = self.groups.get(group_name).context("Group not found")?;
+        let needs_approval = self.needs_approval(group)?;
+
+        let mut outcomes = Vec::new();
+        let mut joiners: Vec<(String, String)> = Vec::new();
+        for name in members {
+            let member = match UserName::from_str(name, true) {
+                Ok(member) => member.to_string(),
+                Err(_) => {
+                    outcomes.push((name.clone(), AddOutcome::Failed(tr("members-unknown-user", &[]))));
+                    continue;
+                }
+            };
+            if group.members.contains(&member) || joiners.iter().any(|(joiner, _)| *joiner == member) {
+                outcomes.push((member, AddOutcome::AlreadyMember));
+                continue;
+            }
+            if let Err(err) = bans::check_banned(group, &member, None) {
+                outcomes.push((member, AddOutcome::Failed(err.to_string())));
+                continue;
+            }
+            if needs_approval {
+                outcomes.push((member, AddOutcome::Pending(String::new()))); // Queued below, once `group` is no longer borrowed
+                continue;
+            }
+            let public_key = match (self.user_keys.get(&member), self.transport.as_mut()) {
+                (Some(key), _) => Ok(key.public_key.clone()),
+                (None, Some(transport)) => transport.fetch_key_package(&member)
+                    .map_err(|err| tr("members-no-key-package", &[("error", &err)]))
+                    .and_then(|package| match log_entries.as_deref().map_or(Ok(()), |entries| transparency::check_key_package(entries, &package)) {
+                        Ok(()) => Ok(package.public_key),
+                        Err(err) => Err(err.to_string()),
+                    }),
+                (None, None) => Err(tr("members-not-initialized", &[])),
+            };
+            let checked = public_key.as_deref().map_or(Ok(()), |key| {
+                bans::check_banned(group, &member, Some(key)).and_then(|_| self.check_revoked(&member, key))
+            });
+            if let Err(err) = checked {
+                outcomes.push((member, AddOutcome::Failed(err.to_string())));
+                continue;
+            }
+            if let Err(err) = policy::check_size(group, joiners.len() + 1) {
+                outcomes.push((member, AddOutcome::Failed(err.to_string())));
+                continue;
+            }
+            match public_key {
+                Ok(public_key) => {
+                    println!("   {}", tr("members-add-proposal", &[("member", &member), ("fingerprint", &crypto::fingerprint(&public_key))]));
+                    joiners.push((member.clone(), public_key));
+                    outcomes.push((member, AddOutcome::Added));
+                }
+                Err(reason) => outcomes.push((member, AddOutcome::Failed(reason))),
+            }
+        }
+
+        for (member, outcome) in outcomes.iter_mut() {
+            if let AddOutcome::Pending(id) = outcome {
+                *id = self.request_join(group_name, member)?;
+            }
+        }
+        for (joiner, public_key) in &joiners {
+            self.observe_key(joiner, public_key)?;
+        }
+
+        let mut savings = None;
+        if !joiners.is_empty() {
+            println!("   {}", tr("members-committing", &[("count", &joiners.len())]));
+            let group = self.groups.get(group_name).context("Group not found")?;
+            savings = commit::batch_savings(group, &user.to_string(), &joiners)?;
+            self.commit(group_name, Proposals { adds: joiners.clone(), ..Proposals::default() })?;
+            self.save_state()?;
+        }
+
+        for (member, outcome) in &outcomes {
+            match outcome {
+                AddOutcome::Added => println!("   {}", tr("members-added-one", &[("member", member)])),
+                AddOutcome::AlreadyMember => println!("   {}", tr("members-already", &[("member", member)])),
+                AddOutcome::Pending(id) => println!("   {}", tr("members-pending", &[("member", member), ("id", &short_id(id))])),
+                AddOutcome::Failed(reason) => println!("   {}", tr("members-failed", &[("member", member), ("reason", reason)])),
+            }
+        }
+        if !joiners.is_empty() {
+            let group = self.groups.get(group_name).context("Group not found")?;
+            println!("{}", tr("members-added", &[("count", &joiners.len()), ("group", &group_name)]).green());
+            println!("   {}", tr("members-epoch", &[("epoch", &group.mls_group.epoch)]));
+        }
+        if let Some(savings) = savings {
+            println!("   {}", tr("members-savings", &[
+                ("batched", &bench::latency(savings.batched)),
+                ("sequential", &bench::latency(savings.sequential)),
+                ("count", &savings.joiners),
+                ("saved", &bench::latency(savings.saved())),
+                ("epochs", &(savings.joiners - 1)),
+                ("batched-size", &bench::size(savings.batched_bytes)),
+                ("sequential-size", &bench::size(savings.sequential_bytes)),
+            ]));
+        }
+        Ok(outcomes)
+    }
+
+    /// Change a group's shared name, topic, or description; an empty topic or description clears it
+    ///
+    /// The change is committed as a new epoch so every member agrees on it. In
+    /// MLS proper this is a GroupContextExtensions proposal and its commit.
+    pub fn set_group_metadata(
+        &mut self,
+        group_name: &str,
+        name: Option<String>,
+        topic: Option<String>,
+        description: Option<String>,
+    ) -> Result<()> {
+        let user = self.current_user.context("No user initialized")?;
+        println!("{}", "Updating group metadata...".green());
+        let group = self.groups.get(group_name).context("Group not found")?;
+        if !group.members.contains(&user.to_string()) {
+            return Err(anyhow::anyhow!("User '{}' is not a member of group '{}'", user, group_name));
+        }
+        if let Some(name) = &name {
+            if name.is_empty() {
+                return Err(anyhow::anyhow!("The group name cannot be empty"));
+            }
+            if name != group_name && self.groups.contains_key(name) {
+                return Err(anyhow::anyhow!("Group '{}' already exists", name));
+            }
+        }
+
+        let mut metadata = group.mls_group.metadata.clone();
+        if let Some(name) = &name {
+            metadata.name = name.clone();
+        }
+        if let Some(topic) = topic {
+            metadata.topic = Some(topic).filter(|topic| !topic.is_empty());
+        }
+        if let Some(description) = description {
+            metadata.description = Some(description).filter(|description| !description.is_empty());
+        }
+        if metadata == group.mls_group.metadata {
+            println!("⚠️  Nothing to change");
+            return Ok(());
+        }
+        println!("   Committing the new group context at epoch {}", group.mls_group.epoch + 1);
+        self.commit(group_name, Proposals { metadata: Some(metadata), ..Proposals::default() })?;
+
+        let mut group = self.groups.remove(group_name).context("Group not found")?;
+        self.dirty_groups.remove(group_name);
+        let new_name = name.unwrap_or_else(|| group_name.to_string());
+        group.name = new_name.clone();
+        self.groups.insert(new_name.clone(), group);
+        self.dirty_groups.insert(new_name.clone());
+        if new_name != group_name {
+            self.note_rename(group_name, &new_name);
+            println!("✅ Group '{}' renamed to '{}'", group_name, new_name);
+        } else {
+            println!("✅ Group '{}' updated", new_name);
+        }
+        self.save_state()?;
+        Ok(())
+    }
+
+    /// Archive a group, hiding it from `groups`, or bring it back
+    pub fn set_archived(&mut self, group_name: &str, archived: bool) -> Result<()> {
+        let group = self.groups.get_mut(group_name).context("Group not found")?;
+        group.archived = archived;
+        self.dirty_groups.insert(group_name.to_string());
+        match archived {
+            true => println!("✅ Group '{}' archived; `groups --all` still lists it", group_name),
+            false => println!("✅ Group '{}' unarchived", group_name),
+        }
+        self.save_state()
+    }
+
+    /// Mute a group, or unmute it with `None`
+    pub fn set_mute(&mut self, group_name: &str, mute: Option<Mute>) -> Result<()> {
+        let group = self.groups.get_mut(group_name).context("Group not found")?;
+        group.mute = mute;
+        self.dirty_groups.insert(group_name.to_string());
+        match mute {
+            Some(mute) => println!("✅ Group '{}' {}", group_name, mute),
+            None => println!("✅ Group '{}' unmuted", group_name),
+        }
+        self.save_state()
+    }
+
+    /// Keep unsent text for a group until it is sent or cleared; `None` or blank text clears it
+    pub fn set_draft(&mut self, group_name: &str, draft: Option<String>) -> Result<()> {
+        let group = self.groups.get_mut(group_name).context("Group not found")?;
+        group.draft = draft.filter(|draft| !draft.trim().is_empty());
+        self.dirty_groups.insert(group_name.to_string());
+        match group.draft {
+            Some(_) => println!("✎ Draft saved for '{}'", group_name),
+            None => println!("✅ Draft for '{}' cleared", group_name),
+        }
+        self.save_state()
+    }
+
+    /// Send a group's draft as a Markdown message and clear it
+    pub fn send_draft(&mut self, group_name: String) -> Result<()> {
+        let group = self.groups.get(&group_name).context("Group not found")?;
+        let draft = group.draft.clone()
+            .with_context(|| format!("No draft for '{}'; `draft set` saves one", group_name))?;
+        self.send_message(group_name.clone(), draft)?;
+        let group = self.groups.get_mut(&group_name).context("Group not found")?;
+        group.draft = None;
+        self.dirty_groups.insert(group_name);
+        self.save_state()
+    }
+
+    /// List the groups in this profile; archived ones only with `all`
+    pub fn list_groups(&self, all: bool) {
+        let now = Utc::now();
+        let mut groups: Vec<&ChatGroup> = self.groups.values().collect();
+        groups.sort_by(|a, b| a.name.cmp(&b.name));
+        let hidden = groups.iter().filter(|group| group.archived && !all).count();
+
+        println!("{}", tr("groups-header", &[]).blue());
+        for group in groups.iter().filter(|group| all || !group.archived) {
+            let mut flags = Vec::new();
+            if group.archived {
+                flags.push("archived".to_string());
+            }
+            if let Some(mute) = group.mute.filter(|_| group.is_muted(now)) {
+                flags.push(mute.to_string());
+            }
+            let flags = match flags.is_empty() {
+                true => String::new(),
+                false => format!(" [{}]", flags.join(", ")).dimmed().to_string(),
+            };
+            let draft = if group.draft.is_some() { " ✎" } else { "" };
+            println!("   {}", tr("groups-entry", &[
+                ("group", &group.name),
+                ("draft", &draft),
+                ("members", &group.members.len()),
+                ("messages", &group.message_count),
+                ("flags", &flags),
+            ]));
+        }
+        if groups.len() == hidden {
+            println!("{}", tr("groups-empty", &[]));
+        }
+        if hidden > 0 {
+            println!("{}", tr("groups-hidden", &[("count", &hidden)]));
+        }
+    }
+
+    /// Delete a group and wipe its files; in networked mode, leave it first unless `local_only`
+    ///
+    /// Leaving commits a new epoch without the current user to the remaining
+    /// members, so they stop sending to this profile. In MLS proper this is a
+    /// self-Remove proposal that another member commits.
+    pub fn delete_group(&mut self, group_name: &str, local_only: bool) -> Result<()> {
+        let user = self.current_user.context("No user initialized")?;
+        println!("{}", "Deleting group...".green());
+        let group = self.groups.get(group_name).context("Group not found")?;
+
+        if !local_only && self.transport.is_some() && !self.remote_members(group).is_empty() {
+            println!("   Leaving: committing epoch {} without '{}'", group.mls_group.epoch + 1, user);
+            self.commit(group_name, Proposals { removes: vec![user.to_string()], ..Proposals::default() })?;
+        }
+
+        let group = self.groups.remove(group_name).context("Group not found")?;
+        self.dirty_groups.remove(group_name);
+        self.storage.delete_group(&group.group_id)?;
+        println!("✅ Group '{}' deleted", group_name);
+        println!("   Message log and group secret wiped from this profile");
+        Ok(())
+    }
+
+    /// Send a Markdown message to a group
+    pub fn send_message(&mut self, group_name: String, content: String) -> Result<()> {
+        self.send_content(group_name, Content::Markdown { text: content })
+    }
+
+    /// Send a message of any content type to a group
+    pub fn send_content(&mut self, group_name: String, content: Content) -> Result<()> {
+        let _user = self.current_user.context("No user initialized")?;
+        println!("{}", tr("send-start", &[]).green());
+        
+        let group = self.groups.get(&group_name)
+            .context("Group not found")?;
+        
+        // Verify user is a member
+        if !group.members.contains(&_user.to_string()) {
+            return Err(anyhow::anyhow!("User '{}' is not a member of group '{}'", _user, group_name));
+        }
+        
+        self.update_if_due(&group_name)?;
+        let group = self.groups.get(&group_name).context("Group not found")?;
+
+        // Simulate MLS message encryption
+        println!("   {}", tr("send-encrypting", &[]));
+        println!("   {}", tr("send-epoch", &[("epoch", &group.mls_group.epoch)]));
+        let content = self.expand_shortcodes(content);
+        self.post_message(&group_name, _user.to_string(), content)?;
+        self.count_sent(&group_name);
+        
+        println!("{}", tr("send-done", &[]));
+        println!("   {}", tr("send-encrypted", &[]));
+        println!("   {}", tr("send-forward-secrecy", &[]));
+        self.save_state()?;
+        Ok(())
+    }
+
+    /// `content` with shortcodes in its text replaced by emoji, unless that is turned off
+    pub(crate) fn expand_shortcodes(&self, content: Content) -> Content {
+        match content {
+            Content::Text { text } if self.shortcodes => Content::Text { text: emoji::expand(&text) },
+            Content::Markdown { text } if self.shortcodes => Content::Markdown { text: emoji::expand(&text) },
+            content => content,
+        }
+    }
+
+    /// Encrypt, distribute, and store a message from `sender`
+    ///
+    /// Callers check membership and save state; bridges use this to record
+    /// messages from senders outside the group.
+    pub(crate) fn post_message(&mut self, group_name: &str, sender: String, content: Content) -> Result<ChatMessage> {
+        let group = self.groups.get(group_name)
+            .context("Group not found")?;
+        let encrypted_content = protocol::encrypt(
+            &protocol::application_key(&group.mls_group.group_secret),
+            &content.to_plaintext()?,
+        );
+
+        // Create chat message
+        let chat_message = new_message(
+            Uuid::new_v4().to_string(),
+            sender,
+            content,
+            encrypted_content,
+            Utc::now(),
+            group,
+        )?;
+
+        if self.transport.is_some() {
+            self.distribute_application(group_name, &chat_message)?;
+        }
+
+        let group = self.groups.get_mut(group_name)
+            .context("Group not found")?;
+        self.storage.append_message(&chat_message)?;
+        group.message_count += 1;
+        if let Some(messages) = group.messages.as_mut() {
+            messages.push(chat_message.clone());
+        }
+        self.dirty_groups.insert(group_name.to_string());
+        Ok(chat_message)
+    }
+
+    /// Label a message in this profile; tags stay local and are never sent
+    pub fn tag_message(&mut self, group_name: &str, message_id: &str, tag: &str) -> Result<()> {
+        let group = self.groups.get_mut(group_name).context("Group not found")?;
+        group.tags.entry(message_id.to_string()).or_default().insert(tag.to_string());
+        self.dirty_groups.insert(group_name.to_string());
+        Ok(())
+    }
+
+    /// List all messages in a group
+    ///
+    /// With `options.status`, the user's own messages are marked ✓ once every
+    /// other member has them and ✓✓ once all have read them. In networked
+    /// mode, listing sends read receipts for the messages shown from others.
+    /// Messages are printed as they are read from storage, so a long history
+    /// is never held in memory; see `for_each_message`.
+    pub fn list_messages(&mut self, group_name: String, options: &ListOptions, filter: &MessageFilter) -> Result<()> {
+        let group = self.groups.get(&group_name).context("Group not found")?;
+        let mut pins = Vec::new();
+        if !group.pinned.is_empty() {
+            self.for_each_message(&group_name, &MessageFilter::default(), |message| {
+                if group.pinned.contains(&message.id) {
+                    pins.push(message.clone());
+                }
+                Ok(())
+            })?;
+        }
+
+        println!("{}", tr("list-header", &[("group", &group_name)]).blue());
+        output::rule('=', 50);
+        if let Some(topic) = &group.mls_group.metadata.topic {
+            println!("{}", tr("list-topic", &[("topic", topic)]));
+        }
+        println!("{}", tr("list-group-id", &[("id", &group.group_id)]));
+        println!("{}", tr("list-epoch", &[("epoch", &group.mls_group.epoch)]));
+        println!("{}", tr("list-members", &[("members", &group.members.join(", "))]));
+        output::rule('=', 50);
+        print_pinned(group, &pins);
+
+        let user = self.current_user.map(|user| user.to_string());
+        // Known before the first message is read, so members' names set the width
+        let sender_width = group.members.iter().map(|member| emoji::width(member)).max().unwrap_or(0);
+        let pinned = self.pinned_keys()?;
+        let mut shown = 0;
+        let mut unread: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        self.for_each_message(&group_name, filter, |message| {
+            let mut mark = if options.status { self.receipt_mark(group, message) } else { String::new() };
+            mark.push_str(trust::trust_mark(&pinned, message));
+            print_message(message, &mark, user.as_deref(), sender_width, options);
+            print_tampered(group, message);
+            trust::print_unverified(&pinned, message);
+            if self.owes_read_receipt(group, message) {
+                unread.entry(message.sender.clone()).or_default().push(message.id.clone());
+            }
+            shown += 1;
+            Ok(())
+        })?;
+        if shown == 0 {
+            println!("{}", tr(if filter.is_empty() { "list-empty" } else { "list-no-match" }, &[]));
+        }
+
+        if self.transport.is_some() {
+            if let Err(err) = self.acknowledge_read(&group_name, unread) {
+                println!("⚠️  Could not send read receipts: {}", err);
+            }
+            self.save_state()?;
+        }
+        Ok(())
+    }
+
+    /// Pass each message of a group that `filter` lets through to `visit`, oldest first
+    ///
+    /// Unless the group's messages are already loaded, they are read from
+    /// storage one at a time and never all held, and with `filter.last` only
+    /// that many are kept at once. Messages past the group's retention are
+    /// skipped the way loading them would drop them; they are removed for
+    /// good the next time the log is loaded.
+    pub fn for_each_message(&self, group_name: &str, filter: &MessageFilter, mut visit: impl FnMut(&ChatMessage) -> Result<()>) -> Result<()> {
+        let group = self.groups.get(group_name).context("Group not found")?;
+        let mut last: Option<VecDeque<ChatMessage>> = filter.last.map(|count| VecDeque::with_capacity(count.min(1024)));
+        let mut each = |message: ChatMessage| -> Result<()> {
+            if !filter.matches(&message) {
+                return Ok(());
+            }
+            match (&mut last, filter.last) {
+                (Some(last), Some(count)) => {
+                    if last.len() == count {
+                        last.pop_front();
+                    }
+                    if count > 0 {
+                        last.push_back(message);
+                    }
+                    Ok(())
+                }
+                _ => visit(&message),
+            }
+        };
+        match &group.messages {
+            Some(messages) => {
+                for message in messages {
+                    each(message.clone())?;
+                }
+            }
+            None => {
+                let mut window = RetentionWindow::new(group.retention.unwrap_or(self.retention), group.message_count);
+                self.storage.stream_messages(&group.group_id, &mut |message| match window.keeps(&message) {
+                    true => each(message),
+                    false => Ok(()),
+                })?;
+            }
+        }
+        for message in last.into_iter().flatten() {
+            visit(&message)?;
+        }
+        Ok(())
+    }
+
+    /// Print new messages in a group as they arrive, until the process is stopped
+    ///
+    /// Each round re-reads the profile from storage, so messages recorded by
+    /// other commands show up too, and in networked mode syncs the inbox.
+    pub fn follow_messages(&mut self, group_name: &str, options: &ListOptions, filter: &MessageFilter) -> Result<()> {
+        let mut shown = FollowCursor::default();
+        shown.pass(self.load_messages(group_name)?.messages.as_deref().unwrap_or_default());
+        println!("{}", format!("Following '{}' (Ctrl-C to stop)...", group_name).green());
+        if let Some(mute) = self.groups.get(group_name).filter(|group| group.is_muted(Utc::now())).and_then(|group| group.mute) {
+            println!("⚠️  '{}' is {}; new messages are shown once it is unmuted", group_name, mute);
+        }
+        loop {
+            thread::sleep(FOLLOW_INTERVAL);
+            if let Err(err) = self.follow_step(group_name, options, filter, &mut shown) {
+                println!("⚠️  {:#}", err);
+            }
+        }
+    }
+
+    /// One round of `follow_messages`; `shown` is the last message passed so far
+    ///
+    /// While the group is muted nothing is printed and `shown` stays put, so
+    /// what arrived meanwhile is printed once it is unmuted.
+    fn follow_step(&mut self, group_name: &str, options: &ListOptions, filter: &MessageFilter, shown: &mut FollowCursor) -> Result<()> {
+        self.load_state()?;
+        self.send_due_scheduled()?;
+        self.load_messages(group_name)?;
+        if self.transport.is_some() {
+            let user = self.current_user.context("No user initialized")?.to_string();
+            for envelope in self.transport()?.fetch_inbox(&user)? {
+                if let Err(err) = self.apply_envelope(&envelope) {
+                    println!("⚠️  Skipped message {} from '{}': {}", envelope.message_id, envelope.sender, err);
+                }
+            }
+        }
+
+        let group = self.groups.get(group_name).context("Group not found")?;
+        let messages = group.messages.as_deref().unwrap_or_default();
+        let muted = group.is_muted(Utc::now());
+        let user = self.current_user.map(|user| user.to_string());
+        let sender_width = sender_width(messages.iter().filter(|message| filter.matches(message)));
+        let pinned = self.pinned_keys()?;
+        let mut printed = Vec::new();
+        if !muted {
+            for message in shown.after(messages).filter(|message| filter.matches(message)) {
+                let mut mark = if options.status { self.receipt_mark(group, message) } else { String::new() };
+                mark.push_str(trust::trust_mark(&pinned, message));
+                print_message(message, &mark, user.as_deref(), sender_width, options);
+                print_tampered(group, message);
+                trust::print_unverified(&pinned, message);
+                printed.push(message.clone());
+            }
+            shown.pass(messages);
+        }
+        for message in &printed {
+            self.notify_message(group_name, message);
+        }
+
+        if self.transport.is_some() {
+            self.send_read_receipts(group_name)?;
+        }
+        self.save_state()
+    }
+
+    /// Show group information
+    pub fn show_group_info(&self, group_name: String) -> Result<()> {
+        let group = self.groups.get(&group_name)
+            .context("Group not found")?;
+        
+        println!("{}", format!("Group: {}", group_name).blue());
+        output::rule('=', 30);
+        println!("Group ID: {}", group.group_id);
+        println!("Current Epoch: {}", group.mls_group.epoch);
+        println!("Tree Hash: {}", group.mls_group.tree_hash);
+        match &group.mls_group.tree {
+            Some(tree) => println!("Ratchet Tree: {} leaves wide, {} path secret(s) held here", tree.width(), group.mls_group.path_secrets.len()),
+            None => println!("Ratchet Tree: none; commits send every member the new secret"),
+        }
+        println!("Ciphersuite: {}", group.ciphersuite);
+        if let Some(topic) = &group.mls_group.metadata.topic {
+            println!("Topic: {}", topic);
+        }
+        if let Some(description) = &group.mls_group.metadata.description {
+            println!("Description: {}", description);
+        }
+        println!("Members: {}", group.members.join(", "));
+        println!("Policy: {}", group.mls_group.metadata.policy);
+        if let Some(retention) = group.retention {
+            println!("Retention: {}", describe_retention(retention));
+        }
+        println!("Message count: {}", group.message_count);
+        if let Some(rotation) = self.describe_rotation(&group_name) {
+            println!("Key rotation: {}", rotation);
+        }
+        if group.archived {
+            println!("Archived: yes");
+        }
+        if let Some(mute) = group.mute.filter(|_| group.is_muted(Utc::now())) {
+            println!("Notifications: {}", mute);
+        } else if group.notify == Notify::Mentions {
+            println!("Notifications: {}", group.notify);
+        }
+        println!("Group Secret: {}...", &group.mls_group.group_secret[..20]);
+        Ok(())
+    }
+
+    /// Load a group's messages from storage if they are not in memory yet
+    pub fn load_messages(&mut self, group_name: &str) -> Result<&ChatGroup> {
+        let group = self.groups.get_mut(group_name)
+            .context("Group not found")?;
+        
+        if group.messages.is_none() {
+            let mut messages = self.storage.load_messages(&group.group_id)?;
+            let expired = expire(&mut messages, group.retention.unwrap_or(self.retention));
+            if !expired.is_empty() {
+                self.storage.replace_messages(&group.group_id, &messages)?;
+                for id in &expired {
+                    group.receipts.remove(id);
+                    group.tags.remove(id);
+                }
+                group.message_count = messages.len();
+                // Saved right away, since read-only commands never call `save_state`
+                self.storage.save_group(group)?;
+            }
+            group.messages = Some(messages);
+        }
+        Ok(group)
+    }
+
+    /// Save changed groups and identity state to disk
+    ///
+    /// Only groups marked dirty since the last save are rewritten.
+    pub(crate) fn save_state(&mut self) -> Result<()> {
+        for name in std::mem::take(&mut self.dirty_groups) {
+            if let Some(group) = self.groups.get(&name) {
+                self.storage.save_group(group)?;
+            }
+        }
+        for (_, transfer) in std::mem::take(&mut self.transfers) {
+            self.storage.save_transfer(&transfer)?;
+        }
+        
+        if self.identity_dirty {
+            self.storage.save_user_keys(&self.keys_to_save()?)?;
+            if let Some(user) = self.current_user {
+                self.storage.save_current_user(user)?;
+            }
+            self.identity_dirty = false;
+        }
+        Ok(())
+    }
+
+    /// Load application state from disk
+    ///
+    /// Only group metadata is read here; messages are loaded per group when needed.
+    pub fn load_state(&mut self) -> Result<()> {
+        self.groups = self.storage.load_groups()?
+            .into_iter()
+            .map(|group| (group.name.clone(), group))
+            .collect();
+        self.user_keys = self.storage.load_user_keys()?;
+        self.current_user = self.storage.load_current_user()?;
+        Ok(())
+    }
+}
+
+/// A retention policy in words, e.g. "30 days, 1000 messages"
+pub(crate) fn describe_retention(retention: Retention) -> String {
+    let mut limits = Vec::new();
+    if let Some(days) = retention.max_age_days {
+        limits.push(format!("{} days", days));
+    }
+    if let Some(count) = retention.max_messages {
+        limits.push(format!("{} messages", count));
+    }
+    match limits.is_empty() {
+        true => "keep everything".to_string(),
+        false => limits.join(", "),
+    }
+}
+
+/// Names in `known` close to `name`, closest first: differing only in case, by a few typos, or by a prefix
+fn suggest<'a>(name: &str, known: &[&'a str]) -> Vec<&'a str> {
+    let name = name.to_lowercase();
+    let limit = (name.chars().count() / 3).max(1);
+    let mut close: Vec<(usize, &str)> = known.iter()
+        .filter_map(|candidate| {
+            let lower = candidate.to_lowercase();
+            let distance = edit_distance(&name, &lower);
+            match distance <= limit || lower.starts_with(&name) {
+                true => Some((distance, *candidate)),
+                false => None,
+            }
+        })
+        .collect();
+    close.sort();
+    close.dedup();
+    close.into_iter().map(|(_, candidate)| candidate).take(3).collect()
+}
+
+/// Edits (insert, delete, substitute, or swap two neighbours) turning `a` into `b`
+fn edit_distance(a: &str, b: &str) -> usize {
+    let (a, b): (Vec<char>, Vec<char>) = (a.chars().collect(), b.chars().collect());
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    d[0] = (0..=b.len()).collect();
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            d[i][j] = (d[i - 1][j] + 1).min(d[i][j - 1] + 1).min(d[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    d[a.len()][b.len()]
+}
+
+/// Drop messages outside `retention` from a history in timestamp order; returns their IDs
+fn expire(messages: &mut Vec<ChatMessage>, retention: Retention) -> Vec<String> {
+    let mut keep_from = 0;
+    if let Some(days) = retention.max_age_days {
+        let cutoff = Utc::now() - chrono::Duration::days(days.into());
+        keep_from = messages.iter().position(|message| message.timestamp >= cutoff).unwrap_or(messages.len());
+    }
+    if let Some(max) = retention.max_messages {
+        keep_from = keep_from.max(messages.len().saturating_sub(max));
+    }
+    messages.drain(..keep_from).map(|message| message.id).collect()
+}
+
+/// Which messages of a log read one at a time `expire` would keep
+struct RetentionWindow {
+    cutoff: Option<DateTime<Utc>>, // Messages before this go, until the first one after it
+    skip: usize,                   // The oldest this many go, over `max_messages`
+    seen: usize,
+    reached_cutoff: bool,
+}
+
+impl RetentionWindow {
+    fn new(retention: Retention, count: usize) -> Self {
+        Self {
+            cutoff: retention.max_age_days.map(|days| Utc::now() - chrono::Duration::days(days.into())),
+            skip: retention.max_messages.map_or(0, |max| count.saturating_sub(max)),
+            seen: 0,
+            reached_cutoff: false,
+        }
+    }
+
+    /// Whether the next message in the log, `message`, is kept
+    fn keeps(&mut self, message: &ChatMessage) -> bool {
+        self.seen += 1;
+        if self.seen <= self.skip {
+            return false;
+        }
+        if !self.reached_cutoff && self.cutoff.is_some_and(|cutoff| message.timestamp < cutoff) {
+            return false;
+        }
+        self.reached_cutoff = true;
+        true
+    }
+}
+
+/// A new group at epoch 1 with `creator`, whose key is `public_key`, as its only member, before it is saved
+pub(crate) fn new_group(name: &str, creator: &str, public_key: &str, policy: GroupPolicy) -> ChatGroup {
+    let group_id = Uuid::new_v4().to_string();
+    let mut tree = RatchetTree::new(creator, public_key);
+    let mls_group = MockMlsGroup {
+        group_id: group_id.clone(),
+        epoch: 1,
+        tree_hash: tree.root_hash(),
+        group_secret: format!("group_secret_{}", Uuid::new_v4()),
+        members: vec![creator.to_string()],
+        metadata: GroupMetadata { name: name.to_string(), policy, ..GroupMetadata::default() },
+        tree: Some(tree),
+        path_secrets: BTreeMap::new(),
+    };
+    ChatGroup {
+        name: name.to_string(),
+        group_id,
+        members: vec![creator.to_string()],
+        message_count: 0,
+        messages: Some(Vec::new()),
+        mls_group,
+        last_seq: 0,
+        receipts: HashMap::new(),
+        tags: HashMap::new(),
+        pinned: Vec::new(),
+        ciphersuite: Ciphersuite::default(),
+        retention: None,
+        archived: false,
+        mute: None,
+        notify: Notify::default(),
+        scheduled: Vec::new(),
+        draft: None,
+        key_rotations: HashMap::new(),
+        join_requests: Vec::new(),
+        outbox: Vec::new(),
+    }
+}
+
+/// A chat message carrying `content`, as sent or received at the group's current epoch
+///
+/// Receipts are not chat messages and are refused.
+pub(crate) fn new_message(
+    id: String,
+    sender: String,
+    content: Content,
+    encrypted_content: String,
+    timestamp: chrono::DateTime<Utc>,
+    group: &ChatGroup,
+) -> Result<ChatMessage> {
+    let content_type = content.content_type().context("A receipt is not a chat message")?;
+    let (content, forwarded_from) = match content {
+        Content::Forwarded { content, from } => (*content, Some(from)),
+        content => (content, None),
+    };
+    let text = content.summary();
+    let (mut attachment, mut reacts_to, mut options, mut location) = (None, None, Vec::new(), None);
+    match content {
+        Content::File(file) | Content::Image(file) => attachment = Some(file),
+        Content::Reaction { message_id, .. } => reacts_to = Some(message_id),
+        Content::Vote { poll_id, .. } => reacts_to = Some(poll_id),
+        Content::Poll { options: choices, .. } => options = choices,
+        Content::Location(place) => location = Some(place),
+        _ => {}
+    }
+    let mentions = match content_type {
+        ContentType::Text | ContentType::Markdown => parse_mentions(&text, &group.members),
+        _ => Vec::new(),
+    };
+    Ok(ChatMessage {
+        id,
+        sender,
+        content: text,
+        encrypted_content,
+        timestamp,
+        group_id: group.group_id.clone(),
+        epoch: group.mls_group.epoch,
+        mentions,
+        content_type,
+        attachment,
+        reacts_to,
+        options,
+        location,
+        forwarded_from,
+        sender_key: None,
+        shared_by: None,
+    })
+}
+
+/// Columns the widest sender name in `messages` takes, for lining up their content
+pub(crate) fn sender_width<'a>(messages: impl IntoIterator<Item = &'a ChatMessage>) -> usize {
+    messages.into_iter().map(|message| emoji::width(&message.sender)).max().unwrap_or(0)
+}
+
+/// True if `message` is from the group's current epoch and its ciphertext fails authentication
+///
+/// Only the current epoch's secret is kept, so messages from earlier epochs
+/// cannot be checked; nor can those saved before messages were really
+/// encrypted, whose ciphertext is a placeholder starting `encrypted_`.
+pub(crate) fn tampered(group: &ChatGroup, message: &ChatMessage) -> bool {
+    message.epoch == group.mls_group.epoch
+        && !message.encrypted_content.starts_with("encrypted_")
+        && protocol::decrypt(&protocol::application_key(&group.mls_group.group_secret), &message.encrypted_content).is_err()
+}
+
+/// Flag `message` under its listing if its ciphertext was changed after it was sent
+fn print_tampered(group: &ChatGroup, message: &ChatMessage) {
+    if tampered(group, message) {
+        println!("   {}", output::words("⚠️  Ciphertext fails authentication: it was changed after it was sent").red().bold());
+    }
+}
+
+/// Print one message as `list` shows it, followed by `mark`, highlighting mentions of `user`
+///
+/// Sender names are padded to `sender_width` columns, and later lines of
+/// multi-line content are indented to start under the first.
+pub(crate) fn print_message(message: &ChatMessage, mark: &str, user: Option<&str>, sender_width: usize, options: &ListOptions) {
+    let own = user == Some(message.sender.as_str());
+    let user = user.filter(|user| message.mentions.iter().any(|mentioned| mentioned == user));
+    let plain = |text: &str| match user {
+        Some(user) => highlight(text, user),
+        None => text.to_string(),
+    };
+    let content = match message.content_type {
+        ContentType::Markdown if !options.raw => markdown::render(&message.content, &plain),
+        ContentType::Text | ContentType::Markdown => plain(&message.content),
+        ContentType::System => theme::paint(Role::System, &message.content).to_string(),
+        ContentType::File | ContentType::Image => match &message.attachment {
+            Some(attachment) => format!("📎 {} ({})", attachment.name, preview::human_size(attachment.size)),
+            None => format!("📎 {}", message.content),
+        },
+        ContentType::Reaction => {
+            let target = message.reacts_to.as_deref().map(short_id).unwrap_or_default();
+            format!("reacted {} to {}", message.content, target.dimmed())
+        }
+        ContentType::Poll => {
+            let options: Vec<String> = message.options.iter().enumerate()
+                .map(|(i, option)| format!("\n  {}. {}", i + 1, option))
+                .collect();
+            format!("📊 {}{}", message.content.bold(), options.concat())
+        }
+        ContentType::Vote => {
+            let target = message.reacts_to.as_deref().map(short_id).unwrap_or_default();
+            format!("voted {} in poll {}", message.content.bold(), target.dimmed())
+        }
+        ContentType::Location => match &message.location {
+            Some(location) => {
+                let place = match &location.label {
+                    Some(label) => format!("{} ({})", label, location.coordinates()),
+                    None => location.coordinates(),
+                };
+                format!("📍 {}\n{}", place, location.osm_url().underline())
+            }
+            None => format!("📍 {}", message.content),
+        },
+    };
+    let content = match &message.forwarded_from {
+        Some(from) => {
+            let note = format!("↪ Forwarded from {} in '{}', {}", from.sender, from.group, from.timestamp.format("%Y-%m-%d %H:%M UTC"));
+            format!("{}\n{}", note.dimmed(), content)
+        }
+        None => content,
+    };
+    let content = match &message.shared_by {
+        Some(member) => format!("{}\n{}", format!("↩ Shared by {} from before you joined", member).dimmed(), content),
+        None => content,
+    };
+    let id = if options.ids { format!("{} ", short_id(&message.id)) } else { String::new() };
+    let time = options.times.format(message.timestamp, Utc::now());
+    if output::is_plain() {
+        // What members typed keeps its emoji; only the symbols this adds are spelled out
+        let content = match message.content_type {
+            ContentType::Text | ContentType::Markdown if message.forwarded_from.is_none() && message.shared_by.is_none() => content,
+            _ => output::words(&content),
+        };
+        let line = format!("{}{}, {}, epoch {}: {} {}", id, message.sender, time, message.epoch, content.replace('\n', "\n  "), output::words(mark));
+        println!("{}", line.trim_end());
+    } else {
+        let sender = emoji::pad(&message.sender, sender_width);
+        let lead = format!("{}[{}] {} (Epoch {}): ", id, time, sender, message.epoch);
+        let content = content.replace('\n', &format!("\n{}", " ".repeat(emoji::width(&lead))));
+        println!("{}[{}] {} (Epoch {}): {}{}", 
+            id.dimmed(),
+            time,
+            theme::sender(&sender, own),
+            theme::paint(Role::Epoch, &message.epoch.to_string()),
+            content,
+            mark
+        );
+    }
+    if let (ContentType::Image, Some(attachment), Some(graphics)) = (message.content_type, &message.attachment, options.preview) {
+        match attachment.bytes().and_then(|data| preview::render(&data, graphics)) {
+            Ok(image) => println!("{}", image),
+            Err(err) => println!("   {}", format!("(no preview: {:#})", err).dimmed()),
+        }
+    }
+    println!("   {}", tr("list-encrypted", &[("ciphertext", &message.encrypted_content.dimmed())]));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages(group: &ChatGroup, ids: &[&str], start: i64) -> Vec<ChatMessage> {
+        ids.iter()
+            .enumerate()
+            .map(|(offset, id)| {
+                let timestamp = DateTime::from_timestamp(start + offset as i64, 0).unwrap();
+                new_message(id.to_string(), "Bob".to_string(), Content::Markdown { text: id.to_string() }, String::new(), timestamp, group).unwrap()
+            })
+            .collect()
+    }
+
+    fn ids<'a>(messages: impl Iterator<Item = &'a ChatMessage>) -> Vec<&'a str> {
+        messages.map(|message| message.id.as_str()).collect()
+    }
+
+    #[test]
+    fn follow_cursor_shows_what_came_after_the_last_message() {
+        let group = new_group("Team", "Alice", "alice-key", GroupPolicy::default());
+        let mut cursor = FollowCursor::default();
+        let log = messages(&group, &["a", "b"], 100);
+        assert_eq!(ids(cursor.after(&log)), ["a", "b"]);
+        cursor.pass(&log);
+        assert!(cursor.after(&log).next().is_none());
+
+        let log = messages(&group, &["a", "b", "c"], 100);
+        assert_eq!(ids(cursor.after(&log)), ["c"]);
+        // Passing nothing, as round after round while muted, keeps the place
+        cursor.pass(&[]);
+        assert_eq!(ids(cursor.after(&log)), ["c"]);
+    }
+
+    #[test]
+    fn follow_cursor_survives_pruning() {
+        let group = new_group("Team", "Alice", "alice-key", GroupPolicy::default());
+        let mut cursor = FollowCursor::default();
+        cursor.pass(&messages(&group, &["a", "b", "c"], 100));
+
+        // Retention dropped the front of the log, not the last message shown
+        let mut log = messages(&group, &["b", "c", "d", "e"], 101);
+        assert_eq!(ids(cursor.after(&log)), ["d", "e"]);
+        // ... or the last message shown as well: everything sent after it is new
+        log.drain(..2);
+        assert_eq!(ids(cursor.after(&log)), ["d", "e"]);
+    }
+}
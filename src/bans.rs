@@ -0,0 +1,117 @@
+//! Bans: removing a member and keeping them out
+//!
+//! `ban` removes the member in a new commit and records them in the group
+//! policy's deny list, by name and by the fingerprint of their key, so
+//! every member holds the same list. Adds, approvals, joins, and join
+//! requests are refused for anyone on it, including under a new name with
+//! the same key. `unban` lifts a ban without adding the member back. Only
+//! admins may ban or unban, and like other policy changes a ban is checked
+//! by the member making the commit.
+//!
+//! The removed member is sent nothing, so they keep the old epoch and can
+//! read nothing sent after it, as with an MLS Remove.
+
+use anyhow::{anyhow, Context, Result};
+use chrono::Utc;
+use colored::*;
+
+use crate::app::MlsChatApp;
+use crate::commit::Proposals;
+use crate::crypto;
+use crate::model::{Ban, ChatGroup};
+use crate::theme;
+
+impl MlsChatApp {
+    /// Ban an identity from a group, removing them first if they are a member
+    pub fn ban_member(&mut self, group_name: &str, member: &str) -> Result<()> {
+        let user = self.current_user.context("No user initialized")?.to_string();
+        self.check_admin(group_name, "ban members")?;
+        let group = self.groups.get(group_name).context("Group not found")?;
+        let member = group.members.iter().find(|name| name.eq_ignore_ascii_case(member))
+            .cloned()
+            .unwrap_or_else(|| member.to_string());
+        if member == user {
+            return Err(anyhow!("You cannot ban yourself; use `delete-group` to leave"));
+        }
+        if group.mls_group.metadata.policy.bans.iter().any(|ban| ban.member.eq_ignore_ascii_case(&member)) {
+            println!("⚠️  {} is already banned from '{}'", member, group_name);
+            return Ok(());
+        }
+        println!("{}", "Banning member...".green());
+
+        let public_key = match (self.user_keys.get(&member), self.transport.as_mut()) {
+            (Some(key), _) => Some(key.public_key.clone()),
+            (None, Some(transport)) => transport.fetch_key_package(&member).ok().map(|package| package.public_key),
+            (None, None) => None,
+        };
+        let credential = public_key.as_deref().map(crypto::fingerprint);
+        match &credential {
+            Some(fingerprint) => println!("   Banning key fingerprint {}", fingerprint),
+            None => println!("   ⚠️  No key found for {}; banning by name only", member),
+        }
+
+        let group = self.groups.get(group_name).context("Group not found")?;
+        let mut metadata = group.mls_group.metadata.clone();
+        let removed = group.members.contains(&member);
+        metadata.policy.admins.retain(|admin| *admin != member);
+        metadata.policy.bans.push(Ban { member: member.clone(), credential, by: user, at: Utc::now() });
+        if removed {
+            println!("   Remove proposal for {} with a new group secret", member);
+        }
+        println!("   Committing the ban at epoch {}", group.mls_group.epoch + 1);
+        let removes = if removed { vec![member.clone()] } else { Vec::new() };
+        self.commit(group_name, Proposals { removes, metadata: Some(metadata), ..Proposals::default() })?;
+
+        let group = self.groups.get_mut(group_name).context("Group not found")?;
+        group.join_requests.retain(|request| request.member != member);
+        println!("✅ {} {}banned from '{}'", member, if removed { "removed and " } else { "" }, group_name);
+        self.dirty_groups.insert(group_name.to_string());
+        self.save_state()
+    }
+
+    /// Lift a ban; the identity may then be added again, but is not added
+    pub fn unban_member(&mut self, group_name: &str, member: &str) -> Result<()> {
+        self.check_admin(group_name, "unban members")?;
+        let group = self.groups.get(group_name).context("Group not found")?;
+        let mut metadata = group.mls_group.metadata.clone();
+        let position = metadata.policy.bans.iter().position(|ban| ban.member.eq_ignore_ascii_case(member))
+            .with_context(|| format!("'{}' is not banned from '{}'; `bans {}` lists who is", member, group_name, group_name))?;
+        let ban = metadata.policy.bans.remove(position);
+        println!("{}", "Lifting ban...".green());
+        println!("   Committing the change at epoch {}", group.mls_group.epoch + 1);
+        self.commit(group_name, Proposals { metadata: Some(metadata), ..Proposals::default() })?;
+
+        println!("✅ {} is no longer banned from '{}'", ban.member, group_name);
+        self.dirty_groups.insert(group_name.to_string());
+        self.save_state()
+    }
+
+    /// List the identities banned from a group, oldest ban first
+    pub fn list_bans(&self, group_name: &str) -> Result<()> {
+        let group = self.groups.get(group_name).context("Group not found")?;
+        let bans = &group.mls_group.metadata.policy.bans;
+        if bans.is_empty() {
+            println!("Nobody is banned from '{}'.", group_name);
+        }
+        for ban in bans {
+            println!("{} {} by {} (key {})",
+                ban.at.format("%Y-%m-%d %H:%M UTC"),
+                theme::sender(&ban.member, false),
+                ban.by,
+                ban.credential.as_deref().unwrap_or("unknown"),
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Fail if `member`, holding `public_key` if known, is banned from a group
+pub(crate) fn check_banned(group: &ChatGroup, member: &str, public_key: Option<&str>) -> Result<()> {
+    match group.mls_group.metadata.policy.bans.iter().find(|ban| ban.matches(member, public_key)) {
+        Some(ban) if ban.member.eq_ignore_ascii_case(member) => {
+            Err(anyhow!("{} is banned from '{}'; an admin can `unban` them", member, group.name))
+        }
+        Some(ban) => Err(anyhow!("{}'s key is the one banned from '{}' as {}", member, group.name, ban.member)),
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,438 @@
+//! `bench`: how long the engine's core steps take, per storage backend
+//!
+//! `bench --members N --messages M` builds a group of N members and sends it
+//! M messages on each backend in turn (memory, files, and files behind
+//! `CachedStorage`), timing group creation, one add-and-commit per member,
+//! encrypting and storing each message, decrypting them all, and saving and
+//! loading the group and its log. File backends run in a fresh temporary
+//! directory that is removed afterwards; no profile is opened or changed.
+//!
+//! A second table shows how one commit scales with the group: for groups
+//! doubling in size up to `--scale` members, it times a single add, update,
+//! and remove through the ratchet tree, counts the path nodes and path
+//! secrets the Commit carries, and times the whole-member-list commit that
+//! groups from before the tree still make, for comparison. Each group is
+//! filled with one commit adding everyone, then an update from every other
+//! leaf so every parent has a key, as in a group whose members have all
+//! committed since joining.
+//!
+//! Last, it onboards `--onboard` new members into the `--members` group at
+//! once with `commit::batch_savings`, as `add-members` and `approve --all`
+//! do, and compares the one commit with one commit per new member.
+//!
+//! The mock has only two identities, so members are synthetic names with
+//! mock keys. Each step runs the code the command behind it runs, building
+//! the Commit and Welcome with `commit::commit_tree`, without printing or a
+//! delivery service, so the numbers compare designs rather than predict a
+//! real deployment.
+
+use anyhow::{anyhow, Context, Result};
+use chrono::Utc;
+use colored::*;
+use std::{
+    env, fs,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+use uuid::Uuid;
+
+use crate::app::{new_group, new_message};
+use crate::cache::{CachedStorage, DEFAULT_CACHED_MESSAGES};
+use crate::commit::{self, BatchSavings, Proposals};
+use crate::content::Content;
+use crate::model::{ChatGroup, ChatMessage, GroupPolicy};
+use crate::output;
+use crate::protocol::{self, MlsMessage};
+use crate::storage::{FileStorage, MemoryStorage, Storage};
+
+/// How many times each save and load is repeated, to average out noise
+const SAMPLES: u32 = 10;
+
+/// What one backend took for each step
+#[derive(Debug, Clone)]
+pub struct BackendTimings {
+    pub backend: &'static str,
+    pub create: Duration,
+    pub commits: Duration, // All adds together
+    pub encrypt: Duration, // Encrypting and storing every message
+    pub decrypt: Duration, // Decrypting every message
+    pub save_group: Duration, // Average of `SAMPLES`
+    pub load_groups: Duration, // Average of `SAMPLES`
+    pub load_messages: Duration, // Average of `SAMPLES`
+}
+
+/// What one commit took in a group of `members`, averaged over `SAMPLES`
+#[derive(Debug, Clone)]
+pub struct CommitScaling {
+    pub members: usize,
+    pub add: Duration,
+    pub update: Duration,
+    pub remove: Duration,
+    pub path_nodes: usize, // In the update's path
+    pub path_secrets: usize, // Ciphertexts in the update's path
+    pub commit_bytes: usize, // The update's Commit
+    pub whole_group: Duration, // One commit the way groups without a ratchet tree make it
+    pub whole_group_bytes: usize,
+}
+
+/// Everything `bench` measured
+#[derive(Debug, Clone)]
+pub struct BenchReport {
+    pub members: usize,
+    pub messages: usize,
+    pub backends: Vec<BackendTimings>,
+    pub scaling: Vec<CommitScaling>,
+    pub onboarding: Option<BatchSavings>, // Into the group of `members`
+}
+
+/// A scratch data directory, removed when dropped
+struct ScratchDir(PathBuf);
+
+impl ScratchDir {
+    fn new() -> Self {
+        Self(env::temp_dir().join(format!("mls-chat-bench-{}", Uuid::new_v4())))
+    }
+}
+
+impl Drop for ScratchDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Time `step`, returning what it returned and how long it took
+fn timed<T>(step: impl FnOnce() -> Result<T>) -> Result<(T, Duration)> {
+    let start = Instant::now();
+    let value = step()?;
+    Ok((value, start.elapsed()))
+}
+
+/// The average time of `SAMPLES` runs of `step`
+fn averaged(mut step: impl FnMut() -> Result<()>) -> Result<Duration> {
+    let start = Instant::now();
+    for _ in 0..SAMPLES {
+        step()?;
+    }
+    Ok(start.elapsed() / SAMPLES)
+}
+
+/// A synthetic member's name
+fn member(number: usize) -> String {
+    format!("member-{}", number)
+}
+
+/// A new benchmark group with `member(1)` as its creator
+fn bench_group() -> ChatGroup {
+    let creator = member(1);
+    new_group("Bench", &creator, &format!("pub_key_{}", Uuid::new_v4()), GroupPolicy { admins: vec![creator.clone()], ..GroupPolicy::default() })
+}
+
+/// Commit `proposals` to `group` as `committer`, serializing what `MlsChatApp::commit` would send
+///
+/// Returns the Commit, so its path can be counted. Welcomes are built only
+/// with `welcomes`, since each carries the whole tree.
+fn commit_as(group: &mut ChatGroup, committer: &str, proposals: Proposals, welcomes: bool) -> Result<MlsMessage> {
+    let mut tree = group.mls_group.tree.take().context("Benchmark groups have a ratchet tree")?;
+    let committed = commit::commit_tree(group, &mut tree, committer, &proposals, |_| welcomes);
+    let committed = match committed {
+        Ok(committed) => committed,
+        Err(err) => {
+            group.mls_group.tree = Some(tree);
+            return Err(err);
+        }
+    };
+    committed.commit.to_bytes()?;
+    for (_, welcome) in &committed.welcomes {
+        welcome.to_bytes()?;
+    }
+    let commit = committed.commit.clone();
+    commit::finish_tree_commit(group, tree, committed, proposals);
+    Ok(commit)
+}
+
+/// Add `member` to `group` in one commit by its creator, with a Welcome, as `add-member` does
+fn add_and_commit(group: &mut ChatGroup, member: &str) -> Result<()> {
+    let adds = vec![(member.to_string(), format!("pub_key_{}", Uuid::new_v4()))];
+    commit_as(group, &self::member(1), Proposals { adds, ..Proposals::default() }, true).map(drop)
+}
+
+/// Run every step on one backend
+fn bench_backend(backend: &'static str, mut storage: Box<dyn Storage>, members: usize, messages: usize) -> Result<BackendTimings> {
+    let (mut group, create) = timed(|| {
+        let group = bench_group();
+        storage.save_group(&group)?;
+        Ok(group)
+    })?;
+
+    let ((), commits) = timed(|| {
+        for number in 2..=members {
+            add_and_commit(&mut group, &member(number))?;
+            storage.save_group(&group)?;
+        }
+        Ok(())
+    })?;
+
+    let key = protocol::application_key(&group.mls_group.group_secret);
+    let (sent, encrypt) = timed(|| {
+        let mut sent: Vec<ChatMessage> = Vec::with_capacity(messages);
+        for number in 0..messages {
+            let sender = group.members[number % group.members.len()].clone();
+            let content = Content::Text { text: format!("Benchmark message {} from {}", number, sender) };
+            let ciphertext = protocol::encrypt(&key, &content.to_plaintext()?);
+            let message = new_message(Uuid::new_v4().to_string(), sender, content, ciphertext, Utc::now(), &group)?;
+            storage.append_message(&message)?;
+            sent.push(message);
+        }
+        Ok(sent)
+    })?;
+    group.message_count = sent.len();
+
+    let ((), decrypt) = timed(|| {
+        for message in &sent {
+            let plaintext = protocol::decrypt(&key, &message.encrypted_content)?;
+            serde_json::from_str::<Content>(&plaintext).context("Benchmark message did not decrypt to content")?;
+        }
+        Ok(())
+    })?;
+
+    let save_group = averaged(|| storage.save_group(&group))?;
+    let load_groups = averaged(|| storage.load_groups().map(drop))?;
+    let load_messages = averaged(|| storage.load_messages(&group.group_id).map(drop))?;
+    Ok(BackendTimings { backend, create, commits, encrypt, decrypt, save_group, load_groups, load_messages })
+}
+
+/// A group of `members` in which every parent node has a key
+fn full_group(members: usize) -> Result<ChatGroup> {
+    let mut group = bench_group();
+    let adds = (2..=members).map(|number| (member(number), format!("pub_key_{}", Uuid::new_v4()))).collect();
+    commit_as(&mut group, &member(1), Proposals { adds, ..Proposals::default() }, false)?;
+    // Every even leaf's update covers every parent once. Taking the leaves in
+    // bit-reversed order, each update finds most of its copath already keyed,
+    // so filling the tree costs O(n log n) rather than O(n²).
+    let width = group.mls_group.tree.as_ref().map_or(1, |tree| tree.width()).max(2);
+    let bits = width.trailing_zeros();
+    for position in 0..width / 2 {
+        let leaf = (position.reverse_bits() >> (u32::BITS - bits)) as usize;
+        if leaf < members {
+            // Leaves are filled in order by the one big commit, so leaf i is member i + 1
+            commit_as(&mut group, &member(leaf + 1), Proposals::default(), false)?;
+        }
+    }
+    Ok(group)
+}
+
+/// One commit the way a group without a ratchet tree makes it, as `commit_legacy` does
+fn whole_group_commit(group: &ChatGroup) -> Result<usize> {
+    let mut next = group.mls_group.clone();
+    next.tree = None;
+    next.path_secrets.clear();
+    next.epoch += 1;
+    next.group_secret = format!("group_secret_{}", Uuid::new_v4());
+    let commit = MlsMessage::Commit {
+        group_id: group.group_id.clone(),
+        epoch: next.epoch,
+        tree_hash: format!("tree_hash_{}", Uuid::new_v4()),
+        members: next.members.clone(),
+        encrypted_group_secret: protocol::encrypt(&protocol::commit_key(&group.mls_group.group_secret), &next.group_secret),
+        metadata: next.metadata.clone(),
+        tree: None,
+    };
+    Ok(commit.to_bytes()?.len())
+}
+
+/// Time single commits in a full group of `members`
+fn bench_scaling(members: usize) -> Result<CommitScaling> {
+    let mut group = full_group(members)?;
+    let committer = member(1);
+    let joiner = member(members + 1);
+    let (mut add, mut update, mut remove) = (Duration::ZERO, Duration::ZERO, Duration::ZERO);
+    let mut commit = None;
+    for _ in 0..SAMPLES {
+        let adds = vec![(joiner.clone(), format!("pub_key_{}", Uuid::new_v4()))];
+        add += timed(|| commit_as(&mut group, &committer, Proposals { adds, ..Proposals::default() }, false))?.1;
+        let (updated, took) = timed(|| commit_as(&mut group, &committer, Proposals::default(), false))?;
+        update += took;
+        commit = Some(updated);
+        let removes = vec![joiner.clone()];
+        remove += timed(|| commit_as(&mut group, &committer, Proposals { removes, ..Proposals::default() }, false))?.1;
+    }
+    let commit = commit.context("No samples")?;
+    let MlsMessage::Commit { tree: Some(change), .. } = &commit else {
+        return Err(anyhow!("A tree group's commit has no tree change"));
+    };
+    let path = change.path.as_ref().context("An update commits a path")?;
+    let mut whole_group_bytes = 0;
+    let whole_group = averaged(|| {
+        whole_group_bytes = whole_group_commit(&group)?;
+        Ok(())
+    })?;
+    Ok(CommitScaling {
+        members,
+        add: add / SAMPLES,
+        update: update / SAMPLES,
+        remove: remove / SAMPLES,
+        path_nodes: path.nodes.len(),
+        path_secrets: path.nodes.iter().map(|node| node.secrets.len()).sum(),
+        commit_bytes: commit.to_bytes()?.len(),
+        whole_group,
+        whole_group_bytes,
+    })
+}
+
+/// Group sizes for the scaling table: doubling from 16 up to `largest`, which is always included
+fn scaling_sizes(largest: usize) -> Vec<usize> {
+    let mut sizes: Vec<usize> = std::iter::successors(Some(16), |size| Some(size * 2))
+        .take_while(|size| *size < largest)
+        .collect();
+    sizes.push(largest.max(2));
+    sizes
+}
+
+/// Time a group of `members` with `messages` messages on every backend, commits in groups of up to
+/// `scale`, and onboarding `onboard` more members at once
+pub fn run(members: usize, messages: usize, scale: usize, onboard: usize) -> Result<BenchReport> {
+    let members = members.max(1);
+    let mut backends = vec![bench_backend("memory", Box::new(MemoryStorage::new()), members, messages)?];
+
+    let scratch = ScratchDir::new();
+    let storage = FileStorage::open(&scratch.0)?;
+    backends.push(bench_backend("file", Box::new(storage), members, messages)?);
+
+    let scratch = ScratchDir::new();
+    let storage = CachedStorage::new(Box::new(FileStorage::open(&scratch.0)?), messages.max(DEFAULT_CACHED_MESSAGES));
+    backends.push(bench_backend("file + cache", Box::new(storage), members, messages)?);
+
+    let scaling = scaling_sizes(scale).into_iter().map(bench_scaling).collect::<Result<_>>()?;
+    let adds: Vec<(String, String)> = (members + 1..=members + onboard)
+        .map(|number| (member(number), format!("pub_key_{}", Uuid::new_v4())))
+        .collect();
+    let onboarding = commit::batch_savings(&full_group(members)?, &member(1), &adds)?;
+    Ok(BenchReport { members, messages, backends, scaling, onboarding })
+}
+
+/// A duration in the unit that reads best
+pub(crate) fn latency(duration: Duration) -> String {
+    let micros = duration.as_secs_f64() * 1e6;
+    match micros {
+        micros if micros < 1000.0 => format!("{:.1} µs", micros),
+        micros if micros < 1e6 => format!("{:.2} ms", micros / 1000.0),
+        micros => format!("{:.2} s", micros / 1e6),
+    }
+}
+
+/// A byte count in the unit that reads best
+pub(crate) fn size(bytes: usize) -> String {
+    match bytes {
+        bytes if bytes < 1024 => format!("{} B", bytes),
+        bytes if bytes < 1024 * 1024 => format!("{:.1} KiB", bytes as f64 / 1024.0),
+        bytes => format!("{:.1} MiB", bytes as f64 / (1024.0 * 1024.0)),
+    }
+}
+
+/// Print `rows` under `header`, the first column left-aligned and the rest right-aligned
+fn print_table(header: &[String], rows: &[Vec<String>]) {
+    let columns = header.len();
+    let widths: Vec<usize> = (0..columns)
+        .map(|column| {
+            std::iter::once(&header[column])
+                .chain(rows.iter().map(|row| &row[column]))
+                .map(|cell| cell.chars().count())
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+    let line = |cells: &[String]| {
+        let cells: Vec<String> = cells.iter()
+            .zip(&widths)
+            .enumerate()
+            .map(|(column, (cell, width))| match column {
+                0 => format!("{:<width$}", cell, width = width),
+                _ => format!("{:>width$}", cell, width = width),
+            })
+            .collect();
+        println!("{}", cells.join("  "));
+    };
+    line(header);
+    output::rule('-', widths.iter().sum::<usize>() + 2 * (columns - 1));
+    for row in rows {
+        line(row);
+    }
+}
+
+/// `count` operations in `duration` as a rate
+fn rate(count: usize, duration: Duration, unit: &str) -> String {
+    match count {
+        0 => "-".to_string(),
+        count => format!("{:.0} {}/s", count as f64 / duration.as_secs_f64().max(1e-9), unit),
+    }
+}
+
+impl BenchReport {
+    /// Print the timings as a table, one column per backend
+    pub fn print(&self) {
+        let adds = self.members - 1;
+        println!("{}", format!("Benchmark: {} member(s), {} message(s)", self.members, self.messages).blue());
+        let rows: Vec<(&str, Vec<String>)> = vec![
+            ("Create group", self.backends.iter().map(|timings| latency(timings.create)).collect()),
+            ("Add + commit", self.backends.iter().map(|timings| rate(adds, timings.commits, "adds")).collect()),
+            ("Encrypt + store", self.backends.iter().map(|timings| rate(self.messages, timings.encrypt, "msgs")).collect()),
+            ("Decrypt", self.backends.iter().map(|timings| rate(self.messages, timings.decrypt, "msgs")).collect()),
+            ("Save group", self.backends.iter().map(|timings| latency(timings.save_group)).collect()),
+            ("Load groups", self.backends.iter().map(|timings| latency(timings.load_groups)).collect()),
+            ("Load messages", self.backends.iter().map(|timings| latency(timings.load_messages)).collect()),
+        ];
+        let header: Vec<String> = std::iter::once(String::new())
+            .chain(self.backends.iter().map(|timings| timings.backend.to_string()))
+            .collect();
+        let rows: Vec<Vec<String>> = rows.into_iter()
+            .map(|(label, cells)| std::iter::once(label.to_string()).chain(cells).collect())
+            .collect();
+        print_table(&header, &rows);
+        println!();
+        println!("Saves and loads are the average of {} runs; the cached backend keeps every message in memory", SAMPLES);
+
+        if self.scaling.is_empty() {
+            return;
+        }
+        println!();
+        println!("{}", "One commit, by group size".blue());
+        let header: Vec<String> = ["Members", "Add", "Update", "Remove", "Path", "Secrets", "Commit", "Whole-group commit"]
+            .iter()
+            .map(|title| title.to_string())
+            .collect();
+        let rows: Vec<Vec<String>> = self.scaling.iter()
+            .map(|row| vec![
+                row.members.to_string(),
+                latency(row.add),
+                latency(row.update),
+                latency(row.remove),
+                row.path_nodes.to_string(),
+                row.path_secrets.to_string(),
+                size(row.commit_bytes),
+                format!("{} / {}", latency(row.whole_group), size(row.whole_group_bytes)),
+            ])
+            .collect();
+        print_table(&header, &rows);
+        println!();
+        println!("Averages of {} runs in a group where every member has committed; Path, Secrets, and Commit are the update's", SAMPLES);
+        println!("Each size is a full tree, so every add here doubles the tree and every remove truncates it again, the worst case for both");
+        println!("Welcomes carry the whole tree, so an add's Welcome is left out; the whole-group commit is the one groups from before the ratchet tree still make");
+
+        let Some(onboarding) = &self.onboarding else { return };
+        println!();
+        println!("{}", format!("Onboarding {} member(s) into a group of {}", onboarding.joiners, self.members).blue());
+        let header: Vec<String> = ["", "Commits", "Time", "Sent"].iter().map(|title| title.to_string()).collect();
+        let rows = vec![
+            vec!["One commit".to_string(), "1".to_string(), latency(onboarding.batched), size(onboarding.batched_bytes)],
+            vec![
+                "One per member".to_string(),
+                onboarding.joiners.to_string(),
+                latency(onboarding.sequential),
+                size(onboarding.sequential_bytes),
+            ],
+        ];
+        print_table(&header, &rows);
+        println!();
+        println!("One commit saves {} and {} epochs; Sent counts the Commits and a Welcome for each new member", latency(onboarding.saved()), onboarding.joiners - 1);
+    }
+}
@@ -0,0 +1,184 @@
+//! Automated group members
+//!
+//! A `Bot` drives an app in networked mode: each round it drains the inbox,
+//! applies every envelope, and hands new members and new messages from other
+//! members to the registered handlers, which answer through `BotContext`.
+//! See `examples/echo_bot.rs` for a complete bot.
+
+use anyhow::{anyhow, Context, Result};
+use colored::*;
+use std::{
+    collections::{BTreeSet, HashMap},
+    thread,
+    time::Duration,
+};
+
+use crate::app::MlsChatApp;
+use crate::content::Content;
+use crate::model::{ChatMessage, UserName};
+
+/// How often a bot checks its inbox, unless set with `with_poll_interval`
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+type Handler = Box<dyn FnMut(&mut BotContext, &ChatMessage) -> Result<()>>;
+type MemberHandler = Box<dyn FnMut(&mut BotContext, &str) -> Result<()>>;
+
+/// A group member run by code instead of a person
+pub struct Bot {
+    app: MlsChatApp,
+    handlers: Vec<Handler>,
+    member_handlers: Vec<MemberHandler>,
+    poll_interval: Duration,
+}
+
+/// What a handler can do in the group its event came from
+pub struct BotContext<'a> {
+    app: &'a mut MlsChatApp,
+    group: String, // Name of the group the event came from
+}
+
+impl Bot {
+    /// Wrap an app with an initialized user and a delivery service
+    pub fn new(app: MlsChatApp) -> Result<Self> {
+        app.current_user().context("No user initialized")?;
+        if app.transport.is_none() {
+            return Err(anyhow!("A bot needs a delivery service (pass --server <addr>)"));
+        }
+        Ok(Self { app, handlers: Vec::new(), member_handlers: Vec::new(), poll_interval: DEFAULT_POLL_INTERVAL })
+    }
+
+    /// Call `handler` for every new message from another member, in arrival order
+    pub fn on_message(mut self, handler: impl FnMut(&mut BotContext, &ChatMessage) -> Result<()> + 'static) -> Self {
+        self.handlers.push(Box::new(handler));
+        self
+    }
+
+    /// Call `handler` with each member added to a group, including the bot itself when it joins one
+    pub fn on_member_added(mut self, handler: impl FnMut(&mut BotContext, &str) -> Result<()> + 'static) -> Self {
+        self.member_handlers.push(Box::new(handler));
+        self
+    }
+
+    /// Check the inbox every `interval` instead of every second
+    pub fn with_poll_interval(mut self, interval: Duration) -> Self {
+        self.poll_interval = interval;
+        self
+    }
+
+    /// The app the bot drives, for setup before `run`
+    pub fn app(&mut self) -> &mut MlsChatApp {
+        &mut self.app
+    }
+
+    /// Post `content` to every group the bot belongs to
+    pub fn announce(&mut self, content: &str) -> Result<()> {
+        let mut names: Vec<String> = self.app.groups.keys().cloned().collect();
+        names.sort();
+        for name in names {
+            BotContext { app: &mut self.app, group: name }.reply(content)?;
+        }
+        self.app.save_state()
+    }
+
+    /// Handle messages until the process is stopped
+    pub fn run(&mut self) -> Result<()> {
+        let user = self.app.current_user().context("No user initialized")?;
+        println!("{}", format!("Running bot as {} (Ctrl-C to stop)...", user).green());
+        loop {
+            if let Err(err) = self.step() {
+                println!("⚠️  Bot round failed: {:#}", err);
+            }
+            thread::sleep(self.poll_interval);
+        }
+    }
+
+    /// One round of `run`: apply the inbox and dispatch what changed; returns how many events were handled
+    pub fn step(&mut self) -> Result<usize> {
+        let user = self.app.current_user().context("No user initialized")?.to_string();
+        self.app.send_due_scheduled()?;
+        let members_before: HashMap<String, Vec<String>> = self.app.groups.values()
+            .map(|group| (group.group_id.clone(), group.members.clone()))
+            .collect();
+        let mut messages = Vec::new();
+        for envelope in self.app.transport()?.fetch_inbox(&user)? {
+            match self.app.apply_envelope(&envelope) {
+                Ok(Some(message)) if message.sender != user => messages.push(message),
+                Ok(_) => {}
+                Err(err) => println!("⚠️  Skipped message {} from '{}': {}", envelope.message_id, envelope.sender, err),
+            }
+        }
+
+        let mut added = Vec::new();
+        let mut names: Vec<String> = self.app.groups.keys().cloned().collect();
+        names.sort();
+        for name in names {
+            let group = self.app.group(&name).context("Group not found")?;
+            match members_before.get(&group.group_id) {
+                Some(before) => added.extend(group.members.iter()
+                    .filter(|member| !before.contains(member))
+                    .map(|member| (name.clone(), member.clone()))),
+                None => added.push((name.clone(), user.clone())),
+            }
+        }
+        for (group, member) in &added {
+            for handler in self.member_handlers.iter_mut() {
+                let mut context = BotContext { app: &mut self.app, group: group.clone() };
+                if let Err(err) = handler(&mut context, member) {
+                    println!("⚠️  Handler failed on '{}' joining '{}': {:#}", member, group, err);
+                }
+            }
+        }
+
+        let mut read = BTreeSet::new();
+        for message in &messages {
+            let Some(group) = self.app.group_name_by_id(&message.group_id) else { continue };
+            for handler in self.handlers.iter_mut() {
+                let mut context = BotContext { app: &mut self.app, group: group.clone() };
+                if let Err(err) = handler(&mut context, message) {
+                    println!("⚠️  Handler failed on message {} from '{}': {:#}", message.id, message.sender, err);
+                }
+            }
+            read.insert(group);
+        }
+        for group in read {
+            self.app.send_read_receipts(&group)?;
+        }
+        self.app.save_state()?;
+        Ok(added.len() + messages.len())
+    }
+}
+
+impl BotContext<'_> {
+    /// Name of the group the event came from
+    pub fn group(&self) -> &str {
+        &self.group
+    }
+
+    /// The user the bot runs as
+    pub fn user(&self) -> Option<UserName> {
+        self.app.current_user()
+    }
+
+    /// Send `content` to the group the event came from
+    pub fn reply(&mut self, content: &str) -> Result<ChatMessage> {
+        let group = self.group.clone();
+        self.send(&group, content)
+    }
+
+    /// Label a message in this group; see `MlsChatApp::tag_message`
+    pub fn tag(&mut self, message_id: &str, tag: &str) -> Result<()> {
+        self.app.tag_message(&self.group, message_id, tag)
+    }
+
+    /// Send `content` to any group the bot belongs to
+    pub fn send(&mut self, group_name: &str, content: &str) -> Result<ChatMessage> {
+        let user = self.app.current_user().context("No user initialized")?;
+        let group = self.app.group(group_name).context("Group not found")?;
+        if !group.members.contains(&user.to_string()) {
+            return Err(anyhow!("User '{}' is not a member of group '{}'", user, group_name));
+        }
+        let message = self.app.post_message(group_name, user.to_string(), Content::Markdown { text: content.to_string() })?;
+        println!("   🤖 {} → '{}': {}", user.to_string().yellow(), group_name, content);
+        Ok(message)
+    }
+}
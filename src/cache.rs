@@ -0,0 +1,475 @@
+//! In-process cache over a storage backend, for the long-running modes
+//!
+//! `list --follow`, `bot`, and `api` keep one profile open for hours and go
+//! back to storage constantly: every `follow` round reloads the whole
+//! profile, and every API request reloads a group's messages. `CachedStorage`
+//! keeps the group metadata, identities, and message logs it last read in
+//! memory and hands them out again while the backend's `stamp` for them, the
+//! size and modification time of the files behind them, is unchanged. A
+//! repeated read then costs a few `stat` calls instead of reading and parsing
+//! JSON again.
+//!
+//! Writes go straight through to the backend. A group or message log written
+//! through the cache updates the cached copy if nothing else changed the
+//! files since they were read, and drops it otherwise, so what other commands
+//! write always shows up on the next read. Message logs are cached up to
+//! `cache.messages` messages in all, keeping the groups used most recently; a
+//! backend that cannot stamp what it stores, like `MemoryStorage`, whose
+//! clones may change it unseen, is always read through.
+
+use anyhow::Result;
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    fs,
+    path::PathBuf,
+    time::SystemTime,
+};
+
+use crate::model::{ChatGroup, ChatMessage, GroupSnapshot, MockKey, PeerKey, RevocationList, Session, Transfer, TreeHead, UserName};
+use crate::storage::Storage;
+
+/// How many messages in all `CachedStorage` keeps, unless `cache.messages` says otherwise
+pub const DEFAULT_CACHED_MESSAGES: usize = 10_000;
+
+/// What a backend can stamp, so a cached copy of it can be checked
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Item<'a> {
+    Groups, // Every group's metadata
+    Messages(&'a str), // One group's message log, by group ID
+    UserKeys,
+    CurrentUser,
+}
+
+/// The size and modification time of each file an item is stored in
+///
+/// Equal stamps mean the item was not written in between.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stamp(Vec<(PathBuf, u64, Option<SystemTime>)>);
+
+impl Stamp {
+    /// Stamp the files among `paths` that exist
+    pub fn of_files(paths: impl IntoIterator<Item = PathBuf>) -> Self {
+        Self(paths.into_iter()
+            .filter_map(|path| {
+                let metadata = fs::metadata(&path).ok()?;
+                Some((path, metadata.len(), metadata.modified().ok()))
+            })
+            .collect())
+    }
+}
+
+/// A copy of an item as it was when `stamp` was taken
+struct Cached<T> {
+    stamp: Stamp,
+    value: T,
+    used: u64, // When it was last read, on the cache's own clock
+}
+
+/// A storage backend with the items it reads kept in memory
+pub struct CachedStorage {
+    inner: Box<dyn Storage>,
+    limit: usize, // Messages kept across all logs
+    clock: Cell<u64>,
+    groups: RefCell<Option<Cached<HashMap<String, ChatGroup>>>>, // Keyed by group ID
+    messages: RefCell<HashMap<String, Cached<Vec<ChatMessage>>>>, // Keyed by group ID
+    user_keys: RefCell<Option<Cached<HashMap<String, MockKey>>>>,
+    current_user: RefCell<Option<Cached<Option<UserName>>>>,
+}
+
+/// `slot`'s value if its stamp is still `stamp`, otherwise `load`'s, kept in `slot`
+fn read_through<T: Clone>(slot: &RefCell<Option<Cached<T>>>, stamp: Option<Stamp>, load: impl FnOnce() -> Result<T>) -> Result<T> {
+    // Stamped before loading, so a write during the load is caught on the next read
+    let Some(stamp) = stamp else { return load() };
+    if let Some(cached) = slot.borrow().as_ref().filter(|cached| cached.stamp == stamp) {
+        return Ok(cached.value.clone());
+    }
+    let value = load()?;
+    *slot.borrow_mut() = Some(Cached { stamp, value: value.clone(), used: 0 });
+    Ok(value)
+}
+
+/// Run `write` on `inner`, then bring `slot` up to date with `update`, or drop it if something else wrote the item first
+fn write_through<T>(
+    inner: &mut dyn Storage,
+    slot: &mut Option<Cached<T>>,
+    item: Item<'_>,
+    write: impl FnOnce(&mut dyn Storage) -> Result<()>,
+    update: impl FnOnce(&mut T),
+) -> Result<()> {
+    let before = inner.stamp(item)?;
+    let current = slot.take().filter(|cached| before.as_ref() == Some(&cached.stamp));
+    write(inner)?;
+    if let (Some(mut cached), Some(stamp)) = (current, inner.stamp(item)?) {
+        update(&mut cached.value);
+        cached.stamp = stamp;
+        *slot = Some(cached);
+    }
+    Ok(())
+}
+
+impl CachedStorage {
+    /// Cache what `inner` stores, holding at most `limit` messages
+    pub fn new(inner: Box<dyn Storage>, limit: usize) -> Self {
+        Self {
+            inner,
+            limit,
+            clock: Cell::new(0),
+            groups: RefCell::new(None),
+            messages: RefCell::new(HashMap::new()),
+            user_keys: RefCell::new(None),
+            current_user: RefCell::new(None),
+        }
+    }
+
+    fn tick(&self) -> u64 {
+        self.clock.set(self.clock.get() + 1);
+        self.clock.get()
+    }
+
+    /// Keep `cached` as a group's log, then forget the least recently used logs until under the limit
+    fn keep_messages(&self, group_id: &str, cached: Cached<Vec<ChatMessage>>) {
+        let mut logs = self.messages.borrow_mut();
+        logs.insert(group_id.to_string(), cached);
+        while logs.values().map(|cached| cached.value.len()).sum::<usize>() > self.limit {
+            let Some(oldest) = logs.iter().min_by_key(|(_, cached)| cached.used).map(|(id, _)| id.clone()) else { break };
+            logs.remove(&oldest);
+        }
+    }
+
+    /// Write to a group's log through the cache, updating its cached copy with `update`
+    fn write_messages(
+        &mut self,
+        group_id: &str,
+        write: impl FnOnce(&mut dyn Storage) -> Result<()>,
+        update: impl FnOnce(&mut Vec<ChatMessage>),
+    ) -> Result<()> {
+        let mut slot = self.messages.get_mut().remove(group_id);
+        write_through(&mut *self.inner, &mut slot, Item::Messages(group_id), write, update)?;
+        if let Some(mut cached) = slot {
+            cached.used = self.tick();
+            self.keep_messages(group_id, cached);
+        }
+        Ok(())
+    }
+}
+
+impl Storage for CachedStorage {
+    fn stamp(&self, item: Item<'_>) -> Result<Option<Stamp>> {
+        self.inner.stamp(item)
+    }
+
+    fn load_groups(&self) -> Result<Vec<ChatGroup>> {
+        let groups = read_through(&self.groups, self.inner.stamp(Item::Groups)?, || {
+            Ok(self.inner.load_groups()?.into_iter().map(|group| (group.group_id.clone(), group)).collect())
+        })?;
+        Ok(groups.into_values().collect())
+    }
+
+    fn save_group(&mut self, group: &ChatGroup) -> Result<()> {
+        let mut stored = group.clone();
+        stored.messages = None;
+        write_through(&mut *self.inner, self.groups.get_mut(), Item::Groups, |inner| inner.save_group(group), |groups| {
+            groups.insert(stored.group_id.clone(), stored);
+        })
+    }
+
+    fn load_messages(&self, group_id: &str) -> Result<Vec<ChatMessage>> {
+        let Some(stamp) = self.inner.stamp(Item::Messages(group_id))? else { return self.inner.load_messages(group_id) };
+        let used = self.tick();
+        if let Some(cached) = self.messages.borrow_mut().get_mut(group_id).filter(|cached| cached.stamp == stamp) {
+            cached.used = used;
+            return Ok(cached.value.clone());
+        }
+        let messages = self.inner.load_messages(group_id)?;
+        self.keep_messages(group_id, Cached { stamp, value: messages.clone(), used });
+        Ok(messages)
+    }
+
+    fn stream_messages(&self, group_id: &str, visit: &mut dyn FnMut(ChatMessage) -> Result<()>) -> Result<()> {
+        // Only a log already cached is served from memory; streaming never fills the cache
+        let stamp = self.inner.stamp(Item::Messages(group_id))?;
+        let cached = self.messages.borrow()
+            .get(group_id)
+            .filter(|cached| stamp.as_ref() == Some(&cached.stamp))
+            .map(|cached| cached.value.clone());
+        match cached {
+            Some(messages) => messages.into_iter().try_for_each(visit),
+            None => self.inner.stream_messages(group_id, visit),
+        }
+    }
+
+    fn find_messages(&self, prefix: &str) -> Result<Vec<ChatMessage>> {
+        self.inner.find_messages(prefix)
+    }
+
+    fn message_logs(&self) -> Result<Vec<String>> {
+        self.inner.message_logs()
+    }
+
+    fn append_message(&mut self, message: &ChatMessage) -> Result<()> {
+        self.write_messages(&message.group_id, |inner| inner.append_message(message), |messages| messages.push(message.clone()))
+    }
+
+    fn replace_messages(&mut self, group_id: &str, messages: &[ChatMessage]) -> Result<()> {
+        self.write_messages(group_id, |inner| inner.replace_messages(group_id, messages), |cached| *cached = messages.to_vec())
+    }
+
+    fn delete_group(&mut self, group_id: &str) -> Result<()> {
+        self.messages.get_mut().remove(group_id);
+        write_through(&mut *self.inner, self.groups.get_mut(), Item::Groups, |inner| inner.delete_group(group_id), |groups| {
+            groups.remove(group_id);
+        })
+    }
+
+    fn load_snapshots(&self, group_id: &str) -> Result<Vec<GroupSnapshot>> {
+        self.inner.load_snapshots(group_id)
+    }
+
+    fn save_snapshot(&mut self, snapshot: &GroupSnapshot) -> Result<()> {
+        self.inner.save_snapshot(snapshot)
+    }
+
+    fn load_transfers(&self) -> Result<Vec<Transfer>> {
+        self.inner.load_transfers()
+    }
+
+    fn load_transfer(&self, id: &str) -> Result<Option<Transfer>> {
+        self.inner.load_transfer(id)
+    }
+
+    fn save_transfer(&mut self, transfer: &Transfer) -> Result<()> {
+        self.inner.save_transfer(transfer)
+    }
+
+    fn delete_transfer(&mut self, id: &str) -> Result<()> {
+        self.inner.delete_transfer(id)
+    }
+
+    fn load_user_keys(&self) -> Result<HashMap<String, MockKey>> {
+        read_through(&self.user_keys, self.inner.stamp(Item::UserKeys)?, || self.inner.load_user_keys())
+    }
+
+    fn save_user_keys(&mut self, keys: &HashMap<String, MockKey>) -> Result<()> {
+        // Rare, and how the saved keys merge with stored ones is the backend's business
+        *self.user_keys.get_mut() = None;
+        self.inner.save_user_keys(keys)
+    }
+
+    fn delete_identities(&mut self) -> Result<()> {
+        *self.user_keys.get_mut() = None;
+        *self.current_user.get_mut() = None;
+        self.inner.delete_identities()
+    }
+
+    fn load_current_user(&self) -> Result<Option<UserName>> {
+        read_through(&self.current_user, self.inner.stamp(Item::CurrentUser)?, || self.inner.load_current_user())
+    }
+
+    fn save_current_user(&mut self, user: UserName) -> Result<()> {
+        write_through(&mut *self.inner, self.current_user.get_mut(), Item::CurrentUser, |inner| inner.save_current_user(user), |current| {
+            *current = Some(user);
+        })
+    }
+
+    fn load_server_tokens(&self) -> Result<HashMap<String, HashMap<String, String>>> {
+        self.inner.load_server_tokens()
+    }
+
+    fn save_server_tokens(&mut self, tokens: &HashMap<String, HashMap<String, String>>) -> Result<()> {
+        self.inner.save_server_tokens(tokens)
+    }
+
+    fn load_peer_keys(&self) -> Result<HashMap<String, HashMap<String, PeerKey>>> {
+        self.inner.load_peer_keys()
+    }
+
+    fn save_peer_keys(&mut self, keys: &HashMap<String, HashMap<String, PeerKey>>) -> Result<()> {
+        self.inner.save_peer_keys(keys)
+    }
+
+    fn load_revocations(&self) -> Result<Option<RevocationList>> {
+        self.inner.load_revocations()
+    }
+
+    fn save_revocations(&mut self, list: &RevocationList) -> Result<()> {
+        self.inner.save_revocations(list)
+    }
+
+    fn load_tree_heads(&self) -> Result<HashMap<String, TreeHead>> {
+        self.inner.load_tree_heads()
+    }
+
+    fn save_tree_heads(&mut self, heads: &HashMap<String, TreeHead>) -> Result<()> {
+        self.inner.save_tree_heads(heads)
+    }
+
+    fn load_session(&self) -> Result<Option<Session>> {
+        self.inner.load_session()
+    }
+
+    fn save_session(&mut self, session: &Session) -> Result<()> {
+        self.inner.save_session(session)
+    }
+
+    fn delete_session(&mut self) -> Result<()> {
+        self.inner.delete_session()
+    }
+
+    fn location(&self) -> String {
+        self.inner.location()
+    }
+
+    fn erasure_caveat(&self) -> Option<String> {
+        self.inner.erasure_caveat()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::{new_group, new_message};
+    use crate::content::Content;
+    use crate::model::GroupPolicy;
+    use crate::storage::{FileStorage, MemoryStorage};
+    use chrono::Utc;
+    use std::path::Path;
+
+    /// A fresh directory under the system's temporary directory
+    fn temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("mls-chat-test-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn message(group: &ChatGroup, text: &str) -> ChatMessage {
+        let content = Content::Markdown { text: text.to_string() };
+        new_message(uuid::Uuid::new_v4().to_string(), "Alice".to_string(), content, String::new(), Utc::now(), group).unwrap()
+    }
+
+    fn contents(messages: Vec<ChatMessage>) -> Vec<String> {
+        messages.into_iter().map(|message| message.content).collect()
+    }
+
+    /// Mark the cached copy of a log, so a read served from memory can be told apart from one from disk
+    fn mark_cached(cache: &CachedStorage, group_id: &str) {
+        cache.messages.borrow_mut().get_mut(group_id).unwrap().value[0].content = "cached".to_string();
+    }
+
+    /// A cache over a new data directory holding `group`, and a second handle on the directory
+    fn open(dir: &Path, group: &ChatGroup, limit: usize) -> (CachedStorage, FileStorage) {
+        let mut cache = CachedStorage::new(Box::new(FileStorage::open(dir).unwrap()), limit);
+        cache.save_group(group).unwrap();
+        (cache, FileStorage::open(dir).unwrap())
+    }
+
+    #[test]
+    fn serves_unchanged_logs_from_memory() {
+        let dir = temp_dir();
+        let group = new_group("Team", "Alice", "alice-key", GroupPolicy::default());
+        let (mut cache, _) = open(&dir, &group, DEFAULT_CACHED_MESSAGES);
+        cache.append_message(&message(&group, "one")).unwrap();
+        assert_eq!(contents(cache.load_messages(&group.group_id).unwrap()), ["one"]);
+
+        mark_cached(&cache, &group.group_id);
+        assert_eq!(contents(cache.load_messages(&group.group_id).unwrap()), ["cached"]);
+        let mut streamed = Vec::new();
+        cache.stream_messages(&group.group_id, &mut |message| {
+            streamed.push(message.content);
+            Ok(())
+        }).unwrap();
+        assert_eq!(streamed, ["cached"]);
+
+        // Written through the cache, so the cached copy is kept and extended
+        cache.append_message(&message(&group, "two")).unwrap();
+        assert_eq!(contents(cache.load_messages(&group.group_id).unwrap()), ["cached", "two"]);
+        cache.replace_messages(&group.group_id, &[message(&group, "three")]).unwrap();
+        assert_eq!(contents(cache.load_messages(&group.group_id).unwrap()), ["three"]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn rereads_what_other_handles_wrote() {
+        let dir = temp_dir();
+        let group = new_group("Team", "Alice", "alice-key", GroupPolicy::default());
+        let (mut cache, mut other) = open(&dir, &group, DEFAULT_CACHED_MESSAGES);
+        cache.append_message(&message(&group, "one")).unwrap();
+        cache.load_messages(&group.group_id).unwrap();
+        mark_cached(&cache, &group.group_id);
+
+        other.append_message(&message(&group, "two")).unwrap();
+        assert_eq!(contents(cache.load_messages(&group.group_id).unwrap()), ["one", "two"]);
+
+        // Written elsewhere before this write, so the cached copy is dropped rather than extended
+        mark_cached(&cache, &group.group_id);
+        other.append_message(&message(&group, "three")).unwrap();
+        cache.append_message(&message(&group, "four")).unwrap();
+        assert!(!cache.messages.borrow().contains_key(&group.group_id));
+        assert_eq!(contents(cache.load_messages(&group.group_id).unwrap()), ["one", "two", "three", "four"]);
+
+        assert_eq!(cache.load_groups().unwrap()[0].name, "Team");
+        let second = new_group("Other", "Alice", "alice-key", GroupPolicy::default());
+        other.save_group(&second).unwrap();
+        assert_eq!(cache.load_groups().unwrap().len(), 2);
+
+        other.save_current_user(UserName::Bob).unwrap();
+        assert_eq!(cache.load_current_user().unwrap(), Some(UserName::Bob));
+        cache.save_current_user(UserName::Alice).unwrap();
+        assert_eq!(cache.load_current_user().unwrap(), Some(UserName::Alice));
+        assert_eq!(other.load_current_user().unwrap(), Some(UserName::Alice));
+
+        cache.delete_group(&group.group_id).unwrap();
+        assert_eq!(cache.load_groups().unwrap().len(), 1);
+        assert!(cache.load_messages(&group.group_id).unwrap().is_empty());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn forgets_the_least_recently_used_logs() {
+        let dir = temp_dir();
+        let groups: Vec<ChatGroup> = ["One", "Two", "Three"].iter()
+            .map(|name| new_group(name, "Alice", "alice-key", GroupPolicy::default()))
+            .collect();
+        let (mut cache, _) = open(&dir, &groups[0], 4);
+        for group in &groups {
+            cache.save_group(group).unwrap();
+            cache.append_message(&message(group, "a")).unwrap();
+            cache.append_message(&message(group, "b")).unwrap();
+        }
+        let cached = |cache: &CachedStorage, group: &ChatGroup| cache.messages.borrow().contains_key(&group.group_id);
+
+        cache.load_messages(&groups[0].group_id).unwrap();
+        cache.load_messages(&groups[1].group_id).unwrap();
+        assert!(cached(&cache, &groups[0]) && cached(&cache, &groups[1]));
+        // One is used again, so Two is the one to go when Three comes in
+        cache.load_messages(&groups[0].group_id).unwrap();
+        cache.load_messages(&groups[2].group_id).unwrap();
+        assert!(cached(&cache, &groups[0]) && !cached(&cache, &groups[1]) && cached(&cache, &groups[2]));
+
+        // A log longer than the whole limit is not kept at all
+        for _ in 0..3 {
+            cache.append_message(&message(&groups[1], "c")).unwrap();
+        }
+        assert_eq!(cache.load_messages(&groups[1].group_id).unwrap().len(), 5);
+        assert!(!cached(&cache, &groups[1]));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn reads_unstamped_backends_through() {
+        let memory = MemoryStorage::new();
+        let mut other = memory.clone();
+        let mut cache = CachedStorage::new(Box::new(memory), DEFAULT_CACHED_MESSAGES);
+        let group = new_group("Team", "Alice", "alice-key", GroupPolicy::default());
+        cache.save_group(&group).unwrap();
+        cache.append_message(&message(&group, "one")).unwrap();
+        assert_eq!(contents(cache.load_messages(&group.group_id).unwrap()), ["one"]);
+        assert!(cache.messages.borrow().is_empty());
+        assert!(cache.groups.borrow().is_none());
+
+        other.append_message(&message(&group, "two")).unwrap();
+        assert_eq!(contents(cache.load_messages(&group.group_id).unwrap()), ["one", "two"]);
+        other.delete_group(&group.group_id).unwrap();
+        assert!(cache.load_groups().unwrap().is_empty());
+    }
+}
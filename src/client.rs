@@ -0,0 +1,214 @@
+//! TCP client for the delivery service
+
+use anyhow::{anyhow, Context, Result};
+use serde::de::DeserializeOwned;
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::TcpStream,
+    time::Duration,
+};
+
+use crate::delivery::{
+    Call, Envelope, FetchInboxRequest, FetchInboxResponse, FetchKeyPackageRequest,
+    FetchKeyPackageResponse, FetchPresenceRequest, FetchPresenceResponse, FetchRangeRequest,
+    FetchRangeResponse, ForwardRequest, ForwardResponse, GroupSummary, HeartbeatRequest, HeartbeatResponse, KeyPackage,
+    ListGroupsRequest, ListGroupsResponse, ListUsersRequest, ListUsersResponse, Presence,
+    PublishKeyPackageRequest, PublishKeyPackageResponse, PurgeKeyPackagesRequest,
+    PurgeKeyPackagesResponse, QueueDepth, QueueDepthsRequest, QueueDepthsResponse, RegisterRequest,
+    RegisterResponse, Reply, Request, RevokeTokenRequest, RevokeTokenResponse, SendMessageRequest,
+    SendMessageResponse, SubscribeRequest, UserSummary,
+};
+use crate::proxy::Socks5Proxy;
+use crate::transport::Transport;
+
+/// Connection to a delivery service speaking the JSON-lines protocol
+pub struct DeliveryClient {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    token: Option<String>, // Sent with every call, once the user has registered
+    namespace: String,     // Empty for the server's default namespace
+}
+
+impl DeliveryClient {
+    /// Connect to a delivery service at `addr` (host:port)
+    pub fn connect(addr: &str) -> Result<Self> {
+        let stream = TcpStream::connect(addr)
+            .with_context(|| format!("Failed to connect to delivery service at {}", addr))?;
+        Self::from_stream(stream)
+    }
+
+    /// Connect to a delivery service at `addr` through a SOCKS5 proxy
+    pub fn connect_via(addr: &str, proxy: &Socks5Proxy) -> Result<Self> {
+        let stream = proxy.connect(addr)
+            .with_context(|| format!("Failed to connect to delivery service at {} via proxy", addr))?;
+        Self::from_stream(stream)
+    }
+
+    fn from_stream(stream: TcpStream) -> Result<Self> {
+        Ok(Self {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+            token: None,
+            namespace: String::new(),
+        })
+    }
+
+    /// Make every call in `namespace` instead of the server's default one
+    pub fn with_namespace(mut self, namespace: impl Into<String>) -> Self {
+        self.namespace = namespace.into();
+        self
+    }
+
+    /// Authenticate every call with a token from `register`
+    pub fn with_token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    /// Give up on calls the server has not answered within `timeout`
+    pub fn with_timeout(self, timeout: Duration) -> Result<Self> {
+        self.writer.set_read_timeout(Some(timeout))?;
+        self.writer.set_write_timeout(Some(timeout))?;
+        Ok(self)
+    }
+
+    /// Claim `user` on the server, returning the token that now guards it
+    pub fn register(&mut self, user: &str) -> Result<String> {
+        let response: RegisterResponse = self.call(Request::Register(RegisterRequest { user: user.to_string() }))?;
+        Ok(response.token)
+    }
+
+    /// A key package the server holds itself, without asking its peers (federation)
+    pub fn lookup_key_package(&mut self, user: &str) -> Result<KeyPackage> {
+        let request = FetchKeyPackageRequest { user: user.to_string(), local_only: true };
+        let response: FetchKeyPackageResponse = self.call(Request::FetchKeyPackage(request))?;
+        Ok(response.key_package)
+    }
+
+    /// Hand a signed envelope to the server its recipients are homed on (federation)
+    pub fn forward(&mut self, request: ForwardRequest) -> Result<()> {
+        let _: ForwardResponse = self.call(Request::Forward(request))?;
+        Ok(())
+    }
+
+    /// Everyone the namespace knows about (admin)
+    pub fn list_users(&mut self) -> Result<Vec<UserSummary>> {
+        let response: ListUsersResponse = self.call(Request::ListUsers(ListUsersRequest {}))?;
+        Ok(response.users)
+    }
+
+    /// Groups the server has relayed messages for, most recently active first (admin)
+    pub fn list_groups(&mut self) -> Result<Vec<GroupSummary>> {
+        let response: ListGroupsResponse = self.call(Request::ListGroups(ListGroupsRequest {}))?;
+        Ok(response.groups)
+    }
+
+    /// Remove key packages published more than `older_than_seconds` ago, returning whose (admin)
+    pub fn purge_key_packages(&mut self, older_than_seconds: u64) -> Result<Vec<String>> {
+        let request = PurgeKeyPackagesRequest { older_than_seconds };
+        let response: PurgeKeyPackagesResponse = self.call(Request::PurgeKeyPackages(request))?;
+        Ok(response.users)
+    }
+
+    /// Drop `user`'s account so its token stops working (admin)
+    pub fn revoke_token(&mut self, user: &str) -> Result<()> {
+        let _: RevokeTokenResponse = self.call(Request::RevokeToken(RevokeTokenRequest { user: user.to_string() }))?;
+        Ok(())
+    }
+
+    /// Non-empty inboxes and how many envelopes wait in each (admin)
+    pub fn queue_depths(&mut self) -> Result<Vec<QueueDepth>> {
+        let response: QueueDepthsResponse = self.call(Request::QueueDepths(QueueDepthsRequest {}))?;
+        Ok(response.queues)
+    }
+
+    fn send_request(&mut self, request: Request) -> Result<()> {
+        let call = Call {
+            request,
+            token: self.token.clone(),
+            namespace: self.namespace.clone(),
+        };
+        let mut line = serde_json::to_string(&call)?;
+        line.push('\n');
+        self.writer.write_all(line.as_bytes())?;
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    fn read_reply<T: DeserializeOwned>(&mut self) -> Result<T> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err(anyhow!("Delivery service closed the connection"));
+        }
+        match serde_json::from_str(&line).context("Malformed reply from delivery service")? {
+            Reply::Result(value) => Ok(serde_json::from_value(value)?),
+            Reply::Error(err) => Err(err.into()),
+        }
+    }
+
+    fn call<T: DeserializeOwned>(&mut self, request: Request) -> Result<T> {
+        self.send_request(request)?;
+        self.read_reply()
+    }
+
+    /// Stream envelopes for `user` as they arrive, starting with anything queued
+    ///
+    /// The stream ends after the first error, such as the server going away.
+    pub fn subscribe(mut self, user: &str) -> Result<impl Iterator<Item = Result<Envelope>>> {
+        self.send_request(Request::Subscribe(SubscribeRequest { user: user.to_string() }))?;
+        let mut closed = false;
+        Ok(std::iter::from_fn(move || {
+            if closed {
+                return None;
+            }
+            let reply = self.read_reply();
+            closed = reply.is_err();
+            Some(reply)
+        }))
+    }
+}
+
+impl Transport for DeliveryClient {
+    fn publish_key_package(&mut self, key_package: KeyPackage) -> Result<()> {
+        let _: PublishKeyPackageResponse =
+            self.call(Request::PublishKeyPackage(PublishKeyPackageRequest { key_package }))?;
+        Ok(())
+    }
+
+    fn fetch_key_package(&mut self, user: &str) -> Result<KeyPackage> {
+        let response: FetchKeyPackageResponse =
+            self.call(Request::FetchKeyPackage(FetchKeyPackageRequest { user: user.to_string(), local_only: false }))?;
+        Ok(response.key_package)
+    }
+
+    fn send_message(&mut self, request: SendMessageRequest) -> Result<SendMessageResponse> {
+        self.call(Request::SendMessage(request))
+    }
+
+    fn fetch_inbox(&mut self, user: &str) -> Result<Vec<Envelope>> {
+        let response: FetchInboxResponse =
+            self.call(Request::FetchInbox(FetchInboxRequest { user: user.to_string() }))?;
+        Ok(response.envelopes)
+    }
+
+    fn fetch_range(&mut self, user: &str, group_id: &str, from_seq: u64) -> Result<Vec<Envelope>> {
+        let request = FetchRangeRequest {
+            user: user.to_string(),
+            group_id: group_id.to_string(),
+            from_seq,
+        };
+        let response: FetchRangeResponse = self.call(Request::FetchRange(request))?;
+        Ok(response.envelopes)
+    }
+
+    fn heartbeat(&mut self, user: &str) -> Result<()> {
+        let _: HeartbeatResponse = self.call(Request::Heartbeat(HeartbeatRequest { user: user.to_string() }))?;
+        Ok(())
+    }
+
+    fn fetch_presence(&mut self, users: &[String]) -> Result<Vec<Presence>> {
+        let response: FetchPresenceResponse =
+            self.call(Request::FetchPresence(FetchPresenceRequest { users: users.to_vec() }))?;
+        Ok(response.presence)
+    }
+}
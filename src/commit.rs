@@ -0,0 +1,278 @@
+//! Committing proposals as a group's next epoch
+//!
+//! Every change to a group, adding or removing members, a new name or policy,
+//! a key update, ends in `MlsChatApp::commit`. In a group with a ratchet tree
+//! the commit changes the tree's leaves and the committer's direct path (see
+//! `tree`), the Commit carries only that change to the remote members who
+//! stay, and each joiner gets a Welcome with the whole tree. Every member,
+//! the committer included, applies the adds and removes to its member lists
+//! in place.
+//!
+//! Groups from before the ratchet tree keep committing the old way: a fresh
+//! random secret encrypted under the previous epoch's, sent with the whole
+//! member list to every remaining member. Their members never get a tree.
+//!
+//! Many adds at once, as when a class of students is onboarded, go in one
+//! commit with one path update. `batch_savings` times that commit against
+//! the one-add commits it replaces, on scratch copies of the group.
+
+use anyhow::{Context, Result};
+use std::{
+    collections::HashSet,
+    time::{Duration, Instant},
+};
+use uuid::Uuid;
+
+use crate::app::MlsChatApp;
+use crate::model::{ChatGroup, GroupMetadata};
+use crate::protocol::{self, MlsMessage};
+use crate::tree::{self, Applied, LeafAdd, RatchetTree, SealedSecret, TreeCommit};
+
+/// What one commit covers
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Proposals {
+    pub adds: Vec<(String, String)>, // Each joiner, with the public key from their key package
+    pub removes: Vec<String>,
+    pub metadata: Option<GroupMetadata>, // The new group context, if it changes
+}
+
+/// A commit made on a group's ratchet tree, and what to send for it, before the group moves to it
+pub(crate) struct TreeCommitted {
+    pub commit: MlsMessage,
+    pub welcomes: Vec<(String, MlsMessage)>, // Joiner and their Welcome
+    group_secret: String,
+    tree_hash: String,
+    applied: Applied,
+}
+
+impl TreeCommitted {
+    /// Put the tree back as it was before the commit
+    pub(crate) fn undo(self, tree: &mut RatchetTree) {
+        tree.restore(self.applied.undo);
+    }
+}
+
+/// What committing many adds at once saves over committing them one by one
+#[derive(Debug, Clone)]
+pub struct BatchSavings {
+    pub joiners: usize,
+    pub batched: Duration, // One commit adding every joiner
+    pub sequential: Duration, // One commit per joiner, in order
+    pub batched_bytes: usize, // The Commit and every Welcome
+    pub sequential_bytes: usize,
+}
+
+impl BatchSavings {
+    /// How much less time the one commit took
+    pub fn saved(&self) -> Duration {
+        self.sequential.saturating_sub(self.batched)
+    }
+}
+
+/// Apply a commit's removes and adds to a group's member lists, in place
+///
+/// The lists stay plain vectors, so a remove still looks its member up.
+pub(crate) fn change_members(group: &mut ChatGroup, removes: &[String], adds: impl IntoIterator<Item = String>) {
+    for removed in removes {
+        for members in [&mut group.members, &mut group.mls_group.members] {
+            if let Some(position) = members.iter().position(|member| member == removed) {
+                members.remove(position);
+            }
+        }
+    }
+    for member in adds {
+        group.mls_group.members.push(member.clone());
+        group.members.push(member);
+    }
+}
+
+/// Commit `proposals` by `committer` on `tree`, which was taken out of `group`
+///
+/// Welcomes are built for the joiners `welcome` picks; each carries a copy
+/// of the tree, so only joiners who will be sent one should be picked.
+pub(crate) fn commit_tree(
+    group: &ChatGroup,
+    tree: &mut RatchetTree,
+    committer: &str,
+    proposals: &Proposals,
+    welcome: impl Fn(&str) -> bool,
+) -> Result<TreeCommitted> {
+    let mut change = TreeCommit {
+        removes: proposals.removes.clone(),
+        adds: proposals.adds.iter()
+            .map(|(member, public_key)| LeafAdd { member: member.clone(), public_key: public_key.clone() })
+            .collect(),
+        path: None,
+    };
+    let applied = tree.commit(Some(committer), &mut change)?;
+    let group_secret = tree::epoch_secret(&group.mls_group.group_secret, &applied.commit_secret);
+    let tree_hash = tree.root_hash();
+    let epoch = group.mls_group.epoch + 1;
+    let metadata = proposals.metadata.clone().unwrap_or_else(|| group.mls_group.metadata.clone());
+
+    let joiners: Vec<&(String, String)> = proposals.adds.iter().filter(|(joiner, _)| welcome(joiner)).collect();
+    let mut welcomes = Vec::new();
+    if !joiners.is_empty() {
+        let removed: HashSet<&String> = proposals.removes.iter().collect();
+        let members: Vec<String> = group.members.iter()
+            .filter(|member| !removed.contains(member))
+            .cloned()
+            .chain(proposals.adds.iter().map(|(joiner, _)| joiner.clone()))
+            .collect();
+        for (joiner, public_key) in joiners {
+            let key = protocol::welcome_key(public_key);
+            let path_secret = applied.welcomes.iter()
+                .find(|(member, _)| member == joiner)
+                .map(|(_, sealed)| SealedSecret { node: sealed.node, ciphertext: protocol::encrypt(&key, &sealed.ciphertext) });
+            let welcome = MlsMessage::Welcome {
+                group_name: match metadata.name.is_empty() {
+                    true => group.name.clone(),
+                    false => metadata.name.clone(),
+                },
+                group_id: group.group_id.clone(),
+                epoch,
+                tree_hash: tree_hash.clone(),
+                members: members.clone(),
+                encrypted_group_secret: protocol::encrypt(&key, &group_secret),
+                metadata: metadata.clone(),
+                tree: Some(tree.clone()),
+                path_secret,
+            };
+            welcomes.push((joiner.clone(), welcome));
+        }
+    }
+    let commit = MlsMessage::Commit {
+        group_id: group.group_id.clone(),
+        epoch,
+        tree_hash: tree_hash.clone(),
+        members: Vec::new(),
+        encrypted_group_secret: String::new(),
+        metadata,
+        tree: Some(change),
+    };
+    Ok(TreeCommitted { commit, welcomes, group_secret, tree_hash, applied })
+}
+
+/// Move `group` to the epoch `committed` made, putting its `tree` back
+pub(crate) fn finish_tree_commit(group: &mut ChatGroup, tree: RatchetTree, committed: TreeCommitted, proposals: Proposals) {
+    let mls_group = &mut group.mls_group;
+    mls_group.epoch += 1;
+    mls_group.group_secret = committed.group_secret;
+    mls_group.tree_hash = committed.tree_hash;
+    committed.applied.update(&mut mls_group.path_secrets);
+    mls_group.tree = Some(tree);
+    if let Some(metadata) = proposals.metadata {
+        mls_group.metadata = metadata;
+    }
+    change_members(group, &proposals.removes, proposals.adds.into_iter().map(|(joiner, _)| joiner));
+}
+
+/// Time committing `adds` to `group` as `committer` in one commit and one by one, on scratch copies
+///
+/// Each run builds and serializes the Commit and a Welcome for every joiner,
+/// as a networked add does, without sending anything. `None` for a group
+/// without a ratchet tree, or fewer than two adds.
+pub(crate) fn batch_savings(group: &ChatGroup, committer: &str, adds: &[(String, String)]) -> Result<Option<BatchSavings>> {
+    if group.mls_group.tree.is_none() || adds.len() < 2 {
+        return Ok(None);
+    }
+    let run = |batches: Vec<Vec<(String, String)>>| -> Result<(Duration, usize)> {
+        let mut scratch = group.clone();
+        let start = Instant::now();
+        let mut bytes = 0;
+        for adds in batches {
+            let mut tree = scratch.mls_group.tree.take().context("Group has no ratchet tree")?;
+            let proposals = Proposals { adds, ..Proposals::default() };
+            let committed = commit_tree(&scratch, &mut tree, committer, &proposals, |_| true)?;
+            bytes += committed.commit.to_bytes()?.len();
+            for (_, welcome) in &committed.welcomes {
+                bytes += welcome.to_bytes()?.len();
+            }
+            finish_tree_commit(&mut scratch, tree, committed, proposals);
+        }
+        Ok((start.elapsed(), bytes))
+    };
+    let (batched, batched_bytes) = run(vec![adds.to_vec()])?;
+    let (sequential, sequential_bytes) = run(adds.iter().map(|add| vec![add.clone()]).collect())?;
+    Ok(Some(BatchSavings { joiners: adds.len(), batched, sequential, batched_bytes, sequential_bytes }))
+}
+
+impl MlsChatApp {
+    /// Commit `proposals` to a group as its next epoch, sending the Commit and any Welcomes if networked
+    ///
+    /// The group is marked dirty but not saved. If sending fails, the group
+    /// is left at its current epoch.
+    pub(crate) fn commit(&mut self, group_name: &str, proposals: Proposals) -> Result<()> {
+        let user = self.current_user.context("No user initialized")?.to_string();
+        let group = self.groups.get_mut(group_name).context("Group not found")?;
+        let Some(mut tree) = group.mls_group.tree.take() else {
+            return self.commit_legacy(group_name, proposals);
+        };
+
+        let networked = self.transport.is_some();
+        let group = self.groups.get(group_name).context("Group not found")?;
+        let committed = commit_tree(group, &mut tree, &user, &proposals, |joiner| networked && !self.user_keys.contains_key(joiner));
+        let sent = match committed {
+            Ok(committed) if networked => match self.send_tree_commit(group_name, &proposals, &committed) {
+                Ok(()) => Ok(committed),
+                Err(err) => {
+                    committed.undo(&mut tree);
+                    Err(err)
+                }
+            },
+            committed => committed,
+        };
+        let group = self.groups.get_mut(group_name).context("Group not found")?;
+        match sent {
+            Ok(committed) => finish_tree_commit(group, tree, committed, proposals),
+            Err(err) => {
+                group.mls_group.tree = Some(tree);
+                return Err(err);
+            }
+        }
+        self.dirty_groups.insert(group_name.to_string());
+        Ok(())
+    }
+
+    /// Send a tree commit to the remote members who stay, and its Welcomes to the joiners
+    fn send_tree_commit(&mut self, group_name: &str, proposals: &Proposals, committed: &TreeCommitted) -> Result<()> {
+        let group = self.groups.get(group_name).context("Group not found")?;
+        let removed: HashSet<&String> = proposals.removes.iter().collect();
+        let mut existing = self.remote_members(group);
+        if !removed.is_empty() {
+            existing.retain(|member| !removed.contains(member)); // A removed member gets no Commit, so never the new secret
+        }
+        let mut outgoing = Vec::new();
+        if !existing.is_empty() {
+            outgoing.push((existing, committed.commit.to_bytes()?));
+        }
+        for (joiner, welcome) in &committed.welcomes {
+            outgoing.push((vec![joiner.clone()], welcome.to_bytes()?));
+        }
+        let group_id = group.group_id.clone();
+        self.send_handshake(&group_id, outgoing)
+    }
+
+    /// Commit the way groups without a ratchet tree do, with a fresh secret and the whole member list
+    fn commit_legacy(&mut self, group_name: &str, proposals: Proposals) -> Result<()> {
+        let group = self.groups.get(group_name).context("Group not found")?;
+        let mut next = group.mls_group.clone();
+        next.epoch += 1;
+        next.group_secret = format!("group_secret_{}", Uuid::new_v4());
+        next.tree_hash = format!("tree_hash_{}", Uuid::new_v4());
+        next.members.retain(|member| !proposals.removes.contains(member));
+        next.members.extend(proposals.adds.iter().map(|(joiner, _)| joiner.clone()));
+        if let Some(metadata) = proposals.metadata {
+            next.metadata = metadata;
+        }
+        if self.transport.is_some() {
+            self.distribute_commit(group_name, &next, &proposals.adds)?;
+        }
+
+        let group = self.groups.get_mut(group_name).context("Group not found")?;
+        group.members = next.members.clone();
+        group.mls_group = next;
+        self.dirty_groups.insert(group_name.to_string());
+        Ok(())
+    }
+}
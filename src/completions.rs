@@ -0,0 +1,406 @@
+//! Shell completion scripts, generated from the CLI definition
+//!
+//! `mls-chat completions <shell>` prints a script completing subcommands,
+//! options, and their fixed values. It walks the same `clap::Command` the CLI
+//! parses with, so new commands are picked up without touching this module.
+//!
+//! Group and member arguments are completed from the profile instead: the
+//! scripts run the hidden `mls-chat __complete <groups|users>` command, which
+//! prints the names it finds in the data directory, one per line.
+
+use clap::{builder::PossibleValue, Arg, Command, ValueEnum};
+use std::fmt::Write;
+
+use crate::app::MlsChatApp;
+use crate::model::UserName;
+
+/// Shells `completions` can write a script for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+/// Names `__complete` can list from the profile
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Candidates {
+    Groups,
+    Users,
+}
+
+/// The names of `kind` in `app`'s profile; users already in `group` are left out
+pub fn candidates(app: &MlsChatApp, kind: Candidates, group: Option<&str>) -> Vec<String> {
+    match kind {
+        Candidates::Groups => {
+            let mut names: Vec<String> = app.groups.keys().cloned().collect();
+            names.sort();
+            names
+        }
+        Candidates::Users => {
+            let members = group.and_then(|group| app.groups.get(group)).map(|group| group.members.as_slice()).unwrap_or_default();
+            UserName::value_variants().iter()
+                .filter(|user| !members.contains(&user.to_string()))
+                .filter_map(|user| user.to_possible_value().map(|value| value.get_name().to_string()))
+                .collect()
+        }
+    }
+}
+
+impl Candidates {
+    /// The kind as `__complete` takes it
+    fn name(self) -> &'static str {
+        match self {
+            Candidates::Groups => "groups",
+            Candidates::Users => "users",
+        }
+    }
+}
+
+/// The `__complete` kind for an argument, picked by its name
+fn dynamic_kind(arg: &Arg) -> Option<Candidates> {
+    match arg.get_id().as_str() {
+        "group" => Some(Candidates::Groups),
+        "member" => Some(Candidates::Users),
+        _ => None,
+    }
+}
+
+/// The completion script for `shell`
+pub fn generate(shell: Shell, cmd: &Command) -> String {
+    let mut cmd = cmd.clone();
+    cmd.build(); // Copies global options down to every subcommand
+    let mut commands = Vec::new();
+    collect(&cmd, vec![cmd.get_name().to_string()], &mut commands);
+    match shell {
+        Shell::Bash => bash(cmd.get_name(), &commands),
+        Shell::Zsh => zsh(cmd.get_name(), &commands),
+        Shell::Fish => fish(cmd.get_name(), &commands),
+    }
+}
+
+/// A command and the names leading to it, root first
+struct Node<'a> {
+    path: Vec<String>,
+    cmd: &'a Command,
+}
+
+impl Node<'_> {
+    fn key(&self) -> String {
+        self.path.join(":")
+    }
+
+    fn subcommands(&self) -> impl Iterator<Item = &Command> {
+        self.cmd.get_subcommands().filter(|sub| !sub.is_hide_set())
+    }
+
+    fn options(&self) -> impl Iterator<Item = &Arg> {
+        self.cmd.get_arguments().filter(|arg| !arg.is_hide_set() && !arg.is_positional())
+    }
+
+    fn positionals(&self) -> impl Iterator<Item = &Arg> {
+        self.cmd.get_positionals().filter(|arg| !arg.is_hide_set())
+    }
+
+    /// Fixed values of positional arguments not completed from the profile
+    fn positional_values(&self) -> Vec<PossibleValue> {
+        self.positionals()
+            .filter(|arg| dynamic_kind(arg).is_none())
+            .flat_map(|arg| arg.get_possible_values())
+            .filter(|value| !value.is_hide_set())
+            .collect()
+    }
+
+    /// Positional arguments completed from the profile: their index, `__complete` kind, and the group's index
+    ///
+    /// Member arguments pass the group typed before them, as a positional index, so its members are left out.
+    fn dynamic_positionals(&self) -> Vec<(usize, &'static str, Option<usize>)> {
+        let group = self.positionals().position(|arg| dynamic_kind(arg) == Some(Candidates::Groups));
+        self.positionals()
+            .enumerate()
+            .filter_map(|(index, arg)| match dynamic_kind(arg)? {
+                Candidates::Groups => Some((index, Candidates::Groups.name(), None)),
+                Candidates::Users => Some((index, Candidates::Users.name(), group.filter(|group| *group < index))),
+            })
+            .collect()
+    }
+
+    /// Every spelling of the options that take a value, so their values are not counted as positionals
+    fn value_flags(&self) -> Vec<String> {
+        self.options().filter(|arg| takes_value(arg)).flat_map(flags).collect()
+    }
+}
+
+/// Every command under `cmd`, parents before children; `help <command>` is not descended into
+fn collect<'a>(cmd: &'a Command, path: Vec<String>, out: &mut Vec<Node<'a>>) {
+    out.push(Node { path: path.clone(), cmd });
+    for sub in cmd.get_subcommands().filter(|sub| !sub.is_hide_set() && sub.get_name() != "help") {
+        let mut sub_path = path.clone();
+        sub_path.push(sub.get_name().to_string());
+        collect(sub, sub_path, out);
+    }
+}
+
+fn takes_value(arg: &Arg) -> bool {
+    arg.get_action().takes_values()
+}
+
+/// Every spelling of an option: `--long`, then `-s`
+fn flags(arg: &Arg) -> Vec<String> {
+    let mut flags: Vec<String> = arg.get_long().map(|long| format!("--{}", long)).into_iter().collect();
+    flags.extend(arg.get_short().map(|short| format!("-{}", short)));
+    flags
+}
+
+fn about(cmd: &Command) -> String {
+    cmd.get_about().map(|about| about.to_string()).unwrap_or_default()
+}
+
+fn help(arg: &Arg) -> String {
+    arg.get_help().map(|help| help.to_string()).unwrap_or_default()
+}
+
+/// Bash that sets `COMPREPLY` from `__complete <kind><args>`, one name per line so names with spaces stay whole
+fn bash_dynamic(kind: &str, args: &str) -> String {
+    format!(
+        "local IFS=$'\\n'; COMPREPLY=($(compgen -W \"$(\"${{COMP_WORDS[0]}}\" \"${{context[@]}}\" __complete {}{} 2>/dev/null)\" -- \"$cur\"))",
+        kind, args
+    )
+}
+
+/// Zsh that offers the names from `__complete <kind><args>`
+fn zsh_dynamic(kind: &str, args: &str) -> String {
+    format!("candidates=(${{(f)\"$(\"${{words[1]}}\" \"${{context[@]}}\" __complete {}{} 2>/dev/null)\"}}); compadd -a candidates", kind, args)
+}
+
+fn bash(name: &str, commands: &[Node]) -> String {
+    let function = format!("_{}", name.replace('-', "_"));
+    let mut out = String::new();
+    let _ = writeln!(out, "{}() {{", function);
+    let _ = writeln!(out, "    local cur=\"${{COMP_WORDS[COMP_CWORD]}}\" prev=\"${{COMP_WORDS[COMP_CWORD-1]}}\"");
+    let _ = writeln!(out, "    local cmd_path=\"{}\" candidates i word", name);
+    let _ = writeln!(out, "    local -a positionals=() context=()");
+    let _ = writeln!(out, "    for ((i = 1; i < COMP_CWORD; i++)); do");
+    let _ = writeln!(out, "        word=\"${{COMP_WORDS[i]}}\"");
+    let _ = writeln!(out, "        case \"${{cmd_path}}:${{word}}\" in");
+    for node in commands.iter().skip(1) {
+        let _ = writeln!(out, "            {}) cmd_path=\"{}\"; positionals=(); continue ;;", node.key(), node.key());
+    }
+    // The data directory is passed on to `__complete`; other option values are skipped
+    let _ = writeln!(out, "            *:--data-dir) context=(--data-dir \"${{COMP_WORDS[i+1]}}\"); ((i++)); continue ;;");
+    for node in commands {
+        let patterns: Vec<String> = node.value_flags().iter().map(|flag| format!("{}:{}", node.key(), flag)).collect();
+        if !patterns.is_empty() {
+            let _ = writeln!(out, "            {}) ((i++)); continue ;;", patterns.join("|"));
+        }
+    }
+    let _ = writeln!(out, "        esac");
+    let _ = writeln!(out, "        [[ \"$word\" == -* ]] || positionals+=(\"$word\")");
+    let _ = writeln!(out, "    done\n");
+
+    // Values for the option just typed; files when the option has no fixed values
+    let _ = writeln!(out, "    case \"${{cmd_path}}:${{prev}}\" in");
+    for node in commands {
+        for arg in node.options().filter(|arg| takes_value(arg)) {
+            let values: Vec<String> = arg.get_possible_values().iter().map(|value| value.get_name().to_string()).collect();
+            let action = match (dynamic_kind(arg), values.is_empty()) {
+                (Some(kind), _) => format!("{}; return 0", bash_dynamic(kind.name(), "")),
+                (None, true) => "return 0".to_string(),
+                (None, false) => format!("COMPREPLY=($(compgen -W \"{}\" -- \"$cur\")); return 0", values.join(" ")),
+            };
+            let patterns: Vec<String> = flags(arg).iter().map(|flag| format!("{}:{}", node.key(), flag)).collect();
+            let _ = writeln!(out, "        {}) {} ;;", patterns.join("|"), action);
+        }
+    }
+    let _ = writeln!(out, "    esac\n");
+
+    // Group and member names from the profile
+    let _ = writeln!(out, "    if [[ \"$cur\" != -* ]]; then");
+    let _ = writeln!(out, "        case \"${{cmd_path}}:${{#positionals[@]}}\" in");
+    for node in commands {
+        for (index, kind, group) in node.dynamic_positionals() {
+            let group = group.map(|group| format!(" \"${{positionals[{}]}}\"", group)).unwrap_or_default();
+            let _ = writeln!(out, "            {}:{}) {}; return 0 ;;", node.key(), index, bash_dynamic(kind, &group));
+        }
+    }
+    let _ = writeln!(out, "        esac");
+    let _ = writeln!(out, "    fi\n");
+
+    let _ = writeln!(out, "    case \"$cmd_path\" in");
+    for node in commands {
+        let mut words: Vec<String> = node.subcommands().map(|sub| sub.get_name().to_string()).collect();
+        words.extend(node.positional_values().iter().map(|value| value.get_name().to_string()));
+        words.extend(node.options().flat_map(flags));
+        let _ = writeln!(out, "        {}) candidates=\"{}\" ;;", node.key(), words.join(" "));
+    }
+    let _ = writeln!(out, "    esac");
+    let _ = writeln!(out, "    COMPREPLY=($(compgen -W \"$candidates\" -- \"$cur\"))");
+    let _ = writeln!(out, "}}\n");
+    let _ = writeln!(out, "complete -o default -F {} {}", function, name);
+    out
+}
+
+fn zsh(name: &str, commands: &[Node]) -> String {
+    let function = format!("_{}", name.replace('-', "_"));
+    // `_describe` entries are `name:description`, so colons in either are escaped
+    let entry = |word: &str, description: &str| {
+        let word = word.replace(':', "\\:");
+        match description.is_empty() {
+            true => format!("'{}'", word),
+            false => format!("'{}:{}'", word, description.replace(':', "\\:").replace('\'', "'\\''")),
+        }
+    };
+    let mut out = String::new();
+    let _ = writeln!(out, "#compdef {}\n", name);
+    let _ = writeln!(out, "{}() {{", function);
+    let _ = writeln!(out, "    local cmd_path=\"{}\" i", name);
+    let _ = writeln!(out, "    local -a candidates positionals context");
+    let _ = writeln!(out, "    for ((i = 2; i < CURRENT; i++)); do");
+    let _ = writeln!(out, "        case \"${{cmd_path}}:${{words[i]}}\" in");
+    for node in commands.iter().skip(1) {
+        let _ = writeln!(out, "            {}) cmd_path=\"{}\"; positionals=(); continue ;;", node.key(), node.key());
+    }
+    let _ = writeln!(out, "            *:--data-dir) context=(--data-dir \"${{words[i+1]}}\"); ((i++)); continue ;;");
+    for node in commands {
+        let patterns: Vec<String> = node.value_flags().iter().map(|flag| format!("{}:{}", node.key(), flag)).collect();
+        if !patterns.is_empty() {
+            let _ = writeln!(out, "            {}) ((i++)); continue ;;", patterns.join("|"));
+        }
+    }
+    let _ = writeln!(out, "        esac");
+    let _ = writeln!(out, "        [[ \"${{words[i]}}\" == -* ]] || positionals+=(\"${{words[i]}}\")");
+    let _ = writeln!(out, "    done\n");
+
+    let _ = writeln!(out, "    case \"${{cmd_path}}:${{words[CURRENT-1]}}\" in");
+    for node in commands {
+        for arg in node.options().filter(|arg| takes_value(arg)) {
+            let values = arg.get_possible_values();
+            let action = match (dynamic_kind(arg), values.is_empty()) {
+                (Some(kind), _) => format!("{}; return", zsh_dynamic(kind.name(), "")),
+                (None, true) => "_files; return".to_string(),
+                (None, false) => {
+                    let entries: Vec<String> = values.iter()
+                        .map(|value| entry(value.get_name(), &value.get_help().map(|help| help.to_string()).unwrap_or_default()))
+                        .collect();
+                    format!("candidates=({}); _describe 'value' candidates; return", entries.join(" "))
+                }
+            };
+            let patterns: Vec<String> = flags(arg).iter().map(|flag| format!("{}:{}", node.key(), flag)).collect();
+            let _ = writeln!(out, "        {}) {} ;;", patterns.join("|"), action);
+        }
+    }
+    let _ = writeln!(out, "    esac\n");
+
+    let _ = writeln!(out, "    if [[ \"${{words[CURRENT]}}\" != -* ]]; then");
+    let _ = writeln!(out, "        case \"${{cmd_path}}:${{#positionals}}\" in");
+    for node in commands {
+        for (index, kind, group) in node.dynamic_positionals() {
+            // zsh arrays start at 1
+            let group = group.map(|group| format!(" \"${{positionals[{}]}}\"", group + 1)).unwrap_or_default();
+            let _ = writeln!(out, "            {}:{}) {}; return ;;", node.key(), index, zsh_dynamic(kind, &group));
+        }
+    }
+    let _ = writeln!(out, "        esac");
+    let _ = writeln!(out, "    fi\n");
+
+    let _ = writeln!(out, "    case \"$cmd_path\" in");
+    for node in commands {
+        let mut entries: Vec<String> = node.subcommands().map(|sub| entry(sub.get_name(), &about(sub))).collect();
+        entries.extend(node.positional_values().iter().map(|value| entry(value.get_name(), "")));
+        for arg in node.options() {
+            entries.extend(flags(arg).iter().map(|flag| entry(flag, &help(arg))));
+        }
+        let _ = writeln!(out, "        {}) candidates=({}) ;;", node.key(), entries.join(" "));
+    }
+    let _ = writeln!(out, "    esac");
+    let _ = writeln!(out, "    _describe '{}' candidates", name);
+    let _ = writeln!(out, "}}\n");
+    let _ = writeln!(out, "if [ \"$funcstack[1]\" = \"{}\" ]; then", function);
+    let _ = writeln!(out, "    {} \"$@\"", function);
+    let _ = writeln!(out, "else");
+    let _ = writeln!(out, "    compdef {} {}", function, name);
+    let _ = writeln!(out, "fi");
+    out
+}
+
+fn fish(name: &str, commands: &[Node]) -> String {
+    let quote = |text: &str| format!("'{}'", text.replace('\\', "\\\\").replace('\'', "\\'"));
+    let prefix = format!("__{}", name.replace('-', "_"));
+    let mut out = String::new();
+    // Group and member names come from `__complete`, run against the data directory on the command line
+    let _ = writeln!(out, "function {}_complete", prefix);
+    let _ = writeln!(out, "    set -l tokens (commandline -opc)");
+    let _ = writeln!(out, "    set -l context");
+    let _ = writeln!(out, "    if set -l index (contains -i -- --data-dir $tokens)");
+    let _ = writeln!(out, "        set context --data-dir $tokens[(math $index + 1)]");
+    let _ = writeln!(out, "    end");
+    let _ = writeln!(out, "    $tokens[1] $context __complete $argv 2>/dev/null");
+    let _ = writeln!(out, "end\n");
+    // Words typed so far that are not options, skipping the values of the options given as arguments
+    let _ = writeln!(out, "function {}_args", prefix);
+    let _ = writeln!(out, "    set -l skip 0");
+    let _ = writeln!(out, "    for token in (commandline -opc)[2..-1]");
+    let _ = writeln!(out, "        if test $skip = 1");
+    let _ = writeln!(out, "            set skip 0");
+    let _ = writeln!(out, "        else if contains -- $token $argv");
+    let _ = writeln!(out, "            set skip 1");
+    let _ = writeln!(out, "        else if not string match -q -- '-*' $token");
+    let _ = writeln!(out, "            echo $token");
+    let _ = writeln!(out, "        end");
+    let _ = writeln!(out, "    end");
+    let _ = writeln!(out, "end\n");
+    for node in commands {
+        // Conditions on the subcommands typed so far; fish only checks that each was seen
+        let seen: Vec<String> = node.path.iter().skip(1).map(|name| format!("__fish_seen_subcommand_from {}", name)).collect();
+        let here = match seen.is_empty() {
+            true => "__fish_use_subcommand".to_string(),
+            false => seen.join("; and "),
+        };
+        let children: Vec<&str> = node.subcommands().map(Command::get_name).collect();
+        let before_children = match (seen.is_empty(), children.is_empty()) {
+            (true, _) | (_, true) => here.clone(),
+            (false, false) => format!("{}; and not __fish_seen_subcommand_from {}", here, children.join(" ")),
+        };
+
+        for sub in node.subcommands() {
+            let _ = writeln!(out, "complete -c {} -n {} -f -a {} -d {}", name, quote(&before_children), sub.get_name(), quote(&about(sub)));
+        }
+        let values = node.positional_values();
+        if !values.is_empty() {
+            let names: Vec<&str> = values.iter().map(PossibleValue::get_name).collect();
+            let _ = writeln!(out, "complete -c {} -n {} -f -a {}", name, quote(&here), quote(&names.join(" ")));
+        }
+        // Positionals are counted after the subcommand names leading to this command
+        let args = format!("({}_args {})", prefix, node.value_flags().join(" "));
+        let depth = node.path.len() - 1;
+        for (index, kind, group) in node.dynamic_positionals() {
+            let condition = format!("{}; and test (count {}) -eq {}", here, args, depth + index);
+            let group = group.map(|group| format!(" {}[{}]", args, depth + group + 1)).unwrap_or_default();
+            let _ = writeln!(out, "complete -c {} -n {} -f -a {}", name, quote(&condition), quote(&format!("({}_complete {}{})", prefix, kind, group)));
+        }
+        for arg in node.options() {
+            let mut line = format!("complete -c {} -n {}", name, quote(&here));
+            if let Some(long) = arg.get_long() {
+                let _ = write!(line, " -l {}", long);
+            }
+            if let Some(short) = arg.get_short() {
+                let _ = write!(line, " -s {}", short);
+            }
+            if takes_value(arg) {
+                let values: Vec<String> = arg.get_possible_values().iter().map(|value| value.get_name().to_string()).collect();
+                match (dynamic_kind(arg), values.is_empty()) {
+                    (Some(kind), _) => {
+                        let _ = write!(line, " -x -a {}", quote(&format!("({}_complete {})", prefix, kind.name())));
+                    }
+                    (None, true) => line.push_str(" -r"),
+                    (None, false) => {
+                        let _ = write!(line, " -x -a {}", quote(&values.join(" ")));
+                    }
+                }
+            }
+            let _ = write!(line, " -d {}", quote(&help(arg)));
+            let _ = writeln!(out, "{}", line);
+        }
+    }
+    out
+}
@@ -0,0 +1,636 @@
+//! User configuration in `~/.config/mls-chat/config.toml`
+//!
+//! The file holds defaults for command-line options and profile behaviour:
+//!
+//! ```toml
+//! data-dir = "/home/alice/.mls-chat"
+//! server = "chat.example.com:7878"
+//! identity = "alice"
+//! output = "json"
+//! emoji-shortcodes = false
+//!
+//! default-group = "Team"
+//!
+//! [retention]
+//! max-age-days = 90
+//!
+//! [key-rotation]
+//! after-days = 7
+//!
+//! [transparency]
+//! log = "/srv/mls-chat/keys.log"
+//!
+//! [session]
+//! idle-timeout-minutes = 30
+//! wipe-after-failures = 10
+//!
+//! [history]
+//! share-epochs = 3
+//!
+//! [cache]
+//! messages = 50000
+//!
+//! [notify]
+//! command = "auto"
+//!
+//! [matrix]
+//! access-token = "syt_..."
+//!
+//! [alias]
+//! t = "Team"
+//!
+//! [snippet]
+//! hello = "Hi {group}, {user} here"
+//!
+//! [theme]
+//! name = "high-contrast"
+//! ```
+//!
+//! Only the part of TOML these settings need is understood: `key = value`
+//! lines with string, integer, or boolean values, `[section]` headers, and
+//! `#` comments. `config set` and `config unset` edit the file in place, keeping comments and
+//! layout. Command-line options always win over the file.
+
+use anyhow::{anyhow, Context, Result};
+use chrono::TimeDelta;
+use clap::ValueEnum;
+use std::{
+    collections::HashMap,
+    env, fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use crate::cache;
+use crate::history;
+use crate::i18n;
+use crate::model::{Retention, RotationPolicy, UserName};
+use crate::notify::Notifier;
+use crate::session;
+use crate::snippets;
+use crate::theme::{self, Role, Style, Theme};
+use crate::timestamps::{self, Timestamps, Zone};
+use crate::transparency::TransparencyLog;
+
+/// Overrides the config file location, mainly for tests and scripts
+pub const CONFIG_ENV: &str = "MLS_CHAT_CONFIG";
+
+/// A setting's value as written in the file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigValue {
+    String(String),
+    Integer(i64),
+    Bool(bool),
+}
+
+/// A known setting, with how to check values given for it
+pub struct Setting {
+    pub key: &'static str, // Dotted for keys inside a section, e.g. retention.max-age-days
+    pub help: &'static str,
+    parse: fn(&str) -> Result<ConfigValue>,
+}
+
+/// Every setting the config file may hold
+pub const SETTINGS: &[Setting] = &[
+    Setting { key: "data-dir", help: "Profile directory used when --data-dir is not given", parse: any_string },
+    Setting { key: "server", help: "Delivery service used when --server is not given", parse: any_string },
+    Setting { key: "identity", help: "User `init` creates when none is named (alice or bob)", parse: identity },
+    Setting { key: "output", help: "How `list`, `info`, `groups`, and `whoami` print: text or json", parse: output },
+    Setting { key: "color", help: "Colored output: auto (off when NO_COLOR is set), always, or never", parse: color },
+    Setting { key: "retention.max-age-days", help: "Delete messages older than this many days", parse: positive },
+    Setting { key: "retention.max-messages", help: "Keep at most this many messages per group", parse: positive },
+    Setting { key: "key-rotation.after-days", help: "Rotate your key in a group before sending once this many days have passed", parse: positive },
+    Setting { key: "key-rotation.after-messages", help: "Rotate your key in a group before sending once you have sent this many messages", parse: positive },
+    Setting { key: "transparency.log", help: "Key transparency log file to publish keys to and check fetched ones against", parse: any_string },
+    Setting { key: "session.idle-timeout-minutes", help: "Ask for the passphrase again once an unlocked profile has gone unused this long", parse: positive },
+    Setting { key: "session.wipe-after-failures", help: "Wipe the profile's keys and groups after this many wrong passphrases in a row", parse: positive },
+    Setting { key: "history.share-epochs", help: "How many epochs back `share-history` reaches by default (default 5)", parse: positive },
+    Setting { key: "notify.command", help: "Raise desktop notifications in `list --follow`: auto (notify-send or osascript), off (default), or a program given title and body", parse: any_string },
+    Setting { key: "cache.messages", help: "Messages `list --follow`, `bot`, and `api` keep in memory between reads (default 10000)", parse: positive },
+    Setting { key: "emoji-shortcodes", help: "Turn :wave:-style shortcodes into emoji in sent messages: true (default) or false", parse: boolean },
+    Setting { key: "image-preview", help: "How `list` draws image attachments: auto, kitty, sixel, blocks, or off", parse: image_preview },
+    Setting { key: "language", help: "Language of command output: auto (from LANG, the default), en, or es", parse: language },
+    Setting { key: "timezone", help: "Zone message times are shown in: local (default), utc, or an offset such as +02:00", parse: timezone },
+    Setting { key: "time-format", help: "strftime format for today's message times (default %H:%M:%S)", parse: time_format },
+    Setting { key: "date-format", help: "strftime format for older message times (default %Y-%m-%d %H:%M)", parse: time_format },
+    Setting { key: "relative-times", help: "Show the last week's message times as \"2h ago\": true or false (default)", parse: boolean },
+    Setting { key: "theme.name", help: "Built-in color theme: default, high-contrast, light, or mono", parse: theme_name },
+    Setting { key: "theme.own", help: "Style of your name on your messages, e.g. \"bold green\" (see the [theme] docs)", parse: style },
+    Setting { key: "theme.sender", help: "Style of other senders' names", parse: style },
+    Setting { key: "theme.system", help: "Style of system messages", parse: style },
+    Setting { key: "theme.error", help: "Style of the \"Error:\" failed commands print", parse: style },
+    Setting { key: "theme.epoch", help: "Style of epoch numbers on messages", parse: style },
+    Setting { key: "theme.mention", help: "Style of mentions of you, e.g. \"black on yellow\"", parse: style },
+    Setting { key: "matrix.access-token", help: "Access token `bridge matrix` posts with when --access-token and MLS_CHAT_MATRIX_TOKEN are not set", parse: any_string },
+    Setting { key: "default-group", help: "Group `send`, `list`, `info`, and `presence` use when none is named", parse: any_string },
+    Setting { key: "alias.<name>", help: "Group a short name stands for (see `alias`)", parse: any_string },
+    Setting { key: "snippet.<name>", help: "Text `send --snippet <name>` sends (see `snippet`)", parse: snippet },
+];
+
+/// Prefix of alias keys, which unlike other settings may have any name
+pub const ALIAS_PREFIX: &str = "alias.";
+
+/// Prefix of snippet keys, which like aliases may have any name
+pub const SNIPPET_PREFIX: &str = "snippet.";
+
+/// The loaded config file
+#[derive(Debug, Clone)]
+pub struct Config {
+    path: PathBuf,
+    lines: Vec<String>, // The file as read, so edits keep comments and layout
+    values: Vec<(String, ConfigValue)>, // Dotted key -> value, in file order
+}
+
+impl Config {
+    /// Where the config file lives: `$MLS_CHAT_CONFIG`, else under `$XDG_CONFIG_HOME` or `~/.config`
+    pub fn default_path() -> Option<PathBuf> {
+        if let Some(path) = env::var_os(CONFIG_ENV) {
+            return Some(PathBuf::from(path));
+        }
+        let base = match env::var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()) {
+            Some(dir) => PathBuf::from(dir),
+            None => PathBuf::from(env::var_os("HOME")?).join(".config"),
+        };
+        Some(base.join("mls-chat").join("config.toml"))
+    }
+
+    /// Load the config file at the default path; a missing file is an empty config
+    pub fn load() -> Result<Self> {
+        match Self::default_path() {
+            Some(path) => Self::open(&path),
+            None => Ok(Self { path: PathBuf::new(), lines: Vec::new(), values: Vec::new() }),
+        }
+    }
+
+    /// Load the config file at `path`; a missing file is an empty config
+    pub fn open(path: &Path) -> Result<Self> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        let lines: Vec<String> = text.lines().map(str::to_string).collect();
+        let mut values = Vec::new();
+        let mut section = String::new();
+        for (number, line) in lines.iter().enumerate() {
+            let at = || format!("{}:{}", path.display(), number + 1);
+            match parse_line(line).with_context(at)? {
+                Line::Blank => {}
+                Line::Section(name) => section = name,
+                Line::Entry(key, raw) => {
+                    let key = dotted(&section, &key);
+                    let value = parse_value(&raw).with_context(at)?;
+                    let value = validate(&key, &value).with_context(at)?;
+                    values.push((key, value));
+                }
+            }
+        }
+        Ok(Self { path: path.to_path_buf(), lines, values })
+    }
+
+    /// Where this config was read from
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Every setting in the file, in file order
+    pub fn values(&self) -> &[(String, ConfigValue)] {
+        &self.values
+    }
+
+    /// The value of `key`, if set; the last one wins if the file sets it twice
+    pub fn get(&self, key: &str) -> Option<&ConfigValue> {
+        self.values.iter().rev().find(|(known, _)| known == key).map(|(_, value)| value)
+    }
+
+    /// The value of a string setting
+    pub fn string(&self, key: &str) -> Option<&str> {
+        match self.get(key)? {
+            ConfigValue::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// The value of an integer setting
+    pub fn integer(&self, key: &str) -> Option<i64> {
+        match self.get(key)? {
+            ConfigValue::Integer(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// The value of a boolean setting
+    pub fn boolean(&self, key: &str) -> Option<bool> {
+        match self.get(key)? {
+            ConfigValue::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// The `identity` setting
+    pub fn identity(&self) -> Option<UserName> {
+        self.string("identity").and_then(|name| UserName::from_str(name, true).ok())
+    }
+
+    /// True if `output = "json"`
+    pub fn json_output(&self) -> bool {
+        self.string("output") == Some("json")
+    }
+
+    /// The retention settings, for `MlsChatApp::set_retention`
+    pub fn retention(&self) -> Retention {
+        Retention {
+            max_age_days: self.integer("retention.max-age-days").map(|days| days as u32),
+            max_messages: self.integer("retention.max-messages").map(|count| count as usize),
+        }
+    }
+
+    /// The key rotation settings, for `MlsChatApp::set_rotation`
+    pub fn rotation(&self) -> RotationPolicy {
+        RotationPolicy {
+            after_days: self.integer("key-rotation.after-days").map(|days| days as u32),
+            after_messages: self.integer("key-rotation.after-messages").map(|count| count as usize),
+        }
+    }
+
+    /// The `transparency.log` file, for `MlsChatApp::set_transparency_log`
+    pub fn transparency_log(&self) -> Option<TransparencyLog> {
+        self.string("transparency.log").map(TransparencyLog::new)
+    }
+
+    /// How long an unlocked profile stays unlocked unused, for `MlsChatApp::set_idle_timeout`
+    pub fn idle_timeout(&self) -> TimeDelta {
+        self.integer("session.idle-timeout-minutes")
+            .and_then(TimeDelta::try_minutes)
+            .unwrap_or(session::DEFAULT_IDLE_TIMEOUT)
+    }
+
+    /// After how many wrong passphrases in a row the profile is wiped, for `MlsChatApp::set_wipe_after_failures`
+    pub fn wipe_after_failures(&self) -> Option<u32> {
+        self.integer("session.wipe-after-failures").map(|count| count as u32)
+    }
+
+    /// How many epochs of history `share-history` sends, for `MlsChatApp::set_history_epochs`
+    pub fn history_epochs(&self) -> u32 {
+        self.integer("history.share-epochs").map_or(history::DEFAULT_SHARE_EPOCHS, |epochs| epochs as u32)
+    }
+
+    /// How many messages the long-running modes cache, for `MlsChatApp::open_cached`
+    pub fn cached_messages(&self) -> usize {
+        self.integer("cache.messages").map_or(cache::DEFAULT_CACHED_MESSAGES, |count| count as usize)
+    }
+
+    /// What raises desktop notifications, from `notify.command`, for `MlsChatApp::set_notifier`
+    pub fn notifier(&self) -> Option<Notifier> {
+        self.string("notify.command").and_then(Notifier::parse)
+    }
+
+    /// How message times are shown, from `timezone`, `time-format`, `date-format`, and `relative-times`
+    pub fn timestamps(&self) -> Timestamps {
+        let defaults = Timestamps::default();
+        Timestamps {
+            zone: self.string("timezone").and_then(|zone| Zone::parse(zone).ok()).unwrap_or(defaults.zone),
+            time_format: self.string("time-format").map(str::to_string).unwrap_or(defaults.time_format),
+            date_format: self.string("date-format").map(str::to_string).unwrap_or(defaults.date_format),
+            relative: self.boolean("relative-times").unwrap_or(defaults.relative),
+        }
+    }
+
+    /// Every `[alias]` entry, short name first, for `MlsChatApp::set_aliases`
+    pub fn aliases(&self) -> HashMap<String, String> {
+        self.values.iter()
+            .filter_map(|(key, value)| match value {
+                ConfigValue::String(group) => Some((key.strip_prefix(ALIAS_PREFIX)?.to_string(), group.clone())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Every `[snippet]` entry, by name
+    pub fn snippets(&self) -> HashMap<String, String> {
+        self.values.iter()
+            .filter_map(|(key, value)| match value {
+                ConfigValue::String(text) => Some((key.strip_prefix(SNIPPET_PREFIX)?.to_string(), text.clone())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The color theme: `theme.name`, with any `theme.<role>` styles in place of its own
+    pub fn theme(&self) -> Theme {
+        let mut theme = self.string("theme.name").and_then(Theme::named).unwrap_or_default();
+        for role in Role::ALL {
+            if let Some(style) = self.string(&format!("theme.{}", role.key())).and_then(|style| Style::parse(style).ok()) {
+                theme.set(role, style);
+            }
+        }
+        theme
+    }
+
+    /// Apply the `color` setting and theme to all later output; a non-empty
+    /// `NO_COLOR` turns colors off unless the setting is `always`
+    pub fn apply_color(&self) {
+        match self.string("color") {
+            Some("always") => colored::control::set_override(true),
+            Some("never") => colored::control::set_override(false),
+            _ if env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty()) => colored::control::set_override(false),
+            _ => {}
+        }
+        theme::init(self.theme());
+    }
+
+    /// Check `raw` against `key`'s rules and write it to the file
+    pub fn set(&mut self, key: &str, raw: &str) -> Result<ConfigValue> {
+        let setting = setting(key)?;
+        let value = (setting.parse)(raw).with_context(|| format!("Invalid value for {}", key))?;
+        let (section, name) = split_key(key);
+        let entry = format!("{} = {}", name, value);
+
+        // Replace the last existing entry, or add one after the last line of its section
+        let (existing, section_end) = self.find(section, name);
+        match (existing.last(), section_end) {
+            (Some(&index), _) => self.lines[index] = entry,
+            (None, Some(end)) => self.lines.insert(end, entry),
+            (None, None) => {
+                if self.lines.last().is_some_and(|line| !line.trim().is_empty()) {
+                    self.lines.push(String::new());
+                }
+                self.lines.push(format!("[{}]", section));
+                self.lines.push(entry);
+            }
+        }
+        self.values.push((key.to_string(), value.clone()));
+        self.save()?;
+        Ok(value)
+    }
+
+    /// Remove `key` from the file; returns false if it was not set
+    pub fn unset(&mut self, key: &str) -> Result<bool> {
+        setting(key)?;
+        let (section, name) = split_key(key);
+        let (existing, _) = self.find(section, name);
+        if existing.is_empty() {
+            return Ok(false);
+        }
+        for index in existing.into_iter().rev() {
+            self.lines.remove(index);
+        }
+        self.values.retain(|(known, _)| known != key);
+        self.save()?;
+        Ok(true)
+    }
+
+    /// Lines setting `name` in `section`, and the index just past the section's header or last entry
+    fn find(&self, section: &str, name: &str) -> (Vec<usize>, Option<usize>) {
+        let mut current = String::new();
+        let mut existing = Vec::new();
+        let mut section_end = if section.is_empty() { Some(0) } else { None };
+        for (index, line) in self.lines.iter().enumerate() {
+            match parse_line(line) {
+                Ok(Line::Section(found)) => {
+                    if found == section {
+                        section_end = Some(index + 1);
+                    }
+                    current = found;
+                }
+                Ok(Line::Entry(found, _)) if current == section => {
+                    if found == name {
+                        existing.push(index);
+                    }
+                    section_end = Some(index + 1);
+                }
+                _ => {}
+            }
+        }
+        (existing, section_end)
+    }
+
+    fn save(&self) -> Result<()> {
+        if self.path.as_os_str().is_empty() {
+            return Err(anyhow!("No config file location; set HOME or {}", CONFIG_ENV));
+        }
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        let mut text = self.lines.join("\n");
+        text.push('\n');
+        fs::write(&self.path, text).with_context(|| format!("Failed to write {}", self.path.display()))
+    }
+}
+
+impl fmt::Display for ConfigValue {
+    /// The value in TOML syntax
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigValue::String(value) => write!(
+                f,
+                "\"{}\"",
+                value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n").replace('\t', "\\t")
+            ),
+            ConfigValue::Integer(value) => write!(f, "{}", value),
+            ConfigValue::Bool(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+/// Look up a known setting; every `alias.<name>` key shares one entry, as does every `snippet.<name>`
+pub fn setting(key: &str) -> Result<&'static Setting> {
+    if let Some(name) = key.strip_prefix(ALIAS_PREFIX) {
+        check_alias_name(name)?;
+        return SETTINGS.iter().find(|setting| setting.key.starts_with(ALIAS_PREFIX)).context("Aliases are not configurable");
+    }
+    if let Some(name) = key.strip_prefix(SNIPPET_PREFIX) {
+        check_snippet_name(name)?;
+        return SETTINGS.iter().find(|setting| setting.key.starts_with(SNIPPET_PREFIX)).context("Snippets are not configurable");
+    }
+    SETTINGS.iter().find(|setting| setting.key == key).ok_or_else(|| {
+        let known: Vec<&str> = SETTINGS.iter().map(|setting| setting.key).collect();
+        anyhow!("Unknown setting '{}'; known settings: {}", key, known.join(", "))
+    })
+}
+
+/// Alias names are bare TOML keys: letters, digits, `-`, and `_`
+pub fn check_alias_name(name: &str) -> Result<()> {
+    check_name("alias", name)
+}
+
+/// Snippet names are bare TOML keys too
+pub fn check_snippet_name(name: &str) -> Result<()> {
+    check_name("snippet", name)
+}
+
+fn check_name(kind: &str, name: &str) -> Result<()> {
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return Err(anyhow!("Invalid {} '{}'; use letters, digits, '-', and '_'", kind, name));
+    }
+    Ok(())
+}
+
+enum Line {
+    Blank,
+    Section(String),
+    Entry(String, String), // Key and raw value
+}
+
+fn parse_line(line: &str) -> Result<Line> {
+    let line = strip_comment(line).trim();
+    if line.is_empty() {
+        return Ok(Line::Blank);
+    }
+    if let Some(name) = line.strip_prefix('[') {
+        let name = name.strip_suffix(']').context("Unterminated section header")?;
+        return Ok(Line::Section(name.trim().to_string()));
+    }
+    let (key, value) = line.split_once('=').context("Expected `key = value`")?;
+    Ok(Line::Entry(key.trim().to_string(), value.trim().to_string()))
+}
+
+/// `line` without a trailing `#` comment, leaving `#` inside strings alone
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (index, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..index],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn parse_value(raw: &str) -> Result<ConfigValue> {
+    if let Some(inner) = raw.strip_prefix('"') {
+        let inner = inner.strip_suffix('"').context("Unterminated string")?;
+        let mut value = String::new();
+        let mut chars = inner.chars();
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                value.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('"') => value.push('"'),
+                Some('\\') => value.push('\\'),
+                Some('n') => value.push('\n'),
+                Some('t') => value.push('\t'),
+                other => return Err(anyhow!("Unsupported escape \\{}", other.map(String::from).unwrap_or_default())),
+            }
+        }
+        return Ok(ConfigValue::String(value));
+    }
+    match raw {
+        "true" => Ok(ConfigValue::Bool(true)),
+        "false" => Ok(ConfigValue::Bool(false)),
+        _ => raw.replace('_', "").parse().map(ConfigValue::Integer)
+            .map_err(|_| anyhow!("Expected a quoted string, an integer, or true/false, got `{}`", raw)),
+    }
+}
+
+/// Section and name of a dotted key; top-level keys have an empty section
+fn split_key(key: &str) -> (&str, &str) {
+    key.rsplit_once('.').unwrap_or(("", key))
+}
+
+fn dotted(section: &str, key: &str) -> String {
+    if section.is_empty() { key.to_string() } else { format!("{}.{}", section, key) }
+}
+
+/// Check a value read from the file the way `config set` would; returns it normalized
+fn validate(key: &str, value: &ConfigValue) -> Result<ConfigValue> {
+    let raw = match value {
+        ConfigValue::String(value) => value.clone(),
+        other => other.to_string(),
+    };
+    let expected = (setting(key)?.parse)(&raw).with_context(|| format!("Invalid value for {}", key))?;
+    if std::mem::discriminant(&expected) != std::mem::discriminant(value) {
+        return Err(anyhow!("Invalid value for {}: expected {}", key, kind(&expected)));
+    }
+    Ok(expected)
+}
+
+fn kind(value: &ConfigValue) -> &'static str {
+    match value {
+        ConfigValue::String(_) => "a quoted string",
+        ConfigValue::Integer(_) => "an integer",
+        ConfigValue::Bool(_) => "true or false",
+    }
+}
+
+fn any_string(raw: &str) -> Result<ConfigValue> {
+    if raw.is_empty() {
+        return Err(anyhow!("Value must not be empty"));
+    }
+    Ok(ConfigValue::String(raw.to_string()))
+}
+
+/// Any non-empty text whose placeholders are well formed
+fn snippet(raw: &str) -> Result<ConfigValue> {
+    snippets::placeholders(raw)?;
+    any_string(raw)
+}
+
+fn identity(raw: &str) -> Result<ConfigValue> {
+    let user = UserName::from_str(raw, true).map_err(|_| anyhow!("Unknown user '{}'; expected alice or bob", raw))?;
+    Ok(ConfigValue::String(user.to_string().to_lowercase()))
+}
+
+fn one_of(raw: &str, choices: &[&str]) -> Result<ConfigValue> {
+    match choices.iter().find(|choice| choice.eq_ignore_ascii_case(raw)) {
+        Some(choice) => Ok(ConfigValue::String(choice.to_string())),
+        None => Err(anyhow!("Expected one of {}, got '{}'", choices.join(", "), raw)),
+    }
+}
+
+fn output(raw: &str) -> Result<ConfigValue> {
+    one_of(raw, &["text", "json"])
+}
+
+fn color(raw: &str) -> Result<ConfigValue> {
+    one_of(raw, &["auto", "always", "never"])
+}
+
+fn boolean(raw: &str) -> Result<ConfigValue> {
+    match raw {
+        "true" => Ok(ConfigValue::Bool(true)),
+        "false" => Ok(ConfigValue::Bool(false)),
+        _ => Err(anyhow!("Expected true or false, got '{}'", raw)),
+    }
+}
+
+fn image_preview(raw: &str) -> Result<ConfigValue> {
+    one_of(raw, &["auto", "kitty", "sixel", "blocks", "off"])
+}
+
+fn language(raw: &str) -> Result<ConfigValue> {
+    let mut choices = vec!["auto"];
+    choices.extend(i18n::languages());
+    one_of(raw, &choices)
+}
+
+fn theme_name(raw: &str) -> Result<ConfigValue> {
+    one_of(raw, &theme::names())
+}
+
+fn style(raw: &str) -> Result<ConfigValue> {
+    Style::parse(raw)?;
+    Ok(ConfigValue::String(raw.trim().to_string()))
+}
+
+fn timezone(raw: &str) -> Result<ConfigValue> {
+    Zone::parse(raw)?;
+    Ok(ConfigValue::String(raw.trim().to_string()))
+}
+
+fn time_format(raw: &str) -> Result<ConfigValue> {
+    timestamps::check_format(raw)?;
+    Ok(ConfigValue::String(raw.to_string()))
+}
+
+fn positive(raw: &str) -> Result<ConfigValue> {
+    match raw.parse::<i64>() {
+        Ok(value) if value >= 1 && value <= u32::MAX as i64 => Ok(ConfigValue::Integer(value)),
+        _ => Err(anyhow!("Expected a whole number of at least 1, got '{}'", raw)),
+    }
+}
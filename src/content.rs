@@ -0,0 +1,147 @@
+//! Typed payloads inside encrypted messages
+//!
+//! What an Application or Receipt message encrypts is a `Content` serialized
+//! as JSON, tagged with its type, so a receiver learns whether it holds text,
+//! Markdown, a file, a reaction, a poll, or a receipt from the payload itself.
+//! Clients from before content types encrypted the bare message text; that
+//! still decrypts, as Markdown, which is how it was always shown.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{fs, path::Path};
+
+use crate::crypto::base64;
+use crate::model::{Attachment, ChatMessage, ContentType, Location, Provenance};
+use crate::protocol::ReceiptBody;
+
+/// The plaintext of an encrypted message
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Content {
+    /// Text shown exactly as typed
+    Text { text: String },
+    /// Text with Markdown markup, rendered by `list`
+    Markdown { text: String },
+    /// Any file, carried whole
+    File(Attachment),
+    /// A picture, carried whole like a file
+    Image(Attachment),
+    /// An emoji response to an earlier message
+    Reaction { message_id: String, emoji: String },
+    /// Delivery or read acknowledgement; never stored as a chat message
+    Receipt(ReceiptBody),
+    /// A notice generated by a client rather than typed by a member
+    System { text: String },
+    /// A question with fixed choices for members to vote on
+    Poll { question: String, options: Vec<String> },
+    /// A member's choice in a poll; a later vote replaces an earlier one
+    Vote { poll_id: String, option: String },
+    /// A place, as coordinates and an optional label
+    Location(Location),
+    /// Another payload passed on from a different group, with where it came from
+    Forwarded { content: Box<Content>, from: Provenance },
+}
+
+impl Content {
+    /// Serialize for encryption
+    pub fn to_plaintext(&self) -> Result<String> {
+        Ok(serde_json::to_string(self)?)
+    }
+
+    /// Parse decrypted plaintext; anything that is not a typed payload is Markdown from an older client
+    pub fn from_plaintext(plaintext: &str) -> Self {
+        serde_json::from_str(plaintext).unwrap_or_else(|_| Content::Markdown { text: plaintext.to_string() })
+    }
+
+    /// The content type this payload is stored as, or None for a receipt
+    ///
+    /// A forwarded payload is stored as what it carries.
+    pub fn content_type(&self) -> Option<ContentType> {
+        Some(match self {
+            Content::Forwarded { content, .. } => return content.content_type(),
+            Content::Text { .. } => ContentType::Text,
+            Content::Markdown { .. } => ContentType::Markdown,
+            Content::File(_) => ContentType::File,
+            Content::Image(_) => ContentType::Image,
+            Content::Reaction { .. } => ContentType::Reaction,
+            Content::System { .. } => ContentType::System,
+            Content::Poll { .. } => ContentType::Poll,
+            Content::Vote { .. } => ContentType::Vote,
+            Content::Location(_) => ContentType::Location,
+            Content::Receipt(_) => return None,
+        })
+    }
+
+    /// The text a chat message shows for this payload: the text itself,
+    /// a file's name, a reaction's emoji, a poll's question, the option voted
+    /// for, or a location's label, else its coordinates
+    pub fn summary(&self) -> String {
+        match self {
+            Content::Text { text } | Content::Markdown { text } | Content::System { text } => text.clone(),
+            Content::File(attachment) | Content::Image(attachment) => attachment.name.clone(),
+            Content::Reaction { emoji, .. } => emoji.clone(),
+            Content::Poll { question, .. } => question.clone(),
+            Content::Vote { option, .. } => option.clone(),
+            Content::Location(location) => location.label.clone().unwrap_or_else(|| location.coordinates()),
+            Content::Forwarded { content, .. } => content.summary(),
+            Content::Receipt(body) => format!("{:?} receipt for {} message(s)", body.status, body.message_ids.len()),
+        }
+    }
+
+    /// The payload a stored message was sent as
+    pub fn of_message(message: &ChatMessage) -> Self {
+        if let Some(from) = &message.forwarded_from {
+            let original = ChatMessage { forwarded_from: None, ..message.clone() };
+            return Content::Forwarded { content: Box::new(Content::of_message(&original)), from: from.clone() };
+        }
+        let text = message.content.clone();
+        if let (ContentType::Location, Some(location)) = (message.content_type, &message.location) {
+            return Content::Location(location.clone());
+        }
+        match (message.content_type, &message.attachment, &message.reacts_to) {
+            (ContentType::File, Some(attachment), _) => Content::File(attachment.clone()),
+            (ContentType::Image, Some(attachment), _) => Content::Image(attachment.clone()),
+            (ContentType::Reaction, _, Some(message_id)) => Content::Reaction { message_id: message_id.clone(), emoji: text },
+            (ContentType::Vote, _, Some(poll_id)) => Content::Vote { poll_id: poll_id.clone(), option: text },
+            (ContentType::Poll, _, _) => Content::Poll { question: text, options: message.options.clone() },
+            (ContentType::Text, _, _) => Content::Text { text },
+            (ContentType::System, _, _) => Content::System { text },
+            _ => Content::Markdown { text },
+        }
+    }
+}
+
+impl Content {
+    /// A File or Image message carrying the file at `path`
+    ///
+    /// Images are recognised by their first bytes, whatever the file is called.
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let data = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        let name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_else(|| "file".to_string());
+        let mime = match data.as_slice() {
+            [0x89, b'P', b'N', b'G', ..] => "image/png",
+            [0xFF, 0xD8, ..] => "image/jpeg",
+            [b'G', b'I', b'F', b'8', ..] => "image/gif",
+            [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => "image/webp",
+            [b'P', b'6', ..] => "image/x-portable-pixmap",
+            _ => "application/octet-stream",
+        };
+        let attachment = Attachment::new(name, mime.to_string(), &data);
+        Ok(match mime.starts_with("image/") {
+            true => Content::Image(attachment),
+            false => Content::File(attachment),
+        })
+    }
+}
+
+impl Attachment {
+    /// Carry `data` as an attachment called `name`
+    pub fn new(name: String, mime: String, data: &[u8]) -> Self {
+        Self { name, mime, size: data.len() as u64, data: base64::encode(data) }
+    }
+
+    /// The attachment's bytes
+    pub fn bytes(&self) -> Result<Vec<u8>> {
+        base64::decode(&self.data)
+    }
+}
@@ -0,0 +1,216 @@
+//! Identity key rotation with a continuity statement
+//!
+//! `identity rotate` replaces the current user's long-term key. The old key
+//! signs a statement naming the new one, and the statement goes to every
+//! group the user is in, after an Update that puts the new key in their
+//! leaf. A statement signed by a key the peer never knew for that user is
+//! refused. Other identities in this profile learn the new key straight away.
+//!
+//! A peer who knew the old key still does not take the new one on the
+//! statement's word. `protocol::sign` is keyed by the old *public* key, so any
+//! member who has seen it can write a valid statement for someone else. Until
+//! key changes are signed with something only the user holds, a statement
+//! that checks out is recorded as the pinned key's pending replacement, with
+//! the same warning as any other changed key, and `trust accept` pins it.
+//!
+//! Welcomes still in flight to the old key cannot be opened after
+//! rotating, so whoever sent them has to add the user again.
+
+use anyhow::{anyhow, Context, Result};
+use chrono::Utc;
+use colored::*;
+use uuid::Uuid;
+
+use crate::app::MlsChatApp;
+use crate::crypto;
+use crate::delivery::SendMessageRequest;
+use crate::model::{MockKey, PeerKey};
+use crate::protocol::{self, KeyChangeBody, MlsMessage};
+
+impl MlsChatApp {
+    /// Replace the current user's identity key, vouched for by the old one, and tell every group
+    pub fn rotate_identity(&mut self) -> Result<()> {
+        let user = self.current_user.context("No user initialized")?.to_string();
+        println!("{}", "Rotating your identity key...".green());
+        let old = self.user_keys.get(&user).context("No key for the current user")?.clone();
+        let new = MockKey {
+            id: Uuid::new_v4().to_string(),
+            public_key: format!("pub_key_{}", Uuid::new_v4()),
+            private_key: format!("priv_key_{}", Uuid::new_v4()),
+            sealed_private_key: None,
+            store_kdf: None,
+            unlock_attempts: None,
+        };
+        let mut body = KeyChangeBody {
+            user: user.clone(),
+            old_key_id: old.id.clone(),
+            old_public_key: old.public_key.clone(),
+            new_key_id: new.id.clone(),
+            new_public_key: new.public_key.clone(),
+            at: Utc::now(),
+            signature: String::new(),
+        };
+        body.signature = protocol::sign(&old.public_key, &body.statement());
+        println!("   Old key {} signs new key {}", crypto::fingerprint(&old.public_key), crypto::fingerprint(&new.public_key));
+
+        self.user_keys.insert(user.clone(), new);
+        self.identity_dirty = true;
+        self.save_state()?;
+        let locals: Vec<String> = self.user_keys.keys().filter(|local| **local != user).cloned().collect();
+        for local in locals {
+            self.learn_key_change(&local, &body, true)?;
+        }
+        if self.transport.is_some() {
+            self.publish_key_package()?;
+        } else if self.transparency_log.is_some() {
+            self.publish_to_transparency_log()?;
+        }
+
+        let mut names: Vec<String> = self.groups.values()
+            .filter(|group| group.members.contains(&user))
+            .map(|group| group.name.clone())
+            .collect();
+        names.sort();
+        for name in &names {
+            self.self_update(name)?;
+            if self.transport.is_some() {
+                self.distribute_key_change(name, &body)?;
+            }
+        }
+        println!("✅ Identity key for '{}' rotated; continuity statement sent to {} group(s)", user, names.len());
+        Ok(())
+    }
+
+    /// Send a continuity statement to every other member of the group
+    fn distribute_key_change(&mut self, group_name: &str, body: &KeyChangeBody) -> Result<()> {
+        let user = self.current_user.context("No user initialized")?.to_string();
+        let group = self.groups.get(group_name).context("Group not found")?;
+        let recipients = self.remote_members(group);
+        if recipients.is_empty() {
+            return Ok(());
+        }
+        let key_change = MlsMessage::KeyChange {
+            group_id: group.group_id.clone(),
+            epoch: group.mls_group.epoch,
+            sender: user.clone(),
+            ciphertext: protocol::encrypt(
+                &protocol::application_key(&group.mls_group.group_secret),
+                &serde_json::to_string(body)?,
+            ),
+        };
+        let group_id = group.group_id.clone();
+        let request = SendMessageRequest {
+            sender: user,
+            group_id: group_id.clone(),
+            recipients,
+            payload: key_change.to_bytes()?,
+        };
+        let seq = self.transport()?.send_message(request)?.seq;
+        self.record_sent_seq(&group_id, seq);
+        Ok(())
+    }
+
+    /// Apply a KeyChange from another member to the current user's known keys
+    pub(crate) fn apply_key_change(&mut self, group_name: &str, sender: &str, epoch: u32, ciphertext: &str) -> Result<()> {
+        let user = self.current_user.context("No user initialized")?.to_string();
+        let group = self.groups.get(group_name).context("Group not found")?;
+        if epoch != group.mls_group.epoch {
+            return Err(anyhow!("Key change from epoch {} cannot be decrypted at epoch {}", epoch, group.mls_group.epoch));
+        }
+        let body = protocol::decrypt(&protocol::application_key(&group.mls_group.group_secret), ciphertext)?;
+        let body: KeyChangeBody = serde_json::from_str(&body).context("Malformed key change")?;
+        if body.user != sender {
+            return Err(anyhow!("'{}' sent a key change for '{}' in '{}'; ignored", sender, body.user, group_name));
+        }
+        self.learn_key_change(&user, &body, false)
+    }
+
+    /// Record the new key in a continuity statement as `local`'s view of its user, if the old key signed it
+    ///
+    /// Only a statement from this profile's own identities is `vouched` for and
+    /// pinned at once; one received from a group goes to `observe_key`, which
+    /// holds the new key as pending until `trust accept`.
+    fn learn_key_change(&mut self, local: &str, body: &KeyChangeBody, vouched: bool) -> Result<()> {
+        let old_fingerprint = crypto::fingerprint(&body.old_public_key);
+        let new_fingerprint = crypto::fingerprint(&body.new_public_key);
+        if !protocol::verify(&body.old_public_key, &body.statement(), &body.signature) {
+            return Err(anyhow!("Key change for '{}' is not signed by their previous key; not applied", body.user));
+        }
+        let mut peer_keys = self.storage.load_peer_keys()?;
+        let known = peer_keys.entry(local.to_string()).or_default();
+        match known.get(&body.user) {
+            Some(key) if key.fingerprint == new_fingerprint => return Ok(()),
+            Some(key) if key.fingerprint != old_fingerprint => {
+                return Err(anyhow!(
+                    "Key change for '{}' is signed by {}, not the key {} knows for them ({}); not applied",
+                    body.user, old_fingerprint, local, key.fingerprint
+                ));
+            }
+            _ => {}
+        }
+        if !vouched {
+            println!(
+                "   🔑 {} says they rotated their identity key to {}, in a statement signed by their previous key {}",
+                body.user, new_fingerprint, old_fingerprint
+            );
+            println!("   Any member who knows their previous key could have written it, so it is treated like any other new key:");
+            self.observe_key(&body.user, &body.new_public_key)?;
+            return Ok(());
+        }
+        let pending = known.get(&body.user).and_then(|key| key.pending.clone()).filter(|pending| *pending != new_fingerprint);
+        known.insert(body.user.clone(), PeerKey { fingerprint: new_fingerprint.clone(), since: body.at, continued_from: Some(old_fingerprint), pending });
+        self.storage.save_peer_keys(&peer_keys)?;
+        println!("   🔑 {} rotated their identity key to {}; signed by their previous key", body.user, new_fingerprint);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::UserName;
+    use crate::storage::MemoryStorage;
+
+    fn key_change(old_public_key: &str, signed_by: &str) -> KeyChangeBody {
+        let mut body = KeyChangeBody {
+            user: "Alice".to_string(),
+            old_key_id: "old".to_string(),
+            old_public_key: old_public_key.to_string(),
+            new_key_id: "new".to_string(),
+            new_public_key: "pub_key_new".to_string(),
+            at: Utc::now(),
+            signature: String::new(),
+        };
+        body.signature = protocol::sign(signed_by, &body.statement());
+        body
+    }
+
+    #[test]
+    fn received_key_changes_wait_for_trust_accept() {
+        let mut app = MlsChatApp::with_storage(Box::new(MemoryStorage::new()));
+        app.init_user(UserName::Bob).unwrap();
+        app.observe_key("Alice", "pub_key_old").unwrap();
+
+        app.learn_key_change("Bob", &key_change("pub_key_old", "pub_key_old"), false).unwrap();
+        let pinned = &app.storage.load_peer_keys().unwrap()["Bob"]["Alice"];
+        assert_eq!(pinned.fingerprint, crypto::fingerprint("pub_key_old"));
+        assert_eq!(pinned.pending, Some(crypto::fingerprint("pub_key_new")));
+
+        app.trust_accept(UserName::Alice).unwrap();
+        let pinned = &app.storage.load_peer_keys().unwrap()["Bob"]["Alice"];
+        assert_eq!(pinned.fingerprint, crypto::fingerprint("pub_key_new"));
+    }
+
+    #[test]
+    fn key_changes_from_unknown_or_wrong_keys_are_refused() {
+        let mut app = MlsChatApp::with_storage(Box::new(MemoryStorage::new()));
+        app.init_user(UserName::Bob).unwrap();
+        app.observe_key("Alice", "pub_key_old").unwrap();
+
+        assert!(app.learn_key_change("Bob", &key_change("pub_key_old", "pub_key_other"), false).is_err());
+        assert!(app.learn_key_change("Bob", &key_change("pub_key_other", "pub_key_other"), false).is_err());
+        let pinned = &app.storage.load_peer_keys().unwrap()["Bob"]["Alice"];
+        assert_eq!(pinned.fingerprint, crypto::fingerprint("pub_key_old"));
+        assert_eq!(pinned.pending, None);
+    }
+}
@@ -0,0 +1,341 @@
+//! Dependency-free cryptographic primitives for the demo
+//!
+//! Small, readable implementations of SHA-256, HMAC-SHA256, PBKDF2, and a hash-based
+//! stream cipher with an HMAC tag, so that messages leaving this process are
+//! actually encrypted and authenticated. They are not constant-time and are
+//! no substitute for an audited library; a real deployment would use the
+//! ciphersuite primitives of an MLS implementation instead.
+
+use anyhow::{anyhow, Result};
+use uuid::Uuid;
+
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+
+const ROUND_CONSTANTS: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+const INITIAL_STATE: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+/// SHA-256 digest of `data`
+pub fn sha256(data: &[u8]) -> [u8; 32] {
+    finish(INITIAL_STATE, data, 0)
+}
+
+/// Hash the rest of a message, `data`, into `state`, which has already taken `done` bytes, and pad it out
+fn finish(mut state: [u32; 8], data: &[u8], done: u64) -> [u8; 32] {
+    let blocks = data.chunks_exact(64);
+    let rest = blocks.remainder();
+    for block in blocks {
+        compress(&mut state, block);
+    }
+
+    // The last bytes, 0x80, zeros, and the bit length fill one block or two
+    let mut tail = [0u8; 128];
+    tail[..rest.len()].copy_from_slice(rest);
+    tail[rest.len()] = 0x80;
+    let tail_len = if rest.len() < 56 { 64 } else { 128 };
+    let bit_len = (done + data.len() as u64).wrapping_mul(8);
+    tail[tail_len - 8..tail_len].copy_from_slice(&bit_len.to_be_bytes());
+    for block in tail[..tail_len].chunks_exact(64) {
+        compress(&mut state, block);
+    }
+
+    let mut digest = [0u8; 32];
+    for (bytes, word) in digest.chunks_exact_mut(4).zip(state) {
+        bytes.copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
+
+/// Run one 64-byte block through the compression function
+fn compress(state: &mut [u32; 8], block: &[u8]) {
+    let mut schedule = [0u32; 64];
+    for (word, bytes) in schedule.iter_mut().zip(block.chunks_exact(4)) {
+        *word = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    }
+    for i in 16..64 {
+        let s0 = schedule[i - 15].rotate_right(7) ^ schedule[i - 15].rotate_right(18) ^ (schedule[i - 15] >> 3);
+        let s1 = schedule[i - 2].rotate_right(17) ^ schedule[i - 2].rotate_right(19) ^ (schedule[i - 2] >> 10);
+        schedule[i] = schedule[i - 16]
+            .wrapping_add(s0)
+            .wrapping_add(schedule[i - 7])
+            .wrapping_add(s1);
+    }
+
+    let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = *state;
+    for (constant, word) in ROUND_CONSTANTS.iter().zip(schedule) {
+        let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+        let choice = (e & f) ^ (!e & g);
+        let t1 = h
+            .wrapping_add(s1)
+            .wrapping_add(choice)
+            .wrapping_add(*constant)
+            .wrapping_add(word);
+        let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+        let majority = (a & b) ^ (a & c) ^ (b & c);
+        let t2 = s0.wrapping_add(majority);
+
+        h = g;
+        g = f;
+        f = e;
+        e = d.wrapping_add(t1);
+        d = c;
+        c = b;
+        b = a;
+        a = t1.wrapping_add(t2);
+    }
+
+    for (word, value) in state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+        *word = word.wrapping_add(value);
+    }
+}
+
+/// HMAC-SHA256 of `data` under `key`
+pub fn hmac_sha256(key: &[u8], data: &[u8]) -> [u8; 32] {
+    Hmac::new(key).mac(data)
+}
+
+/// HMAC-SHA256 under one key, with the padded key already hashed, for MACing many messages
+struct Hmac {
+    inner: [u32; 8], // State after `key ^ ipad`
+    outer: [u32; 8], // State after `key ^ opad`
+}
+
+impl Hmac {
+    fn new(key: &[u8]) -> Self {
+        let mut block_key = [0u8; 64];
+        if key.len() > 64 {
+            block_key[..32].copy_from_slice(&sha256(key));
+        } else {
+            block_key[..key.len()].copy_from_slice(key);
+        }
+        let pad = |byte: u8| {
+            let mut state = INITIAL_STATE;
+            compress(&mut state, &block_key.map(|key_byte| key_byte ^ byte));
+            state
+        };
+        Self { inner: pad(0x36), outer: pad(0x5c) }
+    }
+
+    fn mac(&self, data: &[u8]) -> [u8; 32] {
+        finish(self.outer, &finish(self.inner, data, 64), 64)
+    }
+}
+
+/// Derive a purpose-specific 32-byte key from a secret
+pub fn derive_key(secret: &str, label: &str) -> [u8; 32] {
+    hmac_sha256(secret.as_bytes(), label.as_bytes())
+}
+
+/// PBKDF2-HMAC-SHA256 (RFC 8018) of `passphrase` with `salt`, one 32-byte block
+///
+/// Each guess at the passphrase costs `iterations` HMACs, which is what makes
+/// an offline search of a copied profile slow.
+pub fn pbkdf2_sha256(passphrase: &[u8], salt: &[u8], iterations: u32) -> [u8; 32] {
+    let hmac = Hmac::new(passphrase);
+    let mut first = salt.to_vec();
+    first.extend_from_slice(&1u32.to_be_bytes());
+    let mut block = hmac.mac(&first);
+    let mut derived = block;
+    for _ in 1..iterations {
+        block = hmac.mac(&block);
+        for (byte, next) in derived.iter_mut().zip(block) {
+            *byte ^= next;
+        }
+    }
+    derived
+}
+
+/// A fresh random 16-byte salt
+pub fn salt() -> [u8; 16] {
+    *Uuid::new_v4().as_bytes()
+}
+
+/// Encrypt and authenticate `plaintext`, returning `nonce || ciphertext || tag`
+pub fn seal(key: &[u8; 32], plaintext: &[u8]) -> Vec<u8> {
+    let nonce: [u8; NONCE_LEN] = Uuid::new_v4().as_bytes()[..NONCE_LEN]
+        .try_into()
+        .expect("UUID has at least 12 bytes");
+
+    let mut sealed = Vec::with_capacity(NONCE_LEN + plaintext.len() + TAG_LEN);
+    sealed.extend_from_slice(&nonce);
+    sealed.extend(apply_keystream(key, &nonce, plaintext));
+    let tag = hmac_sha256(key, &sealed);
+    sealed.extend_from_slice(&tag[..TAG_LEN]);
+    sealed
+}
+
+/// Verify and decrypt data produced by `seal`
+pub fn open(key: &[u8; 32], sealed: &[u8]) -> Result<Vec<u8>> {
+    if sealed.len() < NONCE_LEN + TAG_LEN {
+        return Err(anyhow!("Ciphertext too short"));
+    }
+    let (body, tag) = sealed.split_at(sealed.len() - TAG_LEN);
+    let expected = hmac_sha256(key, body);
+    if !constant_eq(&expected[..TAG_LEN], tag) {
+        return Err(anyhow!("Authentication failed: wrong key or tampered ciphertext"));
+    }
+
+    let (nonce, ciphertext) = body.split_at(NONCE_LEN);
+    Ok(apply_keystream(key, nonce, ciphertext))
+}
+
+/// XOR `data` with SHA-256(key || nonce || counter) blocks
+fn apply_keystream(key: &[u8; 32], nonce: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(data.len());
+    for (counter, chunk) in data.chunks(32).enumerate() {
+        let mut block_input = Vec::with_capacity(32 + nonce.len() + 8);
+        block_input.extend_from_slice(key);
+        block_input.extend_from_slice(nonce);
+        block_input.extend_from_slice(&(counter as u64).to_be_bytes());
+        let keystream = sha256(&block_input);
+        output.extend(chunk.iter().zip(keystream).map(|(byte, key_byte)| byte ^ key_byte));
+    }
+    output
+}
+
+pub(crate) fn constant_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// SHA-256 of a public key in hex, in groups of four, for comparing keys by eye
+pub fn fingerprint(public_key: &str) -> String {
+    let digest = hex(&sha256(public_key.as_bytes()));
+    let groups: Vec<&str> = (0..digest.len()).step_by(4).map(|start| &digest[start..start + 4]).collect();
+    groups.join(" ")
+}
+
+/// Lowercase hex encoding
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Standard base64 with padding, as used by the proto3 JSON mapping for `bytes`
+pub mod base64 {
+    use anyhow::{anyhow, Result};
+
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    /// Encode bytes as base64
+    pub fn encode(data: &[u8]) -> String {
+        let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+        for chunk in data.chunks(3) {
+            let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+            let triple = u32::from_be_bytes([0, b[0], b[1], b[2]]);
+            for i in 0..4 {
+                if i <= chunk.len() {
+                    let index = (triple >> (18 - 6 * i)) & 0x3f;
+                    encoded.push(ALPHABET[index as usize] as char);
+                } else {
+                    encoded.push('=');
+                }
+            }
+        }
+        encoded
+    }
+
+    /// Decode base64, accepting input with or without padding
+    pub fn decode(encoded: &str) -> Result<Vec<u8>> {
+        let mut decoded = Vec::with_capacity(encoded.len() / 4 * 3);
+        let mut buffer = 0u32;
+        let mut bits = 0;
+        for c in encoded.trim_end_matches('=').bytes() {
+            let value = ALPHABET
+                .iter()
+                .position(|&a| a == c)
+                .ok_or_else(|| anyhow!("Invalid base64 character '{}'", c as char))?;
+            buffer = (buffer << 6) | value as u32;
+            bits += 6;
+            if bits >= 8 {
+                bits -= 8;
+                decoded.push((buffer >> bits) as u8);
+            }
+        }
+        Ok(decoded)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sha256_matches_known_answers() {
+        let cases: [(&[u8], &str); 3] = [
+            (b"", "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"),
+            (b"abc", "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"),
+            // 56 bytes, so the padding spills into a second block
+            (b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq", "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"),
+        ];
+        for (data, digest) in cases {
+            assert_eq!(hex(&sha256(data)), digest);
+        }
+        assert_eq!(hex(&sha256(&[b'a'; 1000])), "41edece42d63e8d9bf515a9ba6932e1c20cbc9f5a5d134645adb5db1b9737ea3");
+    }
+
+    #[test]
+    fn hmac_sha256_matches_rfc_4231() {
+        assert_eq!(hex(&hmac_sha256(&[0x0b; 20], b"Hi There")), "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7");
+        assert_eq!(hex(&hmac_sha256(b"Jefe", b"what do ya want for nothing?")), "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843");
+        // Keys longer than a block are hashed first
+        assert_eq!(
+            hex(&hmac_sha256(&[0xaa; 131], b"Test Using Larger Than Block-Size Key - Hash Key First")),
+            "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54"
+        );
+    }
+
+    #[test]
+    fn pbkdf2_sha256_matches_known_answers() {
+        assert_eq!(hex(&pbkdf2_sha256(b"password", b"salt", 1)), "120fb6cffcf8b32c43e7225256c4f837a86548c92ccc35480805987cb70be17b");
+        assert_eq!(hex(&pbkdf2_sha256(b"password", b"salt", 4096)), "c5e478d59288c841aa530db6845c4c8d962893a001ce4e11a4963873aa98134a");
+        assert_ne!(pbkdf2_sha256(b"password", b"pepper", 1), pbkdf2_sha256(b"password", b"salt", 1));
+    }
+
+    #[test]
+    fn seal_round_trips() {
+        let key = sha256(b"key");
+        for plaintext in [&b""[..], b"hi", &[7; 100]] {
+            let sealed = seal(&key, plaintext);
+            assert_eq!(sealed.len(), NONCE_LEN + plaintext.len() + TAG_LEN);
+            assert_eq!(open(&key, &sealed).unwrap(), plaintext);
+        }
+        // A fresh nonce each time
+        assert_ne!(seal(&key, b"hi"), seal(&key, b"hi"));
+    }
+
+    #[test]
+    fn open_refuses_tampering_and_wrong_keys() {
+        let key = sha256(b"key");
+        let sealed = seal(&key, b"attack at dawn");
+        for index in [0, NONCE_LEN, sealed.len() - 1] {
+            let mut tampered = sealed.clone();
+            tampered[index] ^= 1;
+            assert!(open(&key, &tampered).is_err());
+        }
+        assert!(open(&sha256(b"other key"), &sealed).is_err());
+        assert!(open(&key, &sealed[..sealed.len() - 1]).is_err());
+        assert!(open(&key, &sealed[..NONCE_LEN + TAG_LEN - 1]).is_err());
+    }
+
+    #[test]
+    fn base64_matches_rfc_4648() {
+        let cases = [("", ""), ("f", "Zg=="), ("fo", "Zm8="), ("foo", "Zm9v"), ("foob", "Zm9vYg=="), ("fooba", "Zm9vYmE="), ("foobar", "Zm9vYmFy")];
+        for (plain, encoded) in cases {
+            assert_eq!(base64::encode(plain.as_bytes()), encoded);
+            assert_eq!(base64::decode(encoded).unwrap(), plain.as_bytes());
+            assert_eq!(base64::decode(encoded.trim_end_matches('=')).unwrap(), plain.as_bytes());
+        }
+        assert!(base64::decode("Zm9v!").is_err());
+    }
+}
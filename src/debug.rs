@@ -0,0 +1,104 @@
+//! `debug state` and `debug diff`: look inside the persisted state
+//!
+//! `debug state` prints a group exactly as it is stored, MLS state and
+//! message log included, as JSON. Saving that before and after a command and
+//! running `debug diff` on the two files shows what the command changed,
+//! field by field, for instance the epoch, tree hash, and secret a commit
+//! replaces. Arrays are compared by position, so a member inserted at the
+//! front shows as every later entry changing.
+
+use anyhow::{Context, Result};
+use colored::*;
+use serde_json::Value;
+use std::{fs, path::Path};
+
+use crate::app::MlsChatApp;
+
+/// One difference between two states
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    Added(String, Value),          // Path and new value
+    Removed(String, Value),        // Path and old value
+    Changed(String, Value, Value), // Path, old value, and new value
+}
+
+impl MlsChatApp {
+    /// A group as persisted: its metadata and MLS state, with its message log under `messages`
+    pub fn debug_state(&mut self, group_name: &str) -> Result<Value> {
+        let group = self.load_messages(group_name)?;
+        let mut state = serde_json::to_value(group)?;
+        state["messages"] = serde_json::to_value(group.messages.as_deref().unwrap_or_default())?;
+        Ok(state)
+    }
+}
+
+/// Every difference from `old` to `new`, in document order
+pub fn diff(old: &Value, new: &Value) -> Vec<Change> {
+    let mut changes = Vec::new();
+    diff_at(String::new(), old, new, &mut changes);
+    changes
+}
+
+fn diff_at(path: String, old: &Value, new: &Value, changes: &mut Vec<Change>) {
+    match (old, new) {
+        (Value::Object(old), Value::Object(new)) => {
+            for (key, old_value) in old {
+                let path = join(&path, key);
+                match new.get(key) {
+                    Some(new_value) => diff_at(path, old_value, new_value, changes),
+                    None => changes.push(Change::Removed(path, old_value.clone())),
+                }
+            }
+            for (key, new_value) in new.iter().filter(|(key, _)| !old.contains_key(*key)) {
+                changes.push(Change::Added(join(&path, key), new_value.clone()));
+            }
+        }
+        (Value::Array(old), Value::Array(new)) => {
+            for (index, old_value) in old.iter().enumerate() {
+                let path = format!("{}[{}]", path, index);
+                match new.get(index) {
+                    Some(new_value) => diff_at(path, old_value, new_value, changes),
+                    None => changes.push(Change::Removed(path, old_value.clone())),
+                }
+            }
+            for (index, new_value) in new.iter().enumerate().skip(old.len()) {
+                changes.push(Change::Added(format!("{}[{}]", path, index), new_value.clone()));
+            }
+        }
+        _ if old != new => changes.push(Change::Changed(path, old.clone(), new.clone())),
+        _ => {}
+    }
+}
+
+fn join(path: &str, key: &str) -> String {
+    match path.is_empty() {
+        true => key.to_string(),
+        false => format!("{}.{}", path, key),
+    }
+}
+
+/// Print the differences between two saved states, one field per line
+pub fn print_diff(old_file: &Path, new_file: &Path) -> Result<()> {
+    let old = read_state(old_file)?;
+    let new = read_state(new_file)?;
+    let changes = diff(&old, &new);
+    if changes.is_empty() {
+        println!("No differences.");
+        return Ok(());
+    }
+    for change in &changes {
+        match change {
+            Change::Added(path, value) => println!("{}", format!("+ {}: {}", path, value).green()),
+            Change::Removed(path, value) => println!("{}", format!("- {}: {}", path, value).red()),
+            Change::Changed(path, old, new) => println!("{} {}: {} → {}", "~".yellow(), path, old, new),
+        }
+    }
+    println!();
+    println!("{} difference(s)", changes.len());
+    Ok(())
+}
+
+fn read_state(path: &Path) -> Result<Value> {
+    let data = fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    serde_json::from_str(&data).with_context(|| format!("{} is not JSON; save states with `debug state <group> > file`", path.display()))
+}
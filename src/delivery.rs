@@ -0,0 +1,564 @@
+//! Delivery service API shared by the server and clients
+//!
+//! Each call is one line of JSON over TCP, with camelCase field names,
+//! `bytes` as base64, and 64-bit integers as strings, so any language with
+//! a JSON library can talk to it. `schema/delivery.schema.json` describes
+//! every call and result as JSON Schema, and a test keeps it in step with
+//! the types here:
+//!
+//! ```text
+//! -> {"method":"SendMessage","params":{"sender":"Alice","groupId":"...","recipients":["Bob"],"payload":"..."}}
+//! <- {"result":{"messageId":"..."}}
+//! <- {"error":{"code":"NOT_FOUND","message":"..."}}
+//! ```
+//!
+//! Calls on a registered user's behalf must carry the token from `Register`
+//! next to the method, as `"token":"..."`. Calls to a namespace other than
+//! the default one name it as `"namespace":"..."`.
+//!
+//! Admin calls (`ListUsers`, `ListGroups`, `PurgeKeyPackages`, `RevokeToken`,
+//! `QueueDepths`) carry the namespace's admin token in `"token"` instead.
+//!
+//! `Subscribe` turns the connection into a stream: the server keeps writing
+//! one `{"result": <Envelope>}` line per delivered message until the client
+//! disconnects.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Default address for `serve` and `--server`
+pub const DEFAULT_SERVER_ADDR: &str = "127.0.0.1:7878";
+
+/// Public part of a user's key material, published so others can add them
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KeyPackage {
+    pub user: String,
+    pub key_id: String,
+    pub public_key: String,
+}
+
+/// A message queued for one recipient
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Envelope {
+    pub message_id: String,
+    pub sender: String,
+    pub group_id: String,
+    #[serde(with = "base64_bytes")]
+    pub payload: Vec<u8>, // Serialized MLS message, opaque to the server
+    pub received_at: DateTime<Utc>,
+    #[serde(default, with = "u64_string")]
+    pub seq: u64, // Position in the group's server-assigned order; 0 if unsequenced
+}
+
+/// An envelope tagged with its recipients, for channels where everyone sees every message
+///
+/// Transports without per-user queues, such as a shared MQTT topic, carry
+/// these so each client can keep only what is addressed to it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AddressedEnvelope {
+    pub recipients: Vec<String>,
+    #[serde(flatten)]
+    pub envelope: Envelope,
+}
+
+impl AddressedEnvelope {
+    /// Wrap a send request in a new envelope with a fresh message ID
+    pub fn new(request: SendMessageRequest) -> Self {
+        Self {
+            recipients: request.recipients,
+            envelope: Envelope {
+                message_id: Uuid::new_v4().to_string(),
+                sender: request.sender,
+                group_id: request.group_id,
+                payload: request.payload,
+                received_at: Utc::now(),
+                seq: 0,
+            },
+        }
+    }
+
+    pub fn is_for(&self, user: &str) -> bool {
+        self.recipients.iter().any(|recipient| recipient == user)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PublishKeyPackageRequest {
+    pub key_package: KeyPackage,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PublishKeyPackageResponse {}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FetchKeyPackageRequest {
+    pub user: String,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub local_only: bool, // Set by peer servers, so a miss is not passed on to further peers
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FetchKeyPackageResponse {
+    pub key_package: KeyPackage,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SendMessageRequest {
+    pub sender: String,
+    pub group_id: String,
+    pub recipients: Vec<String>,
+    #[serde(with = "base64_bytes")]
+    pub payload: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SendMessageResponse {
+    pub message_id: String,
+    #[serde(default, with = "u64_string")]
+    pub seq: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FetchInboxRequest {
+    pub user: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FetchInboxResponse {
+    pub envelopes: Vec<Envelope>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FetchRangeRequest {
+    pub user: String,
+    pub group_id: String,
+    #[serde(with = "u64_string")]
+    pub from_seq: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FetchRangeResponse {
+    pub envelopes: Vec<Envelope>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RegisterRequest {
+    pub user: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RegisterResponse {
+    pub token: String, // Bearer token for all later calls on the user's behalf
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HeartbeatRequest {
+    pub user: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HeartbeatResponse {}
+
+/// Whether a user is reachable, as far as the delivery service can tell
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Presence {
+    pub user: String,
+    pub online: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_seen: Option<DateTime<Utc>>, // None if the user has never contacted the service
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FetchPresenceRequest {
+    pub users: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FetchPresenceResponse {
+    pub presence: Vec<Presence>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SubscribeRequest {
+    pub user: String,
+}
+
+/// An envelope passed from the sender's server to the server its recipients are homed on
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ForwardRequest {
+    pub origin: String,              // Federation name of the forwarding server
+    pub envelope: AddressedEnvelope, // Addressed to recipients homed on the receiving server
+    pub signature: String,           // Hex HMAC-SHA256 of the envelope under the secret the two servers share
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ForwardResponse {}
+
+/// What the delivery service holds for one user, as shown to operators
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserSummary {
+    pub user: String,
+    pub registered: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_package_published_at: Option<DateTime<Utc>>, // None if no key package is published
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_seen: Option<DateTime<Utc>>,
+    pub online: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ListUsersRequest {}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ListUsersResponse {
+    pub users: Vec<UserSummary>,
+}
+
+/// A group the delivery service has relayed messages for
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GroupSummary {
+    pub group_id: String,
+    #[serde(with = "u64_string")]
+    pub messages: u64,
+    pub members: Vec<String>, // Everyone who has sent or been sent a message in the group
+    pub last_message_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ListGroupsRequest {}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ListGroupsResponse {
+    pub groups: Vec<GroupSummary>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PurgeKeyPackagesRequest {
+    #[serde(with = "u64_string")]
+    pub older_than_seconds: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PurgeKeyPackagesResponse {
+    pub users: Vec<String>, // Whose key packages were removed
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RevokeTokenRequest {
+    pub user: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RevokeTokenResponse {}
+
+/// How many envelopes are waiting in one user's inbox
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueueDepth {
+    pub user: String,
+    pub depth: u32,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct QueueDepthsRequest {}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct QueueDepthsResponse {
+    pub queues: Vec<QueueDepth>,
+}
+
+/// One RPC call, as sent by a client
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "method", content = "params")]
+pub enum Request {
+    Register(RegisterRequest),
+    PublishKeyPackage(PublishKeyPackageRequest),
+    FetchKeyPackage(FetchKeyPackageRequest),
+    SendMessage(SendMessageRequest),
+    FetchInbox(FetchInboxRequest),
+    FetchRange(FetchRangeRequest),
+    Heartbeat(HeartbeatRequest),
+    FetchPresence(FetchPresenceRequest),
+    Subscribe(SubscribeRequest),
+    Forward(ForwardRequest),
+    ListUsers(ListUsersRequest),
+    ListGroups(ListGroupsRequest),
+    PurgeKeyPackages(PurgeKeyPackagesRequest),
+    RevokeToken(RevokeTokenRequest),
+    QueueDepths(QueueDepthsRequest),
+}
+
+impl Request {
+    /// The RPC's name, as sent in `"method"`
+    pub fn method(&self) -> &'static str {
+        match self {
+            Request::Register(_) => "Register",
+            Request::PublishKeyPackage(_) => "PublishKeyPackage",
+            Request::FetchKeyPackage(_) => "FetchKeyPackage",
+            Request::SendMessage(_) => "SendMessage",
+            Request::FetchInbox(_) => "FetchInbox",
+            Request::FetchRange(_) => "FetchRange",
+            Request::Heartbeat(_) => "Heartbeat",
+            Request::FetchPresence(_) => "FetchPresence",
+            Request::Subscribe(_) => "Subscribe",
+            Request::Forward(_) => "Forward",
+            Request::ListUsers(_) => "ListUsers",
+            Request::ListGroups(_) => "ListGroups",
+            Request::PurgeKeyPackages(_) => "PurgeKeyPackages",
+            Request::RevokeToken(_) => "RevokeToken",
+            Request::QueueDepths(_) => "QueueDepths",
+        }
+    }
+
+    /// The user making the call, for calls made on a user's own behalf
+    pub fn caller(&self) -> Option<&str> {
+        match self {
+            Request::PublishKeyPackage(request) => Some(&request.key_package.user),
+            Request::SendMessage(request) => Some(&request.sender),
+            Request::FetchInbox(request) => Some(&request.user),
+            Request::FetchRange(request) => Some(&request.user),
+            Request::Heartbeat(request) => Some(&request.user),
+            Request::Subscribe(request) => Some(&request.user),
+            Request::Register(_)
+            | Request::FetchKeyPackage(_)
+            | Request::FetchPresence(_)
+            | Request::Forward(_)
+            | Request::ListUsers(_)
+            | Request::ListGroups(_)
+            | Request::PurgeKeyPackages(_)
+            | Request::RevokeToken(_)
+            | Request::QueueDepths(_) => None,
+        }
+    }
+
+    /// Operator calls, which need the namespace's admin token
+    pub fn is_admin(&self) -> bool {
+        matches!(
+            self,
+            Request::ListUsers(_)
+                | Request::ListGroups(_)
+                | Request::PurgeKeyPackages(_)
+                | Request::RevokeToken(_)
+                | Request::QueueDepths(_)
+        )
+    }
+}
+
+/// A request as it travels on the wire, with the caller's token if they have one
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Call {
+    #[serde(flatten)]
+    pub request: Request,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub namespace: String, // Empty for the server's default namespace
+}
+
+/// Outcome of an RPC call, or one item of a `Subscribe` stream
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Reply {
+    Result(serde_json::Value),
+    Error(RpcError),
+}
+
+/// Error status returned by the delivery service, modelled on gRPC status codes
+#[derive(Debug, Clone, Serialize, Deserialize, thiserror::Error)]
+#[error("{code:?}: {message}")]
+pub struct RpcError {
+    pub code: StatusCode,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum StatusCode {
+    InvalidArgument,
+    NotFound,
+    AlreadyExists,
+    Unauthenticated,
+    PermissionDenied,
+    ResourceExhausted, // Rate limited or over a size limit; the HTTP equivalent is 429
+    Internal,
+}
+
+impl RpcError {
+    pub fn new(code: StatusCode, message: impl Into<String>) -> Self {
+        Self { code, message: message.into() }
+    }
+}
+
+/// Serde adapter encoding `bytes` fields as base64 strings
+mod base64_bytes {
+    use crate::crypto::base64;
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&base64::encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        base64::decode(&encoded).map_err(D::Error::custom)
+    }
+}
+
+/// Serde adapter encoding 64-bit integers as strings, as proto3 JSON does
+///
+/// Plain JSON numbers are accepted too.
+mod u64_string {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum StringOrNumber {
+        String(String),
+        Number(u64),
+    }
+
+    pub fn serialize<S: Serializer>(value: &u64, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(value)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+        match StringOrNumber::deserialize(deserializer)? {
+            StringOrNumber::String(value) => value.parse().map_err(D::Error::custom),
+            StringOrNumber::Number(value) => Ok(value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    const SCHEMA: &str = include_str!("../schema/delivery.schema.json");
+
+    /// The definition a `{"$ref": "#/$defs/<name>"}` points at
+    fn resolve<'a>(schema: &'a Value, reference: &Value) -> &'a Value {
+        let name = reference["$ref"].as_str().unwrap().strip_prefix("#/$defs/").unwrap();
+        &schema["$defs"][name]
+    }
+
+    /// Check that `value` has every field `definition` requires and none it does not list
+    fn check_fields(definition: &Value, value: &Value, what: &str) {
+        let fields = value.as_object().unwrap_or_else(|| panic!("{} is not an object", what));
+        let properties = definition["properties"].as_object().unwrap();
+        for field in fields.keys() {
+            assert!(properties.contains_key(field), "{} has field {} the schema lacks", what, field);
+        }
+        for field in definition["required"].as_array().into_iter().flatten() {
+            assert!(fields.contains_key(field.as_str().unwrap()), "{} lacks required field {}", what, field);
+        }
+    }
+
+    fn envelope() -> Envelope {
+        AddressedEnvelope::new(SendMessageRequest {
+            sender: "Alice".to_string(),
+            group_id: "g".to_string(),
+            recipients: vec!["Bob".to_string()],
+            payload: b"mls".to_vec(),
+        }).envelope
+    }
+
+    #[test]
+    fn schema_matches_the_wire_types() {
+        let schema: Value = serde_json::from_str(SCHEMA).unwrap();
+        let key_package = KeyPackage { user: "Bob".to_string(), key_id: "k".to_string(), public_key: "p".to_string() };
+        let user = || "Bob".to_string();
+        let envelopes = json!({ "envelopes": [envelope()] });
+        let calls = vec![
+            (Request::Register(RegisterRequest { user: user() }), json!(RegisterResponse { token: "t".to_string() })),
+            (Request::PublishKeyPackage(PublishKeyPackageRequest { key_package: key_package.clone() }), json!({})),
+            (
+                Request::FetchKeyPackage(FetchKeyPackageRequest { user: user(), local_only: true }),
+                json!(FetchKeyPackageResponse { key_package }),
+            ),
+            (
+                Request::SendMessage(SendMessageRequest {
+                    sender: "Alice".to_string(),
+                    group_id: "g".to_string(),
+                    recipients: vec![user()],
+                    payload: b"mls".to_vec(),
+                }),
+                json!(SendMessageResponse { message_id: "m".to_string(), seq: 7 }),
+            ),
+            (Request::FetchInbox(FetchInboxRequest { user: user() }), envelopes.clone()),
+            (Request::FetchRange(FetchRangeRequest { user: user(), group_id: "g".to_string(), from_seq: 3 }), envelopes),
+            (Request::Heartbeat(HeartbeatRequest { user: user() }), json!(HeartbeatResponse {})),
+            (
+                Request::FetchPresence(FetchPresenceRequest { users: vec![user()] }),
+                json!(FetchPresenceResponse { presence: vec![Presence { user: user(), online: true, last_seen: Some(Utc::now()) }] }),
+            ),
+            (Request::Subscribe(SubscribeRequest { user: user() }), json!(envelope())),
+            (
+                Request::Forward(ForwardRequest {
+                    origin: "b.example".to_string(),
+                    envelope: AddressedEnvelope { recipients: vec![user()], envelope: envelope() },
+                    signature: "00".to_string(),
+                }),
+                json!(ForwardResponse {}),
+            ),
+            (
+                Request::ListUsers(ListUsersRequest {}),
+                json!(ListUsersResponse {
+                    users: vec![UserSummary {
+                        user: user(),
+                        registered: true,
+                        key_package_published_at: Some(Utc::now()),
+                        last_seen: Some(Utc::now()),
+                        online: false,
+                    }],
+                }),
+            ),
+            (
+                Request::ListGroups(ListGroupsRequest {}),
+                json!(ListGroupsResponse {
+                    groups: vec![GroupSummary { group_id: "g".to_string(), messages: 2, members: vec![user()], last_message_at: Utc::now() }],
+                }),
+            ),
+            (
+                Request::PurgeKeyPackages(PurgeKeyPackagesRequest { older_than_seconds: 60 }),
+                json!(PurgeKeyPackagesResponse { users: vec![user()] }),
+            ),
+            (Request::RevokeToken(RevokeTokenRequest { user: user() }), json!(RevokeTokenResponse {})),
+            (
+                Request::QueueDepths(QueueDepthsRequest {}),
+                json!(QueueDepthsResponse { queues: vec![QueueDepth { user: user(), depth: 1 }] }),
+            ),
+        ];
+
+        let branches = schema["$defs"]["Call"]["oneOf"].as_array().unwrap();
+        assert_eq!(branches.len(), calls.len(), "the schema and Request list different methods");
+        for (request, result) in calls {
+            let method = request.method();
+            let call = json!(Call { request, token: Some("t".to_string()), namespace: "ns".to_string() });
+            check_fields(&schema["$defs"]["Call"], &call, "Call");
+            let branch = branches.iter()
+                .find(|branch| branch["properties"]["method"]["const"] == method)
+                .unwrap_or_else(|| panic!("the schema has no {} call", method));
+            check_fields(resolve(&schema, &branch["properties"]["params"]), &call["params"], method);
+            check_fields(resolve(&schema, &branch["x-result"]), &result, method);
+        }
+
+        let error = json!(Reply::Error(RpcError::new(StatusCode::ResourceExhausted, "slow down")));
+        let codes = schema["$defs"]["StatusCode"]["enum"].as_array().unwrap();
+        assert!(codes.contains(&error["error"]["code"]));
+    }
+}
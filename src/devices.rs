@@ -0,0 +1,349 @@
+//! Syncing state between devices of the same identity
+//!
+//! A device snapshot carries the identity key, every group with its MLS
+//! state, message history, and receipts. Snapshots are sealed with a key
+//! derived from a passphrase both devices know, and travel either as a file
+//! (`device export`/`device import`) or directly over TCP (`device listen`
+//! on one device, `device pair` on the other, which swaps snapshots both
+//! ways). The key is PBKDF2 of the passphrase under a fresh salt that travels
+//! with each snapshot, as the store key is (see `session`), so a captured
+//! snapshot costs as many HMACs a guess to attack offline; export files are
+//! readable by their owner only.
+//!
+//! Merging is conflict-free: groups are matched by ID, the newer epoch's MLS
+//! state wins, histories are the union of both by message ID in timestamp
+//! order, receipts keep the furthest status, and message tags are combined.
+//! Merging the same snapshot twice, or in either direction, gives the same
+//! result.
+
+use anyhow::{anyhow, Context, Result};
+use colored::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs,
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    path::Path,
+};
+
+use crate::app::MlsChatApp;
+use crate::crypto;
+use crate::model::{ChatGroup, ChatMessage, MockKey, StoreKdf, UserName};
+use crate::progress;
+use crate::prompt;
+use crate::session::{new_kdf, STORE_ITERATIONS};
+use crate::storage::rewrite_private;
+
+/// Default address for `device listen`
+pub const DEFAULT_DEVICE_ADDR: &str = "0.0.0.0:7890";
+
+/// Most PBKDF2 iterations a snapshot may ask for, so a hostile one cannot tie up the device opening it
+const MAX_SNAPSHOT_ITERATIONS: u32 = STORE_ITERATIONS * 10;
+
+/// Everything one device knows, as handed to another device of the same identity
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceSnapshot {
+    pub user: UserName,
+    pub key: MockKey,
+    pub groups: Vec<SnapshotGroup>,
+}
+
+/// A group together with its message history
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotGroup {
+    pub group: ChatGroup,
+    pub messages: Vec<ChatMessage>,
+}
+
+/// A snapshot as it travels: sealed, with how its key comes from the passphrase
+#[derive(Debug, Serialize, Deserialize)]
+struct SealedSnapshot {
+    kdf: StoreKdf,
+    sealed: String, // Base64
+}
+
+/// One line of the direct sync exchange
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum DeviceLine {
+    Snapshot(SealedSnapshot),
+    Error(String),
+}
+
+/// What merging a snapshot changed
+#[derive(Debug, Default)]
+struct MergeSummary {
+    adopted_identity: bool,
+    new_groups: usize,
+    advanced_groups: usize, // Groups moved to a newer epoch
+    new_messages: usize,
+}
+
+impl MlsChatApp {
+    /// Write this device's snapshot to `path`, sealed with `passphrase`, or one read from stdin
+    pub fn export_snapshot(&mut self, path: &Path, passphrase: Option<&str>) -> Result<()> {
+        let passphrase = match passphrase {
+            Some(passphrase) => passphrase.to_string(),
+            None => prompt::new_passphrase("Passphrase sealing the snapshot")?,
+        };
+        let sealed = serde_json::to_string(&self.sealed_snapshot(&passphrase)?)?;
+        rewrite_private(path, sealed).with_context(|| format!("Failed to write {}", path.display()))?;
+        println!("✅ Device snapshot written to {}", path.display());
+        println!("   {}", "It holds the identity key; keep it as safe as the profile".yellow());
+        Ok(())
+    }
+
+    /// Merge the snapshot at `path` into this profile
+    ///
+    /// An empty profile takes on the snapshot's identity, linking it as a new device.
+    pub fn import_snapshot(&mut self, path: &Path, passphrase: Option<&str>) -> Result<()> {
+        let sealed = fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        let sealed: SealedSnapshot = serde_json::from_str(&sealed).context("Malformed device snapshot")?;
+        let passphrase = shared_passphrase(passphrase)?;
+        let snapshot = progress::spin("Decrypting the snapshot", || open_snapshot(&sealed, &passphrase))?;
+        self.merge_snapshot(snapshot)
+    }
+
+    /// Wait for one `device pair` connection and swap snapshots with it
+    pub fn listen_for_device(&mut self, listener: TcpListener, passphrase: Option<&str>) -> Result<()> {
+        let passphrase = &shared_passphrase(passphrase)?;
+        println!("{}", format!("Waiting for another device on {}", listener.local_addr()?).green());
+        let (stream, peer) = listener.accept()?;
+        println!("   Device connected from {}", peer);
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut writer = stream;
+
+        let theirs = match progress::spin("Waiting for the other device", || read_line(&mut reader))? {
+            DeviceLine::Snapshot(sealed) => progress::spin("Decrypting the snapshot", || open_snapshot(&sealed, passphrase)),
+            DeviceLine::Error(err) => Err(anyhow!("Other device failed: {}", err)),
+        };
+        let theirs = match theirs {
+            Ok(snapshot) => snapshot,
+            Err(err) => {
+                write_line(&mut writer, &DeviceLine::Error(format!("{:#}", err)))?;
+                return Err(err);
+            }
+        };
+        // Send our state from before the merge; merging is symmetric, so both sides converge
+        write_line(&mut writer, &DeviceLine::Snapshot(self.sealed_snapshot(passphrase)?))?;
+        self.merge_snapshot(theirs)
+    }
+
+    /// Connect to a device running `device listen` at `addr` and swap snapshots with it
+    pub fn pair_with_device(&mut self, addr: &str, passphrase: Option<&str>) -> Result<()> {
+        let passphrase = &shared_passphrase(passphrase)?;
+        let stream = TcpStream::connect(addr).with_context(|| format!("Failed to connect to device at {}", addr))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut writer = stream;
+
+        write_line(&mut writer, &DeviceLine::Snapshot(self.sealed_snapshot(passphrase)?))?;
+        match progress::spin("Waiting for the other device", || read_line(&mut reader))? {
+            DeviceLine::Snapshot(sealed) => {
+                let snapshot = progress::spin("Decrypting the snapshot", || open_snapshot(&sealed, passphrase))?;
+                self.merge_snapshot(snapshot)
+            }
+            DeviceLine::Error(err) => Err(anyhow!("Other device refused the sync: {}", err)),
+        }
+    }
+
+    /// This device's snapshot, sealed under a fresh salt
+    fn sealed_snapshot(&mut self, passphrase: &str) -> Result<SealedSnapshot> {
+        let snapshot = self.snapshot()?;
+        let plaintext = serde_json::to_vec(&snapshot)?;
+        let kdf = new_kdf();
+        let sealed = progress::spin("Encrypting the snapshot", || -> Result<String> {
+            Ok(crypto::base64::encode(&crypto::seal(&snapshot_key(passphrase, &kdf)?, &plaintext)))
+        })?;
+        Ok(SealedSnapshot { kdf, sealed })
+    }
+
+    fn snapshot(&mut self) -> Result<DeviceSnapshot> {
+        let user = self.current_user.context("No user initialized")?;
+        let mut key = self.user_keys.get(&user.to_string()).cloned()
+            .with_context(|| format!("User '{}' not initialized", user))?;
+        // Sealed under this profile's passphrase; the receiving profile seals it under its own
+        key.sealed_private_key = None;
+
+        let mut names: Vec<String> = self.groups.keys().cloned().collect();
+        names.sort();
+        let mut groups = Vec::new();
+        let mut bar = progress::Bar::new("groups read", names.len());
+        for name in names {
+            let group = bar.step(|| self.load_messages(&name).cloned())?;
+            groups.push(SnapshotGroup {
+                messages: group.messages.clone().unwrap_or_default(),
+                group,
+            });
+        }
+        Ok(DeviceSnapshot { user, key, groups })
+    }
+
+    /// Fold another device's snapshot into this profile and save
+    fn merge_snapshot(&mut self, snapshot: DeviceSnapshot) -> Result<()> {
+        let mut summary = MergeSummary::default();
+        match self.current_user {
+            None => {
+                self.user_keys.insert(snapshot.user.to_string(), snapshot.key.clone());
+                self.current_user = Some(snapshot.user);
+                self.identity_dirty = true;
+                summary.adopted_identity = true;
+            }
+            Some(user) if user != snapshot.user => {
+                return Err(anyhow!("This profile is {}, but the snapshot is from {}", user, snapshot.user));
+            }
+            Some(user) => {
+                let key = self.user_keys.get(&user.to_string()).context("No key for the current user")?;
+                if key.id != snapshot.key.id {
+                    return Err(anyhow!(
+                        "The other device has a different identity key for {}; link devices by importing a snapshot into an empty profile",
+                        user
+                    ));
+                }
+            }
+        }
+
+        for incoming in snapshot.groups {
+            self.merge_group(incoming, &mut summary)?;
+        }
+        self.save_state()?;
+
+        if summary.adopted_identity {
+            println!("✅ Linked this device as '{}'", snapshot.user);
+        }
+        println!(
+            "✅ Merged device snapshot: {} new group(s), {} advanced to a newer epoch, {} new message(s)",
+            summary.new_groups, summary.advanced_groups, summary.new_messages
+        );
+        Ok(())
+    }
+
+    fn merge_group(&mut self, incoming: SnapshotGroup, summary: &mut MergeSummary) -> Result<()> {
+        let SnapshotGroup { group: mut theirs, messages } = incoming;
+        let Some(name) = self.group_name_by_id(&theirs.group_id) else {
+            if self.groups.contains_key(&theirs.name) {
+                theirs.name = format!("{} ({})", theirs.name, &theirs.group_id[..8.min(theirs.group_id.len())]);
+            }
+            self.storage.replace_messages(&theirs.group_id, &messages)?;
+            theirs.message_count = messages.len();
+            theirs.messages = Some(messages);
+            summary.new_groups += 1;
+            summary.new_messages += theirs.message_count;
+            self.dirty_groups.insert(theirs.name.clone());
+            self.groups.insert(theirs.name.clone(), theirs);
+            return Ok(());
+        };
+
+        self.load_messages(&name)?;
+        let ours = self.groups.get_mut(&name).context("Group not found")?;
+        if theirs.mls_group.epoch > ours.mls_group.epoch {
+            ours.mls_group = theirs.mls_group;
+            ours.members = theirs.members;
+            summary.advanced_groups += 1;
+        }
+        ours.last_seq = ours.last_seq.max(theirs.last_seq);
+        for (message_id, statuses) in theirs.receipts {
+            let merged = ours.receipts.entry(message_id).or_default();
+            for (member, status) in statuses {
+                let current = merged.entry(member).or_insert(status);
+                *current = (*current).max(status);
+            }
+        }
+        for (message_id, tags) in theirs.tags {
+            ours.tags.entry(message_id).or_default().extend(tags);
+        }
+
+        let mut history: HashMap<String, ChatMessage> = ours.messages.take().unwrap_or_default()
+            .into_iter()
+            .map(|message| (message.id.clone(), message))
+            .collect();
+        let before = history.len();
+        for message in messages {
+            history.entry(message.id.clone()).or_insert(message);
+        }
+        let mut history: Vec<ChatMessage> = history.into_values().collect();
+        history.sort_by(|a, b| a.timestamp.cmp(&b.timestamp).then_with(|| a.id.cmp(&b.id)));
+        if history.len() > before {
+            self.storage.replace_messages(&ours.group_id, &history)?;
+            summary.new_messages += history.len() - before;
+        }
+        ours.message_count = history.len();
+        ours.messages = Some(history);
+        self.dirty_groups.insert(name);
+        Ok(())
+    }
+}
+
+/// The passphrase both devices know, as given or read from stdin
+fn shared_passphrase(passphrase: Option<&str>) -> Result<String> {
+    match passphrase {
+        Some(passphrase) => Ok(passphrase.to_string()),
+        None => prompt::passphrase("Passphrase shared with the other device"),
+    }
+}
+
+/// Key sealing one snapshot, shared by devices that know the passphrase
+fn snapshot_key(passphrase: &str, kdf: &StoreKdf) -> Result<[u8; 32]> {
+    if kdf.iterations > MAX_SNAPSHOT_ITERATIONS {
+        return Err(anyhow!("The device snapshot asks for {} PBKDF2 iterations, more than the {} allowed", kdf.iterations, MAX_SNAPSHOT_ITERATIONS));
+    }
+    let salt = crypto::base64::decode(&kdf.salt).context("Malformed device snapshot salt")?;
+    Ok(crypto::pbkdf2_sha256(passphrase.as_bytes(), &salt, kdf.iterations))
+}
+
+fn open_snapshot(sealed: &SealedSnapshot, passphrase: &str) -> Result<DeviceSnapshot> {
+    let key = snapshot_key(passphrase, &sealed.kdf)?;
+    let sealed = crypto::base64::decode(&sealed.sealed).context("Malformed device snapshot")?;
+    let plaintext = crypto::open(&key, &sealed)
+        .context("Could not open the device snapshot; is the passphrase the same on both devices?")?;
+    serde_json::from_slice(&plaintext).context("Malformed device snapshot")
+}
+
+fn write_line(writer: &mut TcpStream, line: &DeviceLine) -> Result<()> {
+    let mut json = serde_json::to_string(line)?;
+    json.push('\n');
+    writer.write_all(json.as_bytes())?;
+    writer.flush()?;
+    Ok(())
+}
+
+fn read_line(reader: &mut impl BufRead) -> Result<DeviceLine> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Err(anyhow!("Other device closed the connection"));
+    }
+    serde_json::from_str(&line).context("Malformed message from other device")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    fn sealed(passphrase: &str, kdf: StoreKdf) -> SealedSnapshot {
+        let mut app = MlsChatApp::with_storage(Box::new(MemoryStorage::new()));
+        app.init_user(UserName::Alice).unwrap();
+        let plaintext = serde_json::to_vec(&app.snapshot().unwrap()).unwrap();
+        let sealed = crypto::base64::encode(&crypto::seal(&snapshot_key(passphrase, &kdf).unwrap(), &plaintext));
+        SealedSnapshot { kdf, sealed }
+    }
+
+    #[test]
+    fn snapshots_open_only_under_their_passphrase_and_salt() {
+        let kdf = StoreKdf { salt: crypto::base64::encode(b"0123456789abcdef"), iterations: 1000 };
+        let snapshot = sealed("correct horse", kdf.clone());
+        assert_eq!(open_snapshot(&snapshot, "correct horse").unwrap().user, UserName::Alice);
+        assert!(open_snapshot(&snapshot, "battery staple").is_err());
+
+        let resalted = SealedSnapshot { kdf: StoreKdf { salt: crypto::base64::encode(b"fedcba9876543210"), ..kdf }, sealed: snapshot.sealed };
+        assert!(open_snapshot(&resalted, "correct horse").is_err());
+    }
+
+    #[test]
+    fn snapshots_cannot_demand_unbounded_iterations() {
+        let kdf = StoreKdf { salt: crypto::base64::encode(b"0123456789abcdef"), iterations: u32::MAX };
+        let err = snapshot_key("correct horse", &kdf).unwrap_err();
+        assert!(format!("{:#}", err).contains("PBKDF2 iterations"));
+    }
+}
@@ -0,0 +1,407 @@
+//! `doctor`: find what is wrong with a profile and its delivery service
+//!
+//! Every check reads and nothing is changed, so `doctor` also runs on a
+//! profile other commands refuse to open. The state files are parsed here
+//! directly rather than through `FileStorage`, which would migrate older
+//! layouts as it read them. Each finding says how to fix it.
+//!
+//! Key packages carry no expiry in this protocol, so the key package check
+//! is whether the published one still matches the identity's key.
+
+use anyhow::{anyhow, Result};
+use chrono::{TimeDelta, Utc};
+use serde::Serialize;
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+use crate::model::{ChatGroup, ChatMessage, MockKey, UserName};
+use crate::output;
+use crate::preview::human_size;
+use crate::storage;
+use crate::transport::Transport;
+
+/// Clock difference from the delivery service worth a warning
+const MAX_SKEW: TimeDelta = TimeDelta::seconds(60);
+
+/// File names crashed or interrupted runs and editors leave behind
+const LEFTOVER_SUFFIXES: &[&str] = &[".lock", ".tmp", ".swp", "~"];
+
+/// How serious a finding is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Ok,
+    Warning, // Works for now, but worth fixing
+    Problem, // Something is broken or will fail
+}
+
+/// The outcome of one check
+#[derive(Debug, Clone, Serialize)]
+pub struct Finding {
+    pub check: &'static str,
+    pub severity: Severity,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fix: Option<String>,
+}
+
+/// Everything `doctor` found, in the order it checked
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Report {
+    pub findings: Vec<Finding>,
+}
+
+impl Report {
+    fn ok(&mut self, check: &'static str, message: impl Into<String>) {
+        self.findings.push(Finding { check, severity: Severity::Ok, message: message.into(), fix: None });
+    }
+
+    fn warn(&mut self, check: &'static str, message: impl Into<String>, fix: impl Into<String>) {
+        self.findings.push(Finding { check, severity: Severity::Warning, message: message.into(), fix: Some(fix.into()) });
+    }
+
+    fn problem(&mut self, check: &'static str, message: impl Into<String>, fix: impl Into<String>) {
+        self.findings.push(Finding { check, severity: Severity::Problem, message: message.into(), fix: Some(fix.into()) });
+    }
+
+    /// How many findings are problems rather than warnings
+    pub fn problems(&self) -> usize {
+        self.findings.iter().filter(|finding| finding.severity == Severity::Problem).count()
+    }
+
+    /// Print each finding with its fix
+    pub fn print(&self) {
+        for finding in &self.findings {
+            let mark = match (finding.severity, output::is_plain()) {
+                (Severity::Ok, false) => "✅",
+                (Severity::Warning, false) => "⚠️ ",
+                (Severity::Problem, false) => "❌",
+                (Severity::Ok, true) => "OK:",
+                (Severity::Warning, true) => "Warning:",
+                (Severity::Problem, true) => "Problem:",
+            };
+            println!("{} {}: {}", mark, finding.check, finding.message);
+            if let Some(fix) = &finding.fix {
+                println!("   Fix: {}", fix);
+            }
+        }
+        let warnings = self.findings.iter().filter(|finding| finding.severity == Severity::Warning).count();
+        println!();
+        match (self.problems(), warnings) {
+            (0, 0) => println!("Everything looks fine."),
+            (problems, warnings) => println!("{} problem(s), {} warning(s).", problems, warnings),
+        }
+    }
+
+    /// An error if any finding is a problem, so `doctor` exits non-zero
+    pub fn into_result(self) -> Result<()> {
+        match self.problems() {
+            0 => Ok(()),
+            problems => Err(anyhow!("doctor found {} problem(s)", problems)),
+        }
+    }
+}
+
+/// Check the profile in `data_dir`, and the delivery service if there is one
+///
+/// `account` names the delivery service as `login` saves tokens for it, and
+/// is None when not networked; `connect` reaches it with the saved token.
+pub fn diagnose(
+    data_dir: &Path,
+    account: Option<&str>,
+    connect: impl FnOnce(Option<String>) -> Result<Box<dyn Transport>>,
+) -> Report {
+    let mut report = Report::default();
+    if !data_dir.is_dir() {
+        report.problem("profile", format!("No profile at {}", data_dir.display()), "Run `init` to create one, or pass the right --data-dir");
+        return report;
+    }
+    check_layout(data_dir, &mut report);
+    let group_ids = check_groups(data_dir, &mut report);
+    check_message_logs(data_dir, &group_ids, &mut report);
+    check_leftovers(data_dir, &mut report);
+    check_erasure(data_dir, &mut report);
+    let identity = check_identity(data_dir, &mut report);
+    let Some(account) = account else {
+        report.ok("server", "Not networked; pass --server to check the delivery service");
+        return report;
+    };
+    let token = identity.as_ref().and_then(|(user, _)| saved_token(data_dir, account, &user.to_string()));
+    match connect(token) {
+        Err(err) => report.problem(
+            "server",
+            format!("Could not connect: {:#}", err),
+            "Check the --server address and that the delivery service is running; behind a firewall, try --proxy",
+        ),
+        Ok(mut transport) => check_server(transport.as_mut(), identity.as_ref(), &mut report),
+    }
+    report
+}
+
+/// Files only older versions wrote, which the next command migrates
+fn check_layout(data_dir: &Path, report: &mut Report) {
+    let legacy: Vec<&str> = ["app_state.json", "user_keys.json"].into_iter()
+        .filter(|name| data_dir.join(name).exists())
+        .collect();
+    match legacy.is_empty() {
+        true => report.ok("layout", "Profile uses the current file layout"),
+        false => report.warn(
+            "layout",
+            format!("Written by an older version: {}", legacy.join(", ")),
+            "Run any command, such as `groups`, to migrate it; back the profile up first",
+        ),
+    }
+}
+
+/// Parse every group file; returns the IDs of the groups that parsed
+fn check_groups(data_dir: &Path, report: &mut Report) -> HashSet<String> {
+    let mut ids = HashSet::new();
+    let mut inline = Vec::new();
+    let mut broken = 0;
+    for path in files(&data_dir.join("groups"), "json") {
+        let group = fs::read_to_string(&path).map_err(anyhow::Error::from)
+            .and_then(|data| Ok((serde_json::from_str::<serde_json::Value>(&data)?, serde_json::from_str::<ChatGroup>(&data)?)));
+        let (value, group) = match group {
+            Ok(parsed) => parsed,
+            Err(err) => {
+                broken += 1;
+                report.problem(
+                    "groups",
+                    format!("{} cannot be read: {:#}", path.display(), err),
+                    "Restore the profile from another device with `device import`, or move the file aside to drop the group",
+                );
+                continue;
+            }
+        };
+        if value.get("messages").is_some() {
+            inline.push(group.name.clone());
+        }
+        if path.file_stem().and_then(|stem| stem.to_str()) != Some(group.group_id.as_str()) {
+            report.warn(
+                "groups",
+                format!("{} holds group '{}' ({}), not the group its name says", path.display(), group.name, group.group_id),
+                format!("Rename it to {}.json", group.group_id),
+            );
+        }
+        if group.message_count > 0 && !message_log(data_dir, &group.group_id).exists() {
+            report.problem(
+                "groups",
+                format!("The history of '{}' is missing ({} message(s) expected)", group.name, group.message_count),
+                "Restore it from another device with `device import`; new messages are kept either way",
+            );
+        }
+        ids.insert(group.group_id);
+    }
+    if !inline.is_empty() {
+        report.warn(
+            "layout",
+            format!("Group(s) with messages stored inline by an older version: {}", inline.join(", ")),
+            "Run any command, such as `groups`, to move them into messages/",
+        );
+    }
+    if broken == 0 {
+        report.ok("groups", format!("{} group file(s) read", ids.len()));
+    }
+    ids
+}
+
+/// Parse every message log, and find logs (with their attachments) left by groups that are gone
+fn check_message_logs(data_dir: &Path, group_ids: &HashSet<String>, report: &mut Report) {
+    let mut messages = 0;
+    let mut broken = 0;
+    for path in files(&data_dir.join("messages"), "jsonl") {
+        let data = match fs::read_to_string(&path) {
+            Ok(data) => data,
+            Err(err) => {
+                broken += 1;
+                report.problem("messages", format!("{} cannot be read: {}", path.display(), err), "Check the file's permissions");
+                continue;
+            }
+        };
+        let mut parsed = Vec::new();
+        for (number, line) in data.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()) {
+            match serde_json::from_str::<ChatMessage>(line) {
+                Ok(message) => parsed.push(message),
+                Err(err) => {
+                    broken += 1;
+                    report.problem(
+                        "messages",
+                        format!("{} line {} is damaged: {}", path.display(), number + 1, err),
+                        format!("Delete line {} to keep the rest of the history, or restore it with `device import`", number + 1),
+                    );
+                }
+            }
+        }
+        messages += parsed.len();
+        let group_id = path.file_stem().and_then(|stem| stem.to_str()).unwrap_or_default();
+        if !group_ids.contains(group_id) {
+            let attachments: Vec<&ChatMessage> = parsed.iter().filter(|message| message.attachment.is_some()).collect();
+            let bytes: u64 = attachments.iter().filter_map(|message| message.attachment.as_ref()).map(|attachment| attachment.size).sum();
+            report.warn(
+                "attachments",
+                format!(
+                    "{} belongs to no group: {} message(s), {} attachment(s) ({})",
+                    path.display(), parsed.len(), attachments.len(), human_size(bytes)
+                ),
+                "If the group was deleted, `mls-chat gc` removes the log and its attachments".to_string(),
+            );
+        }
+    }
+    if broken == 0 {
+        report.ok("messages", format!("{} stored message(s) read", messages));
+    }
+}
+
+/// Lock, temporary, and backup files an interrupted run or an editor left in the profile
+fn check_leftovers(data_dir: &Path, report: &mut Report) {
+    let mut leftovers = Vec::new();
+    for dir in [data_dir.to_path_buf(), data_dir.join("groups"), data_dir.join("messages"), data_dir.join("identities")] {
+        let Ok(entries) = fs::read_dir(&dir) else { continue };
+        for path in entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()) {
+            let name = path.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+            if LEFTOVER_SUFFIXES.iter().any(|suffix| name.ends_with(suffix)) {
+                leftovers.push(path);
+            }
+        }
+    }
+    if leftovers.is_empty() {
+        report.ok("leftovers", "No lock or temporary files left behind");
+    }
+    for path in leftovers {
+        report.warn(
+            "leftovers",
+            format!("{} was left behind", path.display()),
+            format!("If no other mls-chat is using this profile, remove it: rm {}", path.display()),
+        );
+    }
+}
+
+/// Whether overwriting removed data, as deleting groups and pruning messages do, can erase it here
+fn check_erasure(data_dir: &Path, report: &mut Report) {
+    match storage::erasure_caveat(data_dir) {
+        None => report.ok("secure delete", "Removed data is overwritten before it is deleted"),
+        Some(caveat) => report.warn(
+            "secure delete",
+            caveat,
+            "If removed messages and keys must not be recoverable, keep the profile on a file system that writes in place, such as ext4, or on an encrypted volume",
+        ),
+    }
+}
+
+/// The active identity and its key, if both can be read
+fn check_identity(data_dir: &Path, report: &mut Report) -> Option<(UserName, MockKey)> {
+    let current = data_dir.join("current_user.json");
+    if !current.exists() {
+        report.warn("identity", "No active identity", "Run `init` to create one");
+        return None;
+    }
+    let user: UserName = match fs::read_to_string(&current).map_err(anyhow::Error::from).and_then(|data| Ok(serde_json::from_str(&data)?)) {
+        Ok(user) => user,
+        Err(err) => {
+            report.problem("identity", format!("{} cannot be read: {:#}", current.display(), err), "Pick the identity again with `identity use <user>`");
+            return None;
+        }
+    };
+    let key_file = data_dir.join("identities").join(format!("{}.json", user));
+    let legacy = data_dir.join("user_keys.json");
+    let key = match fs::read_to_string(&key_file) {
+        Ok(data) => serde_json::from_str::<MockKey>(&data).map_err(anyhow::Error::from),
+        Err(_) if legacy.exists() => fs::read_to_string(&legacy).map_err(anyhow::Error::from)
+            .and_then(|data| Ok(serde_json::from_str::<HashMap<String, MockKey>>(&data)?))
+            .and_then(|keys| keys.get(&user.to_string()).cloned().ok_or_else(|| anyhow!("no key for {}", user))),
+        Err(err) => Err(err.into()),
+    };
+    match key {
+        Ok(key) => {
+            report.ok("identity", format!("Active identity {} has its key", user));
+            Some((user, key))
+        }
+        Err(err) => {
+            report.problem(
+                "identity",
+                format!("The key of {} cannot be read ({}): {:#}", user, key_file.display(), err),
+                "Restore it from another device with `device import`; without it this identity cannot read or send",
+            );
+            None
+        }
+    }
+}
+
+/// Reachability, clock skew, and the published key package
+fn check_server(transport: &mut dyn Transport, identity: Option<&(UserName, MockKey)>, report: &mut Report) {
+    let Some((user, key)) = identity else {
+        report.warn("server", "Not checked without an active identity", "Run `init`, then `doctor` again");
+        return;
+    };
+    let user = user.to_string();
+    let sent = Utc::now();
+    let started = Instant::now();
+    if let Err(err) = transport.heartbeat(&user) {
+        report.problem(
+            "server",
+            format!("The delivery service did not answer: {:#}", err),
+            "Check that it is running and reachable; if it needs a token, run `login`",
+        );
+        return;
+    }
+    let elapsed = started.elapsed();
+    report.ok("server", format!("Delivery service answered in {} ms", elapsed.as_millis()));
+
+    // The service stamps the heartbeat with its own clock; compare it to ours halfway through the call
+    let midpoint = sent + TimeDelta::from_std(elapsed / 2).unwrap_or_default();
+    match transport.fetch_presence(std::slice::from_ref(&user)) {
+        Ok(presence) => match presence.iter().find(|presence| presence.user == user).and_then(|presence| presence.last_seen) {
+            Some(seen) => {
+                let skew = seen - midpoint;
+                let seconds = skew.num_milliseconds() as f64 / 1000.0;
+                match skew.abs() > MAX_SKEW {
+                    true => report.warn(
+                        "clock",
+                        format!("This clock is {:.0}s {} the delivery service's", seconds.abs(), if seconds < 0.0 { "ahead of" } else { "behind" }),
+                        "Turn on time synchronisation, e.g. `timedatectl set-ntp true`; message times and `send --at` depend on it",
+                    ),
+                    false => report.ok("clock", format!("Within {:.1}s of the delivery service", seconds.abs())),
+                }
+            }
+            None => report.ok("clock", "Not checked: the delivery service reported no time for this user"),
+        },
+        Err(_) => report.ok("clock", "Not checked: this transport does not report the service's time"),
+    }
+
+    match transport.fetch_key_package(&user) {
+        Ok(published) if published.key_id == key.id => report.ok("key package", "Published and matches the identity key"),
+        Ok(_) => report.problem(
+            "key package",
+            "The published key package is for an older key, so new groups would add a key you no longer have",
+            "Run `publish`",
+        ),
+        Err(err) => report.problem("key package", format!("Not available: {:#}", err), "Run `publish` so others can add you to groups"),
+    }
+}
+
+/// The token `login` saved for `user` on `account`, if any
+fn saved_token(data_dir: &Path, account: &str, user: &str) -> Option<String> {
+    let data = fs::read_to_string(data_dir.join("server_tokens.json")).ok()?;
+    let mut tokens: HashMap<String, HashMap<String, String>> = serde_json::from_str(&data).ok()?;
+    tokens.get_mut(account)?.remove(user)
+}
+
+fn message_log(data_dir: &Path, group_id: &str) -> PathBuf {
+    data_dir.join("messages").join(format!("{}.jsonl", group_id))
+}
+
+/// Files in `dir` with extension `extension`, sorted; none if `dir` is missing
+fn files(dir: &Path, extension: &str) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = fs::read_dir(dir).into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().and_then(|ext| ext.to_str()) == Some(extension))
+        .collect();
+    files.sort();
+    files
+}
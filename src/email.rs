@@ -0,0 +1,438 @@
+//! Email transport: MLS messages as MIME attachments over SMTP and IMAP
+//!
+//! A store-and-forward channel with no delivery service at all. Each
+//! outgoing message is mailed to its recipients' addresses, and
+//! `fetch_inbox` polls the IMAP inbox, taking and deleting mls-chat mail.
+//! Key packages are mailed to every contact by `publish`, and stay in the
+//! mailbox so any number of later `add-member` calls can find them.
+//!
+//! Servers are spoken to in plaintext (no STARTTLS), which suits local and
+//! test mail servers; use `--proxy` or a TLS tunnel for anything else.
+//!
+//! Settings come from a JSON file named by `--server email:<path>`:
+//!
+//! ```json
+//! {
+//!   "address": "alice@example.org",
+//!   "smtp_server": "localhost:25",
+//!   "imap_server": "localhost:143",
+//!   "username": "alice",
+//!   "password": "secret",
+//!   "contacts": { "Bob": "bob@example.org" }
+//! }
+//! ```
+
+use anyhow::{anyhow, Context, Result};
+use chrono::Utc;
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    fs,
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpStream,
+    time::Duration,
+};
+use uuid::Uuid;
+
+use crate::crypto::base64;
+use crate::delivery::{AddressedEnvelope, Envelope, KeyPackage, SendMessageRequest, SendMessageResponse};
+use crate::http::{MAX_BODY_LEN, MAX_LINE_LEN};
+use crate::proxy::Socks5Proxy;
+use crate::transport::Transport;
+
+/// Header marking mail that belongs to mls-chat, and what it carries
+const TYPE_HEADER: &str = "X-MLS-Chat-Type";
+/// Header naming whose key package a mail carries
+const USER_HEADER: &str = "X-MLS-Chat-User";
+const IO_TIMEOUT: Duration = Duration::from_secs(30);
+/// Most lines accepted in one multi-line SMTP reply
+const MAX_REPLY_LINES: usize = 100;
+
+/// Mail accounts and addresses used by the email transport
+#[derive(Debug, Clone, Deserialize)]
+pub struct EmailConfig {
+    pub address: String,
+    pub smtp_server: String, // host:port
+    pub imap_server: String, // host:port
+    pub username: String,
+    pub password: String,
+    #[serde(default)]
+    pub contacts: HashMap<String, String>, // User name to email address
+}
+
+impl EmailConfig {
+    /// Read settings from a JSON file
+    pub fn load(path: &str) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read email settings from {}", path))?;
+        serde_json::from_str(&content).with_context(|| format!("Invalid email settings in {}", path))
+    }
+}
+
+/// Transport exchanging MLS messages by email
+pub struct EmailTransport {
+    config: EmailConfig,
+    proxy: Option<Socks5Proxy>,
+}
+
+impl EmailTransport {
+    pub fn new(config: EmailConfig, proxy: Option<Socks5Proxy>) -> Self {
+        Self { config, proxy }
+    }
+
+    fn connect(&self, addr: &str) -> Result<TcpStream> {
+        let stream = match &self.proxy {
+            Some(proxy) => proxy.connect(addr)?,
+            None => TcpStream::connect(addr)?,
+        };
+        stream.set_read_timeout(Some(IO_TIMEOUT))?;
+        Ok(stream)
+    }
+
+    fn address_of(&self, user: &str) -> Result<&str> {
+        self.config.contacts.get(user)
+            .map(String::as_str)
+            .with_context(|| format!("No email address for '{}'; add it to \"contacts\" in the email settings", user))
+    }
+
+    /// Mail `attachment` to `to` as an mls-chat message of `kind`
+    fn send_mail(&self, to: &[&str], kind: &str, extra_headers: &[(&str, &str)], subject: &str, attachment: &[u8]) -> Result<()> {
+        let boundary = format!("mls-chat-{}", Uuid::new_v4().simple());
+        let mut mail = format!(
+            "From: <{}>\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\nMessage-ID: <{}@mls-chat>\r\nMIME-Version: 1.0\r\n{}: {}\r\n",
+            self.config.address,
+            to.iter().map(|addr| format!("<{}>", addr)).collect::<Vec<_>>().join(", "),
+            subject,
+            Utc::now().to_rfc2822(),
+            Uuid::new_v4(),
+            TYPE_HEADER,
+            kind,
+        );
+        for (name, value) in extra_headers {
+            mail.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        mail.push_str(&format!(
+            "Content-Type: multipart/mixed; boundary=\"{boundary}\"\r\n\r\n\
+             --{boundary}\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n\
+             This mail carries an encrypted mls-chat message. Run `mls-chat sync` to read it.\r\n\
+             --{boundary}\r\nContent-Type: application/json\r\nContent-Transfer-Encoding: base64\r\n\
+             Content-Disposition: attachment; filename=\"{kind}.json\"\r\n\r\n"
+        ));
+        let encoded = base64::encode(attachment);
+        for line in encoded.as_bytes().chunks(76) {
+            mail.push_str(std::str::from_utf8(line)?);
+            mail.push_str("\r\n");
+        }
+        mail.push_str(&format!("--{}--\r\n", boundary));
+
+        let mut smtp = Smtp::connect(self.connect(&self.config.smtp_server)
+            .with_context(|| format!("Failed to connect to SMTP server at {}", self.config.smtp_server))?)?;
+        smtp.login(&self.config.username, &self.config.password)?;
+        smtp.send(&self.config.address, to, &mail)?;
+        smtp.quit()
+    }
+
+    fn imap(&self) -> Result<Imap> {
+        let stream = self.connect(&self.config.imap_server)
+            .with_context(|| format!("Failed to connect to IMAP server at {}", self.config.imap_server))?;
+        let mut imap = Imap::connect(stream)?;
+        imap.command(&format!("LOGIN {} {}", quote(&self.config.username), quote(&self.config.password)))
+            .context("IMAP login failed")?;
+        imap.command("SELECT INBOX")?;
+        Ok(imap)
+    }
+}
+
+impl Transport for EmailTransport {
+    fn publish_key_package(&mut self, key_package: KeyPackage) -> Result<()> {
+        let to: Vec<&str> = self.config.contacts.iter()
+            .filter(|(user, _)| **user != key_package.user)
+            .map(|(_, addr)| addr.as_str())
+            .collect();
+        if to.is_empty() {
+            return Err(anyhow!("No contacts to send the key package to; add them to \"contacts\" in the email settings"));
+        }
+        let subject = format!("mls-chat key package for {}", key_package.user);
+        self.send_mail(&to, "key-package", &[(USER_HEADER, &key_package.user)], &subject, &serde_json::to_vec(&key_package)?)
+    }
+
+    fn fetch_key_package(&mut self, user: &str) -> Result<KeyPackage> {
+        let mut imap = self.imap()?;
+        let uids = imap.search(&format!("HEADER {} key-package HEADER {} {}", TYPE_HEADER, USER_HEADER, quote(user)))?;
+        // The newest key package wins
+        let uid = uids.into_iter().max()
+            .with_context(|| format!("No key package from '{}' in the mailbox; ask them to run `publish`", user))?;
+        let mail = imap.fetch(uid)?;
+        imap.logout()?;
+        serde_json::from_slice(&attachment(&mail)?).context("Malformed key package mail")
+    }
+
+    fn send_message(&mut self, request: SendMessageRequest) -> Result<SendMessageResponse> {
+        let to = request.recipients.iter()
+            .map(|recipient| self.address_of(recipient))
+            .collect::<Result<Vec<_>>>()?;
+        let subject = format!("mls-chat message from {}", request.sender);
+        let message = AddressedEnvelope::new(request);
+        self.send_mail(&to, "message", &[], &subject, &serde_json::to_vec(&message)?)?;
+        Ok(SendMessageResponse {
+            message_id: message.envelope.message_id,
+            seq: 0,
+        })
+    }
+
+    fn fetch_inbox(&mut self, user: &str) -> Result<Vec<Envelope>> {
+        let mut imap = self.imap()?;
+        let mut uids = imap.search(&format!("HEADER {} message", TYPE_HEADER))?;
+        uids.sort_unstable();
+
+        let mut envelopes = Vec::new();
+        for uid in uids {
+            let mail = imap.fetch(uid)?;
+            match attachment(&mail).and_then(|data| Ok(serde_json::from_slice::<AddressedEnvelope>(&data)?)) {
+                Ok(message) if message.is_for(user) => envelopes.push(message.envelope),
+                Ok(_) => continue, // Someone else's, in a shared mailbox
+                Err(err) => eprintln!("⚠️  Ignoring malformed mls-chat mail {}: {}", uid, err),
+            }
+            imap.command(&format!("UID STORE {} +FLAGS.SILENT (\\Deleted)", uid))?;
+        }
+        imap.command("EXPUNGE")?;
+        imap.logout()?;
+        Ok(envelopes)
+    }
+}
+
+/// Extract and decode the base64 attachment from an mls-chat mail
+fn attachment(mail: &[u8]) -> Result<Vec<u8>> {
+    let mail = String::from_utf8_lossy(mail);
+    let start = mail.find("Content-Disposition: attachment")
+        .context("Mail has no attachment")?;
+    let body_start = mail[start..].find("\r\n\r\n")
+        .map(|offset| start + offset + 4)
+        .context("Malformed attachment")?;
+    let body_end = mail[body_start..].find("--")
+        .map(|offset| body_start + offset)
+        .unwrap_or(mail.len());
+    let encoded: String = mail[body_start..body_end].chars().filter(|c| !c.is_whitespace()).collect();
+    base64::decode(&encoded)
+}
+
+/// Quote an IMAP string argument
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// A minimal SMTP client session
+struct Smtp {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    extensions: Vec<String>,
+}
+
+impl Smtp {
+    fn connect(stream: TcpStream) -> Result<Self> {
+        let mut smtp = Self {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+            extensions: Vec::new(),
+        };
+        smtp.expect(220)?;
+        smtp.extensions = smtp.command("EHLO mls-chat", 250)?;
+        Ok(smtp)
+    }
+
+    fn login(&mut self, username: &str, password: &str) -> Result<()> {
+        let supports_plain = self.extensions.iter()
+            .any(|line| line.starts_with("AUTH") && line.split_whitespace().any(|method| method == "PLAIN"));
+        if !supports_plain {
+            return Ok(()); // Local relays often take mail without authentication
+        }
+        let credentials = base64::encode(format!("\0{}\0{}", username, password).as_bytes());
+        self.command(&format!("AUTH PLAIN {}", credentials), 235)
+            .context("SMTP authentication failed")?;
+        Ok(())
+    }
+
+    fn send(&mut self, from: &str, to: &[&str], mail: &str) -> Result<()> {
+        self.command(&format!("MAIL FROM:<{}>", from), 250)?;
+        for recipient in to {
+            self.command(&format!("RCPT TO:<{}>", recipient), 250)
+                .with_context(|| format!("SMTP server rejected recipient {}", recipient))?;
+        }
+        self.command("DATA", 354)?;
+        // Dot-stuff lines that start with a period
+        let mut data = mail.replace("\r\n.", "\r\n..");
+        data.push_str(".\r\n");
+        self.writer.write_all(data.as_bytes())?;
+        self.expect(250)?;
+        Ok(())
+    }
+
+    fn quit(mut self) -> Result<()> {
+        self.command("QUIT", 221)?;
+        Ok(())
+    }
+
+    fn command(&mut self, line: &str, code: u16) -> Result<Vec<String>> {
+        self.writer.write_all(format!("{}\r\n", line).as_bytes())?;
+        self.expect(code)
+    }
+
+    /// Read a possibly multi-line reply, failing unless it has `code`
+    fn expect(&mut self, code: u16) -> Result<Vec<String>> {
+        let mut lines = Vec::new();
+        loop {
+            let mut line = Vec::new();
+            if read_line(&mut self.reader, &mut line)? == 0 {
+                return Err(anyhow!("SMTP server closed the connection"));
+            }
+            if lines.len() == MAX_REPLY_LINES {
+                return Err(anyhow!("SMTP reply longer than {} lines", MAX_REPLY_LINES));
+            }
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end();
+            let reply_code = line.get(..3).and_then(|c| c.parse::<u16>().ok())
+                .with_context(|| format!("Malformed SMTP reply: {}", line))?;
+            lines.push(line.get(4..).unwrap_or_default().to_string());
+            if line.as_bytes().get(3) != Some(&b'-') {
+                if reply_code != code {
+                    return Err(anyhow!("SMTP server replied: {}", line));
+                }
+                return Ok(lines);
+            }
+        }
+    }
+}
+
+/// A minimal IMAP4rev1 client session
+struct Imap {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    next_tag: u32,
+}
+
+impl Imap {
+    fn connect(stream: TcpStream) -> Result<Self> {
+        let mut imap = Self {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+            next_tag: 1,
+        };
+        let mut greeting = Vec::new();
+        read_line(&mut imap.reader, &mut greeting)?;
+        let greeting = String::from_utf8_lossy(&greeting);
+        if !greeting.starts_with("* OK") && !greeting.starts_with("* PREAUTH") {
+            return Err(anyhow!("IMAP server refused the connection: {}", greeting.trim_end()));
+        }
+        Ok(imap)
+    }
+
+    fn search(&mut self, criteria: &str) -> Result<Vec<u32>> {
+        let responses = self.command(&format!("UID SEARCH {}", criteria))?;
+        Ok(responses.iter()
+            .filter_map(|response| String::from_utf8_lossy(response).strip_prefix("* SEARCH").map(str::to_string))
+            .flat_map(|uids| uids.split_whitespace().filter_map(|uid| uid.parse().ok()).collect::<Vec<u32>>())
+            .collect())
+    }
+
+    /// Fetch a whole message without marking it seen
+    fn fetch(&mut self, uid: u32) -> Result<Vec<u8>> {
+        let responses = self.command(&format!("UID FETCH {} BODY.PEEK[]", uid))?;
+        responses.into_iter()
+            .find(|response| response.starts_with(b"* ") && response.windows(5).any(|w| w == b"FETCH"))
+            .and_then(|response| {
+                // The message is the literal: everything after the first "}\r\n"
+                let start = response.windows(3).position(|w| w == b"}\r\n")? + 3;
+                Some(response[start..].to_vec())
+            })
+            .with_context(|| format!("IMAP server returned no message for UID {}", uid))
+    }
+
+    fn logout(mut self) -> Result<()> {
+        self.command("LOGOUT")?;
+        Ok(())
+    }
+
+    /// Run a command, returning its untagged responses with literals inlined
+    fn command(&mut self, command: &str) -> Result<Vec<Vec<u8>>> {
+        let tag = format!("a{}", self.next_tag);
+        self.next_tag += 1;
+        self.writer.write_all(format!("{} {}\r\n", tag, command).as_bytes())?;
+
+        let mut responses = Vec::new();
+        loop {
+            let response = self.read_response()?;
+            if response.starts_with(format!("{} ", tag).as_bytes()) {
+                let status = String::from_utf8_lossy(&response[tag.len() + 1..]).trim_end().to_string();
+                if status.starts_with("OK") {
+                    return Ok(responses);
+                }
+                return Err(anyhow!("IMAP command failed: {}", status));
+            }
+            responses.push(response);
+        }
+    }
+
+    fn read_response(&mut self) -> Result<Vec<u8>> {
+        read_response(&mut self.reader)
+    }
+}
+
+/// Read one line into `line`, refusing lines longer than `MAX_LINE_LEN`; returns the bytes read, 0 at the end
+fn read_line(reader: &mut impl BufRead, line: &mut Vec<u8>) -> Result<usize> {
+    let read = reader.take(MAX_LINE_LEN as u64).read_until(b'\n', line)?;
+    if read == MAX_LINE_LEN && !line.ends_with(b"\n") {
+        return Err(anyhow!("Mail server sent a line longer than {} bytes", MAX_LINE_LEN));
+    }
+    Ok(read)
+}
+
+/// Read one IMAP response line, including any `{n}` literals it carries, up to `MAX_BODY_LEN` bytes in all
+fn read_response(reader: &mut impl BufRead) -> Result<Vec<u8>> {
+    let mut response = Vec::new();
+    loop {
+        let mut line = Vec::new();
+        if read_line(reader, &mut line)? == 0 {
+            return Err(anyhow!("IMAP server closed the connection"));
+        }
+        response.extend(&line);
+        let literal_len = std::str::from_utf8(&line).ok()
+            .and_then(|line| line.trim_end().strip_suffix('}'))
+            .and_then(|line| line.rsplit_once('{'))
+            .and_then(|(_, len)| len.parse::<usize>().ok());
+        if response.len().checked_add(literal_len.unwrap_or(0)).is_none_or(|length| length > MAX_BODY_LEN) {
+            return Err(anyhow!("IMAP response longer than {} bytes", MAX_BODY_LEN));
+        }
+        match literal_len {
+            Some(len) => {
+                let mut literal = vec![0; len];
+                reader.read_exact(&mut literal)?;
+                response.extend(literal);
+            }
+            None => return Ok(response),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn reads_responses_with_literals() {
+        let mut reader = Cursor::new(b"* 1 FETCH (BODY[] {5}\r\nhello)\r\na1 OK done\r\n".to_vec());
+        assert_eq!(read_response(&mut reader).unwrap(), b"* 1 FETCH (BODY[] {5}\r\nhello)\r\n");
+        assert_eq!(read_response(&mut reader).unwrap(), b"a1 OK done\r\n");
+        assert!(read_response(&mut reader).is_err());
+    }
+
+    #[test]
+    fn refuses_oversized_literals_and_lines() {
+        let huge = format!("* 1 FETCH (BODY[] {{{}}}\r\n", usize::MAX);
+        assert!(read_response(&mut Cursor::new(huge.into_bytes())).is_err());
+        let big = format!("* 1 FETCH (BODY[] {{{}}}\r\n", MAX_BODY_LEN);
+        assert!(read_response(&mut Cursor::new(big.into_bytes())).is_err());
+        let long = vec![b'x'; MAX_LINE_LEN + 1];
+        assert!(read_line(&mut Cursor::new(long), &mut Vec::new()).is_err());
+    }
+}
@@ -0,0 +1,167 @@
+//! Emoji shortcodes and display width
+//!
+//! `send` turns `:wave:`-style shortcodes into emoji unless the
+//! `emoji-shortcodes` setting is false. Codes inside backticks, and names
+//! that are not in the table, are left as typed. `width` groups characters
+//! into the clusters terminals draw as one glyph: ZWJ sequences, skin tones,
+//! flags, keycaps, and combining marks.
+
+/// Shortcode names and the emoji they stand for
+pub const SHORTCODES: &[(&str, &str)] = &[
+    ("+1", "👍"),
+    ("-1", "👎"),
+    ("100", "💯"),
+    ("angry", "😠"),
+    ("bug", "🐛"),
+    ("cake", "🍰"),
+    ("check", "✔️"),
+    ("clap", "👏"),
+    ("coffee", "☕"),
+    ("confused", "😕"),
+    ("cry", "😢"),
+    ("eyes", "👀"),
+    ("fire", "🔥"),
+    ("grin", "😁"),
+    ("heart", "❤️"),
+    ("joy", "😂"),
+    ("key", "🔑"),
+    ("laughing", "😆"),
+    ("lock", "🔒"),
+    ("muscle", "💪"),
+    ("ok", "🆗"),
+    ("ok_hand", "👌"),
+    ("party", "🥳"),
+    ("pray", "🙏"),
+    ("question", "❓"),
+    ("rocket", "🚀"),
+    ("sad", "😞"),
+    ("see_no_evil", "🙈"),
+    ("shrug", "🤷"),
+    ("smile", "😄"),
+    ("sparkles", "✨"),
+    ("star", "⭐"),
+    ("sunglasses", "😎"),
+    ("tada", "🎉"),
+    ("thinking", "🤔"),
+    ("thumbsdown", "👎"),
+    ("thumbsup", "👍"),
+    ("warning", "⚠️"),
+    ("wave", "👋"),
+    ("white_check_mark", "✅"),
+    ("wink", "😉"),
+    ("x", "❌"),
+    ("zap", "⚡"),
+];
+
+/// The emoji a shortcode name stands for
+pub fn lookup(name: &str) -> Option<&'static str> {
+    SHORTCODES.iter().find(|(known, _)| *known == name).map(|(_, emoji)| *emoji)
+}
+
+/// `text` with every known `:name:` outside backticks replaced by its emoji
+pub fn expand(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut in_code = false;
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        if c == '`' {
+            in_code = !in_code;
+        } else if c == ':' && !in_code {
+            let name_len = rest[1..].find(|c: char| !is_name_char(c)).unwrap_or(rest.len() - 1);
+            if rest[1 + name_len..].starts_with(':') {
+                if let Some(emoji) = lookup(&rest[1..1 + name_len]) {
+                    out.push_str(emoji);
+                    rest = &rest[name_len + 2..];
+                    continue;
+                }
+            }
+        }
+        out.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+    out
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '_' | '+' | '-')
+}
+
+/// Columns a terminal uses to draw `text`, which should hold no ANSI styling
+pub fn width(text: &str) -> usize {
+    let mut total = 0;
+    let mut cluster: Option<usize> = None; // Width of the cluster being read
+    let mut joined = false; // The last character was a zero-width joiner
+    let mut flag_half = false; // The cluster is a lone regional indicator
+    for c in text.chars() {
+        let code = c as u32;
+        if joined {
+            joined = false; // The joined character draws inside the current cluster
+            continue;
+        }
+        if cluster.is_some() {
+            match code {
+                0x200D => {
+                    joined = true;
+                    continue;
+                }
+                0xFE0F => {
+                    cluster = Some(2); // Emoji presentation
+                    continue;
+                }
+                0xFE0E => {
+                    cluster = Some(1); // Text presentation
+                    continue;
+                }
+                0x1F1E6..=0x1F1FF if flag_half => {
+                    flag_half = false; // Second half of a flag
+                    continue;
+                }
+                _ if is_extender(code) => continue,
+                _ => {}
+            }
+        }
+        total += cluster.take().unwrap_or(0);
+        flag_half = (0x1F1E6..=0x1F1FF).contains(&code);
+        cluster = Some(match code {
+            _ if c.is_control() || is_extender(code) || code == 0x200B => 0,
+            _ if is_wide(code) => 2,
+            _ => 1,
+        });
+    }
+    total + cluster.unwrap_or(0)
+}
+
+/// `text` followed by enough spaces to take up `columns`
+pub fn pad(text: &str, columns: usize) -> String {
+    format!("{}{}", text, " ".repeat(columns.saturating_sub(width(text))))
+}
+
+/// Characters that draw on top of the one before them
+fn is_extender(code: u32) -> bool {
+    matches!(code,
+        0x0300..=0x036F | 0x0483..=0x0489 | 0x0591..=0x05BD | 0x064B..=0x065F
+        | 0x1AB0..=0x1AFF | 0x1DC0..=0x1DFF | 0x20D0..=0x20FF // Combining marks, keycap
+        | 0xFE00..=0xFE0F | 0xFE20..=0xFE2F                   // Variation selectors, half marks
+        | 0x1F3FB..=0x1F3FF                                   // Skin tones
+        | 0xE0020..=0xE007F                                   // Tag sequences in subdivision flags
+    )
+}
+
+/// Characters drawn two columns wide: East Asian wide and emoji-presentation ones
+fn is_wide(code: u32) -> bool {
+    matches!(code,
+        0x1100..=0x115F | 0x231A..=0x231B | 0x2329..=0x232A | 0x23E9..=0x23EC | 0x23F0 | 0x23F3
+        | 0x25FD..=0x25FE | 0x2614..=0x2615 | 0x2648..=0x2653 | 0x267F | 0x2693 | 0x26A1
+        | 0x26AA..=0x26AB | 0x26BD..=0x26BE | 0x26C4..=0x26C5 | 0x26CE | 0x26D4 | 0x26EA
+        | 0x26F2..=0x26F3 | 0x26F5 | 0x26FA | 0x26FD | 0x2705 | 0x270A..=0x270B | 0x2728
+        | 0x274C | 0x274E | 0x2753..=0x2755 | 0x2757 | 0x2795..=0x2797 | 0x27B0 | 0x27BF
+        | 0x2B1B..=0x2B1C | 0x2B50 | 0x2B55
+        | 0x2E80..=0x303E | 0x3041..=0x33FF | 0x3400..=0x4DBF | 0x4E00..=0x9FFF
+        | 0xA000..=0xA4CF | 0xAC00..=0xD7A3 | 0xF900..=0xFAFF | 0xFE30..=0xFE4F
+        | 0xFF00..=0xFF60 | 0xFFE0..=0xFFE6
+        | 0x1F004 | 0x1F0CF | 0x1F18E | 0x1F191..=0x1F19A | 0x1F1E6..=0x1F1FF
+        | 0x1F200..=0x1F2FF | 0x1F300..=0x1F64F | 0x1F680..=0x1F6FF | 0x1F7E0..=0x1F7EB
+        | 0x1F90C..=0x1F9FF | 0x1FA70..=0x1FAFF
+        | 0x20000..=0x3FFFD
+    )
+}
@@ -0,0 +1,43 @@
+//! Forwarding messages between groups
+//!
+//! A forwarded message is sent to the destination group like any other, so
+//! it is encrypted under that group's key and the source group's secret never
+//! leaves it. The payload carries where the message came from, which only
+//! the forwarder vouches for: receivers cannot check it against a group they
+//! may not belong to.
+
+use anyhow::{anyhow, Context, Result};
+
+use crate::app::MlsChatApp;
+use crate::content::Content;
+use crate::model::Provenance;
+use crate::pins::short_id;
+
+impl MlsChatApp {
+    /// Send a copy of a message from one group to another; `message_id` may be a unique prefix
+    ///
+    /// Forwarding a forwarded message keeps its first provenance.
+    pub fn forward_message(&mut self, source: &str, message_id: &str, destination: String) -> Result<()> {
+        if source == destination {
+            return Err(anyhow!("Message is already in '{}'", destination));
+        }
+        let (message_id, message) = self.find_message(source, message_id)?;
+        let message = message
+            .with_context(|| format!("Message {} is pinned in '{}' but no longer stored here", short_id(&message_id), source))?;
+        let group = self.groups.get(source).context("Group not found")?;
+        let content = match Content::of_message(&message) {
+            forwarded @ Content::Forwarded { .. } => forwarded,
+            content => Content::Forwarded {
+                content: Box::new(content),
+                from: Provenance {
+                    sender: message.sender.clone(),
+                    group: group.name.clone(),
+                    timestamp: message.timestamp,
+                    message_id: message.id.clone(),
+                },
+            },
+        };
+        println!("↪ Forwarding message {} from '{}' to '{}'", short_id(&message_id), source, destination);
+        self.send_content(destination, content)
+    }
+}
@@ -0,0 +1,158 @@
+//! `gc`: removing stored data that no group refers to any more
+//!
+//! Attachments are stored inside the messages that carry them, so each
+//! stored copy has exactly one reference, its message, and goes with it:
+//! deleting a group, pruning by retention, and rolling back all rewrite the
+//! log, overwriting what they drop. What can be left behind is what outlives
+//! its group: a message log, with every attachment in it, whose group file
+//! was removed by hand or by an interrupted run, and unfinished chunk
+//! transfers (see `transfer`) for a group that is gone.
+//!
+//! `garbage` counts the attachments each group's messages still refer to and
+//! finds the rest, and `collect_garbage` removes it through
+//! `Storage::delete_group` and `delete_transfer`, overwriting as they do.
+//! Sizes are of the stored JSON, so they are close to, but not exactly, the
+//! space the file system gets back.
+
+use anyhow::Result;
+use colored::*;
+use std::collections::HashSet;
+
+use crate::app::MlsChatApp;
+use crate::i18n::tr;
+use crate::model::Transfer;
+use crate::pins::short_id;
+use crate::preview::human_size;
+
+/// A message log whose group is no longer stored
+#[derive(Debug, Clone)]
+pub struct OrphanedLog {
+    pub group_id: String,
+    pub messages: usize,
+    pub attachments: usize,
+    pub attachment_bytes: u64, // The files, before encoding
+    pub bytes: u64, // The whole log as stored
+}
+
+/// What `gc` would remove, and the attachments it keeps
+#[derive(Debug, Clone, Default)]
+pub struct Garbage {
+    pub logs: Vec<OrphanedLog>,
+    pub transfers: Vec<Transfer>, // Of groups that are gone
+    pub kept_attachments: usize, // Referred to by the messages of stored groups
+    pub kept_bytes: u64,
+    pub groups: usize, // Stored groups whose messages were counted
+}
+
+/// Bytes a transfer's chunks take, as stored
+fn transfer_bytes(transfer: &Transfer) -> u64 {
+    transfer.chunks.values().map(|chunk| chunk.len() as u64).sum()
+}
+
+impl Garbage {
+    /// True if there is nothing to remove
+    pub fn is_empty(&self) -> bool {
+        self.logs.is_empty() && self.transfers.is_empty()
+    }
+
+    /// Bytes removing everything would reclaim
+    pub fn bytes(&self) -> u64 {
+        self.logs.iter().map(|log| log.bytes).sum::<u64>() + self.transfers.iter().map(transfer_bytes).sum::<u64>()
+    }
+
+    /// What collecting loses, for confirming first
+    pub fn losses(&self) -> Vec<String> {
+        let mut losses: Vec<String> = self.logs.iter()
+            .map(|log| tr("gc-loss-log", &[
+                ("group", &short_id(&log.group_id)),
+                ("messages", &log.messages),
+                ("attachments", &log.attachments),
+                ("attachment-size", &human_size(log.attachment_bytes)),
+                ("size", &human_size(log.bytes)),
+            ]))
+            .collect();
+        losses.extend(self.transfers.iter().map(|transfer| tr("gc-loss-transfer", &[
+            ("direction", &transfer.direction),
+            ("id", &short_id(&transfer.id)),
+            ("group", &short_id(&transfer.group_id)),
+            ("size", &human_size(transfer_bytes(transfer))),
+        ])));
+        losses
+    }
+
+    /// Print what is kept and what would be removed
+    pub fn print(&self) {
+        println!("{}", tr("gc-kept", &[
+            ("attachments", &self.kept_attachments),
+            ("size", &human_size(self.kept_bytes)),
+            ("groups", &self.groups),
+        ]));
+        if self.is_empty() {
+            println!("{}", tr("gc-empty", &[]));
+        }
+    }
+}
+
+impl MlsChatApp {
+    /// Count the attachments stored groups refer to, and find the logs and transfers no group does
+    pub fn garbage(&self) -> Result<Garbage> {
+        let mut garbage = Garbage { groups: self.groups.len(), ..Garbage::default() };
+        let group_ids: HashSet<&str> = self.groups.values().map(|group| group.group_id.as_str()).collect();
+        for group in self.groups.values() {
+            self.storage.stream_messages(&group.group_id, &mut |message| {
+                if let Some(attachment) = &message.attachment {
+                    garbage.kept_attachments += 1;
+                    garbage.kept_bytes += attachment.size;
+                }
+                Ok(())
+            })?;
+        }
+
+        let mut orphans: Vec<String> = self.storage.message_logs()?
+            .into_iter()
+            .filter(|group_id| !group_ids.contains(group_id.as_str()))
+            .collect();
+        orphans.sort();
+        for group_id in orphans {
+            let mut log = OrphanedLog { group_id, messages: 0, attachments: 0, attachment_bytes: 0, bytes: 0 };
+            self.storage.stream_messages(&log.group_id, &mut |message| {
+                log.messages += 1;
+                log.bytes += serde_json::to_vec(&message)?.len() as u64 + 1; // One line each
+                if let Some(attachment) = &message.attachment {
+                    log.attachments += 1;
+                    log.attachment_bytes += attachment.size;
+                }
+                Ok(())
+            })?;
+            garbage.logs.push(log);
+        }
+
+        garbage.transfers = self.storage.load_transfers()?
+            .into_iter()
+            .filter(|transfer| !group_ids.contains(transfer.group_id.as_str()))
+            .collect();
+        Ok(garbage)
+    }
+
+    /// Remove what `garbage` found; returns the bytes reclaimed
+    pub fn collect_garbage(&mut self, garbage: Garbage) -> Result<u64> {
+        for log in &garbage.logs {
+            // Also removes any snapshots, transfers, and index entries the group left
+            self.storage.delete_group(&log.group_id)?;
+            println!("{}", tr("gc-removed-log", &[("group", &short_id(&log.group_id))]));
+        }
+        for transfer in &garbage.transfers {
+            self.storage.delete_transfer(&transfer.id)?;
+            println!("{}", tr("gc-removed-transfer", &[("direction", &transfer.direction), ("id", &short_id(&transfer.id))]));
+        }
+        let bytes = garbage.bytes();
+        let attachments: usize = garbage.logs.iter().map(|log| log.attachments).sum();
+        println!("{}", tr("gc-done", &[
+            ("size", &human_size(bytes)),
+            ("logs", &garbage.logs.len()),
+            ("attachments", &attachments),
+            ("transfers", &garbage.transfers.len()),
+        ]).green());
+        Ok(bytes)
+    }
+}
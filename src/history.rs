@@ -0,0 +1,122 @@
+//! Sharing recent history with a new member
+//!
+//! MLS gives a joiner no past messages: the Welcome carries only the current
+//! epoch's secret, and earlier epochs' keys are gone. `share-history <group>
+//! <member>` closes that gap at the application level. An existing member
+//! sends a History with their own copies of the messages from the last
+//! `history.share-epochs` epochs, encrypted to the joiner's key the way a
+//! Welcome is and delivered to the joiner alone; `add-member --share-history`
+//! does it right after the add.
+//!
+//! The joiner keeps the messages marked with who shared them and without a
+//! `sender_key`: they come from the sharer, not from the senders, so they are
+//! only as trustworthy as the sharer, who could have left messages out or
+//! written them. Sharing is always a choice made per joiner, since it hands
+//! over messages the other members sent before that person could see them.
+
+use anyhow::{anyhow, Context, Result};
+use colored::*;
+
+use crate::app::MlsChatApp;
+use crate::delivery::SendMessageRequest;
+use crate::protocol::{self, HistoryBody, MlsMessage};
+
+/// How many epochs back `share-history` reaches, unless `history.share-epochs` says otherwise
+pub const DEFAULT_SHARE_EPOCHS: u32 = 5;
+
+impl MlsChatApp {
+    /// Share this many epochs of history by default
+    pub fn set_history_epochs(&mut self, epochs: u32) {
+        self.history_epochs = epochs;
+    }
+
+    /// Send `member` this profile's messages from the group's last `epochs` epochs, or `history_epochs`
+    pub fn share_history(&mut self, group_name: &str, member: &str, epochs: Option<u32>) -> Result<()> {
+        let user = self.current_user.context("No user initialized")?.to_string();
+        let group = self.groups.get(group_name).context("Group not found")?;
+        if !group.members.iter().any(|other| other == member) {
+            return Err(anyhow!("'{}' is not a member of '{}'; add them first", member, group_name));
+        }
+        if member == user || self.user_keys.contains_key(member) {
+            println!("'{}' shares this profile's copy of '{}' and already sees its history", member, group_name);
+            return Ok(());
+        }
+        if self.transport.is_none() {
+            return Err(anyhow!("Sharing history needs a delivery service (pass --server <addr>)"));
+        }
+        let public_key = self.key_package(member)?.public_key;
+        let epochs = epochs.unwrap_or(self.history_epochs);
+
+        let group = self.load_messages(group_name)?;
+        let from_epoch = group.mls_group.epoch.saturating_sub(epochs);
+        let messages: Vec<_> = group.messages.as_deref().unwrap_or_default()
+            .iter()
+            .filter(|message| message.epoch >= from_epoch)
+            .cloned()
+            .collect();
+        if messages.is_empty() {
+            println!("No messages in '{}' since epoch {} to share", group_name, from_epoch);
+            return Ok(());
+        }
+        let count = messages.len();
+        let body = HistoryBody { from_epoch, messages };
+        let history = MlsMessage::History {
+            group_id: group.group_id.clone(),
+            epoch: group.mls_group.epoch,
+            sender: user.clone(),
+            ciphertext: protocol::encrypt(&protocol::welcome_key(&public_key), &serde_json::to_string(&body)?),
+        };
+        let group_id = group.group_id.clone();
+        let request = SendMessageRequest {
+            sender: user,
+            group_id: group_id.clone(),
+            recipients: vec![member.to_string()],
+            payload: history.to_bytes()?,
+        };
+        let seq = self.transport()?.send_message(request)?.seq;
+        self.record_sent_seq(&group_id, seq);
+        self.save_state()?;
+        println!("{}", format!("📜 Shared {} message(s) of '{}' from epoch {} on with {}", count, group_name, from_epoch, member).green());
+        println!("   Encrypted to their key and sent to them alone");
+        Ok(())
+    }
+
+    /// Keep the messages another member shared with the current user, skipping ones already here
+    pub(crate) fn apply_history(&mut self, group_name: &str, sender: &str, ciphertext: &str) -> Result<()> {
+        let user = self.current_user.context("No user initialized")?.to_string();
+        let public_key = self.user_keys.get(&user).context("No key for the current user")?.public_key.clone();
+        let body = protocol::decrypt(&protocol::welcome_key(&public_key), ciphertext)
+            .context("Failed to decrypt shared history")?;
+        let body: HistoryBody = serde_json::from_str(&body).context("Malformed shared history")?;
+
+        let group = self.load_messages(group_name)?;
+        if !group.members.iter().any(|member| member == sender) {
+            return Err(anyhow!("'{}' shared history of '{}' without being a member; ignored", sender, group_name));
+        }
+        let group_id = group.group_id.clone();
+        let epoch = group.mls_group.epoch;
+        let mut messages = group.messages.clone().unwrap_or_default();
+        let mut added = 0;
+        for mut message in body.messages {
+            if message.group_id != group_id || message.epoch > epoch || messages.iter().any(|known| known.id == message.id) {
+                continue;
+            }
+            message.shared_by = Some(sender.to_string());
+            message.sender_key = None; // Vouched for by the sharer, not the sender's key
+            messages.push(message);
+            added += 1;
+        }
+        if added == 0 {
+            println!("   History of '{}' from {} has nothing new", group_name, sender);
+            return Ok(());
+        }
+        messages.sort_by_key(|message| message.timestamp);
+        self.storage.replace_messages(&group_id, &messages)?;
+        let group = self.groups.get_mut(group_name).context("Group not found")?;
+        group.message_count = messages.len();
+        group.messages = Some(messages);
+        self.dirty_groups.insert(group_name.to_string());
+        println!("   📜 {} shared {} earlier message(s) of '{}' from epoch {} on", sender, added, group_name, body.from_epoch);
+        Ok(())
+    }
+}
@@ -0,0 +1,158 @@
+//! Event hooks: scripts a bot runs when something happens in its groups
+//!
+//! Hooks live in the profile's `hooks` directory, named after the event they
+//! handle, as with git hooks: `on_message` and `on_member_added`. Any
+//! executable works. A hook gets the event as one JSON object on stdin and
+//! answers with zero or more JSON actions on stdout, one per line:
+//!
+//! ```text
+//! {"reply": "text"}   post text to the group the event came from
+//! {"log": "text"}     print text in the bot's output
+//! {"tag": "label"}    label the message (on_message only)
+//! ```
+//!
+//! Actions are the only way a hook can affect the chat; it never sees keys or
+//! the profile's state. Hooks that run past `HOOK_TIMEOUT` are killed.
+
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::bot::{Bot, BotContext};
+
+/// How long a hook may run before it is killed
+pub const HOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What a hook is told about, as written to its stdin
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "camelCase")]
+enum HookEvent<'a> {
+    #[serde(rename_all = "camelCase")]
+    Message {
+        group: &'a str,
+        message_id: &'a str,
+        sender: &'a str,
+        content: &'a str,
+        timestamp: DateTime<Utc>,
+    },
+    MemberAdded { group: &'a str, member: &'a str },
+}
+
+/// One line of a hook's stdout
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum HookAction {
+    Reply(String),
+    Log(String),
+    Tag(String),
+}
+
+/// The hooks found in one directory
+pub struct Hooks {
+    on_message: Option<PathBuf>,
+    on_member_added: Option<PathBuf>,
+}
+
+impl Hooks {
+    /// Look for hooks in `dir`; missing ones are skipped
+    pub fn open(dir: &Path) -> Self {
+        let find = |name: &str| Some(dir.join(name)).filter(|path| path.is_file());
+        Self { on_message: find("on_message"), on_member_added: find("on_member_added") }
+    }
+
+    /// True if `dir` holds no hooks
+    pub fn is_empty(&self) -> bool {
+        self.on_message.is_none() && self.on_member_added.is_none()
+    }
+
+    /// Register the hooks as `bot` handlers
+    pub fn attach(self, mut bot: Bot) -> Bot {
+        if let Some(script) = self.on_message {
+            println!("   🪝 on_message: {}", script.display());
+            bot = bot.on_message(move |context, message| {
+                let event = HookEvent::Message {
+                    group: context.group(),
+                    message_id: &message.id,
+                    sender: &message.sender,
+                    content: &message.content,
+                    timestamp: message.timestamp,
+                };
+                let actions = run_hook(&script, &event)?;
+                apply_actions(context, actions, Some(&message.id))
+            });
+        }
+        if let Some(script) = self.on_member_added {
+            println!("   🪝 on_member_added: {}", script.display());
+            bot = bot.on_member_added(move |context, member| {
+                let actions = run_hook(&script, &HookEvent::MemberAdded { group: context.group(), member })?;
+                apply_actions(context, actions, None)
+            });
+        }
+        bot
+    }
+}
+
+/// Run `script` with `event` on stdin and parse the actions it prints
+fn run_hook(script: &Path, event: &HookEvent) -> Result<Vec<HookAction>> {
+    let mut child = Command::new(script)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to run hook {}", script.display()))?;
+
+    let mut stdin = child.stdin.take().context("Hook stdin unavailable")?;
+    stdin.write_all(serde_json::to_string(event)?.as_bytes())?;
+    drop(stdin);
+    let mut stdout = child.stdout.take().context("Hook stdout unavailable")?;
+    // Read on another thread so a chatty hook cannot fill the pipe and stall
+    let reader = thread::spawn(move || {
+        let mut output = String::new();
+        stdout.read_to_string(&mut output).map(|_| output)
+    });
+
+    let deadline = Instant::now() + HOOK_TIMEOUT;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(anyhow!("Hook {} ran longer than {:?} and was killed", script.display(), HOOK_TIMEOUT));
+        }
+        thread::sleep(Duration::from_millis(20));
+    };
+    let output = reader.join().map_err(|_| anyhow!("Hook output reader panicked"))??;
+    if !status.success() {
+        return Err(anyhow!("Hook {} failed ({})", script.display(), status));
+    }
+
+    output.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| serde_json::from_str(line).with_context(|| format!("Hook printed an invalid action: {}", line)))
+        .collect()
+}
+
+fn apply_actions(context: &mut BotContext, actions: Vec<HookAction>, message_id: Option<&str>) -> Result<()> {
+    for action in actions {
+        match action {
+            HookAction::Reply(content) => {
+                context.reply(&content)?;
+            }
+            HookAction::Log(text) => println!("   📝 [{}] {}", context.group(), text),
+            HookAction::Tag(tag) => {
+                let message_id = message_id.context("Only on_message hooks can tag")?;
+                context.tag(message_id, &tag)?;
+                println!("   🏷️  Tagged {} as '{}'", message_id, tag);
+            }
+        }
+    }
+    Ok(())
+}
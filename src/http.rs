@@ -0,0 +1,334 @@
+//! Minimal HTTP/1.1 support for the REST API and bridges
+//!
+//! Just enough to exchange one JSON request per connection: no keep-alive,
+//! no TLS, and chunked bodies only in responses.
+
+use anyhow::{anyhow, Context, Result};
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpStream,
+    time::Duration,
+};
+
+use crate::proxy::Socks5Proxy;
+
+/// Largest body accepted, in bytes
+pub(crate) const MAX_BODY_LEN: usize = 1024 * 1024;
+/// Most header lines accepted per request
+const MAX_HEADERS: usize = 100;
+/// Longest request, status, header, or chunk-size line accepted, in bytes
+pub(crate) const MAX_LINE_LEN: usize = 8 * 1024;
+
+/// A parsed HTTP request
+pub(crate) struct HttpRequest {
+    pub method: String,
+    pub path: String, // Without the query string
+    pub headers: HashMap<String, String>, // Keyed by lowercase name
+    pub body: Vec<u8>,
+}
+
+impl HttpRequest {
+    /// Read one request, or `None` if the connection closed before sending anything
+    pub fn read(reader: &mut impl BufRead) -> Result<Option<Self>> {
+        let mut line = String::new();
+        if read_line(reader, &mut line)? == 0 {
+            return Ok(None);
+        }
+        let mut parts = line.split_whitespace();
+        let (method, target) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(method), Some(target), Some(version)) if version.starts_with("HTTP/1.") => {
+                (method.to_string(), target)
+            }
+            _ => return Err(anyhow!("Malformed request line")),
+        };
+        let path = target.split('?').next().unwrap_or_default().to_string();
+        let headers = read_headers(reader)?;
+
+        let length = match headers.get("content-length") {
+            Some(length) => length.parse::<usize>().context("Invalid Content-Length")?,
+            None => 0,
+        };
+        if length > MAX_BODY_LEN {
+            return Err(anyhow!("Request body too large"));
+        }
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body)?;
+
+        Ok(Some(Self { method, path, headers, body }))
+    }
+
+    /// Look up a header by name, ignoring case
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(&name.to_ascii_lowercase()).map(String::as_str)
+    }
+}
+
+/// A JSON or plain-text response
+pub(crate) struct HttpResponse {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+impl HttpResponse {
+    pub fn json(status: u16, body: &impl Serialize) -> Self {
+        Self {
+            status,
+            content_type: "application/json",
+            body: serde_json::to_string(body).unwrap_or_default(),
+        }
+    }
+
+    /// An `{"error": message}` response
+    pub fn error(status: u16, message: impl Into<String>) -> Self {
+        Self::json(status, &serde_json::json!({ "error": message.into() }))
+    }
+
+    pub fn text(status: u16, content_type: &'static str, body: String) -> Self {
+        Self { status, content_type, body }
+    }
+
+    pub fn write_to(&self, writer: &mut impl Write) -> Result<()> {
+        write!(
+            writer,
+            "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.status,
+            reason_phrase(self.status),
+            self.content_type,
+            self.body.len(),
+            self.body
+        )?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Send a JSON request to an `http://` URL, through `proxy` if one is given,
+/// returning the status and JSON body
+///
+/// Non-JSON response bodies come back as `Value::Null`.
+pub(crate) fn send(
+    method: &str,
+    url: &str,
+    bearer_token: Option<&str>,
+    body: Option<&serde_json::Value>,
+    timeout: Duration,
+    proxy: Option<&Socks5Proxy>,
+) -> Result<(u16, serde_json::Value)> {
+    if url.starts_with("https://") {
+        return Err(anyhow!("HTTPS is not supported (TLS is not built in); use an http:// URL or a local TLS proxy"));
+    }
+    let rest = url.strip_prefix("http://").context("URL must start with http://")?;
+    let (authority, path) = match rest.find('/') {
+        Some(slash) => rest.split_at(slash),
+        None => (rest, "/"),
+    };
+    let addr = if authority.contains(':') { authority.to_string() } else { format!("{}:80", authority) };
+
+    let stream = match proxy {
+        Some(proxy) => proxy.connect(&addr)?,
+        None => TcpStream::connect(&addr).with_context(|| format!("Failed to connect to {}", addr))?,
+    };
+    stream.set_read_timeout(Some(timeout))?;
+    let mut writer = stream.try_clone()?;
+    let body = body.map(|body| body.to_string()).unwrap_or_default();
+    write!(writer, "{} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n", method, path, authority)?;
+    if let Some(token) = bearer_token {
+        write!(writer, "Authorization: Bearer {}\r\n", token)?;
+    }
+    write!(
+        writer,
+        "Content-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+        body.len(),
+        body
+    )?;
+    writer.flush()?;
+
+    let mut reader = BufReader::new(stream);
+    let mut status_line = String::new();
+    read_line(&mut reader, &mut status_line)?;
+    let status = status_line.split_whitespace().nth(1)
+        .and_then(|status| status.parse::<u16>().ok())
+        .with_context(|| format!("Malformed HTTP status line from {}", addr))?;
+    let headers = read_headers(&mut reader)?;
+
+    let mut response = Vec::new();
+    if headers.get("transfer-encoding").is_some_and(|encoding| encoding.eq_ignore_ascii_case("chunked")) {
+        loop {
+            let mut size = String::new();
+            read_line(&mut reader, &mut size)?;
+            let size = usize::from_str_radix(size.trim().split(';').next().unwrap_or_default(), 16)
+                .context("Malformed chunk size")?;
+            if response.len().checked_add(size).is_none_or(|length| length > MAX_BODY_LEN) {
+                return Err(anyhow!("Response body too large"));
+            }
+            let mut chunk = vec![0; size + 2]; // Chunk plus its CRLF
+            reader.read_exact(&mut chunk)?;
+            if size == 0 {
+                break;
+            }
+            response.extend(&chunk[..size]);
+        }
+    } else if let Some(length) = headers.get("content-length") {
+        let length = length.parse::<usize>().context("Invalid Content-Length")?;
+        if length > MAX_BODY_LEN {
+            return Err(anyhow!("Response body too large"));
+        }
+        response.resize(length, 0);
+        reader.read_exact(&mut response)?;
+    } else {
+        reader.take(MAX_BODY_LEN as u64).read_to_end(&mut response)?;
+    }
+
+    Ok((status, serde_json::from_slice(&response).unwrap_or_default()))
+}
+
+/// Percent-encode a URL path segment or query value
+pub(crate) fn percent_encode(value: &str) -> String {
+    value.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+/// Read one line into `line`, refusing lines longer than `MAX_LINE_LEN`; returns the bytes read, 0 at the end
+fn read_line(reader: &mut impl BufRead, line: &mut String) -> Result<usize> {
+    let read = reader.take(MAX_LINE_LEN as u64).read_line(line)?;
+    if read == MAX_LINE_LEN && !line.ends_with('\n') {
+        return Err(anyhow!("Line longer than {} bytes", MAX_LINE_LEN));
+    }
+    Ok(read)
+}
+
+/// Read header lines up to the blank line that ends them
+fn read_headers(reader: &mut impl BufRead) -> Result<HashMap<String, String>> {
+    let mut headers = HashMap::new();
+    loop {
+        let mut line = String::new();
+        if read_line(reader, &mut line)? == 0 {
+            return Err(anyhow!("Connection closed inside headers"));
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            return Ok(headers);
+        }
+        if headers.len() == MAX_HEADERS {
+            return Err(anyhow!("Too many headers"));
+        }
+        let (name, value) = line.split_once(':').context("Malformed header")?;
+        headers.insert(name.trim().to_ascii_lowercase(), value.trim().to_string());
+    }
+}
+
+fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        429 => "Too Many Requests",
+        _ => "Internal Server Error",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{io::Cursor, net::TcpListener, thread};
+
+    fn read(request: &str) -> Result<Option<HttpRequest>> {
+        HttpRequest::read(&mut Cursor::new(request.as_bytes().to_vec()))
+    }
+
+    /// Answer one connection to a local port with `response`, returning its URL
+    fn serve_once(response: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            HttpRequest::read(&mut BufReader::new(stream.try_clone().unwrap())).unwrap();
+            stream.write_all(response.as_bytes()).unwrap();
+        });
+        format!("http://{}/", addr)
+    }
+
+    #[test]
+    fn reads_a_request() {
+        let request = read("POST /groups?x=1 HTTP/1.1\r\nContent-Length: 2\r\nX-Test: yes\r\n\r\n{}").unwrap().unwrap();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/groups");
+        assert_eq!(request.header("x-TEST"), Some("yes"));
+        assert_eq!(request.body, b"{}");
+        assert!(read("").unwrap().is_none());
+    }
+
+    #[test]
+    fn refuses_malformed_requests() {
+        assert!(read("GET /\r\n\r\n").is_err());
+        assert!(read("GET / SPDY/3\r\n\r\n").is_err());
+        assert!(read("GET / HTTP/1.1\r\nNo colon\r\n\r\n").is_err());
+        assert!(read("GET / HTTP/1.1\r\nContent-Length: -1\r\n\r\n").is_err());
+        assert!(read("GET / HTTP/1.1\r\nHost: x\r\n").is_err());
+        assert!(read("POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\nshort").is_err());
+    }
+
+    #[test]
+    fn refuses_oversized_requests() {
+        let long_line = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(MAX_LINE_LEN));
+        assert!(read(&long_line).is_err());
+        let headers: String = (0..=MAX_HEADERS).map(|i| format!("H{}: x\r\n", i)).collect();
+        assert!(read(&format!("GET / HTTP/1.1\r\n{}\r\n", headers)).is_err());
+        assert!(read(&format!("POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\n", MAX_BODY_LEN + 1)).is_err());
+    }
+
+    #[test]
+    fn sends_and_reads_chunked_responses() {
+        let url = serve_once("HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n7\r\n{\"a\":1}\r\n0\r\n\r\n");
+        let (status, body) = send("GET", &url, None, None, Duration::from_secs(5), None).unwrap();
+        assert_eq!(status, 200);
+        assert_eq!(body["a"], 1);
+    }
+
+    #[test]
+    fn sends_through_a_proxy() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let proxy: Socks5Proxy = format!("socks5://{}", listener.local_addr().unwrap()).parse().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut greeting = [0u8; 3];
+            stream.read_exact(&mut greeting).unwrap();
+            stream.write_all(&[0x05, 0x00]).unwrap();
+            let mut request = [0u8; 5];
+            stream.read_exact(&mut request).unwrap();
+            let mut target = vec![0u8; request[4] as usize + 2];
+            stream.read_exact(&mut target).unwrap();
+            assert_eq!(&target[..request[4] as usize], b"homeserver.test");
+            stream.write_all(&[0x05, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0]).unwrap();
+            let request = HttpRequest::read(&mut BufReader::new(stream.try_clone().unwrap())).unwrap().unwrap();
+            assert_eq!(request.header("host"), Some("homeserver.test:8008"));
+            stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 7\r\n\r\n{\"a\":1}").unwrap();
+        });
+        let (status, body) = send("GET", "http://homeserver.test:8008/", None, None, Duration::from_secs(5), Some(&proxy)).unwrap();
+        assert_eq!(status, 200);
+        assert_eq!(body["a"], 1);
+    }
+
+    #[test]
+    fn refuses_malformed_responses() {
+        let timeout = Duration::from_secs(5);
+        let overflow = serve_once("HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n1\r\nx\r\nffffffffffffffff\r\n");
+        assert!(send("GET", &overflow, None, None, timeout, None).is_err());
+        let no_status = serve_once("HTTP/1.1\r\n\r\n");
+        assert!(send("GET", &no_status, None, None, timeout, None).is_err());
+        let bad_size = serve_once("HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\n");
+        assert!(send("GET", &bad_size, None, None, timeout, None).is_err());
+        assert!(send("GET", "https://example.com/", None, None, timeout, None).is_err());
+    }
+}
@@ -0,0 +1,156 @@
+//! Translated output
+//!
+//! User-facing text is looked up by key in a catalog for the chosen
+//! language. Catalogs are `locales/<language>.ftl` files in a small subset of
+//! Fluent: one `key = text` message per line, `#` comments, and `{ $name }`
+//! placeables filled from the arguments given to `tr`.
+//!
+//! The language is the `language` setting, else the first of `LC_ALL`,
+//! `LC_MESSAGES`, and `LANG` that names one with a catalog, else English.
+//! A message missing from a catalog falls back to English, so a partial
+//! translation still works.
+
+use std::{collections::HashMap, env, fmt::Display, sync::OnceLock};
+
+use crate::output;
+
+/// Built-in catalogs, English first
+const CATALOGS: &[(&str, &str)] = &[
+    ("en", include_str!("../locales/en.ftl")),
+    ("es", include_str!("../locales/es.ftl")),
+];
+
+/// Environment variables naming the user's locale, most specific first
+const LOCALE_VARS: &[&str] = &["LC_ALL", "LC_MESSAGES", "LANG"];
+
+static ACTIVE: OnceLock<Catalogs> = OnceLock::new();
+
+struct Catalogs {
+    language: &'static str,
+    messages: HashMap<&'static str, &'static str>,
+    english: HashMap<&'static str, &'static str>,
+}
+
+/// Languages with a catalog, as `language` takes them
+pub fn languages() -> Vec<&'static str> {
+    CATALOGS.iter().map(|(language, _)| *language).collect()
+}
+
+/// Choose the language for the rest of the run from the `language` setting or
+/// the environment; only the first call has any effect
+pub fn init(setting: Option<&str>) {
+    let language = setting.filter(|setting| *setting != "auto")
+        .and_then(supported)
+        .or_else(|| LOCALE_VARS.iter().filter_map(|var| env::var(var).ok()).find_map(|locale| supported(&locale)))
+        .unwrap_or("en");
+    let _ = ACTIVE.set(Catalogs::new(language));
+}
+
+/// The language output is in
+pub fn language() -> &'static str {
+    active().language
+}
+
+/// The message `key` in the chosen language, with each `{ $name }` replaced by its argument,
+/// and in plain output without its emoji
+pub fn tr(key: &str, args: &[(&str, &dyn Display)]) -> String {
+    let catalogs = active();
+    let Some(text) = catalogs.messages.get(key).or_else(|| catalogs.english.get(key)) else {
+        return key.to_string();
+    };
+    let mut out = String::with_capacity(text.len());
+    let mut rest = *text;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        let Some(end) = rest[start..].find('}') else {
+            break;
+        };
+        let name = rest[start + 1..start + end].trim().trim_start_matches('$');
+        match args.iter().find(|(arg, _)| *arg == name) {
+            Some((_, value)) => out.push_str(&value.to_string()),
+            None => out.push_str(&rest[start..start + end + 1]),
+        }
+        rest = &rest[start + end + 1..];
+    }
+    out.push_str(rest);
+    output::words(&out)
+}
+
+impl Catalogs {
+    fn new(language: &'static str) -> Self {
+        Self { language, messages: catalog(language), english: catalog("en") }
+    }
+}
+
+fn active() -> &'static Catalogs {
+    ACTIVE.get_or_init(|| Catalogs::new("en"))
+}
+
+/// The catalog language a setting or locale such as `es_ES.UTF-8` names, if there is one
+fn supported(locale: &str) -> Option<&'static str> {
+    let language = locale.split(['_', '.', '@', '-']).next()?.to_ascii_lowercase();
+    CATALOGS.iter().map(|(name, _)| *name).find(|name| *name == language)
+}
+
+fn catalog(language: &str) -> HashMap<&'static str, &'static str> {
+    let source = CATALOGS.iter().find(|(name, _)| *name == language).map_or("", |(_, source)| *source);
+    source.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, text)| (key.trim(), text.trim()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+    use std::fs;
+    use std::path::Path;
+
+    /// The `{ $name }` placeables in a message
+    fn placeables(text: &str) -> BTreeSet<&str> {
+        text.split('{').skip(1).filter_map(|rest| rest.split_once('}')).map(|(name, _)| name.trim().trim_start_matches('$')).collect()
+    }
+
+    #[test]
+    fn catalogs_have_the_same_keys_and_placeables() {
+        let english = catalog("en");
+        for language in languages().into_iter().filter(|language| *language != "en") {
+            let messages = catalog(language);
+            let keys: BTreeSet<_> = messages.keys().collect();
+            assert_eq!(keys, english.keys().collect(), "keys of {}.ftl", language);
+            for (key, text) in &messages {
+                assert_eq!(placeables(text), placeables(english[key]), "placeables of {} in {}.ftl", key, language);
+            }
+        }
+    }
+
+    #[test]
+    fn every_key_used_is_in_the_english_catalog() {
+        let english = catalog("en");
+        let src = Path::new(env!("CARGO_MANIFEST_DIR")).join("src");
+        for entry in fs::read_dir(&src).unwrap() {
+            let path = entry.unwrap().path();
+            if path.ends_with("i18n.rs") {
+                continue; // Its tests look up keys that are missing on purpose
+            }
+            let source = fs::read_to_string(&path).unwrap();
+            for (start, call) in source.match_indices("tr(\"") {
+                if source[..start].ends_with(|c: char| c.is_alphanumeric() || c == '_') {
+                    continue; // Another function ending in `tr`
+                }
+                let key = source[start + call.len()..].split('"').next().unwrap();
+                assert!(english.contains_key(key), "{} uses '{}', which en.ftl lacks", path.display(), key);
+            }
+        }
+    }
+
+    #[test]
+    fn fills_placeables() {
+        assert_eq!(tr("members-epoch", &[("epoch", &3)]), "New epoch: 3");
+        assert_eq!(tr("request-invited", &[]), "invited by { $member }");
+        assert_eq!(tr("no-such-key", &[]), "no-such-key");
+    }
+}
@@ -0,0 +1,271 @@
+//! On-disk index of message IDs, for looking a message up without reading its group's log
+//!
+//! `FileStorage` keeps one entry per stored message, naming its group and
+//! the byte offset of its line in the group's `messages/<group_id>.jsonl`,
+//! in `index/<bucket>.jsonl`. The bucket is the ID's first two characters,
+//! so finding an ID, or a prefix as `list --ids` shows it, reads one bucket
+//! of a few hundred entries however long the histories grow. Appending a
+//! message appends its entry; rewriting a log rewrites that group's entries.
+//!
+//! The index holds no content and is rebuilt from the logs whenever it is
+//! missing, as in profiles from before it existed, or an entry no longer
+//! points at its message.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs::{self, OpenOptions},
+    io::Write,
+    path::PathBuf,
+};
+
+use crate::storage;
+
+/// Where one message is stored
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IndexEntry {
+    pub id: String,
+    pub group_id: String,
+    pub offset: u64, // Of the message's line in the group's log
+}
+
+/// The message index in one data directory
+#[derive(Debug, Clone)]
+pub(crate) struct MessageIndex {
+    dir: PathBuf,
+}
+
+/// The bucket holding `id`: its first two characters when they are hex digits, as in UUIDs
+fn bucket(id: &str) -> String {
+    match id.get(..2) {
+        Some(start) if start.chars().all(|c| c.is_ascii_hexdigit()) => start.to_ascii_lowercase(),
+        _ => "other".to_string(),
+    }
+}
+
+impl MessageIndex {
+    pub(crate) fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// True once the index has been built
+    pub(crate) fn exists(&self) -> bool {
+        self.dir.is_dir()
+    }
+
+    fn bucket_file(&self, bucket: &str) -> PathBuf {
+        self.dir.join(format!("{}.jsonl", bucket))
+    }
+
+    fn read_bucket(&self, bucket: &str) -> Result<Vec<IndexEntry>> {
+        let path = self.bucket_file(bucket);
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+        fs::read_to_string(&path)?
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| serde_json::from_str(line).with_context(|| format!("Failed to parse {}", path.display())))
+            .collect()
+    }
+
+    fn write_bucket(&self, bucket: &str, entries: &[IndexEntry]) -> Result<()> {
+        let path = self.bucket_file(bucket);
+        if entries.is_empty() {
+            return storage::wipe(&path);
+        }
+        let mut lines = String::new();
+        for entry in entries {
+            lines.push_str(&serde_json::to_string(entry)?);
+            lines.push('\n');
+        }
+        fs::write(&path, lines).with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// Every bucket that has entries
+    fn buckets(&self) -> Result<Vec<String>> {
+        let Ok(entries) = fs::read_dir(&self.dir) else { return Ok(Vec::new()) };
+        let mut buckets = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) == Some("jsonl") {
+                if let Some(stem) = path.file_stem().and_then(|stem| stem.to_str()) {
+                    buckets.push(stem.to_string());
+                }
+            }
+        }
+        Ok(buckets)
+    }
+
+    /// Record one appended message
+    pub(crate) fn add(&self, entry: &IndexEntry) -> Result<()> {
+        fs::create_dir_all(&self.dir).context("Failed to create message index")?;
+        let path = self.bucket_file(&bucket(&entry.id));
+        let mut file = OpenOptions::new().create(true).append(true).open(&path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        writeln!(file, "{}", serde_json::to_string(entry)?)?;
+        Ok(())
+    }
+
+    /// Replace a group's entries with `entries`; none removes the group
+    pub(crate) fn replace_group(&self, group_id: &str, entries: Vec<IndexEntry>) -> Result<()> {
+        fs::create_dir_all(&self.dir).context("Failed to create message index")?;
+        let mut buckets = self.buckets()?;
+        buckets.extend(entries.iter().map(|entry| bucket(&entry.id)));
+        buckets.sort();
+        buckets.dedup();
+        for name in buckets {
+            let mut kept: Vec<IndexEntry> = self.read_bucket(&name)?
+                .into_iter()
+                .filter(|entry| entry.group_id != group_id)
+                .collect();
+            kept.extend(entries.iter().filter(|entry| bucket(&entry.id) == name).cloned());
+            self.write_bucket(&name, &kept)?;
+        }
+        Ok(())
+    }
+
+    /// Start over with `entries`, the locations of every stored message
+    pub(crate) fn rebuild(&self, entries: Vec<IndexEntry>) -> Result<()> {
+        for name in self.buckets()? {
+            self.write_bucket(&name, &[])?;
+        }
+        fs::create_dir_all(&self.dir).context("Failed to create message index")?;
+        let mut buckets: HashMap<String, Vec<IndexEntry>> = HashMap::new();
+        for entry in entries {
+            buckets.entry(bucket(&entry.id)).or_default().push(entry);
+        }
+        for (name, entries) in buckets {
+            self.write_bucket(&name, &entries)?;
+        }
+        Ok(())
+    }
+
+    /// Entries whose ID is or starts with `prefix`
+    ///
+    /// A prefix shorter than a bucket name is looked up in every bucket.
+    pub(crate) fn lookup(&self, prefix: &str) -> Result<Vec<IndexEntry>> {
+        let buckets = match prefix.len() >= 2 {
+            true => vec![bucket(prefix)],
+            false => self.buckets()?,
+        };
+        let mut found = Vec::new();
+        for name in buckets {
+            found.extend(self.read_bucket(&name)?.into_iter().filter(|entry| entry.id.starts_with(prefix)));
+        }
+        Ok(found)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::{new_group, new_message};
+    use crate::content::Content;
+    use crate::model::{ChatGroup, ChatMessage, GroupPolicy};
+    use crate::storage::{FileStorage, Storage};
+    use chrono::Utc;
+
+    /// A fresh directory under the system's temporary directory
+    fn temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("mls-chat-test-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn entry(id: &str, group_id: &str, offset: u64) -> IndexEntry {
+        IndexEntry { id: id.to_string(), group_id: group_id.to_string(), offset }
+    }
+
+    fn ids(mut entries: Vec<IndexEntry>) -> Vec<String> {
+        entries.sort_by(|a, b| a.id.cmp(&b.id));
+        entries.into_iter().map(|entry| entry.id).collect()
+    }
+
+    #[test]
+    fn buckets_by_the_first_two_hex_digits() {
+        assert_eq!(bucket("3fa85f64-5717"), "3f");
+        assert_eq!(bucket("AB12"), "ab");
+        assert_eq!(bucket("zz-1"), "other");
+        assert_eq!(bucket("a"), "other");
+        assert_eq!(bucket("é1"), "other");
+    }
+
+    #[test]
+    fn looks_up_ids_and_prefixes() {
+        let dir = temp_dir();
+        let index = MessageIndex::new(dir.join("index"));
+        assert!(!index.exists());
+        assert!(index.lookup("ab").unwrap().is_empty());
+
+        index.add(&entry("ab01", "one", 0)).unwrap();
+        index.add(&entry("ab02", "one", 10)).unwrap();
+        index.add(&entry("cd01", "two", 0)).unwrap();
+        assert!(index.exists());
+        assert_eq!(index.lookup("ab01").unwrap(), [entry("ab01", "one", 0)]);
+        assert_eq!(ids(index.lookup("ab").unwrap()), ["ab01", "ab02"]);
+        // Shorter than a bucket name, so every bucket is read
+        assert_eq!(ids(index.lookup("").unwrap()), ["ab01", "ab02", "cd01"]);
+        assert_eq!(ids(index.lookup("c").unwrap()), ["cd01"]);
+        assert!(index.lookup("ef").unwrap().is_empty());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn replaces_one_group_and_rebuilds_all() {
+        let dir = temp_dir();
+        let index = MessageIndex::new(dir.join("index"));
+        index.add(&entry("ab01", "one", 0)).unwrap();
+        index.add(&entry("ab02", "two", 0)).unwrap();
+        index.add(&entry("cd01", "one", 10)).unwrap();
+
+        index.replace_group("one", vec![entry("ef01", "one", 0)]).unwrap();
+        assert_eq!(ids(index.lookup("").unwrap()), ["ab02", "ef01"]);
+        // The emptied bucket is removed rather than left as an empty file
+        assert!(!dir.join("index/cd.jsonl").exists());
+
+        index.replace_group("two", Vec::new()).unwrap();
+        assert_eq!(ids(index.lookup("").unwrap()), ["ef01"]);
+
+        index.rebuild(vec![entry("0a01", "three", 0), entry("zz01", "three", 5)]).unwrap();
+        assert_eq!(ids(index.lookup("").unwrap()), ["0a01", "zz01"]);
+        assert_eq!(index.lookup("zz").unwrap(), [entry("zz01", "three", 5)]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    fn message(group: &ChatGroup, id: &str, text: &str) -> ChatMessage {
+        let content = Content::Markdown { text: text.to_string() };
+        new_message(id.to_string(), "Alice".to_string(), content, String::new(), Utc::now(), group).unwrap()
+    }
+
+    #[test]
+    fn storage_rebuilds_a_missing_or_stale_index() {
+        let dir = temp_dir();
+        let group = new_group("Team", "Alice", "alice-key", GroupPolicy::default());
+        let mut storage = FileStorage::open(&dir).unwrap();
+        storage.save_group(&group).unwrap();
+        storage.append_message(&message(&group, "aa01", "one")).unwrap();
+        storage.append_message(&message(&group, "bb01", "two")).unwrap();
+        drop(storage);
+
+        // A profile from before the index existed
+        fs::remove_dir_all(dir.join("index")).unwrap();
+        let storage = FileStorage::open(&dir).unwrap();
+        assert_eq!(storage.find_messages("bb01").unwrap()[0].content, "two");
+
+        // The log rewritten behind the index's back, moving the second message to the front
+        let log = dir.join("messages").join(format!("{}.jsonl", group.group_id));
+        let mut lines: Vec<String> = fs::read_to_string(&log).unwrap().lines().map(str::to_string).collect();
+        lines.reverse();
+        fs::write(&log, lines.join("\n") + "\n").unwrap();
+        assert_eq!(storage.find_messages("aa01").unwrap()[0].content, "one");
+        assert_eq!(storage.find_messages("bb01").unwrap()[0].content, "two");
+
+        // And a message that is gone is not found, rather than another message at its offset
+        fs::write(&log, lines[1].clone() + "\n").unwrap();
+        assert!(storage.find_messages("bb01").unwrap().is_empty());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
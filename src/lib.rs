@@ -0,0 +1,97 @@
+//! MLS Chat: a minimal messaging engine demonstrating MLS protocol concepts
+//!
+//! The `mls-chat` binary is a thin CLI over this library. Library users can
+//! drive the same engine directly, for example with in-memory storage:
+//!
+//! ```
+//! use mls_chat::{MemoryStorage, MlsChatApp, UserName};
+//!
+//! # fn main() -> anyhow::Result<()> {
+//! let mut app = MlsChatApp::with_storage(Box::new(MemoryStorage::new()));
+//! app.init_user(UserName::Alice)?;
+//! app.create_group("TestGroup".to_string())?;
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod admin;
+pub mod agent;
+pub mod api;
+pub mod app;
+pub mod attest;
+pub mod bans;
+pub mod bench;
+pub mod bot;
+pub mod cache;
+pub mod client;
+pub mod commit;
+pub mod completions;
+pub mod config;
+pub mod content;
+pub mod continuity;
+pub mod crypto;
+pub mod debug;
+pub mod delivery;
+pub mod demo;
+pub mod devices;
+pub mod doctor;
+pub mod email;
+pub mod emoji;
+pub mod forward;
+pub mod gc;
+pub mod history;
+pub mod hooks;
+mod http;
+pub mod i18n;
+pub mod index;
+pub mod manpages;
+pub mod markdown;
+pub mod matrix;
+pub mod mentions;
+mod metrics;
+pub mod model;
+pub mod mqtt;
+pub mod network;
+pub mod notify;
+pub mod outbox;
+pub mod output;
+pub mod pins;
+pub mod plugins;
+pub mod policy;
+pub mod polls;
+pub mod preview;
+pub mod progress;
+pub mod prompt;
+pub mod protocol;
+pub mod proxy;
+pub mod receipts;
+pub mod reset;
+pub mod revocation;
+pub mod rotation;
+pub mod scheduled;
+pub mod server;
+pub mod session;
+pub mod snapshots;
+pub mod snippets;
+pub mod stats;
+pub mod storage;
+pub mod theme;
+pub mod timestamps;
+pub mod transcript;
+pub mod transfer;
+pub mod transparency;
+pub mod trust;
+pub mod transport;
+pub mod tree;
+pub mod wipe;
+pub mod wizard;
+
+pub use api::ApiServer;
+pub use app::MlsChatApp;
+pub use bot::{Bot, BotContext};
+pub use client::DeliveryClient;
+pub use server::DeliveryServer;
+pub use transport::Transport;
+pub use content::Content;
+pub use model::{Attachment, Ban, ChatGroup, ChatMessage, Ciphersuite, ContentType, GroupMetadata, GroupPolicy, IdentityStatus, JoinRule, KeyPackageStatus, Location, MockKey, MockMlsGroup, Mute, Notify, Provenance, ReceiptStatus, Retention, UserName};
+pub use storage::{FileStorage, MemoryStorage, Storage};
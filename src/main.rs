@@ -1,14 +1,48 @@
-use anyhow::{Context, Result};
-use chrono::{DateTime, Utc};
-use clap::{Parser, Subcommand};
-use colored::*;
-use serde::{Deserialize, Serialize};
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, TimeDelta, Utc};
+use clap::{ArgGroup, CommandFactory, Parser, Subcommand};
+use mls_chat::admin;
+use mls_chat::agent::{self, AgentClient};
+use mls_chat::attest;
+use mls_chat::bench;
+use mls_chat::api::{GroupView, MessageView, DEFAULT_API_ADDR};
+use mls_chat::app::{AddOutcome, ListOptions, MessageFilter};
+use mls_chat::completions::{self, Candidates, Shell};
+use mls_chat::config::{self, Config, ConfigValue};
+use mls_chat::debug;
+use mls_chat::demo;
+use mls_chat::delivery::DEFAULT_SERVER_ADDR;
+use mls_chat::devices::DEFAULT_DEVICE_ADDR;
+use mls_chat::doctor;
+use mls_chat::email::{EmailConfig, EmailTransport};
+use mls_chat::hooks::Hooks;
+use mls_chat::i18n;
+use mls_chat::manpages;
+use mls_chat::matrix::{BridgeMode, MatrixBridge, MatrixConfig, ACCESS_TOKEN_ENV};
+use mls_chat::mqtt::MqttTransport;
+use mls_chat::output;
+use mls_chat::plugins::{self, PluginContext};
+use mls_chat::policy::PolicyChange;
+use mls_chat::preview;
+use mls_chat::progress;
+use mls_chat::prompt;
+use mls_chat::proxy::Socks5Proxy;
+use mls_chat::reset::ResetScope;
+use mls_chat::scheduled;
+use mls_chat::server::{self, RateLimits, DEFAULT_MAX_MESSAGE_BYTES};
+use mls_chat::snippets;
+use mls_chat::theme::{self, Role};
+use mls_chat::timestamps::Timestamps;
+use mls_chat::transcript;
+use mls_chat::wipe;
+use mls_chat::{ApiServer, Bot, Content, ContentType, DeliveryClient, DeliveryServer, GroupPolicy, JoinRule, Location, MlsChatApp, Mute, Notify, Transport, UserName};
 use std::{
-    collections::HashMap,
-    fs,
-    path::Path,
+    env, io,
+    net::TcpListener,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    thread,
 };
-use uuid::Uuid;
 
 /// Minimal CLI-based messaging app demonstrating MLS protocol concepts
 #[derive(Parser)]
@@ -16,6 +50,28 @@ use uuid::Uuid;
 #[command(about = "End-to-end encrypted messaging using MLS protocol concepts")]
 #[command(version)]
 struct Cli {
+    /// Directory holding this profile's state [default: data-dir from the config file, else mls_chat_data]
+    #[arg(long, global = true)]
+    data_dir: Option<String>,
+    /// Delivery service address (host:port), MQTT broker (mqtt://host:port), or
+    /// email settings file (email:<path>); enables networked mode [default: server from the config file]
+    #[arg(long, global = true)]
+    server: Option<String>,
+    /// SOCKS5 proxy for reaching the delivery service or Matrix homeserver (socks5://[user:pass@]host:port)
+    #[arg(long, global = true)]
+    proxy: Option<Socks5Proxy>,
+    /// Namespace to use on a delivery service hosting several
+    #[arg(long, global = true)]
+    namespace: Option<String>,
+    /// Show message times in UTC, whatever the timezone setting
+    #[arg(long, global = true)]
+    utc: bool,
+    /// Screen-reader-friendly output: no colors, emoji, or separator lines, and one line per item
+    #[arg(long, global = true)]
+    plain: bool,
+    /// Go ahead with destructive commands without asking for confirmation
+    #[arg(long, short, global = true)]
+    yes: bool,
     #[command(subcommand)]
     command: Commands,
 }
@@ -24,366 +80,1870 @@ struct Cli {
 enum Commands {
     /// Initialize a new user identity
     Init {
-        /// User name (Alice or Bob)
+        /// User name (Alice or Bob) [default: identity from the config file]
         #[arg(value_enum)]
-        user: UserName,
+        user: Option<UserName>,
+    },
+    /// List this profile's identities, or switch the active one
+    Identity {
+        #[command(subcommand)]
+        action: IdentityAction,
+    },
+    /// Show the active identity, its fingerprint, key package, and where the profile is stored
+    Whoami,
+    /// Enter the passphrase now, keeping the profile unlocked until it goes unused
+    Unlock {
+        /// Passphrase the identity keys are sealed with; asked for if not given
+        #[arg(long)]
+        passphrase: Option<String>,
+    },
+    /// Lock the profile now, so the next command asks for the passphrase
+    Lock,
+    /// Hold the unlocked store key in memory and serve this profile's commands over a Unix socket
+    Agent {
+        /// Passphrase the identity keys are sealed with; asked for if not given
+        #[arg(long)]
+        passphrase: Option<String>,
     },
     /// Create a new group
     CreateGroup {
         /// Group name
-        name: String,
+        #[arg(required_unless_present = "interactive")]
+        name: Option<String>,
+        /// Ask for the name, ciphersuite, members, and retention, and confirm before creating
+        #[arg(long, short)]
+        interactive: bool,
+        /// Allow at most this many members
+        #[arg(long, value_name = "N", conflicts_with = "interactive", value_parser = clap::value_parser!(u32).range(1..))]
+        max_members: Option<u32>,
+        /// Whether anyone with the group's state may `join`, or only those added
+        #[arg(long, value_enum, default_value_t = JoinRule::InviteOnly, conflicts_with = "interactive")]
+        join: JoinRule,
+        /// Only admins (at first, you) may add members or let them join
+        #[arg(long, conflicts_with = "interactive")]
+        approval: bool,
+    },
+    /// Delete a group, wiping its messages and secrets; in networked mode, leave it first
+    DeleteGroup {
+        /// Group name or alias
+        group: String,
+        /// Only delete this profile's copy, without telling the other members
+        #[arg(long)]
+        local_only: bool,
+    },
+    /// Wipe a group, or every group and identity, from this profile
+    #[command(group(ArgGroup::new("scope").required(true).args(["group", "all"])))]
+    Reset {
+        /// Only this group (name or alias)
+        #[arg(long)]
+        group: Option<String>,
+        /// Every group, and every identity unless --keep-identity
+        #[arg(long)]
+        all: bool,
+        /// With --all, keep the identities and `login` tokens
+        #[arg(long, requires = "all")]
+        keep_identity: bool,
+    },
+    /// Change a group's shared name, topic, or description
+    Group {
+        #[command(subcommand)]
+        action: GroupAction,
+    },
+    /// List groups; archived ones only with --all
+    Groups {
+        /// Include archived groups
+        #[arg(long, short)]
+        all: bool,
+    },
+    /// Hide a group from `groups` until it is unarchived
+    Archive {
+        /// Group name or alias
+        group: String,
+    },
+    /// Show an archived group in `groups` again
+    Unarchive {
+        /// Group name or alias
+        group: String,
+    },
+    /// Stop announcing a group's new messages in `sync` and `list --follow`
+    Mute {
+        /// Group name or alias
+        group: String,
+        /// Unmute automatically after this long, e.g. 30m, 8h, or 2d [default: until unmuted]
+        #[arg(long, value_parser = parse_duration)]
+        duration: Option<TimeDelta>,
+    },
+    /// Announce a muted group's new messages again
+    Unmute {
+        /// Group name or alias
+        group: String,
+    },
+    /// Choose which of a group's new messages raise a desktop notification in `list --follow`
+    Notify {
+        /// Group name or alias
+        group: String,
+        /// Which messages: every message from someone else, or only those that @mention you
+        #[arg(value_enum)]
+        mode: Notify,
     },
     /// Add a member to the group
     AddMember {
-        /// Group name
+        /// Group name or alias
         group: String,
         /// Member to add (Alice or Bob)
         #[arg(value_enum)]
         member: UserName,
+        /// Then send them recent history, as `share-history` does
+        #[arg(long)]
+        share_history: bool,
+    },
+    /// Send a member this profile's messages from the group's last few epochs, encrypted to them
+    ShareHistory {
+        /// Group name or alias
+        group: String,
+        /// Member to send them to (Alice or Bob)
+        #[arg(value_enum)]
+        member: UserName,
+        /// Epochs back to reach [default: history.share-epochs from the config file, else 5]
+        #[arg(long)]
+        epochs: Option<u32>,
+    },
+    /// Join an open group this profile has, by external commit
+    Join {
+        /// Group name or alias
+        group: String,
+    },
+    /// Remove a member and keep them from being added or joining again
+    Ban {
+        /// Group name or alias
+        group: String,
+        /// Identity to ban, whether or not they are still a member
+        identity: String,
+    },
+    /// Lift a ban; the identity is not added back
+    Unban {
+        /// Group name or alias
+        group: String,
+        /// Banned identity
+        identity: String,
+    },
+    /// List the identities banned from the group
+    Bans {
+        /// Group name or alias
+        group: String,
+    },
+    /// Show the peer keys you have pinned, or accept one that changed
+    Trust {
+        #[command(subcommand)]
+        action: TrustAction,
+    },
+    /// Publish your key to the key transparency log, or show the log
+    Transparency {
+        #[command(subcommand)]
+        action: TransparencyAction,
+    },
+    /// Load or show the list of revoked credentials
+    Revocation {
+        #[command(subcommand)]
+        action: RevocationAction,
+    },
+    /// List the adds and joins waiting for an admin's approval
+    Requests {
+        /// Group name or alias
+        group: String,
+    },
+    /// Approve waiting requests, adding each group's members in one commit
+    Approve {
+        /// Request IDs, or enough of their start to be unique (see `requests`)
+        #[arg(required_unless_present = "all")]
+        request_ids: Vec<String>,
+        /// Approve every request waiting in this group or alias instead
+        #[arg(long, value_name = "GROUP", conflicts_with = "request_ids")]
+        all: Option<String>,
+    },
+    /// Deny a waiting request; its member is not added
+    Deny {
+        /// Request ID, or enough of its start to be unique (see `requests`)
+        request_id: String,
+    },
+    /// Rotate your key in the group with an Update commit
+    Update {
+        /// Group name or alias
+        group: String,
+    },
+    /// Walk through what the group's encryption protects against, on a scratch copy
+    Demo {
+        #[command(subcommand)]
+        action: DemoAction,
+    },
+    /// Add several members to the group in one commit
+    AddMembers {
+        /// Group name or alias
+        group: String,
+        /// Members to add (Alice or Bob)
+        #[arg(required = true)]
+        members: Vec<String>,
     },
     /// Send a message to the group
     Send {
-        /// Group name
-        group: String,
+        /// Group name or alias; given alone, it is the message and goes to default-group
+        #[arg(required_unless_present_any = ["file", "snippet"])]
+        group: Option<String>,
         /// Message content
-        message: String,
+        #[arg(conflicts_with_all = ["file", "snippet"])]
+        message: Option<String>,
+        /// Send as plain text, shown exactly as typed instead of rendered as Markdown
+        #[arg(long, conflicts_with = "file")]
+        text: bool,
+        /// Send a file instead; images can be previewed by `list`
+        #[arg(long, conflicts_with = "snippet")]
+        file: Option<PathBuf>,
+        /// Send a saved snippet instead (see `snippet add`), with its placeholders filled in
+        #[arg(long, value_name = "NAME")]
+        snippet: Option<String>,
+        /// Value for a snippet placeholder, e.g. `--var topic=Release`; repeat for more
+        #[arg(long = "var", value_name = "NAME=VALUE", value_parser = parse_var, requires = "snippet")]
+        vars: Vec<(String, String)>,
+        /// Hold the message until this time (UTC), e.g. "2024-06-01 09:00", or for a while, e.g. 30m
+        #[arg(long, value_parser = parse_at)]
+        at: Option<DateTime<Utc>>,
+    },
+    /// Keep unsent text for a group, and send it later
+    Draft {
+        #[command(subcommand)]
+        action: DraftAction,
+    },
+    /// List messages waiting to be sent by `send --at`, or cancel one
+    Scheduled {
+        #[command(subcommand)]
+        action: ScheduledAction,
+    },
+    /// Send a copy of a message to another group, noting where it came from
+    Forward {
+        /// Group the message is in
+        src_group: String,
+        /// Message ID, or enough of its start to be unique (see `list --ids`)
+        message_id: String,
+        /// Group to send the copy to
+        dst_group: String,
+    },
+    /// Share a location, which `list` shows with an OpenStreetMap link
+    #[command(allow_negative_numbers = true)]
+    SendLocation {
+        /// Group name or alias
+        group: String,
+        /// Latitude in degrees, negative for south
+        lat: f64,
+        /// Longitude in degrees, negative for west
+        lon: f64,
+        /// Name of the place, shown instead of the coordinates
+        #[arg(long)]
+        label: Option<String>,
     },
     /// List all messages in a group
     List {
-        /// Group name
+        /// Group name or alias [default: default-group from the config file]
+        group: Option<String>,
+        /// Mark your messages ✓ when delivered to every member and ✓✓ when read by all
+        #[arg(long)]
+        status: bool,
+        /// Keep printing new messages as they arrive, until Ctrl-C
+        #[arg(long, short)]
+        follow: bool,
+        /// Start each message with the first characters of its ID, for `pin` and `show`
+        #[arg(long)]
+        ids: bool,
+        /// Print messages as sent, without rendering Markdown
+        #[arg(long)]
+        raw: bool,
+        /// Show image attachments as their file name and size instead of drawing them
+        #[arg(long)]
+        no_preview: bool,
+        /// Only messages from this member
+        #[arg(long)]
+        from: Option<String>,
+        /// Only messages of this content type
+        #[arg(long = "type", value_enum)]
+        content_type: Option<ContentType>,
+        /// Only messages sent in this epoch
+        #[arg(long)]
+        epoch: Option<u32>,
+        /// Only the last N messages (after --from, --type, and --epoch)
+        #[arg(long, short = 'n', value_name = "N")]
+        limit: Option<usize>,
+    },
+    /// Pin a message at the top of `list` for every member
+    Pin {
+        /// Group name or alias
+        group: String,
+        /// Message ID, or enough of its start to be unique (see `list --ids`)
+        message_id: String,
+    },
+    /// Unpin a pinned message
+    Unpin {
+        /// Group name or alias
         group: String,
+        /// Message ID, or enough of its start to be unique
+        message_id: String,
+    },
+    /// Ask a group a question, vote, and see the results
+    Poll {
+        #[command(subcommand)]
+        action: PollAction,
+    },
+    /// List the messages in a group that mention you by @name
+    Mentions {
+        /// Group name or alias [default: default-group from the config file]
+        group: Option<String>,
+    },
+    /// Count a group's messages by member, day, epoch, and hour
+    Stats {
+        /// Group name or alias [default: default-group from the config file]
+        group: Option<String>,
+        /// Print JSON, whatever the output setting
+        #[arg(long)]
+        json: bool,
+    },
+    /// Show one message and its delivery status for each member
+    Show {
+        /// Message ID, or enough of its start to be unique
+        message_id: String,
     },
     /// Show group information
     Info {
-        /// Group name
+        /// Group name or alias [default: default-group from the config file]
+        group: Option<String>,
+    },
+    /// Register the current user with a delivery service and save the token in this profile
+    Login {
+        /// Delivery service address (host:port)
+        server: String,
+    },
+    /// Show which group members are online, and when others were last seen
+    Presence {
+        /// Group name or alias [default: default-group from the config file]
+        group: Option<String>,
+    },
+    /// Publish the current user's key package to the delivery service
+    Publish,
+    /// Fetch and apply incoming messages from the delivery service
+    Sync {
+        /// Group to re-fetch (with --from-seq)
+        #[arg(long, requires = "from_seq")]
+        group: Option<String>,
+        /// Re-fetch the group's messages from this sequence number onwards
+        #[arg(long, requires = "group")]
+        from_seq: Option<u64>,
+    },
+    /// Remove message logs and transfers left by groups that are gone, reporting the space reclaimed
+    Gc,
+    /// List messages the delivery service has not accepted yet, or retry or drop one
+    #[command(args_conflicts_with_subcommands = true)]
+    Outbox {
+        #[command(subcommand)]
+        action: Option<OutboxAction>,
+        /// Only this group (name or alias)
+        group: Option<String>,
+    },
+    /// List messages sent or received in chunks that have not finished, or resume or drop one
+    Transfers {
+        #[command(subcommand)]
+        action: Option<TransferAction>,
+    },
+    /// Run a delivery service
+    Serve {
+        /// Address to listen on
+        #[arg(long, default_value = DEFAULT_SERVER_ADDR)]
+        listen: String,
+        /// http:// URL to POST to when a message is queued for an offline user
+        #[arg(long)]
+        webhook: Option<String>,
+        /// Only serve users who have registered with `login`
+        #[arg(long)]
+        require_auth: bool,
+        /// Host an isolated namespace (NAME or NAME=ADMIN_TOKEN); may be repeated
+        #[arg(long = "add-namespace", value_name = "NAME[=ADMIN_TOKEN]")]
+        namespaces: Vec<String>,
+        /// Token for `serve-admin` calls to the default namespace; admin calls are refused without it
+        #[arg(long)]
+        admin_token: Option<String>,
+        /// Messages each user may send per minute (unlimited by default)
+        #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+        max_messages_per_minute: Option<u32>,
+        /// Key packages each user or address may fetch per minute (unlimited by default)
+        #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+        max_key_package_fetches_per_minute: Option<u32>,
+        /// Largest message payload accepted, in bytes
+        #[arg(long, default_value_t = DEFAULT_MAX_MESSAGE_BYTES)]
+        max_message_bytes: usize,
+        /// Address to serve Prometheus metrics on, at /metrics
+        #[arg(long)]
+        metrics_listen: Option<String>,
+        /// Name this server goes by among federated peers
+        #[arg(long)]
+        federation_name: Option<String>,
+        /// Federate with another server, signing envelopes with a secret only the two share; may be repeated
+        #[arg(long = "peer", value_name = "NAME=HOST:PORT=SECRET", requires = "federation_name")]
+        peers: Vec<String>,
+    },
+    /// Manage a running delivery service (--server, and --namespace if not the default)
+    ServeAdmin {
+        /// Admin token of the namespace
+        #[arg(long)]
+        admin_token: String,
+        #[command(subcommand)]
+        action: AdminAction,
+    },
+    /// Sync groups, history, and read state with another device of the same identity
+    Device {
+        #[command(subcommand)]
+        action: DeviceAction,
+    },
+    /// Bridge a group to another chat network
+    Bridge {
+        #[command(subcommand)]
+        network: BridgeNetwork,
+    },
+    /// Run this profile as a bot, handing events to the scripts in its hooks directory
+    Bot {
+        /// Directory holding on_message and on_member_added hooks [default: <data-dir>/hooks]
+        #[arg(long)]
+        hooks: Option<PathBuf>,
+    },
+    /// Serve a REST API for this profile
+    Api {
+        /// Address to listen on
+        #[arg(long, default_value = DEFAULT_API_ADDR)]
+        listen: String,
+        /// Bearer token clients must present
+        #[arg(long)]
+        token: String,
+    },
+    /// Check the profile and delivery service for problems, and say how to fix each
+    Doctor,
+    /// Time group creation, commits, encryption, and storage on each backend; needs no profile
+    Bench {
+        /// Members the benchmark group grows to, one add and commit each
+        #[arg(long, default_value_t = 100)]
+        members: usize,
+        /// Messages sent to it
+        #[arg(long, default_value_t = 1000)]
+        messages: usize,
+        /// Largest group the commit scaling table times a commit in
+        #[arg(long, default_value_t = 4096)]
+        scale: usize,
+        /// New members onboarded at once, to compare one commit with one per member
+        #[arg(long, default_value_t = 200)]
+        onboard: usize,
+    },
+    /// Write a group's message history to a JSON file
+    Export {
+        /// Group name or alias
+        group: String,
+        /// File to write
+        #[arg(long)]
+        out: PathBuf,
+        /// Chain the history with transcript hashes and sign it, for `verify-export`
+        #[arg(long)]
+        verified: bool,
+    },
+    /// Check a `export --verified` file's hash chain, epochs, and signature; needs no profile
+    VerifyExport {
+        /// File written by `export --verified`
+        file: PathBuf,
+    },
+    /// Write a signed statement of a group's epoch, tree hash, and members, or verify one
+    #[command(args_conflicts_with_subcommands = true)]
+    Attest {
+        #[command(subcommand)]
+        action: Option<AttestAction>,
+        /// Group name or alias
+        #[arg(required = true)]
+        group: Option<String>,
+        /// File to write the attestation to
+        #[arg(long, required = true)]
+        out: Option<PathBuf>,
+    },
+    /// Keep a copy of a group's whole state, secrets included, for `rollback` (debugging and teaching only)
+    Snapshot {
+        /// Group name or alias
+        group: String,
+    },
+    /// List a group's snapshots
+    Snapshots {
+        /// Group name or alias
+        group: String,
+    },
+    /// Put a group back to a snapshot; breaks forward secrecy and desyncs the group (debugging and teaching only)
+    Rollback {
+        /// Group name or alias
+        group: String,
+        /// Snapshot ID, or a unique prefix of it, from `snapshots`
+        snapshot_id: String,
+    },
+    /// Overwrite and delete the whole profile: keys, groups, messages, attachments, and logs
+    Wipe,
+    /// Inspect persisted state, e.g. to see what a commit changes
+    Debug {
+        #[command(subcommand)]
+        action: DebugAction,
+    },
+    /// Show or change settings in the config file
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+    /// Print a shell completion script (e.g. `source <(mls-chat completions bash)`)
+    Completions {
+        /// Shell to complete for
+        #[arg(value_enum)]
+        shell: Shell,
+    },
+    /// Write man pages for every command into a directory
+    Manpages {
+        /// Directory to write the pages to
+        dir: PathBuf,
+    },
+    /// List group aliases, or save one (e.g. `alias t Team`) in the config file
+    Alias {
+        /// Short name
+        #[arg(requires = "group")]
+        name: Option<String>,
+        /// Group the short name stands for
+        group: Option<String>,
+        /// Remove the alias instead
+        #[arg(long, value_name = "NAME", conflicts_with_all = ["name", "group"])]
+        remove: Option<String>,
+    },
+    /// Save canned messages in the config file, for `send --snippet`
+    Snippet {
+        #[command(subcommand)]
+        action: SnippetAction,
+    },
+    /// List plugin commands (mls-chat-<name> executables on PATH)
+    Plugins,
+    /// Print group or user names for the completion scripts, one per line
+    #[command(name = "__complete", hide = true)]
+    Complete {
+        /// What to list
+        #[arg(value_enum)]
+        kind: Candidates,
+        /// Group whose members are left out of `users`
+        group: Option<String>,
+    },
+    /// Run the mls-chat-<name> plugin for any other command
+    #[command(external_subcommand)]
+    Plugin(Vec<String>),
+}
+
+#[derive(Subcommand)]
+enum IdentityAction {
+    /// List identities, marking the active one with *
+    List,
+    /// Make another identity the active one
+    Use {
+        /// User name (Alice or Bob)
+        #[arg(value_enum)]
+        user: UserName,
+    },
+    /// Replace your identity key, signed by the old one, and tell your groups
+    Rotate,
+    /// Seal the identity keys under a passphrase, or change it
+    Protect {
+        /// New passphrase; asked for twice if not given
+        #[arg(long)]
+        passphrase: Option<String>,
+    },
+    /// Store the identity keys in the clear again
+    Unprotect,
+}
+
+#[derive(Subcommand)]
+enum GroupAction {
+    /// Set the name, topic, or description every member sees
+    #[command(group(ArgGroup::new("fields").required(true).multiple(true).args(["name", "topic", "description"])))]
+    Set {
+        /// Group name or alias
+        group: String,
+        /// New name for the group
+        #[arg(long)]
+        name: Option<String>,
+        /// One-line topic, shown above the messages; empty to clear
+        #[arg(long)]
+        topic: Option<String>,
+        /// Longer description, shown by `info`; empty to clear
+        #[arg(long)]
+        description: Option<String>,
+    },
+    /// Change how large the group may grow and who may bring members in; admins only
+    #[command(group(ArgGroup::new("rules").required(true).multiple(true).args(["max_members", "join", "approval", "add_admin", "remove_admin"])))]
+    Policy {
+        /// Group name or alias
         group: String,
+        /// Allow at most this many members; 0 for no limit
+        #[arg(long, value_name = "N")]
+        max_members: Option<usize>,
+        /// Whether anyone with the group's state may `join`, or only those added
+        #[arg(long, value_enum)]
+        join: Option<JoinRule>,
+        /// Whether only admins may add members or let them join
+        #[arg(long, value_name = "BOOL")]
+        approval: Option<bool>,
+        /// Make a member an admin; repeat for more
+        #[arg(long, value_name = "MEMBER")]
+        add_admin: Vec<String>,
+        /// Stop a member being an admin; repeat for more
+        #[arg(long, value_name = "MEMBER")]
+        remove_admin: Vec<String>,
     },
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
-enum UserName {
-    Alice,
-    Bob,
+#[derive(Subcommand)]
+enum DraftAction {
+    /// Save a draft, replacing any earlier one
+    Set {
+        /// Group name or alias
+        group: String,
+        /// Draft text
+        text: String,
+    },
+    /// Print a group's draft
+    Show {
+        /// Group name or alias
+        group: String,
+    },
+    /// Throw a group's draft away
+    Clear {
+        /// Group name or alias
+        group: String,
+    },
+    /// Send a group's draft as a message
+    Send {
+        /// Group name or alias
+        group: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum DemoAction {
+    /// Post-compromise security: show an Update locking out an attacker with an old epoch's secret
+    Pcs {
+        /// Group name or alias
+        group: String,
+    },
+    /// Forward secrecy: show that deleted epoch secrets leave old ciphertexts unrecoverable
+    Fs {
+        /// Group name or alias
+        group: String,
+    },
+    /// Key substitution: show a malicious delivery service caught by comparing fingerprints
+    Mitm,
+    /// Integrity: flip a bit in a message's ciphertext and show `list` flag it
+    Tamper {
+        /// Group name or alias
+        group: String,
+        /// Message ID from the current epoch, or enough of its start to be unique (see `list --ids`)
+        message_id: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum SnippetAction {
+    /// Save a snippet, replacing any of the same name
+    Add {
+        /// Snippet name
+        name: String,
+        /// Text to send; {group}, {user}, {date}, and {time} are filled in, and other {names} by `send --var`
+        text: String,
+    },
+    /// List snippets with the placeholders each needs
+    List,
+    /// Remove a snippet
+    Remove {
+        /// Snippet name
+        name: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum ScheduledAction {
+    /// List scheduled messages, soonest first
+    List,
+    /// Drop a scheduled message before it is sent
+    Cancel {
+        /// Scheduled message ID, or enough of its start to be unique (see `scheduled list`)
+        id: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum TrustAction {
+    /// List pinned peer keys and any changes waiting to be accepted
+    List,
+    /// Trust the new key seen for a user, after checking it with them
+    Accept {
+        #[arg(value_enum)]
+        user: UserName,
+    },
+}
+
+#[derive(Subcommand)]
+enum TransparencyAction {
+    /// Append your key to the log, unless it is already there
+    Publish,
+    /// Show the log's tree head and entries, or check one user's key against it
+    Show {
+        /// User whose entry and key to check
+        #[arg(value_enum)]
+        user: Option<UserName>,
+    },
+}
+
+#[derive(Subcommand)]
+enum OutboxAction {
+    /// Send a queued message again now
+    Retry {
+        /// Message ID, or enough of its start to be unique (see `outbox`)
+        id: String,
+    },
+    /// Stop trying to deliver a queued message; it stays in this profile's history
+    Drop {
+        /// Message ID, or enough of its start to be unique (see `outbox`)
+        id: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum TransferAction {
+    /// List unfinished uploads and downloads (the default)
+    List,
+    /// Send the rest of an upload, or fetch a download's missing chunks again
+    Resume {
+        /// Message ID, or enough of its start to be unique (see `transfers`)
+        id: String,
+    },
+    /// Give up on an upload or download
+    Drop {
+        /// Message ID, or enough of its start to be unique (see `transfers`)
+        id: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum RevocationAction {
+    /// Replace the revocation list with one read from a file, one key fingerprint per line
+    Update {
+        /// Revocation list file
+        file: String,
+    },
+    /// List the revoked credentials loaded
+    List,
+}
+
+#[derive(Subcommand)]
+enum PollAction {
+    /// Send a poll to a group
+    Create {
+        /// Group name or alias
+        group: String,
+        /// The question
+        question: String,
+        /// A choice members can vote for; give at least two
+        #[arg(long = "option", required = true, num_args = 1)]
+        options: Vec<String>,
+    },
+    /// Vote in a poll, replacing any earlier vote of yours
+    Vote {
+        /// Group name or alias
+        group: String,
+        /// Poll message ID, or enough of its start to be unique (see `list --ids`)
+        poll_id: String,
+        /// The option's text, or its number as `list` shows it
+        option: String,
+    },
+    /// Count the votes in a poll
+    Results {
+        /// Group name or alias
+        group: String,
+        /// Poll message ID, or enough of its start to be unique
+        poll_id: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum AdminAction {
+    /// List users with their account, presence, and key package
+    Users,
+    /// List groups the server has relayed messages for
+    Groups,
+    /// Remove key packages published more than a number of days ago
+    PurgeKeyPackages {
+        /// Age in days
+        #[arg(long, default_value_t = 30)]
+        older_than_days: u64,
+    },
+    /// Revoke a user's token, so they must `login` again
+    Revoke {
+        /// User name
+        user: String,
+    },
+    /// Show how many envelopes wait in each inbox
+    Queues,
+}
+
+#[derive(Subcommand)]
+enum ConfigAction {
+    /// Print one setting, or every setting in the file
+    Get {
+        /// Setting name, e.g. server or retention.max-age-days
+        key: Option<String>,
+    },
+    /// Check and save a setting
+    Set {
+        /// Setting name
+        key: String,
+        /// New value
+        #[arg(allow_hyphen_values = true)]
+        value: String,
+    },
+    /// Remove a setting, going back to the built-in default
+    Unset {
+        /// Setting name
+        key: String,
+    },
+    /// List the settings the file may hold
+    Keys,
+}
+
+#[derive(Subcommand)]
+enum AttestAction {
+    /// Check an attestation's signature and show what it says; needs no profile
+    Verify {
+        /// Attestation file written by `attest`
+        file: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+enum DeviceAction {
+    /// Write this device's snapshot to a file
+    Export {
+        /// Snapshot file to write
+        file: PathBuf,
+        /// Passphrase sealing the snapshot; asked for if not given
+        #[arg(long)]
+        passphrase: Option<String>,
+    },
+    /// Merge a snapshot file into this profile, or link an empty profile as a new device
+    Import {
+        /// Snapshot file to read
+        file: PathBuf,
+        /// Passphrase the snapshot was sealed with; asked for if not given
+        #[arg(long)]
+        passphrase: Option<String>,
+    },
+    /// Wait for another device to `pair` and swap snapshots with it
+    Listen {
+        /// Address to listen on
+        #[arg(long, default_value = DEFAULT_DEVICE_ADDR)]
+        listen: String,
+        /// Passphrase shared by both devices; asked for if not given
+        #[arg(long)]
+        passphrase: Option<String>,
+    },
+    /// Swap snapshots with a device running `device listen`
+    Pair {
+        /// Address of the listening device (host:port)
+        addr: String,
+        /// Passphrase shared by both devices; asked for if not given
+        #[arg(long)]
+        passphrase: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum DebugAction {
+    /// Print a group exactly as stored, MLS state and messages included, as JSON
+    State {
+        /// Group name or alias
+        group: String,
+    },
+    /// Show which fields differ between two saved `debug state` outputs
+    Diff {
+        /// State from before
+        old: PathBuf,
+        /// State from after
+        new: PathBuf,
+    },
 }
 
-impl std::fmt::Display for UserName {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            UserName::Alice => write!(f, "Alice"),
-            UserName::Bob => write!(f, "Bob"),
+#[derive(Subcommand)]
+enum BridgeNetwork {
+    /// Mirror a group into a Matrix room
+    Matrix {
+        /// Group name
+        group: String,
+        /// Homeserver base URL (http:// only)
+        #[arg(long)]
+        homeserver: String,
+        /// Room ID, e.g. '!abc123:example.org'
+        #[arg(long)]
+        room: String,
+        /// Access token of the Matrix account the bridge posts as [default: $MLS_CHAT_MATRIX_TOKEN, then matrix.access-token from the config file]
+        #[arg(long)]
+        access_token: Option<String>,
+        /// Whether the room sees ciphertext or cleartext
+        #[arg(long, value_enum, default_value_t = BridgeMode::Ciphertext)]
+        mode: BridgeMode,
+    },
+}
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("{} {:?}", theme::paint(Role::Error, "Error:"), err);
+            ExitCode::FAILURE
         }
     }
 }
 
-/// Mock cryptographic key for demonstration
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct MockKey {
-    id: String,
-    public_key: String,
-    private_key: String, // In real implementation, this would be encrypted
-}
-
-/// Mock MLS group state for demonstration
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct MockMlsGroup {
-    group_id: String,
-    epoch: u32,
-    tree_hash: String,
-    group_secret: String, // In real implementation, this would be encrypted
-    members: Vec<String>,
-}
-
-/// Represents a message in the MLS group
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct ChatMessage {
-    id: String,
-    sender: String,
-    content: String,
-    encrypted_content: String, // Mock encrypted content
-    timestamp: DateTime<Utc>,
-    group_id: String,
-    epoch: u32,
-}
-
-/// Represents a group in the MLS chat application
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct ChatGroup {
-    name: String,
-    group_id: String,
-    members: Vec<String>,
-    messages: Vec<ChatMessage>,
-    mls_group: MockMlsGroup,
-}
-
-/// Main application state
-struct MlsChatApp {
-    current_user: Option<UserName>,
-    groups: HashMap<String, ChatGroup>,
-    user_keys: HashMap<String, MockKey>,
-    data_dir: String,
-}
-
-impl MlsChatApp {
-    fn new() -> Result<Self> {
-        let data_dir = "mls_chat_data".to_string();
-        fs::create_dir_all(&data_dir).context("Failed to create data directory")?;
-        
-        Ok(Self {
-            current_user: None,
-            groups: HashMap::new(),
-            user_keys: HashMap::new(),
-            data_dir,
-        })
+fn run() -> Result<()> {
+    let cli = Cli::parse();
+    let mut config = Config::load()?;
+    config.apply_color();
+    output::set_plain(cli.plain);
+    if cli.plain || config.json_output() {
+        progress::disable();
+    }
+    i18n::init(config.string("language"));
+    match &cli.command {
+        Commands::Config { action } => return run_config(&mut config, action),
+        Commands::Snippet { action } => return run_snippet(&mut config, action),
+        Commands::Debug { action: DebugAction::Diff { old, new } } => return debug::print_diff(old, new),
+        Commands::Completions { shell } => {
+            print!("{}", completions::generate(*shell, &Cli::command()));
+            return Ok(());
+        }
+        Commands::Manpages { dir } => {
+            let pages = manpages::write_all(&Cli::command(), dir)?;
+            println!("✅ Wrote {} man page(s) to {}", pages.len(), dir.display());
+            return Ok(());
+        }
+        _ => {}
+    }
+    let data_dir = cli.data_dir.clone()
+        .or_else(|| config.string("data-dir").map(str::to_string))
+        .unwrap_or_else(|| "mls_chat_data".to_string());
+    let server = cli.server.clone().or_else(|| config.string("server").map(str::to_string));
+    if let Commands::Complete { kind, group } = &cli.command {
+        // Runs on every Tab press, so it never creates a profile and fails silently
+        if Path::new(&data_dir).is_dir() {
+            if let Ok(mut app) = MlsChatApp::open(&data_dir) {
+                if app.load_state().is_ok() {
+                    for name in completions::candidates(&app, *kind, group.as_deref()) {
+                        println!("{}", name);
+                    }
+                }
+            }
+        }
+        return Ok(());
+    }
+    let bridging = matches!(cli.command, Commands::Bridge { .. });
+    if cli.proxy.is_some() && server.is_none() && !bridging {
+        return Err(anyhow!("--proxy needs a delivery service; pass --server <addr>"));
     }
 
-    /// Initialize a new user identity
-    fn init_user(&mut self, user: UserName) -> Result<()> {
-        println!("{}", "Initializing user identity...".green());
-        
-        // Generate mock cryptographic keys
-        let key = MockKey {
-            id: Uuid::new_v4().to_string(),
-            public_key: format!("pub_key_{}", Uuid::new_v4()),
-            private_key: format!("priv_key_{}", Uuid::new_v4()),
-        };
-        
-        self.user_keys.insert(user.to_string(), key);
-        self.current_user = Some(user);
-        
-        println!("✅ User '{}' initialized successfully", user);
-        println!("   Generated cryptographic identity and key package");
-        self.save_state()?;
-        Ok(())
+    if let Commands::Serve {
+        listen,
+        webhook,
+        require_auth,
+        namespaces,
+        admin_token,
+        max_messages_per_minute,
+        max_key_package_fetches_per_minute,
+        max_message_bytes,
+        metrics_listen,
+        federation_name,
+        peers,
+    } = &cli.command
+    {
+        let mut server = DeliveryServer::new().with_limits(RateLimits {
+            messages_per_minute: *max_messages_per_minute,
+            key_package_fetches_per_minute: *max_key_package_fetches_per_minute,
+            max_message_bytes: *max_message_bytes,
+        });
+        if *require_auth {
+            server = server.require_auth();
+        }
+        if let Some(admin_token) = admin_token {
+            server = server.with_admin_token(admin_token.as_str());
+        }
+        if let Some(url) = webhook {
+            server = server.with_webhook(url.as_str())?;
+        }
+        for namespace in namespaces {
+            let (name, admin_token) = match namespace.split_once('=') {
+                Some((name, admin_token)) => (name, admin_token.to_string()),
+                None => {
+                    let admin_token = server::new_token();
+                    println!("🔐 Admin token for namespace '{}': {}", namespace, admin_token);
+                    (namespace.as_str(), admin_token)
+                }
+            };
+            server = server.with_namespace(name, admin_token)?;
+        }
+        if let Some(name) = federation_name {
+            server = server.with_federation(name.as_str());
+        }
+        for peer in peers {
+            let mut parts = peer.splitn(3, '=');
+            let (Some(name), Some(addr), Some(secret)) = (parts.next(), parts.next(), parts.next()) else {
+                return Err(anyhow!("Invalid --peer '{}'; expected NAME=HOST:PORT=SECRET", peer));
+            };
+            server = server.with_peer(name, addr, secret)?;
+        }
+        if let Some(addr) = metrics_listen {
+            let listener = TcpListener::bind(addr)
+                .with_context(|| format!("Failed to listen on {}", addr))?;
+            let server = server.clone();
+            thread::spawn(move || server.serve_metrics(listener));
+        }
+        let listener = TcpListener::bind(listen)
+            .with_context(|| format!("Failed to listen on {}", listen))?;
+        return server.serve(listener);
     }
 
-    /// Create a new MLS group
-    fn create_group(&mut self, name: String) -> Result<()> {
-        let user = self.current_user.context("No user initialized")?;
-        println!("{}", "Creating new MLS group...".green());
-        
-        // Verify user has keys
-        if !self.user_keys.contains_key(&user.to_string()) {
-            return Err(anyhow::anyhow!("User '{}' not initialized", user));
-        }
-        
-        // Create mock MLS group
-        let group_id = Uuid::new_v4().to_string();
-        let mls_group = MockMlsGroup {
-            group_id: group_id.clone(),
-            epoch: 1,
-            tree_hash: format!("tree_hash_{}", Uuid::new_v4()),
-            group_secret: format!("group_secret_{}", Uuid::new_v4()),
-            members: vec![user.to_string()],
-        };
-        
-        // Create chat group
-        let chat_group = ChatGroup {
-            name: name.clone(),
-            group_id: group_id.clone(),
-            members: vec![user.to_string()],
-            messages: Vec::new(),
-            mls_group,
+    let namespace = cli.namespace.as_deref();
+    if let Commands::ServeAdmin { admin_token, action } = &cli.command {
+        let server = server.as_deref().context("serve-admin needs --server <addr>")?;
+        let mut client = connect_delivery(server, cli.proxy.as_ref(), namespace)?.with_token(admin_token.as_str());
+        return match action {
+            AdminAction::Users => admin::show_users(&mut client),
+            AdminAction::Groups => admin::show_groups(&mut client),
+            AdminAction::PurgeKeyPackages { older_than_days } => {
+                let losses: Vec<String> = admin::stale_key_packages(&mut client, *older_than_days)?
+                    .into_iter()
+                    .map(|user| format!("{}'s key package is deleted; nobody can add {} until they `publish` again", user, user))
+                    .collect();
+                match prompt::confirm_loss(&mut io::stdin().lock(), &losses, cli.yes)? {
+                    true => admin::purge_key_packages(&mut client, *older_than_days),
+                    false => Ok(()),
+                }
+            }
+            AdminAction::Revoke { user } => {
+                let losses = [format!("{}'s token stops working and their subscriptions end, until they `login` again", user)];
+                match prompt::confirm_loss(&mut io::stdin().lock(), &losses, cli.yes)? {
+                    true => admin::revoke_token(&mut client, user),
+                    false => Ok(()),
+                }
+            }
+            AdminAction::Queues => admin::show_queues(&mut client),
         };
-        
-        self.groups.insert(name.clone(), chat_group);
-        println!("✅ Group '{}' created successfully", name);
-        println!("   MLS Group ID: {}", group_id);
-        println!("   Initial epoch: 1");
-        println!("   Group secret generated and distributed");
-        self.save_state()?;
-        Ok(())
     }
 
-    /// Add a member to an existing group
-    fn add_member(&mut self, group_name: String, member: UserName) -> Result<()> {
-        let _user = self.current_user.context("No user initialized")?;
-        println!("{}", "Adding member to group...".green());
-        
-        let group = self.groups.get_mut(&group_name)
-            .context("Group not found")?;
-        
-        if group.members.contains(&member.to_string()) {
-            println!("⚠️  Member '{}' is already in the group", member);
-            return Ok(());
-        }
-        
-        // Verify member has keys
-        if !self.user_keys.contains_key(&member.to_string()) {
-            return Err(anyhow::anyhow!("Member '{}' not initialized", member));
-        }
-        
-        // Simulate MLS add proposal and commit
-        println!("   Creating Add proposal for '{}'", member);
-        println!("   Generating new group secret");
-        println!("   Distributing updated keys to all members");
-        
-        // Update group state
-        group.mls_group.epoch += 1;
-        group.mls_group.group_secret = format!("group_secret_{}", Uuid::new_v4());
-        group.mls_group.tree_hash = format!("tree_hash_{}", Uuid::new_v4());
-        group.members.push(member.to_string());
-        group.mls_group.members = group.members.clone();
-        
-        println!("✅ Member '{}' added to group '{}'", member, group_name);
-        println!("   Epoch updated to: {}", group.mls_group.epoch);
-        println!("   Group secret rotated for security");
-        self.save_state()?;
-        Ok(())
+    if let Commands::VerifyExport { file } = &cli.command {
+        // Verified against the file alone, so it works away from the original profile
+        let (export, summary) = transcript::verify_export(file)?;
+        println!("✅ Transcript verified: {} message(s) in {} epoch(s)", summary.messages, summary.epochs);
+        export.print();
+        return Ok(());
     }
 
-    /// Send a message to a group
-    fn send_message(&mut self, group_name: String, content: String) -> Result<()> {
-        let _user = self.current_user.context("No user initialized")?;
-        println!("{}", "Sending encrypted message...".green());
-        
-        let group = self.groups.get_mut(&group_name)
-            .context("Group not found")?;
-        
-        // Verify user is a member
-        if !group.members.contains(&_user.to_string()) {
-            return Err(anyhow::anyhow!("User '{}' is not a member of group '{}'", _user, group_name));
-        }
-        
-        // Simulate MLS message encryption
-        println!("   Encrypting message with group secret");
-        println!("   Using epoch: {}", group.mls_group.epoch);
-        
-        let encrypted_content = format!("encrypted_{}_{}", 
-            group.mls_group.group_secret[..16].to_string(), 
-            content.replace(" ", "_")
-        );
-        
-        // Create chat message
-        let chat_message = ChatMessage {
-            id: Uuid::new_v4().to_string(),
-            sender: _user.to_string(),
-            content: content.clone(),
-            encrypted_content,
-            timestamp: Utc::now(),
-            group_id: group.group_id.clone(),
-            epoch: group.mls_group.epoch,
-        };
-        
-        group.messages.push(chat_message);
-        
-        println!("✅ Message sent successfully");
-        println!("   Message encrypted with group key");
-        println!("   Forward secrecy maintained");
-        self.save_state()?;
-        Ok(())
+    if let Commands::Attest { action: Some(AttestAction::Verify { file }), .. } = &cli.command {
+        // An auditor may have no profile at all
+        let attestation = attest::verify_file(file)?;
+        println!("✅ Signature verified");
+        attestation.print();
+        return Ok(());
     }
 
-    /// List all messages in a group
-    fn list_messages(&self, group_name: String) -> Result<()> {
-        let group = self.groups.get(&group_name)
-            .context("Group not found")?;
-        
-        println!("{}", format!("Messages in group '{}':", group_name).blue());
-        println!("{}", "=".repeat(50));
-        println!("Group ID: {}", group.group_id);
-        println!("Current Epoch: {}", group.mls_group.epoch);
-        println!("Members: {}", group.members.join(", "));
-        println!("{}", "=".repeat(50));
-        
-        if group.messages.is_empty() {
-            println!("No messages yet.");
-        } else {
-            for message in &group.messages {
-                println!("[{}] {} (Epoch {}): {}", 
-                    message.timestamp.format("%H:%M:%S"),
-                    message.sender.yellow(),
-                    message.epoch,
-                    message.content
-                );
-                println!("   Encrypted: {}", message.encrypted_content.dimmed());
-            }
+    if let Commands::Wipe = &cli.command {
+        // Before opening the profile, so a locked or damaged one can be wiped too
+        let losses = wipe::wipe_losses(Path::new(&data_dir))?;
+        if prompt::confirm_loss(&mut io::stdin().lock(), &losses, cli.yes)? {
+            let report = wipe::wipe_profile(Path::new(&data_dir))?;
+            println!("🗑️  Wiped {} file(s), {}, and removed {}", report.files, preview::human_size(report.bytes), data_dir);
         }
-        Ok(())
+        return Ok(());
     }
 
-    /// Show group information
-    fn show_group_info(&self, group_name: String) -> Result<()> {
-        let group = self.groups.get(&group_name)
-            .context("Group not found")?;
-        
-        println!("{}", format!("Group: {}", group_name).blue());
-        println!("{}", "=".repeat(30));
-        println!("Group ID: {}", group.group_id);
-        println!("Current Epoch: {}", group.mls_group.epoch);
-        println!("Tree Hash: {}", group.mls_group.tree_hash);
-        println!("Members: {}", group.members.join(", "));
-        println!("Message count: {}", group.messages.len());
-        println!("Group Secret: {}...", &group.mls_group.group_secret[..20]);
-        Ok(())
+    if let Commands::Bench { members, messages, scale, onboard } = &cli.command {
+        // Runs in scratch directories of its own, leaving every profile alone
+        progress::spin("Benchmarking", || bench::run(*members, *messages, *scale, *onboard))?.print();
+        return Ok(());
     }
 
-    /// Save application state to disk
-    fn save_state(&self) -> Result<()> {
-        let state_file = format!("{}/app_state.json", self.data_dir);
-        let state = serde_json::to_string_pretty(&self.groups)?;
-        fs::write(state_file, state)?;
-        
-        let keys_file = format!("{}/user_keys.json", self.data_dir);
-        let keys_state = serde_json::to_string_pretty(&self.user_keys)?;
-        fs::write(keys_file, keys_state)?;
-        
-        let current_user_file = format!("{}/current_user.json", self.data_dir);
-        if let Some(user) = &self.current_user {
-            let user_state = serde_json::to_string_pretty(&user)?;
-            fs::write(current_user_file, user_state)?;
+    if let Commands::Doctor = &cli.command {
+        // Before opening the profile, which would fail on the damage doctor is there to report
+        let account = server.as_deref().map(|server| account(server, namespace));
+        let report = doctor::diagnose(Path::new(&data_dir), account.as_deref(), |token| {
+            let server = server.as_deref().context("No server")?;
+            progress::spin("Connecting to the delivery service", || connect_transport(server, cli.proxy.as_ref(), namespace, token))
+        });
+        match config.json_output() {
+            true => println!("{}", serde_json::to_string_pretty(&report)?),
+            false => report.print(),
         }
-        Ok(())
+        return report.into_result();
     }
 
-    /// Load application state from disk
-    fn load_state(&mut self) -> Result<()> {
-        let state_file = format!("{}/app_state.json", self.data_dir);
-        if Path::new(&state_file).exists() {
-            let data = fs::read_to_string(state_file)?;
-            self.groups = serde_json::from_str(&data)?;
-        }
-        
-        let keys_file = format!("{}/user_keys.json", self.data_dir);
-        if Path::new(&keys_file).exists() {
-            let data = fs::read_to_string(keys_file)?;
-            self.user_keys = serde_json::from_str(&data)?;
-        }
-        
-        let current_user_file = format!("{}/current_user.json", self.data_dir);
-        if Path::new(&current_user_file).exists() {
-            let data = fs::read_to_string(current_user_file)?;
-            self.current_user = serde_json::from_str(&data)?;
+    let mut app = match &cli.command {
+        // These stay up and read the same state over and over
+        Commands::List { follow: true, .. } | Commands::Bot { .. } | Commands::Api { .. } => MlsChatApp::open_cached(&data_dir, config.cached_messages())?,
+        _ => MlsChatApp::open(&data_dir)?,
+    };
+    app.set_retention(config.retention());
+    app.set_rotation(config.rotation());
+    if let Some(log) = config.transparency_log() {
+        app.set_transparency_log(log);
+    }
+    app.set_aliases(config.aliases());
+    app.set_emoji_shortcodes(config.boolean("emoji-shortcodes").unwrap_or(true));
+    app.set_idle_timeout(config.idle_timeout());
+    app.set_wipe_after_failures(config.wipe_after_failures());
+    app.set_history_epochs(config.history_epochs());
+    app.set_notifier(config.notifier());
+    app.set_agent(AgentClient::new(agent::socket_path(Path::new(&data_dir))));
+    app.load_state()?;
+    match &cli.command {
+        Commands::Lock => return app.lock(),
+        Commands::Agent { passphrase } => return app.run_agent(passphrase.as_deref()),
+        Commands::Unlock { passphrase } => app.unlock_identities(passphrase.as_deref())?,
+        _ => app.unlock_identities(None)?,
+    }
+    if let Some(server) = &server {
+        let token = app.server_token(&account(server, namespace))?;
+        app.set_transport(connect_transport(server, cli.proxy.as_ref(), namespace, token)?, server);
+    }
+    if sends_messages(&cli.command) {
+        // Other commands leave the queues to `outbox retry` and later sends
+        if let Err(err) = app.deliver_outbox() {
+            println!("{}", output::words(&format!("⚠️  Could not deliver queued messages: {:#}", err)));
+        }
+        if let Err(err) = app.send_due_scheduled() {
+            println!("{}", output::words(&format!("⚠️  Could not send scheduled messages: {:#}", err)));
         }
-        Ok(())
     }
-}
 
-fn main() -> Result<()> {
-    let cli = Cli::parse();
-    
-    let mut app = MlsChatApp::new()?;
-    app.load_state()?;
-    
     match cli.command {
         Commands::Init { user } => {
+            let user = user.or_else(|| config.identity())
+                .context("Name a user to initialize, or set one with `config set identity <user>`")?;
             app.init_user(user)?;
         }
-        Commands::CreateGroup { name } => {
-            app.create_group(name)?;
+        Commands::Identity { action: IdentityAction::List } => {
+            app.list_identities();
+        }
+        Commands::Identity { action: IdentityAction::Use { user } } => {
+            app.use_identity(user)?;
+        }
+        Commands::Identity { action: IdentityAction::Rotate } => {
+            app.rotate_identity()?;
+        }
+        Commands::Identity { action: IdentityAction::Protect { passphrase } } => {
+            app.protect_identities(passphrase.as_deref())?;
+        }
+        Commands::Identity { action: IdentityAction::Unprotect } => {
+            app.unprotect_identities()?;
+        }
+        Commands::Export { group, out, verified } => {
+            let group = app.resolve_group(&group)?;
+            app.export_transcript(&group, &out, verified)?;
+        }
+        Commands::Attest { action: None, group, out } => {
+            let group = app.resolve_group(&group.context("Name the group to attest")?)?;
+            app.attest_group(&group, &out.context("Give the file to write with --out")?)?;
+        }
+        Commands::Snapshot { group } => {
+            let group = app.resolve_group(&group)?;
+            app.snapshot_group(&group)?;
+        }
+        Commands::Snapshots { group } => {
+            let group = app.resolve_group(&group)?;
+            app.list_snapshots(&group)?;
+        }
+        Commands::Rollback { group, snapshot_id } => {
+            let group = app.resolve_group(&group)?;
+            if prompt::confirm_loss(&mut io::stdin().lock(), &app.rollback_losses(&group, &snapshot_id)?, cli.yes)? {
+                app.rollback_group(&group, &snapshot_id)?;
+            }
+        }
+        Commands::Unlock { .. } => {
+            app.show_session();
+        }
+        Commands::Lock | Commands::Agent { .. } => unreachable!("handled before the profile is unlocked"),
+        Commands::Whoami if config.json_output() => {
+            println!("{}", serde_json::to_string_pretty(&app.identity_status()?)?);
+        }
+        Commands::Whoami => {
+            app.show_identity()?;
+        }
+        Commands::CreateGroup { name, interactive: true, .. } => {
+            app.create_group_interactive(name, &mut io::stdin().lock())?;
+        }
+        Commands::CreateGroup { name, interactive: false, max_members, join, approval } => {
+            let policy = GroupPolicy { max_members: max_members.map(|max| max as usize), join, approval, ..GroupPolicy::default() };
+            app.create_group_with_policy(name.context("Name the group, or pass --interactive")?, policy)?;
+        }
+        Commands::DeleteGroup { group, local_only } => {
+            let group = app.resolve_group(&group)?;
+            let info = app.group(&group).context("Group not found")?;
+            let user = app.current_user().map(|user| user.to_string()).unwrap_or_default();
+            let others: Vec<String> = info.members.iter().filter(|member| **member != user).cloned().collect();
+            let mut losses = vec![
+                format!("Group '{}' and its {} message(s) are deleted from this profile", group, info.message_count),
+                "Its group secret is wiped, so this profile cannot decrypt the group's history again".to_string(),
+            ];
+            match (server.is_some(), local_only) {
+                (true, false) if !others.is_empty() => {
+                    losses.push(format!("You leave the group; the other members ({}) continue at a new epoch without you", others.join(", ")));
+                }
+                (true, true) => {
+                    losses.push("Other members still list you, and `sync` reports what they send as an unknown group".to_string());
+                }
+                _ => {}
+            }
+            if prompt::confirm_loss(&mut io::stdin().lock(), &losses, cli.yes)? {
+                app.delete_group(&group, local_only)?;
+            }
+        }
+        Commands::Reset { group, all: _, keep_identity } => {
+            let scope = match group {
+                Some(group) => ResetScope::Group(app.resolve_group(&group)?),
+                None => ResetScope::All { keep_identity },
+            };
+            if prompt::confirm_loss(&mut io::stdin().lock(), &app.reset_losses(&scope)?, cli.yes)? {
+                let removed = app.reset(&scope)?;
+                forget_groups(&mut config, &removed)?;
+            }
+        }
+        Commands::Group { action: GroupAction::Set { group, name, topic, description } } => {
+            let group = app.resolve_group(&group)?;
+            app.set_group_metadata(&group, name, topic, description)?;
+        }
+        Commands::Group { action: GroupAction::Policy { group, max_members, join, approval, add_admin, remove_admin } } => {
+            let group = app.resolve_group(&group)?;
+            let change = PolicyChange {
+                max_members: max_members.map(|max| Some(max).filter(|max| *max > 0)),
+                join,
+                approval,
+                add_admins: add_admin,
+                remove_admins: remove_admin,
+            };
+            app.set_group_policy(&group, &change)?;
+        }
+        Commands::Join { group } => {
+            app.join_group(&app.resolve_group(&group)?)?;
+        }
+        Commands::Ban { group, identity } => {
+            app.ban_member(&app.resolve_group(&group)?, &identity)?;
+        }
+        Commands::Unban { group, identity } => {
+            app.unban_member(&app.resolve_group(&group)?, &identity)?;
+        }
+        Commands::Bans { group } => {
+            app.list_bans(&app.resolve_group(&group)?)?;
+        }
+        Commands::Trust { action: TrustAction::List } => {
+            app.list_trust()?;
+        }
+        Commands::Trust { action: TrustAction::Accept { user } } => {
+            app.trust_accept(user)?;
+        }
+        Commands::Transparency { action: TransparencyAction::Publish } => {
+            app.publish_to_transparency_log()?;
+        }
+        Commands::Transparency { action: TransparencyAction::Show { user } } => {
+            app.show_transparency(user.map(|user| user.to_string()).as_deref())?;
+        }
+        Commands::Revocation { action: RevocationAction::Update { file } } => {
+            app.update_revocations(&file)?;
+        }
+        Commands::Revocation { action: RevocationAction::List } => {
+            app.list_revocations()?;
+        }
+        Commands::Requests { group } => {
+            app.list_requests(&app.resolve_group(&group)?)?;
+        }
+        Commands::Approve { all: Some(group), .. } => {
+            app.approve_all(&app.resolve_group(&group)?)?;
+        }
+        Commands::Approve { request_ids, all: None } => {
+            app.approve_requests(&request_ids)?;
+        }
+        Commands::Deny { request_id } => {
+            app.deny_request(&request_id)?;
+        }
+        Commands::Groups { all } if config.json_output() => {
+            let mut groups: Vec<GroupView> = app.groups()
+                .filter(|group| all || !group.archived)
+                .map(GroupView::from)
+                .collect();
+            groups.sort_by(|a, b| a.name.cmp(&b.name));
+            println!("{}", serde_json::to_string_pretty(&groups)?);
+        }
+        Commands::Groups { all } => {
+            app.list_groups(all);
+        }
+        Commands::Archive { group } => {
+            app.set_archived(&app.resolve_group(&group)?, true)?;
+        }
+        Commands::Unarchive { group } => {
+            app.set_archived(&app.resolve_group(&group)?, false)?;
+        }
+        Commands::Mute { group, duration } => {
+            let mute = match duration {
+                Some(duration) => Mute::Until(Utc::now() + duration),
+                None => Mute::Forever,
+            };
+            app.set_mute(&app.resolve_group(&group)?, Some(mute))?;
+        }
+        Commands::Unmute { group } => {
+            app.set_mute(&app.resolve_group(&group)?, None)?;
+        }
+        Commands::Notify { group, mode } => {
+            app.set_notify(&app.resolve_group(&group)?, mode)?;
+        }
+        Commands::AddMember { group, member, share_history } => {
+            let group = app.resolve_group(&group)?;
+            app.add_member(group.clone(), member)?;
+            if share_history && app.group(&group).is_some_and(|group| group.members.contains(&member.to_string())) {
+                app.share_history(&group, &member.to_string(), None)?;
+            }
+        }
+        Commands::ShareHistory { group, member, epochs } => {
+            let group = app.resolve_group(&group)?;
+            app.share_history(&group, &member.to_string(), epochs)?;
+        }
+        Commands::Update { group } => {
+            app.self_update(&app.resolve_group(&group)?)?;
+        }
+        Commands::Demo { action: DemoAction::Mitm } => {
+            demo::mitm()?;
+        }
+        Commands::Demo { action: DemoAction::Pcs { group } } => {
+            app.demo_pcs(&app.resolve_group(&group)?)?;
+        }
+        Commands::Demo { action: DemoAction::Fs { group } } => {
+            app.demo_fs(&app.resolve_group(&group)?)?;
+        }
+        Commands::Demo { action: DemoAction::Tamper { group, message_id } } => {
+            let group = app.resolve_group(&group)?;
+            app.demo_tamper(&group, &message_id)?;
+        }
+        Commands::AddMembers { group, members } => {
+            let group = app.resolve_group(&group)?;
+            let outcomes = app.add_members(&group, &members)?;
+            let failed = outcomes.iter().filter(|(_, outcome)| matches!(outcome, AddOutcome::Failed(_))).count();
+            if failed > 0 {
+                return Err(anyhow!("{} of {} member(s) could not be added", failed, outcomes.len()));
+            }
+        }
+        Commands::Send { group, snippet: Some(snippet), vars, text, at, .. } => {
+            let template = config.snippets().remove(&snippet)
+                .with_context(|| format!("No snippet '{}'; add one with `mls-chat snippet add {} <text>`", snippet, snippet))?;
+            let group = pick_group(&app, &config, group)?;
+            let message = app.expand_snippet(&group, &template, &vars)?;
+            send_or_schedule(&mut app, group, typed_text(message, text), at)?;
+        }
+        Commands::Send { group, file: Some(file), at, .. } => {
+            let group = pick_group(&app, &config, group)?;
+            send_or_schedule(&mut app, group, Content::from_file(&file)?, at)?;
+        }
+        Commands::Send { group: Some(group), message: Some(message), text, file: None, at, .. } => {
+            let group = app.resolve_group(&group)?;
+            send_or_schedule(&mut app, group, typed_text(message, text), at)?;
+        }
+        Commands::Send { group: message, message: None, text, file: None, at, .. } => {
+            let message = message.context("Give a message to send")?;
+            let group = pick_group(&app, &config, None)?;
+            send_or_schedule(&mut app, group, typed_text(message, text), at)?;
+        }
+        Commands::Send { group: None, message: Some(_), .. } => {
+            unreachable!("clap fills the group before the message")
+        }
+        Commands::Draft { action: DraftAction::Set { group, text } } => {
+            app.set_draft(&app.resolve_group(&group)?, Some(text))?;
+        }
+        Commands::Draft { action: DraftAction::Show { group } } => {
+            let group = app.resolve_group(&group)?;
+            match app.group(&group).and_then(|group| group.draft.as_deref()) {
+                Some(draft) => println!("{}", draft),
+                None => println!("No draft for '{}'", group),
+            }
+        }
+        Commands::Draft { action: DraftAction::Clear { group } } => {
+            app.set_draft(&app.resolve_group(&group)?, None)?;
+        }
+        Commands::Draft { action: DraftAction::Send { group } } => {
+            app.send_draft(app.resolve_group(&group)?)?;
+        }
+        Commands::Scheduled { action: ScheduledAction::List } => {
+            app.list_scheduled();
+        }
+        Commands::Scheduled { action: ScheduledAction::Cancel { id } } => {
+            app.cancel_scheduled(&id)?;
+        }
+        Commands::Forward { src_group, message_id, dst_group } => {
+            let (source, destination) = (app.resolve_group(&src_group)?, app.resolve_group(&dst_group)?);
+            app.forward_message(&source, &message_id, destination)?;
+        }
+        Commands::SendLocation { group, lat, lon, label } => {
+            let group = app.resolve_group(&group)?;
+            app.send_content(group, Content::Location(Location::new(lat, lon, label)?))?;
+        }
+        Commands::List { group, follow: false, from, content_type, epoch, limit, .. } if config.json_output() => {
+            let filter = MessageFilter { from, content_type, epoch, last: limit };
+            let group = pick_group(&app, &config, group)?;
+            let mut array = JsonArray::default();
+            app.for_each_message(&group, &filter, |message| array.push(&MessageView::from(message)))?;
+            array.finish();
+        }
+        Commands::List { group, status, follow, ids, raw, no_preview, from, content_type, epoch, limit } => {
+            let group = pick_group(&app, &config, group)?;
+            let preview = if no_preview || output::is_plain() { None } else { preview::detect(config.string("image-preview")) };
+            let options = ListOptions { status, ids, raw, preview, times: timestamps(&config, cli.utc) };
+            let filter = MessageFilter { from, content_type, epoch, last: limit };
+            app.list_messages(group.clone(), &options, &filter)?;
+            if follow {
+                app.follow_messages(&group, &options, &filter)?;
+            }
+        }
+        Commands::Pin { group, message_id } => {
+            app.set_pinned(&app.resolve_group(&group)?, &message_id, true)?;
+        }
+        Commands::Unpin { group, message_id } => {
+            app.set_pinned(&app.resolve_group(&group)?, &message_id, false)?;
+        }
+        Commands::Poll { action: PollAction::Create { group, question, options } } => {
+            app.create_poll(app.resolve_group(&group)?, question, options)?;
+        }
+        Commands::Poll { action: PollAction::Vote { group, poll_id, option } } => {
+            app.vote(&app.resolve_group(&group)?, &poll_id, &option)?;
         }
-        Commands::AddMember { group, member } => {
-            app.add_member(group, member)?;
+        Commands::Poll { action: PollAction::Results { group, poll_id } } if config.json_output() => {
+            let results = app.poll_results(&app.resolve_group(&group)?, &poll_id)?;
+            println!("{}", serde_json::to_string_pretty(&results)?);
         }
-        Commands::Send { group, message } => {
-            app.send_message(group, message)?;
+        Commands::Poll { action: PollAction::Results { group, poll_id } } => {
+            app.show_poll_results(&app.resolve_group(&group)?, &poll_id)?;
         }
-        Commands::List { group } => {
-            app.list_messages(group)?;
+        Commands::Mentions { group } if config.json_output() => {
+            let messages = app.mentions_of_user(&pick_group(&app, &config, group)?)?;
+            let messages: Vec<MessageView> = messages.iter().map(MessageView::from).collect();
+            println!("{}", serde_json::to_string_pretty(&messages)?);
+        }
+        Commands::Mentions { group } => {
+            let options = ListOptions { times: timestamps(&config, cli.utc), ..ListOptions::default() };
+            app.list_mentions(&pick_group(&app, &config, group)?, &options)?;
+        }
+        Commands::Stats { group, json } if json || config.json_output() => {
+            let stats = app.group_stats(&pick_group(&app, &config, group)?)?;
+            println!("{}", serde_json::to_string_pretty(&stats)?);
+        }
+        Commands::Stats { group, .. } => {
+            app.show_stats(&pick_group(&app, &config, group)?)?;
+        }
+        Commands::Show { message_id } => {
+            app.show_message(&message_id, &timestamps(&config, cli.utc))?;
+        }
+        Commands::Info { group } if config.json_output() => {
+            let group = pick_group(&app, &config, group)?;
+            let group = app.group(&group).context("Group not found")?;
+            println!("{}", serde_json::to_string_pretty(&GroupView::from(group))?);
         }
         Commands::Info { group } => {
-            app.show_group_info(group)?;
+            let group = pick_group(&app, &config, group)?;
+            app.show_group_info(group.clone())?;
+            app.flag_revoked(&group)?;
+        }
+        Commands::Login { server } => {
+            app.login(&account(&server, namespace), connect_delivery(&server, cli.proxy.as_ref(), namespace)?)?;
+        }
+        Commands::Presence { group } => {
+            app.show_presence(&pick_group(&app, &config, group)?)?;
+        }
+        Commands::Publish => {
+            app.publish_key_package()?;
+        }
+        Commands::Sync { group: Some(group), from_seq: Some(from_seq) } => {
+            app.sync_from(&app.resolve_group(&group)?, from_seq)?;
+        }
+        Commands::Sync { .. } => {
+            app.sync()?;
+        }
+        Commands::Gc => {
+            let garbage = app.garbage()?;
+            garbage.print();
+            if !garbage.is_empty() && prompt::confirm_loss(&mut io::stdin().lock(), &garbage.losses(), cli.yes)? {
+                app.collect_garbage(garbage)?;
+            }
+        }
+        Commands::Outbox { action: None, group } => {
+            let group = group.map(|group| app.resolve_group(&group)).transpose()?;
+            app.list_outbox(group.as_deref())?;
+        }
+        Commands::Outbox { action: Some(OutboxAction::Retry { id }), .. } => {
+            app.retry_outbox(&id)?;
+        }
+        Commands::Outbox { action: Some(OutboxAction::Drop { id }), .. } => {
+            app.drop_outbox(&id)?;
+        }
+        Commands::Transfers { action: None | Some(TransferAction::List) } => {
+            app.list_transfers()?;
+        }
+        Commands::Transfers { action: Some(TransferAction::Resume { id }) } => {
+            app.resume_transfer(&id)?;
+        }
+        Commands::Transfers { action: Some(TransferAction::Drop { id }) } => {
+            app.drop_transfer(&id)?;
+        }
+        Commands::Device { action: DeviceAction::Export { file, passphrase } } => {
+            app.export_snapshot(&file, passphrase.as_deref())?;
+        }
+        Commands::Device { action: DeviceAction::Import { file, passphrase } } => {
+            app.import_snapshot(&file, passphrase.as_deref())?;
+        }
+        Commands::Device { action: DeviceAction::Listen { listen, passphrase } } => {
+            let listener = TcpListener::bind(&listen)
+                .with_context(|| format!("Failed to listen on {}", listen))?;
+            app.listen_for_device(listener, passphrase.as_deref())?;
+        }
+        Commands::Device { action: DeviceAction::Pair { addr, passphrase } } => {
+            app.pair_with_device(&addr, passphrase.as_deref())?;
+        }
+        Commands::Bridge { network: BridgeNetwork::Matrix { group, homeserver, room, access_token, mode } } => {
+            let access_token = access_token
+                .or_else(|| env::var(ACCESS_TOKEN_ENV).ok().filter(|token| !token.is_empty()))
+                .or_else(|| config.string("matrix.access-token").map(str::to_string))
+                .with_context(|| format!(
+                    "No Matrix access token; pass --access-token, set {}, or set matrix.access-token in the config file",
+                    ACCESS_TOKEN_ENV
+                ))?;
+            let config = MatrixConfig { homeserver, access_token, room_id: room, mode, proxy: cli.proxy.clone() };
+            let group = app.resolve_group(&group)?;
+            MatrixBridge::connect(config)?.run(&mut app, &group)?;
+        }
+        Commands::Bot { hooks } => {
+            let dir = hooks.unwrap_or_else(|| PathBuf::from(&data_dir).join("hooks"));
+            let hooks = Hooks::open(&dir);
+            if hooks.is_empty() {
+                println!("⚠️  No hooks in {}; add an executable on_message or on_member_added", dir.display());
+            }
+            return hooks.attach(Bot::new(app)?).run();
+        }
+        Commands::Api { listen, token } => {
+            let listener = TcpListener::bind(&listen)
+                .with_context(|| format!("Failed to listen on {}", listen))?;
+            return ApiServer::new(app, token)?.serve(listener);
+        }
+        Commands::Alias { remove: Some(name), .. } => {
+            match config.unset(&format!("{}{}", config::ALIAS_PREFIX, name))? {
+                true => println!("✅ Removed alias '{}'", name),
+                false => println!("⚠️  No alias '{}'", name),
+            }
+        }
+        Commands::Alias { name: Some(name), group: Some(group), remove: None } => {
+            config::check_alias_name(&name)?;
+            let group = app.resolve_group(&group)?;
+            config.set(&format!("{}{}", config::ALIAS_PREFIX, name), &group)?;
+            println!("✅ '{}' now stands for group '{}'", name, group);
+        }
+        Commands::Alias { .. } => {
+            let mut aliases: Vec<(String, String)> = config.aliases().into_iter().collect();
+            aliases.sort();
+            if aliases.is_empty() {
+                println!("No aliases; add one with `mls-chat alias <name> <group>`");
+            }
+            for (name, group) in aliases {
+                println!("   {} → {}", name, group);
+            }
+        }
+        Commands::Plugins => {
+            let plugins = plugins::discover();
+            if plugins.is_empty() {
+                println!("No plugins found; install an executable named {}<name> on PATH", plugins::PLUGIN_PREFIX);
+            }
+            for (name, path) in plugins {
+                println!("   {} ({})", name, path.display());
+            }
+        }
+        Commands::Plugin(args) => {
+            let (name, args) = args.split_first().context("Missing plugin name")?;
+            let context = PluginContext {
+                version: env!("CARGO_PKG_VERSION").to_string(),
+                data_dir: data_dir.clone(),
+                server: server.clone(),
+                namespace: cli.namespace.clone(),
+                user: app.current_user().map(|user| user.to_string()),
+            };
+            let code = app.run_plugin(name, args, &context)?;
+            if code != 0 {
+                std::process::exit(code);
+            }
+        }
+        Commands::Debug { action: DebugAction::State { group } } => {
+            let group = app.resolve_group(&group)?;
+            println!("{}", serde_json::to_string_pretty(&app.debug_state(&group)?)?);
+        }
+        Commands::Serve { .. }
+        | Commands::ServeAdmin { .. }
+        | Commands::Debug { action: DebugAction::Diff { .. } }
+        | Commands::Doctor
+        | Commands::Bench { .. }
+        | Commands::Wipe
+        | Commands::Attest { action: Some(_), .. }
+        | Commands::VerifyExport { .. }
+        | Commands::Config { .. }
+        | Commands::Completions { .. }
+        | Commands::Snippet { .. }
+        | Commands::Manpages { .. }
+        | Commands::Complete { .. } => {
+            unreachable!("handled before loading state")
+        }
+    }
+    follow_renames(&mut config, &app.take_renames())?;
+
+    Ok(())
+}
+
+/// Commands that send messages anyway, so also retry the outbox and send the scheduled messages that are due
+///
+/// `list --follow` and `bot` send scheduled messages every second as well.
+fn sends_messages(command: &Commands) -> bool {
+    matches!(
+        command,
+        Commands::Send { .. }
+            | Commands::Forward { .. }
+            | Commands::SendLocation { .. }
+            | Commands::Poll { action: PollAction::Create { .. } | PollAction::Vote { .. } }
+            | Commands::List { follow: true, .. }
+            | Commands::Bot { .. }
+            | Commands::Api { .. }
+    )
+}
+
+/// How to show message times: the config file's settings, or UTC with `--utc`
+fn timestamps(config: &Config, utc: bool) -> Timestamps {
+    match utc {
+        true => config.timestamps().utc(),
+        false => config.timestamps(),
+    }
+}
+
+/// The group a command names, else `default-group` from the config file, resolved through aliases
+fn pick_group(app: &MlsChatApp, config: &Config, group: Option<String>) -> Result<String> {
+    let group = group.or_else(|| config.string("default-group").map(str::to_string))
+        .context("Name a group, or set one with `config set default-group <group>`")?;
+    app.resolve_group(&group)
+}
+
+/// Drop the default group and aliases that name any of `groups`, so nothing points at a group that is gone
+fn forget_groups(config: &mut Config, groups: &[String]) -> Result<()> {
+    let stale: Vec<String> = config.values().iter()
+        .filter(|(key, _)| key == "default-group" || key.starts_with(config::ALIAS_PREFIX))
+        .filter(|(_, value)| matches!(value, ConfigValue::String(group) if groups.contains(group)))
+        .map(|(key, _)| key.clone())
+        .collect();
+    for key in stale {
+        config.unset(&key)?;
+        println!("   Removed {} from the config file", key);
+    }
+    Ok(())
+}
+
+/// Point the default group and aliases that name a renamed group at its new name
+fn follow_renames(config: &mut Config, renames: &[(String, String)]) -> Result<()> {
+    for (old, new) in renames {
+        let stale: Vec<String> = config.values().iter()
+            .filter(|(key, _)| key == "default-group" || key.starts_with(config::ALIAS_PREFIX))
+            .filter(|(_, value)| matches!(value, ConfigValue::String(group) if group == old))
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in stale {
+            config.set(&key, new)?;
+            println!("   Pointed {} in the config file at '{}'", key, new);
         }
     }
-    
     Ok(())
-} 
\ No newline at end of file
+}
+
+/// What `send` sends: Markdown, or with `--text` text shown as typed
+fn typed_text(text: String, as_typed: bool) -> Content {
+    match as_typed {
+        true => Content::Text { text },
+        false => Content::Markdown { text },
+    }
+}
+
+/// Run a `config` subcommand
+fn run_config(config: &mut Config, action: &ConfigAction) -> Result<()> {
+    match action {
+        ConfigAction::Get { key: Some(key) } => {
+            config::setting(key)?;
+            match config.get(key) {
+                Some(value) => println!("{}", value),
+                None => println!("{} is not set", key),
+            }
+        }
+        ConfigAction::Get { key: None } => {
+            if config.values().is_empty() {
+                println!("No settings in {}", config.path().display());
+            }
+            for (key, value) in config.values() {
+                println!("{} = {}", key, value);
+            }
+        }
+        ConfigAction::Set { key, value } => {
+            let value = config.set(key, value)?;
+            println!("✅ {} = {} saved to {}", key, value, config.path().display());
+        }
+        ConfigAction::Unset { key } => match config.unset(key)? {
+            true => println!("✅ Removed {} from {}", key, config.path().display()),
+            false => println!("{} is not set", key),
+        },
+        ConfigAction::Keys => {
+            for setting in config::SETTINGS {
+                println!("   {} — {}", setting.key, setting.help);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Run a `snippet` subcommand
+fn run_snippet(config: &mut Config, action: &SnippetAction) -> Result<()> {
+    match action {
+        SnippetAction::Add { name, text } => {
+            config::check_snippet_name(name)?;
+            config.set(&format!("{}{}", config::SNIPPET_PREFIX, name), text)?;
+            println!("✅ Saved snippet '{}'; send it with `mls-chat send <group> --snippet {}`", name, name);
+        }
+        SnippetAction::List => {
+            let mut snippets: Vec<(String, String)> = config.snippets().into_iter().collect();
+            snippets.sort();
+            if snippets.is_empty() {
+                println!("No snippets; add one with `mls-chat snippet add <name> <text>`");
+            }
+            for (name, text) in snippets {
+                println!("   {} → {}", name, text.replace('\n', " ⏎ "));
+                let vars: Vec<&str> = snippets::placeholders(&text)?.into_iter()
+                    .filter(|placeholder| !snippets::BUILT_IN.contains(placeholder))
+                    .collect();
+                if !vars.is_empty() {
+                    println!("      needs --var {}", vars.iter().map(|var| format!("{}=…", var)).collect::<Vec<_>>().join(" --var "));
+                }
+            }
+        }
+        SnippetAction::Remove { name } => match config.unset(&format!("{}{}", config::SNIPPET_PREFIX, name))? {
+            true => println!("✅ Removed snippet '{}'", name),
+            false => println!("⚠️  No snippet '{}'", name),
+        },
+    }
+    Ok(())
+}
+
+/// A `--var` value: a placeholder name, `=`, and what it stands for
+fn parse_var(text: &str) -> Result<(String, String), String> {
+    match text.split_once('=') {
+        Some((name, value)) if !name.trim().is_empty() => Ok((name.trim().to_string(), value.to_string())),
+        _ => Err(format!("'{}' is not NAME=VALUE, e.g. topic=Release", text)),
+    }
+}
+
+/// Connect to a delivery service, an MQTT broker for `mqtt://host:port`, or
+/// email accounts for `email:<settings.json>`
+///
+/// `namespace` and `token` apply to a delivery service, and are unused otherwise.
+fn connect_transport(
+    server: &str,
+    proxy: Option<&Socks5Proxy>,
+    namespace: Option<&str>,
+    token: Option<String>,
+) -> Result<Box<dyn Transport>> {
+    if let Some(broker) = server.strip_prefix("mqtt://") {
+        return Ok(Box::new(MqttTransport::new(broker, proxy.cloned())));
+    }
+    if let Some(settings) = server.strip_prefix("email:") {
+        return Ok(Box::new(EmailTransport::new(EmailConfig::load(settings)?, proxy.cloned())));
+    }
+    let client = connect_delivery(server, proxy, namespace)?;
+    Ok(match token {
+        Some(token) => Box::new(client.with_token(token)),
+        None => Box::new(client),
+    })
+}
+
+/// Connect to a delivery service, through `proxy` if one is given
+fn connect_delivery(server: &str, proxy: Option<&Socks5Proxy>, namespace: Option<&str>) -> Result<DeliveryClient> {
+    let client = match proxy {
+        Some(proxy) => DeliveryClient::connect_via(server, proxy)?,
+        None => DeliveryClient::connect(server)?,
+    };
+    Ok(match namespace {
+        Some(namespace) => client.with_namespace(namespace),
+        None => client,
+    })
+}
+
+/// When `send --at` should send: a time, or a duration from now such as `30m`
+fn parse_at(text: &str) -> Result<DateTime<Utc>, String> {
+    match parse_duration(text) {
+        Ok(duration) => Ok(Utc::now() + duration),
+        Err(_) => scheduled::parse_time(text)
+            .map_err(|_| format!("'{}' is not a time such as \"2024-06-01 09:00\" (UTC) or a duration such as 30m", text)),
+    }
+}
+
+/// Send `content` now, or queue it for `at`
+fn send_or_schedule(app: &mut MlsChatApp, group: String, content: Content, at: Option<DateTime<Utc>>) -> Result<()> {
+    match at {
+        Some(at) => app.schedule_content(&group, content, at),
+        None => app.send_content(group, content),
+    }
+}
+
+/// A duration such as `90s`, `30m`, `8h`, `2d`, or `1w`
+fn parse_duration(text: &str) -> Result<TimeDelta, String> {
+    let error = || format!("'{}' is not a duration such as 30m, 8h, or 2d", text);
+    let split = text.find(|c: char| !c.is_ascii_digit()).ok_or_else(error)?;
+    let count: i64 = text[..split].parse().map_err(|_| error())?;
+    let duration = match &text[split..] {
+        "s" => TimeDelta::try_seconds(count),
+        "m" => TimeDelta::try_minutes(count),
+        "h" => TimeDelta::try_hours(count),
+        "d" => TimeDelta::try_days(count),
+        "w" => TimeDelta::try_weeks(count),
+        _ => None,
+    };
+    duration.filter(|duration| duration > &TimeDelta::zero()).ok_or_else(error)
+}
+
+/// Where `login` tokens for a server and namespace are kept in the profile
+fn account(server: &str, namespace: Option<&str>) -> String {
+    match namespace {
+        Some(namespace) => format!("{}/{}", server, namespace),
+        None => server.to_string(),
+    }
+}
+
+/// A JSON array printed one element at a time, laid out as `serde_json::to_string_pretty` lays out a whole one
+#[derive(Default)]
+struct JsonArray {
+    started: bool,
+}
+
+impl JsonArray {
+    fn push(&mut self, value: &impl serde::Serialize) -> Result<()> {
+        let json = serde_json::to_string_pretty(value)?;
+        let indented: Vec<String> = json.lines().map(|line| format!("  {}", line)).collect();
+        print!("{}{}", if self.started { ",\n" } else { "[\n" }, indented.join("\n"));
+        self.started = true;
+        Ok(())
+    }
+
+    fn finish(self) {
+        println!("{}", if self.started { "\n]" } else { "[]" });
+    }
+}
@@ -0,0 +1,160 @@
+//! Man pages, generated from the CLI definition
+//!
+//! `mls-chat manpages <dir>` writes `mls-chat.1` and one page per
+//! subcommand (`mls-chat-send.1`, `mls-chat-serve-admin-users.1`, ...) in
+//! roff, ready for `man -l` or installing under `share/man/man1`.
+
+use anyhow::{Context, Result};
+use clap::{Arg, Command};
+use std::{
+    fmt::Write,
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Write a page for `cmd` and each of its subcommands into `dir`; returns the files written
+pub fn write_all(cmd: &Command, dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut cmd = cmd.clone();
+    cmd.build(); // Copies global options down to every subcommand
+    fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    let mut written = Vec::new();
+    write_page(&cmd, &[cmd.get_name()], dir, &mut written)?;
+    Ok(written)
+}
+
+fn write_page(cmd: &Command, path: &[&str], dir: &Path, written: &mut Vec<PathBuf>) -> Result<()> {
+    let file = dir.join(format!("{}.1", path.join("-")));
+    fs::write(&file, render(cmd, path)).with_context(|| format!("Failed to write {}", file.display()))?;
+    written.push(file);
+    for sub in visible_subcommands(cmd) {
+        let mut sub_path = path.to_vec();
+        sub_path.push(sub.get_name());
+        write_page(sub, &sub_path, dir, written)?;
+    }
+    Ok(())
+}
+
+fn visible_subcommands(cmd: &Command) -> impl Iterator<Item = &Command> {
+    cmd.get_subcommands().filter(|sub| !sub.is_hide_set() && sub.get_name() != "help")
+}
+
+/// The roff page for `cmd`, reached from the root through `path`
+fn render(cmd: &Command, path: &[&str]) -> String {
+    let title = path.join("-");
+    let version = env!("CARGO_PKG_VERSION");
+    let about = cmd.get_about().map(|about| about.to_string()).unwrap_or_default();
+    let mut out = String::new();
+    let _ = writeln!(out, ".TH {} 1 \"\" \"{} {}\"", title.to_uppercase(), path[0], version);
+
+    let _ = writeln!(out, ".SH NAME");
+    let _ = writeln!(out, "{} \\- {}", escape(&title), escape(&about));
+
+    let _ = writeln!(out, ".SH SYNOPSIS");
+    let mut synopsis = format!("\\fB{}\\fR", escape(&path.join(" ")));
+    if cmd.get_arguments().any(|arg| !arg.is_positional() && !arg.is_hide_set()) {
+        synopsis.push_str(" [\\fIOPTIONS\\fR]");
+    }
+    for arg in cmd.get_arguments().filter(|arg| arg.is_positional() && !arg.is_hide_set()) {
+        let name = value_name(arg);
+        synopsis.push_str(&match arg.is_required_set() {
+            true => format!(" <\\fI{}\\fR>", name),
+            false => format!(" [\\fI{}\\fR]", name),
+        });
+    }
+    if cmd.has_subcommands() {
+        synopsis.push_str(" <\\fICOMMAND\\fR>");
+    }
+    let _ = writeln!(out, "{}", synopsis);
+
+    let description = cmd.get_long_about().map(|about| about.to_string()).unwrap_or(about);
+    if !description.is_empty() {
+        let _ = writeln!(out, ".SH DESCRIPTION");
+        let _ = writeln!(out, "{}", escape(&description));
+    }
+
+    let positionals: Vec<&Arg> = cmd.get_arguments().filter(|arg| arg.is_positional() && !arg.is_hide_set()).collect();
+    if !positionals.is_empty() {
+        let _ = writeln!(out, ".SH ARGUMENTS");
+        for arg in positionals {
+            let _ = writeln!(out, ".TP\n\\fI{}\\fR", value_name(arg));
+            write_help(&mut out, arg);
+        }
+    }
+
+    let options: Vec<&Arg> = cmd.get_arguments().filter(|arg| !arg.is_positional() && !arg.is_hide_set()).collect();
+    if !options.is_empty() {
+        let _ = writeln!(out, ".SH OPTIONS");
+        for arg in options {
+            let mut flags = Vec::new();
+            if let Some(short) = arg.get_short() {
+                flags.push(format!("\\fB\\-{}\\fR", short));
+            }
+            if let Some(long) = arg.get_long() {
+                flags.push(format!("\\fB\\-\\-{}\\fR", escape(long)));
+            }
+            let mut line = flags.join(", ");
+            if arg.get_action().takes_values() {
+                let _ = write!(line, " <\\fI{}\\fR>", value_name(arg));
+            }
+            let _ = writeln!(out, ".TP\n{}", line);
+            write_help(&mut out, arg);
+        }
+    }
+
+    if cmd.has_subcommands() {
+        let _ = writeln!(out, ".SH COMMANDS");
+        for sub in visible_subcommands(cmd) {
+            let page = format!("{}-{}", title, sub.get_name());
+            let _ = writeln!(out, ".TP\n\\fB{}\\fR", escape(sub.get_name()));
+            let _ = writeln!(out, "{}", escape(&sub.get_about().map(|about| about.to_string()).unwrap_or_default()));
+            let _ = writeln!(out, "See \\fB{}\\fR(1).", escape(&page));
+        }
+    }
+
+    if path.len() > 1 {
+        let _ = writeln!(out, ".SH SEE ALSO");
+        let _ = writeln!(out, "\\fB{}\\fR(1)", escape(&path[..path.len() - 1].join("-")));
+    }
+    out
+}
+
+/// An argument's help, with its fixed values and default if it takes a value
+fn write_help(out: &mut String, arg: &Arg) {
+    let help = arg.get_long_help().or_else(|| arg.get_help()).map(|help| help.to_string()).unwrap_or_default();
+    let _ = writeln!(out, "{}", escape(&help));
+    let values: Vec<String> = arg.get_possible_values().iter()
+        .filter(|value| !value.is_hide_set())
+        .map(|value| value.get_name().to_string())
+        .collect();
+    if !arg.get_action().takes_values() {
+        return;
+    }
+    if !values.is_empty() {
+        let _ = writeln!(out, ".br\nValues: {}", escape(&values.join(", ")));
+    }
+    let defaults: Vec<String> = arg.get_default_values().iter().map(|value| value.to_string_lossy().into_owned()).collect();
+    if !defaults.is_empty() {
+        let _ = writeln!(out, ".br\nDefault: {}", escape(&defaults.join(", ")));
+    }
+}
+
+fn value_name(arg: &Arg) -> String {
+    match arg.get_value_names() {
+        Some([name, ..]) => name.to_string(),
+        _ => arg.get_id().to_string().to_uppercase(),
+    }
+}
+
+/// Text as roff sees it: backslashes doubled, hyphens literal, no leading control characters
+fn escape(text: &str) -> String {
+    text.lines()
+        .map(|line| {
+            let line = line.replace('\\', "\\e").replace('-', "\\-");
+            match line.starts_with('.') || line.starts_with('\'') {
+                true => format!("\\&{}", line),
+                false => line,
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
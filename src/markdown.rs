@@ -0,0 +1,121 @@
+//! Markdown in terminal output
+//!
+//! Covers what chat messages use: **bold**, *italics*, `code`, fenced code
+//! blocks, and [links](url). Anything else, including markers that are never
+//! closed, is printed as written.
+
+use colored::*;
+
+/// `text` with its Markdown turned into terminal styles
+///
+/// `plain` renders the text outside code, so callers can highlight words in it.
+pub fn render(text: &str, plain: &dyn Fn(&str) -> String) -> String {
+    let lines: Vec<&str> = text.split('\n').collect();
+    let mut fences: Vec<usize> = (0..lines.len()).filter(|&index| lines[index].trim_start().starts_with("```")).collect();
+    if fences.len() % 2 == 1 {
+        fences.pop(); // An unclosed fence is just text
+    }
+
+    let mut out: Vec<String> = Vec::new();
+    let mut in_block = false;
+    for (index, line) in lines.iter().enumerate() {
+        if fences.contains(&index) {
+            if !in_block && out.is_empty() {
+                out.push(String::new()); // Start the block on a line of its own
+            }
+            in_block = !in_block;
+            continue;
+        }
+        out.push(match in_block {
+            true => format!("    {}", line.cyan()),
+            false => inline(line, plain),
+        });
+    }
+    out.join("\n")
+}
+
+/// One line of text with its inline markup rendered
+fn inline(text: &str, plain: &dyn Fn(&str) -> String) -> String {
+    let mut out = String::new();
+    let mut run = String::new(); // Plain text not yet passed to `plain`
+    let mut rest = text;
+    let mut previous: Option<char> = None;
+    while let Some(c) = rest.chars().next() {
+        if let Some((styled, after)) = span(rest, previous, plain) {
+            out.push_str(&plain(&run));
+            run.clear();
+            out.push_str(&styled);
+            previous = rest[..rest.len() - after.len()].chars().last();
+            rest = after;
+            continue;
+        }
+        let mut len = c.len_utf8();
+        match rest[len..].chars().next() {
+            Some(escaped) if c == '\\' && escaped.is_ascii_punctuation() => {
+                run.push(escaped);
+                len += escaped.len_utf8();
+            }
+            _ => run.push(c),
+        }
+        previous = Some(c);
+        rest = &rest[len..];
+    }
+    out.push_str(&plain(&run));
+    out
+}
+
+/// The styled span `rest` starts with, if any, and the text after it
+fn span<'a>(rest: &'a str, previous: Option<char>, plain: &dyn Fn(&str) -> String) -> Option<(String, &'a str)> {
+    if let Some(code) = rest.strip_prefix('`') {
+        let end = code.find('`').filter(|&end| end > 0)?;
+        return Some((code[..end].cyan().to_string(), &code[end + 1..]));
+    }
+    if let Some(link) = rest.strip_prefix('[') {
+        let middle = link.find("](")?;
+        let (label, target) = (&link[..middle], &link[middle + 2..]);
+        let end = target.find(')')?;
+        let url = &target[..end];
+        if label.is_empty() || url.is_empty() || label.contains('[') {
+            return None;
+        }
+        let styled = match label == url {
+            true => url.underline().to_string(),
+            false => format!("{} ({})", label.underline(), url.dimmed()),
+        };
+        return Some((styled, &target[end + 1..]));
+    }
+
+    // A word never starts emphasis halfway through, so snake_case stays as typed
+    if previous.is_some_and(char::is_alphanumeric) {
+        return None;
+    }
+    for marker in ["**", "__", "*", "_"] {
+        let Some(inner) = rest.strip_prefix(marker) else { continue };
+        let end = closing(inner, marker)?;
+        let content = inline(&inner[..end], plain);
+        let styled = match marker.len() {
+            2 => content.bold().to_string(),
+            _ => content.italic().to_string(),
+        };
+        return Some((styled, &inner[end + marker.len()..]));
+    }
+    None
+}
+
+/// Where `marker` closes emphasis in `inner`: not right away, not after a space, and not before a letter
+fn closing(inner: &str, marker: &str) -> Option<usize> {
+    if inner.starts_with(char::is_whitespace) {
+        return None;
+    }
+    let mut from = 0;
+    while let Some(found) = inner[from..].find(marker) {
+        let end = from + found;
+        let after = inner[end + marker.len()..].chars().next();
+        let doubled = marker.len() == 1 && after.is_some_and(|c| marker.starts_with(c));
+        if end > 0 && !inner[..end].ends_with(char::is_whitespace) && !after.is_some_and(char::is_alphanumeric) && !doubled {
+            return Some(end);
+        }
+        from = end + marker.len();
+    }
+    None
+}
@@ -0,0 +1,185 @@
+//! Bridge between an MLS group and a Matrix room
+//!
+//! Messages that reach the group through the delivery service are posted to
+//! the room, and text messages other Matrix users post in the room are
+//! recorded in the group and sent on to its members. Depending on
+//! `BridgeMode`, the room sees either the MLS ciphertext or the cleartext.
+//! Homeservers must be reachable over plain `http://`, since TLS is not built in;
+//! `--proxy` applies to them as it does to the delivery service.
+
+use anyhow::{anyhow, Context, Result};
+use colored::*;
+use serde_json::{json, Value};
+use std::{thread, time::Duration};
+use uuid::Uuid;
+
+use crate::app::MlsChatApp;
+use crate::content::Content;
+use crate::http::{self, percent_encode};
+use crate::model::ChatMessage;
+use crate::proxy::Socks5Proxy;
+
+/// How long each `/sync` long-poll waits for room events
+const SYNC_TIMEOUT: Duration = Duration::from_secs(2);
+/// Pause after a failed round before trying again
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+/// Holds the access token when `--access-token` is not given
+pub const ACCESS_TOKEN_ENV: &str = "MLS_CHAT_MATRIX_TOKEN";
+
+/// What the Matrix room gets to see
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum BridgeMode {
+    /// Post the MLS ciphertext, so the homeserver learns nothing new
+    Ciphertext,
+    /// Post readable messages, trusting the homeserver with their contents
+    Cleartext,
+}
+
+/// Where and how to bridge
+#[derive(Debug, Clone)]
+pub struct MatrixConfig {
+    pub homeserver: String, // e.g. http://localhost:8008
+    pub access_token: String,
+    pub room_id: String,
+    pub mode: BridgeMode,
+    pub proxy: Option<Socks5Proxy>,
+}
+
+/// A running bridge, logged in to the homeserver
+pub struct MatrixBridge {
+    config: MatrixConfig,
+    user_id: String, // The bridge's own Matrix user, whose events are not ingested
+    since: String,   // Sync token; only events after it are ingested
+}
+
+impl MatrixBridge {
+    /// Log in with the configured token and skip the room's existing history
+    pub fn connect(config: MatrixConfig) -> Result<Self> {
+        let mut bridge = Self {
+            config,
+            user_id: String::new(),
+            since: String::new(),
+        };
+        let whoami = bridge.call("GET", "/_matrix/client/v3/account/whoami", None, Duration::from_secs(10))?;
+        bridge.user_id = whoami["user_id"].as_str()
+            .context("Homeserver did not report a user ID")?
+            .to_string();
+
+        let filter = percent_encode(&json!({ "room": { "timeline": { "limit": 0 } } }).to_string());
+        let initial = bridge.call(
+            "GET",
+            &format!("/_matrix/client/v3/sync?timeout=0&filter={}", filter),
+            None,
+            Duration::from_secs(30),
+        )?;
+        bridge.since = next_batch(&initial)?;
+        Ok(bridge)
+    }
+
+    /// Bridge `group_name` until the process is stopped
+    pub fn run(&mut self, app: &mut MlsChatApp, group_name: &str) -> Result<()> {
+        let group = app.group(group_name).context("Group not found")?;
+        let user = app.current_user().context("No user initialized")?;
+        if !group.members.contains(&user.to_string()) {
+            return Err(anyhow!("User '{}' is not a member of group '{}'", user, group_name));
+        }
+        println!(
+            "{}",
+            format!("Bridging '{}' to Matrix room {} as {}", group_name, self.config.room_id, self.user_id).green()
+        );
+
+        loop {
+            if let Err(err) = self.step(app, group_name) {
+                eprintln!("⚠️  Bridge round failed: {:#}", err);
+                thread::sleep(RETRY_DELAY);
+            }
+        }
+    }
+
+    /// Forward new group messages to the room, then ingest new room messages
+    pub fn step(&mut self, app: &mut MlsChatApp, group_name: &str) -> Result<()> {
+        let group_id = app.group(group_name).context("Group not found")?.group_id.clone();
+
+        if app.transport.is_some() {
+            let user = app.current_user().context("No user initialized")?.to_string();
+            let envelopes = app.transport()?.fetch_inbox(&user)?;
+            for envelope in envelopes {
+                match app.apply_envelope(&envelope) {
+                    Ok(Some(message)) if message.group_id == group_id => self.post(&message)?,
+                    Ok(_) => {}
+                    Err(err) => println!("⚠️  Skipped message {} from '{}': {}", envelope.message_id, envelope.sender, err),
+                }
+            }
+            app.save_state()?;
+        }
+
+        for (sender, body) in self.poll()? {
+            app.post_message(group_name, sender.clone(), Content::Text { text: body })?;
+            app.save_state()?;
+            println!("   ⬅️  {} → '{}'", sender.yellow(), group_name);
+        }
+        Ok(())
+    }
+
+    /// Post a group message to the room
+    fn post(&self, message: &ChatMessage) -> Result<()> {
+        let body = match self.config.mode {
+            BridgeMode::Ciphertext => format!("🔒 {} (epoch {}): {}", message.sender, message.epoch, message.encrypted_content),
+            BridgeMode::Cleartext => format!("{}: {}", message.sender, message.content),
+        };
+        let path = format!(
+            "/_matrix/client/v3/rooms/{}/send/m.room.message/{}",
+            percent_encode(&self.config.room_id),
+            Uuid::new_v4()
+        );
+        self.call("PUT", &path, Some(&json!({ "msgtype": "m.text", "body": body })), Duration::from_secs(10))?;
+        println!("   ➡️  {} → Matrix", message.sender.yellow());
+        Ok(())
+    }
+
+    /// Wait briefly for new text messages in the room from other users
+    fn poll(&mut self) -> Result<Vec<(String, String)>> {
+        let filter = percent_encode(&json!({ "room": { "rooms": [self.config.room_id] } }).to_string());
+        let path = format!(
+            "/_matrix/client/v3/sync?since={}&timeout={}&filter={}",
+            percent_encode(&self.since),
+            SYNC_TIMEOUT.as_millis(),
+            filter
+        );
+        let response = self.call("GET", &path, None, SYNC_TIMEOUT + Duration::from_secs(30))?;
+
+        let events = response["rooms"]["join"][&self.config.room_id]["timeline"]["events"]
+            .as_array()
+            .cloned()
+            .unwrap_or_default();
+        let messages = events.iter()
+            .filter(|event| event["type"] == "m.room.message" && event["content"]["msgtype"] == "m.text")
+            .filter(|event| event["sender"] != self.user_id.as_str())
+            .filter_map(|event| Some((event["sender"].as_str()?.to_string(), event["content"]["body"].as_str()?.to_string())))
+            .collect();
+
+        self.since = next_batch(&response)?;
+        Ok(messages)
+    }
+
+    fn call(&self, method: &str, path: &str, body: Option<&Value>, timeout: Duration) -> Result<Value> {
+        let url = format!("{}{}", self.config.homeserver.trim_end_matches('/'), path);
+        let (status, response) = http::send(method, &url, Some(&self.config.access_token), body, timeout, self.config.proxy.as_ref())
+            .with_context(|| format!("Matrix request to {} failed", self.config.homeserver))?;
+        if status != 200 {
+            return Err(anyhow!(
+                "Matrix homeserver returned {} {}: {}",
+                status,
+                response["errcode"].as_str().unwrap_or("error"),
+                response["error"].as_str().unwrap_or("no details")
+            ));
+        }
+        Ok(response)
+    }
+}
+
+fn next_batch(sync: &Value) -> Result<String> {
+    sync["next_batch"].as_str()
+        .map(str::to_string)
+        .context("Matrix sync response has no next_batch token")
+}
@@ -0,0 +1,92 @@
+//! `@name` mentions
+//!
+//! Each message records which group members its content mentions, worked out
+//! by the sender and again by every recipient from the decrypted content, so
+//! nothing about mentions travels outside the ciphertext. `list` highlights
+//! mentions of the current user and `mentions` lists the messages that have one.
+
+use anyhow::{Context, Result};
+use colored::*;
+
+use crate::app::{print_message, sender_width, ListOptions, MlsChatApp};
+use crate::model::ChatMessage;
+use crate::output;
+use crate::theme::{self, Role};
+
+/// Members named as `@name` in `content`, in order of first mention, matched case-insensitively
+///
+/// An `@` inside a word, as in an email address, does not start a mention.
+pub fn parse_mentions(content: &str, members: &[String]) -> Vec<String> {
+    let mut mentioned: Vec<String> = Vec::new();
+    for (_, name) in mention_spans(content) {
+        let Some(member) = members.iter().find(|member| member.eq_ignore_ascii_case(name)) else { continue };
+        if !mentioned.contains(member) {
+            mentioned.push(member.clone());
+        }
+    }
+    mentioned
+}
+
+/// `content` with each `@name` naming `user` highlighted
+pub(crate) fn highlight(content: &str, user: &str) -> String {
+    let mut out = String::new();
+    let mut last = 0;
+    for (start, name) in mention_spans(content) {
+        if !name.eq_ignore_ascii_case(user) {
+            continue;
+        }
+        let end = start + 1 + name.len();
+        out.push_str(&content[last..start]);
+        out.push_str(&theme::paint(Role::Mention, &content[start..end]).to_string());
+        last = end;
+    }
+    out.push_str(&content[last..]);
+    out
+}
+
+/// Byte offset of each `@` that starts a mention, with the name after it
+fn mention_spans(content: &str) -> Vec<(usize, &str)> {
+    let is_name_char = |c: char| c.is_alphanumeric() || c == '_' || c == '-';
+    let mut spans = Vec::new();
+    let mut previous: Option<char> = None;
+    for (index, c) in content.char_indices() {
+        if c == '@' && !previous.is_some_and(is_name_char) {
+            let rest = &content[index + 1..];
+            let len = rest.find(|c: char| !is_name_char(c)).unwrap_or(rest.len());
+            if len > 0 {
+                spans.push((index, &rest[..len]));
+            }
+        }
+        previous = Some(c);
+    }
+    spans
+}
+
+impl MlsChatApp {
+    /// Messages in a group that mention the current user, oldest first
+    pub fn mentions_of_user(&mut self, group_name: &str) -> Result<Vec<ChatMessage>> {
+        let user = self.current_user.context("No user initialized")?.to_string();
+        let group = self.load_messages(group_name)?;
+        Ok(group.messages.as_deref().unwrap_or_default()
+            .iter()
+            .filter(|message| message.mentions.contains(&user))
+            .cloned()
+            .collect())
+    }
+
+    /// Print the messages in a group that mention the current user
+    pub fn list_mentions(&mut self, group_name: &str, options: &ListOptions) -> Result<()> {
+        let user = self.current_user.context("No user initialized")?.to_string();
+        let messages = self.mentions_of_user(group_name)?;
+        println!("{}", format!("Messages mentioning {} in '{}':", user, group_name).blue());
+        output::rule('=', 50);
+        if messages.is_empty() {
+            println!("No mentions yet.");
+        }
+        let sender_width = sender_width(&messages);
+        for message in &messages {
+            print_message(message, "", Some(&user), sender_width, options);
+        }
+        Ok(())
+    }
+}
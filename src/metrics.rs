@@ -0,0 +1,111 @@
+//! Prometheus metrics for the delivery service
+//!
+//! `serve --metrics-listen <addr>` exposes these at `/metrics` in the text
+//! exposition format, for scraping by Prometheus and graphing in Grafana.
+//! Every series is labelled with its namespace (`""` for the default one).
+
+use std::{collections::BTreeMap, fmt::Write, time::Duration};
+
+/// Upper bounds of the request latency buckets, in seconds
+const LATENCY_BUCKETS: [f64; 8] = [0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0];
+
+/// Counters the server bumps as it works
+#[derive(Default)]
+pub(crate) struct Metrics {
+    messages_relayed: BTreeMap<String, u64>,    // Accepted SendMessage calls, by namespace
+    key_packages_served: BTreeMap<String, u64>, // Successful FetchKeyPackage calls, by namespace
+    subscribers: BTreeMap<String, u64>,         // Open Subscribe streams, by namespace
+    latencies: BTreeMap<(String, &'static str), Histogram>, // By namespace and RPC method
+}
+
+/// Gauges read from server state at scrape time
+pub(crate) struct NamespaceGauges {
+    pub queued_envelopes: usize,
+    pub users: usize, // Everyone with an account, key package, or recent call
+}
+
+#[derive(Default)]
+struct Histogram {
+    buckets: [u64; LATENCY_BUCKETS.len()], // Observations at or under each bound
+    count: u64,
+    sum: f64,
+}
+
+impl Metrics {
+    pub fn message_relayed(&mut self, namespace: &str) {
+        *self.messages_relayed.entry(namespace.to_string()).or_default() += 1;
+    }
+
+    pub fn key_package_served(&mut self, namespace: &str) {
+        *self.key_packages_served.entry(namespace.to_string()).or_default() += 1;
+    }
+
+    pub fn subscriber_joined(&mut self, namespace: &str) {
+        *self.subscribers.entry(namespace.to_string()).or_default() += 1;
+    }
+
+    pub fn subscriber_left(&mut self, namespace: &str) {
+        if let Some(count) = self.subscribers.get_mut(namespace) {
+            *count = count.saturating_sub(1);
+        }
+    }
+
+    /// Record how long one call took to answer
+    pub fn observe(&mut self, namespace: &str, method: &'static str, elapsed: Duration) {
+        let histogram = self.latencies.entry((namespace.to_string(), method)).or_default();
+        let seconds = elapsed.as_secs_f64();
+        for (bucket, bound) in histogram.buckets.iter_mut().zip(LATENCY_BUCKETS) {
+            if seconds <= bound {
+                *bucket += 1;
+            }
+        }
+        histogram.count += 1;
+        histogram.sum += seconds;
+    }
+
+    /// Everything in the Prometheus text format
+    pub fn render(&self, gauges: &BTreeMap<String, NamespaceGauges>) -> String {
+        let mut out = String::new();
+        write_family(&mut out, "mls_chat_messages_relayed_total", "counter", "Messages accepted for delivery");
+        for (namespace, count) in &self.messages_relayed {
+            let _ = writeln!(out, "mls_chat_messages_relayed_total{{namespace=\"{}\"}} {}", escape(namespace), count);
+        }
+        write_family(&mut out, "mls_chat_key_packages_served_total", "counter", "Key packages handed out by FetchKeyPackage");
+        for (namespace, count) in &self.key_packages_served {
+            let _ = writeln!(out, "mls_chat_key_packages_served_total{{namespace=\"{}\"}} {}", escape(namespace), count);
+        }
+        write_family(&mut out, "mls_chat_subscribers", "gauge", "Clients holding an open Subscribe stream");
+        for (namespace, count) in &self.subscribers {
+            let _ = writeln!(out, "mls_chat_subscribers{{namespace=\"{}\"}} {}", escape(namespace), count);
+        }
+        write_family(&mut out, "mls_chat_queued_envelopes", "gauge", "Envelopes waiting in inboxes");
+        for (namespace, gauge) in gauges {
+            let _ = writeln!(out, "mls_chat_queued_envelopes{{namespace=\"{}\"}} {}", escape(namespace), gauge.queued_envelopes);
+        }
+        write_family(&mut out, "mls_chat_users", "gauge", "Users the server knows about");
+        for (namespace, gauge) in gauges {
+            let _ = writeln!(out, "mls_chat_users{{namespace=\"{}\"}} {}", escape(namespace), gauge.users);
+        }
+        write_family(&mut out, "mls_chat_request_duration_seconds", "histogram", "Time to answer each call, by method");
+        for ((namespace, method), histogram) in &self.latencies {
+            let labels = format!("namespace=\"{}\",method=\"{}\"", escape(namespace), method);
+            for (count, bound) in histogram.buckets.iter().zip(LATENCY_BUCKETS) {
+                let _ = writeln!(out, "mls_chat_request_duration_seconds_bucket{{{},le=\"{}\"}} {}", labels, bound, count);
+            }
+            let _ = writeln!(out, "mls_chat_request_duration_seconds_bucket{{{},le=\"+Inf\"}} {}", labels, histogram.count);
+            let _ = writeln!(out, "mls_chat_request_duration_seconds_sum{{{}}} {}", labels, histogram.sum);
+            let _ = writeln!(out, "mls_chat_request_duration_seconds_count{{{}}} {}", labels, histogram.count);
+        }
+        out
+    }
+}
+
+fn write_family(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+/// Escape a label value as the text format requires
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
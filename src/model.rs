@@ -0,0 +1,604 @@
+//! Data models for users, groups, and messages
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use crate::content::Content;
+use crate::crypto;
+use crate::tree::RatchetTree;
+
+/// Demo user identities
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
+pub enum UserName {
+    Alice,
+    Bob,
+}
+
+impl std::fmt::Display for UserName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UserName::Alice => write!(f, "Alice"),
+            UserName::Bob => write!(f, "Bob"),
+        }
+    }
+}
+
+/// Mock cryptographic key for demonstration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MockKey {
+    pub id: String,
+    pub public_key: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub private_key: String, // Empty while sealed and not yet unlocked
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sealed_private_key: Option<String>, // The private key sealed under the profile's passphrase, base64
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub store_kdf: Option<StoreKdf>, // How the sealing key comes from the passphrase; None if sealed before keys were salted
+    #[serde(default, skip_serializing_if = "Option::is_none", deserialize_with = "readable_or_none")]
+    pub unlock_attempts: Option<UnlockAttempts>, // Wrong passphrases in a row, kept next to the sealed key; None if unsealed or damaged
+}
+
+/// Read a field that is None when it cannot be read, rather than failing the whole file
+fn readable_or_none<'de, D: serde::Deserializer<'de>, T: serde::de::DeserializeOwned>(deserializer: D) -> Result<Option<T>, D::Error> {
+    let value = serde_json::Value::deserialize(deserializer)?;
+    Ok(serde_json::from_value(value).ok())
+}
+
+/// The salt and PBKDF2 iteration count a profile's store key is derived with
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StoreKdf {
+    pub salt: String, // Base64, random per profile
+    pub iterations: u32,
+}
+
+/// A group's full state at one moment, kept by `snapshot` for `rollback`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GroupSnapshot {
+    pub id: String,
+    pub at: DateTime<Utc>,
+    pub group: ChatGroup, // Its `messages` are not serialized, so they are kept below
+    pub messages: Vec<ChatMessage>,
+}
+
+/// Which way a chunked message is going
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransferDirection {
+    Upload,
+    Download,
+}
+
+impl std::fmt::Display for TransferDirection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TransferDirection::Upload => write!(f, "upload"),
+            TransferDirection::Download => write!(f, "download"),
+        }
+    }
+}
+
+/// A message too large for one envelope, part way through being sent or received in chunks (see `transfer`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Transfer {
+    pub id: String, // The chunked message's ID
+    pub group_id: String,
+    pub direction: TransferDirection,
+    pub sender: String,
+    pub epoch: u32, // The message's
+    pub size: u64, // Bytes of the whole serialized message
+    pub hash: String, // Its SHA-256, hex
+    pub chunk_count: u32,
+    pub chunks: BTreeMap<u32, String>, // Base64; an upload's still to send, or a download's received so far
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub recipients: Vec<String>, // An upload's
+    #[serde(default)]
+    pub manifest_sent: bool, // An upload's
+    #[serde(default)]
+    pub from_seq: u64, // A download's manifest, where fetching it again starts; 0 if unsequenced
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>, // The attachment's file name, which only the uploader knows
+    pub started: DateTime<Utc>,
+}
+
+/// Failed passphrase entries since the last one that worked, for spacing out further tries
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UnlockAttempts {
+    pub failures: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_failure: Option<DateTime<Utc>>,
+}
+
+/// An unlocked profile's store key, kept until it has gone unused for the idle timeout
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Session {
+    pub key: String, // Hex
+    pub last_used: DateTime<Utc>,
+}
+
+/// What one identity in this profile knows of a peer's identity key: the key pinned on first use or accepted since
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PeerKey {
+    pub fingerprint: String, // As `crypto::fingerprint` prints it
+    pub since: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub continued_from: Option<String>, // The previous key's fingerprint, when learned from a signed key change
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pending: Option<String>, // A different key seen since, waiting for `trust accept`
+}
+
+/// The last transparency log tree head a profile checked, refusing logs that do not extend it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TreeHead {
+    pub size: usize,
+    pub root: String, // Hex Merkle tree hash of the first `size` entries
+    pub checked: DateTime<Utc>,
+}
+
+/// Credentials loaded by `revocation update`, refused for new members and flagged in existing ones
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RevocationList {
+    pub source: String, // The file it was loaded from
+    pub updated: DateTime<Utc>,
+    pub revoked: Vec<RevokedCredential>,
+}
+
+/// One revoked credential, by the fingerprint of its key
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RevokedCredential {
+    pub fingerprint: String, // As `crypto::fingerprint` prints it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+/// What `whoami` reports about the active identity
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IdentityStatus {
+    pub user: String,
+    pub key_id: String,
+    pub fingerprint: String,     // SHA-256 of the public key, see `crypto::fingerprint`
+    pub credential_type: String, // Always "basic": the credential is the user name
+    pub key_package: KeyPackageStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key_package_error: Option<String>, // Why the delivery service had no key package to give
+    pub groups: usize,
+    pub storage: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub erasure_caveat: Option<String>, // Why removed data may still be recoverable from the storage
+}
+
+/// Whether the delivery service can hand out this identity's key package
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyPackageStatus {
+    Published, // Matches the identity's current key
+    Outdated,  // Published for a different key
+    Missing,   // Not published, or the delivery service could not be asked
+    Unchecked, // Not networked
+}
+
+/// Mock MLS group state for demonstration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MockMlsGroup {
+    pub group_id: String,
+    pub epoch: u32,
+    pub tree_hash: String,
+    pub group_secret: String, // In real implementation, this would be encrypted
+    pub members: Vec<String>,
+    #[serde(default)]
+    pub metadata: GroupMetadata, // Agreed by every member, like an MLS group context extension
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tree: Option<RatchetTree>, // None in groups from before the ratchet tree, whose commits send every member the new secret
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty", with = "crate::tree::node_pairs")]
+    pub path_secrets: BTreeMap<u32, String>, // This profile's secrets for tree nodes, by node index
+}
+
+/// The group's shared name, topic, and description
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct GroupMetadata {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub name: String, // Empty for groups created before names were shared
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub topic: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "GroupPolicy::is_default")]
+    pub policy: GroupPolicy,
+}
+
+/// How large a group may grow and who may bring members in
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GroupPolicy {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_members: Option<usize>,
+    #[serde(default)]
+    pub join: JoinRule,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub approval: bool, // Only admins may add members or let them join
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub admins: Vec<String>, // Empty for groups created before policies, where every member is one
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub bans: Vec<Ban>, // Identities that may not be added or join, oldest ban first
+}
+
+impl GroupPolicy {
+    pub fn is_default(&self) -> bool {
+        *self == GroupPolicy::default()
+    }
+
+    /// True if `member` may change the policy and, with `approval`, add members
+    pub fn is_admin(&self, member: &str) -> bool {
+        self.admins.is_empty() || self.admins.iter().any(|admin| admin == member)
+    }
+}
+
+impl std::fmt::Display for GroupPolicy {
+    /// The policy in words, e.g. "invite-only, at most 8 members, admin approval; admins: Alice"
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.join)?;
+        if let Some(max) = self.max_members {
+            write!(f, ", at most {} members", max)?;
+        }
+        if self.approval {
+            write!(f, ", admin approval")?;
+        }
+        match self.admins.is_empty() {
+            true => write!(f, "; every member is an admin")?,
+            false => write!(f, "; admins: {}", self.admins.join(", "))?,
+        }
+        if !self.bans.is_empty() {
+            write!(f, "; {} banned", self.bans.len())?;
+        }
+        Ok(())
+    }
+}
+
+/// An identity `ban` keeps out of a group, by name and by credential
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Ban {
+    pub member: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub credential: Option<String>, // Fingerprint of their key when banned, if it could be found
+    pub by: String, // The admin who banned them
+    pub at: DateTime<Utc>,
+}
+
+impl Ban {
+    /// True if `member`, holding `public_key` if known, is the one banned
+    pub fn matches(&self, member: &str, public_key: Option<&str>) -> bool {
+        self.member.eq_ignore_ascii_case(member)
+            || public_key.is_some_and(|key| self.credential.as_deref() == Some(crypto::fingerprint(key).as_str()))
+    }
+}
+
+/// Whether anyone may join a group, or only those a member adds
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum JoinRule {
+    #[default]
+    InviteOnly, // Joined only by being added
+    Open,       // Also joined by external commit with `join`
+}
+
+impl std::fmt::Display for JoinRule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JoinRule::InviteOnly => write!(f, "invite-only"),
+            JoinRule::Open => write!(f, "open"),
+        }
+    }
+}
+
+/// Represents a message in the MLS group
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatMessage {
+    pub id: String,
+    pub sender: String,
+    pub content: String,
+    pub encrypted_content: String, // Mock encrypted content
+    pub timestamp: DateTime<Utc>,
+    pub group_id: String,
+    pub epoch: u32,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mentions: Vec<String>, // Members named as `@name` in the content
+    #[serde(default, skip_serializing_if = "ContentType::is_markdown")]
+    pub content_type: ContentType, // What `content` is; see `content::Content`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attachment: Option<Attachment>, // The file, for File and Image messages
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reacts_to: Option<String>, // The message a Reaction or Vote responds to
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub options: Vec<String>, // The choices, for Poll messages
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location: Option<Location>, // The place, for Location messages
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub forwarded_from: Option<Provenance>, // Where a forwarded message was first sent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sender_key: Option<String>, // Fingerprint of the key it arrived with; None if sent from this profile or an older client
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shared_by: Option<String>, // The member who passed it on with `share-history`, for messages from before this profile joined
+}
+
+/// Where a forwarded message came from, as the member who forwarded it saw it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Provenance {
+    pub sender: String,
+    pub group: String, // The group's name in the forwarder's profile
+    pub timestamp: DateTime<Utc>,
+    pub message_id: String,
+}
+
+/// What kind of payload a chat message carried
+///
+/// Messages stored before content types existed are Markdown, since `list`
+/// always rendered them as such.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ContentType {
+    Text,
+    #[default]
+    Markdown,
+    File,
+    Image,
+    Reaction,
+    System,
+    Poll,
+    Vote,
+    Location,
+}
+
+impl ContentType {
+    pub fn is_markdown(&self) -> bool {
+        *self == ContentType::Markdown
+    }
+}
+
+impl std::fmt::Display for ContentType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ContentType::Text => write!(f, "text"),
+            ContentType::Markdown => write!(f, "markdown"),
+            ContentType::File => write!(f, "file"),
+            ContentType::Image => write!(f, "image"),
+            ContentType::Reaction => write!(f, "reaction"),
+            ContentType::System => write!(f, "system"),
+            ContentType::Poll => write!(f, "poll"),
+            ContentType::Vote => write!(f, "vote"),
+            ContentType::Location => write!(f, "location"),
+        }
+    }
+}
+
+/// A file carried inside an encrypted message
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Attachment {
+    pub name: String,
+    pub mime: String,
+    pub size: u64,    // Bytes before encoding
+    pub data: String, // Base64
+}
+
+/// A point on the map shared in a message
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Location {
+    pub latitude: f64,  // Degrees north, -90 to 90
+    pub longitude: f64, // Degrees east, -180 to 180
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+}
+
+impl Location {
+    /// The point at `latitude`, `longitude`, checking both are in range
+    pub fn new(latitude: f64, longitude: f64, label: Option<String>) -> anyhow::Result<Self> {
+        if !(-90.0..=90.0).contains(&latitude) {
+            return Err(anyhow::anyhow!("Latitude {} is not between -90 and 90", latitude));
+        }
+        if !(-180.0..=180.0).contains(&longitude) {
+            return Err(anyhow::anyhow!("Longitude {} is not between -180 and 180", longitude));
+        }
+        let label = label.map(|label| label.trim().to_string()).filter(|label| !label.is_empty());
+        Ok(Self { latitude, longitude, label })
+    }
+
+    /// The coordinates to five decimal places, about a metre
+    pub fn coordinates(&self) -> String {
+        format!("{:.5}, {:.5}", self.latitude, self.longitude)
+    }
+
+    /// A link to the point on OpenStreetMap
+    pub fn osm_url(&self) -> String {
+        format!(
+            "https://www.openstreetmap.org/?mlat={lat:.5}&mlon={lon:.5}#map=16/{lat:.5}/{lon:.5}",
+            lat = self.latitude,
+            lon = self.longitude,
+        )
+    }
+}
+
+/// How far a message has got with one member, in order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReceiptStatus {
+    Delivered,
+    Read,
+}
+
+/// Represents a group in the MLS chat application
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatGroup {
+    pub name: String,
+    pub group_id: String,
+    pub members: Vec<String>,
+    #[serde(default)]
+    pub message_count: usize,
+    #[serde(skip)]
+    pub messages: Option<Vec<ChatMessage>>, // Loaded on demand from storage
+    pub mls_group: MockMlsGroup,
+    #[serde(default)]
+    pub last_seq: u64, // Highest delivery service sequence number applied or sent; 0 if none
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub receipts: HashMap<String, HashMap<String, ReceiptStatus>>, // Message ID -> member -> furthest status
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub tags: HashMap<String, BTreeSet<String>>, // Message ID -> labels set locally, never sent
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pinned: Vec<String>, // Message IDs pinned for every member, oldest pin first
+    #[serde(default)]
+    pub ciphersuite: Ciphersuite,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retention: Option<Retention>, // Overrides the config file's retention for this group
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub archived: bool, // Hidden from `groups` unless --all
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mute: Option<Mute>, // New messages arrive without a notification
+    #[serde(default, skip_serializing_if = "Notify::is_all")]
+    pub notify: Notify, // Which unmuted messages raise a desktop notification; local to this profile
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub scheduled: Vec<ScheduledMessage>, // Waiting to be sent, soonest first; local to this profile
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub draft: Option<String>, // Unsent text kept for next time; local to this profile
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub key_rotations: HashMap<String, KeyRotation>, // Member -> their last Update from this profile
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub join_requests: Vec<JoinRequest>, // Adds and joins waiting for an admin, oldest first; local to this profile
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub outbox: Vec<OutboxMessage>, // Stored here but not yet accepted by the delivery service, oldest first; local to this profile
+}
+
+impl ChatGroup {
+    /// True if the group is muted at `now`
+    pub fn is_muted(&self, now: DateTime<Utc>) -> bool {
+        match self.mute {
+            Some(Mute::Forever) => true,
+            Some(Mute::Until(until)) => now < until,
+            None => false,
+        }
+    }
+}
+
+/// A message `send --at` holds back until its time
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScheduledMessage {
+    pub id: String,
+    pub sender: String,
+    pub at: DateTime<Utc>,
+    pub content: Content,
+}
+
+/// A sent message the delivery service did not accept, kept for `outbox retry`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutboxMessage {
+    pub id: String, // The message's ID
+    pub sender: String,
+    pub epoch: u32, // Its ciphertext's epoch; it cannot be sent once the group moves on
+    pub server: String, // The delivery service it was for
+    pub recipients: Vec<String>,
+    pub payload: String, // The serialized Application, as it was first sent
+    pub queued: DateTime<Utc>,
+    pub attempts: u32, // Sends tried so far, the first included
+    pub last_error: String,
+}
+
+/// How long a group stays muted
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Mute {
+    Forever,
+    Until(DateTime<Utc>),
+}
+
+impl std::fmt::Display for Mute {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Mute::Forever => write!(f, "muted"),
+            Mute::Until(until) => write!(f, "muted until {}", until.format("%Y-%m-%d %H:%M UTC")),
+        }
+    }
+}
+
+/// Which of an unmuted group's new messages raise a desktop notification
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Notify {
+    #[default]
+    All,      // Every message from someone else
+    Mentions, // Only messages that @mention you
+}
+
+impl Notify {
+    pub fn is_all(&self) -> bool {
+        *self == Notify::All
+    }
+}
+
+impl std::fmt::Display for Notify {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Notify::All => write!(f, "every message"),
+            Notify::Mentions => write!(f, "mentions only"),
+        }
+    }
+}
+
+/// MLS ciphersuites a group can use
+///
+/// The demo's primitives in `crypto.rs` stand in for the suite's algorithms.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
+pub enum Ciphersuite {
+    #[default]
+    #[value(name = "MLS_128_DHKEMX25519_AES128GCM_SHA256_Ed25519")]
+    #[serde(rename = "MLS_128_DHKEMX25519_AES128GCM_SHA256_Ed25519")]
+    Mls128Dhkemx25519Aes128gcmSha256Ed25519,
+}
+
+impl std::fmt::Display for Ciphersuite {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Ciphersuite::Mls128Dhkemx25519Aes128gcmSha256Ed25519 => write!(f, "MLS_128_DHKEMX25519_AES128GCM_SHA256_Ed25519"),
+        }
+    }
+}
+
+/// When a member of this profile last sent an Update in a group
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KeyRotation {
+    pub at: DateTime<Utc>, // Time of the Update, or when counting began if there has been none
+    #[serde(default)]
+    pub updated: bool, // False until the first Update
+    #[serde(default)]
+    pub messages_sent: usize, // Messages the member has sent since
+}
+
+/// An add or join held back until an admin approves it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JoinRequest {
+    pub id: String,
+    pub member: String, // Who would join
+    pub requested_by: String, // The member who invited them, or `member` for a join
+    pub at: DateTime<Utc>,
+}
+
+/// When `send` rotates the sender's key with an Update first; unset limits never do
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RotationPolicy {
+    pub after_days: Option<u32>,
+    pub after_messages: Option<usize>,
+}
+
+/// How much message history each group keeps locally
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Retention {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_age_days: Option<u32>, // Messages older than this are deleted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_messages: Option<usize>, // Only the newest this many are kept
+}
@@ -0,0 +1,469 @@
+//! MQTT 3.1.1 transport, for running over an existing broker such as Mosquitto
+//!
+//! Topics:
+//! - `mls-chat/keypackages/<user>`: each user's key package, retained
+//! - `mls-chat/groups/<group_id>`: every message for a group, tagged with its recipients
+//!
+//! Each user has a persistent broker session (client ID `mls-chat-<user>`)
+//! subscribed to all group topics at QoS 1, created when they publish their
+//! key package, so the broker queues messages while they are offline. Anyone
+//! on the broker can see every group's ciphertext; clients keep only
+//! envelopes addressed to them.
+
+use anyhow::{anyhow, Context, Result};
+use std::{
+    collections::VecDeque,
+    io::{ErrorKind, Read, Write},
+    net::TcpStream,
+    time::Duration,
+};
+use uuid::Uuid;
+
+use crate::delivery::{AddressedEnvelope, Envelope, KeyPackage, SendMessageRequest, SendMessageResponse};
+use crate::proxy::Socks5Proxy;
+use crate::transport::Transport;
+
+const TOPIC_PREFIX: &str = "mls-chat";
+const KEEP_ALIVE_SECS: u16 = 60;
+/// Upper bound on waiting for a reply the broker owes us
+const REPLY_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long the broker must stay quiet before queued messages count as drained
+const DRAIN_TIMEOUT: Duration = Duration::from_millis(500);
+
+const CONNECT: u8 = 1;
+const CONNACK: u8 = 2;
+const PUBLISH: u8 = 3;
+const PUBACK: u8 = 4;
+const SUBSCRIBE: u8 = 8;
+const SUBACK: u8 = 9;
+const DISCONNECT: u8 = 14;
+
+/// Transport publishing MLS messages to per-group topics on an MQTT broker
+pub struct MqttTransport {
+    broker: String,
+    proxy: Option<Socks5Proxy>,
+}
+
+impl MqttTransport {
+    /// Use the broker at `broker` (host:port), optionally through a SOCKS5 proxy
+    ///
+    /// Connections are opened per call, so an unreachable broker is reported
+    /// on first use.
+    pub fn new(broker: &str, proxy: Option<Socks5Proxy>) -> Self {
+        Self {
+            broker: broker.to_string(),
+            proxy,
+        }
+    }
+
+    /// Start a throwaway session that leaves nothing behind on the broker
+    fn connect_ephemeral(&self) -> Result<Session> {
+        let client_id = format!("{}-{}", TOPIC_PREFIX, &Uuid::new_v4().simple().to_string()[..12]);
+        Session::connect(self, &client_id, true)
+    }
+
+    /// Resume `user`'s persistent session, subscribing it to all group topics
+    fn connect_user(&self, user: &str) -> Result<Session> {
+        let mut session = Session::connect(self, &format!("{}-{}", TOPIC_PREFIX, user), false)?;
+        session.subscribe(&format!("{}/groups/+", TOPIC_PREFIX))?;
+        Ok(session)
+    }
+}
+
+impl Transport for MqttTransport {
+    fn publish_key_package(&mut self, key_package: KeyPackage) -> Result<()> {
+        // Connecting as the user creates their session, so messages queue from now on
+        let mut session = self.connect_user(&key_package.user)?;
+        let topic = format!("{}/keypackages/{}", TOPIC_PREFIX, key_package.user);
+        session.publish(&topic, &serde_json::to_vec(&key_package)?, true)?;
+        session.disconnect()
+    }
+
+    fn fetch_key_package(&mut self, user: &str) -> Result<KeyPackage> {
+        let mut session = self.connect_ephemeral()?;
+        let topic = format!("{}/keypackages/{}", TOPIC_PREFIX, user);
+        session.subscribe(&topic)?;
+
+        // A retained key package arrives right after the subscription
+        while let Some(publish) = session.next_publish(DRAIN_TIMEOUT)? {
+            session.ack(&publish)?;
+            if publish.topic == topic {
+                session.disconnect()?;
+                return serde_json::from_slice(&publish.payload).context("Malformed key package on broker");
+            }
+        }
+        session.disconnect()?;
+        Err(anyhow!("No key package published for '{}'", user))
+    }
+
+    fn send_message(&mut self, request: SendMessageRequest) -> Result<SendMessageResponse> {
+        let topic = format!("{}/groups/{}", TOPIC_PREFIX, request.group_id);
+        let message = AddressedEnvelope::new(request);
+
+        let mut session = self.connect_ephemeral()?;
+        session.publish(&topic, &serde_json::to_vec(&message)?, false)?;
+        session.disconnect()?;
+        Ok(SendMessageResponse {
+            message_id: message.envelope.message_id,
+            seq: 0,
+        })
+    }
+
+    fn fetch_inbox(&mut self, user: &str) -> Result<Vec<Envelope>> {
+        let mut session = self.connect_user(user)?;
+        let mut envelopes = Vec::new();
+        while let Some(publish) = session.next_publish(DRAIN_TIMEOUT)? {
+            session.ack(&publish)?;
+            match serde_json::from_slice::<AddressedEnvelope>(&publish.payload) {
+                Ok(message) if message.is_for(user) => envelopes.push(message.envelope),
+                Ok(_) => {}
+                Err(err) => eprintln!("⚠️  Ignoring malformed message on '{}': {}", publish.topic, err),
+            }
+        }
+        session.disconnect()?;
+        Ok(envelopes)
+    }
+}
+
+/// One connection to the broker
+struct Session {
+    stream: TcpStream,
+    next_packet_id: u16,
+    stashed: VecDeque<Publish>, // Delivered while waiting for a reply
+}
+
+struct Packet {
+    kind: u8,
+    flags: u8,
+    body: Vec<u8>,
+}
+
+struct Publish {
+    topic: String,
+    packet_id: Option<u16>,
+    payload: Vec<u8>,
+}
+
+impl Session {
+    fn connect(transport: &MqttTransport, client_id: &str, clean_session: bool) -> Result<Self> {
+        let stream = match &transport.proxy {
+            Some(proxy) => proxy.connect(&transport.broker),
+            None => TcpStream::connect(&transport.broker).map_err(Into::into),
+        }
+        .with_context(|| format!("Failed to connect to MQTT broker at {}", transport.broker))?;
+        let mut session = Self { stream, next_packet_id: 1, stashed: VecDeque::new() };
+
+        let mut body = Vec::new();
+        put_string(&mut body, "MQTT");
+        body.push(4); // Protocol level 3.1.1
+        body.push(if clean_session { 0x02 } else { 0x00 });
+        body.extend(KEEP_ALIVE_SECS.to_be_bytes());
+        put_string(&mut body, client_id);
+        session.write_packet(CONNECT << 4, &body)?;
+
+        let reply = session.expect(CONNACK)?;
+        match reply.body.get(1) {
+            Some(0) => Ok(session),
+            Some(code) => Err(anyhow!("MQTT broker refused the connection: {}", connack_message(*code))),
+            None => Err(anyhow!("Malformed CONNACK from MQTT broker")),
+        }
+    }
+
+    fn subscribe(&mut self, topic: &str) -> Result<()> {
+        let packet_id = self.packet_id();
+        let mut body = packet_id.to_be_bytes().to_vec();
+        put_string(&mut body, topic);
+        body.push(1); // QoS 1
+        self.write_packet(SUBSCRIBE << 4 | 0x02, &body)?;
+
+        let reply = self.expect(SUBACK)?;
+        if reply.body.get(2) == Some(&0x80) {
+            return Err(anyhow!("MQTT broker rejected subscription to '{}'", topic));
+        }
+        Ok(())
+    }
+
+    /// Publish at QoS 1 and wait for the broker to accept it
+    fn publish(&mut self, topic: &str, payload: &[u8], retain: bool) -> Result<()> {
+        let packet_id = self.packet_id();
+        let mut body = Vec::new();
+        put_string(&mut body, topic);
+        body.extend(packet_id.to_be_bytes());
+        body.extend(payload);
+        let flags = 0x02 | u8::from(retain);
+        self.write_packet(PUBLISH << 4 | flags, &body)?;
+        self.expect(PUBACK)?;
+        Ok(())
+    }
+
+    fn ack(&mut self, publish: &Publish) -> Result<()> {
+        match publish.packet_id {
+            Some(packet_id) => self.write_packet(PUBACK << 4, &packet_id.to_be_bytes()),
+            None => Ok(()),
+        }
+    }
+
+    fn disconnect(mut self) -> Result<()> {
+        self.write_packet(DISCONNECT << 4, &[])
+    }
+
+    /// Read until a packet of `kind` arrives
+    ///
+    /// Messages delivered in the meantime are stashed for `next_publish`.
+    /// Sessions that never drain them leave them unacknowledged, so the
+    /// broker redelivers them to the next session that does.
+    fn expect(&mut self, kind: u8) -> Result<Packet> {
+        loop {
+            let packet = self.read_packet(REPLY_TIMEOUT)?
+                .with_context(|| format!("Timed out waiting for MQTT packet type {}", kind))?;
+            if packet.kind == kind {
+                return Ok(packet);
+            }
+            if let Some(publish) = packet.as_publish()? {
+                self.stashed.push_back(publish);
+            }
+        }
+    }
+
+    /// Next delivered message, or `None` once the broker has been quiet for `timeout`
+    fn next_publish(&mut self, timeout: Duration) -> Result<Option<Publish>> {
+        if let Some(publish) = self.stashed.pop_front() {
+            return Ok(Some(publish));
+        }
+        while let Some(packet) = self.read_packet(timeout)? {
+            if let Some(publish) = packet.as_publish()? {
+                return Ok(Some(publish));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Read one packet, or `None` if nothing arrives within `timeout`
+    fn read_packet(&mut self, timeout: Duration) -> Result<Option<Packet>> {
+        self.stream.set_read_timeout(Some(timeout))?;
+        let mut header = [0u8; 1];
+        match self.stream.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => return Ok(None),
+            Err(err) => return Err(err).context("MQTT broker closed the connection"),
+        }
+
+        // The rest of the packet is already on its way
+        self.stream.set_read_timeout(Some(REPLY_TIMEOUT))?;
+        let mut length = 0usize;
+        for shift in (0..4).map(|i| i * 7) {
+            let mut byte = [0u8; 1];
+            self.stream.read_exact(&mut byte)?;
+            length |= ((byte[0] & 0x7F) as usize) << shift;
+            if byte[0] & 0x80 == 0 {
+                let mut body = vec![0u8; length];
+                self.stream.read_exact(&mut body)?;
+                return Ok(Some(Packet { kind: header[0] >> 4, flags: header[0] & 0x0F, body }));
+            }
+        }
+        Err(anyhow!("Malformed MQTT packet length"))
+    }
+
+    fn write_packet(&mut self, header: u8, body: &[u8]) -> Result<()> {
+        let mut packet = vec![header];
+        let mut length = body.len();
+        loop {
+            let mut byte = (length % 128) as u8;
+            length /= 128;
+            if length > 0 {
+                byte |= 0x80;
+            }
+            packet.push(byte);
+            if length == 0 {
+                break;
+            }
+        }
+        packet.extend(body);
+        self.stream.write_all(&packet)?;
+        Ok(())
+    }
+
+    fn packet_id(&mut self) -> u16 {
+        let packet_id = self.next_packet_id;
+        self.next_packet_id = self.next_packet_id.checked_add(1).unwrap_or(1);
+        packet_id
+    }
+}
+
+impl Packet {
+    fn as_publish(&self) -> Result<Option<Publish>> {
+        if self.kind != PUBLISH {
+            return Ok(None);
+        }
+        let qos = (self.flags >> 1) & 0x03;
+        let topic_len = self.body.get(..2)
+            .map(|len| u16::from_be_bytes([len[0], len[1]]) as usize)
+            .context("Malformed MQTT PUBLISH")?;
+        let topic = self.body.get(2..2 + topic_len).context("Malformed MQTT PUBLISH")?;
+        let mut offset = 2 + topic_len;
+        let packet_id = if qos > 0 {
+            let id = self.body.get(offset..offset + 2).context("Malformed MQTT PUBLISH")?;
+            offset += 2;
+            Some(u16::from_be_bytes([id[0], id[1]]))
+        } else {
+            None
+        };
+        Ok(Some(Publish {
+            topic: String::from_utf8_lossy(topic).into_owned(),
+            packet_id,
+            payload: self.body[offset..].to_vec(),
+        }))
+    }
+}
+
+fn put_string(buf: &mut Vec<u8>, value: &str) {
+    buf.extend((value.len() as u16).to_be_bytes());
+    buf.extend(value.as_bytes());
+}
+
+fn connack_message(code: u8) -> &'static str {
+    match code {
+        1 => "unacceptable protocol version",
+        2 => "client identifier rejected",
+        3 => "server unavailable",
+        4 => "bad user name or password",
+        5 => "not authorized",
+        _ => "unknown error",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{net::TcpListener, thread::{self, JoinHandle}};
+
+    fn session(stream: TcpStream) -> Session {
+        Session { stream, next_packet_id: 1, stashed: VecDeque::new() }
+    }
+
+    /// Two ends of a local connection, each as a session
+    fn pair() -> (Session, Session) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        (session(client), session(listener.accept().unwrap().0))
+    }
+
+    /// A broker on a local port that runs `script` on the first connection
+    fn broker(script: impl FnOnce(&mut Session) + Send + 'static) -> (String, JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let handle = thread::spawn(move || script(&mut session(listener.accept().unwrap().0)));
+        (addr, handle)
+    }
+
+    fn read(session: &mut Session, kind: u8) -> Packet {
+        let packet = session.read_packet(REPLY_TIMEOUT).unwrap().unwrap();
+        assert_eq!(packet.kind, kind);
+        packet
+    }
+
+    /// Answer a CONNECT and a SUBSCRIBE as a broker would
+    fn accept_subscriber(broker: &mut Session) {
+        read(broker, CONNECT);
+        broker.write_packet(CONNACK << 4, &[0, 0]).unwrap();
+        let subscribe = read(broker, SUBSCRIBE);
+        broker.write_packet(SUBACK << 4, &[subscribe.body[0], subscribe.body[1], 1]).unwrap();
+    }
+
+    fn publish_body(topic: &str, packet_id: u16, payload: &[u8]) -> Vec<u8> {
+        let mut body = Vec::new();
+        put_string(&mut body, topic);
+        body.extend(packet_id.to_be_bytes());
+        body.extend(payload);
+        body
+    }
+
+    #[test]
+    fn round_trips_packets() {
+        let (mut client, mut server) = pair();
+        let body = publish_body("mls-chat/groups/g", 7, &[0xAB; 300]); // Two length bytes
+        client.write_packet(PUBLISH << 4 | 0x02, &body).unwrap();
+        let packet = read(&mut server, PUBLISH);
+        assert_eq!((packet.flags, &packet.body), (0x02, &body));
+        let publish = packet.as_publish().unwrap().unwrap();
+        assert_eq!((publish.topic.as_str(), publish.packet_id), ("mls-chat/groups/g", Some(7)));
+        assert_eq!(publish.payload, [0xAB; 300]);
+
+        let qos0 = Packet { kind: PUBLISH, flags: 0, body: publish_body("t", 7, b"") };
+        assert_eq!(qos0.as_publish().unwrap().unwrap().packet_id, None);
+        assert!(Packet { kind: PUBACK, flags: 0, body: vec![0, 1] }.as_publish().unwrap().is_none());
+        assert!(server.read_packet(Duration::from_millis(50)).unwrap().is_none());
+    }
+
+    #[test]
+    fn refuses_malformed_packets() {
+        assert!(Packet { kind: PUBLISH, flags: 0, body: vec![0] }.as_publish().is_err());
+        assert!(Packet { kind: PUBLISH, flags: 0, body: vec![0, 5, b'a'] }.as_publish().is_err());
+        assert!(Packet { kind: PUBLISH, flags: 0x02, body: vec![0, 1, b'a', 0] }.as_publish().is_err());
+
+        let (mut client, mut server) = pair();
+        client.stream.write_all(&[PUBLISH << 4, 0xFF, 0xFF, 0xFF, 0xFF, 0x01]).unwrap();
+        assert!(server.read_packet(REPLY_TIMEOUT).err().unwrap().to_string().contains("Malformed MQTT packet length"));
+
+        let (mut client, mut server) = pair();
+        client.stream.write_all(&[PUBACK << 4, 2, 0]).unwrap();
+        drop(client);
+        assert!(server.read_packet(REPLY_TIMEOUT).is_err());
+    }
+
+    #[test]
+    fn fetches_a_retained_key_package() {
+        let (addr, broker) = broker(|broker| {
+            accept_subscriber(broker);
+            let key_package = br#"{"user":"Bob","keyId":"k","publicKey":"p"}"#;
+            broker.write_packet(PUBLISH << 4 | 0x03, &publish_body("mls-chat/keypackages/Bob", 9, key_package)).unwrap();
+            assert_eq!(read(broker, PUBACK).body, [0, 9]);
+            read(broker, DISCONNECT);
+        });
+        let key_package = MqttTransport::new(&addr, None).fetch_key_package("Bob").unwrap();
+        assert_eq!((key_package.user.as_str(), key_package.key_id.as_str()), ("Bob", "k"));
+        broker.join().unwrap();
+    }
+
+    #[test]
+    fn keeps_only_envelopes_for_the_user() {
+        let message = |recipient: &str| {
+            let mut message = AddressedEnvelope::new(SendMessageRequest {
+                sender: "Alice".to_string(),
+                group_id: "g".to_string(),
+                recipients: vec![recipient.to_string()],
+                payload: b"mls".to_vec(),
+            });
+            message.envelope.message_id = recipient.to_string();
+            serde_json::to_vec(&message).unwrap()
+        };
+        let (for_bob, for_carol) = (message("Bob"), message("Carol"));
+        let (addr, broker) = broker(move |broker| {
+            accept_subscriber(broker);
+            for (packet_id, payload) in [(1, &for_bob[..]), (2, b"not json"), (3, &for_carol[..])] {
+                broker.write_packet(PUBLISH << 4 | 0x02, &publish_body("mls-chat/groups/g", packet_id, payload)).unwrap();
+                assert_eq!(read(broker, PUBACK).body, packet_id.to_be_bytes());
+            }
+            read(broker, DISCONNECT);
+        });
+        let envelopes = MqttTransport::new(&addr, None).fetch_inbox("Bob").unwrap();
+        assert_eq!(envelopes.iter().map(|envelope| envelope.message_id.as_str()).collect::<Vec<_>>(), ["Bob"]);
+        broker.join().unwrap();
+    }
+
+    #[test]
+    fn reports_refused_connections() {
+        let (addr, _broker) = broker(|broker| {
+            read(broker, CONNECT);
+            broker.write_packet(CONNACK << 4, &[0, 5]).unwrap();
+        });
+        let err = MqttTransport::new(&addr, None).fetch_key_package("Bob").unwrap_err();
+        assert!(err.to_string().contains("not authorized"));
+
+        let (addr, _broker) = broker(|broker| {
+            read(broker, CONNECT);
+            broker.write_packet(CONNACK << 4, &[]).unwrap();
+        });
+        assert!(MqttTransport::new(&addr, None).fetch_key_package("Bob").is_err());
+    }
+}
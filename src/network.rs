@@ -0,0 +1,608 @@
+//! Networked mode: exchanging MLS messages through a delivery service
+
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
+use colored::*;
+use std::collections::{BTreeMap, HashMap};
+
+use crate::app::{new_message, MlsChatApp};
+use crate::client::DeliveryClient;
+use crate::commit;
+use crate::content::Content;
+use crate::crypto;
+use crate::delivery::{Envelope, KeyPackage, SendMessageRequest};
+use crate::model::{ChatGroup, ChatMessage, Ciphersuite, MockMlsGroup, Notify, ReceiptStatus};
+use crate::progress;
+use crate::protocol::{self, MlsMessage};
+use crate::theme;
+use crate::transfer;
+use crate::transport::Transport;
+use crate::tree;
+
+impl MlsChatApp {
+    /// The configured delivery service
+    pub(crate) fn transport(&mut self) -> Result<&mut dyn Transport> {
+        match self.transport.as_mut() {
+            Some(transport) => Ok(transport.as_mut()),
+            None => Err(anyhow!("No delivery service configured (pass --server <addr>)")),
+        }
+    }
+
+    /// Register the current user with a delivery service, keep the token, and publish a key package
+    ///
+    /// The authenticated client becomes this app's transport.
+    pub fn login(&mut self, server: &str, mut client: DeliveryClient) -> Result<()> {
+        let user = self.current_user.context("No user initialized")?.to_string();
+        let token = client.register(&user)
+            .with_context(|| format!("Failed to register '{}' at {}", user, server))?;
+
+        let mut tokens = self.storage.load_server_tokens()?;
+        tokens.entry(server.to_string()).or_default().insert(user.clone(), token.clone());
+        self.storage.save_server_tokens(&tokens)?;
+        println!("✅ Registered '{}' at {}; token saved in this profile", user, server);
+
+        self.set_transport(Box::new(client.with_token(token)), server);
+        self.publish_key_package()
+    }
+
+    /// Token the current user got from `login` at `server`, if any
+    pub fn server_token(&self, server: &str) -> Result<Option<String>> {
+        let Some(user) = self.current_user else { return Ok(None) };
+        let mut tokens = self.storage.load_server_tokens()?;
+        Ok(tokens.get_mut(server).and_then(|users| users.remove(&user.to_string())))
+    }
+
+    /// Publish the current user's key package to the delivery service
+    pub fn publish_key_package(&mut self) -> Result<()> {
+        let user = self.current_user.context("No user initialized")?;
+        let key_package = self.local_key_package(&user.to_string())
+            .with_context(|| format!("User '{}' not initialized", user))?;
+
+        self.transport()?.publish_key_package(key_package)?;
+        println!("✅ Key package for '{}' published to delivery service", user);
+        if self.transparency_log.is_some() {
+            self.publish_to_transparency_log()?;
+        }
+        Ok(())
+    }
+
+    /// Key package for a user whose keys are in this profile
+    pub(crate) fn local_key_package(&self, user: &str) -> Option<KeyPackage> {
+        self.user_keys.get(user).map(|key| KeyPackage {
+            user: user.to_string(),
+            key_id: key.id.clone(),
+            public_key: key.public_key.clone(),
+        })
+    }
+
+    /// Key package for any user, asking the delivery service if the keys are not local
+    pub fn key_package(&mut self, user: &str) -> Result<KeyPackage> {
+        match self.local_key_package(user) {
+            Some(key_package) => Ok(key_package),
+            None => self.fetch_checked_key_package(user),
+        }
+    }
+
+    /// Show which members of a group the delivery service has seen recently
+    ///
+    /// Sends a heartbeat first, so the current user shows up as online to others.
+    pub fn show_presence(&mut self, group_name: &str) -> Result<()> {
+        let user = self.current_user.context("No user initialized")?.to_string();
+        let members = self.groups.get(group_name).context("Group not found")?.members.clone();
+        self.transport()?.heartbeat(&user)?;
+        let presence = self.transport()?.fetch_presence(&members)?;
+
+        println!("{}", format!("Presence in '{}':", group_name).blue());
+        for member in presence {
+            let you = if member.user == user { " (you)" } else { "" };
+            let status = match (member.online, member.last_seen) {
+                (true, _) => "online".green().to_string(),
+                (false, Some(seen)) => format!("last seen {}", ago(seen)).dimmed().to_string(),
+                (false, None) => "never seen".dimmed().to_string(),
+            };
+            let dot = if member.online { "●".green() } else { "○".dimmed() };
+            println!("   {} {}{}: {}", dot, member.user, you, status);
+        }
+        Ok(())
+    }
+
+    /// Send a Commit moving existing members to `next`, and a Welcome to each joiner, in a group without a ratchet tree
+    ///
+    /// `joiners` pairs each new member with the public key from their key package.
+    pub(crate) fn distribute_commit(
+        &mut self,
+        group_name: &str,
+        next: &MockMlsGroup,
+        joiners: &[(String, String)],
+    ) -> Result<()> {
+        let group = self.groups.get(group_name).context("Group not found")?;
+
+        let mut existing = self.remote_members(group);
+        existing.retain(|member| next.members.contains(member)); // A removed member gets no Commit, so never the new secret
+        let commit = MlsMessage::Commit {
+            group_id: group.group_id.clone(),
+            epoch: next.epoch,
+            tree_hash: next.tree_hash.clone(),
+            members: next.members.clone(),
+            encrypted_group_secret: protocol::encrypt(
+                &protocol::commit_key(&group.mls_group.group_secret),
+                &next.group_secret,
+            ),
+            metadata: next.metadata.clone(),
+            tree: None,
+        };
+
+        let mut outgoing = Vec::new();
+        if !existing.is_empty() {
+            outgoing.push((existing, commit.to_bytes()?));
+        }
+        for (joiner, public_key) in joiners.iter().filter(|(joiner, _)| !self.user_keys.contains_key(joiner)) {
+            let welcome = MlsMessage::Welcome {
+                group_name: match next.metadata.name.is_empty() {
+                    true => group.name.clone(),
+                    false => next.metadata.name.clone(),
+                },
+                group_id: group.group_id.clone(),
+                epoch: next.epoch,
+                tree_hash: next.tree_hash.clone(),
+                members: next.members.clone(),
+                encrypted_group_secret: protocol::encrypt(
+                    &protocol::welcome_key(public_key),
+                    &next.group_secret,
+                ),
+                metadata: next.metadata.clone(),
+                tree: None,
+                path_secret: None,
+            };
+            outgoing.push((vec![joiner.clone()], welcome.to_bytes()?));
+        }
+        let group_id = group.group_id.clone();
+        self.send_handshake(&group_id, outgoing)
+    }
+
+    /// Send a commit's Commit and Welcomes, each payload to its recipients
+    pub(crate) fn send_handshake(&mut self, group_id: &str, outgoing: Vec<(Vec<String>, Vec<u8>)>) -> Result<()> {
+        if outgoing.is_empty() {
+            return Ok(());
+        }
+        let user = self.current_user.context("No user initialized")?.to_string();
+        let mut seq = 0;
+        for (recipients, payload) in outgoing {
+            let request = SendMessageRequest {
+                sender: user.clone(),
+                group_id: group_id.to_string(),
+                recipients,
+                payload,
+            };
+            seq = self.transport()?.send_message(request)?.seq;
+        }
+        println!("   Commit and Welcome sent via delivery service");
+        print_seq(seq);
+        self.record_sent_seq(group_id, seq);
+        Ok(())
+    }
+
+    /// Send an application message to every other member of the group
+    pub(crate) fn distribute_application(&mut self, group_name: &str, message: &ChatMessage) -> Result<()> {
+        let group = self.groups.get(group_name).context("Group not found")?;
+        let recipients = self.remote_members(group);
+        if recipients.is_empty() {
+            return Ok(());
+        }
+
+        let application = MlsMessage::Application {
+            group_id: message.group_id.clone(),
+            epoch: message.epoch,
+            message_id: message.id.clone(),
+            sender: message.sender.clone(),
+            timestamp: message.timestamp,
+            ciphertext: message.encrypted_content.clone(),
+            sender_key: self.user_keys.get(&message.sender).map(|key| key.public_key.clone()),
+        };
+        let payload = application.to_bytes()?;
+        if payload.len() > transfer::CHUNK_BYTES {
+            return self.upload(message, recipients, payload);
+        }
+        let request = SendMessageRequest {
+            sender: message.sender.clone(),
+            group_id: message.group_id.clone(),
+            recipients: recipients.clone(),
+            payload: payload.clone(),
+        };
+        match self.transport()?.send_message(request) {
+            Ok(response) => {
+                println!("   Ciphertext handed to delivery service");
+                print_seq(response.seq);
+                self.record_sent_seq(&message.group_id, response.seq);
+                Ok(())
+            }
+            Err(err) => self.queue_outbox(group_name, message, recipients, payload, err),
+        }
+    }
+
+    /// Fetch and apply everything queued for the current user
+    pub fn sync(&mut self) -> Result<()> {
+        let user = self.current_user.context("No user initialized")?;
+        println!("{}", "Fetching messages from delivery service...".green());
+
+        let transport = self.transport()?;
+        let envelopes = progress::spin("Waiting for the delivery service", || transport.fetch_inbox(&user.to_string()))?;
+        if envelopes.is_empty() {
+            println!("No new messages.");
+            return Ok(());
+        }
+
+        let total = envelopes.len();
+        let applied = self.apply_envelopes(envelopes);
+        self.save_state()?;
+        println!("✅ Applied {} of {} incoming message(s)", applied, total);
+        check_all_applied(applied, total)
+    }
+
+    /// Re-fetch and apply the current user's messages in a group from `from_seq` onwards
+    ///
+    /// Anything already applied is recognised and skipped, so overlapping
+    /// ranges are safe.
+    pub fn sync_from(&mut self, group_name: &str, from_seq: u64) -> Result<()> {
+        let user = self.current_user.context("No user initialized")?;
+        // Loaded so replayed messages can be recognised
+        let group_id = self.load_messages(group_name)?.group_id.clone();
+        println!("{}", format!("Fetching '{}' from sequence #{}...", group_name, from_seq).green());
+
+        let transport = self.transport()?;
+        let envelopes = progress::spin("Waiting for the delivery service", || transport.fetch_range(&user.to_string(), &group_id, from_seq))?;
+        if envelopes.is_empty() {
+            println!("No messages from #{} onwards.", from_seq);
+            return Ok(());
+        }
+
+        let total = envelopes.len();
+        let applied = self.apply_envelopes(envelopes);
+        self.save_state()?;
+        println!("✅ Applied {} of {} message(s)", applied, total);
+        check_all_applied(applied, total)
+    }
+
+    /// Apply envelopes in order, reporting the ones that fail; returns how many applied
+    fn apply_envelopes(&mut self, envelopes: Vec<Envelope>) -> usize {
+        let mut applied = 0;
+        let mut bar = progress::Bar::new("messages applied", envelopes.len());
+        for envelope in envelopes {
+            bar.step(|| match self.apply_envelope(&envelope) {
+                Ok(_) => applied += 1,
+                Err(err) => println!("⚠️  Skipped message {} from '{}': {}", envelope.message_id, envelope.sender, err),
+            });
+        }
+        applied
+    }
+
+    /// Apply one incoming envelope to local state
+    ///
+    /// Sequenced envelopes already applied are skipped, and any messages
+    /// missed before one are fetched and applied first. Returns the chat
+    /// message, if the envelope carried one.
+    pub fn apply_envelope(&mut self, envelope: &Envelope) -> Result<Option<ChatMessage>> {
+        if envelope.seq > 0 {
+            if let Some(name) = self.group_name_by_id(&envelope.group_id) {
+                let last_seq = self.groups.get(&name).context("Group not found")?.last_seq;
+                if envelope.seq <= last_seq {
+                    println!("   #{} in '{}' already applied", envelope.seq, name);
+                    return Ok(None);
+                }
+                if last_seq > 0 && envelope.seq > last_seq + 1 {
+                    self.backfill(&name, last_seq + 1, envelope.seq)?;
+                }
+            }
+        }
+
+        let message = self.apply_mls_message(envelope)?;
+        self.record_seq(&envelope.group_id, envelope.seq);
+        Ok(message)
+    }
+
+    /// Fetch and apply the current user's messages in a group numbered `from_seq` up to `before_seq`
+    ///
+    /// Numbers missing from the range belonged to other members.
+    fn backfill(&mut self, group_name: &str, from_seq: u64, before_seq: u64) -> Result<()> {
+        let user = self.current_user.context("No user initialized")?.to_string();
+        let group_id = self.groups.get(group_name).context("Group not found")?.group_id.clone();
+        let missed: Vec<Envelope> = self.transport()?
+            .fetch_range(&user, &group_id, from_seq)
+            .with_context(|| format!(
+                "Messages #{}-#{} in '{}' were missed and could not be fetched",
+                from_seq,
+                before_seq - 1,
+                group_name
+            ))?
+            .into_iter()
+            .filter(|envelope| envelope.seq < before_seq)
+            .collect();
+        if missed.is_empty() {
+            return Ok(());
+        }
+
+        println!("   ⚠️  Recovering {} missed message(s) in '{}' before #{}", missed.len(), group_name, before_seq);
+        for envelope in missed {
+            match self.apply_mls_message(&envelope) {
+                Ok(_) => self.record_seq(&group_id, envelope.seq),
+                Err(err) => println!("⚠️  Skipped missed message {} from '{}': {}", envelope.message_id, envelope.sender, err),
+            }
+        }
+        Ok(())
+    }
+
+    /// Note that a group has seen sequence number `seq`
+    pub(crate) fn record_seq(&mut self, group_id: &str, seq: u64) {
+        let Some(name) = self.group_name_by_id(group_id) else { return };
+        if let Some(group) = self.groups.get_mut(&name) {
+            if seq > group.last_seq {
+                group.last_seq = seq;
+                self.dirty_groups.insert(name);
+            }
+        }
+    }
+
+    /// Note that the current user sent sequence number `seq` in a group
+    ///
+    /// Only a number right after the last one seen counts: a send that jumps
+    /// ahead may have skipped envelopes still waiting in the inbox, such as
+    /// the rest of a batch being applied while receipts go out.
+    pub(crate) fn record_sent_seq(&mut self, group_id: &str, seq: u64) {
+        let Some(name) = self.group_name_by_id(group_id) else { return };
+        if self.groups.get(&name).is_some_and(|group| seq == group.last_seq + 1) {
+            self.record_seq(group_id, seq);
+        }
+    }
+
+    /// `name` for the group `group_id`, suffixed with its ID if another local group already uses it
+    pub(crate) fn unique_group_name(&self, name: String, group_id: &str) -> String {
+        match self.groups.get(&name) {
+            Some(other) if other.group_id != group_id => {
+                format!("{} ({})", name, group_id.chars().take(8).collect::<String>())
+            }
+            _ => name,
+        }
+    }
+
+    /// Apply the MLS message carried by an envelope
+    pub(crate) fn apply_mls_message(&mut self, envelope: &Envelope) -> Result<Option<ChatMessage>> {
+        match MlsMessage::from_bytes(&envelope.payload)? {
+            MlsMessage::Welcome { group_name, group_id, epoch, tree_hash, members, encrypted_group_secret, metadata, tree, path_secret } => {
+                if self.group_name_by_id(&group_id).is_some() {
+                    println!("   Already a member of '{}', ignoring Welcome", group_name);
+                    return Ok(None);
+                }
+                let user = self.current_user.context("No user initialized")?;
+                let key = self.user_keys.get(&user.to_string())
+                    .with_context(|| format!("User '{}' not initialized", user))?;
+                let welcome_key = protocol::welcome_key(&key.public_key);
+                let group_secret = protocol::decrypt(&welcome_key, &encrypted_group_secret)
+                    .context("Failed to decrypt Welcome")?;
+                let mut tree = tree;
+                let mut path_secrets = BTreeMap::new();
+                if let Some(tree) = tree.as_mut() {
+                    if tree.root_hash() != tree_hash {
+                        return Err(anyhow!("The Welcome's ratchet tree does not hash to its tree hash"));
+                    }
+                    if let Some(sealed) = &path_secret {
+                        let secret = protocol::decrypt(&welcome_key, &sealed.ciphertext)
+                            .context("Failed to decrypt the Welcome's path secret")?;
+                        path_secrets.extend(tree.join(sealed, secret)?);
+                    }
+                }
+
+                let name = self.unique_group_name(group_name, &group_id);
+                let group = ChatGroup {
+                    name: name.clone(),
+                    group_id: group_id.clone(),
+                    members: members.clone(),
+                    message_count: 0,
+                    messages: Some(Vec::new()),
+                    mls_group: MockMlsGroup { group_id, epoch, tree_hash, group_secret, members, metadata, tree, path_secrets },
+                    last_seq: 0,
+                    receipts: HashMap::new(),
+                    tags: HashMap::new(),
+                    pinned: Vec::new(),
+                    ciphersuite: Ciphersuite::default(),
+                    retention: None,
+                    archived: false,
+                    mute: None,
+                    notify: Notify::default(),
+                    scheduled: Vec::new(),
+                    draft: None,
+                    key_rotations: HashMap::new(),
+                    join_requests: Vec::new(),
+                    outbox: Vec::new(),
+                };
+                self.groups.insert(name.clone(), group);
+                self.dirty_groups.insert(name.clone());
+                println!("   📥 Joined group '{}' at epoch {} (invited by {})", name, epoch, envelope.sender);
+            }
+            MlsMessage::Commit { group_id, epoch, tree_hash, members, encrypted_group_secret, metadata, tree: change } => {
+                let name = self.group_name_by_id(&group_id).context("Commit for unknown group")?;
+                let identities: HashMap<String, String> = self.user_keys.iter()
+                    .map(|(member, key)| (member.clone(), key.public_key.clone()))
+                    .collect();
+                let group = self.groups.get_mut(&name).context("Group not found")?;
+                if epoch <= group.mls_group.epoch {
+                    println!("   Commit for epoch {} of '{}' already applied", epoch, name);
+                    return Ok(None);
+                }
+                if epoch != group.mls_group.epoch + 1 {
+                    return Err(anyhow!(
+                        "Commit for epoch {} cannot be applied at epoch {}: the commit for epoch {} is missing",
+                        epoch,
+                        group.mls_group.epoch,
+                        group.mls_group.epoch + 1
+                    ));
+                }
+                match change {
+                    Some(change) => {
+                        let mls_group = &mut group.mls_group;
+                        let tree = mls_group.tree.as_mut()
+                            .context("The Commit changes a ratchet tree this profile's copy of the group does not have")?;
+                        let applied = tree.apply(&change, &envelope.sender, &tree_hash, &mls_group.path_secrets, &identities)?;
+                        applied.update(&mut mls_group.path_secrets);
+                        mls_group.group_secret = tree::epoch_secret(&mls_group.group_secret, &applied.commit_secret);
+                        commit::change_members(group, &change.removes, change.adds.into_iter().map(|add| add.member));
+                    }
+                    None => {
+                        let group_secret = protocol::decrypt(
+                            &protocol::commit_key(&group.mls_group.group_secret),
+                            &encrypted_group_secret,
+                        )
+                        .context("Failed to decrypt Commit")?;
+                        group.mls_group.group_secret = group_secret;
+                        group.mls_group.tree = None; // A tree the commit did not change is out of date
+                        group.mls_group.path_secrets.clear();
+                        group.mls_group.members = members.clone();
+                        group.members = members;
+                    }
+                }
+                group.mls_group.epoch = epoch;
+                group.mls_group.tree_hash = tree_hash;
+                let renamed = !metadata.name.is_empty() && metadata.name != group.mls_group.metadata.name;
+                group.mls_group.metadata = metadata;
+                self.dirty_groups.insert(name.clone());
+                println!("   🔄 '{}' moved to epoch {} (commit by {})", name, epoch, envelope.sender);
+                if renamed {
+                    let mut group = self.groups.remove(&name).context("Group not found")?;
+                    self.dirty_groups.remove(&name);
+                    let new_name = self.unique_group_name(group.mls_group.metadata.name.clone(), &group_id);
+                    group.name = new_name.clone();
+                    self.groups.insert(new_name.clone(), group);
+                    self.dirty_groups.insert(new_name.clone());
+                    self.note_rename(&name, &new_name);
+                    println!("   ✏️  '{}' renamed to '{}' by {}", name, new_name, envelope.sender);
+                }
+            }
+            MlsMessage::Application { group_id, epoch, message_id, sender, timestamp, ciphertext, sender_key } => {
+                let name = self.group_name_by_id(&group_id).context("Message for unknown group")?;
+                let group = self.groups.get_mut(&name).context("Group not found")?;
+                let known = group.messages.as_ref()
+                    .is_some_and(|messages| messages.iter().any(|message| message.id == message_id));
+                if known {
+                    println!("   Message {} in '{}' already received", message_id, name);
+                    return Ok(None);
+                }
+                if epoch != group.mls_group.epoch {
+                    return Err(anyhow!(
+                        "Message from epoch {} cannot be decrypted at epoch {}",
+                        epoch,
+                        group.mls_group.epoch
+                    ));
+                }
+                let plaintext = protocol::decrypt(&protocol::application_key(&group.mls_group.group_secret), &ciphertext)?;
+                let content = match Content::from_plaintext(&plaintext) {
+                    Content::Receipt(body) => {
+                        self.record_receipt(&name, &sender, body)?;
+                        return Ok(None);
+                    }
+                    content => content,
+                };
+
+                let mut message = new_message(message_id, sender.clone(), content, ciphertext, timestamp, group)?;
+                if let Some(public_key) = &sender_key {
+                    self.observe_key(&sender, public_key)?;
+                    message.sender_key = Some(crypto::fingerprint(public_key));
+                }
+                let group = self.groups.get_mut(&name).context("Group not found")?;
+                self.storage.append_message(&message)?;
+                group.message_count += 1;
+                if let Some(messages) = group.messages.as_mut() {
+                    messages.push(message.clone());
+                }
+                self.dirty_groups.insert(name.clone());
+                let mentions_user = self.current_user.is_some_and(|user| message.mentions.contains(&user.to_string()));
+                if !group.is_muted(Utc::now()) {
+                    let note = if mentions_user { " (mentions you)" } else { "" };
+                    println!("   💬 New message in '{}' from {}{}", name, theme::sender(&sender, false), note);
+                }
+
+                if self.is_remote_member(&name, &sender) {
+                    if let Err(err) = self.send_receipt(&name, &sender, ReceiptStatus::Delivered, vec![message.id.clone()]) {
+                        println!("⚠️  Could not send delivery receipt to '{}': {}", sender, err);
+                    }
+                }
+                return Ok(Some(message));
+            }
+            MlsMessage::Receipt { group_id, epoch, sender, ciphertext } => {
+                let name = self.group_name_by_id(&group_id).context("Receipt for unknown group")?;
+                self.apply_receipt(&name, &sender, epoch, &ciphertext)?;
+            }
+            MlsMessage::Pin { group_id, epoch, sender, ciphertext } => {
+                let name = self.group_name_by_id(&group_id).context("Pin for unknown group")?;
+                self.apply_pin(&name, &sender, epoch, &ciphertext)?;
+            }
+            MlsMessage::KeyChange { group_id, epoch, sender, ciphertext } => {
+                let name = self.group_name_by_id(&group_id).context("Key change for unknown group")?;
+                self.apply_key_change(&name, &sender, epoch, &ciphertext)?;
+            }
+            MlsMessage::History { group_id, sender, ciphertext, .. } => {
+                let name = self.group_name_by_id(&group_id).context("Shared history for unknown group")?;
+                self.apply_history(&name, &sender, &ciphertext)?;
+            }
+            MlsMessage::Manifest { group_id, message_id, size, chunks, hash } => {
+                let name = self.group_name_by_id(&group_id).context("Manifest for unknown group")?;
+                self.apply_manifest(&name, envelope, message_id, size, chunks, hash)?;
+            }
+            MlsMessage::Chunk { group_id, message_id, index, data } => {
+                self.group_name_by_id(&group_id).context("Chunk for unknown group")?;
+                return self.apply_chunk(envelope, &message_id, index, data);
+            }
+        }
+        Ok(None)
+    }
+
+    /// Whether `user` is a member of the group whose keys are not in this profile
+    pub(crate) fn is_remote_member(&self, group_name: &str, user: &str) -> bool {
+        self.groups.get(group_name).is_some_and(|group| group.members.iter().any(|member| member == user))
+            && !self.user_keys.contains_key(user)
+    }
+
+    /// Members whose keys are not in this profile, and so need messages delivered
+    ///
+    /// Local users share this profile's group state and are never sent anything.
+    pub(crate) fn remote_members(&self, group: &ChatGroup) -> Vec<String> {
+        group.members.iter()
+            .filter(|member| !self.user_keys.contains_key(*member))
+            .cloned()
+            .collect()
+    }
+
+    /// Find the local name of a group by its MLS group ID
+    pub(crate) fn group_name_by_id(&self, group_id: &str) -> Option<String> {
+        self.groups.values()
+            .find(|group| group.group_id == group_id)
+            .map(|group| group.name.clone())
+    }
+}
+
+/// Show the sequence number a delivery service assigned, if it assigns them
+pub(crate) fn print_seq(seq: u64) {
+    if seq > 0 {
+        println!("   Sequenced as #{} in the group", seq);
+    }
+}
+
+/// Fail if any envelope could not be applied, since the group may now be stuck behind it
+fn check_all_applied(applied: usize, total: usize) -> Result<()> {
+    if applied < total {
+        return Err(anyhow!(
+            "{} message(s) could not be applied; once the cause is fixed, recover with `sync --group <name> --from-seq <n>`",
+            total - applied
+        ));
+    }
+    Ok(())
+}
+
+/// Rough age of a timestamp, such as "5m ago"
+pub(crate) fn ago(time: DateTime<Utc>) -> String {
+    let seconds = (Utc::now() - time).num_seconds().max(0);
+    match seconds {
+        0..=59 => "just now".to_string(),
+        60..=3599 => format!("{}m ago", seconds / 60),
+        3600..=86399 => format!("{}h ago", seconds / 3600),
+        _ => format!("{}d ago", seconds / 86400),
+    }
+}
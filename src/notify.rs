@@ -0,0 +1,153 @@
+//! Desktop notifications for new messages
+//!
+//! `list --follow` raises one for each message from someone else it prints,
+//! through the program the `notify.command` setting names: `auto` picks
+//! `notify-send` on Linux desktops and `osascript` on macOS, and any other
+//! program is run with the title and body as its two arguments. Notifications
+//! name the group and sender but never the content. Muted groups raise none,
+//! and a group set to `notify <group> mentions` only for messages that
+//! @mention the user; the mode is local to this profile, like `mute`.
+
+use anyhow::{anyhow, Context, Result};
+use chrono::Utc;
+use std::process::{Command, Stdio};
+
+use crate::app::MlsChatApp;
+use crate::model::{ChatMessage, Notify};
+
+/// How notifications are raised, from the `notify.command` setting
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Notifier {
+    NotifySend,      // Linux desktops, through libnotify
+    Osascript,       // macOS Notification Center
+    Command(String), // Any other program, given the title and body
+}
+
+impl Notifier {
+    /// The notifier a `notify.command` value names; `off` names none
+    pub fn parse(command: &str) -> Option<Self> {
+        match command {
+            "off" | "" => None,
+            "auto" if cfg!(target_os = "macos") => Some(Notifier::Osascript),
+            "auto" | "notify-send" => Some(Notifier::NotifySend),
+            "osascript" => Some(Notifier::Osascript),
+            program => Some(Notifier::Command(program.to_string())),
+        }
+    }
+
+    /// Raise one notification, waiting for the program to finish
+    pub fn raise(&self, title: &str, body: &str) -> Result<()> {
+        let mut command = match self {
+            Notifier::NotifySend => {
+                let mut command = Command::new("notify-send");
+                command.args(["--app-name", "mls-chat", title, body]);
+                command
+            }
+            Notifier::Osascript => {
+                // Passed as arguments so nothing in them needs AppleScript quoting
+                let mut command = Command::new("osascript");
+                command.args([
+                    "-e", "on run argv",
+                    "-e", "display notification (item 2 of argv) with title (item 1 of argv)",
+                    "-e", "end run",
+                    title, body,
+                ]);
+                command
+            }
+            Notifier::Command(program) => {
+                let mut command = Command::new(program);
+                command.args([title, body]);
+                command
+            }
+        };
+        let status = command.stdin(Stdio::null()).stdout(Stdio::null()).stderr(Stdio::null())
+            .status()
+            .with_context(|| format!("Failed to run {}", self))?;
+        match status.success() {
+            true => Ok(()),
+            false => Err(anyhow!("{} exited with {}", self, status)),
+        }
+    }
+}
+
+impl std::fmt::Display for Notifier {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Notifier::NotifySend => write!(f, "notify-send"),
+            Notifier::Osascript => write!(f, "osascript"),
+            Notifier::Command(program) => write!(f, "{}", program),
+        }
+    }
+}
+
+impl MlsChatApp {
+    /// Raise desktop notifications with `notifier` for what `list --follow` prints; `None` for none
+    pub fn set_notifier(&mut self, notifier: Option<Notifier>) {
+        self.notifier = notifier;
+    }
+
+    /// Choose which of a group's new messages raise a desktop notification
+    pub fn set_notify(&mut self, group_name: &str, notify: Notify) -> Result<()> {
+        let group = self.groups.get_mut(group_name).context("Group not found")?;
+        group.notify = notify;
+        let muted = group.is_muted(Utc::now());
+        self.dirty_groups.insert(group_name.to_string());
+        println!("✅ '{}' notifies you of {}", group_name, notify);
+        if muted {
+            println!("   It is muted, so it raises none until it is unmuted");
+        }
+        if self.notifier.is_none() {
+            println!("   Desktop notifications are off; `config set notify.command auto` turns them on for `list --follow`");
+        }
+        self.save_state()
+    }
+
+    /// Raise a notification for a message `list --follow` printed, if its group's settings call for one
+    ///
+    /// A notifier that fails is turned off for the rest of the run, with a warning, rather than failing every round.
+    pub(crate) fn notify_message(&mut self, group_name: &str, message: &ChatMessage) {
+        let (Some(notifier), Some(user)) = (&self.notifier, self.current_user.map(|user| user.to_string())) else {
+            return;
+        };
+        let Some(group) = self.groups.get(group_name) else {
+            return;
+        };
+        if message.sender == user || group.is_muted(Utc::now()) {
+            return;
+        }
+        let mentioned = message.mentions.contains(&user);
+        if group.notify == Notify::Mentions && !mentioned {
+            return;
+        }
+        let body = match mentioned {
+            true => format!("{} mentioned you", message.sender),
+            false => format!("New message from {}", message.sender),
+        };
+        if let Err(err) = notifier.raise(&format!("mls-chat: {}", group_name), &body) {
+            println!("⚠️  Desktop notifications are off for the rest of this run: {:#}", err);
+            self.notifier = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_notify_commands() {
+        assert_eq!(Notifier::parse("off"), None);
+        assert_eq!(Notifier::parse(""), None);
+        assert_eq!(Notifier::parse("osascript"), Some(Notifier::Osascript));
+        assert_eq!(Notifier::parse("notify-send"), Some(Notifier::NotifySend));
+        assert_eq!(Notifier::parse("/usr/local/bin/ping-me"), Some(Notifier::Command("/usr/local/bin/ping-me".to_string())));
+        assert!(Notifier::parse("auto").is_some());
+    }
+
+    #[test]
+    fn reports_failing_programs() {
+        assert!(Notifier::Command("true".to_string()).raise("title", "body").is_ok());
+        assert!(Notifier::Command("false".to_string()).raise("title", "body").is_err());
+        assert!(Notifier::Command("/nonexistent/notifier".to_string()).raise("title", "body").is_err());
+    }
+}
@@ -0,0 +1,233 @@
+//! The outbox: sent messages the delivery service has not accepted yet
+//!
+//! A message is stored as soon as it is sent, whether or not the delivery
+//! service takes it. When the service refuses or drops a send, for example
+//! on a rate limit or a restart under a long-running `bot`, the Application
+//! is kept in its group's outbox, local to this profile like `scheduled`,
+//! with the delivery service it was for, the attempts so far, and the last
+//! error. Commands that send anyway, the same ones that send due `scheduled`
+//! messages, try the outbox again when they start with a connection to that
+//! delivery service, oldest first; nothing else touches it, and `outbox retry`
+//! and `outbox drop` handle one message by hand.
+//!
+//! As with `transfers`, a queued message is bound to the epoch it was
+//! encrypted in. Once the group commits past it, the others can no longer
+//! decrypt it, so it is not sent any more and should be dropped and sent again.
+//! Messages too large for one envelope wait in `transfers` instead.
+
+use anyhow::{anyhow, Context, Result};
+use chrono::Utc;
+use colored::*;
+
+use crate::app::MlsChatApp;
+use crate::delivery::SendMessageRequest;
+use crate::i18n::tr;
+use crate::model::{ChatMessage, OutboxMessage, TransferDirection};
+use crate::network::print_seq;
+use crate::output;
+use crate::pins::short_id;
+
+impl MlsChatApp {
+    /// Keep a message the delivery service did not take in its group's outbox; the caller still stores it
+    pub(crate) fn queue_outbox(
+        &mut self,
+        group_name: &str,
+        message: &ChatMessage,
+        recipients: Vec<String>,
+        payload: Vec<u8>,
+        err: anyhow::Error,
+    ) -> Result<()> {
+        let queued = OutboxMessage {
+            id: message.id.clone(),
+            sender: message.sender.clone(),
+            epoch: message.epoch,
+            server: self.server.clone(),
+            recipients,
+            payload: String::from_utf8(payload).context("Serialized message is not UTF-8")?,
+            queued: Utc::now(),
+            attempts: 1,
+            last_error: format!("{:#}", err),
+        };
+        let group = self.groups.get_mut(group_name).context("Group not found")?;
+        group.outbox.push(queued);
+        self.dirty_groups.insert(group_name.to_string());
+        println!("{}", tr("outbox-queued", &[("id", &short_id(&message.id)), ("error", &format!("{:#}", err))]));
+        println!("   {}", tr("outbox-queued-hint", &[("group", &group_name)]));
+        Ok(())
+    }
+
+    /// Print the messages waiting in one group's outbox, or in every group's
+    pub fn list_outbox(&self, group_name: Option<&str>) -> Result<()> {
+        let mut groups: Vec<&str> = match group_name {
+            Some(name) => vec![self.groups.get(name).context("Group not found")?.name.as_str()],
+            None => self.groups.keys().map(String::as_str).collect(),
+        };
+        groups.sort();
+        match group_name {
+            Some(name) => println!("{}", tr("outbox-header-group", &[("group", &name)]).blue()),
+            None => println!("{}", tr("outbox-header", &[]).blue()),
+        }
+        output::rule('=', 50);
+
+        let mut waiting = 0;
+        for name in &groups {
+            let group = self.groups.get(*name).context("Group not found")?;
+            for queued in &group.outbox {
+                waiting += 1;
+                println!("{}", tr("outbox-entry", &[
+                    ("id", &short_id(&queued.id).dimmed()),
+                    ("group", name),
+                    ("sender", &queued.sender),
+                    ("server", &queued.server),
+                    ("recipients", &queued.recipients.len()),
+                    ("queued", &queued.queued.format("%Y-%m-%d %H:%M UTC")),
+                    ("attempts", &queued.attempts),
+                ]));
+                println!("   {}", tr("outbox-last-error", &[("error", &queued.last_error)]));
+                if queued.epoch != group.mls_group.epoch {
+                    println!("   {}", tr("outbox-stale", &[
+                        ("group", name),
+                        ("from", &queued.epoch),
+                        ("to", &group.mls_group.epoch),
+                        ("id", &short_id(&queued.id)),
+                    ]).yellow());
+                }
+            }
+        }
+        if waiting == 0 {
+            println!("{}", tr("outbox-empty", &[]));
+        }
+
+        let group_ids: Vec<&str> = groups.iter()
+            .filter_map(|name| self.groups.get(*name))
+            .map(|group| group.group_id.as_str())
+            .collect();
+        let uploads = self.storage.load_transfers()?
+            .into_iter()
+            .filter(|transfer| transfer.direction == TransferDirection::Upload && group_ids.contains(&transfer.group_id.as_str()))
+            .count();
+        if uploads > 0 {
+            println!("{}", tr("outbox-uploads", &[("count", &uploads)]));
+        }
+        Ok(())
+    }
+
+    /// Send a queued message again now; `id` may be a unique prefix
+    pub fn retry_outbox(&mut self, id: &str) -> Result<()> {
+        let (group_name, queued) = self.find_outbox(id)?;
+        self.transport()?;
+        if queued.server != self.server {
+            return Err(anyhow!(
+                "Message {} was for the delivery service at {}; pass --server {} to retry it there",
+                short_id(&queued.id), queued.server, queued.server
+            ));
+        }
+        let epoch = self.groups.get(&group_name).context("Group not found")?.mls_group.epoch;
+        if queued.epoch != epoch {
+            return Err(anyhow!(
+                "'{}' has moved from epoch {} to {}, so the message can no longer be read; drop it with `outbox drop {}` and send it again",
+                group_name, queued.epoch, epoch, short_id(&queued.id)
+            ));
+        }
+        let sent = self.deliver_queued(&group_name, &queued);
+        self.save_state()?;
+        let seq = sent.with_context(|| format!("Message {} is still in the outbox", short_id(&queued.id)))?;
+        println!("{}", tr("outbox-retried", &[("id", &short_id(&queued.id)), ("recipients", &queued.recipients.len())]));
+        print_seq(seq);
+        Ok(())
+    }
+
+    /// Give up on delivering a queued message; `id` may be a unique prefix
+    ///
+    /// The message stays in this profile's history; the others never receive it.
+    pub fn drop_outbox(&mut self, id: &str) -> Result<()> {
+        let (group_name, queued) = self.find_outbox(id)?;
+        let group = self.groups.get_mut(&group_name).context("Group not found")?;
+        group.outbox.retain(|message| message.id != queued.id);
+        self.dirty_groups.insert(group_name.clone());
+        println!("{}", tr("outbox-dropped", &[("id", &short_id(&queued.id)), ("group", &group_name)]));
+        println!("   {}", tr("outbox-dropped-hint", &[]));
+        self.save_state()
+    }
+
+    /// Try every queued message for the connected delivery service again, oldest first; returns how many went out
+    ///
+    /// `main` calls this only for commands that send messages themselves.
+    /// Messages from an earlier epoch are left for `outbox drop`, and those
+    /// for another delivery service for when it is connected.
+    pub fn deliver_outbox(&mut self) -> Result<usize> {
+        if self.transport.is_none() {
+            return Ok(0);
+        }
+        let mut due: Vec<(String, OutboxMessage)> = Vec::new();
+        for group in self.groups.values() {
+            for queued in group.outbox.iter().filter(|queued| queued.server == self.server && queued.epoch == group.mls_group.epoch) {
+                due.push((group.name.clone(), queued.clone()));
+            }
+        }
+        if due.is_empty() {
+            return Ok(0);
+        }
+        due.sort_by_key(|(_, queued)| queued.queued);
+
+        let mut sent = 0;
+        for (group_name, queued) in due {
+            match self.deliver_queued(&group_name, &queued) {
+                Ok(_) => {
+                    println!("{}", tr("outbox-delivered", &[("id", &short_id(&queued.id)), ("group", &group_name)]));
+                    sent += 1;
+                }
+                Err(err) => println!("{}", tr("outbox-still-queued", &[
+                    ("id", &short_id(&queued.id)),
+                    ("group", &group_name),
+                    ("error", &format!("{:#}", err)),
+                ])),
+            }
+        }
+        self.save_state()?;
+        Ok(sent)
+    }
+
+    /// Send one queued message, taking it out of the outbox if the delivery service accepts it; returns its sequence number
+    fn deliver_queued(&mut self, group_name: &str, queued: &OutboxMessage) -> Result<u64> {
+        let group = self.groups.get(group_name).context("Group not found")?;
+        let group_id = group.group_id.clone();
+        let request = SendMessageRequest {
+            sender: queued.sender.clone(),
+            group_id: group_id.clone(),
+            recipients: queued.recipients.clone(),
+            payload: queued.payload.clone().into_bytes(),
+        };
+        let sent = self.transport()?.send_message(request);
+        let group = self.groups.get_mut(group_name).context("Group not found")?;
+        self.dirty_groups.insert(group_name.to_string());
+        match sent {
+            Ok(response) => {
+                group.outbox.retain(|message| message.id != queued.id);
+                self.record_sent_seq(&group_id, response.seq);
+                Ok(response.seq)
+            }
+            Err(err) => {
+                if let Some(message) = group.outbox.iter_mut().find(|message| message.id == queued.id) {
+                    message.attempts += 1;
+                    message.last_error = format!("{:#}", err);
+                }
+                Err(err)
+            }
+        }
+    }
+
+    /// The queued message `id` is a unique prefix of, with its group's name
+    fn find_outbox(&self, id: &str) -> Result<(String, OutboxMessage)> {
+        let matches: Vec<(String, OutboxMessage)> = self.groups.values()
+            .flat_map(|group| group.outbox.iter().map(move |queued| (group.name.clone(), queued)))
+            .filter(|(_, queued)| !id.is_empty() && queued.id.starts_with(id))
+            .map(|(group, queued)| (group, queued.clone()))
+            .collect();
+        match matches.len() {
+            1 => Ok(matches.into_iter().next().context("No queued message")?),
+            0 => Err(anyhow!("No queued message '{}'; `outbox` lists them", id)),
+            count => Err(anyhow!("'{}' matches {} queued messages; give more of the ID", id, count)),
+        }
+    }
+}
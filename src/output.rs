@@ -0,0 +1,72 @@
+//! Plain output for screen readers and simple terminals
+//!
+//! `--plain` turns off colors and makes the commands that support it print
+//! one self-contained line per item: no separator rules, no column padding,
+//! and words instead of emoji and symbols, so that nothing is read aloud as
+//! "heavy check mark" or "box drawings light horizontal".
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static PLAIN: AtomicBool = AtomicBool::new(false);
+
+/// Symbols with a meaning, and the words plain output says instead
+const WORDS: &[(&str, &str)] = &[
+    ("⚠️", "Warning:"),
+    ("❌", "Error:"),
+    ("📎", "Attachment:"),
+    ("📊", "Poll:"),
+    ("📍", "Location:"),
+    ("↪", ""),
+    ("✎", "(draft)"),
+    ("✓✓", "(read by all)"),
+    ("✓", "(delivered to all)"),
+    ("🔒", "(verified)"),
+    ("█", ""),
+];
+
+/// Turn plain output on for the rest of the run; it also turns colors off
+pub fn set_plain(plain: bool) {
+    PLAIN.store(plain, Ordering::Relaxed);
+    if plain {
+        colored::control::set_override(false);
+    }
+}
+
+/// True if `--plain` was given
+pub fn is_plain() -> bool {
+    PLAIN.load(Ordering::Relaxed)
+}
+
+/// Print a separator line of `width` copies of `c`, except in plain output
+pub fn rule(c: char, width: usize) {
+    if !is_plain() {
+        println!("{}", c.to_string().repeat(width));
+    }
+}
+
+/// `text` as plain output shows it: symbols replaced by words or dropped, and
+/// the spaces around them tidied; unchanged unless plain output is on
+pub fn words(text: &str) -> String {
+    if !is_plain() {
+        return text.to_string();
+    }
+    text.lines().map(plain_line).collect::<Vec<_>>().join("\n")
+}
+
+fn plain_line(line: &str) -> String {
+    let indent = &line[..line.len() - line.trim_start().len()];
+    let mut line = line.to_string();
+    for (symbol, word) in WORDS {
+        line = line.replace(symbol, word);
+    }
+    let line: String = line.chars().filter(|c| !is_symbol(*c)).collect();
+    let words: Vec<&str> = line.split_whitespace().collect();
+    format!("{}{}", indent, words.join(" "))
+}
+
+/// Emoji, dingbats, arrows, and box drawing, which screen readers spell out
+fn is_symbol(c: char) -> bool {
+    matches!(c as u32,
+        0x2190..=0x21FF | 0x2300..=0x23FF | 0x2500..=0x27BF | 0x2B00..=0x2BFF
+        | 0x1F000..=0x1FAFF | 0xFE0E | 0xFE0F | 0x200D)
+}
@@ -0,0 +1,176 @@
+//! Pinned messages
+//!
+//! A pin is group state every member shares: pinning or unpinning sends an
+//! encrypted Pin to the other members, who apply it to their own copy. `list`
+//! shows a group's pinned messages above the rest.
+
+use anyhow::{anyhow, Context, Result};
+use colored::*;
+
+use crate::app::MlsChatApp;
+use crate::delivery::SendMessageRequest;
+use crate::model::{ChatGroup, ChatMessage};
+use crate::output;
+use crate::protocol::{self, MlsMessage, PinBody};
+use crate::theme;
+
+/// How many characters of a message ID `list --ids` and the pinned panel show
+pub const SHORT_ID_LEN: usize = 8;
+
+impl MlsChatApp {
+    /// Pin a message in a group for every member, or unpin it; `message_id` may be a unique prefix
+    pub fn set_pinned(&mut self, group_name: &str, message_id: &str, pinned: bool) -> Result<()> {
+        let user = self.current_user.context("No user initialized")?.to_string();
+        let group = self.groups.get(group_name).context("Group not found")?;
+        if !may_pin(group, &user) {
+            return Err(anyhow!("User '{}' may not change pins in group '{}'", user, group_name));
+        }
+        let (message_id, _) = self.find_message(group_name, message_id)?;
+        let group = self.groups.get(group_name).context("Group not found")?;
+        if group.pinned.contains(&message_id) == pinned {
+            println!("⚠️  Message {} is already {}", short_id(&message_id), if pinned { "pinned" } else { "not pinned" });
+            return Ok(());
+        }
+
+        if self.transport.is_some() {
+            self.distribute_pin(group_name, &PinBody { message_id: message_id.clone(), pinned })?;
+        }
+        let group = self.groups.get_mut(group_name).context("Group not found")?;
+        change_pin(group, &message_id, pinned);
+        self.dirty_groups.insert(group_name.to_string());
+        match pinned {
+            true => println!("📌 Pinned message {} in '{}'", short_id(&message_id), group_name),
+            false => println!("✅ Unpinned message {} in '{}'", short_id(&message_id), group_name),
+        }
+        self.save_state()
+    }
+
+    /// Send a pin change to every other member of the group
+    fn distribute_pin(&mut self, group_name: &str, body: &PinBody) -> Result<()> {
+        let user = self.current_user.context("No user initialized")?.to_string();
+        let group = self.groups.get(group_name).context("Group not found")?;
+        let recipients = self.remote_members(group);
+        if recipients.is_empty() {
+            return Ok(());
+        }
+        let pin = MlsMessage::Pin {
+            group_id: group.group_id.clone(),
+            epoch: group.mls_group.epoch,
+            sender: user.clone(),
+            ciphertext: protocol::encrypt(
+                &protocol::application_key(&group.mls_group.group_secret),
+                &serde_json::to_string(body)?,
+            ),
+        };
+        let group_id = group.group_id.clone();
+        let request = SendMessageRequest {
+            sender: user,
+            group_id: group_id.clone(),
+            recipients,
+            payload: pin.to_bytes()?,
+        };
+        let seq = self.transport()?.send_message(request)?.seq;
+        self.record_sent_seq(&group_id, seq);
+        Ok(())
+    }
+
+    /// Apply a Pin from another member
+    ///
+    /// The pinned message may not be here yet, or at all if it predates this
+    /// member joining; the pin is kept anyway and shown once the message is.
+    pub(crate) fn apply_pin(&mut self, group_name: &str, sender: &str, epoch: u32, ciphertext: &str) -> Result<()> {
+        let group = self.groups.get_mut(group_name).context("Group not found")?;
+        if epoch != group.mls_group.epoch {
+            return Err(anyhow!("Pin from epoch {} cannot be decrypted at epoch {}", epoch, group.mls_group.epoch));
+        }
+        if !may_pin(group, sender) {
+            return Err(anyhow!("'{}' may not change pins in '{}'", sender, group_name));
+        }
+        let body = protocol::decrypt(&protocol::application_key(&group.mls_group.group_secret), ciphertext)?;
+        let body: PinBody = serde_json::from_str(&body).context("Malformed pin")?;
+        change_pin(group, &body.message_id, body.pinned);
+        self.dirty_groups.insert(group_name.to_string());
+        let verb = if body.pinned { "📌 pinned" } else { "unpinned" };
+        println!("   {} {} message {} in '{}'", sender, verb, short_id(&body.message_id), group_name);
+        Ok(())
+    }
+}
+
+/// Print those of `messages` that are pinned in `group`, oldest pin first; nothing if there are none
+pub(crate) fn print_pinned(group: &ChatGroup, messages: &[ChatMessage]) {
+    let pinned: Vec<_> = group.pinned.iter()
+        .filter_map(|id| messages.iter().find(|message| message.id == *id))
+        .collect();
+    if pinned.is_empty() {
+        return;
+    }
+    println!("📌 Pinned:");
+    for message in pinned {
+        println!("   {} {}: {}", short_id(&message.id).dimmed(), theme::sender(&message.sender, false), message.content);
+    }
+    output::rule('-', 50);
+}
+
+/// The start of a message ID, as `list --ids` shows it
+pub(crate) fn short_id(message_id: &str) -> &str {
+    &message_id[..message_id.len().min(SHORT_ID_LEN)]
+}
+
+/// Whether `user` may pin and unpin messages in `group`
+///
+/// Until groups have roles, every member may.
+fn may_pin(group: &ChatGroup, user: &str) -> bool {
+    group.members.iter().any(|member| member == user)
+}
+
+fn change_pin(group: &mut ChatGroup, message_id: &str, pinned: bool) {
+    group.pinned.retain(|id| id != message_id);
+    if pinned {
+        group.pinned.push(message_id.to_string());
+    }
+}
+
+/// The full ID of the loaded or pinned message in `group` whose ID is or starts with `prefix`
+pub(crate) fn find_message_id(group: &ChatGroup, prefix: &str) -> Result<String> {
+    let ids: Vec<&str> = group.messages.as_deref().unwrap_or_default()
+        .iter()
+        .map(|message| message.id.as_str())
+        .collect();
+    pick_message_id(group, ids, prefix)
+}
+
+/// The one ID among `ids` and `group`'s pins that is `prefix`, or else starts with it
+fn pick_message_id(group: &ChatGroup, ids: Vec<&str>, prefix: &str) -> Result<String> {
+    let mut ids: Vec<&str> = ids.into_iter().chain(group.pinned.iter().map(String::as_str)).collect();
+    ids.sort_unstable();
+    ids.dedup();
+    if ids.contains(&prefix) {
+        return Ok(prefix.to_string());
+    }
+    let matches: Vec<&str> = ids.into_iter()
+        .filter(|id| !prefix.is_empty() && id.starts_with(prefix))
+        .collect();
+    match matches.as_slice() {
+        [id] => Ok(id.to_string()),
+        [] => Err(anyhow!("No message '{}' in group '{}'; `list --ids` shows message IDs", prefix, group.name)),
+        _ => Err(anyhow!("'{}' matches {} messages in group '{}'; give more of the ID", prefix, matches.len(), group.name)),
+    }
+}
+
+impl MlsChatApp {
+    /// The full ID of the stored or pinned message in `group_name` whose ID is or starts with `prefix`,
+    /// and the message unless it is only pinned
+    ///
+    /// Looked up in the message index, so the group's log is not read.
+    pub(crate) fn find_message(&self, group_name: &str, prefix: &str) -> Result<(String, Option<ChatMessage>)> {
+        let group = self.groups.get(group_name).context("Group not found")?;
+        let mut found: Vec<ChatMessage> = match prefix.is_empty() {
+            true => Vec::new(),
+            false => self.storage.find_messages(prefix)?,
+        };
+        found.retain(|message| message.group_id == group.group_id);
+        let id = pick_message_id(group, found.iter().map(|message| message.id.as_str()).collect(), prefix)?;
+        let message = found.into_iter().find(|message| message.id == id);
+        Ok((id, message))
+    }
+}
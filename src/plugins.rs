@@ -0,0 +1,180 @@
+//! External subcommands, git-style
+//!
+//! `mls-chat <name> [args...]` runs `mls-chat-<name> [args...]` from `PATH`
+//! when `<name>` is not a built-in command. The plugin gets one line of JSON
+//! on stdin describing the invocation, then works with the profile by writing
+//! calls to stdout, one JSON object per line, and reading one reply line from
+//! stdin for each:
+//!
+//! ```text
+//! → {"context": {"version": "0.1.0", "dataDir": "mls_chat_data", "user": "Alice", ...}}
+//! ← {"id": 1, "method": "sendMessage", "params": {"group": "Team", "content": "hi"}}
+//! → {"id": 1, "result": {"id": "...", "sender": "Alice", "content": "hi", ...}}
+//! ```
+//!
+//! Stdout lines that are not JSON objects are shown to the user as they are,
+//! and calls that fail get `{"id": ..., "error": "..."}` instead.
+//! The host process holds the profile for the plugin's whole run, so plugins
+//! never read or write the data directory themselves.
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{
+    env,
+    io::{BufRead, BufReader, ErrorKind, Write},
+    path::PathBuf,
+    process::{ChildStdin, Command, Stdio},
+};
+
+use crate::api::{GroupView, MessageView};
+use crate::app::MlsChatApp;
+
+/// Prefix of plugin executables on `PATH`
+pub const PLUGIN_PREFIX: &str = "mls-chat-";
+
+/// How the plugin was invoked, sent as its first line of input
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PluginContext {
+    pub version: String,
+    pub data_dir: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub server: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+}
+
+/// One call from a plugin
+#[derive(Debug, Deserialize)]
+struct PluginRequest {
+    id: Value, // Echoed back in the reply
+    #[serde(flatten)]
+    call: PluginCall,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "method", content = "params", rename_all = "camelCase")]
+enum PluginCall {
+    ListGroups,
+    #[serde(rename_all = "camelCase")]
+    ListMessages { group: String },
+    #[serde(rename_all = "camelCase")]
+    SendMessage { group: String, content: String },
+    #[serde(rename_all = "camelCase")]
+    TagMessage { group: String, message_id: String, tag: String },
+    Sync,
+}
+
+/// Plugins on `PATH`, by command name, first match winning as in a shell
+pub fn discover() -> Vec<(String, PathBuf)> {
+    let mut plugins: Vec<(String, PathBuf)> = Vec::new();
+    let Some(path) = env::var_os("PATH") else { return plugins };
+    for dir in env::split_paths(&path) {
+        let Ok(entries) = dir.read_dir() else { continue };
+        for entry in entries.flatten() {
+            let file_name = entry.file_name();
+            let Some(name) = file_name.to_str().and_then(|name| name.strip_prefix(PLUGIN_PREFIX)) else { continue };
+            if name.is_empty() || !is_executable(&entry.path()) || plugins.iter().any(|(known, _)| known == name) {
+                continue;
+            }
+            plugins.push((name.to_string(), entry.path()));
+        }
+    }
+    plugins.sort();
+    plugins
+}
+
+#[cfg(unix)]
+fn is_executable(path: &std::path::Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    path.metadata().is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &std::path::Path) -> bool {
+    path.is_file()
+}
+
+impl MlsChatApp {
+    /// Run the `mls-chat-<name>` plugin with `args`, serving its calls until it exits; returns its exit code
+    pub fn run_plugin(&mut self, name: &str, args: &[String], context: &PluginContext) -> Result<i32> {
+        let program = format!("{}{}", PLUGIN_PREFIX, name);
+        let mut child = match Command::new(&program).args(args).stdin(Stdio::piped()).stdout(Stdio::piped()).spawn() {
+            Ok(child) => child,
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                return Err(anyhow!("Unknown command '{}' (no {} on PATH; see `mls-chat plugins`)", name, program));
+            }
+            Err(err) => return Err(err).with_context(|| format!("Failed to run {}", program)),
+        };
+        let mut stdin = child.stdin.take().context("Plugin stdin unavailable")?;
+        let stdout = child.stdout.take().context("Plugin stdout unavailable")?;
+
+        // A plugin need not read its input, so a closed stdin is not an error
+        let mut open = write_line(&mut stdin, &json!({ "context": context })).is_ok();
+        for line in BufReader::new(stdout).lines() {
+            let line = line.context("Failed to read plugin output")?;
+            let Ok(Value::Object(object)) = serde_json::from_str::<Value>(&line) else {
+                println!("{}", line);
+                continue;
+            };
+            let id = object.get("id").cloned().unwrap_or(Value::Null);
+            let reply = match serde_json::from_value::<PluginRequest>(Value::Object(object)) {
+                Ok(request) => match self.handle_plugin_call(request.call) {
+                    Ok(result) => json!({ "id": request.id, "result": result }),
+                    Err(err) => json!({ "id": request.id, "error": format!("{:#}", err) }),
+                },
+                Err(err) => json!({ "id": id, "error": format!("Invalid call: {}", err) }),
+            };
+            if open {
+                open = write_line(&mut stdin, &reply).is_ok();
+            }
+        }
+        drop(stdin);
+
+        let status = child.wait()?;
+        self.save_state()?;
+        Ok(status.code().unwrap_or(1))
+    }
+
+    fn handle_plugin_call(&mut self, call: PluginCall) -> Result<Value> {
+        Ok(match call {
+            PluginCall::ListGroups => {
+                let mut groups: Vec<GroupView> = self.groups.values().map(GroupView::from).collect();
+                groups.sort_by(|a, b| a.name.cmp(&b.name));
+                serde_json::to_value(groups)?
+            }
+            PluginCall::ListMessages { group } => {
+                let group = self.load_messages(&group)?;
+                let messages: Vec<MessageView> = group.messages.as_deref().unwrap_or_default()
+                    .iter()
+                    .map(MessageView::from)
+                    .collect();
+                serde_json::to_value(messages)?
+            }
+            PluginCall::SendMessage { group, content } => {
+                self.send_message(group.clone(), content)?;
+                let group = self.load_messages(&group)?;
+                let message = group.messages.as_deref().unwrap_or_default().last().context("Sent message not found")?;
+                serde_json::to_value(MessageView::from(message))?
+            }
+            PluginCall::TagMessage { group, message_id, tag } => {
+                self.tag_message(&group, &message_id, &tag)?;
+                Value::Null
+            }
+            PluginCall::Sync => {
+                self.sync()?;
+                Value::Null
+            }
+        })
+    }
+}
+
+fn write_line(stdin: &mut ChildStdin, value: &Value) -> std::io::Result<()> {
+    let mut line = value.to_string();
+    line.push('\n');
+    stdin.write_all(line.as_bytes())?;
+    stdin.flush()
+}
@@ -0,0 +1,304 @@
+//! Group policy: how large a group may grow and who may bring members in
+//!
+//! The policy is part of the group context in `GroupMetadata`, so it travels
+//! in every Commit and Welcome and all members agree on it. `max_members`
+//! caps the group, and the join rule says whether someone who has the
+//! group's state may join by external commit with `join` or must wait to be
+//! added. With `approval`, only admins may add members or let them join.
+//! The group's creator starts as its only admin; groups created before
+//! policies list none, and every member counts as one there.
+//!
+//! Under `approval`, an add or join by anyone else is queued as a
+//! `JoinRequest` instead. `requests` lists the queue, and `approve` makes
+//! the adds, committing only then and all of a group's at once, while
+//! `deny` drops the request. The
+//! queue is kept with the group in this profile and is not sent, so an
+//! admin sees the requests made from the profile they use.
+//!
+//! The rules are checked by the member making the commit. Receivers apply
+//! commits as they arrive, since refusing one the rest of the group accepted
+//! would split the group.
+
+use anyhow::{anyhow, Context, Result};
+use chrono::Utc;
+use colored::*;
+use std::collections::BTreeMap;
+use uuid::Uuid;
+
+use crate::app::{AddOutcome, MlsChatApp};
+use crate::commit::Proposals;
+use crate::bans;
+use crate::i18n::tr;
+use crate::model::{ChatGroup, GroupPolicy, JoinRequest, JoinRule};
+use crate::pins::short_id;
+use crate::theme;
+
+/// What `group policy` changes; fields left as `None` or empty keep their value
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PolicyChange {
+    pub max_members: Option<Option<usize>>, // Some(None) lifts the limit
+    pub join: Option<JoinRule>,
+    pub approval: Option<bool>,
+    pub add_admins: Vec<String>,
+    pub remove_admins: Vec<String>,
+}
+
+impl PolicyChange {
+    /// `policy` with this change made
+    fn apply(&self, policy: &GroupPolicy, group: &ChatGroup) -> Result<GroupPolicy> {
+        let mut next = policy.clone();
+        if let Some(max_members) = self.max_members {
+            if let Some(max) = max_members.filter(|max| *max < group.members.len()) {
+                return Err(anyhow!("The group already has {} members, more than {}", group.members.len(), max));
+            }
+            next.max_members = max_members;
+        }
+        if let Some(join) = self.join {
+            next.join = join;
+        }
+        if let Some(approval) = self.approval {
+            next.approval = approval;
+        }
+        if next.admins.is_empty() && (!self.add_admins.is_empty() || !self.remove_admins.is_empty()) {
+            // Naming admins in a group from before policies starts the list from every member
+            next.admins = group.members.clone();
+        }
+        for admin in &self.add_admins {
+            let member = group.members.iter().find(|member| member.eq_ignore_ascii_case(admin))
+                .with_context(|| format!("'{}' is not a member of group '{}'", admin, group.name))?;
+            if !next.admins.contains(member) {
+                next.admins.push(member.clone());
+            }
+        }
+        next.admins.retain(|admin| !self.remove_admins.iter().any(|removed| removed.eq_ignore_ascii_case(admin)));
+        if next.admins.is_empty() && !policy.admins.is_empty() {
+            return Err(anyhow!("A group needs at least one admin"));
+        }
+        Ok(next)
+    }
+}
+
+impl MlsChatApp {
+    /// Change a group's policy, committed as a new epoch; only admins may
+    pub fn set_group_policy(&mut self, group_name: &str, change: &PolicyChange) -> Result<()> {
+        let user = self.current_user.context("No user initialized")?.to_string();
+        println!("{}", tr("policy-start", &[]).green());
+        let group = self.groups.get(group_name).context("Group not found")?;
+        let policy = &group.mls_group.metadata.policy;
+        if !group.members.contains(&user) {
+            return Err(anyhow!("User '{}' is not a member of group '{}'", user, group_name));
+        }
+        if !policy.is_admin(&user) {
+            return Err(anyhow!("Only admins of '{}' can change its policy ({})", group_name, policy.admins.join(", ")));
+        }
+        let next_policy = change.apply(policy, group)?;
+        if next_policy == *policy {
+            println!("{}", tr("policy-unchanged", &[]));
+            return Ok(());
+        }
+
+        let mut metadata = group.mls_group.metadata.clone();
+        metadata.policy = next_policy;
+        println!("   {}", tr("policy-committing", &[("epoch", &(group.mls_group.epoch + 1))]));
+        self.commit(group_name, Proposals { metadata: Some(metadata), ..Proposals::default() })?;
+
+        let group = self.groups.get(group_name).context("Group not found")?;
+        println!("{}", tr("policy-set", &[("group", &group_name), ("policy", &group.mls_group.metadata.policy)]));
+        self.save_state()
+    }
+
+    /// Join a group this profile has the state of by external commit, if its policy is open
+    ///
+    /// In MLS proper the joiner needs only the group's published GroupInfo.
+    /// Here the Commit is sealed like any other, under the current epoch's
+    /// secret, so the group must already be in this profile, as it is when
+    /// another of the profile's identities is a member. A group that needs
+    /// approval queues the join for an admin, who adds the member instead.
+    pub fn join_group(&mut self, group_name: &str) -> Result<()> {
+        let user = self.current_user.context("No user initialized")?.to_string();
+        println!("{}", tr("join-start", &[]).green());
+        let group = self.groups.get(group_name).context("Group not found")?;
+        if group.members.contains(&user) {
+            println!("{}", tr("join-already", &[("user", &user), ("group", &group_name)]));
+            return Ok(());
+        }
+        let policy = &group.mls_group.metadata.policy;
+        if policy.join != JoinRule::Open {
+            return Err(anyhow!("Group '{}' is invite-only; ask a member to add you", group_name));
+        }
+        let public_key = self.user_keys.get(&user).map(|key| key.public_key.clone())
+            .with_context(|| format!("User '{}' not initialized", user))?;
+        bans::check_banned(group, &user, Some(&public_key))?;
+        self.check_revoked(&user, &public_key)?;
+        check_size(group, 1)?;
+        if self.needs_approval(group)? {
+            return self.request_join(group_name, &user).map(|_| ());
+        }
+
+        println!("   {}", tr("join-committing", &[("user", &user), ("epoch", &(group.mls_group.epoch + 1))]));
+        self.commit(group_name, Proposals { adds: vec![(user.clone(), public_key)], ..Proposals::default() })?;
+        println!("{}", tr("join-done", &[("user", &user), ("group", &group_name)]));
+        self.save_state()
+    }
+
+    /// True if the current user's adds to a group must wait for an admin
+    pub(crate) fn needs_approval(&self, group: &ChatGroup) -> Result<bool> {
+        let user = self.current_user.context("No user initialized")?.to_string();
+        let policy = &group.mls_group.metadata.policy;
+        Ok(policy.approval && !policy.is_admin(&user))
+    }
+
+    /// Queue `member` to join a group once an admin approves; returns the request ID
+    pub(crate) fn request_join(&mut self, group_name: &str, member: &str) -> Result<String> {
+        let user = self.current_user.context("No user initialized")?.to_string();
+        let group = self.groups.get_mut(group_name).context("Group not found")?;
+        let admins = group.mls_group.metadata.policy.admins.join(", ");
+        if let Some(request) = group.join_requests.iter().find(|request| request.member == member) {
+            println!("{}", tr("request-already", &[("member", &member), ("group", &group_name), ("id", &short_id(&request.id))]));
+            return Ok(request.id.clone());
+        }
+        let id = Uuid::new_v4().to_string();
+        group.join_requests.push(JoinRequest { id: id.clone(), member: member.to_string(), requested_by: user, at: Utc::now() });
+        println!("{}", tr("request-queued", &[("group", &group_name), ("member", &member), ("id", &short_id(&id))]));
+        println!("   {}", tr("request-ask", &[("admins", &admins), ("id", &short_id(&id))]));
+        self.dirty_groups.insert(group_name.to_string());
+        self.save_state()?;
+        Ok(id)
+    }
+
+    /// List a group's requests waiting for an admin, oldest first
+    pub fn list_requests(&self, group_name: &str) -> Result<()> {
+        let group = self.groups.get(group_name).context("Group not found")?;
+        if group.join_requests.is_empty() {
+            println!("{}", tr("requests-empty", &[("group", &group_name)]));
+        }
+        for request in &group.join_requests {
+            let via = if request.requested_by == request.member {
+                tr("request-asked", &[])
+            } else {
+                tr("request-invited", &[("member", &theme::sender(&request.requested_by, false))])
+            };
+            println!("{} {} {} {}",
+                short_id(&request.id).dimmed(),
+                request.at.format("%Y-%m-%d %H:%M UTC"),
+                theme::sender(&request.member, false),
+                via,
+            );
+        }
+        Ok(())
+    }
+
+    /// Approve requests, adding their members; `ids` may be unique prefixes
+    ///
+    /// The requests for one group are approved together, in one commit, so
+    /// approving many costs one epoch and one path update rather than one each.
+    pub fn approve_requests(&mut self, ids: &[String]) -> Result<()> {
+        let mut by_group: BTreeMap<String, Vec<JoinRequest>> = BTreeMap::new();
+        for id in ids {
+            let (group_name, request) = self.find_request(id)?;
+            let requests = by_group.entry(group_name).or_default();
+            if !requests.iter().any(|queued| queued.id == request.id) {
+                requests.push(request);
+            }
+        }
+        for group_name in by_group.keys() {
+            self.check_admin(group_name, "approve requests")?;
+        }
+        for (group_name, requests) in by_group {
+            self.approve_together(&group_name, requests)?;
+        }
+        Ok(())
+    }
+
+    /// Approve every request waiting in a group, in one commit
+    pub fn approve_all(&mut self, group_name: &str) -> Result<()> {
+        let group = self.groups.get(group_name).context("Group not found")?;
+        if group.join_requests.is_empty() {
+            println!("{}", tr("requests-empty", &[("group", &group_name)]));
+            return Ok(());
+        }
+        let requests = group.join_requests.clone();
+        self.approve_together(group_name, requests)
+    }
+
+    /// Add the members of `requests` to a group with `add_members`, dropping the requests that went through
+    ///
+    /// A request whose member cannot be added stays queued.
+    fn approve_together(&mut self, group_name: &str, requests: Vec<JoinRequest>) -> Result<()> {
+        self.check_admin(group_name, "approve requests")?;
+        for request in &requests {
+            println!("{}", tr("request-approved", &[("id", &short_id(&request.id)), ("member", &request.member), ("group", &group_name)]));
+        }
+        let members: Vec<String> = requests.iter().map(|request| request.member.clone()).collect();
+        let outcomes = self.add_members(group_name, &members)?;
+        let done: Vec<&String> = outcomes.iter()
+            .filter(|(_, outcome)| matches!(outcome, AddOutcome::Added | AddOutcome::AlreadyMember))
+            .map(|(member, _)| member)
+            .collect();
+        let group = self.groups.get_mut(group_name).context("Group not found")?;
+        group.join_requests.retain(|request| {
+            let approved = requests.iter().any(|approved| approved.id == request.id);
+            !(approved && done.contains(&&request.member))
+        });
+        self.dirty_groups.insert(group_name.to_string());
+        self.save_state()?;
+        let failed = requests.len() - requests.iter().filter(|request| done.contains(&&request.member)).count();
+        if failed > 0 {
+            return Err(anyhow!("{} of {} request(s) could not be approved and stay queued", failed, requests.len()));
+        }
+        Ok(())
+    }
+
+    /// Deny a request without adding anyone; admins may deny any, and a member their own
+    pub fn deny_request(&mut self, id: &str) -> Result<()> {
+        let (group_name, request) = self.find_request(id)?;
+        let user = self.current_user.context("No user initialized")?.to_string();
+        if request.requested_by != user {
+            self.check_admin(&group_name, "deny requests")?;
+        }
+        println!("{}", tr("request-denied", &[("id", &short_id(&request.id)), ("member", &request.member), ("group", &group_name)]));
+        self.drop_request(&group_name, &request.id)
+    }
+
+    fn find_request(&self, id: &str) -> Result<(String, JoinRequest)> {
+        let matches: Vec<(&ChatGroup, &JoinRequest)> = self.groups.values()
+            .flat_map(|group| group.join_requests.iter().map(move |request| (group, request)))
+            .filter(|(_, request)| !id.is_empty() && request.id.starts_with(id))
+            .collect();
+        match matches.as_slice() {
+            [(group, request)] => Ok((group.name.clone(), (*request).clone())),
+            [] => Err(anyhow!("No request '{}'; `requests <group>` shows them", id)),
+            _ => Err(anyhow!("'{}' matches {} requests; give more of the ID", id, matches.len())),
+        }
+    }
+
+    pub(crate) fn check_admin(&self, group_name: &str, action: &str) -> Result<()> {
+        let user = self.current_user.context("No user initialized")?.to_string();
+        let group = self.groups.get(group_name).context("Group not found")?;
+        let policy = &group.mls_group.metadata.policy;
+        if !group.members.contains(&user) || !policy.is_admin(&user) {
+            return Err(anyhow!("Only admins of '{}' can {} ({})", group_name, action, policy.admins.join(", ")));
+        }
+        Ok(())
+    }
+
+    fn drop_request(&mut self, group_name: &str, id: &str) -> Result<()> {
+        let group = self.groups.get_mut(group_name).context("Group not found")?;
+        group.join_requests.retain(|request| request.id != id);
+        self.dirty_groups.insert(group_name.to_string());
+        self.save_state()
+    }
+}
+
+/// Fail if `count` more members would take a group past its `max_members`
+pub(crate) fn check_size(group: &ChatGroup, count: usize) -> Result<()> {
+    match group.mls_group.metadata.policy.max_members {
+        Some(max) if group.members.len() + count > max => Err(anyhow!(
+            "Group '{}' is full: it allows at most {} members and has {}",
+            group.name,
+            max,
+            group.members.len()
+        )),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,138 @@
+//! Polls
+//!
+//! A poll is a chat message carrying a question and its options; a vote is
+//! another message naming the poll and the option chosen. Both are encrypted
+//! like any message, so the tally is worked out by each member from the votes
+//! in their own history, counting only each member's latest vote.
+
+use anyhow::{anyhow, Context, Result};
+use colored::*;
+use serde::Serialize;
+
+use crate::app::MlsChatApp;
+use crate::content::Content;
+use crate::model::{ChatGroup, ChatMessage, ContentType};
+use crate::output;
+use crate::pins::{find_message_id, short_id};
+use crate::theme;
+
+/// Where a poll stands
+#[derive(Debug, Clone, Serialize)]
+pub struct PollResults {
+    pub poll_id: String,
+    pub question: String,
+    pub creator: String,
+    pub options: Vec<OptionTally>,
+}
+
+/// One option of a poll and the members whose latest vote chose it
+#[derive(Debug, Clone, Serialize)]
+pub struct OptionTally {
+    pub option: String,
+    pub voters: Vec<String>,
+}
+
+impl MlsChatApp {
+    /// Ask a group a question with the given options
+    pub fn create_poll(&mut self, group_name: String, question: String, options: Vec<String>) -> Result<()> {
+        let options: Vec<String> = options.into_iter().map(|option| option.trim().to_string()).collect();
+        if question.trim().is_empty() {
+            return Err(anyhow!("A poll needs a question"));
+        }
+        if options.len() < 2 {
+            return Err(anyhow!("A poll needs at least two options"));
+        }
+        if let Some(option) = options.iter().find(|option| option.is_empty()) {
+            return Err(anyhow!("Poll option '{}' is empty", option));
+        }
+        for (i, option) in options.iter().enumerate() {
+            if options[..i].iter().any(|earlier| earlier.eq_ignore_ascii_case(option)) {
+                return Err(anyhow!("Poll option '{}' is given twice", option));
+            }
+        }
+        self.send_content(group_name, Content::Poll { question, options })
+    }
+
+    /// Vote in a poll, by option text or by its number in the list; `poll_id` may be a unique prefix
+    pub fn vote(&mut self, group_name: &str, poll_id: &str, option: &str) -> Result<()> {
+        self.load_messages(group_name)?;
+        let group = self.groups.get(group_name).context("Group not found")?;
+        let poll = find_poll(group, poll_id)?;
+        let chosen = option.parse::<usize>().ok()
+            .and_then(|n| n.checked_sub(1))
+            .and_then(|i| poll.options.get(i))
+            .or_else(|| poll.options.iter().find(|candidate| candidate.eq_ignore_ascii_case(option.trim())))
+            .with_context(|| format!("Poll {} has no option '{}'; choose from: {}", short_id(&poll.id), option, poll.options.join(", ")))?
+            .clone();
+        let content = Content::Vote { poll_id: poll.id.clone(), option: chosen };
+        self.send_content(group_name.to_string(), content)
+    }
+
+    /// Count the votes in a poll; `poll_id` may be a unique prefix
+    pub fn poll_results(&mut self, group_name: &str, poll_id: &str) -> Result<PollResults> {
+        self.load_messages(group_name)?;
+        let group = self.groups.get(group_name).context("Group not found")?;
+        let poll = find_poll(group, poll_id)?;
+        Ok(tally(poll, group.messages.as_deref().unwrap_or_default()))
+    }
+
+    /// Print a poll's results as bars
+    pub fn show_poll_results(&mut self, group_name: &str, poll_id: &str) -> Result<()> {
+        let results = self.poll_results(group_name, poll_id)?;
+        let total: usize = results.options.iter().map(|tally| tally.voters.len()).sum();
+        println!("{}", format!("📊 {}", results.question).blue());
+        output::rule('=', 50);
+        println!("Asked by {} (poll {})", theme::sender(&results.creator, false), short_id(&results.poll_id));
+        let width = results.options.iter().map(|tally| tally.option.chars().count()).max().unwrap_or(0);
+        for (i, tally) in results.options.iter().enumerate() {
+            let share = (tally.voters.len() * 20).checked_div(total).unwrap_or(0);
+            let voters = if tally.voters.is_empty() { String::new() } else { format!(" ({})", tally.voters.join(", ")) };
+            println!("  {}. {:<width$}  {}{} {}{}",
+                i + 1,
+                tally.option,
+                "█".repeat(share).green(),
+                " ".repeat(20 - share),
+                tally.voters.len(),
+                voters.dimmed(),
+                width = width,
+            );
+        }
+        println!("{} vote(s)", total);
+        Ok(())
+    }
+}
+
+/// The votes cast in `messages` for `poll`, each member's latest vote counting once
+///
+/// Votes for an option the poll does not have are ignored.
+pub fn tally(poll: &ChatMessage, messages: &[ChatMessage]) -> PollResults {
+    let mut latest: Vec<&ChatMessage> = Vec::new();
+    for vote in messages.iter().filter(|message| message.content_type == ContentType::Vote && message.reacts_to.as_deref() == Some(poll.id.as_str())) {
+        match latest.iter_mut().find(|earlier| earlier.sender == vote.sender) {
+            Some(earlier) if earlier.timestamp <= vote.timestamp => *earlier = vote,
+            Some(_) => {}
+            None => latest.push(vote),
+        }
+    }
+    let options = poll.options.iter()
+        .map(|option| OptionTally {
+            option: option.clone(),
+            voters: latest.iter().filter(|vote| vote.content == *option).map(|vote| vote.sender.clone()).collect(),
+        })
+        .collect();
+    PollResults {
+        poll_id: poll.id.clone(),
+        question: poll.content.clone(),
+        creator: poll.sender.clone(),
+        options,
+    }
+}
+
+/// The loaded poll in `group` whose ID is or starts with `prefix`
+fn find_poll<'a>(group: &'a ChatGroup, prefix: &str) -> Result<&'a ChatMessage> {
+    let id = find_message_id(group, prefix)?;
+    group.messages.as_deref().unwrap_or_default().iter()
+        .find(|message| message.id == id)
+        .filter(|message| message.content_type == ContentType::Poll)
+        .with_context(|| format!("Message {} in group '{}' is not a poll", short_id(&id), group.name))
+}
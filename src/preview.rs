@@ -0,0 +1,632 @@
+//! Inline previews of image attachments
+//!
+//! `list` draws a downscaled copy of each image message under it, using the
+//! kitty graphics protocol or sixel when the terminal speaks one, and
+//! half-block characters in 24-bit color otherwise. PNG (8-bit,
+//! non-interlaced) and binary PPM are decoded, with a small inflate for PNG's
+//! zlib streams; other formats fall back to the file name and size.
+
+use anyhow::{anyhow, Context, Result};
+use std::{env, fmt, io::IsTerminal};
+
+use crate::crypto::base64;
+
+/// Columns a half-block preview may take; each row of characters draws two pixel rows
+const BLOCK_WIDTH: usize = 48;
+const BLOCK_HEIGHT: usize = 48;
+
+/// Pixels a kitty or sixel preview may take
+const PIXEL_WIDTH: usize = 320;
+const PIXEL_HEIGHT: usize = 240;
+
+/// Largest image decoded for a preview, in pixels
+const MAX_PIXELS: u64 = 40_000_000;
+
+/// How a terminal can draw images
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Graphics {
+    Kitty,
+    Sixel,
+    Blocks, // Unicode half blocks in 24-bit color
+}
+
+impl fmt::Display for Graphics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Graphics::Kitty => write!(f, "kitty"),
+            Graphics::Sixel => write!(f, "sixel"),
+            Graphics::Blocks => write!(f, "blocks"),
+        }
+    }
+}
+
+/// How previews are drawn for `setting` (the `image-preview` config value), or None for none
+///
+/// Previews need a terminal, so output to a pipe or file never gets one.
+/// `auto`, the default, goes by the environment: kitty for terminals known
+/// to speak its protocol outside tmux, sixel for `TERM`s that advertise it,
+/// and half blocks wherever colors are on.
+pub fn detect(setting: Option<&str>) -> Option<Graphics> {
+    if !std::io::stdout().is_terminal() {
+        return None;
+    }
+    match setting.unwrap_or("auto") {
+        "off" => None,
+        "kitty" => Some(Graphics::Kitty),
+        "sixel" => Some(Graphics::Sixel),
+        "blocks" => Some(Graphics::Blocks),
+        _ => {
+            let term = env::var("TERM").unwrap_or_default();
+            let program = env::var("TERM_PROGRAM").unwrap_or_default();
+            let in_tmux = env::var_os("TMUX").is_some();
+            if !in_tmux && (term == "xterm-kitty" || env::var_os("KITTY_WINDOW_ID").is_some() || program == "WezTerm" || program == "ghostty") {
+                Some(Graphics::Kitty)
+            } else if !in_tmux && (term.contains("sixel") || term.starts_with("foot") || term.starts_with("mlterm")) {
+                Some(Graphics::Sixel)
+            } else if colored::control::SHOULD_COLORIZE.should_colorize() {
+                Some(Graphics::Blocks)
+            } else {
+                None
+            }
+        }
+    }
+}
+
+/// A decoded image as rows of RGB pixels
+struct Image {
+    width: usize,
+    height: usize,
+    pixels: Vec<[u8; 3]>,
+}
+
+/// The terminal output drawing `data`, an encoded image, with `graphics`
+pub fn render(data: &[u8], graphics: Graphics) -> Result<String> {
+    let image = decode(data)?;
+    Ok(match graphics {
+        Graphics::Blocks => blocks(&image.fit(BLOCK_WIDTH, BLOCK_HEIGHT)),
+        Graphics::Kitty => kitty(&image.fit(PIXEL_WIDTH, PIXEL_HEIGHT)),
+        Graphics::Sixel => sixel(&image.fit(PIXEL_WIDTH, PIXEL_HEIGHT)),
+    })
+}
+
+/// A size such as `812 B`, `12.3 KB`, or `4.1 MB`
+pub fn human_size(bytes: u64) -> String {
+    match bytes {
+        0..=1023 => format!("{} B", bytes),
+        1024..=1_048_575 => format!("{:.1} KB", bytes as f64 / 1024.0),
+        _ => format!("{:.1} MB", bytes as f64 / 1_048_576.0),
+    }
+}
+
+fn decode(data: &[u8]) -> Result<Image> {
+    if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        return decode_png(data);
+    }
+    if data.starts_with(b"P6") {
+        return decode_ppm(data);
+    }
+    let format = match data {
+        [0xFF, 0xD8, ..] => "JPEG",
+        [b'G', b'I', b'F', ..] => "GIF",
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => "WebP",
+        _ => "this image format",
+    };
+    Err(anyhow!("{} is not supported; previews need PNG or PPM", format))
+}
+
+impl Image {
+    /// A copy scaled down, keeping its shape, to fit `max_width` by `max_height`
+    ///
+    /// Each output pixel is the average of the block it covers. Images that
+    /// already fit are returned as they are.
+    fn fit(&self, max_width: usize, max_height: usize) -> Image {
+        let scale = (max_width as f64 / self.width as f64).min(max_height as f64 / self.height as f64);
+        if scale >= 1.0 {
+            return Image { width: self.width, height: self.height, pixels: self.pixels.clone() };
+        }
+        let width = ((self.width as f64 * scale).round() as usize).max(1);
+        let height = ((self.height as f64 * scale).round() as usize).max(1);
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            let (top, bottom) = (y * self.height / height, ((y + 1) * self.height / height).max(y * self.height / height + 1));
+            for x in 0..width {
+                let (left, right) = (x * self.width / width, ((x + 1) * self.width / width).max(x * self.width / width + 1));
+                let mut sum = [0u64; 3];
+                for row in top..bottom {
+                    for pixel in &self.pixels[row * self.width + left..row * self.width + right] {
+                        for channel in 0..3 {
+                            sum[channel] += pixel[channel] as u64;
+                        }
+                    }
+                }
+                let count = ((bottom - top) * (right - left)) as u64;
+                pixels.push(sum.map(|total| (total / count) as u8));
+            }
+        }
+        Image { width, height, pixels }
+    }
+
+    fn pixel(&self, x: usize, y: usize) -> [u8; 3] {
+        self.pixels[y * self.width + x]
+    }
+}
+
+/// Half blocks: the upper half's color in the foreground, the lower half's in the background
+fn blocks(image: &Image) -> String {
+    let mut out = String::new();
+    for y in (0..image.height).step_by(2) {
+        out.push_str("   ");
+        for x in 0..image.width {
+            let [r, g, b] = image.pixel(x, y);
+            match y + 1 < image.height {
+                true => {
+                    let [br, bg, bb] = image.pixel(x, y + 1);
+                    out.push_str(&format!("\x1b[38;2;{};{};{}m\x1b[48;2;{};{};{}m▀", r, g, b, br, bg, bb));
+                }
+                false => out.push_str(&format!("\x1b[38;2;{};{};{}m\x1b[49m▀", r, g, b)),
+            }
+        }
+        out.push_str("\x1b[0m\n");
+    }
+    out.pop();
+    out
+}
+
+/// The kitty graphics protocol's raw RGB form, sent in the 4096-byte chunks it requires
+fn kitty(image: &Image) -> String {
+    let raw: Vec<u8> = image.pixels.iter().flatten().copied().collect();
+    let encoded = base64::encode(&raw);
+    let chunks: Vec<&[u8]> = encoded.as_bytes().chunks(4096).collect();
+    let mut out = String::from("   ");
+    for (index, chunk) in chunks.iter().enumerate() {
+        let more = u8::from(index + 1 < chunks.len());
+        let chunk = std::str::from_utf8(chunk).unwrap_or_default();
+        match index {
+            0 => out.push_str(&format!("\x1b_Ga=T,f=24,s={},v={},m={};{}\x1b\\", image.width, image.height, more, chunk)),
+            _ => out.push_str(&format!("\x1b_Gm={};{}\x1b\\", more, chunk)),
+        }
+    }
+    out
+}
+
+/// Sixel with a 6×6×6 color cube, one band of six pixel rows at a time
+fn sixel(image: &Image) -> String {
+    let level = |value: u8| (value as usize * 5 + 127) / 255;
+    let indexes: Vec<usize> = image.pixels.iter().map(|&[r, g, b]| level(r) * 36 + level(g) * 6 + level(b)).collect();
+
+    let mut out = format!("\x1bPq\"1;1;{};{}", image.width, image.height);
+    let mut used = [false; 216];
+    for &index in &indexes {
+        used[index] = true;
+    }
+    for (index, _) in used.iter().enumerate().filter(|(_, used)| **used) {
+        let percent = |level: usize| level * 20;
+        out.push_str(&format!("#{};2;{};{};{}", index, percent(index / 36), percent(index / 6 % 6), percent(index % 6)));
+    }
+    for band in (0..image.height).step_by(6) {
+        let rows = band..(band + 6).min(image.height);
+        let mut colors: Vec<usize> = rows.clone().flat_map(|y| indexes[y * image.width..(y + 1) * image.width].iter().copied()).collect();
+        colors.sort_unstable();
+        colors.dedup();
+        for color in colors {
+            out.push_str(&format!("#{}", color));
+            let sixels: Vec<u8> = (0..image.width)
+                .map(|x| rows.clone().filter(|y| indexes[y * image.width + x] == color).fold(0u8, |bits, y| bits | 1 << (y - band)))
+                .map(|bits| 63 + bits)
+                .collect();
+            let mut x = 0;
+            while x < sixels.len() {
+                let run = sixels[x..].iter().take_while(|&&sixel| sixel == sixels[x]).count();
+                match run {
+                    1..=3 => out.push_str(&(sixels[x] as char).to_string().repeat(run)),
+                    _ => out.push_str(&format!("!{}{}", run, sixels[x] as char)),
+                }
+                x += run;
+            }
+            out.push('$');
+        }
+        out.push('-');
+    }
+    out.push_str("\x1b\\");
+    out
+}
+
+/// Binary PPM (`P6`) with a maximum value of 255
+fn decode_ppm(data: &[u8]) -> Result<Image> {
+    let mut fields = Vec::new();
+    let mut pos = 2;
+    while fields.len() < 3 {
+        while data.get(pos).is_some_and(u8::is_ascii_whitespace) {
+            pos += 1;
+        }
+        if data.get(pos) == Some(&b'#') {
+            while data.get(pos).is_some_and(|&byte| byte != b'\n') {
+                pos += 1;
+            }
+            continue;
+        }
+        let start = pos;
+        while data.get(pos).is_some_and(u8::is_ascii_digit) {
+            pos += 1;
+        }
+        let field = std::str::from_utf8(&data[start..pos]).ok().and_then(|field| field.parse::<usize>().ok());
+        fields.push(field.context("Malformed PPM header")?);
+    }
+    let (width, height) = check_size(fields[0] as u64, fields[1] as u64)?;
+    if fields[2] != 255 {
+        return Err(anyhow!("PPM with a maximum value of {} is not supported", fields[2]));
+    }
+    let body = data.get(pos + 1..pos + 1 + width * height * 3).context("PPM image is truncated")?;
+    let pixels = body.chunks_exact(3).map(|pixel| [pixel[0], pixel[1], pixel[2]]).collect();
+    Ok(Image { width, height, pixels })
+}
+
+fn check_size(width: u64, height: u64) -> Result<(usize, usize)> {
+    if width == 0 || height == 0 || width * height > MAX_PIXELS {
+        return Err(anyhow!("A {}×{} image is too large to preview", width, height));
+    }
+    Ok((width as usize, height as usize))
+}
+
+/// 8-bit, non-interlaced PNG in any color type; transparency is drawn over black
+fn decode_png(data: &[u8]) -> Result<Image> {
+    let mut pos = 8;
+    let mut header = None;
+    let mut palette: &[u8] = &[];
+    let mut compressed = Vec::new();
+    while pos + 8 <= data.len() {
+        let length = u32::from_be_bytes(data[pos..pos + 4].try_into()?) as usize;
+        let kind = &data[pos + 4..pos + 8];
+        let body = data.get(pos + 8..pos + 8 + length).context("PNG chunk is truncated")?;
+        match kind {
+            b"IHDR" if length >= 13 => header = Some(body),
+            b"PLTE" => palette = body,
+            b"IDAT" => compressed.extend_from_slice(body),
+            b"IEND" => break,
+            _ => {}
+        }
+        pos += 12 + length; // Length, type, body, CRC
+    }
+    let header = header.context("PNG has no header")?;
+    let (width, height) = check_size(
+        u32::from_be_bytes(header[0..4].try_into()?) as u64,
+        u32::from_be_bytes(header[4..8].try_into()?) as u64,
+    )?;
+    let (depth, color, interlace) = (header[8], header[9], header[12]);
+    if depth != 8 {
+        return Err(anyhow!("PNGs with {}-bit samples are not supported", depth));
+    }
+    if interlace != 0 {
+        return Err(anyhow!("Interlaced PNGs are not supported"));
+    }
+    let channels = match color {
+        0 | 3 => 1, // Gray, palette index
+        4 => 2,     // Gray and alpha
+        2 => 3,     // RGB
+        6 => 4,     // RGBA
+        _ => return Err(anyhow!("PNG color type {} is not valid", color)),
+    };
+
+    let raw = inflate(compressed.get(2..).context("PNG image data is empty")?)?; // Past the zlib header
+    let stride = width * channels;
+    let mut rows: Vec<u8> = Vec::with_capacity(stride * height);
+    for y in 0..height {
+        let line = raw.get(y * (stride + 1)..(y + 1) * (stride + 1)).context("PNG image data is truncated")?;
+        let (filter, line) = (line[0], &line[1..]);
+        let start = rows.len();
+        for (x, &byte) in line.iter().enumerate() {
+            let left = if x >= channels { rows[start + x - channels] } else { 0 };
+            let up = if y > 0 { rows[start + x - stride] } else { 0 };
+            let up_left = if y > 0 && x >= channels { rows[start + x - stride - channels] } else { 0 };
+            let predicted = match filter {
+                0 => 0,
+                1 => left,
+                2 => up,
+                3 => ((left as u16 + up as u16) / 2) as u8,
+                4 => paeth(left, up, up_left),
+                _ => return Err(anyhow!("PNG filter {} is not valid", filter)),
+            };
+            rows.push(byte.wrapping_add(predicted));
+        }
+    }
+
+    let pixels = rows.chunks_exact(channels).map(|sample| match color {
+        0 => [sample[0]; 3],
+        3 => {
+            let at = sample[0] as usize * 3;
+            palette.get(at..at + 3).map_or([0; 3], |rgb| [rgb[0], rgb[1], rgb[2]])
+        }
+        4 => [over_black(sample[0], sample[1]); 3],
+        2 => [sample[0], sample[1], sample[2]],
+        _ => [0, 1, 2].map(|channel| over_black(sample[channel], sample[3])),
+    }).collect();
+    Ok(Image { width, height, pixels })
+}
+
+fn paeth(left: u8, up: u8, up_left: u8) -> u8 {
+    let estimate = left as i16 + up as i16 - up_left as i16;
+    let distance = |value: u8| (estimate - value as i16).abs();
+    match (distance(left), distance(up), distance(up_left)) {
+        (a, b, c) if a <= b && a <= c => left,
+        (_, b, c) if b <= c => up,
+        _ => up_left,
+    }
+}
+
+fn over_black(value: u8, alpha: u8) -> u8 {
+    (value as u16 * alpha as u16 / 255) as u8
+}
+
+/// Reads a DEFLATE stream least significant bit first
+struct Bits<'a> {
+    data: &'a [u8],
+    pos: usize,  // Next byte
+    bit: u32,    // Next bit within it
+}
+
+impl Bits<'_> {
+    fn bit(&mut self) -> Result<u32> {
+        let byte = *self.data.get(self.pos).context("Compressed image data is truncated")?;
+        let bit = (byte >> self.bit) & 1;
+        self.bit += 1;
+        if self.bit == 8 {
+            self.bit = 0;
+            self.pos += 1;
+        }
+        Ok(bit as u32)
+    }
+
+    fn bits(&mut self, count: u32) -> Result<usize> {
+        let mut value = 0;
+        for shift in 0..count {
+            value |= (self.bit()? as usize) << shift;
+        }
+        Ok(value)
+    }
+}
+
+/// A canonical Huffman code: how many codes have each length, and the symbols in code order
+struct Huffman {
+    counts: [usize; 16],
+    symbols: Vec<usize>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0; 16];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut symbols: Vec<(u8, usize)> = lengths.iter().enumerate()
+            .filter(|(_, &length)| length > 0)
+            .map(|(symbol, &length)| (length, symbol))
+            .collect();
+        symbols.sort();
+        Self { counts, symbols: symbols.into_iter().map(|(_, symbol)| symbol).collect() }
+    }
+
+    fn decode(&self, bits: &mut Bits) -> Result<usize> {
+        let (mut code, mut first, mut index) = (0, 0, 0);
+        for length in 1..16 {
+            code |= bits.bit()? as usize;
+            let count = self.counts[length];
+            if code < first + count {
+                return self.symbols.get(index + code - first).copied().context("Invalid Huffman code");
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(anyhow!("Invalid Huffman code"))
+    }
+}
+
+const LENGTH_BASE: [usize; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u32; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DISTANCE_BASE: [usize; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097,
+    6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u32; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+
+/// Order in which a dynamic block lists the lengths of the code-length code
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+/// Decompress a raw DEFLATE stream (RFC 1951)
+fn inflate(data: &[u8]) -> Result<Vec<u8>> {
+    let mut bits = Bits { data, pos: 0, bit: 0 };
+    let mut out = Vec::new();
+    loop {
+        let last = bits.bit()? == 1;
+        match bits.bits(2)? {
+            0 => {
+                if bits.bit > 0 {
+                    bits.bit = 0;
+                    bits.pos += 1;
+                }
+                let header = data.get(bits.pos..bits.pos + 4).context("Compressed image data is truncated")?;
+                let length = u16::from_le_bytes([header[0], header[1]]) as usize;
+                bits.pos += 4;
+                out.extend_from_slice(data.get(bits.pos..bits.pos + length).context("Compressed image data is truncated")?);
+                bits.pos += length;
+            }
+            1 => {
+                let mut lengths = [8u8; 288];
+                lengths[144..256].fill(9);
+                lengths[256..280].fill(7);
+                inflate_block(&mut bits, &mut out, &Huffman::new(&lengths), &Huffman::new(&[5; 30]))?;
+            }
+            2 => {
+                let (literals, distances) = dynamic_codes(&mut bits)?;
+                inflate_block(&mut bits, &mut out, &literals, &distances)?;
+            }
+            _ => return Err(anyhow!("Invalid compressed block type")),
+        }
+        if out.len() as u64 > MAX_PIXELS * 4 + MAX_PIXELS / 4 {
+            return Err(anyhow!("Image data is too large to preview"));
+        }
+        if last {
+            return Ok(out);
+        }
+    }
+}
+
+/// The literal/length and distance codes a dynamic block starts with
+fn dynamic_codes(bits: &mut Bits) -> Result<(Huffman, Huffman)> {
+    let literal_count = bits.bits(5)? + 257;
+    let distance_count = bits.bits(5)? + 1;
+    let code_length_count = bits.bits(4)? + 4;
+    let mut code_lengths = [0u8; 19];
+    for &symbol in &CODE_LENGTH_ORDER[..code_length_count] {
+        code_lengths[symbol] = bits.bits(3)? as u8;
+    }
+    let code_lengths = Huffman::new(&code_lengths);
+
+    let mut lengths = Vec::with_capacity(literal_count + distance_count);
+    while lengths.len() < literal_count + distance_count {
+        let (value, repeat) = match code_lengths.decode(bits)? {
+            symbol @ 0..=15 => (symbol as u8, 1),
+            16 => (*lengths.last().context("Invalid code lengths")?, 3 + bits.bits(2)?),
+            17 => (0, 3 + bits.bits(3)?),
+            _ => (0, 11 + bits.bits(7)?),
+        };
+        lengths.extend(std::iter::repeat_n(value, repeat));
+    }
+    if lengths.len() > literal_count + distance_count {
+        return Err(anyhow!("Invalid code lengths"));
+    }
+    Ok((Huffman::new(&lengths[..literal_count]), Huffman::new(&lengths[literal_count..])))
+}
+
+/// Decode one compressed block's symbols into `out`
+fn inflate_block(bits: &mut Bits, out: &mut Vec<u8>, literals: &Huffman, distances: &Huffman) -> Result<()> {
+    loop {
+        let symbol = literals.decode(bits)?;
+        match symbol {
+            0..=255 => out.push(symbol as u8),
+            256 => return Ok(()),
+            _ => {
+                let index = symbol - 257;
+                let length = LENGTH_BASE.get(index).context("Invalid length code")? + bits.bits(LENGTH_EXTRA[index])?;
+                let index = distances.decode(bits)?;
+                let distance = DISTANCE_BASE.get(index).context("Invalid distance code")? + bits.bits(DISTANCE_EXTRA[index])?;
+                if distance > out.len() {
+                    return Err(anyhow!("Invalid back-reference in compressed image data"));
+                }
+                let start = out.len() - distance;
+                for offset in 0..length {
+                    out.push(out[start + offset]);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 3×2 RGB PNG whose rows use the Sub and Up filters, compressed with a fixed Huffman block
+    const PNG: &[u8] = &[
+        0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a, 0x00, 0x00, 0x00, 0x0d, 0x49, 0x48, 0x44, 0x52,
+        0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x02, 0x08, 0x02, 0x00, 0x00, 0x00, 0x12, 0x16, 0xf1,
+        0x4d, 0x00, 0x00, 0x00, 0x18, 0x49, 0x44, 0x41, 0x54, 0x78, 0xda, 0x63, 0xfc, 0xcf, 0xc0, 0xc0,
+        0x08, 0xc6, 0x4c, 0xdc, 0x22, 0x72, 0x1a, 0xc6, 0x36, 0x6e, 0x01, 0xd1, 0x00, 0x33, 0xac, 0x04,
+        0xc8, 0xf9, 0x37, 0x86, 0xc7, 0x00, 0x00, 0x00, 0x00, 0x49, 0x45, 0x4e, 0x44, 0xae, 0x42, 0x60,
+        0x82,
+    ];
+    /// The same image with its data in a stored (uncompressed) block
+    const STORED_PNG: &[u8] = &[
+        0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a, 0x00, 0x00, 0x00, 0x0d, 0x49, 0x48, 0x44, 0x52,
+        0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x02, 0x08, 0x02, 0x00, 0x00, 0x00, 0x12, 0x16, 0xf1,
+        0x4d, 0x00, 0x00, 0x00, 0x1f, 0x49, 0x44, 0x41, 0x54, 0x78, 0x01, 0x01, 0x14, 0x00, 0xeb, 0xff,
+        0x01, 0xff, 0x00, 0x00, 0x01, 0xff, 0x00, 0x00, 0x01, 0xff, 0x02, 0x0b, 0x14, 0x1e, 0x28, 0x33,
+        0x3c, 0x46, 0x50, 0x5b, 0x33, 0xac, 0x04, 0xc8, 0x24, 0x2f, 0xc8, 0xa4, 0x00, 0x00, 0x00, 0x00,
+        0x49, 0x45, 0x4e, 0x44, 0xae, 0x42, 0x60, 0x82,
+    ];
+    const PIXELS: [[u8; 3]; 6] = [[255, 0, 0], [0, 255, 0], [0, 0, 255], [10, 20, 30], [40, 50, 60], [70, 80, 90]];
+
+    /// `skewed_text(200)` as zlib compresses it at level 9: one dynamic Huffman block, without the zlib framing
+    const DYNAMIC: &[u8] = &[
+        0x1d, 0x8e, 0x41, 0x0a, 0x45, 0x31, 0x08, 0x03, 0xf7, 0x3d, 0x85, 0x57, 0x13, 0x3a, 0xf0, 0x0a,
+        0x52, 0xc1, 0xa6, 0xf7, 0xff, 0xf6, 0xaf, 0xc6, 0xc5, 0x98, 0x84, 0x71, 0x71, 0x67, 0x4d, 0x64,
+        0xee, 0xb2, 0x26, 0x2e, 0x9a, 0xe9, 0x53, 0xa9, 0xed, 0x60, 0xca, 0x9a, 0x41, 0x5f, 0x2c, 0x94,
+        0x4b, 0x83, 0xb2, 0x71, 0xdd, 0xfc, 0x16, 0x47, 0xe5, 0x03, 0xdb, 0xa4, 0xd0, 0x87, 0x46, 0xb6,
+        0xf8, 0x72, 0x1a, 0x91, 0x92, 0x7f, 0x65, 0xd0, 0x39, 0xb7, 0x3c, 0x5a, 0xf1, 0xbb, 0x68, 0x9f,
+        0x50, 0xf5, 0x4f, 0xfb, 0x5a, 0x33, 0x12, 0x82, 0xea, 0x7e, 0x5e, 0xfd, 0xdb, 0x00, 0xd7, 0xfe,
+        0x5e, 0xef, 0xd8, 0xfa, 0xb4, 0x91, 0xe6, 0x49, 0x6c, 0x64, 0xf9, 0xe9, 0x6c, 0xd7, 0x1b, 0x5a,
+        0x3b, 0x5a, 0x3d, 0xf0, 0x03,
+    ];
+
+    /// Text with skewed letter frequencies, from a fixed linear congruential sequence
+    fn skewed_text(len: usize) -> Vec<u8> {
+        let alphabet = b"eeeeeeeetttttaaaoinshrdlu  \n";
+        let mut x: u64 = 12345;
+        (0..len)
+            .map(|_| {
+                x = (x * 1103515245 + 12345) & 0x7fff_ffff;
+                alphabet[x as usize % alphabet.len()]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn decodes_known_images() {
+        for png in [PNG, STORED_PNG] {
+            let image = decode(png).unwrap();
+            assert_eq!((image.width, image.height), (3, 2));
+            assert_eq!(image.pixels, PIXELS);
+        }
+        let mut ppm = b"P6\n# comment\n3 2\n255\n".to_vec();
+        ppm.extend(PIXELS.iter().flatten());
+        assert_eq!(decode(&ppm).unwrap().pixels, PIXELS);
+        assert_eq!(inflate(DYNAMIC).unwrap(), skewed_text(200));
+    }
+
+    #[test]
+    fn refuses_truncated_images() {
+        // Every cut before the end of the image data fails; none may panic
+        for len in 0..PNG.len() {
+            let decoded = decode(&PNG[..len]);
+            if len < 65 {
+                assert!(decoded.is_err(), "a PNG cut at {} bytes decoded", len);
+            }
+        }
+        let mut ppm = b"P6 3 2 255\n".to_vec();
+        ppm.extend(PIXELS.iter().flatten().take(17));
+        assert!(decode(&ppm).is_err());
+        for len in 0..DYNAMIC.len() {
+            assert!(inflate(&DYNAMIC[..len]).is_err());
+        }
+    }
+
+    #[test]
+    fn refuses_corrupt_images() {
+        // Flipping any bit of the image or its compressed data must fail cleanly or decode something
+        for data in [PNG, STORED_PNG, DYNAMIC] {
+            for at in 0..data.len() {
+                for bit in 0..8 {
+                    let mut corrupt = data.to_vec();
+                    corrupt[at] ^= 1 << bit;
+                    let _ = decode(&corrupt);
+                    let _ = inflate(&corrupt);
+                }
+            }
+        }
+
+        let mut huge = PNG.to_vec();
+        huge[16..20].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(decode(&huge).err().unwrap().to_string().contains("too large"));
+        let mut bad_filter = STORED_PNG.to_vec();
+        bad_filter[48] = 9;
+        assert!(decode(&bad_filter).err().unwrap().to_string().contains("filter"));
+        let mut bad_block = DYNAMIC.to_vec();
+        bad_block[0] |= 0b110; // Block type 3
+        assert!(inflate(&bad_block).is_err());
+        assert!(decode(b"\xFF\xD8\xFF\xE0").err().unwrap().to_string().contains("JPEG"));
+        assert!(decode(b"P6 3 2 65535\n").is_err());
+    }
+}
@@ -0,0 +1,130 @@
+//! Progress feedback for slow operations
+//!
+//! `spin` shows a spinner while one long step runs, such as waiting on the
+//! delivery service, and `Bar` counts through many steps, such as applying a
+//! synced inbox. Both draw on stderr, only once an operation has taken long
+//! enough to notice, and never when stderr is not a terminal or output is
+//! `--plain` or JSON, so scripts and screen readers see none of it.
+
+use std::io::{self, IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long an operation runs before any progress is drawn
+const DELAY: Duration = Duration::from_millis(300);
+
+/// Time between spinner frames
+const FRAME: Duration = Duration::from_millis(100);
+
+const FRAMES: &[char] = &['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+
+/// Width of the bar itself, between its brackets
+const BAR_WIDTH: usize = 30;
+
+static DISABLED: AtomicBool = AtomicBool::new(false);
+
+/// Turn progress off for the rest of the run, for `--plain` and JSON output
+pub fn disable() {
+    DISABLED.store(true, Ordering::Relaxed);
+}
+
+fn enabled() -> bool {
+    !DISABLED.load(Ordering::Relaxed) && io::stderr().is_terminal()
+}
+
+/// Run `work` with a spinner and `message` on stderr until it returns
+///
+/// `work` must not print, or its output would be drawn over.
+pub fn spin<T>(message: &str, work: impl FnOnce() -> T) -> T {
+    if !enabled() {
+        return work();
+    }
+    let done = Arc::new(AtomicBool::new(false));
+    let spinner = {
+        let done = done.clone();
+        let message = message.to_string();
+        thread::spawn(move || {
+            let start = Instant::now();
+            let mut drawn = false;
+            for frame in FRAMES.iter().cycle() {
+                if done.load(Ordering::Relaxed) {
+                    break;
+                }
+                if start.elapsed() >= DELAY {
+                    draw(&format!("{} {}", frame, message));
+                    drawn = true;
+                }
+                thread::sleep(FRAME);
+            }
+            if drawn {
+                clear();
+            }
+        })
+    };
+    let result = work();
+    done.store(true, Ordering::Relaxed);
+    let _ = spinner.join();
+    result
+}
+
+/// A count of steps done out of a known total
+pub struct Bar {
+    message: String,
+    total: usize,
+    done: usize,
+    start: Instant,
+    drawn: bool,
+    enabled: bool,
+}
+
+impl Bar {
+    pub fn new(message: &str, total: usize) -> Self {
+        Self { message: message.to_string(), total, done: 0, start: Instant::now(), drawn: false, enabled: enabled() }
+    }
+
+    /// Run one step and count it; the bar is cleared while `work` runs, so it may print
+    pub fn step<T>(&mut self, work: impl FnOnce() -> T) -> T {
+        if self.drawn {
+            clear();
+        }
+        let result = work();
+        self.done += 1;
+        if self.enabled && self.done < self.total && self.start.elapsed() >= DELAY {
+            let filled = (self.done * BAR_WIDTH).checked_div(self.total).unwrap_or(0);
+            draw(&format!(
+                "[{}{}] {}/{} {}",
+                "=".repeat(filled),
+                " ".repeat(BAR_WIDTH - filled),
+                self.done,
+                self.total,
+                self.message
+            ));
+            self.drawn = true;
+        } else {
+            self.drawn = false;
+        }
+        result
+    }
+}
+
+impl Drop for Bar {
+    fn drop(&mut self) {
+        if self.drawn {
+            clear();
+        }
+    }
+}
+
+fn draw(line: &str) {
+    let mut stderr = io::stderr().lock();
+    let _ = write!(stderr, "\r\x1b[2K{}", line);
+    let _ = stderr.flush();
+}
+
+fn clear() {
+    let mut stderr = io::stderr().lock();
+    let _ = write!(stderr, "\r\x1b[2K");
+    let _ = stderr.flush();
+}
@@ -0,0 +1,95 @@
+//! Line-based prompts for interactive commands
+//!
+//! Answers are read from any `BufRead`, so anything that prompts can also be
+//! driven from a pipe. Destructive commands go through `confirm_loss`, which
+//! the global `--yes` flag answers for scripts.
+
+use anyhow::{anyhow, Context, Result};
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::process::Command;
+
+/// Print `question` and read one answer; a blank answer means `default`
+pub(crate) fn ask(input: &mut impl BufRead, question: &str, default: Option<&str>) -> Result<String> {
+    match default {
+        Some(default) if !default.is_empty() => print!("{} [{}]: ", question, default),
+        _ => print!("{}: ", question),
+    }
+    io::stdout().flush()?;
+    let mut line = String::new();
+    if input.read_line(&mut line).context("Failed to read answer")? == 0 {
+        println!();
+        return Err(anyhow!("Input ended before the question was answered"));
+    }
+    let answer = line.trim();
+    Ok(match answer.is_empty() {
+        true => default.unwrap_or_default().to_string(),
+        false => answer.to_string(),
+    })
+}
+
+/// Ask a yes/no question until it gets `y`, `yes`, `n`, or `no`; blank means `default`
+pub(crate) fn confirm(input: &mut impl BufRead, question: &str, default: bool) -> Result<bool> {
+    let hint = if default { "Y/n" } else { "y/N" };
+    loop {
+        let answer = ask(input, &format!("{} [{}]", question, hint), None)?;
+        match answer.to_lowercase().as_str() {
+            "" => return Ok(default),
+            "y" | "yes" => return Ok(true),
+            "n" | "no" => return Ok(false),
+            _ => println!("⚠️  Answer y or n"),
+        }
+    }
+}
+
+/// Ask for a passphrase on stdin, without echoing it when stdin is a terminal
+pub(crate) fn passphrase(question: &str) -> Result<String> {
+    let stdin = io::stdin();
+    let terminal = stdin.is_terminal();
+    if terminal {
+        // Best effort: without `stty` the passphrase is echoed
+        let _ = Command::new("stty").arg("-echo").status();
+    }
+    let answer = ask(&mut stdin.lock(), question, None);
+    if terminal {
+        let _ = Command::new("stty").arg("echo").status();
+        println!();
+    }
+    answer
+}
+
+/// Ask for a new passphrase twice, until both answers match; it must not be empty
+pub(crate) fn new_passphrase(question: &str) -> Result<String> {
+    loop {
+        let first = passphrase(question)?;
+        if first.is_empty() {
+            println!("⚠️  The passphrase must not be empty");
+            continue;
+        }
+        if passphrase("Repeat it")? == first {
+            return Ok(first);
+        }
+        println!("⚠️  The passphrases did not match; try again");
+    }
+}
+
+/// Before a destructive operation: list what will be lost and ask whether to go ahead
+///
+/// Nothing to lose, or `assume_yes` (the `--yes` flag), goes ahead without asking.
+/// Input that ends before an answer is an error, so an unattended run never proceeds by default.
+pub fn confirm_loss(input: &mut impl BufRead, losses: &[String], assume_yes: bool) -> Result<bool> {
+    if losses.is_empty() {
+        return Ok(true);
+    }
+    println!("⚠️  This cannot be undone:");
+    for loss in losses {
+        println!("   - {}", loss);
+    }
+    if assume_yes {
+        return Ok(true);
+    }
+    let proceed = confirm(input, "Continue?", false).context("Not confirmed; pass --yes to skip this question")?;
+    if !proceed {
+        println!("Cancelled; nothing was changed");
+    }
+    Ok(proceed)
+}
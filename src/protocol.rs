@@ -0,0 +1,220 @@
+//! Mock MLS messages exchanged between clients through the delivery service
+//!
+//! These mirror the shape of real MLS handshake and application messages.
+//! Group secrets travel encrypted: in a Commit under a key derived from the
+//! previous epoch's secret, and in a Welcome under a key derived from the
+//! joiner's public key; in a group with a ratchet tree a Commit carries path
+//! secrets instead, each under a key derived from a tree node's public key.
+//! These keys are a stand-in for HPKE: they show the message flow, but since
+//! a public key is public they do not protect the secret the way real HPKE
+//! would.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::crypto::{self, base64};
+use crate::model::{ChatMessage, GroupMetadata, ReceiptStatus};
+use crate::tree::{RatchetTree, SealedSecret, TreeCommit};
+
+/// A message carried in an envelope payload
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MlsMessage {
+    /// Brings a new member into a group at the current epoch
+    Welcome {
+        group_name: String,
+        group_id: String,
+        epoch: u32,
+        tree_hash: String,
+        members: Vec<String>,
+        encrypted_group_secret: String,
+        #[serde(default)]
+        metadata: GroupMetadata,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        tree: Option<RatchetTree>, // The group's ratchet tree, if it has one
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        path_secret: Option<SealedSecret>, // The committer's path secret above the joiner, under the Welcome key
+    },
+    /// Moves existing members to a new epoch
+    ///
+    /// A group with a ratchet tree sends only the change, in `tree`; its
+    /// members derive the new secret from the path, and apply the adds and
+    /// removes to their own member lists.
+    Commit {
+        group_id: String,
+        epoch: u32,
+        tree_hash: String,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        members: Vec<String>,
+        #[serde(default, skip_serializing_if = "String::is_empty")]
+        encrypted_group_secret: String,
+        #[serde(default)]
+        metadata: GroupMetadata,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        tree: Option<TreeCommit>,
+    },
+    /// An encrypted chat message
+    Application {
+        group_id: String,
+        epoch: u32,
+        message_id: String,
+        sender: String,
+        timestamp: DateTime<Utc>,
+        ciphertext: String, // An encrypted `Content`
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        sender_key: Option<String>, // The sender's public key, as their leaf credential would give it
+    },
+    /// An encrypted acknowledgement that messages were delivered or read
+    Receipt {
+        group_id: String,
+        epoch: u32,
+        sender: String,
+        ciphertext: String, // An encrypted `Content::Receipt`
+    },
+    /// An encrypted change to the group's pinned messages
+    Pin {
+        group_id: String,
+        epoch: u32,
+        sender: String,
+        ciphertext: String, // An encrypted `PinBody`
+    },
+    /// An encrypted statement that the sender replaced their identity key
+    KeyChange {
+        group_id: String,
+        epoch: u32,
+        sender: String,
+        ciphertext: String, // An encrypted `KeyChangeBody`
+    },
+    /// Past messages a member passes on to one new member, encrypted to the joiner's key
+    History {
+        group_id: String,
+        epoch: u32,
+        sender: String,
+        ciphertext: String, // An encrypted `HistoryBody`
+    },
+    /// Announces a message too large for one envelope, which follows as `Chunk`s
+    Manifest {
+        group_id: String,
+        message_id: String, // The chunked message's
+        size: u64, // Bytes of the whole serialized message
+        chunks: u32,
+        hash: String, // SHA-256 of the whole serialized message, hex
+    },
+    /// One piece of a chunked message
+    Chunk {
+        group_id: String,
+        message_id: String,
+        index: u32,
+        data: String, // Base64
+    },
+}
+
+/// What a Receipt acknowledges
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReceiptBody {
+    pub status: ReceiptStatus,
+    pub message_ids: Vec<String>,
+}
+
+/// What a Pin changes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PinBody {
+    pub message_id: String,
+    pub pinned: bool, // False to unpin
+}
+
+/// What a History passes on
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryBody {
+    pub from_epoch: u32, // The oldest epoch shared
+    pub messages: Vec<ChatMessage>,
+}
+
+/// A continuity statement: the old identity key vouching for its replacement
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KeyChangeBody {
+    pub user: String,
+    pub old_key_id: String,
+    pub old_public_key: String,
+    pub new_key_id: String,
+    pub new_public_key: String,
+    pub at: DateTime<Utc>,
+    pub signature: String, // By the old key, over `statement()`
+}
+
+impl KeyChangeBody {
+    /// The bytes the old key signs
+    pub fn statement(&self) -> String {
+        format!(
+            "mls-chat key continuity\n{}\n{}\n{}\n{}\n{}",
+            self.user,
+            self.old_key_id,
+            self.new_key_id,
+            self.new_public_key,
+            self.at.to_rfc3339(),
+        )
+    }
+}
+
+impl MlsMessage {
+    /// Serialize for an envelope payload
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        Ok(serde_json::to_vec(self)?)
+    }
+
+    /// Parse an envelope payload
+    pub fn from_bytes(payload: &[u8]) -> Result<Self> {
+        serde_json::from_slice(payload).context("Malformed MLS message")
+    }
+}
+
+/// Key protecting application messages in an epoch
+pub fn application_key(group_secret: &str) -> [u8; 32] {
+    crypto::derive_key(group_secret, "mls-chat application")
+}
+
+/// Key protecting the next epoch's secret in a Commit
+pub fn commit_key(previous_group_secret: &str) -> [u8; 32] {
+    crypto::derive_key(previous_group_secret, "mls-chat commit")
+}
+
+/// Key protecting the group secret in a Welcome, and shared history, for one recipient
+pub fn welcome_key(recipient_public_key: &str) -> [u8; 32] {
+    crypto::derive_key(recipient_public_key, "mls-chat welcome")
+}
+
+/// Key protecting a path secret encrypted to a ratchet tree node
+pub fn node_key(node_public_key: &str) -> [u8; 32] {
+    crypto::derive_key(node_public_key, "mls-chat node")
+}
+
+/// Stand-in for a signature by the owner of `public_key`
+///
+/// The mock's keys are random strings with no key pair behind them, so the
+/// tag is keyed by the public key itself. It binds the statement to that
+/// key, but anyone who knows the key could make one; a real client signs
+/// with the private key under the ciphersuite's signature scheme.
+pub fn sign(public_key: &str, statement: &str) -> String {
+    let key = crypto::derive_key(public_key, "mls-chat signature");
+    crypto::hex(&crypto::hmac_sha256(&key, statement.as_bytes()))
+}
+
+/// Check a `sign` tag
+pub fn verify(public_key: &str, statement: &str, signature: &str) -> bool {
+    sign(public_key, statement) == signature
+}
+
+/// Encrypt `plaintext` under `key`, base64-encoded
+pub fn encrypt(key: &[u8; 32], plaintext: &str) -> String {
+    base64::encode(&crypto::seal(key, plaintext.as_bytes()))
+}
+
+/// Decrypt base64 data produced by `encrypt`
+pub fn decrypt(key: &[u8; 32], ciphertext: &str) -> Result<String> {
+    let sealed = base64::decode(ciphertext)?;
+    let plaintext = crypto::open(key, &sealed)?;
+    String::from_utf8(plaintext).context("Decrypted message is not valid UTF-8")
+}
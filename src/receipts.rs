@@ -0,0 +1,192 @@
+//! Delivery and read receipts in networked mode
+//!
+//! A member acknowledges a message with an encrypted Receipt sent back to
+//! its sender: `Delivered` when the message is applied, `Read` once it has
+//! been shown by `list`. Each group keeps the furthest status reported per
+//! message and member.
+
+use anyhow::{Context, Result};
+use colored::*;
+use std::collections::BTreeMap;
+
+use crate::app::MlsChatApp;
+use crate::content::Content;
+use crate::delivery::SendMessageRequest;
+use crate::model::{ChatGroup, ChatMessage, ReceiptStatus};
+use crate::output;
+use crate::protocol::{self, MlsMessage, ReceiptBody};
+use crate::theme;
+use crate::timestamps::Timestamps;
+
+impl MlsChatApp {
+    /// Tell `recipient` that the current user's copies of `message_ids` have reached `status`
+    pub(crate) fn send_receipt(
+        &mut self,
+        group_name: &str,
+        recipient: &str,
+        status: ReceiptStatus,
+        message_ids: Vec<String>,
+    ) -> Result<()> {
+        let user = self.current_user.context("No user initialized")?.to_string();
+        let group = self.groups.get(group_name).context("Group not found")?;
+        let body = Content::Receipt(ReceiptBody { status, message_ids }).to_plaintext()?;
+        let receipt = MlsMessage::Receipt {
+            group_id: group.group_id.clone(),
+            epoch: group.mls_group.epoch,
+            sender: user.clone(),
+            ciphertext: protocol::encrypt(&protocol::application_key(&group.mls_group.group_secret), &body),
+        };
+        let group_id = group.group_id.clone();
+        let request = SendMessageRequest {
+            sender: user,
+            group_id: group_id.clone(),
+            recipients: vec![recipient.to_string()],
+            payload: receipt.to_bytes()?,
+        };
+        let seq = self.transport()?.send_message(request)?.seq;
+        self.record_sent_seq(&group_id, seq);
+        Ok(())
+    }
+
+    /// Record a Receipt from another member
+    ///
+    /// Receipts from another epoch cannot be decrypted and are dropped,
+    /// since a missing acknowledgement does no harm.
+    pub(crate) fn apply_receipt(&mut self, group_name: &str, sender: &str, epoch: u32, ciphertext: &str) -> Result<()> {
+        let group = self.groups.get_mut(group_name).context("Group not found")?;
+        if epoch != group.mls_group.epoch {
+            println!("   Receipt from {} for epoch {} ignored at epoch {}", sender, epoch, group.mls_group.epoch);
+            return Ok(());
+        }
+        let body = protocol::decrypt(&protocol::application_key(&group.mls_group.group_secret), ciphertext)?;
+        let body = match Content::from_plaintext(&body) {
+            Content::Receipt(body) => body,
+            _ => serde_json::from_str(&body).context("Malformed receipt")?, // A bare body, from an older client
+        };
+        self.record_receipt(group_name, sender, body)
+    }
+
+    /// Record what a member's receipt acknowledges
+    pub(crate) fn record_receipt(&mut self, group_name: &str, sender: &str, body: ReceiptBody) -> Result<()> {
+        let group = self.groups.get_mut(group_name).context("Group not found")?;
+        for message_id in &body.message_ids {
+            let status = group.receipts.entry(message_id.clone()).or_default()
+                .entry(sender.to_string())
+                .or_insert(body.status);
+            *status = (*status).max(body.status);
+        }
+        self.dirty_groups.insert(group_name.to_string());
+        let verb = match body.status {
+            ReceiptStatus::Delivered => "received",
+            ReceiptStatus::Read => "read",
+        };
+        println!("   ✓ {} {} {} message(s) in '{}'", sender, verb, body.message_ids.len(), group_name);
+        Ok(())
+    }
+
+    /// Send read receipts for loaded messages from other members not yet acknowledged as read
+    pub(crate) fn send_read_receipts(&mut self, group_name: &str) -> Result<()> {
+        let group = self.groups.get(group_name).context("Group not found")?;
+        let mut unread: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for message in group.messages.as_deref().unwrap_or_default() {
+            if self.owes_read_receipt(group, message) {
+                unread.entry(message.sender.clone()).or_default().push(message.id.clone());
+            }
+        }
+        self.acknowledge_read(group_name, unread)
+    }
+
+    /// Whether `message` is from another member and not yet acknowledged as read by the current user
+    pub(crate) fn owes_read_receipt(&self, group: &ChatGroup, message: &ChatMessage) -> bool {
+        let Some(user) = self.current_user.map(|user| user.to_string()) else { return false };
+        let acknowledged = group.receipts.get(&message.id)
+            .and_then(|statuses| statuses.get(&user))
+            .is_some_and(|status| *status == ReceiptStatus::Read);
+        !acknowledged && self.is_remote_member(&group.name, &message.sender)
+    }
+
+    /// Send a read receipt to each sender for `unread`, their message IDs by sender, and note them as read
+    pub(crate) fn acknowledge_read(&mut self, group_name: &str, unread: BTreeMap<String, Vec<String>>) -> Result<()> {
+        let user = self.current_user.context("No user initialized")?.to_string();
+        for (sender, message_ids) in unread {
+            self.send_receipt(group_name, &sender, ReceiptStatus::Read, message_ids.clone())?;
+            let group = self.groups.get_mut(group_name).context("Group not found")?;
+            for message_id in message_ids {
+                group.receipts.entry(message_id).or_default().insert(user.clone(), ReceiptStatus::Read);
+            }
+            self.dirty_groups.insert(group_name.to_string());
+        }
+        Ok(())
+    }
+
+    /// Show a message and how far it has got with each member
+    pub fn show_message(&mut self, message_id: &str, times: &Timestamps) -> Result<()> {
+        let mut found: Vec<(String, ChatMessage)> = match message_id.is_empty() {
+            true => Vec::new(),
+            false => self.storage.find_messages(message_id)?,
+        }
+        .into_iter()
+        .filter_map(|message| Some((self.group_name_by_id(&message.group_id)?, message)))
+        .collect();
+        if let Some(exact) = found.iter().position(|(_, message)| message.id == message_id) {
+            found = vec![found.swap_remove(exact)];
+        }
+        let (name, message) = match found.len() {
+            1 => found.remove(0),
+            0 => return Err(anyhow::anyhow!("Message '{}' not found", message_id)),
+            count => return Err(anyhow::anyhow!("'{}' matches {} messages; give more of the ID", message_id, count)),
+        };
+        let group = self.groups.get(&name).context("Group not found")?;
+
+        println!("{}", format!("Message {}", message.id).blue());
+        output::rule('=', 50);
+        println!("Group: {}", group.name);
+        println!("From: {}", theme::sender(&message.sender, false));
+        println!("Sent: {}", times.full(message.timestamp));
+        println!("Epoch: {}", message.epoch);
+        println!("Type: {}", message.content_type);
+        println!("Content: {}", message.content);
+        if let Some(from) = &message.forwarded_from {
+            println!("Forwarded from: {} in '{}', {} (message {})", from.sender, from.group, from.timestamp.format("%Y-%m-%d %H:%M:%S UTC"), from.message_id);
+        }
+        if let Some(tags) = group.tags.get(&message.id) {
+            let tags: Vec<&str> = tags.iter().map(String::as_str).collect();
+            println!("Tags: {}", tags.join(", "));
+        }
+        if group.pinned.contains(&message.id) {
+            println!("Pinned: yes");
+        }
+        output::rule('=', 50);
+        let user = self.current_user.map(|user| user.to_string());
+        for member in group.members.iter().filter(|member| **member != message.sender) {
+            let is_user = user.as_deref() == Some(member.as_str());
+            let mut status = group.receipts.get(&message.id).and_then(|statuses| statuses.get(member)).copied();
+            if is_user {
+                // The user's own copy is here, so it has been delivered
+                status = status.max(Some(ReceiptStatus::Delivered));
+            }
+            let status = match status {
+                _ if self.user_keys.contains_key(member) && !is_user => "same profile".to_string(),
+                Some(ReceiptStatus::Read) => "✓✓ read".green().to_string(),
+                Some(ReceiptStatus::Delivered) => "✓ delivered".to_string(),
+                None => "not yet delivered".dimmed().to_string(),
+            };
+            println!("   {}{}: {}", member, if is_user { " (you)" } else { "" }, status);
+        }
+        Ok(())
+    }
+
+    /// ✓✓ once every remote recipient has read a message, ✓ once all have it, else blank
+    pub(crate) fn receipt_mark(&self, group: &ChatGroup, message: &ChatMessage) -> String {
+        let statuses = group.receipts.get(&message.id);
+        let slowest = group.members.iter()
+            .filter(|member| **member != message.sender && !self.user_keys.contains_key(*member))
+            .map(|member| statuses.and_then(|statuses| statuses.get(member)).copied())
+            .min();
+        match slowest {
+            Some(Some(ReceiptStatus::Read)) => " ✓✓".green().to_string(),
+            Some(Some(ReceiptStatus::Delivered)) => " ✓".to_string(),
+            _ => String::new(),
+        }
+    }
+}
@@ -0,0 +1,69 @@
+//! `reset`: wipe some or all of a profile's state
+//!
+//! Resetting goes through storage, so group secrets, message logs, and
+//! keys are overwritten before they are removed, just as `delete-group`
+//! does, and what is left behind stays consistent. Nothing is sent: other
+//! members are not told, as with `delete-group --local-only`.
+
+use anyhow::{Context, Result};
+use colored::*;
+
+use crate::app::MlsChatApp;
+
+/// What `reset` wipes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ResetScope {
+    Group(String),               // One group
+    All { keep_identity: bool }, // Every group, and the identities unless kept
+}
+
+impl MlsChatApp {
+    /// What resetting `scope` loses, for confirming first
+    pub fn reset_losses(&self, scope: &ResetScope) -> Result<Vec<String>> {
+        let mut losses = Vec::new();
+        match scope {
+            ResetScope::Group(name) => {
+                let group = self.groups.get(name).context("Group not found")?;
+                losses.push(format!("Group '{}', its {} message(s), and its group secret are wiped from this profile", name, group.message_count));
+                losses.push("Other members are not told, and still list you".to_string());
+            }
+            ResetScope::All { keep_identity } => {
+                let messages: usize = self.groups.values().map(|group| group.message_count).sum();
+                losses.push(format!("All {} group(s) and their {} message(s) are wiped from this profile", self.groups.len(), messages));
+                if !keep_identity {
+                    let mut users: Vec<&str> = self.user_keys.keys().map(String::as_str).collect();
+                    users.sort();
+                    losses.push(format!(
+                        "The keys of {} are wiped; these identities cannot read or send again, here or in any group",
+                        if users.is_empty() { "every identity".to_string() } else { users.join(", ") }
+                    ));
+                    losses.push("Saved `login` tokens are wiped".to_string());
+                }
+            }
+        }
+        Ok(losses)
+    }
+
+    /// Wipe `scope`; returns the names of the groups removed
+    pub fn reset(&mut self, scope: &ResetScope) -> Result<Vec<String>> {
+        let mut names: Vec<String> = match scope {
+            ResetScope::Group(name) => vec![name.clone()],
+            ResetScope::All { .. } => self.groups.keys().cloned().collect(),
+        };
+        names.sort();
+        for name in &names {
+            let group = self.groups.remove(name).context("Group not found")?;
+            self.dirty_groups.remove(name);
+            self.storage.delete_group(&group.group_id)?;
+            println!("🗑️  Wiped group '{}'", name);
+        }
+        if let ResetScope::All { keep_identity: false } = scope {
+            self.storage.delete_identities()?;
+            self.user_keys.clear();
+            self.current_user = None;
+            println!("🗑️  Wiped every identity's keys and saved tokens");
+        }
+        println!("{}", "✅ Reset complete".green());
+        Ok(names)
+    }
+}
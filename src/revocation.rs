@@ -0,0 +1,133 @@
+//! Revocation: refusing and flagging credentials known to be compromised
+//!
+//! `revocation update <file>` loads a list of revoked credentials, one key
+//! fingerprint per line as `whoami` and `add-member` print it, optionally
+//! followed by `# reason`; blank lines and lines starting with `#` are
+//! skipped:
+//!
+//! ```text
+//! # Revoked 2026-10-01
+//! 4115 e157 47f4 b9c1 f33d de64 476b 7919 bd4a 7abb 0fec 0b4d 52e2 45f1 f75a 858f # laptop stolen
+//! ```
+//!
+//! The list replaces the previous one. Adds, approvals, and joins are then
+//! refused for a revoked key, and members already holding one are flagged,
+//! when the list is loaded and by `info`. A member's key is the one this
+//! profile holds for them, or else their key package on the delivery
+//! service. Credentials here are the mock's bare keys, not X.509
+//! certificates, so there is nothing to ask an OCSP responder about.
+
+use anyhow::{anyhow, Context, Result};
+use chrono::Utc;
+use colored::*;
+use std::fs;
+
+use crate::app::MlsChatApp;
+use crate::crypto;
+use crate::model::{RevocationList, RevokedCredential};
+
+/// Parse a revocation list file's text
+pub fn parse_revocations(text: &str) -> Result<Vec<RevokedCredential>> {
+    let mut revoked = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let (fingerprint, reason) = match line.split_once('#') {
+            Some((fingerprint, reason)) => (fingerprint, Some(reason.trim())),
+            None => (line, None),
+        };
+        let digits: String = fingerprint.chars().filter(|c| !c.is_whitespace()).collect::<String>().to_lowercase();
+        if digits.is_empty() {
+            continue;
+        }
+        if digits.len() != 64 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(anyhow!("Line {}: expected a key fingerprint of 64 hex digits, found '{}'", number + 1, fingerprint.trim()));
+        }
+        let groups: Vec<&str> = (0..digits.len()).step_by(4).map(|start| &digits[start..start + 4]).collect();
+        revoked.push(RevokedCredential {
+            fingerprint: groups.join(" "),
+            reason: reason.filter(|reason| !reason.is_empty()).map(str::to_string),
+        });
+    }
+    Ok(revoked)
+}
+
+impl MlsChatApp {
+    /// Replace the revocation list with the one in `path`, then flag members holding a revoked key
+    pub fn update_revocations(&mut self, path: &str) -> Result<()> {
+        let text = fs::read_to_string(path).with_context(|| format!("Failed to read revocation list {}", path))?;
+        let revoked = parse_revocations(&text).with_context(|| format!("Invalid revocation list {}", path))?;
+        let list = RevocationList { source: path.to_string(), updated: Utc::now(), revoked };
+        self.storage.save_revocations(&list)?;
+        println!("{}", format!("✅ Loaded {} revoked credential(s) from {}", list.revoked.len(), path).green());
+
+        let mut names: Vec<String> = self.groups.keys().cloned().collect();
+        names.sort();
+        let mut flagged = 0;
+        for name in names {
+            flagged += self.flag_revoked(&name)?;
+        }
+        if flagged > 0 {
+            println!("   Consider `ban <group> <member>` to remove them");
+        }
+        Ok(())
+    }
+
+    /// List the loaded revocation list
+    pub fn list_revocations(&self) -> Result<()> {
+        let Some(list) = self.storage.load_revocations()? else {
+            println!("No revocation list loaded; load one with `revocation update <file>`.");
+            return Ok(());
+        };
+        println!("{} revoked credential(s) from {}, loaded {}", list.revoked.len(), list.source, list.updated.format("%Y-%m-%d %H:%M UTC"));
+        for revoked in &list.revoked {
+            println!("   {}{}", revoked.fingerprint, describe_reason(revoked));
+        }
+        Ok(())
+    }
+
+    /// Fail if `public_key` is on the revocation list
+    pub(crate) fn check_revoked(&self, member: &str, public_key: &str) -> Result<()> {
+        match self.revocation(public_key)? {
+            Some(revoked) => Err(anyhow!("{}'s credential is revoked{}", member, describe_reason(&revoked))),
+            None => Ok(()),
+        }
+    }
+
+    /// Warn about each member of a group whose key is on the revocation list; returns how many
+    pub fn flag_revoked(&mut self, group_name: &str) -> Result<usize> {
+        let flagged = self.revoked_members(group_name)?;
+        for (member, revoked) in &flagged {
+            println!("{}", format!("⚠️  {} in '{}' holds a revoked credential{}", member, group_name, describe_reason(revoked)).yellow());
+        }
+        Ok(flagged.len())
+    }
+
+    /// The members of a group whose key is on the revocation list
+    pub fn revoked_members(&mut self, group_name: &str) -> Result<Vec<(String, RevokedCredential)>> {
+        if self.storage.load_revocations()?.is_none_or(|list| list.revoked.is_empty()) {
+            return Ok(Vec::new());
+        }
+        let members = self.groups.get(group_name).context("Group not found")?.members.clone();
+        let mut flagged = Vec::new();
+        for member in members {
+            let public_key = match (self.user_keys.get(&member), self.transport.as_mut()) {
+                (Some(key), _) => Some(key.public_key.clone()),
+                (None, Some(transport)) => transport.fetch_key_package(&member).ok().map(|package| package.public_key),
+                (None, None) => None,
+            };
+            if let Some(revoked) = public_key.map(|key| self.revocation(&key)).transpose()?.flatten() {
+                flagged.push((member, revoked));
+            }
+        }
+        Ok(flagged)
+    }
+
+    fn revocation(&self, public_key: &str) -> Result<Option<RevokedCredential>> {
+        let fingerprint = crypto::fingerprint(public_key);
+        Ok(self.storage.load_revocations()?
+            .and_then(|list| list.revoked.into_iter().find(|revoked| revoked.fingerprint == fingerprint)))
+    }
+}
+
+fn describe_reason(revoked: &RevokedCredential) -> String {
+    revoked.reason.as_deref().map(|reason| format!(" ({})", reason)).unwrap_or_default()
+}
@@ -0,0 +1,100 @@
+//! Updates: rotating your own key in a group
+//!
+//! An Update commit gives the group a new epoch and secret without changing
+//! who is in it, so whoever held the old secret can no longer read what is
+//! sent next. `update` sends one by hand; with `key-rotation.after-days` or
+//! `key-rotation.after-messages` set, `send` sends one first whenever the
+//! sender's last Update in that group is older or has had that many messages
+//! sent since. Counting starts with the first message a member sends from
+//! this profile, since earlier rotations are not recorded.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, TimeDelta, Utc};
+use colored::*;
+
+use crate::app::MlsChatApp;
+use crate::commit::Proposals;
+use crate::model::{KeyRotation, RotationPolicy};
+
+impl MlsChatApp {
+    /// Rotate the current user's key in a group by committing an Update
+    pub fn self_update(&mut self, group_name: &str) -> Result<()> {
+        let user = self.current_user.context("No user initialized")?.to_string();
+        println!("{}", "Rotating your key...".green());
+        let group = self.groups.get(group_name).context("Group not found")?;
+        if !group.members.contains(&user) {
+            return Err(anyhow::anyhow!("User '{}' is not a member of group '{}'", user, group_name));
+        }
+
+        println!("   Update proposal for {} with a fresh leaf key", user);
+        self.commit(group_name, Proposals::default())?;
+
+        let group = self.groups.get_mut(group_name).context("Group not found")?;
+        group.key_rotations.insert(user, KeyRotation { at: Utc::now(), updated: true, messages_sent: 0 });
+        println!("✅ Key rotated in '{}'", group_name);
+        println!("   New epoch: {}", group.mls_group.epoch);
+        self.dirty_groups.insert(group_name.to_string());
+        self.save_state()
+    }
+
+    /// Send an Update first if the rotation policy says the current user's key is due
+    pub(crate) fn update_if_due(&mut self, group_name: &str) -> Result<()> {
+        let user = self.current_user.context("No user initialized")?.to_string();
+        let policy = self.rotation;
+        if policy == RotationPolicy::default() {
+            return Ok(());
+        }
+        let group = self.groups.get_mut(group_name).context("Group not found")?;
+        let rotation = *group.key_rotations.entry(user)
+            .or_insert(KeyRotation { at: Utc::now(), updated: false, messages_sent: 0 });
+        self.dirty_groups.insert(group_name.to_string());
+        let (due_at, messages_left) = next_due(&rotation, policy);
+        if due_at.is_some_and(|due_at| due_at <= Utc::now()) || messages_left == Some(0) {
+            println!("   Key rotation is due");
+            self.self_update(group_name)?;
+        }
+        Ok(())
+    }
+
+    /// Count a message the current user sent towards `key-rotation.after-messages`
+    pub(crate) fn count_sent(&mut self, group_name: &str) {
+        let Some(user) = self.current_user else { return };
+        if let Some(rotation) = self.groups.get_mut(group_name).and_then(|group| group.key_rotations.get_mut(&user.to_string())) {
+            rotation.messages_sent += 1;
+            self.dirty_groups.insert(group_name.to_string());
+        }
+    }
+
+    /// The current user's last rotation in a group and when the next is due, for `info`
+    pub(crate) fn describe_rotation(&self, group_name: &str) -> Option<String> {
+        let user = self.current_user?.to_string();
+        let group = self.groups.get(group_name)?;
+        let rotation = group.key_rotations.get(&user);
+        let last = match rotation {
+            Some(rotation) if rotation.updated => format!("last {}", rotation.at.format("%Y-%m-%d %H:%M UTC")),
+            _ => "none yet".to_string(),
+        };
+        if self.rotation == RotationPolicy::default() {
+            return rotation.map(|_| format!("{}; no automatic rotation", last));
+        }
+        let Some(rotation) = rotation else {
+            return Some(format!("{}; counting starts with your next message", last));
+        };
+        let (due_at, messages_left) = next_due(rotation, self.rotation);
+        let mut due = Vec::new();
+        if let Some(due_at) = due_at {
+            due.push(due_at.format("%Y-%m-%d %H:%M UTC").to_string());
+        }
+        if let Some(left) = messages_left {
+            due.push(format!("after {} more message(s)", left));
+        }
+        Some(format!("{}; next due {}", last, due.join(" or ")))
+    }
+}
+
+/// When `rotation` is next due under `policy`, and how many messages remain before it is
+fn next_due(rotation: &KeyRotation, policy: RotationPolicy) -> (Option<DateTime<Utc>>, Option<usize>) {
+    let due_at = policy.after_days.and_then(|days| TimeDelta::try_days(days.into())).map(|age| rotation.at + age);
+    let messages_left = policy.after_messages.map(|limit| limit.saturating_sub(rotation.messages_sent));
+    (due_at, messages_left)
+}
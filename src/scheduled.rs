@@ -0,0 +1,153 @@
+//! Scheduled messages
+//!
+//! `send --at` keeps a message in its group's local queue instead of sending
+//! it. Nothing runs in the background: queued messages are sent by the first
+//! command that sends messages anyway, such as `send` or `poll vote`, to start
+//! after they fall due, and every second by long-running commands such as
+//! `list --follow` and bots. Read-only and destructive commands, `sync` and
+//! `poll results` among them, never send them. A message is sent at its time or later, never earlier, and carries
+//! the time it was actually sent.
+
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use colored::*;
+use uuid::Uuid;
+
+use crate::app::MlsChatApp;
+use crate::content::Content;
+use crate::model::ScheduledMessage;
+use crate::output;
+use crate::pins::short_id;
+use crate::theme;
+
+/// Formats `send --at` accepts besides RFC 3339, read as UTC
+const TIME_FORMATS: &[&str] = &["%Y-%m-%d %H:%M", "%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M", "%Y-%m-%dT%H:%M:%S"];
+
+impl MlsChatApp {
+    /// Queue `content` to be sent to a group by the current user at `at`
+    pub fn schedule_content(&mut self, group_name: &str, content: Content, at: DateTime<Utc>) -> Result<()> {
+        let user = self.current_user.context("No user initialized")?.to_string();
+        if at <= Utc::now() {
+            return Err(anyhow!("{} has already passed; leave out --at to send now", at.format("%Y-%m-%d %H:%M UTC")));
+        }
+        let content = self.expand_shortcodes(content);
+        let group = self.groups.get_mut(group_name).context("Group not found")?;
+        if !group.members.contains(&user) {
+            return Err(anyhow!("User '{}' is not a member of group '{}'", user, group_name));
+        }
+        let id = Uuid::new_v4().to_string();
+        let position = group.scheduled.partition_point(|queued| queued.at <= at);
+        group.scheduled.insert(position, ScheduledMessage { id: id.clone(), sender: user, at, content });
+        self.dirty_groups.insert(group_name.to_string());
+        println!("⏰ Message {} will be sent to '{}' at {}", short_id(&id), group_name, at.format("%Y-%m-%d %H:%M UTC"));
+        println!("   It goes out with the first `send`, `forward`, or `poll vote` run after then, or from `list --follow` or a bot");
+        self.save_state()
+    }
+
+    /// Every queued message, with its group's name, soonest first
+    pub fn scheduled_messages(&self) -> Vec<(&str, &ScheduledMessage)> {
+        let mut queued: Vec<(&str, &ScheduledMessage)> = self.groups.values()
+            .flat_map(|group| group.scheduled.iter().map(move |message| (group.name.as_str(), message)))
+            .collect();
+        queued.sort_by_key(|(_, message)| message.at);
+        queued
+    }
+
+    /// Print the queued messages
+    pub fn list_scheduled(&self) {
+        let queued = self.scheduled_messages();
+        println!("{}", "Scheduled messages:".blue());
+        output::rule('=', 50);
+        if queued.is_empty() {
+            println!("Nothing scheduled.");
+        }
+        for (group, message) in queued {
+            println!("{} {} → '{}' from {}: {}",
+                short_id(&message.id).dimmed(),
+                message.at.format("%Y-%m-%d %H:%M UTC"),
+                group,
+                theme::sender(&message.sender, false),
+                message.content.summary(),
+            );
+        }
+    }
+
+    /// Drop a queued message before it is sent; `id` may be a unique prefix
+    pub fn cancel_scheduled(&mut self, id: &str) -> Result<()> {
+        let matches: Vec<(String, String)> = self.scheduled_messages().into_iter()
+            .filter(|(_, message)| !id.is_empty() && message.id.starts_with(id))
+            .map(|(group, message)| (group.to_string(), message.id.clone()))
+            .collect();
+        let (group_name, id) = match matches.as_slice() {
+            [found] => found.clone(),
+            [] => return Err(anyhow!("No scheduled message '{}'; `scheduled list` shows them", id)),
+            _ => return Err(anyhow!("'{}' matches {} scheduled messages; give more of the ID", id, matches.len())),
+        };
+        let group = self.groups.get_mut(&group_name).context("Group not found")?;
+        group.scheduled.retain(|message| message.id != id);
+        self.dirty_groups.insert(group_name.clone());
+        println!("✅ Cancelled scheduled message {} to '{}'", short_id(&id), group_name);
+        self.save_state()
+    }
+
+    /// Send the current user's queued messages whose time has come; returns how many went out
+    ///
+    /// A message that fails to send stays queued for the next attempt, unless
+    /// the user has since left its group, in which case it is dropped.
+    pub fn send_due_scheduled(&mut self) -> Result<usize> {
+        let Some(user) = self.current_user.map(|user| user.to_string()) else {
+            return Ok(0);
+        };
+        let now = Utc::now();
+        let mut due: Vec<(String, ScheduledMessage)> = Vec::new();
+        for group in self.groups.values() {
+            for message in group.scheduled.iter().filter(|message| message.at <= now && message.sender == user) {
+                due.push((group.name.clone(), message.clone()));
+            }
+        }
+        due.sort_by_key(|(_, message)| message.at);
+
+        let mut sent = 0;
+        for (group_name, message) in due {
+            let group = self.groups.get(&group_name).context("Group not found")?;
+            let outcome = match group.members.contains(&user) {
+                true => self.post_message(&group_name, user.clone(), message.content.clone()).map(Some),
+                false => Ok(None),
+            };
+            let keep = match outcome {
+                Ok(Some(_)) => {
+                    println!("⏰ Sent scheduled message {} to '{}'", short_id(&message.id), group_name);
+                    sent += 1;
+                    false
+                }
+                Ok(None) => {
+                    println!("⚠️  Dropped scheduled message {}: you are no longer in '{}'", short_id(&message.id), group_name);
+                    false
+                }
+                Err(err) => {
+                    println!("⚠️  Could not send scheduled message {} to '{}': {:#}", short_id(&message.id), group_name, err);
+                    true
+                }
+            };
+            if !keep {
+                let group = self.groups.get_mut(&group_name).context("Group not found")?;
+                group.scheduled.retain(|queued| queued.id != message.id);
+                self.dirty_groups.insert(group_name);
+            }
+        }
+        self.save_state()?;
+        Ok(sent)
+    }
+}
+
+/// A time as `send --at` takes it: RFC 3339, or `YYYY-MM-DD HH:MM[:SS]` in UTC
+pub fn parse_time(text: &str) -> Result<DateTime<Utc>> {
+    let text = text.trim();
+    if let Ok(time) = DateTime::parse_from_rfc3339(text) {
+        return Ok(time.with_timezone(&Utc));
+    }
+    TIME_FORMATS.iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(text, format).ok())
+        .map(|time| time.and_utc())
+        .with_context(|| format!("Invalid time '{}'; expected e.g. \"2024-06-01 09:00\" (UTC)", text))
+}
@@ -0,0 +1,1032 @@
+//! Delivery service: a key package directory plus per-user inboxes
+//!
+//! The server only relays opaque payloads. It never holds group secrets and
+//! cannot read application messages. One server can host several isolated
+//! namespaces, each with its own users, key packages, and inboxes.
+//!
+//! Servers can also federate: messages for users homed on a peer server are
+//! forwarded there as envelopes signed with a secret only the two of them
+//! share, so one peer cannot pass envelopes off as another's. A peer may
+//! only speak for users homed with it: an envelope whose sender is local
+//! here is refused.
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Duration, Utc};
+use colored::*;
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, VecDeque},
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{
+        mpsc::{self, Sender},
+        Arc, Mutex, MutexGuard,
+    },
+    thread,
+    time,
+};
+use uuid::Uuid;
+
+use crate::client::DeliveryClient;
+use crate::crypto;
+use crate::delivery::{
+    AddressedEnvelope, Call, Envelope, FetchInboxResponse, FetchKeyPackageResponse, FetchPresenceResponse,
+    FetchRangeRequest, FetchRangeResponse, ForwardRequest, ForwardResponse, GroupSummary, HeartbeatResponse, KeyPackage, ListGroupsResponse,
+    ListUsersResponse, Presence, PublishKeyPackageResponse, PurgeKeyPackagesResponse, QueueDepth,
+    QueueDepthsResponse, RegisterResponse, Reply, Request, RevokeTokenResponse, RpcError, SendMessageRequest,
+    SendMessageResponse, StatusCode, UserSummary,
+};
+use crate::http::{self, HttpRequest, HttpResponse};
+use crate::metrics::{Metrics, NamespaceGauges};
+
+/// How recently a user must have called in to count as online
+const ONLINE_WINDOW: Duration = Duration::seconds(60);
+/// How long to wait for the notification webhook to answer
+const WEBHOOK_TIMEOUT: time::Duration = time::Duration::from_secs(10);
+/// How long a metrics scrape may take to send its request
+const METRICS_READ_TIMEOUT: time::Duration = time::Duration::from_secs(10);
+/// How long to wait on a peer server before giving up on a call
+const PEER_TIMEOUT: time::Duration = time::Duration::from_secs(10);
+/// How many times to try forwarding an envelope before dropping it
+const FORWARD_ATTEMPTS: u32 = 3;
+/// Period the per-minute rate limits count calls over
+const RATE_WINDOW: Duration = Duration::seconds(60);
+/// Largest payload accepted unless configured otherwise
+pub const DEFAULT_MAX_MESSAGE_BYTES: usize = 1024 * 1024;
+/// Room in a request line for everything besides the payload: method, recipients, token
+const LINE_HEADROOM: usize = 64 * 1024;
+
+/// Abuse controls, applied per identity within each namespace
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimits {
+    pub messages_per_minute: Option<u32>,            // SendMessage calls per sender; unlimited if None
+    pub key_package_fetches_per_minute: Option<u32>, // FetchKeyPackage calls per requester; unlimited if None
+    pub max_message_bytes: usize,                    // Largest payload SendMessage accepts
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        Self {
+            messages_per_minute: None,
+            key_package_fetches_per_minute: None,
+            max_message_bytes: DEFAULT_MAX_MESSAGE_BYTES,
+        }
+    }
+}
+
+/// Kinds of call with a rate limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Limited {
+    Messages,
+    KeyPackageFetches,
+}
+
+/// Delivery service shared by all connections
+#[derive(Clone, Default)]
+pub struct DeliveryServer {
+    state: Arc<Mutex<ServerState>>,
+    webhook: Option<Arc<String>>, // Told when a message is queued for a user who is not subscribed
+    require_auth: bool,           // Reject calls on behalf of unregistered users
+    namespaces: Arc<HashMap<String, String>>, // Namespaces besides the default, with their admin tokens
+    admin_token: Option<Arc<String>>,         // For admin calls to the default namespace; disabled if unset
+    limits: RateLimits,
+    metrics: Arc<Mutex<Metrics>>,
+    federation: Option<Arc<Federation>>,
+    forwarders: Arc<Mutex<HashMap<String, Sender<Forward>>>>, // Per-peer queues, in send order
+}
+
+/// A signed envelope waiting for a peer, with the namespace it belongs to
+type Forward = (String, ForwardRequest);
+
+/// This server's place among federated delivery services
+#[derive(Clone)]
+struct Federation {
+    name: String,                 // How peers know this server
+    peers: HashMap<String, Peer>, // By peer name
+}
+
+/// A federated peer server
+#[derive(Clone)]
+struct Peer {
+    addr: String,
+    secret: String, // Shared with this peer alone; keys envelope signatures both ways
+}
+
+#[derive(Default)]
+struct ServerState {
+    namespaces: HashMap<String, Namespace>, // Keyed by name; the default namespace is ""
+}
+
+/// Everything one namespace holds, isolated from the others
+#[derive(Default)]
+struct Namespace {
+    key_packages: HashMap<String, (KeyPackage, DateTime<Utc>)>, // With when each was published
+    inboxes: HashMap<String, Vec<Envelope>>, // Queued for users not currently subscribed
+    history: HashMap<String, Vec<AddressedEnvelope>>, // Every accepted message per group, in sequence order
+    subscribers: HashMap<String, Vec<Sender<Envelope>>>,
+    last_seen: HashMap<String, DateTime<Utc>>, // Last call each user made on their own behalf
+    accounts: HashMap<String, String>,           // Registered users and their tokens
+    recent_calls: HashMap<(Limited, String), VecDeque<DateTime<Utc>>>, // Rate-limited calls per identity within RATE_WINDOW
+    homes: HashMap<String, String>, // Users homed on a peer server, by peer name
+}
+
+impl DeliveryServer {
+    /// Create a server with empty state
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Host a namespace next to the default one, administered with `admin_token`
+    pub fn with_namespace(mut self, name: impl Into<String>, admin_token: impl Into<String>) -> Result<Self> {
+        let name = name.into();
+        if name.trim().is_empty() {
+            return Err(anyhow!("Namespace names cannot be empty"));
+        }
+        if Arc::make_mut(&mut self.namespaces).insert(name.clone(), admin_token.into()).is_some() {
+            return Err(anyhow!("Namespace '{}' given twice", name));
+        }
+        Ok(self)
+    }
+
+    /// Accept admin calls to the default namespace with `admin_token`
+    pub fn with_admin_token(mut self, admin_token: impl Into<String>) -> Self {
+        self.admin_token = Some(Arc::new(admin_token.into()));
+        self
+    }
+
+    /// Federate with other delivery services as `name`
+    pub fn with_federation(mut self, name: impl Into<String>) -> Self {
+        let peers = self.federation.as_ref().map(|federation| federation.peers.clone()).unwrap_or_default();
+        self.federation = Some(Arc::new(Federation { name: name.into(), peers }));
+        self
+    }
+
+    /// Forward messages for users homed on the peer `name` at `addr`, signing envelopes both ways with `secret`
+    ///
+    /// The peer must be given the same secret for this server, and no other peer should know it.
+    pub fn with_peer(mut self, name: impl Into<String>, addr: impl Into<String>, secret: impl Into<String>) -> Result<Self> {
+        let name = name.into();
+        let secret = secret.into();
+        if secret.is_empty() {
+            return Err(anyhow!("Peer '{}' needs a secret", name));
+        }
+        let federation = self.federation.as_mut()
+            .ok_or_else(|| anyhow!("Set up federation before adding peers"))?;
+        if Arc::make_mut(federation).peers.insert(name.clone(), Peer { addr: addr.into(), secret }).is_some() {
+            return Err(anyhow!("Peer '{}' given twice", name));
+        }
+        Ok(self)
+    }
+
+    /// Enforce rate and size limits on every namespace
+    pub fn with_limits(mut self, limits: RateLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Only serve calls on behalf of users who have registered
+    pub fn require_auth(mut self) -> Self {
+        self.require_auth = true;
+        self
+    }
+
+    /// POST `{"groupId", "recipient"}` to `url` whenever a message is queued for an offline user
+    ///
+    /// The payload is never included, so notifiers such as ntfy or Gotify
+    /// learn only that something is waiting.
+    pub fn with_webhook(mut self, url: impl Into<String>) -> Result<Self> {
+        let url = url.into();
+        if !url.starts_with("http://") {
+            return Err(anyhow!("Webhook URL must start with http:// (TLS is not built in)"));
+        }
+        self.webhook = Some(Arc::new(url));
+        Ok(self)
+    }
+
+    /// Accept connections forever, handling each on its own thread
+    pub fn serve(&self, listener: TcpListener) -> Result<()> {
+        println!("{}", format!("Delivery service listening on {}", listener.local_addr()?).green());
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(err) => {
+                    eprintln!("⚠️  Failed to accept connection: {}", err);
+                    continue;
+                }
+            };
+            let server = self.clone();
+            thread::spawn(move || {
+                let peer = stream
+                    .peer_addr()
+                    .map(|addr| addr.to_string())
+                    .unwrap_or_else(|_| "unknown peer".to_string());
+                if let Err(err) = server.handle_connection(stream) {
+                    eprintln!("⚠️  Connection from {} closed: {}", peer, err);
+                }
+            });
+        }
+        Ok(())
+    }
+
+    /// Answer `GET /metrics` scrapes forever, one connection at a time
+    pub fn serve_metrics(&self, listener: TcpListener) -> Result<()> {
+        println!("{}", format!("Metrics at http://{}/metrics", listener.local_addr()?).green());
+        for stream in listener.incoming() {
+            let result = stream.map_err(anyhow::Error::from).and_then(|stream| {
+                stream.set_read_timeout(Some(METRICS_READ_TIMEOUT))?;
+                let mut reader = BufReader::new(stream.try_clone()?);
+                let response = match HttpRequest::read(&mut reader) {
+                    Ok(Some(request)) if request.method == "GET" && request.path == "/metrics" => {
+                        let body = self.metrics().render(&self.gauges());
+                        HttpResponse::text(200, "text/plain; version=0.0.4", body)
+                    }
+                    Ok(Some(request)) => HttpResponse::error(404, format!("No route for {}", request.path)),
+                    Ok(None) => return Ok(()),
+                    Err(err) => HttpResponse::error(400, err.to_string()),
+                };
+                response.write_to(&mut &stream)
+            });
+            if let Err(err) = result {
+                eprintln!("⚠️  Metrics request failed: {}", err);
+            }
+        }
+        Ok(())
+    }
+
+    fn state(&self) -> MutexGuard<'_, ServerState> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn metrics(&self) -> MutexGuard<'_, Metrics> {
+        self.metrics.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Queue depths and user counts for every namespace
+    fn gauges(&self) -> BTreeMap<String, NamespaceGauges> {
+        let mut state = self.state();
+        std::iter::once("").chain(self.namespaces.keys().map(String::as_str))
+            .map(|name| {
+                let space = state.namespace(name);
+                let gauges = NamespaceGauges {
+                    queued_envelopes: space.inboxes.values().map(Vec::len).sum(),
+                    users: space.users().len(),
+                };
+                (name.to_string(), gauges)
+            })
+            .collect()
+    }
+
+    /// Serve requests on one connection until it closes or becomes a subscription
+    ///
+    /// A request line longer than a largest payload, base64-encoded, can be
+    /// is refused and the connection dropped, before the rest is read.
+    fn handle_connection(&self, stream: TcpStream) -> Result<()> {
+        let peer = stream.peer_addr().map(|addr| addr.ip().to_string()).unwrap_or_default();
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut writer = stream;
+        let mut line = String::new();
+        let max_line = self.limits.max_message_bytes.saturating_mul(2).saturating_add(LINE_HEADROOM);
+
+        loop {
+            line.clear();
+            if (&mut reader).take(max_line as u64 + 1).read_line(&mut line)? == 0 {
+                return Ok(());
+            }
+            if line.len() > max_line {
+                let message = format!("Request exceeds the {} byte line limit", max_line);
+                write_reply(&mut writer, &Reply::Error(RpcError::new(StatusCode::InvalidArgument, message.clone())))?;
+                return Err(anyhow!(message));
+            }
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let reply = match serde_json::from_str::<Call>(&line) {
+                Ok(call) => match self.authorize(&call).and_then(|()| self.check_limits(&call, &peer)) {
+                    Err(err) => Reply::Error(err),
+                    Ok(()) => match call.request {
+                        Request::Subscribe(request) => {
+                            return self.stream_envelopes(&call.namespace, &request.user, writer);
+                        }
+                        request => {
+                            let started = time::Instant::now();
+                            let method = request.method();
+                            let result = self.handle_request(&call.namespace, request);
+                            self.metrics().observe(&call.namespace, method, started.elapsed());
+                            match result {
+                                Ok(result) => Reply::Result(result),
+                                Err(err) => Reply::Error(err),
+                            }
+                        }
+                    },
+                },
+                Err(err) => Reply::Error(RpcError::new(
+                    StatusCode::InvalidArgument,
+                    format!("Malformed request: {}", err),
+                )),
+            };
+            write_reply(&mut writer, &reply)?;
+        }
+    }
+
+    /// Check a call's namespace and token, and note that the calling user was seen
+    ///
+    /// Admin calls need the namespace's admin token. Registered users must
+    /// present their token; unregistered users are only let through when auth
+    /// is not required.
+    fn authorize(&self, call: &Call) -> Result<(), RpcError> {
+        let admin_token = match call.namespace.as_str() {
+            "" => self.admin_token.as_deref(),
+            namespace => Some(self.namespaces.get(namespace).ok_or_else(|| {
+                RpcError::new(StatusCode::NotFound, format!("Unknown namespace '{}'", namespace))
+            })?),
+        };
+        if call.request.is_admin() {
+            let Some(admin_token) = admin_token else {
+                return Err(RpcError::new(
+                    StatusCode::PermissionDenied,
+                    "Admin calls are disabled; start the server with --admin-token",
+                ));
+            };
+            let presented = call.token.as_deref().unwrap_or_default();
+            if !crypto::constant_eq(presented.as_bytes(), admin_token.as_bytes()) {
+                return Err(RpcError::new(StatusCode::Unauthenticated, "Missing or invalid admin token"));
+            }
+            return Ok(());
+        }
+        let Some(user) = call.request.caller() else { return Ok(()) };
+        let mut state = self.state();
+        let space = state.namespace(&call.namespace);
+        match space.accounts.get(user) {
+            Some(token) => {
+                let presented = call.token.as_deref().unwrap_or_default();
+                if !crypto::constant_eq(presented.as_bytes(), token.as_bytes()) {
+                    return Err(RpcError::new(
+                        StatusCode::Unauthenticated,
+                        format!("Missing or invalid token for '{}'", user),
+                    ));
+                }
+            }
+            None if self.require_auth => {
+                return Err(RpcError::new(
+                    StatusCode::Unauthenticated,
+                    format!("'{}' is not registered; run `mls-chat login <server>` first", user),
+                ));
+            }
+            None => {}
+        }
+        space.last_seen.insert(user.to_string(), Utc::now());
+        Ok(())
+    }
+
+    /// Reject oversized messages, and count rate-limited calls against their identity
+    ///
+    /// Messages count against their sender. Key package fetches count against
+    /// the account whose token they carry, or else the caller's IP address.
+    fn check_limits(&self, call: &Call, peer: &str) -> Result<(), RpcError> {
+        let limits = self.limits;
+        let mut state = self.state();
+        let space = state.namespace(&call.namespace);
+        let result = match &call.request {
+            Request::SendMessage(request) if request.payload.len() > limits.max_message_bytes => Err(RpcError::new(
+                StatusCode::ResourceExhausted,
+                format!(
+                    "Message of {} bytes exceeds the {} byte limit",
+                    request.payload.len(),
+                    limits.max_message_bytes
+                ),
+            )),
+            Request::SendMessage(request) => {
+                space.count_call(Limited::Messages, &request.sender, limits.messages_per_minute)
+            }
+            Request::Forward(request) if request.envelope.envelope.payload.len() > limits.max_message_bytes => {
+                Err(RpcError::new(
+                    StatusCode::ResourceExhausted,
+                    format!("Forwarded message exceeds the {} byte limit", limits.max_message_bytes),
+                ))
+            }
+            Request::FetchKeyPackage(_) => {
+                let requester = call.token.as_deref()
+                    .and_then(|token| space.account_for(token))
+                    .unwrap_or(peer)
+                    .to_string();
+                space.count_call(Limited::KeyPackageFetches, &requester, limits.key_package_fetches_per_minute)
+            }
+            _ => Ok(()),
+        };
+        if let Err(err) = &result {
+            println!("🚦 {}{}", tag(&call.namespace), err.message);
+        }
+        result
+    }
+
+    /// Dispatch a unary RPC within a namespace
+    fn handle_request(&self, namespace: &str, request: Request) -> Result<serde_json::Value, RpcError> {
+        let tag = tag(namespace);
+        match request {
+            Request::Register(request) => {
+                require_non_empty("user", &request.user)?;
+                let mut state = self.state();
+                let space = state.namespace(namespace);
+                if space.accounts.contains_key(&request.user) {
+                    return Err(RpcError::new(
+                        StatusCode::AlreadyExists,
+                        format!("'{}' is already registered", request.user),
+                    ));
+                }
+                let token = new_token();
+                space.accounts.insert(request.user.clone(), token.clone());
+                println!("🪪 {}'{}' registered", tag, request.user);
+                to_result(RegisterResponse { token })
+            }
+            Request::PublishKeyPackage(request) => {
+                let key_package = request.key_package;
+                require_non_empty("user", &key_package.user)?;
+                println!("🔑 {}Key package published for '{}'", tag, key_package.user);
+                self.state().namespace(namespace).key_packages
+                    .insert(key_package.user.clone(), (key_package, Utc::now()));
+                to_result(PublishKeyPackageResponse {})
+            }
+            Request::FetchKeyPackage(request) => {
+                let key_package = self.state().namespace(namespace).key_packages.get(&request.user)
+                    .map(|(key_package, _)| key_package.clone());
+                let key_package = match key_package {
+                    None if !request.local_only => self.fetch_remote_key_package(namespace, &request.user),
+                    key_package => key_package,
+                };
+                match key_package {
+                    Some(key_package) => {
+                        self.metrics().key_package_served(namespace);
+                        to_result(FetchKeyPackageResponse { key_package })
+                    }
+                    None => Err(RpcError::new(
+                        StatusCode::NotFound,
+                        format!("No key package published for '{}'", request.user),
+                    )),
+                }
+            }
+            Request::SendMessage(request) => to_result(self.accept_message(namespace, request)?),
+            Request::FetchInbox(request) => {
+                let envelopes = self.state().namespace(namespace).inboxes.remove(&request.user).unwrap_or_default();
+                to_result(FetchInboxResponse { envelopes })
+            }
+            Request::FetchRange(request) => to_result(self.fetch_range(namespace, &request)),
+            Request::Heartbeat(request) => {
+                require_non_empty("user", &request.user)?;
+                to_result(HeartbeatResponse {})
+            }
+            Request::FetchPresence(request) => {
+                let mut state = self.state();
+                let space = state.namespace(namespace);
+                let presence = request.users.into_iter()
+                    .map(|user| space.presence(user))
+                    .collect();
+                to_result(FetchPresenceResponse { presence })
+            }
+            Request::Subscribe(_) => Err(RpcError::new(
+                StatusCode::InvalidArgument,
+                "Subscribe is a streaming call",
+            )),
+            Request::Forward(request) => to_result(self.accept_forward(namespace, request)?),
+            Request::ListUsers(_)
+            | Request::ListGroups(_)
+            | Request::PurgeKeyPackages(_)
+            | Request::RevokeToken(_)
+            | Request::QueueDepths(_) => self.handle_admin(namespace, request),
+        }
+    }
+
+    /// Dispatch an operator call, already checked against the admin token
+    fn handle_admin(&self, namespace: &str, request: Request) -> Result<serde_json::Value, RpcError> {
+        let tag = tag(namespace);
+        let mut state = self.state();
+        let space = state.namespace(namespace);
+        match request {
+            Request::ListUsers(_) => {
+                let users = space.users().into_iter()
+                    .map(|user| {
+                        let presence = space.presence(user.clone());
+                        UserSummary {
+                            registered: space.accounts.contains_key(user),
+                            key_package_published_at: space.key_packages.get(user).map(|(_, at)| *at),
+                            last_seen: presence.last_seen,
+                            online: presence.online,
+                            user: presence.user,
+                        }
+                    })
+                    .collect();
+                to_result(ListUsersResponse { users })
+            }
+            Request::ListGroups(_) => {
+                let mut groups: Vec<GroupSummary> = space.history.iter()
+                    .filter_map(|(group_id, history)| {
+                        let last = history.last()?;
+                        let members: BTreeSet<&String> = history.iter()
+                            .flat_map(|message| message.recipients.iter().chain([&message.envelope.sender]))
+                            .collect();
+                        Some(GroupSummary {
+                            group_id: group_id.clone(),
+                            messages: history.len() as u64,
+                            members: members.into_iter().cloned().collect(),
+                            last_message_at: last.envelope.received_at,
+                        })
+                    })
+                    .collect();
+                groups.sort_by_key(|group| std::cmp::Reverse(group.last_message_at));
+                to_result(ListGroupsResponse { groups })
+            }
+            Request::PurgeKeyPackages(request) => {
+                let seconds = i64::try_from(request.older_than_seconds).unwrap_or(i64::MAX);
+                let cutoff = Utc::now() - Duration::try_seconds(seconds).unwrap_or(Duration::MAX);
+                let mut users: Vec<String> = space.key_packages.iter()
+                    .filter(|(_, (_, published_at))| *published_at < cutoff)
+                    .map(|(user, _)| user.clone())
+                    .collect();
+                users.sort();
+                for user in &users {
+                    space.key_packages.remove(user);
+                }
+                println!("🧹 {}Purged {} stale key package(s)", tag, users.len());
+                to_result(PurgeKeyPackagesResponse { users })
+            }
+            Request::RevokeToken(request) => {
+                if space.accounts.remove(&request.user).is_none() {
+                    return Err(RpcError::new(
+                        StatusCode::NotFound,
+                        format!("'{}' is not registered", request.user),
+                    ));
+                }
+                // Dropping the senders ends the user's live subscriptions
+                space.subscribers.remove(&request.user);
+                println!("🚫 {}Token for '{}' revoked", tag, request.user);
+                to_result(RevokeTokenResponse {})
+            }
+            Request::QueueDepths(_) => {
+                let queues: BTreeMap<&String, usize> = space.inboxes.iter()
+                    .filter(|(_, inbox)| !inbox.is_empty())
+                    .map(|(user, inbox)| (user, inbox.len()))
+                    .collect();
+                let queues = queues.into_iter()
+                    .map(|(user, depth)| QueueDepth { user: user.clone(), depth: depth as u32 })
+                    .collect();
+                to_result(QueueDepthsResponse { queues })
+            }
+            _ => Err(RpcError::new(StatusCode::InvalidArgument, "Not an admin call")),
+        }
+    }
+
+    /// Sequence a message within its group and fan it out to each recipient
+    fn accept_message(&self, namespace: &str, request: SendMessageRequest) -> Result<SendMessageResponse, RpcError> {
+        require_non_empty("sender", &request.sender)?;
+        require_non_empty("groupId", &request.group_id)?;
+        if request.recipients.is_empty() {
+            return Err(RpcError::new(StatusCode::InvalidArgument, "No recipients"));
+        }
+
+        let message = self.relay(namespace, AddressedEnvelope::new(request), false);
+        println!(
+            "📨 {}{} → {} (#{}, {} bytes)",
+            tag(namespace),
+            message.envelope.sender,
+            message.recipients.join(", "),
+            message.envelope.seq,
+            message.envelope.payload.len()
+        );
+        Ok(SendMessageResponse {
+            message_id: message.envelope.message_id,
+            seq: message.envelope.seq,
+        })
+    }
+
+    /// Check a peer's signed envelope and deliver it to its recipients here
+    ///
+    /// The sender is remembered as homed on the peer, so replies go back there.
+    fn accept_forward(&self, namespace: &str, request: ForwardRequest) -> Result<ForwardResponse, RpcError> {
+        let federation = self.federation.as_deref()
+            .ok_or_else(|| RpcError::new(StatusCode::PermissionDenied, "This server does not federate"))?;
+        let Some(peer) = federation.peers.get(&request.origin) else {
+            return Err(RpcError::new(
+                StatusCode::PermissionDenied,
+                format!("'{}' is not a peer of this server", request.origin),
+            ));
+        };
+        let expected = sign(&peer.secret, &request.origin, namespace, &request.envelope);
+        if !crypto::constant_eq(request.signature.as_bytes(), expected.as_bytes()) {
+            return Err(RpcError::new(
+                StatusCode::Unauthenticated,
+                format!("Bad signature on envelope from '{}'", request.origin),
+            ));
+        }
+        let envelope = &request.envelope.envelope;
+        require_non_empty("sender", &envelope.sender)?;
+        require_non_empty("groupId", &envelope.group_id)?;
+
+        {
+            let mut state = self.state();
+            let space = state.namespace(namespace);
+            let seen = space.history.get(&envelope.group_id)
+                .is_some_and(|history| history.iter().any(|message| message.envelope.message_id == envelope.message_id));
+            if seen {
+                return Ok(ForwardResponse {});
+            }
+            if space.is_local(&envelope.sender) {
+                return Err(RpcError::new(
+                    StatusCode::PermissionDenied,
+                    format!("'{}' cannot forward for '{}', who is homed on this server", request.origin, envelope.sender),
+                ));
+            }
+            space.homes.insert(envelope.sender.clone(), request.origin.clone());
+        }
+        let message = self.relay(namespace, request.envelope, true);
+        println!(
+            "🌐 {}{}@{} → {} (#{}, {} bytes)",
+            tag(namespace),
+            message.envelope.sender,
+            request.origin,
+            message.recipients.join(", "),
+            message.envelope.seq,
+            message.envelope.payload.len()
+        );
+        Ok(ForwardResponse {})
+    }
+
+    /// Number a message in its group's history here and hand it to each recipient
+    ///
+    /// Recipients homed on a peer get it forwarded there, unless it came from
+    /// a peer itself. Returns the message with its sequence number.
+    fn relay(&self, namespace: &str, mut message: AddressedEnvelope, from_peer: bool) -> AddressedEnvelope {
+        let mut state = self.state();
+        let space = state.namespace(namespace);
+        let history = space.history.entry(message.envelope.group_id.clone()).or_default();
+        message.envelope.seq = history.len() as u64 + 1;
+        history.push(message.clone());
+
+        let mut queued = Vec::new();
+        let mut remote: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for recipient in &message.recipients {
+            match space.home_of(recipient) {
+                Some(peer) if !from_peer => remote.entry(peer.to_string()).or_default().push(recipient.clone()),
+                _ => {
+                    if space.deliver(recipient, message.envelope.clone()) {
+                        queued.push(recipient);
+                    }
+                }
+            }
+        }
+        drop(state);
+        for recipient in queued {
+            self.notify(namespace, &message.envelope.group_id, recipient);
+        }
+        for (peer, recipients) in remote {
+            let envelope = message.envelope.clone();
+            self.forward(namespace, &peer, AddressedEnvelope { recipients, envelope });
+        }
+        self.metrics().message_relayed(namespace);
+        message
+    }
+
+    /// Queue a signed envelope for the peer its recipients are homed on
+    ///
+    /// Each peer has one worker thread, so envelopes arrive in the order they
+    /// were accepted here.
+    fn forward(&self, namespace: &str, peer: &str, message: AddressedEnvelope) {
+        let Some(federation) = self.federation.clone() else { return };
+        let Some(Peer { addr, secret }) = federation.peers.get(peer).cloned() else { return };
+        let request = ForwardRequest {
+            origin: federation.name.clone(),
+            signature: sign(&secret, &federation.name, namespace, &message),
+            envelope: message,
+        };
+
+        let mut forwarders = self.forwarders.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let sender = forwarders.entry(peer.to_string()).or_insert_with(|| {
+            let (sender, receiver) = mpsc::channel::<Forward>();
+            let peer = peer.to_string();
+            thread::spawn(move || {
+                for (namespace, request) in receiver {
+                    forward_with_retries(&peer, &addr, &namespace, request);
+                }
+            });
+            sender
+        });
+        let _ = sender.send((namespace.to_string(), request));
+    }
+
+    /// Ask each peer for a key package this server does not hold, remembering where it was found
+    fn fetch_remote_key_package(&self, namespace: &str, user: &str) -> Option<KeyPackage> {
+        let federation = self.federation.clone()?;
+        let mut peers: Vec<(&String, &String)> = federation.peers.iter().map(|(name, peer)| (name, &peer.addr)).collect();
+        peers.sort();
+        for (peer, addr) in peers {
+            let found = connect_peer(addr, namespace).and_then(|mut client| client.lookup_key_package(user));
+            match found {
+                Ok(key_package) => {
+                    println!("🌐 {}Key package for '{}' found on '{}'", tag(namespace), user, peer);
+                    self.state().namespace(namespace).homes.insert(user.to_string(), peer.clone());
+                    return Some(key_package);
+                }
+                Err(err) if err.downcast_ref::<RpcError>().is_some_and(|err| err.code == StatusCode::NotFound) => {}
+                Err(err) => eprintln!("⚠️  Key package lookup on '{}' failed: {:#}", peer, err),
+            }
+        }
+        None
+    }
+
+    /// Tell the webhook, if any, that `recipient` has a message waiting
+    fn notify(&self, namespace: &str, group_id: &str, recipient: &str) {
+        let Some(url) = self.webhook.clone() else { return };
+        let mut body = serde_json::json!({ "groupId": group_id, "recipient": recipient });
+        if !namespace.is_empty() {
+            body["namespace"] = namespace.into();
+        }
+        let recipient = recipient.to_string();
+        thread::spawn(move || {
+            match http::send("POST", &url, None, Some(&body), WEBHOOK_TIMEOUT, None) {
+                Ok((status, _)) if (200..300).contains(&status) => {}
+                Ok((status, _)) => eprintln!("⚠️  Webhook for '{}' returned {}", recipient, status),
+                Err(err) => eprintln!("⚠️  Webhook for '{}' failed: {:#}", recipient, err),
+            }
+        });
+    }
+
+    /// The user's envelopes in a group from `from_seq` onwards, without touching their inbox
+    fn fetch_range(&self, namespace: &str, request: &FetchRangeRequest) -> FetchRangeResponse {
+        let mut state = self.state();
+        let history = state.namespace(namespace).history.get(&request.group_id).map(Vec::as_slice).unwrap_or_default();
+        let start = (request.from_seq.max(1) - 1) as usize;
+        let envelopes = history.iter()
+            .skip(start)
+            .filter(|message| message.is_for(&request.user))
+            .map(|message| message.envelope.clone())
+            .collect();
+        FetchRangeResponse { envelopes }
+    }
+
+    /// Stream queued and future envelopes for `user` until the client goes away
+    fn stream_envelopes(&self, namespace: &str, user: &str, mut writer: TcpStream) -> Result<()> {
+        let (sender, receiver) = mpsc::channel();
+        let queued = {
+            let mut state = self.state();
+            let space = state.namespace(namespace);
+            space.subscribers.entry(user.to_string()).or_default().push(sender);
+            space.inboxes.remove(user).unwrap_or_default()
+        };
+        println!("👂 {}'{}' subscribed", tag(namespace), user);
+        self.metrics().subscriber_joined(namespace);
+
+        let mut result = Ok(());
+        for envelope in queued.into_iter().chain(receiver) {
+            let written = serde_json::to_value(&envelope)
+                .map_err(anyhow::Error::from)
+                .and_then(|value| write_reply(&mut writer, &Reply::Result(value)));
+            if let Err(err) = written {
+                // Keep the message for the next fetch instead of dropping it
+                self.state().namespace(namespace).inboxes.entry(user.to_string()).or_default().push(envelope);
+                result = Err(err);
+                break;
+            }
+        }
+        self.metrics().subscriber_left(namespace);
+        result
+    }
+}
+
+impl ServerState {
+    /// A namespace's state, created on first use
+    fn namespace(&mut self, name: &str) -> &mut Namespace {
+        self.namespaces.entry(name.to_string()).or_default()
+    }
+}
+
+impl Namespace {
+    /// Whether a user has an account or key package on this server
+    fn is_local(&self, user: &str) -> bool {
+        self.accounts.contains_key(user) || self.key_packages.contains_key(user)
+    }
+
+    /// The peer a user is homed on, if not this server
+    fn home_of(&self, user: &str) -> Option<&str> {
+        match self.is_local(user) {
+            true => None,
+            false => self.homes.get(user).map(String::as_str),
+        }
+    }
+
+    /// Everyone with an account, a key package, or a call on record
+    fn users(&self) -> BTreeSet<&String> {
+        self.accounts.keys()
+            .chain(self.key_packages.keys())
+            .chain(self.last_seen.keys())
+            .collect()
+    }
+
+    /// A user is online while subscribed or for a short while after any call
+    fn presence(&self, user: String) -> Presence {
+        let last_seen = self.last_seen.get(&user).copied();
+        let subscribed = self.subscribers.get(&user).is_some_and(|subscribers| !subscribers.is_empty());
+        let recent = last_seen.is_some_and(|seen| Utc::now() - seen < ONLINE_WINDOW);
+        Presence {
+            online: subscribed || recent,
+            user,
+            last_seen,
+        }
+    }
+
+    /// The registered user a token belongs to
+    fn account_for(&self, token: &str) -> Option<&str> {
+        self.accounts.iter()
+            .find(|(_, account_token)| crypto::constant_eq(token.as_bytes(), account_token.as_bytes()))
+            .map(|(user, _)| user.as_str())
+    }
+
+    /// Record a call against `identity`, or refuse it if `per_minute` were exceeded
+    fn count_call(&mut self, kind: Limited, identity: &str, per_minute: Option<u32>) -> Result<(), RpcError> {
+        let Some(per_minute) = per_minute else { return Ok(()) };
+        let now = Utc::now();
+        let calls = self.recent_calls.entry((kind, identity.to_string())).or_default();
+        while calls.front().is_some_and(|at| now - *at >= RATE_WINDOW) {
+            calls.pop_front();
+        }
+        if calls.len() >= per_minute as usize {
+            let retry_in = calls.front().map_or(RATE_WINDOW, |oldest| RATE_WINDOW - (now - *oldest));
+            let what = match kind {
+                Limited::Messages => "messages",
+                Limited::KeyPackageFetches => "key package fetches",
+            };
+            return Err(RpcError::new(
+                StatusCode::ResourceExhausted,
+                format!(
+                    "Rate limit of {} {} per minute reached for '{}'; try again in {}s",
+                    per_minute,
+                    what,
+                    identity,
+                    retry_in.num_seconds() + 1
+                ),
+            ));
+        }
+        calls.push_back(now);
+        Ok(())
+    }
+
+    /// Hand an envelope to every live subscriber, or queue it in the inbox if there are none
+    ///
+    /// Returns whether it was queued.
+    fn deliver(&mut self, recipient: &str, envelope: Envelope) -> bool {
+        let mut delivered = false;
+        if let Some(subscribers) = self.subscribers.get_mut(recipient) {
+            subscribers.retain(|subscriber| subscriber.send(envelope.clone()).is_ok());
+            delivered = !subscribers.is_empty();
+        }
+        if !delivered {
+            self.inboxes.entry(recipient.to_string()).or_default().push(envelope);
+        }
+        !delivered
+    }
+}
+
+/// Hex HMAC over everything a forwarded envelope asserts, bound to its origin and namespace, under the secret of one pair of peers
+///
+/// The sequence number is left out, since each server numbers messages itself.
+fn sign(secret: &str, origin: &str, namespace: &str, message: &AddressedEnvelope) -> String {
+    let envelope = &message.envelope;
+    let signed = serde_json::json!([
+        origin,
+        namespace,
+        message.recipients,
+        envelope.message_id,
+        envelope.sender,
+        envelope.group_id,
+        crypto::base64::encode(&envelope.payload),
+        envelope.received_at.to_rfc3339(),
+    ]);
+    crypto::hex(&crypto::hmac_sha256(secret.as_bytes(), signed.to_string().as_bytes()))
+}
+
+/// Connection to a peer server, in the same namespace
+fn connect_peer(addr: &str, namespace: &str) -> Result<DeliveryClient> {
+    DeliveryClient::connect(addr)?.with_namespace(namespace).with_timeout(PEER_TIMEOUT)
+}
+
+/// Deliver one envelope to a peer, retrying a few times before dropping it
+fn forward_with_retries(peer: &str, addr: &str, namespace: &str, request: ForwardRequest) {
+    for attempt in 1..=FORWARD_ATTEMPTS {
+        match connect_peer(addr, namespace).and_then(|mut client| client.forward(request.clone())) {
+            Ok(()) => return,
+            Err(err) if attempt < FORWARD_ATTEMPTS => {
+                eprintln!("⚠️  Forwarding to '{}' failed (attempt {}): {:#}", peer, attempt, err);
+                thread::sleep(time::Duration::from_secs(u64::from(attempt)));
+            }
+            Err(err) => eprintln!(
+                "⚠️  Dropped message {} for '{}': {:#}",
+                request.envelope.envelope.message_id, peer, err
+            ),
+        }
+    }
+}
+
+/// A fresh random token for accounts and namespace admins
+pub fn new_token() -> String {
+    crypto::hex(&crypto::sha256(format!("{}{}", Uuid::new_v4(), Uuid::new_v4()).as_bytes()))
+}
+
+/// Log prefix naming a namespace; empty for the default one
+fn tag(namespace: &str) -> String {
+    if namespace.is_empty() {
+        String::new()
+    } else {
+        format!("[{}] ", namespace)
+    }
+}
+
+fn write_reply(writer: &mut TcpStream, reply: &Reply) -> Result<()> {
+    let mut line = serde_json::to_string(reply)?;
+    line.push('\n');
+    writer.write_all(line.as_bytes())?;
+    writer.flush()?;
+    Ok(())
+}
+
+fn to_result<T: Serialize>(response: T) -> Result<serde_json::Value, RpcError> {
+    serde_json::to_value(response).map_err(|err| RpcError::new(StatusCode::Internal, err.to_string()))
+}
+
+fn require_non_empty(field: &str, value: &str) -> Result<(), RpcError> {
+    if value.trim().is_empty() {
+        return Err(RpcError::new(StatusCode::InvalidArgument, format!("Missing {}", field)));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::delivery::SendMessageRequest;
+
+    fn federated() -> DeliveryServer {
+        DeliveryServer::new()
+            .with_federation("orgA")
+            .with_peer("orgB", "127.0.0.1:1", "ab-secret").unwrap()
+            .with_peer("orgC", "127.0.0.1:1", "ac-secret").unwrap()
+    }
+
+    fn forward(origin: &str, secret: &str, sender: &str) -> ForwardRequest {
+        let envelope = AddressedEnvelope::new(SendMessageRequest {
+            sender: sender.to_string(),
+            group_id: "group".to_string(),
+            recipients: vec!["Alice".to_string()],
+            payload: b"opaque".to_vec(),
+        });
+        ForwardRequest { origin: origin.to_string(), signature: sign(secret, origin, "", &envelope), envelope }
+    }
+
+    #[test]
+    fn forwards_need_the_secret_of_the_peer_they_claim() {
+        let server = federated();
+        server.state().namespace("").accounts.insert("Alice".to_string(), "token".to_string());
+        server.accept_forward("", forward("orgB", "ab-secret", "Bob")).unwrap();
+        assert_eq!(server.state().namespace("").homes.get("Bob").map(String::as_str), Some("orgB"));
+
+        // orgC knows only its own secret, so it cannot pass an envelope off as orgB's
+        let err = server.accept_forward("", forward("orgB", "ac-secret", "Bob")).unwrap_err();
+        assert_eq!(err.code, StatusCode::Unauthenticated);
+        let err = server.accept_forward("", forward("orgD", "ad-secret", "Dave")).unwrap_err();
+        assert_eq!(err.code, StatusCode::PermissionDenied);
+    }
+
+    #[test]
+    fn forwards_cannot_speak_for_local_users() {
+        let server = federated();
+        server.state().namespace("").accounts.insert("Alice".to_string(), "token".to_string());
+        let err = server.accept_forward("", forward("orgB", "ab-secret", "Alice")).unwrap_err();
+        assert_eq!(err.code, StatusCode::PermissionDenied);
+        assert!(!server.state().namespace("").homes.contains_key("Alice"));
+    }
+
+    #[test]
+    fn peers_need_a_secret() {
+        assert!(DeliveryServer::new().with_federation("orgA").with_peer("orgB", "127.0.0.1:1", "").is_err());
+        assert!(DeliveryServer::new().with_peer("orgB", "127.0.0.1:1", "ab-secret").is_err());
+    }
+
+    #[test]
+    fn delivers_to_every_subscriber() {
+        let envelope = forward("orgB", "ab-secret", "Bob").envelope.envelope;
+        let mut space = Namespace::default();
+        let (first, first_stream) = mpsc::channel();
+        let (second, second_stream) = mpsc::channel();
+        space.subscribers.insert("Alice".to_string(), vec![first, second]);
+        assert!(!space.deliver("Alice", envelope.clone()));
+        assert!(first_stream.try_recv().is_ok());
+        assert!(second_stream.try_recv().is_ok());
+
+        // A closed stream is dropped, and the envelope still reaches the other
+        drop(first_stream);
+        assert!(!space.deliver("Alice", envelope.clone()));
+        assert!(second_stream.try_recv().is_ok());
+        assert_eq!(space.subscribers["Alice"].len(), 1);
+
+        drop(second_stream);
+        assert!(space.deliver("Alice", envelope));
+        assert_eq!(space.inboxes["Alice"].len(), 1);
+    }
+}
@@ -0,0 +1,473 @@
+//! Passphrase protection for identity keys, with sessions that lock when idle
+//!
+//! `identity protect` seals every identity's private key under a key derived
+//! from a passphrase, so `identities/` no longer holds them in the clear.
+//! From then on each command needs that key to open the profile. Entering
+//! the passphrase, with `unlock` or when a command asks for it, opens a
+//! session: the derived key is cached in `session.json` with the time it was
+//! last used, and every command that uses it moves that time forward, much as
+//! ssh-agent and gpg-agent keep a key. Once the profile has gone unused for
+//! `session.idle-timeout-minutes` the session is wiped and the passphrase is
+//! asked for again; `lock` wipes it at once.
+//!
+//! The key is PBKDF2 of the passphrase under a salt kept with the sealed keys,
+//! so guessing passphrases for a copied profile costs `STORE_ITERATIONS`
+//! HMACs a guess. While a session is open its key is on disk, in a file only
+//! its owner can read, but readable by anyone who gets a copy of the
+//! profile, so the idle timeout bounds how long a copied profile stays
+//! usable without the passphrase; with `agent` running, the key stays in
+//! the agent's memory instead.
+//!
+//! Wrong passphrases are counted in the identity files, next to the sealed
+//! keys they guard, so the count cannot be reset without touching them. A
+//! protected profile whose count is missing or damaged is taken to be past
+//! `FREE_FAILURES`, so deleting it buys a guesser nothing but the wait.
+//! Group secrets and message logs are not
+//! sealed, which is also why there is no duress passphrase opening a decoy
+//! profile: the real groups would still be on disk for anyone to read.
+
+use anyhow::{anyhow, Context, Result};
+use chrono::{TimeDelta, Utc};
+use colored::*;
+use std::collections::HashMap;
+
+use crate::agent::AgentClient;
+use crate::app::MlsChatApp;
+use crate::crypto;
+use crate::i18n::tr;
+use crate::model::{MockKey, Session, StoreKdf, UnlockAttempts};
+use crate::prompt;
+use crate::reset::ResetScope;
+
+/// How long an unlocked profile stays unlocked unused, unless `session.idle-timeout-minutes` says otherwise
+pub const DEFAULT_IDLE_TIMEOUT: TimeDelta = TimeDelta::minutes(15);
+
+/// Wrong passphrases allowed in a row before further tries must wait
+const FREE_FAILURES: u32 = 3;
+
+/// The wait after the first failure past `FREE_FAILURES`; each failure after doubles it
+const FIRST_DELAY: TimeDelta = TimeDelta::seconds(5);
+
+/// The longest wait between tries
+const MAX_DELAY: TimeDelta = TimeDelta::hours(1);
+
+/// How long the next try must wait after `failures` wrong passphrases in a row
+fn unlock_delay(failures: u32) -> TimeDelta {
+    if failures < FREE_FAILURES {
+        return TimeDelta::zero();
+    }
+    let doublings = (failures - FREE_FAILURES).min(10);
+    (FIRST_DELAY * 2i32.pow(doublings)).min(MAX_DELAY)
+}
+
+/// PBKDF2 iterations for new store keys; each profile keeps its own count, so this can be raised later
+pub(crate) const STORE_ITERATIONS: u32 = 600_000;
+
+/// Key sealing identity keys, derived from the profile's passphrase with its salt
+///
+/// Profiles sealed before keys were salted have no `StoreKdf`; their key is
+/// one HMAC of the passphrase, and `unlock_with_passphrase` re-seals them.
+fn store_key(passphrase: &str, kdf: Option<&StoreKdf>) -> Result<[u8; 32]> {
+    Ok(match kdf {
+        Some(kdf) => crypto::pbkdf2_sha256(passphrase.as_bytes(), &crypto::base64::decode(&kdf.salt)?, kdf.iterations),
+        None => crypto::derive_key(passphrase, "mls-chat identity store"),
+    })
+}
+
+/// A fresh salt, with the current iteration count
+pub(crate) fn new_kdf() -> StoreKdf {
+    StoreKdf { salt: crypto::base64::encode(&crypto::salt()), iterations: STORE_ITERATIONS }
+}
+
+/// What seals and opens identity keys once the profile is unlocked
+#[derive(Debug, Clone)]
+pub(crate) enum StoreKey {
+    Local([u8; 32]),    // From the passphrase entered here or the session cache
+    Agent(AgentClient), // Held by the running `agent`
+}
+
+impl StoreKey {
+    fn open(&self, sealed: &str) -> Result<String> {
+        match self {
+            StoreKey::Local(key) => {
+                let sealed = crypto::base64::decode(sealed)?;
+                String::from_utf8(crypto::open(key, &sealed)?).context("Malformed private key")
+            }
+            StoreKey::Agent(agent) => agent.open(sealed),
+        }
+    }
+
+    fn seal(&self, plaintext: &str) -> Result<String> {
+        match self {
+            StoreKey::Local(key) => Ok(crypto::base64::encode(&crypto::seal(key, plaintext.as_bytes()))),
+            StoreKey::Agent(agent) => agent.seal(plaintext),
+        }
+    }
+}
+
+impl MlsChatApp {
+    /// Ask for the passphrase again once the profile has gone unused for `timeout`
+    pub fn set_idle_timeout(&mut self, timeout: TimeDelta) {
+        self.idle_timeout = timeout;
+    }
+
+    /// True if the identity keys are sealed under a passphrase
+    pub fn is_protected(&self) -> bool {
+        self.user_keys.values().any(|key| key.sealed_private_key.is_some())
+    }
+
+    /// Wipe the profile's keys and groups once this many wrong passphrases are entered in a row
+    pub fn set_wipe_after_failures(&mut self, limit: Option<u32>) {
+        self.wipe_after_failures = limit;
+    }
+
+    /// Ask the agent on `agent`'s socket to open identity keys, whenever it is running
+    pub fn set_agent(&mut self, agent: AgentClient) {
+        self.agent = Some(agent);
+    }
+
+    /// Open sealed identity keys through the agent or the session's key, or with the passphrase
+    ///
+    /// `passphrase` is used when given; otherwise it is read from stdin.
+    /// Profiles without a passphrase need nothing.
+    pub fn unlock_identities(&mut self, passphrase: Option<&str>) -> Result<()> {
+        if !self.is_protected() || self.store_key.is_some() {
+            return Ok(());
+        }
+        if let Some(agent) = self.agent.clone().filter(AgentClient::is_running) {
+            let key = StoreKey::Agent(agent);
+            if self.open_keys(&key).is_ok() {
+                self.store_key = Some(key);
+                return Ok(());
+            }
+            println!("{}", tr("session-agent-mismatch", &[]));
+        }
+        if let Some(session) = self.storage.load_session()? {
+            let idle = Utc::now() - session.last_used;
+            let key = crypto::base64::decode(&session.key).ok().and_then(|key| <[u8; 32]>::try_from(key).ok());
+            match key {
+                Some(key) if idle <= self.idle_timeout && self.open_keys(&StoreKey::Local(key)).is_ok() => return self.start_session(key),
+                _ if idle > self.idle_timeout => println!("{}", tr("session-expired", &[("minutes", &self.idle_timeout.num_minutes())])),
+                _ => {}
+            }
+            self.storage.delete_session()?;
+        }
+        let key = self.unlock_with_passphrase(passphrase)?;
+        self.start_session(key)
+    }
+
+    /// Open sealed identity keys with `passphrase`, or one read from stdin; returns the store key
+    ///
+    /// After `FREE_FAILURES` wrong passphrases in a row, each try must wait
+    /// twice as long as the one before, and `wipe_after_failures` wrong ones
+    /// wipe the profile.
+    pub(crate) fn unlock_with_passphrase(&mut self, passphrase: Option<&str>) -> Result<[u8; 32]> {
+        let mut attempts = match self.unlock_attempts() {
+            Some(attempts) => attempts,
+            None => {
+                let attempts = UnlockAttempts { failures: FREE_FAILURES, last_failure: Some(Utc::now()) };
+                println!("{}", tr("session-count-missing", &[("failures", &attempts.failures)]));
+                self.save_unlock_attempts(attempts)?;
+                attempts
+            }
+        };
+        if let Some(last_failure) = attempts.last_failure {
+            let wait = last_failure + unlock_delay(attempts.failures) - Utc::now();
+            if wait > TimeDelta::zero() {
+                return Err(anyhow!("{} wrong passphrases in a row; try again in {} second(s)", attempts.failures, wait.num_seconds() + 1));
+            }
+        }
+        let passphrase = match passphrase {
+            Some(passphrase) => passphrase.to_string(),
+            None => prompt::passphrase("Passphrase").context("This profile is locked; run `unlock` to enter its passphrase")?,
+        };
+        let kdf = self.store_kdf();
+        let key = store_key(&passphrase, kdf.as_ref())?;
+        if self.open_keys(&StoreKey::Local(key)).is_err() {
+            attempts.failures += 1;
+            attempts.last_failure = Some(Utc::now());
+            self.save_unlock_attempts(attempts)?;
+            if self.wipe_after_failures.is_some_and(|limit| attempts.failures >= limit) {
+                println!("{}", tr("session-wiping", &[("failures", &attempts.failures)]).red().bold());
+                self.reset(&ResetScope::All { keep_identity: false })?;
+                return Err(anyhow!("Wrong passphrase; the profile was wiped after {} wrong passphrases", attempts.failures));
+            }
+            let wait = unlock_delay(attempts.failures);
+            return Err(match wait > TimeDelta::zero() {
+                true => anyhow!("Wrong passphrase; {} in a row, so the next try must wait {} second(s)", attempts.failures, wait.num_seconds()),
+                false => anyhow!("Wrong passphrase"),
+            });
+        }
+        if attempts.failures > 0 {
+            println!("{}", tr("session-failures", &[("failures", &attempts.failures)]));
+        }
+        if attempts != UnlockAttempts::default() {
+            self.save_unlock_attempts(UnlockAttempts::default())?;
+        }
+        if kdf.is_none() {
+            return self.reseal(&passphrase);
+        }
+        Ok(key)
+    }
+
+    /// Seal the identity keys again under `passphrase` with a fresh salt; returns the new store key
+    ///
+    /// Upgrades profiles sealed under the old unsalted key. The private keys must be open.
+    fn reseal(&mut self, passphrase: &str) -> Result<[u8; 32]> {
+        let kdf = new_kdf();
+        let key = store_key(passphrase, Some(&kdf))?;
+        for identity in self.user_keys.values_mut() {
+            identity.store_kdf = Some(kdf.clone());
+        }
+        self.store_key = Some(StoreKey::Local(key));
+        self.identity_dirty = true;
+        self.save_state()?;
+        println!("{}", tr("session-resealed", &[("iterations", &kdf.iterations)]));
+        Ok(key)
+    }
+
+    /// Wrong passphrases in a row, as the sealed identities record them; None if any of them has lost its count
+    fn unlock_attempts(&self) -> Option<UnlockAttempts> {
+        let mut counts = self.user_keys.values()
+            .filter(|identity| identity.sealed_private_key.is_some())
+            .map(|identity| identity.unlock_attempts);
+        let first = counts.next()??;
+        counts.try_fold(first, |most, count| count.map(|count| match count.failures > most.failures {
+            true => count,
+            false => most,
+        }))
+    }
+
+    /// Record wrong passphrases in every sealed identity and write them, whether or not the profile is unlocked
+    fn save_unlock_attempts(&mut self, attempts: UnlockAttempts) -> Result<()> {
+        for identity in self.user_keys.values_mut().filter(|identity| identity.sealed_private_key.is_some()) {
+            identity.unlock_attempts = Some(attempts);
+        }
+        let keys = self.keys_to_save()?;
+        self.storage.save_user_keys(&keys)
+    }
+
+    /// How the profile's store key is derived; every sealed identity shares it, and None means unsalted or unsealed
+    fn store_kdf(&self) -> Option<StoreKdf> {
+        self.user_keys.values().find_map(|identity| identity.store_kdf.clone())
+    }
+
+    /// Seal every identity's private key under `passphrase`, replacing any passphrase set before
+    ///
+    /// Without `passphrase` the new one is read from stdin twice, to catch typing mistakes.
+    pub fn protect_identities(&mut self, passphrase: Option<&str>) -> Result<()> {
+        if self.user_keys.is_empty() {
+            return Err(anyhow!("No identities to protect; run `init` first"));
+        }
+        let passphrase = match passphrase {
+            Some(passphrase) => passphrase.to_string(),
+            None => prompt::new_passphrase("New passphrase")?,
+        };
+        let passphrase = passphrase.as_str();
+        if passphrase.is_empty() {
+            return Err(anyhow!("The passphrase must not be empty"));
+        }
+        let changed = self.is_protected();
+        let kdf = new_kdf();
+        let key = store_key(passphrase, Some(&kdf))?;
+        for identity in self.user_keys.values_mut() {
+            identity.store_kdf = Some(kdf.clone());
+            identity.unlock_attempts = Some(UnlockAttempts::default());
+        }
+        self.store_key = Some(StoreKey::Local(key));
+        self.identity_dirty = true;
+        self.save_state()?;
+        self.start_session(key)?;
+        match changed {
+            true => println!("{}", tr("protect-changed", &[]).green()),
+            false => println!("{}", tr("protect-done", &[]).green()),
+        }
+        println!("   {}", tr("protect-unlocked", &[("minutes", &self.idle_timeout.num_minutes())]));
+        Ok(())
+    }
+
+    /// Write the identity keys in the clear again and end the session
+    pub fn unprotect_identities(&mut self) -> Result<()> {
+        if !self.is_protected() {
+            println!("{}", tr("unprotect-none", &[]));
+            return Ok(());
+        }
+        for key in self.user_keys.values_mut() {
+            key.sealed_private_key = None;
+            key.store_kdf = None;
+            key.unlock_attempts = None;
+        }
+        self.store_key = None;
+        self.identity_dirty = true;
+        self.save_state()?;
+        self.storage.delete_session()?;
+        println!("{}", tr("unprotect-done", &[]).green());
+        Ok(())
+    }
+
+    /// Say how long the profile stays unlocked, for `unlock`
+    pub fn show_session(&self) {
+        match &self.store_key {
+            Some(StoreKey::Agent(agent)) => println!("{}", tr("session-agent", &[("socket", &agent.path().display())])),
+            Some(StoreKey::Local(_)) => println!("{}", tr("session-unlocked", &[("minutes", &self.idle_timeout.num_minutes())])),
+            None => println!("{}", tr("session-unprotected", &[])),
+        }
+    }
+
+    /// End the session now, and stop the agent if one is running, so the next command asks for the passphrase
+    pub fn lock(&mut self) -> Result<()> {
+        self.store_key = None;
+        if let Some(agent) = self.agent.clone().filter(AgentClient::is_running) {
+            agent.lock()?;
+            println!("{}", tr("lock-agent", &[("socket", &agent.path().display())]));
+        }
+        self.storage.delete_session()?;
+        println!("{}", tr("lock-done", &[]));
+        Ok(())
+    }
+
+    /// The identity keys as they are written: sealed under the store key, if there is one
+    pub(crate) fn keys_to_save(&self) -> Result<HashMap<String, MockKey>> {
+        let kdf = self.store_kdf();
+        let attempts = self.unlock_attempts().unwrap_or_default();
+        let mut keys = HashMap::new();
+        for (user, key) in &self.user_keys {
+            let mut key = key.clone();
+            if let Some(store_key) = &self.store_key {
+                if !key.private_key.is_empty() {
+                    key.sealed_private_key = Some(store_key.seal(&key.private_key)?);
+                    key.store_kdf = kdf.clone(); // Identities created since unlocking join the others' salt
+                    key.unlock_attempts.get_or_insert(attempts);
+                }
+                key.private_key.clear();
+            }
+            keys.insert(user.clone(), key);
+        }
+        Ok(keys)
+    }
+
+    /// Fill in each sealed private key, failing without changing any if `key` does not open them all
+    fn open_keys(&mut self, key: &StoreKey) -> Result<()> {
+        let mut opened = Vec::new();
+        for (user, identity) in &self.user_keys {
+            let Some(sealed) = &identity.sealed_private_key else { continue };
+            let private_key = key.open(sealed).with_context(|| format!("Failed to open the key of '{}'", user))?;
+            opened.push((user.clone(), private_key));
+        }
+        for (user, private_key) in opened {
+            if let Some(identity) = self.user_keys.get_mut(&user) {
+                identity.private_key = private_key;
+            }
+        }
+        Ok(())
+    }
+
+    fn start_session(&mut self, key: [u8; 32]) -> Result<()> {
+        self.store_key = Some(StoreKey::Local(key));
+        self.storage.save_session(&Session { key: crypto::base64::encode(&key), last_used: Utc::now() })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::UserName;
+    use crate::storage::{MemoryStorage, Storage};
+
+    #[test]
+    fn store_key_is_pbkdf2_under_the_profile_salt() {
+        let kdf = StoreKdf { salt: crypto::base64::encode(b"0123456789abcdef"), iterations: 1000 };
+        let key = store_key("hunter2", Some(&kdf)).unwrap();
+        assert_eq!(key, crypto::pbkdf2_sha256(b"hunter2", b"0123456789abcdef", 1000));
+        assert_ne!(key, store_key("hunter3", Some(&kdf)).unwrap());
+
+        let other = StoreKdf { salt: crypto::base64::encode(b"fedcba9876543210"), ..kdf.clone() };
+        assert_ne!(key, store_key("hunter2", Some(&other)).unwrap());
+        let bad = StoreKdf { salt: "not base64!".to_string(), ..kdf };
+        assert!(store_key("hunter2", Some(&bad)).is_err());
+    }
+
+    #[test]
+    fn unlock_delay_doubles_after_the_free_failures_up_to_an_hour() {
+        assert_eq!(unlock_delay(0), TimeDelta::zero());
+        assert_eq!(unlock_delay(FREE_FAILURES - 1), TimeDelta::zero());
+        assert_eq!(unlock_delay(FREE_FAILURES), FIRST_DELAY);
+        assert_eq!(unlock_delay(FREE_FAILURES + 1), FIRST_DELAY * 2);
+        assert_eq!(unlock_delay(FREE_FAILURES + 3), FIRST_DELAY * 8);
+        assert_eq!(unlock_delay(FREE_FAILURES + 10), MAX_DELAY);
+        assert_eq!(unlock_delay(u32::MAX), MAX_DELAY);
+    }
+
+    /// A profile sealed under "hunter2" with a cheap key, holding Alice, and a copy of it as it was saved
+    fn protected() -> (MlsChatApp, MemoryStorage) {
+        let storage = MemoryStorage::new();
+        let mut app = MlsChatApp::with_storage(Box::new(storage.clone()));
+        app.init_user(UserName::Alice).unwrap();
+        let kdf = StoreKdf { salt: crypto::base64::encode(b"0123456789abcdef"), iterations: 1000 };
+        let key = store_key("hunter2", Some(&kdf)).unwrap();
+        for identity in app.user_keys.values_mut() {
+            identity.store_kdf = Some(kdf.clone());
+            identity.unlock_attempts = Some(UnlockAttempts::default());
+        }
+        app.store_key = Some(StoreKey::Local(key));
+        app.identity_dirty = true;
+        app.save_state().unwrap();
+
+        let mut locked = MlsChatApp::with_storage(Box::new(storage.clone()));
+        locked.load_state().unwrap();
+        (locked, storage)
+    }
+
+    #[test]
+    fn wrong_passphrases_are_counted_in_the_identity_files() {
+        let (mut app, storage) = protected();
+        assert!(app.unlock_with_passphrase(Some("wrong")).is_err());
+        assert!(app.unlock_with_passphrase(Some("wrong")).is_err());
+        assert_eq!(storage.load_user_keys().unwrap()["Alice"].unlock_attempts.unwrap().failures, 2);
+
+        app.unlock_with_passphrase(Some("hunter2")).unwrap();
+        assert_eq!(storage.load_user_keys().unwrap()["Alice"].unlock_attempts, Some(UnlockAttempts::default()));
+    }
+
+    #[test]
+    fn a_missing_count_is_past_the_free_failures() {
+        let (_, mut storage) = protected();
+        let mut keys = storage.load_user_keys().unwrap();
+        keys.get_mut("Alice").unwrap().unlock_attempts = None;
+        storage.save_user_keys(&keys).unwrap();
+
+        let mut app = MlsChatApp::with_storage(Box::new(storage.clone()));
+        app.load_state().unwrap();
+        let err = app.unlock_with_passphrase(Some("hunter2")).unwrap_err();
+        assert!(err.to_string().contains("try again in"), "{}", err);
+        assert_eq!(storage.load_user_keys().unwrap()["Alice"].unlock_attempts.unwrap().failures, FREE_FAILURES);
+        let damaged: MockKey = serde_json::from_str(r#"{"id":"key","public_key":"pub","unlock_attempts":"none at all"}"#).unwrap();
+        assert_eq!(damaged.unlock_attempts, None);
+    }
+
+    #[test]
+    fn enough_wrong_passphrases_wipe_the_profile() {
+        let (mut app, storage) = protected();
+        app.set_wipe_after_failures(Some(2));
+        let err = app.unlock_with_passphrase(Some("wrong")).unwrap_err();
+        assert!(!err.to_string().contains("wiped"));
+        assert!(!storage.load_user_keys().unwrap().is_empty());
+
+        let err = app.unlock_with_passphrase(Some("wrong")).unwrap_err();
+        assert!(err.to_string().contains("wiped"), "{}", err);
+        assert!(storage.load_user_keys().unwrap().is_empty());
+    }
+
+    #[test]
+    fn legacy_store_keys_are_one_hmac() {
+        assert_eq!(store_key("hunter2", None).unwrap(), crypto::derive_key("hunter2", "mls-chat identity store"));
+    }
+
+    #[test]
+    fn each_kdf_gets_its_own_salt() {
+        let (first, second) = (new_kdf(), new_kdf());
+        assert_ne!(first.salt, second.salt);
+        assert_eq!(crypto::base64::decode(&first.salt).unwrap().len(), 16);
+        assert_eq!(first.iterations, STORE_ITERATIONS);
+    }
+}
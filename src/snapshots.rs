@@ -0,0 +1,122 @@
+//! Group snapshots and rollback, a debugging and teaching tool
+//!
+//! `snapshot <group>` keeps the group's whole local state, its epoch, group
+//! secret, tree hash, members, and message log, under `snapshots/` in the
+//! data directory. `rollback <group> <snapshot-id>` puts that state back, so
+//! a lesson or a bug report can replay the same epoch again.
+//!
+//! Both undo what MLS promises. A snapshot keeps an epoch's secret after the
+//! group has moved past it, which is exactly what forward secrecy forbids,
+//! and a rollback returns this profile to an epoch the other members have
+//! left, so it can no longer read their commits or send them anything they
+//! can read. Never use either on a group that matters; `reset` wipes a
+//! group's snapshots with the group.
+
+use anyhow::{anyhow, Context, Result};
+use chrono::Utc;
+use colored::*;
+use uuid::Uuid;
+
+use crate::app::MlsChatApp;
+use crate::model::GroupSnapshot;
+use crate::pins::short_id;
+
+/// Print the banner every rollback shows
+fn warning_banner(from: u32, to: u32) {
+    let rule = "━".repeat(68);
+    println!("{}", rule.red().bold());
+    println!("{}", "⚠️  ROLLBACK: FOR DEBUGGING AND TEACHING ONLY".red().bold());
+    println!("{}", format!("   This profile goes back from epoch {} to epoch {} and its old group secret.", from, to).red());
+    println!("{}", "   That breaks forward secrecy: a discarded epoch secret is in use again.".red());
+    println!("{}", "   The other members stay where they are, so the group is out of sync".red());
+    println!("{}", "   here until you are removed and added again.".red());
+    println!("{}", rule.red().bold());
+}
+
+impl MlsChatApp {
+    /// Keep a copy of a group's whole local state, returning the snapshot's ID
+    pub fn snapshot_group(&mut self, group_name: &str) -> Result<String> {
+        let group = self.load_messages(group_name)?.clone();
+        let snapshot = GroupSnapshot {
+            id: Uuid::new_v4().to_string(),
+            at: Utc::now(),
+            messages: group.messages.clone().unwrap_or_default(),
+            group,
+        };
+        self.storage.save_snapshot(&snapshot)?;
+        println!("{}", format!("📸 Snapshot {} of '{}' at epoch {}", short_id(&snapshot.id), group_name, snapshot.group.mls_group.epoch).green());
+        println!("   ⚠️  It keeps this epoch's group secret on disk, undoing forward secrecy for it; for debugging and teaching only");
+        Ok(snapshot.id)
+    }
+
+    /// Print a group's snapshots, oldest first
+    pub fn list_snapshots(&self, group_name: &str) -> Result<()> {
+        let group = self.groups.get(group_name).context("Group not found")?;
+        let snapshots = self.storage.load_snapshots(&group.group_id)?;
+        if snapshots.is_empty() {
+            println!("No snapshots of '{}'; `snapshot {}` takes one", group_name, group_name);
+            return Ok(());
+        }
+        println!("📸 Snapshots of '{}' (now at epoch {}):", group_name, group.mls_group.epoch);
+        for snapshot in &snapshots {
+            println!(
+                "  {}  {}  epoch {}, {} member(s), {} message(s)",
+                short_id(&snapshot.id),
+                snapshot.at.format("%Y-%m-%d %H:%M:%S UTC"),
+                snapshot.group.mls_group.epoch,
+                snapshot.group.members.len(),
+                snapshot.messages.len()
+            );
+        }
+        Ok(())
+    }
+
+    /// What rolling a group back to `snapshot_id` loses, for confirming first
+    pub fn rollback_losses(&self, group_name: &str, snapshot_id: &str) -> Result<Vec<String>> {
+        let group = self.groups.get(group_name).context("Group not found")?;
+        let snapshot = self.find_snapshot(group_name, snapshot_id)?;
+        Ok(vec![
+            format!(
+                "Group '{}' at epoch {} with {} message(s) is replaced by its state at epoch {} with {} message(s)",
+                group_name,
+                group.mls_group.epoch,
+                group.message_count,
+                snapshot.group.mls_group.epoch,
+                snapshot.messages.len()
+            ),
+            "An old group secret is used again, breaking forward secrecy".to_string(),
+            "Other members are not rolled back; you cannot read or reach them until added again".to_string(),
+        ])
+    }
+
+    /// Put a group back to a snapshot, keeping its current local name
+    pub fn rollback_group(&mut self, group_name: &str, snapshot_id: &str) -> Result<()> {
+        let snapshot = self.find_snapshot(group_name, snapshot_id)?;
+        let from = self.groups.get(group_name).context("Group not found")?.mls_group.epoch;
+        warning_banner(from, snapshot.group.mls_group.epoch);
+        let mut group = snapshot.group;
+        group.name = group_name.to_string();
+        group.message_count = snapshot.messages.len();
+        self.storage.replace_messages(&group.group_id, &snapshot.messages)?;
+        group.messages = Some(snapshot.messages);
+        self.groups.insert(group_name.to_string(), group);
+        self.dirty_groups.insert(group_name.to_string());
+        self.save_state()?;
+        println!("{}", format!("⏪ Rolled '{}' back to snapshot {}", group_name, short_id(&snapshot.id)).yellow());
+        Ok(())
+    }
+
+    /// The group's snapshot whose ID starts with `id`
+    fn find_snapshot(&self, group_name: &str, id: &str) -> Result<GroupSnapshot> {
+        let group = self.groups.get(group_name).context("Group not found")?;
+        let mut matches: Vec<GroupSnapshot> = self.storage.load_snapshots(&group.group_id)?
+            .into_iter()
+            .filter(|snapshot| !id.is_empty() && snapshot.id.starts_with(id))
+            .collect();
+        match matches.len() {
+            1 => Ok(matches.remove(0)),
+            0 => Err(anyhow!("No snapshot '{}' of '{}'; `snapshots {}` shows them", id, group_name, group_name)),
+            count => Err(anyhow!("'{}' matches {} snapshots; give more of the ID", id, count)),
+        }
+    }
+}
@@ -0,0 +1,100 @@
+//! Snippets: canned messages kept in the config file's `[snippet]` section
+//!
+//! `send --snippet <name>` sends the snippet with its `{placeholders}` filled
+//! in. `{group}`, `{user}`, `{date}`, and `{time}` are always known; any
+//! other name must be given with `--var name=value`, so the same text can
+//! serve every run of a demo script:
+//!
+//! ```toml
+//! [snippet]
+//! standup = "Standup in {group} at {time}; today: {topic}"
+//! ```
+//!
+//! `{{` and `}}` stand for literal braces.
+
+use anyhow::{anyhow, Result};
+use chrono::Local;
+use std::collections::HashMap;
+
+use crate::app::MlsChatApp;
+
+/// Placeholders every snippet may use without `--var`
+pub const BUILT_IN: &[&str] = &["group", "user", "date", "time"];
+
+/// One piece of a snippet's text
+enum Part<'a> {
+    Text(&'a str),
+    Placeholder(&'a str),
+}
+
+/// The placeholder names in `text`, each once, in order of first use
+pub fn placeholders(text: &str) -> Result<Vec<&str>> {
+    let mut names = Vec::new();
+    for part in parse(text)? {
+        if let Part::Placeholder(name) = part {
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+    }
+    Ok(names)
+}
+
+/// `text` with every placeholder replaced from `values`
+pub fn expand(text: &str, values: &HashMap<String, String>) -> Result<String> {
+    let mut out = String::new();
+    for part in parse(text)? {
+        match part {
+            Part::Text(text) => out.push_str(text),
+            Part::Placeholder(name) => match values.get(name) {
+                Some(value) => out.push_str(value),
+                None => return Err(anyhow!("No value for {{{}}}; give one with --var {}=<value>", name, name)),
+            },
+        }
+    }
+    Ok(out)
+}
+
+fn parse(text: &str) -> Result<Vec<Part<'_>>> {
+    let mut parts = Vec::new();
+    let mut rest = text;
+    while let Some(at) = rest.find(['{', '}']) {
+        parts.push(Part::Text(&rest[..at]));
+        let brace = &rest[at..at + 1];
+        rest = &rest[at + 1..];
+        if let Some(after) = rest.strip_prefix(brace) {
+            parts.push(Part::Text(brace));
+            rest = after;
+            continue;
+        }
+        if brace == "}" {
+            return Err(anyhow!("Unmatched '}}' in snippet; write '}}}}' for a literal brace"));
+        }
+        let end = rest.find('}').ok_or_else(|| anyhow!("Unterminated placeholder in snippet; write '{{{{' for a literal brace"))?;
+        let name = rest[..end].trim();
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+            return Err(anyhow!("Invalid placeholder {{{}}}; use letters, digits, '-', and '_'", &rest[..end]));
+        }
+        parts.push(Part::Placeholder(name));
+        rest = &rest[end + 1..];
+    }
+    parts.push(Part::Text(rest));
+    Ok(parts)
+}
+
+impl MlsChatApp {
+    /// The snippet `text` as it would be sent to `group_name`, with `vars` given by `--var`
+    pub fn expand_snippet(&self, group_name: &str, text: &str, vars: &[(String, String)]) -> Result<String> {
+        let now = Local::now();
+        let mut values = HashMap::from([
+            ("group".to_string(), group_name.to_string()),
+            ("date".to_string(), now.format("%Y-%m-%d").to_string()),
+            ("time".to_string(), now.format("%H:%M").to_string()),
+        ]);
+        if let Some(user) = &self.current_user {
+            values.insert("user".to_string(), user.to_string());
+        }
+        values.extend(vars.iter().cloned());
+        expand(text, &values)
+    }
+}
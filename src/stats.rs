@@ -0,0 +1,141 @@
+//! Message statistics for a group
+//!
+//! Everything is counted from the messages stored in this profile, so history
+//! dropped by retention, or sent before this member joined, is not included.
+//! Days, weeks, and hours are in UTC.
+
+use anyhow::Result;
+use chrono::{Datelike, Timelike};
+use colored::*;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::app::MlsChatApp;
+use crate::model::ChatMessage;
+use crate::output;
+
+/// How many of the busiest hours are reported
+const BUSIEST_HOURS: usize = 3;
+
+/// Width of the longest bar `stats` draws
+const BAR_WIDTH: usize = 30;
+
+/// What `stats` reports for a group
+#[derive(Debug, Clone, Serialize)]
+pub struct GroupStats {
+    pub group: String,
+    pub messages: usize,
+    pub per_member: Vec<Count>,      // Most messages first
+    pub per_day: Vec<Count>,         // YYYY-MM-DD, oldest first
+    pub average_bytes: f64,          // Text length, or attachment size, per message
+    pub epochs_per_week: Vec<Count>, // ISO week (YYYY-Www) -> epochs first seen in it
+    pub busiest_hours: Vec<Count>,   // Hour of day (00-23), busiest first
+}
+
+/// How many messages, or epochs, fall under a label
+#[derive(Debug, Clone, Serialize)]
+pub struct Count {
+    pub label: String,
+    pub count: usize,
+}
+
+impl MlsChatApp {
+    /// Count a group's stored messages by member, day, epoch, and hour
+    pub fn group_stats(&mut self, group_name: &str) -> Result<GroupStats> {
+        let group = self.load_messages(group_name)?;
+        let messages = group.messages.as_deref().unwrap_or_default();
+        Ok(GroupStats {
+            group: group.name.clone(),
+            messages: messages.len(),
+            per_member: busiest(tally(messages.iter().map(|message| message.sender.clone()))),
+            per_day: in_order(tally(messages.iter().map(|message| message.timestamp.format("%Y-%m-%d").to_string()))),
+            average_bytes: match messages.len() {
+                0 => 0.0,
+                n => messages.iter().map(size).sum::<u64>() as f64 / n as f64,
+            },
+            epochs_per_week: in_order(epochs_per_week(messages)),
+            busiest_hours: busiest(tally(messages.iter().map(|message| format!("{:02}", message.timestamp.hour()))))
+                .into_iter()
+                .take(BUSIEST_HOURS)
+                .collect(),
+        })
+    }
+
+    /// Print a group's statistics with bar charts
+    pub fn show_stats(&mut self, group_name: &str) -> Result<()> {
+        let stats = self.group_stats(group_name)?;
+        println!("{}", format!("Statistics for '{}'", stats.group).blue());
+        output::rule('=', 50);
+        println!("Messages: {}", stats.messages);
+        if stats.messages == 0 {
+            return Ok(());
+        }
+        println!("Average size: {:.0} bytes", stats.average_bytes);
+        print_counts("Per member", &stats.per_member);
+        print_counts("Per day (UTC)", &stats.per_day);
+        print_counts("New epochs per week", &stats.epochs_per_week);
+        let hours: Vec<String> = stats.busiest_hours.iter()
+            .map(|hour| format!("{}:00 ({})", hour.label, hour.count))
+            .collect();
+        println!();
+        println!("Busiest hours (UTC): {}", hours.join(", "));
+        Ok(())
+    }
+}
+
+/// How big a message's content is: the file for attachments, else the text
+fn size(message: &ChatMessage) -> u64 {
+    match &message.attachment {
+        Some(attachment) => attachment.size,
+        None => message.content.len() as u64,
+    }
+}
+
+/// Each epoch counted once, in the week its first stored message was sent
+fn epochs_per_week(messages: &[ChatMessage]) -> BTreeMap<String, usize> {
+    let mut first_seen: BTreeMap<u32, &ChatMessage> = BTreeMap::new();
+    for message in messages {
+        first_seen.entry(message.epoch)
+            .and_modify(|first| if message.timestamp < first.timestamp { *first = message })
+            .or_insert(message);
+    }
+    tally(first_seen.values().map(|message| {
+        let week = message.timestamp.iso_week();
+        format!("{}-W{:02}", week.year(), week.week())
+    }))
+}
+
+fn tally(labels: impl Iterator<Item = String>) -> BTreeMap<String, usize> {
+    let mut counts = BTreeMap::new();
+    for label in labels {
+        *counts.entry(label).or_insert(0) += 1;
+    }
+    counts
+}
+
+/// Counts by label, in label order
+fn in_order(counts: BTreeMap<String, usize>) -> Vec<Count> {
+    counts.into_iter().map(|(label, count)| Count { label, count }).collect()
+}
+
+/// Counts by label, highest first, ties in label order
+fn busiest(counts: BTreeMap<String, usize>) -> Vec<Count> {
+    let mut counts = in_order(counts);
+    counts.sort_by_key(|count| std::cmp::Reverse(count.count));
+    counts
+}
+
+fn print_counts(title: &str, counts: &[Count]) {
+    println!();
+    println!("{}:", title);
+    let width = counts.iter().map(|count| count.label.chars().count()).max().unwrap_or(0);
+    let most = counts.iter().map(|count| count.count).max().unwrap_or(0);
+    for count in counts {
+        if output::is_plain() {
+            println!("   {}: {}", count.label, count.count);
+            continue;
+        }
+        let bar = (count.count * BAR_WIDTH).checked_div(most).unwrap_or(0).max(1);
+        println!("   {:<width$}  {} {}", count.label, "█".repeat(bar).green(), count.count, width = width);
+    }
+}
@@ -0,0 +1,1146 @@
+//! Storage backends for persisting application state
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    fs::{self, OpenOptions},
+    io::{BufRead, BufReader, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use crate::cache::{Item, Stamp};
+use crate::index::{IndexEntry, MessageIndex};
+use crate::model::{ChatGroup, ChatMessage, GroupSnapshot, MockKey, PeerKey, RevocationList, Session, Transfer, TreeHead, UserName};
+
+/// Persistence backend for application state
+pub trait Storage: Send {
+    /// Load the metadata of every persisted group, without messages
+    fn load_groups(&self) -> Result<Vec<ChatGroup>>;
+    /// Persist a single group's metadata, replacing its previous copy
+    fn save_group(&mut self, group: &ChatGroup) -> Result<()>;
+    /// Load all messages of a group, oldest first
+    fn load_messages(&self, group_id: &str) -> Result<Vec<ChatMessage>>;
+    /// Pass a group's messages to `visit` one at a time, oldest first, without holding the whole log
+    fn stream_messages(&self, group_id: &str, visit: &mut dyn FnMut(ChatMessage) -> Result<()>) -> Result<()>;
+    /// Stored messages, from any group, whose ID is or starts with `prefix`
+    fn find_messages(&self, prefix: &str) -> Result<Vec<ChatMessage>>;
+    /// The IDs of every group with a stored message log, whether or not the group itself is still stored
+    fn message_logs(&self) -> Result<Vec<String>>;
+    /// Append a message to its group's message log
+    fn append_message(&mut self, message: &ChatMessage) -> Result<()>;
+    /// Replace a group's whole message log
+    fn replace_messages(&mut self, group_id: &str, messages: &[ChatMessage]) -> Result<()>;
+    /// Remove a group's metadata, message log, snapshots, and transfers for good
+    fn delete_group(&mut self, group_id: &str) -> Result<()>;
+    /// Load a group's snapshots from `snapshot`, oldest first
+    fn load_snapshots(&self, group_id: &str) -> Result<Vec<GroupSnapshot>>;
+    /// Persist a new snapshot of a group
+    fn save_snapshot(&mut self, snapshot: &GroupSnapshot) -> Result<()>;
+    /// Load every unfinished chunked transfer, oldest first
+    fn load_transfers(&self) -> Result<Vec<Transfer>>;
+    /// Load the unfinished transfer of the message with ID `id`, if there is one
+    fn load_transfer(&self, id: &str) -> Result<Option<Transfer>>;
+    /// Persist a transfer, replacing its previous copy
+    fn save_transfer(&mut self, transfer: &Transfer) -> Result<()>;
+    /// Remove a finished or dropped transfer for good
+    fn delete_transfer(&mut self, id: &str) -> Result<()>;
+    /// Load all user keys
+    fn load_user_keys(&self) -> Result<HashMap<String, MockKey>>;
+    /// Persist all user keys
+    fn save_user_keys(&mut self, keys: &HashMap<String, MockKey>) -> Result<()>;
+    /// Remove every identity's keys, the active user, known peer keys, and delivery service tokens for good
+    fn delete_identities(&mut self) -> Result<()>;
+    /// Load the active user, if one was saved
+    fn load_current_user(&self) -> Result<Option<UserName>>;
+    /// Persist the active user
+    fn save_current_user(&mut self, user: UserName) -> Result<()>;
+    /// Load delivery service tokens from `login`, by server address and then user
+    fn load_server_tokens(&self) -> Result<HashMap<String, HashMap<String, String>>>;
+    /// Persist all delivery service tokens
+    fn save_server_tokens(&mut self, tokens: &HashMap<String, HashMap<String, String>>) -> Result<()>;
+    /// Load the peer keys each local identity knows, by local user and then peer
+    fn load_peer_keys(&self) -> Result<HashMap<String, HashMap<String, PeerKey>>>;
+    /// Persist all known peer keys
+    fn save_peer_keys(&mut self, keys: &HashMap<String, HashMap<String, PeerKey>>) -> Result<()>;
+    /// Load the revocation list from `revocation update`, if one was loaded
+    fn load_revocations(&self) -> Result<Option<RevocationList>>;
+    /// Persist the revocation list, replacing the previous one
+    fn save_revocations(&mut self, list: &RevocationList) -> Result<()>;
+    /// Load the transparency log tree heads last checked, by log
+    fn load_tree_heads(&self) -> Result<HashMap<String, TreeHead>>;
+    /// Persist the checked tree heads, replacing the previous ones
+    fn save_tree_heads(&mut self, heads: &HashMap<String, TreeHead>) -> Result<()>;
+    /// Load the session left by the last unlock, if one is open
+    fn load_session(&self) -> Result<Option<Session>>;
+    /// Persist the session, replacing the previous one
+    fn save_session(&mut self, session: &Session) -> Result<()>;
+    /// End the session, overwriting its key
+    fn delete_session(&mut self) -> Result<()>;
+    /// Where the state is kept, for display
+    fn location(&self) -> String;
+    /// Why data this backend overwrites or removes may still be recoverable, if it may be
+    fn erasure_caveat(&self) -> Option<String>;
+    /// A stamp that changes whenever `item` is written, or None if this backend cannot tell (see `cache`)
+    fn stamp(&self, item: Item<'_>) -> Result<Option<Stamp>>;
+}
+
+/// Where older versions kept every identity's keys
+const LEGACY_KEYS_FILE: &str = "user_keys.json";
+
+/// JSON file storage keeping each group in its own file
+///
+/// Layout:
+/// - `groups/<group_id>.json`: group metadata and MLS state
+/// - `messages/<group_id>.jsonl`: append-only message log, one JSON message per line
+/// - `index/<bucket>.jsonl`: where each message is in the logs, by ID (see `index`)
+/// - `identities/<user>.json`: mock key material, one file per local identity
+/// - `snapshots/<group_id>/<snapshot_id>.json`: group states kept by `snapshot`
+/// - `transfers/<message_id>.json`: chunked messages part way through sending or receiving (see `transfer`)
+/// - `current_user.json`: the active user
+/// - `peer_keys.json`: peer identity keys each local identity knows
+/// - `revocations.json`: the revocation list from `revocation update`
+/// - `tree_heads.json`: the last transparency log tree head checked, by log (see `transparency`)
+/// - `session.json`: the unlocked store key and when it was last used
+pub struct FileStorage {
+    data_dir: PathBuf,
+    index: MessageIndex,
+}
+
+/// Just the ID of a logged message, for indexing without parsing the rest
+#[derive(Deserialize)]
+struct MessageId {
+    id: String,
+}
+
+/// Group as written by older versions, with its messages stored inline
+#[derive(Deserialize)]
+struct InlineGroup {
+    #[serde(flatten)]
+    group: ChatGroup,
+    #[serde(default)]
+    messages: Option<Vec<ChatMessage>>,
+}
+
+impl FileStorage {
+    /// Open a data directory, creating it if needed
+    pub fn open(data_dir: impl AsRef<Path>) -> Result<Self> {
+        let data_dir = data_dir.as_ref().to_path_buf();
+        fs::create_dir_all(data_dir.join("groups")).context("Failed to create data directory")?;
+        fs::create_dir_all(data_dir.join("messages")).context("Failed to create data directory")?;
+
+        let index = MessageIndex::new(data_dir.join("index"));
+        let indexed = index.exists();
+        let storage = Self { data_dir, index };
+        storage.migrate_legacy_state()?;
+        if !indexed {
+            storage.rebuild_index()?;
+        }
+        Ok(storage)
+    }
+
+    /// Index every message in every log from scratch
+    fn rebuild_index(&self) -> Result<()> {
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(self.data_dir.join("messages"))? {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("jsonl") {
+                continue;
+            }
+            let Some(group_id) = path.file_stem().and_then(|stem| stem.to_str()) else { continue };
+            let mut log = BufReader::new(fs::File::open(&path)?);
+            let mut offset = 0;
+            let mut line = String::new();
+            loop {
+                line.clear();
+                let read = log.read_line(&mut line)?;
+                if read == 0 {
+                    break;
+                }
+                if !line.trim().is_empty() {
+                    let message: MessageId = serde_json::from_str(&line)
+                        .with_context(|| format!("Failed to parse {}", path.display()))?;
+                    entries.push(IndexEntry { id: message.id, group_id: group_id.to_string(), offset });
+                }
+                offset += read as u64;
+            }
+        }
+        self.index.rebuild(entries)
+    }
+
+    /// The message an index entry points at, or None if the entry is stale
+    fn read_indexed(&self, entry: &IndexEntry) -> Result<Option<ChatMessage>> {
+        let Ok(log) = fs::File::open(self.message_file(&entry.group_id)) else { return Ok(None) };
+        let mut log = BufReader::new(log);
+        log.seek(SeekFrom::Start(entry.offset))?;
+        let mut line = String::new();
+        log.read_line(&mut line)?;
+        Ok(serde_json::from_str::<ChatMessage>(&line).ok().filter(|message| message.id == entry.id))
+    }
+
+    fn group_file(&self, group_id: &str) -> PathBuf {
+        self.data_dir.join("groups").join(format!("{}.json", group_id))
+    }
+
+    fn message_file(&self, group_id: &str) -> PathBuf {
+        self.data_dir.join("messages").join(format!("{}.jsonl", group_id))
+    }
+
+    fn snapshot_dir(&self, group_id: &str) -> PathBuf {
+        self.data_dir.join("snapshots").join(group_id)
+    }
+
+    /// Where a transfer is kept; its ID comes from the sender, so it must be a plain name
+    fn transfer_file(&self, id: &str) -> Result<PathBuf> {
+        if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+            return Err(anyhow::anyhow!("'{}' is not a valid message ID", id));
+        }
+        Ok(self.data_dir.join("transfers").join(format!("{}.json", id)))
+    }
+
+    fn write_group(&self, group: &ChatGroup) -> Result<()> {
+        let state = serde_json::to_string_pretty(group)?;
+        rewrite(self.group_file(&group.group_id), state)
+            .with_context(|| format!("Failed to write group '{}'", group.name))?;
+        Ok(())
+    }
+
+    fn write_messages(&self, group_id: &str, messages: &[ChatMessage]) -> Result<()> {
+        let mut log = String::new();
+        let mut entries = Vec::new();
+        for message in messages {
+            entries.push(IndexEntry { id: message.id.clone(), group_id: group_id.to_string(), offset: log.len() as u64 });
+            log.push_str(&serde_json::to_string(message)?);
+            log.push('\n');
+        }
+        rewrite(self.message_file(group_id), log)?;
+        self.index.replace_group(group_id, entries)
+    }
+
+    /// Move inline messages into the group's message log and rewrite its metadata
+    fn migrate_inline_group(&self, mut inline: InlineGroup) -> Result<ChatGroup> {
+        if let Some(messages) = inline.messages.take() {
+            inline.group.message_count = messages.len();
+            self.write_messages(&inline.group.group_id, &messages)?;
+            self.write_group(&inline.group)?;
+        }
+        Ok(inline.group)
+    }
+
+    /// Split a single `app_state.json` from older versions into per-group files
+    fn migrate_legacy_state(&self) -> Result<()> {
+        let legacy_file = self.data_dir.join("app_state.json");
+        if !legacy_file.exists() {
+            return Ok(());
+        }
+
+        let data = fs::read_to_string(&legacy_file)?;
+        let groups: HashMap<String, InlineGroup> =
+            serde_json::from_str(&data).context("Failed to parse legacy app_state.json")?;
+        for mut inline in groups.into_values() {
+            inline.messages.get_or_insert_with(Vec::new);
+            self.migrate_inline_group(inline)?;
+        }
+        wipe(&legacy_file)?;
+        Ok(())
+    }
+}
+
+/// Overwrite a file with zeros and flush it to disk before removing it; a missing file is fine
+///
+/// This stops the contents being read back from the file system, though SSDs and
+/// copy-on-write file systems may keep the old blocks elsewhere (see `erasure_caveat`).
+pub(crate) fn wipe(path: &Path) -> Result<()> {
+    let Some(file) = zero(path)? else { return Ok(()) };
+    drop(file);
+    fs::remove_file(path).with_context(|| format!("Failed to remove {}", path.display()))
+}
+
+/// Replace a file's contents, then overwrite the old ones with zeros
+///
+/// The new contents are written to a temporary file, flushed, and renamed
+/// over the old one, so a crash or a full disk leaves either the old file or
+/// the new one, never a zeroed or empty one. Only then are the old contents,
+/// still open, written over, so pruned messages and the secrets of past
+/// epochs are not left in blocks the file system has freed, within the same
+/// limits as `wipe`. On Unix the new file keeps the old one's permissions.
+fn rewrite(path: impl AsRef<Path>, contents: impl AsRef<[u8]>) -> Result<()> {
+    replace(path.as_ref(), contents.as_ref(), None)
+}
+
+/// `rewrite` a file only its owner may read or write
+///
+/// The mode is Unix's; elsewhere the file gets its directory's default access.
+pub(crate) fn rewrite_private(path: impl AsRef<Path>, contents: impl AsRef<[u8]>) -> Result<()> {
+    replace(path.as_ref(), contents.as_ref(), Some(0o600))
+}
+
+fn replace(path: &Path, contents: &[u8], mode: Option<u32>) -> Result<()> {
+    let old = match OpenOptions::new().write(true).open(path) {
+        Ok(file) => Some(file),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => None,
+        Err(err) => return Err(err).with_context(|| format!("Failed to open {}", path.display())),
+    };
+    let mode = match (mode, &old) {
+        (Some(mode), _) => Some(mode),
+        (None, Some(file)) => file_mode(file)?,
+        (None, None) => None,
+    };
+
+    let mut name = path.file_name().context("Path has no file name")?.to_os_string();
+    name.push(".tmp");
+    let temp = path.with_file_name(name);
+    let mut file = fs::File::create(&temp).with_context(|| format!("Failed to create {}", temp.display()))?;
+    if let Some(mode) = mode {
+        set_mode(&temp, mode)?;
+    }
+    file.write_all(contents).with_context(|| format!("Failed to write {}", temp.display()))?;
+    file.sync_all()?;
+    drop(file);
+    fs::rename(&temp, path).with_context(|| format!("Failed to replace {}", path.display()))?;
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::File::open(dir)?.sync_all()?; // Makes the rename itself durable
+    }
+
+    if let Some(mut file) = old {
+        let len = file.metadata()?.len();
+        zero_file(&mut file, len)?;
+    }
+    Ok(())
+}
+
+/// A file's Unix permission bits
+#[cfg(unix)]
+fn file_mode(file: &fs::File) -> Result<Option<u32>> {
+    use std::os::unix::fs::PermissionsExt;
+    Ok(Some(file.metadata()?.permissions().mode()))
+}
+
+/// None: other platforms have no permission bits to carry over
+#[cfg(not(unix))]
+fn file_mode(_file: &fs::File) -> Result<Option<u32>> {
+    Ok(None)
+}
+
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: u32) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode)).with_context(|| format!("Failed to set the mode of {}", path.display()))
+}
+
+/// Nothing to set: access is left to the directory's ACL
+#[cfg(not(unix))]
+fn set_mode(_path: &Path, _mode: u32) -> Result<()> {
+    Ok(())
+}
+
+/// Overwrite a file's contents with zeros in place and flush them; None if there is no file
+fn zero(path: &Path) -> Result<Option<fs::File>> {
+    let len = match fs::metadata(path) {
+        Ok(meta) => meta.len(),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err).with_context(|| format!("Failed to read {}", path.display())),
+    };
+    let mut file = OpenOptions::new().write(true).open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    zero_file(&mut file, len)?;
+    Ok(Some(file))
+}
+
+/// Write `len` zeros from the start of an open file and flush them
+fn zero_file(file: &mut fs::File, len: u64) -> Result<()> {
+    file.seek(SeekFrom::Start(0))?;
+    let zeros = [0u8; 4096];
+    let mut left = len;
+    while left > 0 {
+        let chunk = left.min(zeros.len() as u64) as usize;
+        file.write_all(&zeros[..chunk])?;
+        left -= chunk as u64;
+    }
+    file.sync_all()?;
+    Ok(())
+}
+
+/// Why data overwritten in `dir` may still be recoverable, if the file system it is on says so
+///
+/// Read from `/proc/mounts`, so only known on Linux. Overwriting in place
+/// cannot reach old copies on copy-on-write and log-structured file
+/// systems, below an overlay, or on a file server. Flash wear levelling
+/// keeps old blocks too and cannot be seen from here.
+pub fn erasure_caveat(dir: &Path) -> Option<String> {
+    let dir = fs::canonicalize(dir).ok()?;
+    let mounts = fs::read_to_string("/proc/mounts").ok()?;
+    let (_, fs_type) = mounts.lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let mount_point = PathBuf::from(fields.nth(1)?.replace("\\040", " "));
+            Some((mount_point, fields.next()?.to_string()))
+        })
+        .filter(|(mount_point, _)| dir.starts_with(mount_point))
+        .max_by_key(|(mount_point, _)| mount_point.components().count())?;
+    let why = match fs_type.as_str() {
+        "btrfs" | "zfs" | "bcachefs" => "a copy-on-write file system, which writes changes to new blocks",
+        "f2fs" | "nilfs2" => "a log-structured file system, which writes changes to new blocks",
+        "overlay" => "an overlay, whose lower layers keep the files as they were first copied",
+        "nfs" | "nfs4" | "cifs" | "smb3" => "a network file system, whose server may keep its own copies",
+        fs_type if fs_type.starts_with("fuse") => "a FUSE file system, which may keep its own copies",
+        _ => return None,
+    };
+    Some(format!("{} is on {} ({}), so overwritten data may remain on disk", dir.display(), fs_type, why))
+}
+
+impl Storage for FileStorage {
+    fn load_groups(&self) -> Result<Vec<ChatGroup>> {
+        let mut groups = Vec::new();
+        for entry in fs::read_dir(self.data_dir.join("groups"))? {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("json") {
+                continue;
+            }
+            let data = fs::read_to_string(&path)?;
+            let inline: InlineGroup = serde_json::from_str(&data)
+                .with_context(|| format!("Failed to parse {}", path.display()))?;
+            groups.push(self.migrate_inline_group(inline)?);
+        }
+        Ok(groups)
+    }
+
+    fn save_group(&mut self, group: &ChatGroup) -> Result<()> {
+        self.write_group(group)
+    }
+
+    fn load_messages(&self, group_id: &str) -> Result<Vec<ChatMessage>> {
+        let message_file = self.message_file(group_id);
+        if !message_file.exists() {
+            return Ok(Vec::new());
+        }
+        let data = fs::read_to_string(&message_file)?;
+        data.lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                serde_json::from_str(line)
+                    .with_context(|| format!("Failed to parse {}", message_file.display()))
+            })
+            .collect()
+    }
+
+    fn stream_messages(&self, group_id: &str, visit: &mut dyn FnMut(ChatMessage) -> Result<()>) -> Result<()> {
+        let message_file = self.message_file(group_id);
+        if !message_file.exists() {
+            return Ok(());
+        }
+        let log = fs::File::open(&message_file)
+            .with_context(|| format!("Failed to open {}", message_file.display()))?;
+        for line in BufReader::new(log).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let message = serde_json::from_str(&line)
+                .with_context(|| format!("Failed to parse {}", message_file.display()))?;
+            visit(message)?;
+        }
+        Ok(())
+    }
+
+    fn find_messages(&self, prefix: &str) -> Result<Vec<ChatMessage>> {
+        let mut found = Vec::new();
+        for entry in self.index.lookup(prefix)? {
+            match self.read_indexed(&entry)? {
+                Some(message) => found.push(message),
+                None => {
+                    // A log was changed behind the index's back; index it again and look once more
+                    self.rebuild_index()?;
+                    let mut found = Vec::new();
+                    for entry in self.index.lookup(prefix)? {
+                        found.extend(self.read_indexed(&entry)?);
+                    }
+                    return Ok(found);
+                }
+            }
+        }
+        Ok(found)
+    }
+
+    fn message_logs(&self) -> Result<Vec<String>> {
+        Ok(files_in(&self.data_dir.join("messages"), "jsonl")?
+            .iter()
+            .filter_map(|path| path.file_stem().and_then(|stem| stem.to_str()).map(str::to_string))
+            .collect())
+    }
+
+    fn append_message(&mut self, message: &ChatMessage) -> Result<()> {
+        let mut log = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.message_file(&message.group_id))?;
+        let offset = log.metadata()?.len();
+        writeln!(log, "{}", serde_json::to_string(message)?)?;
+        self.index.add(&IndexEntry { id: message.id.clone(), group_id: message.group_id.clone(), offset })
+    }
+
+    fn replace_messages(&mut self, group_id: &str, messages: &[ChatMessage]) -> Result<()> {
+        self.write_messages(group_id, messages)
+    }
+
+    fn delete_group(&mut self, group_id: &str) -> Result<()> {
+        let snapshots = self.snapshot_dir(group_id);
+        if let Ok(entries) = fs::read_dir(&snapshots) {
+            for entry in entries {
+                wipe(&entry?.path())?;
+            }
+            fs::remove_dir(&snapshots).with_context(|| format!("Failed to remove {}", snapshots.display()))?;
+        }
+        for transfer in self.load_transfers()? {
+            if transfer.group_id == group_id {
+                wipe(&self.transfer_file(&transfer.id)?)?;
+            }
+        }
+        wipe(&self.message_file(group_id))?;
+        self.index.replace_group(group_id, Vec::new())?;
+        wipe(&self.group_file(group_id))
+    }
+
+    fn load_snapshots(&self, group_id: &str) -> Result<Vec<GroupSnapshot>> {
+        let Ok(entries) = fs::read_dir(self.snapshot_dir(group_id)) else { return Ok(Vec::new()) };
+        let mut snapshots = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("json") {
+                continue;
+            }
+            let data = fs::read_to_string(&path)?;
+            let snapshot: GroupSnapshot = serde_json::from_str(&data)
+                .with_context(|| format!("Failed to parse {}", path.display()))?;
+            snapshots.push(snapshot);
+        }
+        snapshots.sort_by_key(|snapshot| snapshot.at);
+        Ok(snapshots)
+    }
+
+    fn save_snapshot(&mut self, snapshot: &GroupSnapshot) -> Result<()> {
+        let dir = self.snapshot_dir(&snapshot.group.group_id);
+        fs::create_dir_all(&dir).context("Failed to create snapshots directory")?;
+        let snapshot_state = serde_json::to_string_pretty(snapshot)?;
+        rewrite(dir.join(format!("{}.json", snapshot.id)), snapshot_state)
+    }
+
+    fn load_transfers(&self) -> Result<Vec<Transfer>> {
+        let mut transfers = Vec::new();
+        for path in files_in(&self.data_dir.join("transfers"), "json")? {
+            let data = fs::read_to_string(&path)?;
+            let transfer: Transfer = serde_json::from_str(&data)
+                .with_context(|| format!("Failed to parse {}", path.display()))?;
+            transfers.push(transfer);
+        }
+        transfers.sort_by_key(|transfer| transfer.started);
+        Ok(transfers)
+    }
+
+    fn load_transfer(&self, id: &str) -> Result<Option<Transfer>> {
+        let path = self.transfer_file(id)?;
+        if !path.exists() {
+            return Ok(None);
+        }
+        let data = fs::read_to_string(&path)?;
+        serde_json::from_str(&data).with_context(|| format!("Failed to parse {}", path.display())).map(Some)
+    }
+
+    fn save_transfer(&mut self, transfer: &Transfer) -> Result<()> {
+        let path = self.transfer_file(&transfer.id)?;
+        fs::create_dir_all(self.data_dir.join("transfers")).context("Failed to create transfers directory")?;
+        rewrite(path, serde_json::to_string(transfer)?)
+    }
+
+    fn delete_transfer(&mut self, id: &str) -> Result<()> {
+        wipe(&self.transfer_file(id)?)
+    }
+
+    fn load_user_keys(&self) -> Result<HashMap<String, MockKey>> {
+        // Older versions kept every identity in one `user_keys.json`
+        let legacy_file = self.data_dir.join(LEGACY_KEYS_FILE);
+        let mut keys: HashMap<String, MockKey> = match legacy_file.exists() {
+            true => serde_json::from_str(&fs::read_to_string(legacy_file)?)?,
+            false => HashMap::new(),
+        };
+        let identities = self.data_dir.join("identities");
+        if !identities.exists() {
+            return Ok(keys);
+        }
+        for entry in fs::read_dir(identities)? {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("json") {
+                continue;
+            }
+            let Some(user) = path.file_stem().and_then(|stem| stem.to_str()) else { continue };
+            let data = fs::read_to_string(&path)?;
+            let key = serde_json::from_str(&data)
+                .with_context(|| format!("Failed to read identity file {}", path.display()))?;
+            keys.insert(user.to_string(), key);
+        }
+        Ok(keys)
+    }
+
+    fn save_user_keys(&mut self, keys: &HashMap<String, MockKey>) -> Result<()> {
+        let identities = self.data_dir.join("identities");
+        fs::create_dir_all(&identities).context("Failed to create identities directory")?;
+        for (user, key) in keys {
+            let key_state = serde_json::to_string_pretty(key)?;
+            rewrite_private(identities.join(format!("{}.json", user)), key_state)
+                .with_context(|| format!("Failed to write identity '{}'", user))?;
+        }
+        let legacy_file = self.data_dir.join(LEGACY_KEYS_FILE);
+        if legacy_file.exists() {
+            wipe(&legacy_file)?;
+        }
+        Ok(())
+    }
+
+    fn delete_identities(&mut self) -> Result<()> {
+        if let Ok(entries) = fs::read_dir(self.data_dir.join("identities")) {
+            for entry in entries {
+                wipe(&entry?.path())?;
+            }
+        }
+        wipe(&self.data_dir.join(LEGACY_KEYS_FILE))?;
+        wipe(&self.data_dir.join("current_user.json"))?;
+        wipe(&self.data_dir.join("peer_keys.json"))?;
+        wipe(&self.data_dir.join("session.json"))?;
+        wipe(&self.data_dir.join("unlock_attempts.json"))?; // Where older versions counted wrong passphrases
+        wipe(&self.data_dir.join("server_tokens.json"))
+    }
+
+    fn load_current_user(&self) -> Result<Option<UserName>> {
+        let current_user_file = self.data_dir.join("current_user.json");
+        if !current_user_file.exists() {
+            return Ok(None);
+        }
+        let data = fs::read_to_string(current_user_file)?;
+        Ok(serde_json::from_str(&data)?)
+    }
+
+    fn save_current_user(&mut self, user: UserName) -> Result<()> {
+        let user_state = serde_json::to_string_pretty(&user)?;
+        rewrite(self.data_dir.join("current_user.json"), user_state)?;
+        Ok(())
+    }
+
+    fn load_server_tokens(&self) -> Result<HashMap<String, HashMap<String, String>>> {
+        let tokens_file = self.data_dir.join("server_tokens.json");
+        if !tokens_file.exists() {
+            return Ok(HashMap::new());
+        }
+        let data = fs::read_to_string(tokens_file)?;
+        Ok(serde_json::from_str(&data)?)
+    }
+
+    fn save_server_tokens(&mut self, tokens: &HashMap<String, HashMap<String, String>>) -> Result<()> {
+        let tokens_state = serde_json::to_string_pretty(tokens)?;
+        rewrite_private(self.data_dir.join("server_tokens.json"), tokens_state)?;
+        Ok(())
+    }
+
+    fn load_peer_keys(&self) -> Result<HashMap<String, HashMap<String, PeerKey>>> {
+        let peer_keys_file = self.data_dir.join("peer_keys.json");
+        if !peer_keys_file.exists() {
+            return Ok(HashMap::new());
+        }
+        let data = fs::read_to_string(peer_keys_file)?;
+        Ok(serde_json::from_str(&data)?)
+    }
+
+    fn save_peer_keys(&mut self, keys: &HashMap<String, HashMap<String, PeerKey>>) -> Result<()> {
+        let peer_keys_state = serde_json::to_string_pretty(keys)?;
+        rewrite(self.data_dir.join("peer_keys.json"), peer_keys_state)?;
+        Ok(())
+    }
+
+    fn load_revocations(&self) -> Result<Option<RevocationList>> {
+        let revocations_file = self.data_dir.join("revocations.json");
+        if !revocations_file.exists() {
+            return Ok(None);
+        }
+        let data = fs::read_to_string(revocations_file)?;
+        Ok(Some(serde_json::from_str(&data)?))
+    }
+
+    fn save_revocations(&mut self, list: &RevocationList) -> Result<()> {
+        let revocations_state = serde_json::to_string_pretty(list)?;
+        rewrite(self.data_dir.join("revocations.json"), revocations_state)?;
+        Ok(())
+    }
+
+    fn load_tree_heads(&self) -> Result<HashMap<String, TreeHead>> {
+        let heads_file = self.data_dir.join("tree_heads.json");
+        if !heads_file.exists() {
+            return Ok(HashMap::new());
+        }
+        let data = fs::read_to_string(heads_file)?;
+        Ok(serde_json::from_str(&data)?)
+    }
+
+    fn save_tree_heads(&mut self, heads: &HashMap<String, TreeHead>) -> Result<()> {
+        let heads_state = serde_json::to_string_pretty(heads)?;
+        rewrite(self.data_dir.join("tree_heads.json"), heads_state)?;
+        Ok(())
+    }
+
+    fn load_session(&self) -> Result<Option<Session>> {
+        let session_file = self.data_dir.join("session.json");
+        if !session_file.exists() {
+            return Ok(None);
+        }
+        let data = fs::read_to_string(session_file)?;
+        Ok(Some(serde_json::from_str(&data)?))
+    }
+
+    fn save_session(&mut self, session: &Session) -> Result<()> {
+        let session_state = serde_json::to_string_pretty(session)?;
+        rewrite_private(self.data_dir.join("session.json"), session_state)?;
+        Ok(())
+    }
+
+    fn delete_session(&mut self) -> Result<()> {
+        wipe(&self.data_dir.join("session.json"))
+    }
+
+    fn location(&self) -> String {
+        let path = fs::canonicalize(&self.data_dir).unwrap_or_else(|_| self.data_dir.clone());
+        path.display().to_string()
+    }
+
+    fn erasure_caveat(&self) -> Option<String> {
+        erasure_caveat(&self.data_dir)
+    }
+
+    fn stamp(&self, item: Item<'_>) -> Result<Option<Stamp>> {
+        let files = match item {
+            Item::Groups => files_in(&self.data_dir.join("groups"), "json")?,
+            Item::Messages(group_id) => vec![self.message_file(group_id)],
+            Item::UserKeys => {
+                let mut files = files_in(&self.data_dir.join("identities"), "json")?;
+                files.push(self.data_dir.join(LEGACY_KEYS_FILE));
+                files
+            }
+            Item::CurrentUser => vec![self.data_dir.join("current_user.json")],
+        };
+        Ok(Some(Stamp::of_files(files)))
+    }
+}
+
+/// The files in `dir` with extension `ext`, sorted; none if `dir` does not exist
+fn files_in(dir: &Path, ext: &str) -> Result<Vec<PathBuf>> {
+    let Ok(entries) = fs::read_dir(dir) else { return Ok(Vec::new()) };
+    let mut files = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().and_then(|found| found.to_str()) == Some(ext) {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// In-memory storage that never touches the filesystem
+///
+/// Clones share the same underlying state, so a test can keep a handle,
+/// drop the app, and build a new one on the same storage to check what was
+/// persisted.
+#[derive(Clone, Default)]
+pub struct MemoryStorage {
+    state: Arc<Mutex<MemoryState>>,
+}
+
+#[derive(Default)]
+struct MemoryState {
+    groups: HashMap<String, ChatGroup>, // Keyed by group ID
+    messages: HashMap<String, Vec<ChatMessage>>,
+    user_keys: HashMap<String, MockKey>,
+    current_user: Option<UserName>,
+    server_tokens: HashMap<String, HashMap<String, String>>,
+    peer_keys: HashMap<String, HashMap<String, PeerKey>>,
+    revocations: Option<RevocationList>,
+    tree_heads: HashMap<String, TreeHead>,
+    session: Option<Session>,
+    snapshots: HashMap<String, Vec<GroupSnapshot>>, // Keyed by group ID
+    transfers: HashMap<String, Transfer>, // Keyed by message ID
+}
+
+impl MemoryStorage {
+    /// Create an empty in-memory store
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn state(&self) -> MutexGuard<'_, MemoryState> {
+        // A panic while holding the lock cannot leave the maps half-written
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn load_groups(&self) -> Result<Vec<ChatGroup>> {
+        Ok(self.state().groups.values().cloned().collect())
+    }
+
+    fn save_group(&mut self, group: &ChatGroup) -> Result<()> {
+        let mut stored = group.clone();
+        stored.messages = None;
+        self.state().groups.insert(stored.group_id.clone(), stored);
+        Ok(())
+    }
+
+    fn load_messages(&self, group_id: &str) -> Result<Vec<ChatMessage>> {
+        Ok(self.state().messages.get(group_id).cloned().unwrap_or_default())
+    }
+
+    fn find_messages(&self, prefix: &str) -> Result<Vec<ChatMessage>> {
+        Ok(self.state().messages.values().flatten().filter(|message| message.id.starts_with(prefix)).cloned().collect())
+    }
+
+    fn stream_messages(&self, group_id: &str, visit: &mut dyn FnMut(ChatMessage) -> Result<()>) -> Result<()> {
+        // Copied first, so `visit` never runs with the state locked
+        for message in self.load_messages(group_id)? {
+            visit(message)?;
+        }
+        Ok(())
+    }
+
+    fn message_logs(&self) -> Result<Vec<String>> {
+        Ok(self.state().messages.keys().cloned().collect())
+    }
+
+    fn append_message(&mut self, message: &ChatMessage) -> Result<()> {
+        self.state()
+            .messages
+            .entry(message.group_id.clone())
+            .or_default()
+            .push(message.clone());
+        Ok(())
+    }
+
+    fn replace_messages(&mut self, group_id: &str, messages: &[ChatMessage]) -> Result<()> {
+        self.state().messages.insert(group_id.to_string(), messages.to_vec());
+        Ok(())
+    }
+
+    fn delete_group(&mut self, group_id: &str) -> Result<()> {
+        let mut state = self.state();
+        state.groups.remove(group_id);
+        state.messages.remove(group_id);
+        state.snapshots.remove(group_id);
+        state.transfers.retain(|_, transfer| transfer.group_id != group_id);
+        Ok(())
+    }
+
+    fn load_snapshots(&self, group_id: &str) -> Result<Vec<GroupSnapshot>> {
+        Ok(self.state().snapshots.get(group_id).cloned().unwrap_or_default())
+    }
+
+    fn save_snapshot(&mut self, snapshot: &GroupSnapshot) -> Result<()> {
+        self.state().snapshots.entry(snapshot.group.group_id.clone()).or_default().push(snapshot.clone());
+        Ok(())
+    }
+
+    fn load_transfers(&self) -> Result<Vec<Transfer>> {
+        let mut transfers: Vec<Transfer> = self.state().transfers.values().cloned().collect();
+        transfers.sort_by_key(|transfer| transfer.started);
+        Ok(transfers)
+    }
+
+    fn load_transfer(&self, id: &str) -> Result<Option<Transfer>> {
+        Ok(self.state().transfers.get(id).cloned())
+    }
+
+    fn save_transfer(&mut self, transfer: &Transfer) -> Result<()> {
+        self.state().transfers.insert(transfer.id.clone(), transfer.clone());
+        Ok(())
+    }
+
+    fn delete_transfer(&mut self, id: &str) -> Result<()> {
+        self.state().transfers.remove(id);
+        Ok(())
+    }
+
+    fn load_user_keys(&self) -> Result<HashMap<String, MockKey>> {
+        Ok(self.state().user_keys.clone())
+    }
+
+    fn save_user_keys(&mut self, keys: &HashMap<String, MockKey>) -> Result<()> {
+        self.state().user_keys = keys.clone();
+        Ok(())
+    }
+
+    fn delete_identities(&mut self) -> Result<()> {
+        let mut state = self.state();
+        state.user_keys.clear();
+        state.current_user = None;
+        state.server_tokens.clear();
+        state.peer_keys.clear();
+        state.session = None;
+        Ok(())
+    }
+
+    fn load_current_user(&self) -> Result<Option<UserName>> {
+        Ok(self.state().current_user)
+    }
+
+    fn save_current_user(&mut self, user: UserName) -> Result<()> {
+        self.state().current_user = Some(user);
+        Ok(())
+    }
+
+    fn load_server_tokens(&self) -> Result<HashMap<String, HashMap<String, String>>> {
+        Ok(self.state().server_tokens.clone())
+    }
+
+    fn save_server_tokens(&mut self, tokens: &HashMap<String, HashMap<String, String>>) -> Result<()> {
+        self.state().server_tokens = tokens.clone();
+        Ok(())
+    }
+
+    fn load_peer_keys(&self) -> Result<HashMap<String, HashMap<String, PeerKey>>> {
+        Ok(self.state().peer_keys.clone())
+    }
+
+    fn save_peer_keys(&mut self, keys: &HashMap<String, HashMap<String, PeerKey>>) -> Result<()> {
+        self.state().peer_keys = keys.clone();
+        Ok(())
+    }
+
+    fn load_revocations(&self) -> Result<Option<RevocationList>> {
+        Ok(self.state().revocations.clone())
+    }
+
+    fn save_revocations(&mut self, list: &RevocationList) -> Result<()> {
+        self.state().revocations = Some(list.clone());
+        Ok(())
+    }
+
+    fn load_tree_heads(&self) -> Result<HashMap<String, TreeHead>> {
+        Ok(self.state().tree_heads.clone())
+    }
+
+    fn save_tree_heads(&mut self, heads: &HashMap<String, TreeHead>) -> Result<()> {
+        self.state().tree_heads = heads.clone();
+        Ok(())
+    }
+
+    fn load_session(&self) -> Result<Option<Session>> {
+        Ok(self.state().session.clone())
+    }
+
+    fn save_session(&mut self, session: &Session) -> Result<()> {
+        self.state().session = Some(session.clone());
+        Ok(())
+    }
+
+    fn delete_session(&mut self) -> Result<()> {
+        self.state().session = None;
+        Ok(())
+    }
+
+    fn location(&self) -> String {
+        "in memory".to_string()
+    }
+
+    fn erasure_caveat(&self) -> Option<String> {
+        Some("Memory storage frees removed data without overwriting it".to_string())
+    }
+
+    fn stamp(&self, _item: Item<'_>) -> Result<Option<Stamp>> {
+        Ok(None) // Clones share the state, so another one may have written it
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::{new_group, new_message};
+    use crate::content::Content;
+    use crate::model::{GroupPolicy, TransferDirection};
+    use chrono::Utc;
+    use std::collections::BTreeMap;
+    #[cfg(unix)]
+    use std::os::unix::fs::PermissionsExt;
+
+    fn message(group: &ChatGroup, id: &str, text: &str) -> ChatMessage {
+        let content = Content::Markdown { text: text.to_string() };
+        new_message(id.to_string(), "Alice".to_string(), content, String::new(), Utc::now(), group).unwrap()
+    }
+
+    fn transfer(group: &ChatGroup) -> Transfer {
+        Transfer {
+            id: "transfer-1".to_string(),
+            group_id: group.group_id.clone(),
+            direction: TransferDirection::Download,
+            sender: "Bob".to_string(),
+            epoch: 1,
+            size: 3,
+            hash: String::new(),
+            chunk_count: 1,
+            chunks: BTreeMap::from([(0, "YWJj".to_string())]),
+            recipients: Vec::new(),
+            manifest_sent: false,
+            from_seq: 0,
+            name: None,
+            started: Utc::now(),
+        }
+    }
+
+    /// Write through one backend and read everything back
+    fn round_trip(storage: &mut dyn Storage) {
+        let group = new_group("Team", "Alice", "alice-key", GroupPolicy::default());
+        storage.save_group(&group).unwrap();
+        let groups = storage.load_groups().unwrap();
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].group_id, group.group_id);
+        assert_eq!(groups[0].mls_group.tree_hash, group.mls_group.tree_hash);
+
+        storage.append_message(&message(&group, "aaaa-1", "one")).unwrap();
+        storage.append_message(&message(&group, "bbbb-2", "two")).unwrap();
+        let contents: Vec<String> = storage.load_messages(&group.group_id).unwrap().into_iter().map(|message| message.content).collect();
+        assert_eq!(contents, ["one", "two"]);
+        let mut streamed = Vec::new();
+        storage.stream_messages(&group.group_id, &mut |message| {
+            streamed.push(message.id);
+            Ok(())
+        }).unwrap();
+        assert_eq!(streamed, ["aaaa-1", "bbbb-2"]);
+        let found = storage.find_messages("bbbb").unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].content, "two");
+        assert_eq!(storage.message_logs().unwrap(), vec![group.group_id.clone()]);
+
+        storage.replace_messages(&group.group_id, &[message(&group, "cccc-3", "three")]).unwrap();
+        let messages = storage.load_messages(&group.group_id).unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].content, "three");
+        assert!(storage.find_messages("aaaa").unwrap().is_empty());
+
+        storage.save_transfer(&transfer(&group)).unwrap();
+        assert_eq!(storage.load_transfer("transfer-1").unwrap().unwrap().chunks, transfer(&group).chunks);
+        assert_eq!(storage.load_transfers().unwrap().len(), 1);
+        storage.delete_transfer("transfer-1").unwrap();
+        assert!(storage.load_transfer("transfer-1").unwrap().is_none());
+
+        let key = MockKey { id: "key-1".to_string(), public_key: "public".to_string(), private_key: "private".to_string(), sealed_private_key: None, store_kdf: None, unlock_attempts: None };
+        storage.save_user_keys(&HashMap::from([("Alice".to_string(), key)])).unwrap();
+        storage.save_current_user(UserName::Alice).unwrap();
+        assert_eq!(storage.load_user_keys().unwrap()["Alice"].private_key, "private");
+        assert_eq!(storage.load_current_user().unwrap(), Some(UserName::Alice));
+        storage.delete_identities().unwrap();
+        assert!(storage.load_user_keys().unwrap().is_empty());
+
+        storage.delete_group(&group.group_id).unwrap();
+        assert!(storage.load_groups().unwrap().is_empty());
+        assert!(storage.load_messages(&group.group_id).unwrap().is_empty());
+        assert!(storage.message_logs().unwrap().is_empty());
+    }
+
+    /// A fresh directory under the system's temporary directory
+    fn temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("mls-chat-test-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn rewrite_replaces_contents_and_zeroes_the_old_copy() {
+        let dir = temp_dir();
+        let path = dir.join("group.json");
+        fs::write(&path, "old secret").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+        // A second name for the old file, which the rename leaves pointing at the old contents
+        fs::hard_link(&path, dir.join("old.json")).unwrap();
+
+        rewrite(&path, "new").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o640);
+        assert_eq!(fs::read(dir.join("old.json")).unwrap(), vec![0; "old secret".len()]);
+        assert!(!dir.join("group.json.tmp").exists());
+
+        rewrite(dir.join("fresh.json"), "created").unwrap();
+        assert_eq!(fs::read_to_string(dir.join("fresh.json")).unwrap(), "created");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn memory_storage_round_trips() {
+        round_trip(&mut MemoryStorage::new());
+    }
+
+    #[test]
+    fn file_storage_round_trips() {
+        let dir = temp_dir();
+        round_trip(&mut FileStorage::open(&dir).unwrap());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn file_storage_reopens_what_it_saved() {
+        let dir = temp_dir();
+        let group = new_group("Team", "Alice", "alice-key", GroupPolicy::default());
+        let mut storage = FileStorage::open(&dir).unwrap();
+        storage.save_group(&group).unwrap();
+        storage.append_message(&message(&group, "aaaa-1", "kept")).unwrap();
+        drop(storage);
+
+        let storage = FileStorage::open(&dir).unwrap();
+        assert_eq!(storage.load_groups().unwrap()[0].name, "Team");
+        assert_eq!(storage.load_messages(&group.group_id).unwrap()[0].content, "kept");
+        assert_eq!(storage.find_messages("aaaa").unwrap().len(), 1);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn sessions_are_readable_by_their_owner_only() {
+        let dir = temp_dir();
+        let mut storage = FileStorage::open(&dir).unwrap();
+        let session = Session { key: "c2VjcmV0".to_string(), last_used: Utc::now() };
+        storage.save_session(&session).unwrap();
+        let path = dir.join("session.json");
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        // Also when an older, wider file was there first
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        storage.save_session(&session).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        assert_eq!(storage.load_session().unwrap().unwrap().key, session.key);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn keys_and_tokens_are_readable_by_their_owner_only() {
+        let dir = temp_dir();
+        let mut storage = FileStorage::open(&dir).unwrap();
+        let key = MockKey {
+            id: "key".to_string(),
+            public_key: "pub_key".to_string(),
+            private_key: "priv_key".to_string(),
+            sealed_private_key: None,
+            store_kdf: None,
+            unlock_attempts: None,
+        };
+        storage.save_user_keys(&HashMap::from([("Alice".to_string(), key)])).unwrap();
+        let tokens = HashMap::from([("127.0.0.1:7878".to_string(), HashMap::from([("Alice".to_string(), "token".to_string())]))]);
+        storage.save_server_tokens(&tokens).unwrap();
+        for path in [dir.join("identities").join("Alice.json"), dir.join("server_tokens.json")] {
+            assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600, "{}", path.display());
+        }
+        assert_eq!(storage.load_server_tokens().unwrap(), tokens);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn memory_storage_clones_share_state() {
+        let mut storage = MemoryStorage::new();
+        let copy = storage.clone();
+        storage.save_group(&new_group("Team", "Alice", "alice-key", GroupPolicy::default())).unwrap();
+        assert_eq!(copy.load_groups().unwrap().len(), 1);
+    }
+}
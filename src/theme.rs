@@ -0,0 +1,238 @@
+//! Color themes
+//!
+//! Output is colored by role rather than with fixed colors: your own
+//! messages, other senders, system messages, errors, epochs, and mentions of
+//! you. `theme.name` picks a built-in theme, and `theme.<role>` overrides
+//! one role of it. Other senders are each given a color of their own from
+//! the theme's palette, picked by a hash of their identity, so a member has
+//! the same color in every group and on every machine:
+//!
+//! ```toml
+//! [theme]
+//! name = "high-contrast"
+//! own = "bold bright green"
+//! epoch = "#ff8800"
+//! mention = "black on yellow"
+//! ```
+//!
+//! A style is a color (a name such as `red` or `bright blue`, or `#rrggbb`),
+//! `auto` for the sender's palette color, or `none`, after any of `bold`,
+//! `dimmed`, `italic`, and `underline`, with `on <color>` for a background.
+//! Whether colors are used at all is still the `color` setting, so
+//! `NO_COLOR` turns every theme off unless `color = "always"`.
+
+use anyhow::{anyhow, Result};
+use colored::{Color, ColoredString, Colorize};
+use std::sync::OnceLock;
+
+use crate::crypto;
+
+/// What a piece of output is, for choosing its style
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Own,     // The current user's name on their messages
+    Sender,  // Everyone else's names
+    System,  // System message text
+    Error,   // The "Error:" a failed command prints
+    Epoch,   // Epoch numbers on messages
+    Mention, // Mentions of the current user
+}
+
+impl Role {
+    pub const ALL: [Role; 6] = [Role::Own, Role::Sender, Role::System, Role::Error, Role::Epoch, Role::Mention];
+
+    /// The role's key in the `[theme]` section
+    pub fn key(self) -> &'static str {
+        match self {
+            Role::Own => "own",
+            Role::Sender => "sender",
+            Role::System => "system",
+            Role::Error => "error",
+            Role::Epoch => "epoch",
+            Role::Mention => "mention",
+        }
+    }
+}
+
+/// Built-in themes, with a style for each role in `Role::ALL` order and the
+/// palette `auto` sender colors come from
+const THEMES: &[(&str, [&str; 6], &[&str])] = &[
+    (
+        "default",
+        ["yellow", "auto", "italic dimmed", "bold red", "none", "black on yellow"],
+        &["cyan", "green", "magenta", "blue", "bright cyan", "bright green", "bright magenta", "bright blue"],
+    ),
+    (
+        "high-contrast",
+        ["bold bright white", "bold auto", "bright cyan", "bold bright red", "bright magenta", "black on bright yellow"],
+        &["bright yellow", "bright cyan", "bright green", "bright magenta"],
+    ),
+    (
+        "light",
+        ["bold blue", "auto", "italic bright black", "bold red", "cyan", "white on blue"],
+        &["magenta", "green", "cyan", "red", "black"],
+    ),
+    ("mono", ["bold", "none", "italic", "bold", "none", "bold underline"], &[]),
+];
+
+static ACTIVE: OnceLock<Theme> = OnceLock::new();
+
+/// How one role is drawn
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Style {
+    pub color: Option<Color>,
+    pub per_member: bool, // `auto`: the palette color of whoever the text names
+    pub background: Option<Color>,
+    pub bold: bool,
+    pub dimmed: bool,
+    pub italic: bool,
+    pub underline: bool,
+}
+
+impl Style {
+    /// A style as theme settings write it, e.g. `bold bright blue` or `black on #ffcc00`
+    pub fn parse(text: &str) -> Result<Self> {
+        let lower = text.trim().to_ascii_lowercase();
+        let (foreground, background) = match lower.split_once(" on ") {
+            Some((foreground, background)) => (foreground, Some(background)),
+            None => (lower.as_str(), None),
+        };
+        let mut style = Style::default();
+        let mut words: Vec<&str> = foreground.split_whitespace().collect();
+        while let Some(word) = words.first() {
+            match *word {
+                "bold" => style.bold = true,
+                "dimmed" | "dim" => style.dimmed = true,
+                "italic" => style.italic = true,
+                "underline" => style.underline = true,
+                _ => break,
+            }
+            words.remove(0);
+        }
+        style.color = match words.join(" ").as_str() {
+            "" | "none" => None,
+            "auto" => {
+                style.per_member = true;
+                None
+            }
+            name => Some(color(name).ok_or_else(|| unknown(text))?),
+        };
+        if let Some(background) = background {
+            style.background = Some(color(background.trim()).ok_or_else(|| unknown(text))?);
+        }
+        Ok(style)
+    }
+
+    /// `text` in this style
+    pub fn paint(&self, text: &str) -> ColoredString {
+        let mut out = text.normal();
+        if let Some(color) = self.color {
+            out = out.color(color);
+        }
+        if let Some(background) = self.background {
+            out = out.on_color(background);
+        }
+        if self.bold {
+            out = out.bold();
+        }
+        if self.dimmed {
+            out = out.dimmed();
+        }
+        if self.italic {
+            out = out.italic();
+        }
+        if self.underline {
+            out = out.underline();
+        }
+        out
+    }
+}
+
+/// A style for every role, and the colors senders are given
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Theme {
+    styles: [Style; 6],
+    palette: Vec<Color>,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme::named("default").unwrap_or_else(|| Theme { styles: Default::default(), palette: Vec::new() })
+    }
+}
+
+impl Theme {
+    /// A built-in theme, if `name` is one
+    pub fn named(name: &str) -> Option<Self> {
+        let (_, styles, palette) = THEMES.iter().find(|(theme, _, _)| *theme == name)?;
+        Some(Theme {
+            styles: styles.map(|style| Style::parse(style).unwrap_or_default()),
+            palette: palette.iter().filter_map(|name| color(name)).collect(),
+        })
+    }
+
+    /// Draw `role` in `style` instead
+    pub fn set(&mut self, role: Role, style: Style) {
+        self.styles[role as usize] = style;
+    }
+
+    pub fn style(&self, role: Role) -> &Style {
+        &self.styles[role as usize]
+    }
+
+    /// The palette color for `member`, the same wherever they appear
+    pub fn member_color(&self, member: &str) -> Option<Color> {
+        let hash = crypto::sha256(member.as_bytes());
+        let index = u32::from_be_bytes([hash[0], hash[1], hash[2], hash[3]]) as usize;
+        self.palette.get(index.checked_rem(self.palette.len())?).copied()
+    }
+
+    /// `name` styled as `role`, with `auto` resolved to `name`'s own color
+    fn paint_name(&self, role: Role, name: &str) -> ColoredString {
+        let mut style = self.style(role).clone();
+        if style.per_member {
+            style.color = self.member_color(name.trim_end());
+        }
+        style.paint(name)
+    }
+}
+
+/// Names of the built-in themes, as `theme.name` takes them
+pub fn names() -> Vec<&'static str> {
+    THEMES.iter().map(|(name, _, _)| *name).collect()
+}
+
+/// Use `theme` for the rest of the run; only the first call has any effect
+pub fn init(theme: Theme) {
+    let _ = ACTIVE.set(theme);
+}
+
+/// `text` in the active theme's style for `role`
+pub fn paint(role: Role, text: &str) -> ColoredString {
+    active().paint_name(role, text)
+}
+
+/// A sender's name in their own color; `own` is the current user's
+pub fn sender(name: &str, own: bool) -> ColoredString {
+    active().paint_name(if own { Role::Own } else { Role::Sender }, name)
+}
+
+fn active() -> &'static Theme {
+    ACTIVE.get_or_init(Theme::default)
+}
+
+/// A color name as `colored` knows it, with `_` for spaces, or `#rrggbb`
+fn color(name: &str) -> Option<Color> {
+    match name.strip_prefix('#') {
+        Some(hex) if hex.len() == 6 && hex.is_ascii() => {
+            let channel = |at: usize| u8::from_str_radix(&hex[at..at + 2], 16).ok();
+            Some(Color::TrueColor { r: channel(0)?, g: channel(2)?, b: channel(4)? })
+        }
+        Some(_) => None,
+        None => name.replace('_', " ").parse().ok(),
+    }
+}
+
+fn unknown(text: &str) -> anyhow::Error {
+    anyhow!("Unknown style '{}'; expected e.g. \"red\", \"bold bright blue\", \"#ff8800\", or \"black on yellow\"", text)
+}
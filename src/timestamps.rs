@@ -0,0 +1,147 @@
+//! How message times are shown
+//!
+//! Times are stored in UTC; this turns them into what `list`, `mentions`,
+//! and `show` print, in the local timezone unless configured otherwise.
+//! Without a timezone database only `local`, `utc`, and fixed offsets such
+//! as `+05:30` are understood, so a fixed offset does not follow daylight
+//! saving time.
+
+use anyhow::{anyhow, Result};
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, FixedOffset, Local, TimeZone, Utc};
+
+/// Format for messages sent today
+pub const DEFAULT_TIME_FORMAT: &str = "%H:%M:%S";
+
+/// Format for older messages
+pub const DEFAULT_DATE_FORMAT: &str = "%Y-%m-%d %H:%M";
+
+/// How long times stay relative before the date format takes over
+const RELATIVE_DAYS: i64 = 7;
+
+/// The timezone times are shown in
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Zone {
+    #[default]
+    Local,
+    Utc,
+    Fixed(FixedOffset),
+}
+
+impl Zone {
+    /// `local`, `utc`, or an offset such as `+02:00`, `-0800`, or `+5`
+    pub fn parse(text: &str) -> Result<Self> {
+        let text = text.trim();
+        match text.to_ascii_lowercase().as_str() {
+            "local" => return Ok(Zone::Local),
+            "utc" | "z" => return Ok(Zone::Utc),
+            _ => {}
+        }
+        let error = || anyhow!("Unknown timezone '{}'; expected local, utc, or an offset such as +02:00", text);
+        let (sign, digits) = match text.split_at_checked(1) {
+            Some(("+", rest)) => (1, rest),
+            Some(("-", rest)) => (-1, rest),
+            _ => return Err(error()),
+        };
+        let (hours, minutes) = match digits.split_once(':') {
+            Some((hours, minutes)) => (hours, minutes),
+            None if digits.len() == 4 => digits.split_at(2),
+            None => (digits, "0"),
+        };
+        let hours: i32 = hours.parse().map_err(|_| error())?;
+        let minutes: i32 = minutes.parse().map_err(|_| error())?;
+        if hours > 14 || minutes > 59 {
+            return Err(error());
+        }
+        FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60)).map(Zone::Fixed).ok_or_else(error)
+    }
+}
+
+/// How to show message times
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Timestamps {
+    pub zone: Zone,
+    pub time_format: String, // For messages sent today in `zone`
+    pub date_format: String, // For older messages
+    pub relative: bool,      // "5m ago" for the last week instead
+}
+
+impl Default for Timestamps {
+    fn default() -> Self {
+        Self {
+            zone: Zone::Local,
+            time_format: DEFAULT_TIME_FORMAT.to_string(),
+            date_format: DEFAULT_DATE_FORMAT.to_string(),
+            relative: false,
+        }
+    }
+}
+
+impl Timestamps {
+    /// Show every time in UTC, whatever the configured zone
+    pub fn utc(mut self) -> Self {
+        self.zone = Zone::Utc;
+        self
+    }
+
+    /// `time` as seen at `now`
+    pub fn format(&self, time: DateTime<Utc>, now: DateTime<Utc>) -> String {
+        if self.relative {
+            if let Some(ago) = ago(time, now) {
+                return ago;
+            }
+        }
+        match self.zone {
+            Zone::Local => self.in_zone(time, now, &Local),
+            Zone::Utc => self.in_zone(time, now, &Utc),
+            Zone::Fixed(offset) => self.in_zone(time, now, &offset),
+        }
+    }
+
+    /// `time` as a full date and time with its zone, for `show`
+    pub fn full(&self, time: DateTime<Utc>) -> String {
+        const FULL: &str = "%Y-%m-%d %H:%M:%S %Z";
+        match self.zone {
+            Zone::Local => time.with_timezone(&Local).format(FULL).to_string(),
+            Zone::Utc => time.format("%Y-%m-%d %H:%M:%S UTC").to_string(),
+            Zone::Fixed(offset) => time.with_timezone(&offset).format(FULL).to_string(),
+        }
+    }
+
+    fn in_zone<Tz: TimeZone>(&self, time: DateTime<Utc>, now: DateTime<Utc>, zone: &Tz) -> String
+    where
+        Tz::Offset: std::fmt::Display,
+    {
+        let time = time.with_timezone(zone);
+        let format = match time.date_naive() == now.with_timezone(zone).date_naive() {
+            true => &self.time_format,
+            false => &self.date_format,
+        };
+        time.format(format).to_string()
+    }
+}
+
+/// "just now", "5m ago", "2h ago", or "3d ago"; None beyond a week, or for times ahead of `now`
+fn ago(time: DateTime<Utc>, now: DateTime<Utc>) -> Option<String> {
+    let elapsed = now.signed_duration_since(time);
+    if elapsed.num_seconds() < -60 || elapsed.num_days() >= RELATIVE_DAYS {
+        return None;
+    }
+    Some(match elapsed.num_minutes() {
+        ..=0 => "just now".to_string(),
+        minutes @ 1..60 => format!("{}m ago", minutes),
+        minutes if minutes < 24 * 60 => format!("{}h ago", minutes / 60),
+        minutes => format!("{}d ago", minutes / (24 * 60)),
+    })
+}
+
+/// Check a strftime-style format, so a typo fails when it is set rather than when times are printed
+pub fn check_format(format: &str) -> Result<()> {
+    if format.is_empty() {
+        return Err(anyhow!("Format must not be empty"));
+    }
+    match StrftimeItems::new(format).any(|item| matches!(item, Item::Error)) {
+        true => Err(anyhow!("Invalid time format '{}'; use strftime codes such as %H:%M or %d %b", format)),
+        false => Ok(()),
+    }
+}
@@ -0,0 +1,348 @@
+//! Transcript export, with a hash chain for legal hold
+//!
+//! `export <group> --out <file>` writes the group's history as JSON: each
+//! message, and an `Epoch` event wherever the epoch changes. `--verified`
+//! chains the events with SHA-256, each hash covering the one before, and
+//! signs the last with the exporting member's identity key, together with
+//! the group's epoch, tree hash, and members when exported, so removing,
+//! reordering, or editing any event breaks the chain from there on.
+//! `verify-export <file>` recomputes the chain and checks the signature
+//! from the file alone, needing no profile.
+//!
+//! Messages carry no signature of their own in this demo; each event has
+//! the fingerprint of the key its sender's message arrived with, where one
+//! was recorded. The export signature is `protocol::sign`, the mock's tag
+//! keyed by the public key, so here it shows the format rather than proving
+//! who exported.
+
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
+use colored::*;
+use serde::{Deserialize, Serialize};
+use std::{fs, path::Path};
+
+use crate::app::MlsChatApp;
+use crate::crypto;
+use crate::model::{ChatMessage, ContentType};
+use crate::protocol;
+
+/// What a verified export says it is, so other JSON is not mistaken for one
+pub const VERIFIED_FORMAT: &str = "mls-chat verified transcript 1";
+
+/// One event in a group's history
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum Event {
+    /// The messages after this one were sent in `epoch`
+    Epoch { epoch: u32 },
+    #[serde(rename_all = "camelCase")]
+    Message {
+        id: String,
+        sender: String,
+        timestamp: DateTime<Utc>,
+        epoch: u32,
+        content_type: ContentType,
+        content: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        sender_key: Option<String>, // Fingerprint of the key it arrived with
+    },
+}
+
+/// An event and the transcript hash up to and including it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+    pub event: Event,
+    pub hash: String, // Hex SHA-256 of the previous hash and this event
+}
+
+/// A transcript with its hash chain, signed by the member who exported it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VerifiedExport {
+    pub format: String,
+    pub group_id: String,
+    pub group_name: String,
+    pub epoch: u32, // The group's epoch when exported
+    pub tree_hash: String,
+    pub members: Vec<String>,
+    pub exported_by: String,
+    pub public_key: String,
+    pub at: DateTime<Utc>,
+    pub entries: Vec<Entry>,
+    pub head: String, // The last entry's hash, or the genesis hash if there are none
+    pub signature: String, // By `public_key`, over `statement()`
+}
+
+impl VerifiedExport {
+    /// The bytes the exporting member signs
+    pub fn statement(&self) -> String {
+        format!(
+            "{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}",
+            VERIFIED_FORMAT,
+            self.group_id,
+            self.group_name,
+            self.epoch,
+            self.tree_hash,
+            self.members.join(","),
+            self.exported_by,
+            self.public_key,
+            self.at.to_rfc3339(),
+            self.head,
+        )
+    }
+
+    /// Print who exported the transcript and the group as it was then
+    pub fn print(&self) {
+        println!("Group: {} ({}), epoch {} when exported", self.group_name, self.group_id, self.epoch);
+        println!("Members: {}", self.members.join(", "));
+        println!("Exported by: {} at {}", self.exported_by, self.at.format("%Y-%m-%d %H:%M:%S UTC"));
+        println!("Key fingerprint: {}", crypto::fingerprint(&self.public_key));
+        println!("Head hash: {}", self.head);
+    }
+}
+
+/// What `verify_export` checked
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExportSummary {
+    pub messages: usize,
+    pub epochs: usize, // `Epoch` events: the first epoch and each change after
+}
+
+/// The hash a group's chain starts from
+fn genesis(group_id: &str) -> String {
+    crypto::hex(&crypto::sha256(format!("mls-chat transcript\n{}", group_id).as_bytes()))
+}
+
+/// The chain's next hash after `previous`, covering `event`
+fn chain(previous: &str, event: &Event) -> Result<String> {
+    let event = serde_json::to_string(event)?;
+    Ok(crypto::hex(&crypto::sha256(format!("{}\n{}", previous, event).as_bytes())))
+}
+
+/// `messages` as events, with an `Epoch` before the first message and wherever the epoch changes
+fn events(messages: &[ChatMessage]) -> Vec<Event> {
+    let mut events = Vec::new();
+    let mut epoch = None;
+    for message in messages {
+        if epoch != Some(message.epoch) {
+            epoch = Some(message.epoch);
+            events.push(Event::Epoch { epoch: message.epoch });
+        }
+        events.push(Event::Message {
+            id: message.id.clone(),
+            sender: message.sender.clone(),
+            timestamp: message.timestamp,
+            epoch: message.epoch,
+            content_type: message.content_type,
+            content: message.content.clone(),
+            sender_key: message.sender_key.clone(),
+        });
+    }
+    events
+}
+
+/// Read a verified export and check its chain, epochs, and signature from the file alone
+pub fn verify_export(path: &Path) -> Result<(VerifiedExport, ExportSummary)> {
+    let data = fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let export: VerifiedExport = serde_json::from_str(&data)
+        .with_context(|| format!("{} is not a verified transcript export", path.display()))?;
+    if export.format != VERIFIED_FORMAT {
+        return Err(anyhow!("{} is '{}', not '{}'", path.display(), export.format, VERIFIED_FORMAT));
+    }
+    let mut hash = genesis(&export.group_id);
+    let mut epoch = None;
+    let mut summary = ExportSummary { messages: 0, epochs: 0 };
+    for (index, entry) in export.entries.iter().enumerate() {
+        hash = chain(&hash, &entry.event)?;
+        if entry.hash != hash {
+            return Err(anyhow!("Entry {} does not match the transcript hash: the transcript was changed at or before it", index + 1));
+        }
+        match &entry.event {
+            Event::Epoch { epoch: next } => {
+                if epoch == Some(*next) || *next > export.epoch {
+                    return Err(anyhow!("Entry {} moves to epoch {}, which does not follow", index + 1, next));
+                }
+                epoch = Some(*next);
+                summary.epochs += 1;
+            }
+            Event::Message { epoch: sent_in, .. } => {
+                if epoch != Some(*sent_in) {
+                    return Err(anyhow!("Entry {} is a message from epoch {} outside that epoch", index + 1, sent_in));
+                }
+                summary.messages += 1;
+            }
+        }
+    }
+    if export.head != hash {
+        return Err(anyhow!("The signed head hash does not match the last entry: entries were added or removed at the end"));
+    }
+    if !protocol::verify(&export.public_key, &export.statement(), &export.signature) {
+        return Err(anyhow!("The signature does not match the export; it was altered or not signed by this key"));
+    }
+    Ok((export, summary))
+}
+
+impl MlsChatApp {
+    /// Write a group's history to `out`; `verified` adds the hash chain and the current user's signature
+    pub fn export_transcript(&mut self, group_name: &str, out: &Path, verified: bool) -> Result<()> {
+        let user = self.current_user.context("No user initialized")?.to_string();
+        let public_key = self.user_keys.get(&user).context("No key for the current user")?.public_key.clone();
+        let group = self.load_messages(group_name)?;
+        let events = events(group.messages.as_deref().unwrap_or_default());
+        let messages = events.iter().filter(|event| matches!(event, Event::Message { .. })).count();
+        let json = match verified {
+            false => serde_json::to_string_pretty(&events)?,
+            true => {
+                let mut hash = genesis(&group.group_id);
+                let mut entries = Vec::new();
+                for event in events {
+                    hash = chain(&hash, &event)?;
+                    entries.push(Entry { event, hash: hash.clone() });
+                }
+                let mut export = VerifiedExport {
+                    format: VERIFIED_FORMAT.to_string(),
+                    group_id: group.group_id.clone(),
+                    group_name: group.name.clone(),
+                    epoch: group.mls_group.epoch,
+                    tree_hash: group.mls_group.tree_hash.clone(),
+                    members: group.members.clone(),
+                    exported_by: user,
+                    public_key,
+                    at: Utc::now(),
+                    entries,
+                    head: hash,
+                    signature: String::new(),
+                };
+                export.signature = protocol::sign(&export.public_key, &export.statement());
+                serde_json::to_string_pretty(&export)?
+            }
+        };
+        fs::write(out, json).with_context(|| format!("Failed to write {}", out.display()))?;
+        println!("{}", format!("✅ Exported {} message(s) from '{}' to {}", messages, group_name, out.display()).green());
+        if verified {
+            println!("   Hash-chained and signed; check it with `verify-export {}`", out.display());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::UserName;
+    use crate::storage::MemoryStorage;
+    use serde_json::Value;
+    use std::{env, path::PathBuf};
+    use uuid::Uuid;
+
+    /// A temporary file, removed when dropped
+    struct TempFile(PathBuf);
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            fs::remove_file(&self.0).ok();
+        }
+    }
+
+    fn temp_file() -> TempFile {
+        TempFile(env::temp_dir().join(format!("mls-chat-transcript-{}.json", Uuid::new_v4())))
+    }
+
+    /// A verified export of two messages in two epochs, as JSON
+    fn export() -> Value {
+        let mut app = MlsChatApp::with_storage(Box::new(MemoryStorage::new()));
+        app.init_user(UserName::Alice).unwrap();
+        app.init_user(UserName::Bob).unwrap();
+        app.create_group("Team".to_string()).unwrap();
+        app.send_message("Team".to_string(), "one".to_string()).unwrap();
+        app.add_member("Team".to_string(), UserName::Bob).unwrap();
+        app.send_message("Team".to_string(), "two".to_string()).unwrap();
+        let file = temp_file();
+        app.export_transcript("Team", &file.0, true).unwrap();
+        serde_json::from_str(&fs::read_to_string(&file.0).unwrap()).unwrap()
+    }
+
+    /// What `verify_export` makes of `export` once written to a file
+    fn verify(export: &Value) -> Result<ExportSummary> {
+        let file = temp_file();
+        fs::write(&file.0, export.to_string()).unwrap();
+        verify_export(&file.0).map(|(_, summary)| summary)
+    }
+
+    fn error(export: &Value) -> String {
+        verify(export).unwrap_err().to_string()
+    }
+
+    /// Rebuild the chain and signature over `export`'s entries, as a forger holding the key would
+    fn reseal(export: &mut Value) {
+        let mut parsed: VerifiedExport = serde_json::from_value(export.clone()).unwrap();
+        let mut hash = genesis(&parsed.group_id);
+        for entry in &mut parsed.entries {
+            hash = chain(&hash, &entry.event).unwrap();
+            entry.hash = hash.clone();
+        }
+        parsed.head = hash;
+        parsed.signature = protocol::sign(&parsed.public_key, &parsed.statement());
+        *export = serde_json::to_value(parsed).unwrap();
+    }
+
+    #[test]
+    fn verifies_an_untouched_export() {
+        let export = export();
+        assert_eq!(verify(&export).unwrap(), ExportSummary { messages: 2, epochs: 2 });
+        assert_eq!(export["entries"].as_array().unwrap().len(), 4);
+    }
+
+    #[test]
+    fn refuses_changed_entries() {
+        let original = export();
+
+        let mut edited = original.clone();
+        edited["entries"][1]["event"]["content"] = "eno".into();
+        assert!(error(&edited).contains("Entry 2 does not match"));
+
+        let mut reordered = original.clone();
+        reordered["entries"].as_array_mut().unwrap().swap(1, 3);
+        assert!(error(&reordered).contains("Entry 2 does not match"));
+
+        let mut cut = original.clone();
+        cut["entries"].as_array_mut().unwrap().pop();
+        assert!(error(&cut).contains("added or removed at the end"));
+        // Moving the head back to the new last entry breaks the signature instead
+        cut["head"] = cut["entries"][2]["hash"].clone();
+        assert!(error(&cut).contains("signature"));
+
+        let mut members = original.clone();
+        members["members"] = serde_json::json!(["Alice"]);
+        assert!(error(&members).contains("signature"));
+    }
+
+    #[test]
+    fn refuses_epochs_out_of_order() {
+        let original = export();
+
+        let mut repeated = original.clone();
+        repeated["entries"][2]["event"]["epoch"] = original["entries"][0]["event"]["epoch"].clone();
+        reseal(&mut repeated);
+        assert!(error(&repeated).contains("does not follow"));
+
+        let mut outside = original.clone();
+        outside["entries"][3]["event"]["epoch"] = original["entries"][0]["event"]["epoch"].clone();
+        reseal(&mut outside);
+        assert!(error(&outside).contains("outside that epoch"));
+
+        let mut future = original.clone();
+        future["epoch"] = 0.into();
+        reseal(&mut future);
+        assert!(error(&future).contains("does not follow"));
+    }
+
+    #[test]
+    fn refuses_other_files() {
+        let mut format = export();
+        format["format"] = "mls-chat verified transcript 0".into();
+        assert!(error(&format).contains("not 'mls-chat verified transcript 1'"));
+        assert!(error(&serde_json::json!([{ "type": "epoch", "epoch": 0 }])).contains("is not a verified transcript export"));
+        assert!(verify_export(Path::new("/nonexistent/transcript.json")).is_err());
+    }
+}
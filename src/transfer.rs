@@ -0,0 +1,436 @@
+//! Chunked transfer of messages too large for one envelope
+//!
+//! Delivery services cap payloads, `serve` at 1 MiB unless told otherwise,
+//! and a file sent with `send --file` travels inside one Application
+//! message. A serialized message over `CHUNK_BYTES` is sent instead as a
+//! `Manifest`, giving its size, chunk count, and SHA-256, followed by one
+//! `Chunk` for each `CHUNK_BYTES` of it. The chunks are pieces of the
+//! Application as it would have been sent, ciphertext and all, so the
+//! delivery service learns nothing new beyond the size.
+//!
+//! Both ends keep a `Transfer` in storage while one is unfinished. An upload
+//! holds the chunks still to send: if sending stops part way, the message is
+//! still stored here and `transfers resume` sends the rest. A download holds
+//! the chunks received so far, across any number of `sync`s; once all are
+//! in, they are joined, checked against the manifest's size and hash, and
+//! applied as the Application they make up. A manifest is only taken if its
+//! chunk count is the one its size splits into and the size is at most
+//! `MAX_TRANSFER_BYTES`, and a chunk only if it is at most `CHUNK_BYTES`, so
+//! a sender cannot make a receiver set aside more memory than the message
+//! holds. `transfers resume` on a download
+//! fetches the group again from the manifest's sequence number, on delivery
+//! services that keep history, to pick up chunks that went missing.
+//!
+//! A transfer is bound to the epoch its message was encrypted in. Once the
+//! group commits past it, the joined message can no longer be decrypted, so
+//! an upload is not resumed then and should be dropped and sent again.
+
+use anyhow::{anyhow, Context, Result};
+use chrono::Utc;
+use colored::*;
+use std::collections::BTreeMap;
+
+use crate::app::MlsChatApp;
+use crate::crypto::{self, base64};
+use crate::delivery::{Envelope, SendMessageRequest};
+use crate::model::{ChatMessage, Transfer, TransferDirection};
+use crate::network::print_seq;
+use crate::pins::short_id;
+use crate::preview::human_size;
+use crate::progress;
+use crate::protocol::MlsMessage;
+
+/// Largest serialized message sent in one envelope, and the size of each chunk of a larger one
+///
+/// Base64 and the envelope around a chunk bring it to about 350 KiB, well
+/// within the default 1 MiB payload limit.
+pub const CHUNK_BYTES: usize = 256 * 1024;
+
+/// Largest message sent or accepted in chunks; a manifest announcing more is refused
+pub const MAX_TRANSFER_BYTES: u64 = 1024 * 1024 * 1024;
+
+/// Length of one full chunk once base64-encoded
+const ENCODED_CHUNK_LEN: usize = CHUNK_BYTES.div_ceil(3) * 4;
+
+/// How much of a transfer is done, e.g. "3 of 8 chunk(s) sent"
+fn progress_of(transfer: &Transfer) -> String {
+    match transfer.direction {
+        TransferDirection::Upload => format!(
+            "{} of {} chunk(s) sent",
+            transfer.chunk_count as usize - transfer.chunks.len(),
+            transfer.chunk_count
+        ),
+        TransferDirection::Download => format!("{} of {} chunk(s) received", transfer.chunks.len(), transfer.chunk_count),
+    }
+}
+
+impl MlsChatApp {
+    /// Send `message`, already serialized as `payload`, to `recipients` as a Manifest and its Chunks
+    ///
+    /// If sending stops part way, the rest is kept for `transfers resume` and
+    /// the message counts as sent, so it is stored here all the same.
+    pub(crate) fn upload(&mut self, message: &ChatMessage, recipients: Vec<String>, payload: Vec<u8>) -> Result<()> {
+        if payload.len() as u64 > MAX_TRANSFER_BYTES {
+            return Err(anyhow!(
+                "The message is {}, more than the {} others accept",
+                human_size(payload.len() as u64), human_size(MAX_TRANSFER_BYTES)
+            ));
+        }
+        let chunks: BTreeMap<u32, String> = payload.chunks(CHUNK_BYTES)
+            .enumerate()
+            .map(|(index, chunk)| (index as u32, base64::encode(chunk)))
+            .collect();
+        let transfer = Transfer {
+            id: message.id.clone(),
+            group_id: message.group_id.clone(),
+            direction: TransferDirection::Upload,
+            sender: message.sender.clone(),
+            epoch: message.epoch,
+            size: payload.len() as u64,
+            hash: crypto::hex(&crypto::sha256(&payload)),
+            chunk_count: chunks.len() as u32,
+            chunks,
+            recipients,
+            manifest_sent: false,
+            from_seq: 0,
+            name: message.attachment.as_ref().map(|attachment| attachment.name.clone()),
+            started: Utc::now(),
+        };
+        println!("   The message is {}, too large for one envelope; sending it in {} chunk(s)", human_size(transfer.size), transfer.chunk_count);
+        self.storage.save_transfer(&transfer)?;
+        if let Err(err) = self.send_chunks(transfer) {
+            println!("⚠️  {:#}", err);
+            println!("   The message is kept here and reaches the others once the rest is sent");
+        }
+        Ok(())
+    }
+
+    /// Send an upload's manifest, if it has not gone yet, and every chunk still held
+    ///
+    /// The transfer is removed once everything is sent, and saved with what
+    /// is left otherwise.
+    fn send_chunks(&mut self, mut transfer: Transfer) -> Result<()> {
+        let mut seq = 0;
+        let mut bar = progress::Bar::new("chunks sent", transfer.chunks.len());
+        let sent = loop {
+            let piece = match transfer.chunks.first_key_value() {
+                _ if !transfer.manifest_sent => MlsMessage::Manifest {
+                    group_id: transfer.group_id.clone(),
+                    message_id: transfer.id.clone(),
+                    size: transfer.size,
+                    chunks: transfer.chunk_count,
+                    hash: transfer.hash.clone(),
+                },
+                Some((&index, data)) => MlsMessage::Chunk {
+                    group_id: transfer.group_id.clone(),
+                    message_id: transfer.id.clone(),
+                    index,
+                    data: data.clone(),
+                },
+                None => break Ok(()),
+            };
+            let request = SendMessageRequest {
+                sender: transfer.sender.clone(),
+                group_id: transfer.group_id.clone(),
+                recipients: transfer.recipients.clone(),
+                payload: piece.to_bytes()?,
+            };
+            let response = match transfer.manifest_sent {
+                false => self.transport().and_then(|transport| transport.send_message(request)),
+                true => bar.step(|| self.transport().and_then(|transport| transport.send_message(request))),
+            };
+            match (response, piece) {
+                (Ok(response), MlsMessage::Chunk { index, .. }) => {
+                    seq = response.seq;
+                    transfer.chunks.remove(&index);
+                }
+                (Ok(response), _) => {
+                    seq = response.seq;
+                    transfer.manifest_sent = true;
+                }
+                (Err(err), _) => break Err(err),
+            }
+        };
+        if seq > 0 {
+            self.record_sent_seq(&transfer.group_id, seq);
+        }
+        match sent {
+            Ok(()) => {
+                self.storage.delete_transfer(&transfer.id)?;
+                println!("   Ciphertext handed to delivery service in {} chunk(s)", transfer.chunk_count);
+                print_seq(seq);
+                Ok(())
+            }
+            Err(err) => {
+                self.storage.save_transfer(&transfer)?;
+                Err(err.context(format!(
+                    "Upload of {} stopped with {}; `transfers resume {}` sends the rest",
+                    short_id(&transfer.id),
+                    progress_of(&transfer),
+                    short_id(&transfer.id)
+                )))
+            }
+        }
+    }
+
+    /// Start receiving a chunked message announced by a Manifest
+    pub(crate) fn apply_manifest(&mut self, group_name: &str, envelope: &Envelope, message_id: String, size: u64, chunks: u32, hash: String) -> Result<()> {
+        if chunks == 0 || size == 0 {
+            return Err(anyhow!("Manifest for message {} announces no data", message_id));
+        }
+        if size > MAX_TRANSFER_BYTES || u64::from(chunks) != size.div_ceil(CHUNK_BYTES as u64) {
+            return Err(anyhow!(
+                "Manifest for message {} announces {} bytes in {} chunk(s), which no sender splits a message into; ignored",
+                short_id(&message_id), size, chunks
+            ));
+        }
+        if self.transfers.contains_key(&message_id) || self.storage.load_transfer(&message_id)?.is_some() {
+            return Ok(()); // Delivered again, as when a download is resumed
+        }
+        let group = self.groups.get(group_name).context("Group not found")?;
+        let transfer = Transfer {
+            id: message_id,
+            group_id: group.group_id.clone(),
+            direction: TransferDirection::Download,
+            sender: envelope.sender.clone(),
+            epoch: group.mls_group.epoch,
+            size,
+            hash,
+            chunk_count: chunks,
+            chunks: BTreeMap::new(),
+            recipients: Vec::new(),
+            manifest_sent: true,
+            from_seq: envelope.seq,
+            name: None,
+            started: Utc::now(),
+        };
+        println!("   📦 Receiving a message of {} in '{}' from {} in {} chunk(s)", human_size(size), group_name, envelope.sender, chunks);
+        self.transfers.insert(transfer.id.clone(), transfer);
+        Ok(())
+    }
+
+    /// Keep one chunk of a message being received; the last one in completes and applies the message
+    pub(crate) fn apply_chunk(&mut self, envelope: &Envelope, message_id: &str, index: u32, data: String) -> Result<Option<ChatMessage>> {
+        let mut transfer = match self.transfers.remove(message_id) {
+            Some(transfer) => transfer,
+            None => self.storage.load_transfer(message_id)?
+                .filter(|transfer| transfer.direction == TransferDirection::Download)
+                .with_context(|| format!("Chunk of message {}, whose manifest never arrived", short_id(message_id)))?,
+        };
+        if transfer.group_id != envelope.group_id || transfer.sender != envelope.sender || index >= transfer.chunk_count || data.len() > ENCODED_CHUNK_LEN {
+            self.transfers.insert(transfer.id.clone(), transfer);
+            return Err(anyhow!("Chunk {} does not belong to message {}", index, short_id(message_id)));
+        }
+        transfer.chunks.insert(index, data);
+        if transfer.chunks.len() < transfer.chunk_count as usize {
+            self.transfers.insert(transfer.id.clone(), transfer);
+            return Ok(None);
+        }
+
+        // Complete: whatever becomes of it, the transfer is done
+        self.storage.delete_transfer(&transfer.id)?;
+        let mut payload = Vec::new();
+        for chunk in transfer.chunks.values() {
+            payload.extend(base64::decode(chunk)?);
+        }
+        if payload.len() as u64 != transfer.size || crypto::hex(&crypto::sha256(&payload)) != transfer.hash {
+            return Err(anyhow!("Message {} does not match its manifest once reassembled; dropped", short_id(&transfer.id)));
+        }
+        if !matches!(MlsMessage::from_bytes(&payload)?, MlsMessage::Application { .. }) {
+            return Err(anyhow!("Message {} reassembled into something other than a chat message", short_id(&transfer.id)));
+        }
+        println!("   📦 All {} chunk(s) of message {} received and verified", transfer.chunk_count, short_id(&transfer.id));
+        self.apply_mls_message(&Envelope { payload, ..envelope.clone() })
+    }
+
+    /// List the chunked messages part way through sending or receiving
+    pub fn list_transfers(&self) -> Result<()> {
+        let transfers = self.all_transfers()?;
+        if transfers.is_empty() {
+            println!("No unfinished transfers.");
+        }
+        for transfer in transfers {
+            let group = self.group_name_by_id(&transfer.group_id).unwrap_or_else(|| "(unknown group)".to_string());
+            let what = match &transfer.name {
+                Some(name) => format!("{} message ({})", human_size(transfer.size), name),
+                None => format!("{} message", human_size(transfer.size)),
+            };
+            println!("{} {} of a {} in '{}' from {}, {}, started {}",
+                short_id(&transfer.id).dimmed(),
+                transfer.direction,
+                what,
+                group,
+                transfer.sender,
+                progress_of(&transfer),
+                transfer.started.format("%Y-%m-%d %H:%M UTC"),
+            );
+        }
+        Ok(())
+    }
+
+    /// Send the rest of an upload, or fetch the missing chunks of a download; `id` may be a unique prefix
+    pub fn resume_transfer(&mut self, id: &str) -> Result<()> {
+        let transfer = self.find_transfer(id)?;
+        let group_name = self.group_name_by_id(&transfer.group_id).context("The transfer's group is no longer in this profile")?;
+        let epoch = self.groups.get(&group_name).context("Group not found")?.mls_group.epoch;
+        match transfer.direction {
+            TransferDirection::Upload => {
+                if epoch != transfer.epoch {
+                    return Err(anyhow!(
+                        "'{}' has moved from epoch {} to {}, so the message can no longer be read; drop it with `transfers drop {}` and send it again",
+                        group_name, transfer.epoch, epoch, short_id(&transfer.id)
+                    ));
+                }
+                println!("{}", format!("Resuming upload of {}: {}", short_id(&transfer.id), progress_of(&transfer)).green());
+                self.send_chunks(transfer)
+            }
+            TransferDirection::Download => {
+                let user = self.current_user.context("No user initialized")?.to_string();
+                println!("{}", format!("Resuming download of {}: {}", short_id(&transfer.id), progress_of(&transfer)).green());
+                let transport = self.transport()?;
+                let envelopes = progress::spin("Waiting for the delivery service", || {
+                    transport.fetch_range(&user, &transfer.group_id, transfer.from_seq.max(1))
+                })?;
+                let mut message = None;
+                for envelope in envelopes {
+                    if let Ok(MlsMessage::Chunk { message_id, index, data, .. }) = MlsMessage::from_bytes(&envelope.payload) {
+                        if message_id == transfer.id {
+                            message = self.apply_chunk(&envelope, &message_id, index, data)?.or(message);
+                        }
+                    }
+                }
+                match (message, self.transfers.get(&transfer.id)) {
+                    (Some(_), _) => println!("✅ Message {} received in '{}'", short_id(&transfer.id), group_name),
+                    (None, Some(transfer)) => println!("⏳ {}; the rest has not arrived", progress_of(transfer)),
+                    (None, None) => {}
+                }
+                self.save_state()
+            }
+        }
+    }
+
+    /// Give up on a transfer, keeping nothing of it; `id` may be a unique prefix
+    pub fn drop_transfer(&mut self, id: &str) -> Result<()> {
+        let transfer = self.find_transfer(id)?;
+        self.transfers.remove(&transfer.id);
+        self.storage.delete_transfer(&transfer.id)?;
+        println!("🗑️  Dropped {} of {} ({})", transfer.direction, short_id(&transfer.id), progress_of(&transfer));
+        if transfer.direction == TransferDirection::Upload {
+            println!("   The message stays in this profile but never reached the others");
+        }
+        Ok(())
+    }
+
+    /// Every unfinished transfer, with downloads received since the last save as they are now
+    fn all_transfers(&self) -> Result<Vec<Transfer>> {
+        let mut transfers: Vec<Transfer> = self.storage.load_transfers()?
+            .into_iter()
+            .filter(|transfer| !self.transfers.contains_key(&transfer.id))
+            .collect();
+        transfers.extend(self.transfers.values().cloned());
+        transfers.sort_by_key(|transfer| transfer.started);
+        Ok(transfers)
+    }
+
+    fn find_transfer(&self, id: &str) -> Result<Transfer> {
+        let mut matches: Vec<Transfer> = self.all_transfers()?
+            .into_iter()
+            .filter(|transfer| !id.is_empty() && transfer.id.starts_with(id))
+            .collect();
+        match matches.len() {
+            1 => Ok(matches.remove(0)),
+            0 => Err(anyhow!("No transfer '{}'; `transfers` lists them", id)),
+            count => Err(anyhow!("'{}' matches {} transfers; give more of the ID", id, count)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::UserName;
+    use crate::storage::MemoryStorage;
+
+    /// Alice's profile with one group, and an envelope from her in it
+    fn app() -> (MlsChatApp, Envelope) {
+        let mut app = MlsChatApp::with_storage(Box::new(MemoryStorage::new()));
+        app.init_user(UserName::Alice).unwrap();
+        app.create_group("Team".to_string()).unwrap();
+        let envelope = Envelope {
+            message_id: "envelope".to_string(),
+            sender: "Alice".to_string(),
+            group_id: app.groups["Team"].group_id.clone(),
+            payload: Vec::new(),
+            received_at: Utc::now(),
+            seq: 0,
+        };
+        (app, envelope)
+    }
+
+    /// A well-formed message over two chunks that is not an Application
+    fn payload(envelope: &Envelope) -> Vec<u8> {
+        let piece = MlsMessage::Chunk { group_id: envelope.group_id.clone(), message_id: "inner".to_string(), index: 0, data: "x".repeat(CHUNK_BYTES) };
+        piece.to_bytes().unwrap()
+    }
+
+    /// Announce `payload` and deliver its chunks, each passed through `corrupt` first
+    fn deliver(app: &mut MlsChatApp, envelope: &Envelope, payload: &[u8], corrupt: impl Fn(u32, &mut Vec<u8>)) -> Result<Option<ChatMessage>> {
+        let chunks: Vec<&[u8]> = payload.chunks(CHUNK_BYTES).collect();
+        let hash = crypto::hex(&crypto::sha256(payload));
+        app.apply_manifest("Team", envelope, "big".to_string(), payload.len() as u64, chunks.len() as u32, hash)?;
+        let mut last = Ok(None);
+        for (index, chunk) in chunks.into_iter().enumerate() {
+            let mut chunk = chunk.to_vec();
+            corrupt(index as u32, &mut chunk);
+            last = app.apply_chunk(envelope, "big", index as u32, base64::encode(&chunk));
+        }
+        last
+    }
+
+    #[test]
+    fn reassembles_and_verifies_chunks() {
+        let (mut app, envelope) = app();
+        let payload = payload(&envelope);
+        assert_eq!(payload.len().div_ceil(CHUNK_BYTES), 2);
+        let err = deliver(&mut app, &envelope, &payload, |_, _| {}).unwrap_err();
+        // The hash matched, so what stopped it is what the chunks joined into
+        assert!(err.to_string().contains("something other than a chat message"), "{:#}", err);
+        assert!(app.transfers.is_empty());
+    }
+
+    #[test]
+    fn drops_transfers_with_a_corrupted_chunk() {
+        let (mut app, envelope) = app();
+        let payload = payload(&envelope);
+        let err = deliver(&mut app, &envelope, &payload, |index, chunk| if index == 1 { chunk[10] ^= 1 }).unwrap_err();
+        assert!(err.to_string().contains("does not match its manifest"), "{:#}", err);
+        assert!(app.transfers.is_empty());
+        assert!(app.storage.load_transfer("big").unwrap().is_none());
+    }
+
+    #[test]
+    fn refuses_manifests_that_do_not_fit_their_chunks() {
+        let (mut app, envelope) = app();
+        let announce = |app: &mut MlsChatApp, size: u64, chunks: u32| app.apply_manifest("Team", &envelope, "big".to_string(), size, chunks, String::new());
+        assert!(announce(&mut app, 0, 0).is_err());
+        assert!(announce(&mut app, MAX_TRANSFER_BYTES + 1, (MAX_TRANSFER_BYTES + 1).div_ceil(CHUNK_BYTES as u64) as u32).is_err());
+        assert!(announce(&mut app, CHUNK_BYTES as u64 * 3, 2).is_err());
+        assert!(announce(&mut app, 10, 2).is_err());
+        assert!(app.transfers.is_empty());
+        assert!(announce(&mut app, CHUNK_BYTES as u64 + 1, 2).is_ok());
+    }
+
+    #[test]
+    fn refuses_chunks_that_do_not_belong() {
+        let (mut app, envelope) = app();
+        app.apply_manifest("Team", &envelope, "big".to_string(), CHUNK_BYTES as u64 + 1, 2, String::new()).unwrap();
+        let oversized = base64::encode(&vec![0; CHUNK_BYTES + 3]);
+        assert!(app.apply_chunk(&envelope, "big", 0, oversized).is_err());
+        assert!(app.apply_chunk(&envelope, "big", 2, base64::encode(b"x")).is_err());
+        let stranger = Envelope { sender: "Mallory".to_string(), ..envelope.clone() };
+        assert!(app.apply_chunk(&stranger, "big", 0, base64::encode(b"x")).is_err());
+        assert!(app.apply_chunk(&envelope, "unknown", 0, base64::encode(b"x")).is_err());
+        // The transfer is kept for the real chunks
+        assert!(app.transfers["big"].chunks.is_empty());
+    }
+}
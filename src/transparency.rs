@@ -0,0 +1,419 @@
+//! Key transparency: an append-only Merkle log of identity keys
+//!
+//! With `transparency.log` set in the config file, publishing a key package
+//! also appends the key's fingerprint to the log, and every key package
+//! fetched from the delivery service is checked against it: the user's
+//! latest log entry must be included in the log's current tree, by an
+//! RFC 9162 inclusion proof, and name the same key. A delivery service
+//! handing out a key of its own for someone is then caught, because it
+//! cannot also write that key into a log it does not control.
+//!
+//! The log here is a local JSON Lines file that several profiles may share,
+//! standing in for a CT-style log service; a service would hand out the
+//! tree head and proofs that `inclusion_proof` computes from the file.
+//! Users with no entry yet are accepted with a warning.
+//!
+//! Because the proof and the root come from the same file, the inclusion
+//! check alone proves nothing about a file that was edited. Each profile
+//! therefore remembers the last tree head it checked, its size and root, and
+//! refuses a log whose first that many entries no longer hash to that root:
+//! one that was truncated, or had an entry swapped for another key. While
+//! the log only grows, the remembered head moves forward with it. This is
+//! the consistency check a log service would prove with RFC 9162
+//! consistency proofs, done here from the entries themselves.
+
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
+use colored::*;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::app::MlsChatApp;
+use crate::crypto::{self, hex, sha256};
+use crate::delivery::KeyPackage;
+use crate::model::TreeHead;
+
+type Hash = [u8; 32];
+
+/// One published key
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LogEntry {
+    pub user: String,
+    pub key_id: String,
+    pub fingerprint: String, // As `crypto::fingerprint` prints it
+    pub at: DateTime<Utc>,
+}
+
+/// A transparency log kept in a JSON Lines file, one entry per line
+#[derive(Debug, Clone)]
+pub struct TransparencyLog {
+    path: PathBuf,
+}
+
+impl TransparencyLog {
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self { path: path.as_ref().to_path_buf() }
+    }
+
+    /// Every entry, oldest first; a missing file is an empty log
+    pub fn entries(&self) -> Result<Vec<LogEntry>> {
+        if !self.path.exists() {
+            return Ok(Vec::new());
+        }
+        let data = fs::read_to_string(&self.path)
+            .with_context(|| format!("Failed to read transparency log {}", self.path.display()))?;
+        data.lines()
+            .filter(|line| !line.trim().is_empty())
+            .enumerate()
+            .map(|(index, line)| {
+                serde_json::from_str(line).with_context(|| format!("Transparency log entry {} is damaged", index))
+            })
+            .collect()
+    }
+
+    /// Append an entry; returns its index
+    pub fn append(&self, entry: &LogEntry) -> Result<usize> {
+        let index = self.entries()?.len();
+        if let Some(dir) = self.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path)
+            .with_context(|| format!("Failed to open transparency log {}", self.path.display()))?;
+        writeln!(file, "{}", serde_json::to_string(entry)?)?;
+        Ok(index)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// Leaf hash of an entry, `SHA-256(0x00 || entry)`
+pub fn leaf_hash(entry: &LogEntry) -> Result<Hash> {
+    let mut data = vec![0u8];
+    data.extend(serde_json::to_vec(entry)?);
+    Ok(sha256(&data))
+}
+
+fn node_hash(left: &Hash, right: &Hash) -> Hash {
+    let mut data = vec![1u8];
+    data.extend_from_slice(left);
+    data.extend_from_slice(right);
+    sha256(&data)
+}
+
+/// Largest power of two below `n`, for `n` > 1
+fn split(n: usize) -> usize {
+    let mut k = 1;
+    while k * 2 < n {
+        k *= 2;
+    }
+    k
+}
+
+/// The Merkle tree hash of `leaves`; the empty tree hashes the empty string
+pub fn tree_root(leaves: &[Hash]) -> Hash {
+    match leaves.len() {
+        0 => sha256(&[]),
+        1 => leaves[0],
+        n => {
+            let k = split(n);
+            node_hash(&tree_root(&leaves[..k]), &tree_root(&leaves[k..]))
+        }
+    }
+}
+
+/// The audit path proving `leaves[index]` is in the tree of `leaves`
+pub fn inclusion_proof(leaves: &[Hash], index: usize) -> Vec<Hash> {
+    if leaves.len() <= 1 {
+        return Vec::new();
+    }
+    let k = split(leaves.len());
+    let (mut path, sibling) = match index < k {
+        true => (inclusion_proof(&leaves[..k], index), tree_root(&leaves[k..])),
+        false => (inclusion_proof(&leaves[k..], index - k), tree_root(&leaves[..k])),
+    };
+    path.push(sibling);
+    path
+}
+
+/// Check an inclusion proof, as in RFC 9162 section 2.1.3.2
+pub fn verify_inclusion(leaf: &Hash, index: usize, size: usize, proof: &[Hash], root: &Hash) -> bool {
+    if index >= size {
+        return false;
+    }
+    let (mut fn_, mut sn, mut r) = (index, size - 1, *leaf);
+    for p in proof {
+        if sn == 0 {
+            return false;
+        }
+        if fn_ & 1 == 1 || fn_ == sn {
+            r = node_hash(p, &r);
+            while fn_ & 1 == 0 && fn_ != 0 {
+                fn_ >>= 1;
+                sn >>= 1;
+            }
+        } else {
+            r = node_hash(&r, p);
+        }
+        fn_ >>= 1;
+        sn >>= 1;
+    }
+    sn == 0 && r == *root
+}
+
+/// Where a user's key sits in the log, with the proof that checked it
+pub struct Inclusion {
+    pub entry: LogEntry,
+    pub index: usize,
+    pub size: usize,
+    pub root: String, // Hex tree head the proof was checked against
+}
+
+/// Check that the first `head.size` of `leaves` still hash to the remembered root
+pub fn check_consistency(leaves: &[Hash], head: &TreeHead) -> Result<()> {
+    if leaves.len() < head.size {
+        return Err(anyhow!(
+            "The transparency log has {} entries, fewer than the {} it had when this profile last checked it; it was truncated or replaced",
+            leaves.len(), head.size
+        ));
+    }
+    if hex(&tree_root(&leaves[..head.size])) != head.root {
+        return Err(anyhow!(
+            "The first {} entries of the transparency log no longer hash to the root this profile checked ({}); it was rewritten",
+            head.size, head.root
+        ));
+    }
+    Ok(())
+}
+
+/// Check that a key package names the key its user's latest entry in `entries` does
+///
+/// `entries` should come from `checked_log_entries`.
+pub(crate) fn check_key_package(entries: &[LogEntry], key_package: &KeyPackage) -> Result<()> {
+    let user = &key_package.user;
+    let Some(inclusion) = check_inclusion(entries, user)? else {
+        println!("   ⚠️  {} has no key in the transparency log; it cannot be checked", user);
+        return Ok(());
+    };
+    let fingerprint = crypto::fingerprint(&key_package.public_key);
+    if inclusion.entry.fingerprint != fingerprint {
+        return Err(anyhow!(
+            "Possible key substitution: the delivery service's key for {} ({}) is not the one {} published to the transparency log ({})",
+            user, fingerprint, user, inclusion.entry.fingerprint
+        ));
+    }
+    println!("   🔍 Key for {} matches transparency log entry {} of {}", user, inclusion.index, inclusion.size);
+    Ok(())
+}
+
+/// Find `user`'s latest entry in `entries` and check its inclusion proof
+pub fn check_inclusion(entries: &[LogEntry], user: &str) -> Result<Option<Inclusion>> {
+    let Some(index) = entries.iter().rposition(|entry| entry.user == user) else {
+        return Ok(None);
+    };
+    let leaves = entries.iter().map(leaf_hash).collect::<Result<Vec<Hash>>>()?;
+    let root = tree_root(&leaves);
+    let proof = inclusion_proof(&leaves, index);
+    if !verify_inclusion(&leaves[index], index, leaves.len(), &proof, &root) {
+        return Err(anyhow!("The transparency log's proof for {}'s entry {} does not check out", user, index));
+    }
+    Ok(Some(Inclusion { entry: entries[index].clone(), index, size: leaves.len(), root: hex(&root) }))
+}
+
+impl MlsChatApp {
+    /// Append the current user's key to the transparency log, unless it is already the latest entry for them
+    pub fn publish_to_transparency_log(&mut self) -> Result<()> {
+        let user = self.current_user.context("No user initialized")?.to_string();
+        let entries = self.checked_log_entries()?.context("No transparency log; set `transparency.log` in the config file")?;
+        let log = self.transparency_log.clone().context("No transparency log")?;
+        let key = self.user_keys.get(&user).with_context(|| format!("User '{}' not initialized", user))?;
+        if entries.iter().rev().find(|entry| entry.user == user).is_some_and(|entry| entry.key_id == key.id) {
+            println!("   Key for '{}' already in the transparency log", user);
+            return Ok(());
+        }
+        let entry = LogEntry { user: user.clone(), key_id: key.id.clone(), fingerprint: crypto::fingerprint(&key.public_key), at: Utc::now() };
+        let index = log.append(&entry)?;
+        self.checked_log_entries()?; // Moves the remembered head past the new entry
+        println!("✅ Key for '{}' appended to the transparency log as entry {}", user, index);
+        Ok(())
+    }
+
+    /// The transparency log's entries, if one is set, once they are consistent with the tree head this profile last checked
+    ///
+    /// The head is then moved to the log as it is now. A log this profile has
+    /// never checked is taken as it is, the way `trust` pins a first key.
+    pub(crate) fn checked_log_entries(&mut self) -> Result<Option<Vec<LogEntry>>> {
+        let Some(log) = &self.transparency_log else { return Ok(None) };
+        let entries = log.entries()?;
+        let leaves = entries.iter().map(leaf_hash).collect::<Result<Vec<Hash>>>()?;
+        let name = log.path().display().to_string();
+        let mut heads = self.storage.load_tree_heads()?;
+        if let Some(head) = heads.get(&name) {
+            check_consistency(&leaves, head)?;
+            if head.size == leaves.len() {
+                return Ok(Some(entries));
+            }
+        }
+        heads.insert(name, TreeHead { size: leaves.len(), root: hex(&tree_root(&leaves)), checked: Utc::now() });
+        self.storage.save_tree_heads(&heads)?;
+        Ok(Some(entries))
+    }
+
+    /// Check a fetched key package against the transparency log, if one is set
+    pub(crate) fn check_transparency(&mut self, key_package: &KeyPackage) -> Result<()> {
+        match self.checked_log_entries()? {
+            Some(entries) => check_key_package(&entries, key_package),
+            None => Ok(()),
+        }
+    }
+
+    /// Fetch a user's key package from the delivery service, check it against the transparency log, and pin it if new
+    pub(crate) fn fetch_checked_key_package(&mut self, user: &str) -> Result<KeyPackage> {
+        let key_package = self.transport()?.fetch_key_package(user)?;
+        self.check_transparency(&key_package)?;
+        self.observe_key(user, &key_package.public_key)?;
+        Ok(key_package)
+    }
+
+    /// Show the log's tree head and, for `user`, their entry and its inclusion proof
+    pub fn show_transparency(&mut self, user: Option<&str>) -> Result<()> {
+        let log = self.transparency_log.clone().context("No transparency log; set `transparency.log` in the config file")?;
+        let entries = self.checked_log_entries()?.context("No transparency log")?;
+        let leaves = entries.iter().map(leaf_hash).collect::<Result<Vec<Hash>>>()?;
+        println!("{}", format!("Transparency log {}", log.path().display()).blue());
+        println!("Tree size: {}", entries.len());
+        println!("Root: {}", hex(&tree_root(&leaves)));
+        let Some(user) = user else {
+            for (index, entry) in entries.iter().enumerate() {
+                println!("   {:>4} {} {} {}", index, entry.at.format("%Y-%m-%d %H:%M UTC"), entry.user, entry.fingerprint);
+            }
+            return Ok(());
+        };
+        let inclusion = check_inclusion(&entries, user)?
+            .with_context(|| format!("'{}' has no key in the transparency log", user))?;
+        println!("Entry {} for {}: {}", inclusion.index, user, inclusion.entry.fingerprint);
+        println!("   Inclusion proof of {} hash(es) checks out against the root", inclusion_proof(&leaves, inclusion.index).len());
+        match self.local_key_package(user) {
+            Some(key_package) => self.check_transparency(&key_package),
+            None => self.fetch_checked_key_package(user).map(|_| ()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The leaves of the RFC 6962 / RFC 9162 reference test tree
+    const LEAVES: [&[u8]; 8] = [
+        b"",
+        &[0x00],
+        &[0x10],
+        &[0x20, 0x21],
+        &[0x30, 0x31],
+        &[0x40, 0x41, 0x42, 0x43],
+        &[0x50, 0x51, 0x52, 0x53, 0x54, 0x55, 0x56, 0x57],
+        &[0x60, 0x61, 0x62, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68, 0x69, 0x6a, 0x6b, 0x6c, 0x6d, 0x6e, 0x6f],
+    ];
+
+    /// Tree heads of the first 1 to 8 leaves
+    const ROOTS: [&str; 8] = [
+        "6e340b9cffb37a989ca544e6bb780a2c78901d3fb33738768511a30617afa01d",
+        "fac54203e7cc696cf0dfcb42c92a1d9dbaf70ad9e621f4bd8d98662f00e3c125",
+        "aeb6bcfe274b70a14fb067a5e5578264db0fa9b51af5e0ba159158f329e06e77",
+        "d37ee418976dd95753c1c73862b9398fa2a2cf9b4ff0fdfe8b30cd95209614b7",
+        "4e3bbb1f7b478dcfe71fb631631519a3bca12c9aefca1612bfce4c13a86264d4",
+        "76e67dadbcdf1e10e1b74ddc608abd2f98dfb16fbce75277b5232a127f2087ef",
+        "ddb89be403809e325750d3d263cd78929c2942b7942a34b77e122c9594a74c8c",
+        "5dc9da79a70659a9ad559cb701ded9a2ab9d823aad2f4960cfe370eff4604328",
+    ];
+
+    fn leaves() -> Vec<Hash> {
+        LEAVES.iter().map(|leaf| sha256(&[&[0u8][..], leaf].concat())).collect()
+    }
+
+    fn unhex(hex: &str) -> Hash {
+        let bytes: Vec<u8> = (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap()).collect();
+        bytes.try_into().unwrap()
+    }
+
+    #[test]
+    fn tree_heads_match_the_reference_vectors() {
+        let leaves = leaves();
+        for (size, root) in ROOTS.iter().enumerate() {
+            assert_eq!(hex(&tree_root(&leaves[..size + 1])), *root, "tree of {} leaves", size + 1);
+        }
+        assert_eq!(hex(&tree_root(&[])), "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+    }
+
+    #[test]
+    fn inclusion_proofs_match_the_reference_vectors() {
+        let leaves = leaves();
+        let cases: [(usize, usize, &[&str]); 4] = [
+            (0, 8, &[
+                "96a296d224f285c67bee93c30f8a309157f0daa35dc5b87e410b78630a09cfc7",
+                "5f083f0a1a33ca076a95279832580db3e0ef4584bdff1f54c8a360f50de3031e",
+                "6b47aaf29ee3c2af9af889bc1fb9254dabd31177f16232dd6aab035ca39bf6e4",
+            ]),
+            (5, 8, &[
+                "bc1a0643b12e4d2d7c77918f44e0f4f79a838b6cf9ec5b5c283e1f4d88599e6b",
+                "ca854ea128ed050b41b35ffc1b87b8eb2bde461e9e3b5596ece6b9d5975a0ae0",
+                "d37ee418976dd95753c1c73862b9398fa2a2cf9b4ff0fdfe8b30cd95209614b7",
+            ]),
+            (2, 3, &["fac54203e7cc696cf0dfcb42c92a1d9dbaf70ad9e621f4bd8d98662f00e3c125"]),
+            (1, 5, &[
+                "6e340b9cffb37a989ca544e6bb780a2c78901d3fb33738768511a30617afa01d",
+                "5f083f0a1a33ca076a95279832580db3e0ef4584bdff1f54c8a360f50de3031e",
+                "bc1a0643b12e4d2d7c77918f44e0f4f79a838b6cf9ec5b5c283e1f4d88599e6b",
+            ]),
+        ];
+        for (index, size, expected) in cases {
+            let proof = inclusion_proof(&leaves[..size], index);
+            assert_eq!(proof.iter().map(|hash| hex(hash)).collect::<Vec<_>>(), expected, "leaf {} of {}", index, size);
+            assert!(verify_inclusion(&leaves[index], index, size, &proof, &unhex(ROOTS[size - 1])));
+        }
+    }
+
+    #[test]
+    fn tampered_inclusion_proofs_fail() {
+        let leaves = leaves();
+        let root = unhex(ROOTS[7]);
+        let proof = inclusion_proof(&leaves, 5);
+        assert!(verify_inclusion(&leaves[5], 5, 8, &proof, &root));
+
+        let mut flipped = proof.clone();
+        flipped[1][0] ^= 1;
+        assert!(!verify_inclusion(&leaves[5], 5, 8, &flipped, &root));
+        assert!(!verify_inclusion(&leaves[4], 5, 8, &proof, &root), "a different leaf");
+        assert!(!verify_inclusion(&leaves[5], 4, 8, &proof, &root), "a different index");
+        assert!(!verify_inclusion(&leaves[5], 5, 6, &proof, &root), "a different size");
+        assert!(!verify_inclusion(&leaves[5], 8, 8, &proof, &root), "an index past the end");
+        assert!(!verify_inclusion(&leaves[5], 5, 8, &proof[..2], &root), "a short proof");
+        let mut long = proof.clone();
+        long.push(root);
+        assert!(!verify_inclusion(&leaves[5], 5, 8, &long, &root), "a long proof");
+    }
+
+    #[test]
+    fn logs_must_extend_the_remembered_tree_head() {
+        let leaves = leaves();
+        let head = TreeHead { size: 5, root: ROOTS[4].to_string(), checked: Utc::now() };
+        check_consistency(&leaves[..5], &head).unwrap();
+        check_consistency(&leaves, &head).unwrap();
+
+        let err = check_consistency(&leaves[..4], &head).unwrap_err();
+        assert!(err.to_string().contains("truncated"));
+        let mut swapped = leaves.clone();
+        swapped[2] = swapped[3];
+        let err = check_consistency(&swapped, &head).unwrap_err();
+        assert!(err.to_string().contains("rewritten"));
+        // Changes past the remembered head are new entries, not a rewrite
+        swapped[2] = leaves[2];
+        swapped[6] = leaves[0];
+        check_consistency(&swapped, &head).unwrap();
+    }
+}
@@ -0,0 +1,39 @@
+//! Client-side access to a delivery service
+
+use anyhow::{anyhow, Result};
+
+use crate::delivery::{Envelope, KeyPackage, Presence, SendMessageRequest, SendMessageResponse};
+
+/// How the app reaches the delivery service
+///
+/// Implementations carry the delivery service calls over some channel; the
+/// app only deals in key packages and envelopes.
+pub trait Transport: Send {
+    /// Publish the local user's key package so others can add them
+    fn publish_key_package(&mut self, key_package: KeyPackage) -> Result<()>;
+    /// Fetch another user's key package
+    fn fetch_key_package(&mut self, user: &str) -> Result<KeyPackage>;
+    /// Queue a message for its recipients, returning its server message ID and sequence number
+    fn send_message(&mut self, request: SendMessageRequest) -> Result<SendMessageResponse>;
+    /// Take everything queued for `user`
+    fn fetch_inbox(&mut self, user: &str) -> Result<Vec<Envelope>>;
+    /// Re-read `user`'s envelopes in a group from sequence number `from_seq` onwards
+    ///
+    /// Only transports backed by a sequencing delivery service support this.
+    fn fetch_range(&mut self, user: &str, group_id: &str, from_seq: u64) -> Result<Vec<Envelope>> {
+        let _ = (user, group_id, from_seq);
+        Err(anyhow!("This transport does not keep message history to fetch from"))
+    }
+    /// Tell the delivery service `user` is online
+    fn heartbeat(&mut self, user: &str) -> Result<()> {
+        let _ = user;
+        Ok(())
+    }
+    /// Who of `users` is online, and when each was last seen
+    ///
+    /// Only transports backed by a delivery service that tracks presence support this.
+    fn fetch_presence(&mut self, users: &[String]) -> Result<Vec<Presence>> {
+        let _ = users;
+        Err(anyhow!("This transport does not track presence"))
+    }
+}
@@ -0,0 +1,728 @@
+//! The ratchet tree behind TreeKEM commits
+//!
+//! Groups created since the tree existed keep one in `MockMlsGroup::tree`,
+//! laid out as in RFC 9420 appendix C: a complete binary tree in an array,
+//! leaf `i` at node `2i` and parents at the odd indices, as many leaves wide
+//! as the next power of two. Leaves hold a member and their leaf key; parents
+//! hold a key shared by the leaves below them, or are blank.
+//!
+//! A commit gives the committer's leaf a fresh secret and hashes it up the
+//! direct path, one path secret per parent. Each path secret is encrypted to
+//! the resolution of the copath child beside it, the fewest nodes whose keys
+//! cover that subtree, so in a full tree a commit costs one ciphertext per
+//! level: O(log n) whether it adds, removes, or updates. The root's secret
+//! becomes the commit secret, mixed with the previous epoch's secret to give
+//! the next. A removed leaf's direct path is blanked first, so nothing the
+//! commit encrypts is under a key the removed member knew. Only the nodes a
+//! commit touches change, and the tree hash is recomputed for those alone.
+//! A member leaving commits their own removal with no path and an empty
+//! commit secret, so they could still work out the next epoch; the next
+//! commit by anyone who stays shuts them out.
+//!
+//! Blank parents widen resolutions. Until a member commits once, the parents
+//! only they sit under stay blank and commits reach that subtree leaf by
+//! leaf, so a tree filled by adds alone costs up to O(n) per commit, as in
+//! MLS; each member's first `update` brings it back down.
+//!
+//! The keys are the mock's: a node's public key is derived from its secret,
+//! and encrypting to it uses a key derived from the public value, the way
+//! `protocol::welcome_key` does. Path lengths, ciphertext counts, and which
+//! members can derive which secrets are TreeKEM's; the secrecy is not.
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use uuid::Uuid;
+
+use crate::crypto;
+use crate::protocol;
+
+/// One node's key, with the member a leaf belongs to
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TreeNode {
+    pub public_key: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub member: Option<String>, // Leaves only
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub unmerged: Vec<u32>, // Parents only: leaves added below since the key was set, which do not know it
+}
+
+/// A group's ratchet tree
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RatchetTree {
+    nodes: Vec<Option<TreeNode>>, // 2 * width - 1 of them; `None` is blank
+    #[serde(skip)]
+    hashes: Vec<Option<[u8; 32]>>, // Each subtree's hash, filled in as needed
+    #[serde(skip)]
+    index: Option<LeafIndex>, // Built on first use
+}
+
+/// Where each member's leaf is, and which leaves are free
+#[derive(Debug, Clone, Default)]
+struct LeafIndex {
+    leaves: HashMap<String, u32>,
+    used: u32, // One past the rightmost member's leaf; every leaf from here on is blank
+    free: BTreeSet<u32>, // Blank leaves left of `used`
+}
+
+impl LeafIndex {
+    fn take(&mut self, member: String, leaf: u32) {
+        self.leaves.insert(member, leaf);
+        self.free.extend(self.used..leaf);
+        self.free.remove(&leaf);
+        self.used = self.used.max(leaf + 1);
+    }
+
+    fn release(&mut self, member: &str, leaf: u32) {
+        self.leaves.remove(member);
+        self.free.insert(leaf);
+        while self.used > 0 && self.free.last() == Some(&(self.used - 1)) {
+            self.free.pop_last();
+            self.used -= 1;
+        }
+    }
+}
+
+/// A member added by a commit, with the key from their key package
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LeafAdd {
+    pub member: String,
+    pub public_key: String,
+}
+
+/// A path secret encrypted to one node's key
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SealedSecret {
+    pub node: u32,
+    pub ciphertext: String,
+}
+
+/// The new key of one parent on the committer's direct path, with its secret for the copath below
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PathNode {
+    pub public_key: String,
+    pub secrets: Vec<SealedSecret>, // One per node in the copath child's resolution
+}
+
+/// The committer's new leaf key and direct path
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UpdatePath {
+    pub leaf: u32,
+    pub leaf_key: String,
+    pub nodes: Vec<PathNode>, // Leaf's parent first, root last
+}
+
+/// What a commit does to the tree: proposals in order, removes before adds, then the path
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TreeCommit {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub removes: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub adds: Vec<LeafAdd>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<UpdatePath>, // None when the committer leaves, so has no leaf to commit from
+}
+
+/// What the tree looked like before a commit changed it, for putting it back
+#[derive(Debug)]
+pub(crate) struct Undo {
+    len: usize,
+    saved: Vec<(u32, Option<TreeNode>)>,
+}
+
+/// A commit applied to the tree, and the path secrets it gave or took away
+#[derive(Debug)]
+pub(crate) struct Applied {
+    pub commit_secret: String,
+    pub learned: Vec<(u32, String)>, // Node and path secret
+    pub forgotten: Vec<u32>, // Nodes whose secrets no longer hold
+    pub welcomes: Vec<(String, SealedSecret)>, // For the committer: each joiner's lowest path secret, still unsealed
+    pub undo: Undo,
+}
+
+impl Applied {
+    /// Bring the profile's path secrets up to date
+    pub(crate) fn update(&self, secrets: &mut BTreeMap<u32, String>) {
+        for node in &self.forgotten {
+            secrets.remove(node);
+        }
+        secrets.extend(self.learned.iter().cloned());
+    }
+}
+
+/// Path secrets by node, stored as `[node, secret]` pairs
+///
+/// Group files are read through a `#[serde(flatten)]`, which cannot give a
+/// map its integer keys back.
+pub(crate) mod node_pairs {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::collections::BTreeMap;
+
+    pub fn serialize<S: Serializer>(secrets: &BTreeMap<u32, String>, serializer: S) -> Result<S::Ok, S::Error> {
+        secrets.iter().collect::<Vec<_>>().serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<BTreeMap<u32, String>, D::Error> {
+        Ok(Vec::<(u32, String)>::deserialize(deserializer)?.into_iter().collect())
+    }
+}
+
+fn level(node: u32) -> u32 {
+    node.trailing_ones()
+}
+
+fn left(node: u32) -> u32 {
+    node ^ (1 << (level(node) - 1))
+}
+
+fn right(node: u32) -> u32 {
+    node ^ (3 << (level(node) - 1))
+}
+
+fn parent(node: u32) -> u32 {
+    let k = level(node);
+    let b = (node >> (k + 1)) & 1;
+    (node | (1 << k)) ^ (b << (k + 1))
+}
+
+fn sibling(node: u32) -> u32 {
+    let p = parent(node);
+    match node < p {
+        true => right(p),
+        false => left(p),
+    }
+}
+
+/// The leftmost leaf under `node`
+fn first_leaf(node: u32) -> u32 {
+    (node + 1 - (1 << level(node))) / 2
+}
+
+/// The hash of a wholly blank subtree `level` levels high, as `RatchetTree::hash` would work it out
+fn blank_hash(level: u32) -> [u8; 32] {
+    let mut hash = crypto::sha256(b"leaf blank");
+    for _ in 0..level {
+        let mut data = b"parent\nblank\n".to_vec();
+        data.extend_from_slice(&hash);
+        data.extend_from_slice(&hash);
+        hash = crypto::sha256(&data);
+    }
+    hash
+}
+
+/// True if `node` is `other` or above it
+fn covers(node: u32, other: u32) -> bool {
+    node.abs_diff(other) < (1 << level(node))
+}
+
+/// The next path secret up from `secret`
+fn next_secret(secret: &str) -> String {
+    crypto::hex(&crypto::derive_key(secret, "mls-chat path"))
+}
+
+/// The public key of the node whose secret is `secret`
+pub fn public_key(secret: &str) -> String {
+    crypto::hex(&crypto::derive_key(secret, "mls-chat node public"))
+}
+
+/// The next epoch's group secret, from this one and a commit's commit secret
+pub fn epoch_secret(previous: &str, commit_secret: &str) -> String {
+    crypto::hex(&crypto::derive_key(&format!("{}\n{}", previous, commit_secret), "mls-chat epoch"))
+}
+
+impl Undo {
+    fn new(tree: &RatchetTree) -> Self {
+        Self { len: tree.nodes.len(), saved: Vec::new() }
+    }
+}
+
+impl RatchetTree {
+    /// A tree of one leaf, the group's creator
+    pub fn new(member: &str, public_key: &str) -> Self {
+        let leaf = TreeNode { public_key: public_key.to_string(), member: Some(member.to_string()), unmerged: Vec::new() };
+        Self { nodes: vec![Some(leaf)], ..Self::default() }
+    }
+
+    /// How many leaves wide the tree is, blank ones included
+    pub fn width(&self) -> u32 {
+        self.nodes.len().div_ceil(2) as u32
+    }
+
+    fn root(&self) -> u32 {
+        self.width() - 1
+    }
+
+    fn direct_path(&self, node: u32) -> Vec<u32> {
+        let root = self.root();
+        let mut path = Vec::new();
+        let mut node = node;
+        while node != root {
+            node = parent(node);
+            path.push(node);
+        }
+        path
+    }
+
+    fn node(&self, node: u32) -> Option<&TreeNode> {
+        self.nodes.get(node as usize).and_then(Option::as_ref)
+    }
+
+    fn index(&mut self) -> &mut LeafIndex {
+        let nodes = &self.nodes;
+        self.index.get_or_insert_with(|| {
+            let mut index = LeafIndex::default();
+            for (position, node) in nodes.iter().enumerate().step_by(2) {
+                if let Some(member) = node.as_ref().and_then(|node| node.member.clone()) {
+                    index.take(member, position as u32 / 2);
+                }
+            }
+            index
+        })
+    }
+
+    /// The leaf of `member`, if they are in the tree
+    pub fn leaf_of(&mut self, member: &str) -> Option<u32> {
+        self.index().leaves.get(member).copied()
+    }
+
+    /// Set one node, noting its old value in `undo` and keeping the hashes and index in step
+    fn set(&mut self, node: u32, value: Option<TreeNode>, undo: &mut Undo) {
+        let old = std::mem::replace(&mut self.nodes[node as usize], value);
+        undo.saved.push((node, old.clone()));
+        self.changed(node, old);
+    }
+
+    fn changed(&mut self, node: u32, old: Option<TreeNode>) {
+        if level(node) == 0 {
+            let leaf = node / 2;
+            let member = self.node(node).and_then(|new| new.member.clone());
+            if let Some(index) = self.index.as_mut() {
+                if let Some(old) = old.and_then(|old| old.member) {
+                    index.release(&old, leaf);
+                }
+                if let Some(member) = member {
+                    index.take(member, leaf);
+                }
+            }
+        }
+        let root = self.root();
+        let mut node = node;
+        loop {
+            if let Some(hash) = self.hashes.get_mut(node as usize) {
+                *hash = None;
+            }
+            if node == root {
+                break;
+            }
+            node = parent(node);
+        }
+    }
+
+    /// Put the tree back as it was before the changes `undo` recorded
+    pub(crate) fn restore(&mut self, undo: Undo) {
+        let reach = undo.saved.iter().map(|(node, _)| *node as usize + 1).max().unwrap_or(0);
+        self.nodes.resize(self.nodes.len().max(undo.len).max(reach), None);
+        for (node, value) in undo.saved.into_iter().rev() {
+            self.nodes[node as usize] = value;
+        }
+        self.nodes.truncate(undo.len);
+        self.hashes.clear();
+        self.index = None;
+    }
+
+    /// The leftmost blank leaf, doubling the tree if there is none
+    fn free_leaf(&mut self) -> u32 {
+        let width = self.width();
+        let index = self.index();
+        if let Some(leaf) = index.free.first().copied() {
+            return leaf;
+        }
+        if index.used < width {
+            return index.used;
+        }
+        self.nodes.resize(self.nodes.len() * 2 + 1, None);
+        width
+    }
+
+    /// Drop the right half of the tree while it holds no members, as RFC 9420 truncates after removes
+    fn truncate(&mut self) {
+        while self.width() > 1 && self.index().used <= self.width() / 2 {
+            // Every node there is blank, since removing a member blanks their direct path
+            let width = self.width() as usize;
+            self.nodes.truncate(width - 1);
+            self.hashes.truncate(width - 1);
+        }
+    }
+
+    /// The nodes whose keys cover the subtree under `node` between them
+    ///
+    /// Leaves from `used` on are known to be blank, so the subtrees wholly
+    /// past the last member are not walked.
+    fn resolution(&self, node: u32, used: u32) -> Vec<u32> {
+        if first_leaf(node) >= used {
+            return Vec::new();
+        }
+        match self.node(node) {
+            Some(found) => std::iter::once(node).chain(found.unmerged.iter().map(|leaf| leaf * 2)).collect(),
+            None if level(node) == 0 => Vec::new(),
+            None => {
+                let mut nodes = self.resolution(left(node), used);
+                nodes.extend(self.resolution(right(node), used));
+                nodes
+            }
+        }
+    }
+
+    /// Apply a commit's removes and adds, returning the joiners' leaf nodes
+    fn change(&mut self, commit: &TreeCommit, undo: &mut Undo, forgotten: &mut Vec<u32>) -> Result<Vec<u32>> {
+        for member in &commit.removes {
+            let leaf = self.leaf_of(member).with_context(|| format!("'{}' has no leaf in the ratchet tree", member))?;
+            let node = leaf * 2;
+            self.set(node, None, undo);
+            forgotten.push(node);
+            for parent in self.direct_path(node) {
+                if self.node(parent).is_some() {
+                    self.set(parent, None, undo);
+                }
+                forgotten.push(parent);
+            }
+        }
+        if !commit.removes.is_empty() {
+            self.truncate();
+        }
+        let mut joiners = Vec::new();
+        for add in &commit.adds {
+            if self.leaf_of(&add.member).is_some() {
+                return Err(anyhow!("'{}' already has a leaf in the ratchet tree", add.member));
+            }
+            let leaf = self.free_leaf();
+            let node = leaf * 2;
+            let joined = TreeNode { public_key: add.public_key.clone(), member: Some(add.member.clone()), unmerged: Vec::new() };
+            self.set(node, Some(joined), undo);
+            for parent in self.direct_path(node) {
+                if let Some(mut above) = self.node(parent).cloned() {
+                    above.unmerged.push(leaf);
+                    self.set(parent, Some(above), undo);
+                }
+            }
+            joiners.push(node);
+        }
+        Ok(joiners)
+    }
+
+    /// Give the committer's leaf and direct path their new keys
+    fn set_path(&mut self, member: &str, path: &UpdatePath, undo: &mut Undo) {
+        let node = path.leaf * 2;
+        let leaf = TreeNode { public_key: path.leaf_key.clone(), member: Some(member.to_string()), unmerged: Vec::new() };
+        self.set(node, Some(leaf), undo);
+        for (parent, new) in self.direct_path(node).into_iter().zip(&path.nodes) {
+            let key = TreeNode { public_key: new.public_key.clone(), member: None, unmerged: Vec::new() };
+            self.set(parent, Some(key), undo);
+        }
+    }
+
+    /// Path secrets from `node` up to the root, starting from `secret`, each checked against the tree
+    fn derive_from(&self, node: u32, secret: String) -> Result<Vec<(u32, String)>> {
+        let mut learned = Vec::new();
+        let (mut node, mut secret) = (node, secret);
+        loop {
+            let key = self.node(node).map(|found| found.public_key.as_str());
+            if key != Some(public_key(&secret).as_str()) {
+                return Err(anyhow!("The path secret for node {} does not match its public key", node));
+            }
+            learned.push((node, secret.clone()));
+            if node == self.root() {
+                return Ok(learned);
+            }
+            node = parent(node);
+            secret = next_secret(&secret);
+        }
+    }
+
+    /// Apply `commit`'s removes and adds, then commit a fresh path from `committer`'s leaf
+    ///
+    /// The commit to send is `commit` with its `path` filled in. Without a
+    /// committer, as when the committer leaves, there is no path and the
+    /// commit secret is empty.
+    pub(crate) fn commit(&mut self, committer: Option<&str>, commit: &mut TreeCommit) -> Result<Applied> {
+        let mut undo = Undo::new(self);
+        let mut forgotten = Vec::new();
+        let joiners = match self.change(commit, &mut undo, &mut forgotten) {
+            Ok(joiners) => joiners,
+            Err(err) => {
+                self.restore(undo);
+                return Err(err);
+            }
+        };
+        let leaf = committer.and_then(|member| self.leaf_of(member).map(|leaf| (member, leaf)));
+        let Some((member, leaf)) = leaf else {
+            commit.path = None;
+            return Ok(Applied { commit_secret: String::new(), learned: Vec::new(), forgotten, welcomes: Vec::new(), undo });
+        };
+
+        let node = leaf * 2;
+        let excluded: HashSet<u32> = joiners.iter().copied().collect();
+        let used = self.index().used;
+        let mut secret = Uuid::new_v4().to_string();
+        let mut learned = vec![(node, secret.clone())];
+        let mut path = UpdatePath { leaf, leaf_key: public_key(&secret), nodes: Vec::new() };
+        let mut child = node;
+        for parent in self.direct_path(node) {
+            secret = next_secret(&secret);
+            let secrets = self.resolution(sibling(child), used)
+                .into_iter()
+                .filter(|target| !excluded.contains(target))
+                .map(|target| {
+                    let key = self.node(target).map(|found| found.public_key.as_str()).unwrap_or_default();
+                    SealedSecret { node: target, ciphertext: protocol::encrypt(&protocol::node_key(key), &secret) }
+                })
+                .collect();
+            path.nodes.push(PathNode { public_key: public_key(&secret), secrets });
+            learned.push((parent, secret.clone()));
+            child = parent;
+        }
+        let welcomes = commit.adds.iter()
+            .zip(&joiners)
+            .filter_map(|(add, joiner)| {
+                let (lowest, secret) = learned.iter().skip(1).find(|(parent, _)| covers(*parent, *joiner))?;
+                Some((add.member.clone(), SealedSecret { node: *lowest, ciphertext: secret.clone() }))
+            })
+            .collect();
+        self.set_path(member, &path, &mut undo);
+        commit.path = Some(path);
+        let commit_secret = crypto::hex(&crypto::derive_key(&secret, "mls-chat commit"));
+        Ok(Applied { commit_secret, learned, forgotten, welcomes, undo })
+    }
+
+    /// Apply a commit `sender` made, opening its path with a secret this profile holds
+    ///
+    /// A node is held if `secrets` has its path secret, or if it is the leaf
+    /// of a local identity whose key is still its leaf key, which is what
+    /// `identities` maps to. The tree is left as it was if anything fails,
+    /// including the resulting tree not hashing to `tree_hash`.
+    pub(crate) fn apply(
+        &mut self,
+        commit: &TreeCommit,
+        sender: &str,
+        tree_hash: &str,
+        secrets: &BTreeMap<u32, String>,
+        identities: &HashMap<String, String>,
+    ) -> Result<Applied> {
+        let undo = Undo::new(self);
+        let mut applied = Applied { commit_secret: String::new(), learned: Vec::new(), forgotten: Vec::new(), welcomes: Vec::new(), undo };
+        match self.apply_changes(commit, sender, tree_hash, secrets, identities, &mut applied) {
+            Ok(()) => Ok(applied),
+            Err(err) => {
+                self.restore(applied.undo);
+                Err(err)
+            }
+        }
+    }
+
+    fn apply_changes(
+        &mut self,
+        commit: &TreeCommit,
+        sender: &str,
+        tree_hash: &str,
+        secrets: &BTreeMap<u32, String>,
+        identities: &HashMap<String, String>,
+        applied: &mut Applied,
+    ) -> Result<()> {
+        self.change(commit, &mut applied.undo, &mut applied.forgotten)?;
+        if let Some(path) = &commit.path {
+            let node = path.leaf * 2;
+            if self.node(node).and_then(|leaf| leaf.member.as_deref()) != Some(sender) {
+                return Err(anyhow!("The commit's path starts at leaf {}, which is not {}'s", path.leaf, sender));
+            }
+            let direct = self.direct_path(node);
+            if direct.len() != path.nodes.len() {
+                return Err(anyhow!("The commit's path has {} nodes where the tree has {}", path.nodes.len(), direct.len()));
+            }
+            let held = |target: u32| -> bool {
+                secrets.contains_key(&target) || self.node(target).is_some_and(|found| {
+                    found.member.as_ref().and_then(|member| identities.get(member)) == Some(&found.public_key)
+                })
+            };
+            let (lowest, sealed) = path.nodes.iter()
+                .enumerate()
+                .find_map(|(position, parent)| parent.secrets.iter().find(|sealed| held(sealed.node)).map(|sealed| (position, sealed)))
+                .context("No key in this profile opens the commit's path; this member may have been removed")?;
+            let key = self.node(sealed.node).map(|found| found.public_key.clone()).unwrap_or_default();
+            let secret = protocol::decrypt(&protocol::node_key(&key), &sealed.ciphertext)
+                .context("Failed to decrypt the commit's path secret")?;
+
+            applied.forgotten.push(node);
+            applied.forgotten.extend(&direct[..lowest]);
+            self.set_path(sender, path, &mut applied.undo);
+            applied.learned = self.derive_from(direct[lowest], secret)?;
+            let root_secret = &applied.learned.last().context("Empty path")?.1;
+            applied.commit_secret = crypto::hex(&crypto::derive_key(root_secret, "mls-chat commit"));
+        }
+        let hash = self.root_hash();
+        if hash != tree_hash {
+            return Err(anyhow!("The ratchet tree here hashes to {} but the commit says {}; this member's tree has diverged", hash, tree_hash));
+        }
+        Ok(())
+    }
+
+    /// Path secrets a joiner derives from the one their Welcome carries
+    pub(crate) fn join(&self, sealed: &SealedSecret, secret: String) -> Result<Vec<(u32, String)>> {
+        self.derive_from(sealed.node, secret)
+    }
+
+    fn hash(&mut self, node: u32) -> [u8; 32] {
+        if self.hashes.len() != self.nodes.len() {
+            self.hashes.resize(self.nodes.len(), None);
+        }
+        if let Some(hash) = self.hashes[node as usize] {
+            return hash;
+        }
+        if first_leaf(node) >= self.index().used {
+            return blank_hash(level(node));
+        }
+        let content = match self.node(node) {
+            Some(found) => format!(
+                "{} {} {}",
+                found.public_key,
+                found.member.as_deref().unwrap_or_default(),
+                found.unmerged.iter().map(u32::to_string).collect::<Vec<_>>().join(",")
+            ),
+            None => "blank".to_string(),
+        };
+        let hash = match level(node) {
+            0 if self.node(node).is_none() => blank_hash(0),
+            0 => crypto::sha256(format!("leaf {}\n{}", node / 2, content).as_bytes()),
+            _ => {
+                let (left, right) = (self.hash(left(node)), self.hash(right(node)));
+                let mut data = format!("parent\n{}\n", content).into_bytes();
+                data.extend_from_slice(&left);
+                data.extend_from_slice(&right);
+                crypto::sha256(&data)
+            }
+        };
+        self.hashes[node as usize] = Some(hash);
+        hash
+    }
+
+    /// The tree hash, covering every node's key, member, and unmerged leaves
+    pub fn root_hash(&mut self) -> String {
+        let root = self.root();
+        crypto::hex(&self.hash(root))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// One member's copy of the tree and what they hold
+    struct Member {
+        name: &'static str,
+        tree: RatchetTree,
+        secrets: BTreeMap<u32, String>,
+        identities: HashMap<String, String>, // Their own leaf key
+    }
+
+    impl Member {
+        fn commit(&mut self, commit: &mut TreeCommit) -> (Applied, String) {
+            let applied = self.tree.commit(Some(self.name), commit).unwrap();
+            applied.update(&mut self.secrets);
+            let hash = self.tree.root_hash();
+            (applied, hash)
+        }
+
+        fn apply(&mut self, commit: &TreeCommit, sender: &str, hash: &str) -> Result<String> {
+            let applied = self.tree.apply(commit, sender, hash, &self.secrets, &self.identities)?;
+            applied.update(&mut self.secrets);
+            Ok(applied.commit_secret)
+        }
+    }
+
+    fn leaf_key(name: &str) -> String {
+        public_key(&format!("{} leaf", name))
+    }
+
+    /// Alice's group with Bob and Carol added in one commit, everyone joined from their Welcome
+    fn group() -> Vec<Member> {
+        let mut alice = Member {
+            name: "Alice",
+            tree: RatchetTree::new("Alice", &leaf_key("Alice")),
+            secrets: BTreeMap::new(),
+            identities: HashMap::from([("Alice".to_string(), leaf_key("Alice"))]),
+        };
+        let mut commit = TreeCommit {
+            adds: ["Bob", "Carol"].iter().map(|name| LeafAdd { member: name.to_string(), public_key: leaf_key(name) }).collect(),
+            ..TreeCommit::default()
+        };
+        let (applied, _) = alice.commit(&mut commit);
+        let mut members = vec![];
+        for name in ["Bob", "Carol"] {
+            let (_, sealed) = applied.welcomes.iter().find(|(member, _)| member == name).unwrap();
+            let learned = alice.tree.join(sealed, sealed.ciphertext.clone()).unwrap();
+            let root = &learned.last().unwrap().1;
+            assert_eq!(crypto::hex(&crypto::derive_key(root, "mls-chat commit")), applied.commit_secret);
+            members.push(Member {
+                name,
+                tree: alice.tree.clone(),
+                secrets: learned.into_iter().collect(),
+                identities: HashMap::from([(name.to_string(), leaf_key(name))]),
+            });
+        }
+        members.insert(0, alice);
+        members
+    }
+
+    #[test]
+    fn joiners_agree_on_the_commit_secret() {
+        let mut members = group();
+        let hashes: Vec<String> = members.iter_mut().map(|member| member.tree.root_hash()).collect();
+        assert!(hashes.windows(2).all(|pair| pair[0] == pair[1]));
+        assert_eq!(members[0].tree.width(), 4);
+    }
+
+    #[test]
+    fn updates_reach_every_member() {
+        let mut members = group();
+        let mut commit = TreeCommit::default();
+        let (applied, hash) = members[1].commit(&mut commit);
+        assert_eq!(commit.path.as_ref().unwrap().nodes.len(), 2); // One per level
+        for index in [0, 2] {
+            let sender = members[1].name;
+            assert_eq!(members[index].apply(&commit, sender, &hash).unwrap(), applied.commit_secret);
+        }
+        // Then a commit from someone else still agrees, now that Bob's subtree has a key
+        let mut commit = TreeCommit::default();
+        let (applied, hash) = members[2].commit(&mut commit);
+        for index in [0, 1] {
+            assert_eq!(members[index].apply(&commit, "Carol", &hash).unwrap(), applied.commit_secret);
+        }
+    }
+
+    #[test]
+    fn removed_members_cannot_open_the_next_path() {
+        let mut members = group();
+        let mut commit = TreeCommit { removes: vec!["Alice".to_string()], ..TreeCommit::default() };
+        let (applied, hash) = members[2].commit(&mut commit);
+        assert_eq!(members[1].apply(&commit, "Carol", &hash).unwrap(), applied.commit_secret);
+        assert!(members[1].tree.leaf_of("Alice").is_none());
+
+        let before = members[0].tree.root_hash();
+        let err = members[0].apply(&commit, "Carol", &hash).unwrap_err();
+        assert!(err.to_string().contains("No key in this profile opens"), "{:#}", err);
+        assert_eq!(members[0].tree.root_hash(), before); // Left as it was
+    }
+
+    #[test]
+    fn refuses_commits_that_do_not_match() {
+        let mut members = group();
+        let mut commit = TreeCommit::default();
+        let (_, hash) = members[1].commit(&mut commit);
+        assert!(members[0].apply(&commit, "Bob", "not the hash").is_err());
+        assert!(members[0].apply(&commit, "Carol", &hash).is_err()); // Bob's path, claimed by Carol
+        let mut short = commit.clone();
+        short.path.as_mut().unwrap().nodes.pop();
+        assert!(members[0].apply(&short, "Bob", &hash).is_err());
+        // None of those changed Alice's tree, so the real commit still applies
+        assert!(members[0].apply(&commit, "Bob", &hash).is_ok());
+    }
+}
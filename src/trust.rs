@@ -0,0 +1,126 @@
+//! Trust store: peer identity keys pinned on first use
+//!
+//! Each identity in the profile keeps its own record of the keys other
+//! users hold. The first key seen for a user, from a key package fetched to
+//! add them or on a message they sent, is pinned. A different key later is
+//! not trusted: it is warned about loudly, kept as pending, and messages
+//! sent with it are flagged in `list` until `trust accept <user>` pins it.
+//! A key change signed by the pinned key (`identity rotate`) is checked but
+//! still needs `trust accept` (see `continuity`). Messages from trusted keys
+//! are marked 🔒.
+//!
+//! Application messages in this mock are not signed, so the key a message
+//! arrives with is the one its sender claims; in MLS it is the sender's leaf
+//! credential, and the message signature proves it.
+
+use anyhow::{Context, Result};
+use chrono::Utc;
+use colored::*;
+use std::collections::HashMap;
+
+use crate::app::MlsChatApp;
+use crate::crypto;
+use crate::model::{ChatMessage, PeerKey, UserName};
+use crate::output;
+
+impl MlsChatApp {
+    /// Check `public_key` against what the current user knows for `peer`, pinning it if they know nothing;
+    /// returns true if the key is trusted
+    pub(crate) fn observe_key(&mut self, peer: &str, public_key: &str) -> Result<bool> {
+        let Some(user) = self.current_user.map(|user| user.to_string()) else { return Ok(false) };
+        if peer == user || self.user_keys.contains_key(peer) {
+            return Ok(true);
+        }
+        let fingerprint = crypto::fingerprint(public_key);
+        let mut peer_keys = self.storage.load_peer_keys()?;
+        let known = peer_keys.entry(user).or_default();
+        match known.get_mut(peer) {
+            None => {
+                known.insert(peer.to_string(), PeerKey { fingerprint: fingerprint.clone(), since: Utc::now(), continued_from: None, pending: None });
+                println!("   🔑 First key seen for {}: {}; pinned", peer, fingerprint);
+            }
+            Some(key) if key.fingerprint == fingerprint => return Ok(true),
+            Some(key) if key.pending.as_deref() == Some(fingerprint.as_str()) => return Ok(false),
+            Some(key) => {
+                println!("{}", output::words(&format!("⚠️  WARNING: {}'s identity key has changed!", peer)).red().bold());
+                println!("   Pinned: {}", key.fingerprint);
+                println!("   Now:    {}", fingerprint);
+                println!("   Someone may be impersonating {}, or they reinstalled. Compare the new fingerprint with them", peer);
+                println!("   another way, then run `trust accept {}`; until then their messages are not shown as verified.", peer.to_lowercase());
+                key.pending = Some(fingerprint);
+                self.storage.save_peer_keys(&peer_keys)?;
+                return Ok(false);
+            }
+        }
+        self.storage.save_peer_keys(&peer_keys)?;
+        Ok(true)
+    }
+
+    /// Pin the pending key seen for `peer`, replacing the one pinned before
+    pub fn trust_accept(&mut self, peer: UserName) -> Result<()> {
+        let user = self.current_user.context("No user initialized")?.to_string();
+        let peer = peer.to_string();
+        let mut peer_keys = self.storage.load_peer_keys()?;
+        let key = peer_keys.get_mut(&user).and_then(|known| known.get_mut(&peer))
+            .with_context(|| format!("{} has no key pinned for {}", user, peer))?;
+        let Some(pending) = key.pending.take() else {
+            println!("⚠️  No new key waiting for {}; {} is already trusted", peer, key.fingerprint);
+            return Ok(());
+        };
+        *key = PeerKey { fingerprint: pending.clone(), since: Utc::now(), continued_from: None, pending: None };
+        self.storage.save_peer_keys(&peer_keys)?;
+        println!("{}", format!("✅ Trusted {} for {}", pending, peer).green());
+        Ok(())
+    }
+
+    /// List the peer keys the current user has pinned, with any waiting to be accepted
+    pub fn list_trust(&self) -> Result<()> {
+        let user = self.current_user.context("No user initialized")?.to_string();
+        let known = self.storage.load_peer_keys()?.remove(&user).unwrap_or_default();
+        if known.is_empty() {
+            println!("No peer keys pinned for {} yet.", user);
+        }
+        let mut peers: Vec<(&String, &PeerKey)> = known.iter().collect();
+        peers.sort_by_key(|(peer, _)| peer.as_str());
+        for (peer, key) in peers {
+            let how = match &key.continued_from {
+                Some(_) => "continued from the previous key",
+                None => "pinned",
+            };
+            println!("{}: {} ({} {})", peer, key.fingerprint, how, key.since.format("%Y-%m-%d %H:%M UTC"));
+            if let Some(pending) = &key.pending {
+                println!("   {}", output::words(&format!("⚠️  changed to {}; `trust accept {}` to trust it", pending, peer.to_lowercase())).yellow());
+            }
+        }
+        Ok(())
+    }
+
+    /// The current user's pinned keys, for `list` to mark messages with
+    pub(crate) fn pinned_keys(&self) -> Result<HashMap<String, PeerKey>> {
+        let Some(user) = self.current_user else { return Ok(HashMap::new()) };
+        Ok(self.storage.load_peer_keys()?.remove(&user.to_string()).unwrap_or_default())
+    }
+}
+
+/// Whether a message arrived with its sender's pinned key; None if it carried no key
+pub(crate) fn verified(pinned: &HashMap<String, PeerKey>, message: &ChatMessage) -> Option<bool> {
+    let fingerprint = message.sender_key.as_ref()?;
+    Some(pinned.get(&message.sender).is_some_and(|key| key.fingerprint == *fingerprint))
+}
+
+/// The mark `list` adds after a message for its sender's key
+pub(crate) fn trust_mark(pinned: &HashMap<String, PeerKey>, message: &ChatMessage) -> &'static str {
+    match verified(pinned, message) {
+        Some(true) => " 🔒",
+        _ => "",
+    }
+}
+
+/// Warn under a message sent with a key other than the one the current user trusts for its sender
+pub(crate) fn print_unverified(pinned: &HashMap<String, PeerKey>, message: &ChatMessage) {
+    if verified(pinned, message) == Some(false) {
+        let fingerprint = message.sender_key.as_deref().unwrap_or_default();
+        let warning = format!("⚠️  Sent with a key other than the one you trust for {} ({}); see `trust list`", message.sender, fingerprint);
+        println!("   {}", output::words(&warning).yellow());
+    }
+}
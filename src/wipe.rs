@@ -0,0 +1,160 @@
+//! `wipe`: destroy a whole profile in one step
+//!
+//! Every file under the data directory, key material, group state, message
+//! logs, attachments, and anything else found there, is overwritten with
+//! zeros and removed, then the directories themselves. It runs before the
+//! profile is opened, so it needs no passphrase and works on a profile
+//! other commands refuse; `wipe_losses` reads what it will destroy from the
+//! directory for `prompt::confirm_loss`. A running `agent` is told to forget
+//! its key first.
+//!
+//! Overwriting has the limits noted on `storage::wipe`: SSDs and
+//! copy-on-write file systems may keep old blocks elsewhere, and copies made
+//! outside the data directory (backups, device snapshots, the transparency
+//! log) are untouched.
+
+use anyhow::{anyhow, Context, Result};
+use std::{fs, path::Path};
+
+use crate::agent::{self, AgentClient};
+use crate::preview::human_size;
+use crate::storage;
+
+/// What `wipe_profile` destroyed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WipeReport {
+    pub files: usize,
+    pub bytes: u64,
+}
+
+/// Fail unless `data_dir` looks like a profile, so a mistyped `--data-dir` cannot wipe something else
+fn check_profile(data_dir: &Path) -> Result<()> {
+    if !data_dir.join("groups").is_dir() || !data_dir.join("messages").is_dir() {
+        return Err(anyhow!("{} does not look like an mls-chat profile; nothing was wiped", data_dir.display()));
+    }
+    Ok(())
+}
+
+/// The names of the files in `dir` with extension `ext`, without it, sorted
+fn stems(dir: &Path, ext: &str) -> Result<Vec<String>> {
+    let Ok(entries) = fs::read_dir(dir) else { return Ok(Vec::new()) };
+    let mut stems = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().and_then(|found| found.to_str()) == Some(ext) {
+            stems.extend(path.file_stem().and_then(|stem| stem.to_str()).map(str::to_string));
+        }
+    }
+    stems.sort();
+    Ok(stems)
+}
+
+/// Count the files below `dir` and their bytes, as `wipe_dir` will find them
+fn survey(dir: &Path, report: &mut WipeReport) -> Result<()> {
+    for entry in fs::read_dir(dir).with_context(|| format!("Failed to read {}", dir.display()))? {
+        let entry = entry?;
+        let kind = entry.file_type()?;
+        if kind.is_dir() {
+            survey(&entry.path(), report)?;
+        } else if kind.is_file() {
+            report.files += 1;
+            report.bytes += entry.metadata()?.len();
+        }
+    }
+    Ok(())
+}
+
+/// What wiping the profile at `data_dir` destroys, for confirming first
+pub fn wipe_losses(data_dir: &Path) -> Result<Vec<String>> {
+    check_profile(data_dir)?;
+    let mut losses = Vec::new();
+    let mut users = stems(&data_dir.join("identities"), "json")?;
+    if data_dir.join("user_keys.json").exists() {
+        users = vec!["every identity".to_string()]; // Kept together by older versions, so not named here
+    }
+    if !users.is_empty() {
+        losses.push(format!("The keys of {} are wiped; these identities cannot read or send again, here or in any group", users.join(", ")));
+    }
+    let groups = stems(&data_dir.join("groups"), "json")?.len();
+    losses.push(format!("All {} group(s), their group secrets, and their message logs and attachments are wiped", groups));
+    let mut total = WipeReport::default();
+    survey(data_dir, &mut total)?;
+    losses.push(format!(
+        "Every file in {}, {} file(s) and {} in all, is overwritten and deleted, and the directory with them",
+        data_dir.display(),
+        total.files,
+        human_size(total.bytes)
+    ));
+    if AgentClient::new(agent::socket_path(data_dir)).is_running() {
+        losses.push("The running agent forgets its key and stops".to_string());
+    }
+    Ok(losses)
+}
+
+/// Overwrite and remove everything in the profile at `data_dir`
+///
+/// Refuses a directory that does not look like a profile. Confirm first
+/// with `wipe_losses`: nothing here asks.
+pub fn wipe_profile(data_dir: &Path) -> Result<WipeReport> {
+    check_profile(data_dir)?;
+    let agent = AgentClient::new(agent::socket_path(data_dir));
+    if agent.is_running() {
+        agent.lock().context("Failed to stop the agent")?;
+    }
+    let mut report = WipeReport::default();
+    wipe_dir(data_dir, &mut report)?;
+    Ok(report)
+}
+
+/// Wipe every file below `dir`, then remove `dir`
+fn wipe_dir(dir: &Path, report: &mut WipeReport) -> Result<()> {
+    for entry in fs::read_dir(dir).with_context(|| format!("Failed to read {}", dir.display()))? {
+        let entry = entry?;
+        let path = entry.path();
+        let kind = entry.file_type()?;
+        if kind.is_dir() {
+            wipe_dir(&path, report)?;
+        } else if kind.is_file() {
+            report.bytes += entry.metadata()?.len();
+            storage::wipe(&path)?;
+            report.files += 1;
+        } else {
+            // Symlinks and sockets: remove the entry, never what it points to
+            fs::remove_file(&path).with_context(|| format!("Failed to remove {}", path.display()))?;
+        }
+    }
+    fs::remove_dir(dir).with_context(|| format!("Failed to remove {}", dir.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::UserName;
+    use crate::storage::FileStorage;
+    use crate::MlsChatApp;
+
+    #[test]
+    fn lists_then_wipes_a_profile() {
+        let dir = std::env::temp_dir().join(format!("mls-chat-test-{}", uuid::Uuid::new_v4()));
+        let mut app = MlsChatApp::with_storage(Box::new(FileStorage::open(&dir).unwrap()));
+        app.init_user(UserName::Alice).unwrap();
+        app.init_user(UserName::Bob).unwrap();
+        app.create_group("Team".to_string()).unwrap();
+        app.send_message("Team".to_string(), "hello".to_string()).unwrap();
+        drop(app);
+
+        let losses = wipe_losses(&dir).unwrap();
+        assert!(losses[0].contains("The keys of Alice, Bob"), "{}", losses[0]);
+        assert!(losses[1].starts_with("All 1 group(s)"), "{}", losses[1]);
+        let report = wipe_profile(&dir).unwrap();
+        assert!(losses[2].contains(&format!("{} file(s)", report.files)), "{}", losses[2]);
+        assert!(!dir.exists());
+
+        // Not a profile, so nothing is listed or wiped
+        fs::create_dir_all(dir.join("groups")).unwrap();
+        assert!(wipe_losses(&dir).is_err());
+        assert!(wipe_profile(&dir).is_err());
+        assert!(dir.join("groups").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,113 @@
+//! Interactive group creation
+//!
+//! `create-group --interactive` asks for the group's name, ciphersuite,
+//! initial members, and retention policy, checks each member's key package as
+//! they are named, and shows a summary before anything is written.
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use colored::*;
+use std::io::BufRead;
+
+use crate::app::{describe_retention, MlsChatApp};
+use crate::model::{Ciphersuite, Retention, UserName};
+use crate::prompt::{ask, confirm};
+
+impl MlsChatApp {
+    /// Create a group from answers read from `input`; `name` is offered as the default name
+    pub fn create_group_interactive(&mut self, name: Option<String>, input: &mut impl BufRead) -> Result<()> {
+        let user = self.current_user.context("No user initialized")?;
+        println!("{}", "Creating a new group; press Enter to accept the [default]".green());
+
+        let name = loop {
+            let answer = ask(input, "Group name", name.as_deref())?;
+            if answer.is_empty() {
+                println!("⚠️  The group needs a name");
+            } else if self.groups.contains_key(&answer) {
+                println!("⚠️  Group '{}' already exists", answer);
+            } else {
+                break answer;
+            }
+        };
+
+        let suites = Ciphersuite::value_variants();
+        println!("Ciphersuites:");
+        for (index, suite) in suites.iter().enumerate() {
+            println!("   {}) {}", index + 1, suite);
+        }
+        let ciphersuite = loop {
+            let answer = ask(input, "Ciphersuite", Some("1"))?;
+            match answer.parse::<usize>().ok().and_then(|number| suites.get(number.wrapping_sub(1))) {
+                Some(suite) => break *suite,
+                None => println!("⚠️  Pick a number from 1 to {}", suites.len()),
+            }
+        };
+
+        let mut members: Vec<UserName> = Vec::new();
+        let answer = ask(input, "Members to add, separated by spaces (e.g. bob)", None)?;
+        for word in answer.split(|c: char| c == ',' || c.is_whitespace()).filter(|word| !word.is_empty()) {
+            let Ok(member) = UserName::from_str(word, true) else {
+                println!("   ⚠️  {}: unknown user, skipped", word);
+                continue;
+            };
+            if member == user || members.contains(&member) {
+                continue;
+            }
+            match self.key_package(&member.to_string()) {
+                Ok(_) => {
+                    println!("   ✅ {}: key package available", member);
+                    members.push(member);
+                }
+                Err(err) => println!("   ⚠️  {}: no key package ({:#}), skipped", member, err),
+            }
+        }
+
+        let default_retention = format!("config file default ({})", describe_retention(self.retention));
+        println!("Retention: {}", default_retention);
+        let retention = match confirm(input, "Set a different retention policy for this group?", false)? {
+            true => Some(Retention {
+                max_age_days: ask_limit(input, "Delete messages older than how many days (blank for no limit)")?
+                    .map(|days| days as u32),
+                max_messages: ask_limit(input, "Keep at most how many messages (blank for no limit)")?,
+            }),
+            false => None,
+        };
+
+        let mut names = vec![format!("{} (you)", user)];
+        names.extend(members.iter().map(UserName::to_string));
+        println!("\n{}", "Summary:".blue());
+        println!("   Name: {}", name);
+        println!("   Ciphersuite: {}", ciphersuite);
+        println!("   Members: {}", names.join(", "));
+        println!("   Retention: {}", retention.map(describe_retention).unwrap_or(default_retention));
+        if !confirm(input, "Create this group?", true)? {
+            println!("Cancelled; nothing was created");
+            return Ok(());
+        }
+
+        self.create_group(name.clone())?;
+        let group = self.groups.get_mut(&name).context("Group not found")?;
+        group.ciphersuite = ciphersuite;
+        group.retention = retention;
+        self.dirty_groups.insert(name.clone());
+        self.save_state()?;
+        for member in members {
+            self.add_member(name.clone(), member)?;
+        }
+        Ok(())
+    }
+}
+
+/// Ask for a positive whole number until one is given; blank means no limit
+fn ask_limit(input: &mut impl BufRead, question: &str) -> Result<Option<usize>> {
+    loop {
+        let answer = ask(input, question, None)?;
+        if answer.is_empty() {
+            return Ok(None);
+        }
+        match answer.parse::<usize>() {
+            Ok(limit) if limit >= 1 && limit <= u32::MAX as usize => return Ok(Some(limit)),
+            _ => println!("⚠️  Enter a whole number of at least 1"),
+        }
+    }
+}
@@ -140,11 +140,12 @@ else
     print_error "Data directory not created"
 fi
 
-if [ -f "mls_chat_data/app_state.json" ]; then
-    print_status "Application state file created successfully"
-    echo "State file size: $(wc -c < mls_chat_data/app_state.json) bytes"
+GROUP_FILES=$(ls mls_chat_data/groups/*.json 2>/dev/null | wc -l)
+if [ "$GROUP_FILES" -eq 2 ]; then
+    print_status "Per-group state files created successfully (2 groups)"
+    echo "State size: $(cat mls_chat_data/groups/*.json | wc -c) bytes"
 else
-    print_error "Application state file not created"
+    print_error "Expected 2 group state files, found $GROUP_FILES"
 fi
 echo ""
 
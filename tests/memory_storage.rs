@@ -0,0 +1,42 @@
+//! The library driven end to end on in-memory storage, as the crate docs show
+
+use mls_chat::app::{ListOptions, MessageFilter};
+use mls_chat::{MemoryStorage, MlsChatApp, UserName};
+
+#[test]
+fn test_complete_workflow() {
+    let storage = MemoryStorage::new();
+    let mut app = MlsChatApp::with_storage(Box::new(storage.clone()));
+
+    // Initialize users (Alice, the first, stays the active one)
+    app.init_user(UserName::Alice).unwrap();
+    app.init_user(UserName::Bob).unwrap();
+    assert_eq!(app.current_user(), Some(UserName::Alice));
+
+    // Create group
+    app.create_group("TestGroup".to_string()).unwrap();
+
+    // Add member
+    app.add_member("TestGroup".to_string(), UserName::Bob).unwrap();
+    let group = app.group("TestGroup").unwrap();
+    assert_eq!(group.members, ["Alice", "Bob"]);
+    assert_eq!(group.mls_group.epoch, 2);
+
+    // Send message
+    app.send_message("TestGroup".to_string(), "Hello".to_string()).unwrap();
+
+    // List
+    app.list_messages("TestGroup".to_string(), &ListOptions::default(), &MessageFilter::default()).unwrap();
+
+    // Reload and verify message
+    let mut app = MlsChatApp::with_storage(Box::new(storage));
+    app.load_state().unwrap();
+    let mut contents = Vec::new();
+    app.for_each_message("TestGroup", &MessageFilter::default(), |message| {
+        contents.push((message.sender.clone(), message.content.clone(), message.epoch));
+        Ok(())
+    }).unwrap();
+    assert_eq!(contents, [("Alice".to_string(), "Hello".to_string(), 2)]);
+    let group = app.load_messages("TestGroup").unwrap();
+    assert_eq!(group.messages.as_ref().unwrap().len(), 1);
+}